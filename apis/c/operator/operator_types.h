@@ -126,10 +126,41 @@ typedef struct ArcDynFn1_DoraResult_Output {
     void (*retain)(void *);
 } ArcDynFn1_DoraResult_Output_t;
 
+/** <No documentation available> */
+typedef struct TopologyResult {
+    /** <No documentation available> */
+    DoraResult_t result;
+
+    /** \brief
+     *  The JSON-encoded topology of the operator, empty on errors.
+     */
+    Vec_uint8_t topology;
+} TopologyResult_t;
+
+/** \brief
+ *  `Arc<dyn Send + Sync + Fn() -> Ret>`
+ */
+typedef struct ArcDynFn0_TopologyResult {
+    /** <No documentation available> */
+    void * env_ptr;
+
+    /** <No documentation available> */
+    TopologyResult_t (*call)(void *);
+
+    /** <No documentation available> */
+    void (*release)(void *);
+
+    /** <No documentation available> */
+    void (*retain)(void *);
+} ArcDynFn0_TopologyResult_t;
+
 /** <No documentation available> */
 typedef struct SendOutput {
     /** <No documentation available> */
     ArcDynFn1_DoraResult_Output_t send_output;
+
+    /** <No documentation available> */
+    ArcDynFn0_TopologyResult_t query_topology;
 } SendOutput_t;
 
 /** <No documentation available> */
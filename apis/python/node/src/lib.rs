@@ -194,6 +194,41 @@ impl Node {
         )
     }
 
+    /// Returns the resolved inputs and outputs of this node, together with
+    /// the IDs of the upstream and downstream nodes.
+    ///
+    /// :rtype: dict
+    pub fn topology(&mut self, py: Python) -> eyre::Result<PyObject> {
+        let topology = self.node.get_mut().topology()?;
+        Ok(pythonize::pythonize(py, &topology).map(|x| x.unbind())?)
+    }
+
+    /// Reports to the daemon that this node finished its initialization.
+    ///
+    /// Stops the `ready_timeout` of the node, if any.
+    ///
+    /// :rtype: None
+    pub fn notify_ready(&mut self) -> eyre::Result<()> {
+        self.node.get_mut().notify_ready()
+    }
+
+    /// Stops the whole dataflow, like `dora stop`.
+    ///
+    /// Requires `allowed_to_stop: true` for this node in the dataflow
+    /// descriptor. All nodes are killed if they don't exit within the
+    /// `grace_duration` (in seconds).
+    ///
+    /// :type grace_duration: float, optional
+    /// :rtype: None
+    #[pyo3(signature = (grace_duration=None))]
+    pub fn request_stop(&mut self, grace_duration: Option<f64>) -> eyre::Result<()> {
+        let grace_duration = grace_duration
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .context("invalid grace duration")?;
+        self.node.get_mut().request_stop(grace_duration)
+    }
+
     /// Returns the dataflow id.
     ///
     /// :rtype: str
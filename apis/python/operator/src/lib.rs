@@ -13,7 +13,7 @@ use futures::{Stream, StreamExt};
 use futures_concurrency::stream::Merge as _;
 use pyo3::{
     prelude::*,
-    types::{IntoPyDict, PyBool, PyDict, PyInt, PyString},
+    types::{IntoPyDict, PyBool, PyBytes, PyDict, PyFloat, PyInt, PyString},
 };
 
 /// Dora Event
@@ -100,6 +100,12 @@ impl PyEvent {
                 if let Some(error) = Self::error(event) {
                     pydict.insert("error", error.to_object(py));
                 }
+                if let Event::InputClosed { delivered, .. } = event {
+                    pydict.insert("delivered", delivered.into_py(py));
+                }
+                if let Event::InputTimeout { elapsed, .. } = event {
+                    pydict.insert("elapsed", elapsed.as_secs_f64().into_py(py));
+                }
             }
             MergedEvent::External(event) => {
                 pydict.insert("value", event.clone_ref(py));
@@ -118,6 +124,7 @@ impl PyEvent {
             Event::Stop => "STOP",
             Event::Input { .. } => "INPUT",
             Event::InputClosed { .. } => "INPUT_CLOSED",
+            Event::InputTimeout { .. } => "INPUT_TIMEOUT",
             Event::Error(_) => "ERROR",
             _other => "UNKNOWN",
         }
@@ -126,7 +133,8 @@ impl PyEvent {
     fn id(event: &Event) -> Option<&str> {
         match event {
             Event::Input { id, .. } => Some(id),
-            Event::InputClosed { id } => Some(id),
+            Event::InputClosed { id, .. } => Some(id),
+            Event::InputTimeout { id, .. } => Some(id),
             _ => None,
         }
     }
@@ -167,16 +175,27 @@ pub fn pydict_to_metadata(dict: Option<Bound<'_, PyDict>>) -> Result<MetadataPar
     if let Some(pymetadata) = dict {
         for (key, value) in pymetadata.iter() {
             let key = key.extract::<String>().context("Parsing metadata keys")?;
-            if value.is_exact_instance_of::<PyBool>() {
-                parameters.insert(key, Parameter::Bool(value.extract()?))
+            let parameter = if value.is_exact_instance_of::<PyBool>() {
+                Parameter::Bool(value.extract()?)
             } else if value.is_instance_of::<PyInt>() {
-                parameters.insert(key, Parameter::Integer(value.extract::<i64>()?))
+                Parameter::Integer(value.extract::<i64>()?)
+            } else if value.is_instance_of::<PyFloat>() {
+                Parameter::Float(value.extract()?)
             } else if value.is_instance_of::<PyString>() {
-                parameters.insert(key, Parameter::String(value.extract()?))
+                Parameter::String(value.extract()?)
+            } else if let Ok(bytes) = value.downcast::<PyBytes>() {
+                Parameter::Bytes(bytes.as_bytes().to_vec())
+            } else if let Ok(list) = value.extract::<Vec<i64>>() {
+                Parameter::ListInt(list)
+            } else if let Ok(list) = value.extract::<Vec<f64>>() {
+                Parameter::ListFloat(list)
+            } else if let Ok(list) = value.extract::<Vec<String>>() {
+                Parameter::ListString(list)
             } else {
                 println!("could not convert type {value}");
-                parameters.insert(key, Parameter::String(value.str()?.to_string()))
+                Parameter::String(value.str()?.to_string())
             };
+            parameters.insert(key, parameter);
         }
     }
     Ok(parameters)
@@ -188,17 +207,18 @@ pub fn metadata_to_pydict<'a>(
 ) -> Result<pyo3::Bound<'a, PyDict>> {
     let dict = PyDict::new_bound(py);
     for (k, v) in metadata.parameters.iter() {
-        match v {
-            Parameter::Bool(bool) => dict
-                .set_item(k, bool)
-                .context("Could not insert metadata into python dictionary")?,
-            Parameter::Integer(int) => dict
-                .set_item(k, int)
-                .context("Could not insert metadata into python dictionary")?,
-            Parameter::String(s) => dict
-                .set_item(k, s)
-                .context("Could not insert metadata into python dictionary")?,
-        }
+        let value = match v {
+            Parameter::Bool(bool) => bool.to_object(py),
+            Parameter::Integer(int) => int.to_object(py),
+            Parameter::String(s) => s.to_object(py),
+            Parameter::Float(float) => float.to_object(py),
+            Parameter::Bytes(bytes) => PyBytes::new_bound(py, bytes).to_object(py),
+            Parameter::ListInt(list) => list.to_object(py),
+            Parameter::ListFloat(list) => list.to_object(py),
+            Parameter::ListString(list) => list.to_object(py),
+        };
+        dict.set_item(k, value)
+            .context("Could not insert metadata into python dictionary")?;
     }
 
     Ok(dict)
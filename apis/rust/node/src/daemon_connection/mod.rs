@@ -1,6 +1,10 @@
-use dora_core::{config::NodeId, uhlc::Timestamp};
+use dora_core::{
+    config::NodeId,
+    topics::{DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT, LOCALHOST},
+    uhlc::Timestamp,
+};
 use dora_message::{
-    daemon_to_node::DaemonReply,
+    daemon_to_node::{env, DaemonReply},
     node_to_daemon::{DaemonRequest, NodeRegisterRequest, Timestamped},
     DataflowId,
 };
@@ -13,6 +17,9 @@ use std::{
     time::Duration,
 };
 
+pub use multiplexed::MultiplexedChannel;
+
+mod multiplexed;
 mod tcp;
 #[cfg(unix)]
 mod unix_domain;
@@ -75,6 +82,25 @@ impl DaemonChannel {
         Ok(())
     }
 
+    /// Authenticates the connection to the local listener of a daemon with
+    /// a join token, see [`NodeJoinInfo`](dora_message::common::NodeJoinInfo).
+    pub fn join(&mut self, token: String, timestamp: Timestamp) -> eyre::Result<()> {
+        let msg = Timestamped {
+            inner: DaemonRequest::Join { token },
+            timestamp,
+        };
+        let reply = self
+            .request(&msg)
+            .wrap_err("failed to send join request to dora-daemon")?;
+        match reply {
+            DaemonReply::Result(result) => result
+                .map_err(|e| eyre!(e))
+                .wrap_err("dora-daemon rejected the join token")?,
+            other => bail!("unexpected join reply: {other:?}"),
+        }
+        Ok(())
+    }
+
     pub fn request(&mut self, request: &Timestamped<DaemonRequest>) -> eyre::Result<DaemonReply> {
         match self {
             DaemonChannel::Shmem(client) => client.request(request),
@@ -83,4 +109,30 @@ impl DaemonChannel {
             DaemonChannel::UnixDomain(stream) => unix_domain::request(stream, request),
         }
     }
+
+    /// Receives a reply that the daemon sends without a preceding request,
+    /// e.g. the messages of an observer.
+    ///
+    /// Returns `None` if the daemon closed the connection.
+    pub fn receive_reply(&mut self) -> eyre::Result<Option<DaemonReply>> {
+        match self {
+            DaemonChannel::Shmem(_) => bail!("shared memory channels only support request/reply"),
+            DaemonChannel::Tcp(stream) => tcp::receive_reply(stream, tcp::Serializer::Bincode),
+            #[cfg(unix)]
+            DaemonChannel::UnixDomain(stream) => {
+                tcp::receive_reply(stream, tcp::Serializer::Bincode)
+            }
+        }
+    }
+}
+
+/// Address of the local listener of the daemon, taken from `DORA_DAEMON_ADDR`
+/// or the default local daemon address if the variable is not set.
+pub fn local_daemon_address() -> eyre::Result<SocketAddr> {
+    match std::env::var(env::DORA_DAEMON_ADDR) {
+        Ok(addr) => addr
+            .parse()
+            .wrap_err_with(|| format!("invalid {} `{addr}`", env::DORA_DAEMON_ADDR)),
+        Err(_) => Ok((LOCALHOST, DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT).into()),
+    }
 }
@@ -0,0 +1,168 @@
+//! Daemon channel that is shared by multiple threads.
+//!
+//! Each request is wrapped in a [`DaemonRequest::Multiplexed`] request with a
+//! unique ID, which the daemon copies into its reply. On socket connections,
+//! threads write their requests without waiting for the replies to earlier
+//! requests of other threads. The thread that reads from the socket hands the
+//! replies to other requests over to their threads, matched by ID.
+//!
+//! Daemons that predate multiplexed requests are sent one request at a time,
+//! without ID.
+
+use super::{
+    tcp::{self, Serializer},
+    DaemonChannel,
+};
+use dora_message::{
+    daemon_to_node::DaemonReply,
+    node_to_daemon::{DaemonRequest, Timestamped},
+};
+use eyre::{bail, eyre, Context};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+pub struct MultiplexedChannel {
+    next_request_id: AtomicU64,
+    transport: Transport,
+}
+
+enum Transport {
+    /// Channel to a daemon that doesn't understand multiplexed requests.
+    Sequential(Mutex<DaemonChannel>),
+    /// Shared memory channels only support a single request at a time.
+    Exclusive(Mutex<DaemonChannel>),
+    /// Both directions of a socket, which use the same framing for TCP and
+    /// Unix domain sockets.
+    Socket {
+        writer: Mutex<Box<dyn Write + Send>>,
+        reader: Mutex<ReplyReader>,
+    },
+}
+
+struct ReplyReader {
+    stream: Box<dyn Read + Send>,
+    /// Replies that were read by another thread than the one waiting for
+    /// them.
+    unclaimed: HashMap<u64, DaemonReply>,
+}
+
+impl MultiplexedChannel {
+    /// Wraps the given channel, which must be registered already.
+    pub fn new(channel: DaemonChannel) -> eyre::Result<Self> {
+        let transport = match channel {
+            DaemonChannel::Shmem(_) => Transport::Exclusive(Mutex::new(channel)),
+            DaemonChannel::Tcp(stream) => {
+                let writer = stream.try_clone().context("failed to clone TCP stream")?;
+                Transport::socket(Box::new(writer), Box::new(stream))
+            }
+            #[cfg(unix)]
+            DaemonChannel::UnixDomain(stream) => {
+                let writer = stream.try_clone().context("failed to clone Unix socket")?;
+                Transport::socket(Box::new(writer), Box::new(stream))
+            }
+        };
+        Ok(Self {
+            next_request_id: AtomicU64::new(0),
+            transport,
+        })
+    }
+
+    /// Wraps the given channel to a daemon that doesn't understand
+    /// multiplexed requests, see [`NodeConfig::supports_multiplexed_requests`].
+    ///
+    /// [`NodeConfig::supports_multiplexed_requests`]: dora_message::daemon_to_node::NodeConfig::supports_multiplexed_requests
+    pub fn sequential(channel: DaemonChannel) -> Self {
+        Self {
+            next_request_id: AtomicU64::new(0),
+            transport: Transport::Sequential(Mutex::new(channel)),
+        }
+    }
+
+    /// Sends the given request and waits for its reply.
+    ///
+    /// Only requests with a reply can be multiplexed.
+    pub fn request(&self, request: Timestamped<DaemonRequest>) -> eyre::Result<DaemonReply> {
+        match &self.transport {
+            Transport::Sequential(channel) => lock(channel)?.request(&request),
+            Transport::Exclusive(channel) => {
+                let (request_id, request) = self.tag(request)?;
+                match lock(channel)?.request(&request)? {
+                    DaemonReply::Multiplexed {
+                        request_id: id,
+                        reply,
+                    } if id == request_id => Ok(*reply),
+                    other => {
+                        bail!("unexpected reply to multiplexed request {request_id}: {other:?}")
+                    }
+                }
+            }
+            Transport::Socket { writer, reader } => {
+                let (request_id, request) = self.tag(request)?;
+                tcp::send_message(&mut *lock(writer)?, &request)?;
+                lock(reader)?.receive(request_id)
+            }
+        }
+    }
+
+    /// Wraps the given request in a multiplexed request with a new ID.
+    fn tag(
+        &self,
+        request: Timestamped<DaemonRequest>,
+    ) -> eyre::Result<(u64, Timestamped<DaemonRequest>)> {
+        if !request.inner.expects_tcp_bincode_reply() {
+            bail!("request can't be multiplexed: {:?}", request.inner);
+        }
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let request = Timestamped {
+            inner: DaemonRequest::Multiplexed {
+                request_id,
+                request: Box::new(request.inner),
+            },
+            timestamp: request.timestamp,
+        };
+        Ok((request_id, request))
+    }
+}
+
+impl Transport {
+    fn socket(writer: Box<dyn Write + Send>, reader: Box<dyn Read + Send>) -> Self {
+        Transport::Socket {
+            writer: Mutex::new(writer),
+            reader: Mutex::new(ReplyReader {
+                stream: reader,
+                unclaimed: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl ReplyReader {
+    /// Reads replies until the one to the given request arrived.
+    fn receive(&mut self, request_id: u64) -> eyre::Result<DaemonReply> {
+        loop {
+            if let Some(reply) = self.unclaimed.remove(&request_id) {
+                return Ok(reply);
+            }
+            let reply = tcp::receive_reply(&mut self.stream, Serializer::Bincode)?
+                .ok_or_else(|| eyre!("server disconnected unexpectedly"))?;
+            match reply {
+                DaemonReply::Multiplexed { request_id, reply } => {
+                    self.unclaimed.insert(request_id, *reply);
+                }
+                other => bail!("unexpected reply on multiplexed channel: {other:?}"),
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> eyre::Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| eyre!("daemon channel is unusable after a thread panicked while using it"))
+}
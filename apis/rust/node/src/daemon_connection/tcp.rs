@@ -8,7 +8,7 @@ use std::{
     net::TcpStream,
 };
 
-enum Serializer {
+pub(super) enum Serializer {
     Bincode,
     SerdeJson,
 }
@@ -29,8 +29,8 @@ pub fn request(
     }
 }
 
-fn send_message(
-    connection: &mut TcpStream,
+pub(super) fn send_message(
+    connection: &mut (impl Write + Unpin),
     message: &Timestamped<DaemonRequest>,
 ) -> eyre::Result<()> {
     let serialized = bincode::serialize(&message).wrap_err("failed to serialize DaemonRequest")?;
@@ -38,8 +38,8 @@ fn send_message(
     Ok(())
 }
 
-fn receive_reply(
-    connection: &mut TcpStream,
+pub(super) fn receive_reply(
+    connection: &mut (impl Read + Unpin),
     serializer: Serializer,
 ) -> eyre::Result<Option<DaemonReply>> {
     let raw = match tcp_receive(connection) {
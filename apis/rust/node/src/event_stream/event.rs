@@ -1,9 +1,15 @@
-use std::{ptr::NonNull, sync::Arc};
+use std::{ptr::NonNull, sync::Arc, time::Duration};
 
 use aligned_vec::{AVec, ConstAlign};
-use dora_arrow_convert::{ArrowData, IntoArrow};
-use dora_core::config::{DataId, OperatorId};
-use dora_message::metadata::{ArrowTypeInfo, BufferOffset, Metadata};
+use dora_arrow_convert::ArrowData;
+use dora_core::{
+    config::{DataId, OperatorId},
+    uhlc,
+};
+use dora_message::{
+    common::CACHED_FILE_PREFIX,
+    metadata::{ArrowTypeInfo, BufferOffset, Metadata},
+};
 use eyre::{Context, Result};
 use shared_memory_extended::{Shmem, ShmemConf};
 
@@ -17,10 +23,46 @@ pub enum Event {
     Input {
         id: DataId,
         metadata: Metadata,
+        /// The received data.
+        ///
+        /// Messages without payload (e.g. zero-length sends) are delivered as
+        /// an empty array of the sent type, so there is no separate "no data"
+        /// case to handle.
+        ///
+        /// Large messages are not copied out of shared memory, see
+        /// [`ArrowData`]. Use [`ArrowData::as_bytes`] to borrow raw bytes
+        /// and [`ArrowData::into_vec`] to take an owned copy.
         data: ArrowData,
     },
+    /// The input was closed, e.g. because its source node finished.
+    ///
+    /// Comparing `delivered` with the number of received messages shows
+    /// whether messages were lost, e.g. because the input queue was full.
     InputClosed {
         id: DataId,
+        /// Number of messages that the daemon delivered to the input.
+        delivered: u64,
+        /// Timestamp of the last delivered message, if any.
+        last_timestamp: Option<uhlc::Timestamp>,
+    },
+    /// The given output gained its first receiver or lost its last one.
+    ///
+    /// Use [`DoraNode::subscriber_count`][crate::DoraNode::subscriber_count]
+    /// to query the number of receivers at any time.
+    SubscribersChanged {
+        output_id: DataId,
+        /// Current number of receivers of the output.
+        subscribers: u64,
+    },
+    /// No message arrived on the input within its configured `timeout`.
+    ///
+    /// Repeated after every further `timeout` while the input stays silent,
+    /// e.g. to hold the last command or to enter a safe mode.
+    InputTimeout {
+        id: DataId,
+        /// Time since the last message on the input, or since the node
+        /// started if there was no message yet.
+        elapsed: Duration,
     },
     Error(String),
 }
@@ -34,7 +76,7 @@ pub enum RawData {
 impl RawData {
     pub fn into_arrow_array(self, type_info: &ArrowTypeInfo) -> Result<arrow::array::ArrayData> {
         let raw_buffer = match self {
-            RawData::Empty => return Ok(().into_arrow().into()),
+            RawData::Empty => return Ok(arrow::array::ArrayData::new_empty(&type_info.data_type)),
             RawData::Vec(data) => {
                 let ptr = NonNull::new(data.as_ptr() as *mut _).unwrap();
                 let len = data.len();
@@ -97,19 +139,42 @@ impl std::fmt::Debug for RawData {
 }
 
 pub struct MappedInputData {
-    memory: Box<Shmem>,
+    memory: MappedMemory,
     len: usize,
 }
 
+enum MappedMemory {
+    SharedMemory(Box<Shmem>),
+    /// File of the persistent output cache of the daemon.
+    File(memmap2::Mmap),
+}
+
 impl MappedInputData {
     pub(crate) unsafe fn map(shared_memory_id: &str, len: usize) -> eyre::Result<Self> {
-        let memory = Box::new(
-            ShmemConf::new()
-                .os_id(shared_memory_id)
-                .writable(false)
-                .open()
-                .wrap_err("failed to map shared memory input")?,
-        );
+        let memory = match shared_memory_id.strip_prefix(CACHED_FILE_PREFIX) {
+            Some(path) => {
+                let file = std::fs::File::open(path)
+                    .wrap_err_with(|| format!("failed to open cached input file `{path}`"))?;
+                let map = memmap2::Mmap::map(&file)
+                    .wrap_err_with(|| format!("failed to map cached input file `{path}`"))?;
+                if map.len() < len {
+                    eyre::bail!(
+                        "cached input file `{path}` is smaller than the message ({} < {len} bytes)",
+                        map.len()
+                    );
+                }
+                MappedMemory::File(map)
+            }
+            // the region is shared with the other receivers of the message,
+            // so it is mapped read-only
+            None => MappedMemory::SharedMemory(Box::new(
+                ShmemConf::new()
+                    .os_id(shared_memory_id)
+                    .writable(false)
+                    .open()
+                    .wrap_err("failed to map shared memory input")?,
+            )),
+        };
         Ok(MappedInputData { memory, len })
     }
 }
@@ -118,7 +183,10 @@ impl std::ops::Deref for MappedInputData {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &self.memory.as_slice()[..self.len] }
+        match &self.memory {
+            MappedMemory::SharedMemory(memory) => unsafe { &memory.as_slice()[..self.len] },
+            MappedMemory::File(map) => &map[..self.len],
+        }
     }
 }
 
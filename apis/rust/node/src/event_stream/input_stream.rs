@@ -0,0 +1,276 @@
+//! Streams that receive the messages of a single input, see
+//! [`EventStream::input_stream`][super::EventStream::input_stream].
+
+use std::{
+    collections::BTreeMap,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use dora_arrow_convert::ArrowData;
+use dora_core::config::DataId;
+use dora_message::{daemon_to_node::NodeEvent, metadata::Metadata};
+use futures::{Stream, StreamExt};
+
+use super::{thread::EventItem, EventStream};
+
+/// A message received on an [`InputStream`].
+#[derive(Debug)]
+pub struct Input {
+    pub id: DataId,
+    pub metadata: Metadata,
+    pub data: ArrowData,
+}
+
+/// Configuration of an [`InputStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputStreamConfig {
+    /// Number of messages that are buffered until the stream is read.
+    pub buffer_size: usize,
+    /// What happens when a message arrives while the buffer is full.
+    pub policy: BufferFullPolicy,
+}
+
+impl Default for InputStreamConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: 10,
+            policy: BufferFullPolicy::default(),
+        }
+    }
+}
+
+/// What happens when a message arrives for an [`InputStream`] whose buffer
+/// is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BufferFullPolicy {
+    /// Drops the oldest buffered message, like the daemon does for full
+    /// input queues.
+    #[default]
+    DropOldest,
+    /// Drops the new message.
+    DropNewest,
+}
+
+/// Receives the messages of a single input.
+///
+/// The stream ends when the input is closed or when the event stream
+/// finishes.
+pub struct InputStream {
+    id: DataId,
+    receiver: flume::r#async::RecvStream<'static, EventItem>,
+    _alive: Arc<()>,
+}
+
+impl InputStream {
+    pub fn id(&self) -> &DataId {
+        &self.id
+    }
+}
+
+impl Stream for InputStream {
+    type Item = Input;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let item = match self.receiver.poll_next_unpin(cx) {
+                Poll::Ready(Some(item)) => item,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            let EventItem::NodeEvent {
+                event: NodeEvent::Input { id, metadata, data },
+                ack_channel,
+            } = item
+            else {
+                tracing::warn!("unexpected event on input stream `{}`: {item:?}", self.id);
+                continue;
+            };
+            match EventStream::input_data(data, &metadata.type_info, ack_channel) {
+                Ok(data) => return Poll::Ready(Some(Input { id, metadata, data })),
+                Err(err) => tracing::warn!("failed to receive input `{id}`: {err:?}"),
+            }
+        }
+    }
+}
+
+/// Forwards the events of inputs that have an [`InputStream`] to the stream.
+///
+/// Shared between the [`EventStream`] and its background thread.
+#[derive(Clone, Default)]
+pub(crate) struct InputRouter(Arc<Mutex<BTreeMap<DataId, InputRoute>>>);
+
+struct InputRoute {
+    tx: flume::Sender<EventItem>,
+    /// Used for dropping the oldest message when the buffer is full.
+    rx: flume::Receiver<EventItem>,
+    policy: BufferFullPolicy,
+    /// Dangling once the input stream was dropped.
+    stream_alive: Weak<()>,
+}
+
+impl InputRouter {
+    pub fn add(&self, id: DataId, config: InputStreamConfig) -> InputStream {
+        let (tx, rx) = flume::bounded(config.buffer_size.max(1));
+        let alive = Arc::new(());
+        let route = InputRoute {
+            tx,
+            rx: rx.clone(),
+            policy: config.policy,
+            stream_alive: Arc::downgrade(&alive),
+        };
+        self.routes().insert(id.clone(), route);
+        InputStream {
+            id,
+            receiver: rx.into_stream(),
+            _alive: alive,
+        }
+    }
+
+    /// Forwards the given item to its input stream.
+    ///
+    /// Returns the item back if its input has no separate stream.
+    pub fn route(&self, item: EventItem) -> Option<EventItem> {
+        let EventItem::NodeEvent { event, .. } = &item else {
+            return Some(item);
+        };
+        let mut routes = self.routes();
+        match event {
+            NodeEvent::Input { id, .. } => match routes.get(id) {
+                Some(route) => {
+                    route.push(item);
+                    None
+                }
+                None => Some(item),
+            },
+            // the stream ends once the buffered messages are read
+            NodeEvent::InputClosed { id, .. } => match routes.remove(id) {
+                Some(_) => None,
+                None => Some(item),
+            },
+            _ => Some(item),
+        }
+    }
+
+    /// Ends all input streams.
+    pub fn close_all(&self) {
+        self.routes().clear();
+    }
+
+    fn routes(&self) -> std::sync::MutexGuard<'_, BTreeMap<DataId, InputRoute>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl InputRoute {
+    fn push(&self, mut item: EventItem) {
+        if self.stream_alive.strong_count() == 0 {
+            // the input stream was dropped
+            return;
+        }
+        loop {
+            match self.tx.try_send(item) {
+                Ok(()) | Err(flume::TrySendError::Disconnected(_)) => break,
+                Err(flume::TrySendError::Full(rejected)) => match self.policy {
+                    BufferFullPolicy::DropOldest => {
+                        // dropping the item reports its drop token
+                        let _ = self.rx.try_recv();
+                        item = rejected;
+                    }
+                    BufferFullPolicy::DropNewest => break,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::uhlc;
+    use dora_message::{
+        daemon_to_node::DataMessage,
+        metadata::{ArrowTypeInfo, Metadata},
+    };
+
+    fn input(clock: &uhlc::HLC, id: &str, value: u8) -> (EventItem, flume::Receiver<()>) {
+        let (ack_channel, ack_rx) = flume::bounded(0);
+        let item = EventItem::NodeEvent {
+            event: NodeEvent::Input {
+                id: DataId::from(id.to_owned()),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::byte_array(1)),
+                data: Some(DataMessage::Vec(aligned_vec::AVec::from_slice(1, &[value]))),
+            },
+            ack_channel,
+        };
+        (item, ack_rx)
+    }
+
+    fn values(stream: &mut InputStream) -> Vec<u8> {
+        futures::executor::block_on_stream(stream)
+            .map(|input| {
+                let data: &[u8] = (&input.data).try_into().unwrap();
+                data[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_buffers_drop_the_oldest_message() {
+        let clock = uhlc::HLC::default();
+        let router = InputRouter::default();
+        let mut stream = router.add(
+            DataId::from("image".to_owned()),
+            InputStreamConfig {
+                buffer_size: 2,
+                policy: BufferFullPolicy::DropOldest,
+            },
+        );
+
+        let mut acks = Vec::new();
+        for value in 0..3 {
+            let (item, ack) = input(&clock, "image", value);
+            assert!(router.route(item).is_none());
+            acks.push(ack);
+        }
+        // the dropped message is acknowledged, so its drop token is reported
+        assert!(acks[0].is_disconnected());
+        assert!(!acks[1].is_disconnected());
+
+        // other inputs are not routed
+        let (other, _) = input(&clock, "tick", 0);
+        assert!(router.route(other).is_some());
+
+        // closing the input ends the stream after the buffered messages
+        let closed = EventItem::NodeEvent {
+            event: NodeEvent::InputClosed {
+                id: DataId::from("image".to_owned()),
+                delivered: 2,
+                last_timestamp: None,
+            },
+            ack_channel: flume::bounded(0).0,
+        };
+        assert!(router.route(closed).is_none());
+        assert_eq!(values(&mut stream), [1, 2]);
+    }
+
+    #[test]
+    fn full_buffers_can_drop_the_newest_message() {
+        let clock = uhlc::HLC::default();
+        let router = InputRouter::default();
+        let mut stream = router.add(
+            DataId::from("image".to_owned()),
+            InputStreamConfig {
+                buffer_size: 1,
+                policy: BufferFullPolicy::DropNewest,
+            },
+        );
+        for value in 0..3 {
+            let (item, _) = input(&clock, "image", value);
+            assert!(router.route(item).is_none());
+        }
+        router.close_all();
+        assert_eq!(values(&mut stream), [0]);
+    }
+}
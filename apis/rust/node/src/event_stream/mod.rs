@@ -1,8 +1,15 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use dora_arrow_convert::ArrowData;
 use dora_message::{
     daemon_to_node::{DaemonCommunication, DaemonReply, DataMessage, NodeEvent},
-    node_to_daemon::{DaemonRequest, Timestamped},
+    metadata::ArrowTypeInfo,
+    node_to_daemon::{DaemonRequest, EventInterest, Timestamped},
     DataflowId,
 };
 pub use event::{Event, MappedInputData, RawData};
@@ -11,25 +18,48 @@ use futures::{
     Stream, StreamExt,
 };
 use futures_timer::Delay;
+pub use input_stream::{BufferFullPolicy, Input, InputStream, InputStreamConfig};
+pub use timer_handler::{TimerBackpressure, TimerContext, TimerTick};
 
 use self::{
     event::SharedMemoryData,
+    input_stream::InputRouter,
     thread::{EventItem, EventStreamThreadHandle},
 };
-use crate::daemon_connection::DaemonChannel;
-use dora_core::{config::NodeId, uhlc};
-use eyre::{eyre, Context};
+use crate::{daemon_connection::DaemonChannel, DoraNode};
+use dora_core::{
+    config::{DataId, InputMapping, NodeId},
+    uhlc,
+};
+use eyre::{bail, eyre, Context};
 
 mod event;
+mod input_stream;
 pub mod merged;
+pub(crate) mod signal;
 mod thread;
+mod timer_handler;
 
 pub struct EventStream {
     node_id: NodeId,
     receiver: flume::r#async::RecvStream<'static, EventItem>,
+    /// Control events that were fetched while inputs were pending, which
+    /// take precedence over the events of `receiver`.
+    control_events: flume::r#async::RecvStream<'static, EventItem>,
+    /// What happens to inputs that are received after a `Stop` event.
+    inputs_after_stop: InputsAfterStop,
+    stopped: bool,
+    /// Notified when the node was stopped by a termination signal.
+    stop_signal: Option<flume::r#async::RecvStream<'static, ()>>,
     _thread_handle: EventStreamThreadHandle,
     close_channel: DaemonChannel,
     clock: Arc<uhlc::HLC>,
+    input_router: InputRouter,
+    /// Inputs that were split off through [`Self::input_stream`].
+    input_streams: BTreeSet<DataId>,
+    /// Errors of the handlers registered through [`Self::on_timer`].
+    handler_errors_tx: flume::Sender<String>,
+    handler_errors: flume::r#async::RecvStream<'static, String>,
 }
 
 impl EventStream {
@@ -38,6 +68,8 @@ impl EventStream {
         dataflow_id: DataflowId,
         node_id: &NodeId,
         daemon_communication: &DaemonCommunication,
+        interest: EventInterest,
+        control_events: bool,
         clock: Arc<uhlc::HLC>,
     ) -> eyre::Result<Self> {
         let channel = match daemon_communication {
@@ -76,7 +108,15 @@ impl EventStream {
             }
         };
 
-        Self::init_on_channel(dataflow_id, node_id, channel, close_channel, clock)
+        Self::init_on_channel(
+            dataflow_id,
+            node_id,
+            channel,
+            close_channel,
+            interest,
+            control_events,
+            clock,
+        )
     }
 
     pub(crate) fn init_on_channel(
@@ -84,12 +124,14 @@ impl EventStream {
         node_id: &NodeId,
         mut channel: DaemonChannel,
         mut close_channel: DaemonChannel,
+        interest: EventInterest,
+        control_events: bool,
         clock: Arc<uhlc::HLC>,
     ) -> eyre::Result<Self> {
         channel.register(dataflow_id, node_id.clone(), clock.new_timestamp())?;
         let reply = channel
             .request(&Timestamped {
-                inner: DaemonRequest::Subscribe,
+                inner: DaemonRequest::Subscribe { interest },
                 timestamp: clock.new_timestamp(),
             })
             .map_err(|e| eyre!(e))
@@ -106,17 +148,132 @@ impl EventStream {
         close_channel.register(dataflow_id, node_id.clone(), clock.new_timestamp())?;
 
         let (tx, rx) = flume::bounded(0);
-        let thread_handle = thread::init(node_id.clone(), tx, channel, clock.clone())?;
+        // older daemons don't support fetching control events out of order
+        let (control_tx, control_rx) = flume::unbounded();
+        let control_tx = control_events.then_some(control_tx);
+        let (handler_errors_tx, handler_errors) = flume::unbounded();
+        let input_router = InputRouter::default();
+        let thread_handle = thread::init(
+            node_id.clone(),
+            tx,
+            control_tx,
+            input_router.clone(),
+            channel,
+            clock.clone(),
+        )?;
 
         Ok(EventStream {
             node_id: node_id.clone(),
             receiver: rx.into_stream(),
+            control_events: control_rx.into_stream(),
+            inputs_after_stop: InputsAfterStop::default(),
+            stopped: false,
+            stop_signal: None,
             _thread_handle: thread_handle,
             close_channel,
             clock,
+            input_router,
+            input_streams: BTreeSet::new(),
+            handler_errors_tx,
+            handler_errors: handler_errors.into_stream(),
         })
     }
 
+    /// Yields a final `Stop` event when the given channel is notified.
+    pub(crate) fn set_stop_signal(&mut self, stop_signal: flume::Receiver<()>) {
+        self.stop_signal = Some(stop_signal.into_stream());
+    }
+
+    /// Splits off the messages of the given input into a separate stream.
+    ///
+    /// This allows handling inputs independently of each other, e.g. in
+    /// separate tasks, so that a slow handler for one input doesn't delay the
+    /// others. Messages of the input are no longer yielded by this event
+    /// stream, including its `InputClosed` event. Instead, the returned
+    /// stream ends when the input is closed. Messages that were received
+    /// before this call are still yielded by this event stream.
+    ///
+    /// Input streams only receive messages as long as this event stream is
+    /// not dropped.
+    ///
+    /// Returns an error if a stream was already created for the input.
+    pub fn input_stream(
+        &mut self,
+        id: DataId,
+        config: InputStreamConfig,
+    ) -> eyre::Result<InputStream> {
+        if !self.input_streams.insert(id.clone()) {
+            bail!("input `{id}` already has a separate input stream");
+        }
+        Ok(self.input_router.add(id, config))
+    }
+
+    /// Invokes the given handler for every tick of the given timer input,
+    /// e.g. to publish the current state of the node periodically.
+    ///
+    /// The handler runs on a separate thread, concurrently with the main
+    /// input loop, until the input is closed. Its future is executed with a
+    /// simple executor, so it can't rely on runtime-specific functionality
+    /// like `tokio::time::sleep`. Outputs are sent through
+    /// [`TimerContext::node`], which locks the given node.
+    ///
+    /// If the handler takes longer than the timer interval, ticks are
+    /// skipped or queued according to `backpressure`.
+    ///
+    /// Errors returned by the handler are yielded as [`Event::Error`] by this
+    /// event stream. If the handler panics, the panic is reported the same
+    /// way and the handler is not invoked anymore.
+    ///
+    /// Returns an error if the input is not a timer input or if it was split
+    /// off already, e.g. through [`Self::input_stream`].
+    pub fn on_timer<F, Fut>(
+        &mut self,
+        node: &Arc<Mutex<DoraNode>>,
+        id: DataId,
+        backpressure: TimerBackpressure,
+        handler: F,
+    ) -> eyre::Result<()>
+    where
+        F: FnMut(TimerContext) -> Fut + Send + 'static,
+        Fut: Future<Output = eyre::Result<()>>,
+    {
+        let interval = {
+            let node = node.lock().unwrap_or_else(|err| err.into_inner());
+            match node
+                .node_config()
+                .inputs
+                .get(&id)
+                .map(|input| &input.mapping)
+            {
+                Some(InputMapping::Timer { interval }) => *interval,
+                Some(_) => bail!("input `{id}` is not a timer input"),
+                None => bail!("node has no input `{id}`"),
+            }
+        };
+        let ticks = self.input_stream(id.clone(), backpressure.stream_config())?;
+        timer_handler::spawn(
+            id,
+            interval,
+            ticks,
+            node.clone(),
+            handler,
+            self.handler_errors_tx.clone(),
+        )
+        .wrap_err("failed to spawn timer handler thread")
+    }
+
+    /// Sets what happens to the inputs that are still buffered or arrive
+    /// after a [`Event::Stop`] was received.
+    ///
+    /// `Stop` events are delivered before the inputs that were queued
+    /// before them, so that a node with a backlog of inputs can react
+    /// immediately. By default, the buffered inputs are still delivered
+    /// afterwards. Inputs of streams split off through
+    /// [`Self::input_stream`] are not affected.
+    pub fn set_inputs_after_stop(&mut self, policy: InputsAfterStop) {
+        self.inputs_after_stop = policy;
+    }
+
     /// wait for the next event on the events stream.
     pub fn recv(&mut self) -> Option<Event> {
         futures::executor::block_on(self.recv_async())
@@ -128,17 +285,16 @@ impl EventStream {
     }
 
     pub async fn recv_async(&mut self) -> Option<Event> {
-        self.receiver.next().await.map(Self::convert_event_item)
+        self.next().await
     }
 
     pub async fn recv_async_timeout(&mut self, dur: Duration) -> Option<Event> {
-        let next_event = match select(Delay::new(dur), self.receiver.next()).await {
-            Either::Left((_elapsed, _)) => {
-                Some(EventItem::TimeoutError(eyre!("Receiver timed out")))
-            }
+        match select(Delay::new(dur), self.next()).await {
+            Either::Left((_elapsed, _)) => Some(Self::convert_event_item(EventItem::TimeoutError(
+                eyre!("Receiver timed out"),
+            ))),
             Either::Right((event, _)) => event,
-        };
-        next_event.map(Self::convert_event_item)
+        }
     }
 
     fn convert_event_item(item: EventItem) -> Event {
@@ -146,42 +302,34 @@ impl EventStream {
             EventItem::NodeEvent { event, ack_channel } => match event {
                 NodeEvent::Stop => Event::Stop,
                 NodeEvent::Reload { operator_id } => Event::Reload { operator_id },
-                NodeEvent::InputClosed { id } => Event::InputClosed { id },
+                NodeEvent::InputClosed {
+                    id,
+                    delivered,
+                    last_timestamp,
+                } => Event::InputClosed {
+                    id,
+                    delivered,
+                    last_timestamp,
+                },
+                NodeEvent::SubscribersChanged {
+                    output_id,
+                    subscribers,
+                } => Event::SubscribersChanged {
+                    output_id,
+                    subscribers,
+                },
+                NodeEvent::InputTimeout { id, elapsed } => Event::InputTimeout { id, elapsed },
                 NodeEvent::Input { id, metadata, data } => {
-                    let data = match data {
-                        None => Ok(None),
-                        Some(DataMessage::Vec(v)) => Ok(Some(RawData::Vec(v))),
-                        Some(DataMessage::SharedMemory {
-                            shared_memory_id,
-                            len,
-                            drop_token: _, // handled in `event_stream_loop`
-                        }) => unsafe {
-                            MappedInputData::map(&shared_memory_id, len).map(|data| {
-                                Some(RawData::SharedMemory(SharedMemoryData {
-                                    data,
-                                    _drop: ack_channel,
-                                }))
-                            })
-                        },
-                    };
-                    let data = data.and_then(|data| {
-                        let raw_data = data.unwrap_or(RawData::Empty);
-                        raw_data
-                            .into_arrow_array(&metadata.type_info)
-                            .map(arrow::array::make_array)
-                    });
-                    match data {
-                        Ok(data) => Event::Input {
-                            id,
-                            metadata,
-                            data: data.into(),
-                        },
+                    match Self::input_data(data, &metadata.type_info, ack_channel) {
+                        Ok(data) => Event::Input { id, metadata, data },
                         Err(err) => Event::Error(format!("{err:?}")),
                     }
                 }
-                NodeEvent::AllInputsClosed => {
+                NodeEvent::AllInputsClosed
+                | NodeEvent::LatestAvailable { .. }
+                | NodeEvent::InputBatch { .. } => {
                     let err = eyre!(
-                        "received `AllInputsClosed` event, which should be handled by background task"
+                        "received `{event:?}` event, which should be handled by background task"
                     );
                     tracing::error!("{err:?}");
                     Event::Error(err.wrap_err("internal error").to_string())
@@ -198,6 +346,37 @@ impl EventStream {
     }
 }
 
+impl EventStream {
+    /// Maps the data of a received input into an arrow array.
+    ///
+    /// The `ack_channel` is dropped when the returned data is no longer
+    /// accessed, which reports the drop token of shared memory messages.
+    fn input_data(
+        data: Option<DataMessage>,
+        type_info: &ArrowTypeInfo,
+        ack_channel: flume::Sender<()>,
+    ) -> eyre::Result<ArrowData> {
+        let data = match data {
+            None => None,
+            Some(DataMessage::Vec(v)) => Some(RawData::Vec(v)),
+            Some(DataMessage::SharedMemory {
+                shared_memory_id,
+                len,
+                drop_token: _, // handled in `event_stream_loop`
+            }) => {
+                let data = unsafe { MappedInputData::map(&shared_memory_id, len)? };
+                Some(RawData::SharedMemory(SharedMemoryData {
+                    data,
+                    _drop: ack_channel,
+                }))
+            }
+        };
+        let raw_data = data.unwrap_or(RawData::Empty);
+        let array = raw_data.into_arrow_array(type_info)?;
+        Ok(arrow::array::make_array(array).into())
+    }
+}
+
 impl Stream for EventStream {
     type Item = Event;
 
@@ -205,12 +384,66 @@ impl Stream for EventStream {
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        self.receiver
-            .poll_next_unpin(cx)
-            .map(|item| item.map(Self::convert_event_item))
+        // a termination signal takes precedence over pending events
+        if let Some(stop_signal) = &mut self.stop_signal {
+            if let std::task::Poll::Ready(Some(())) = stop_signal.poll_next_unpin(cx) {
+                self.stopped = true;
+                return std::task::Poll::Ready(Some(Event::Stop));
+            }
+        }
+        if let std::task::Poll::Ready(Some(error)) = self.handler_errors.poll_next_unpin(cx) {
+            return std::task::Poll::Ready(Some(Event::Error(error)));
+        }
+        let item = match self.control_events.poll_next_unpin(cx) {
+            std::task::Poll::Ready(Some(item)) => item,
+            _ => loop {
+                match self.receiver.poll_next_unpin(cx) {
+                    std::task::Poll::Ready(Some(item)) if self.discards(&item) => {
+                        // dropping the item reports its drop token
+                        continue;
+                    }
+                    std::task::Poll::Ready(Some(item)) => break item,
+                    std::task::Poll::Ready(None) => return std::task::Poll::Ready(None),
+                    std::task::Poll::Pending => return std::task::Poll::Pending,
+                }
+            },
+        };
+        let event = Self::convert_event_item(item);
+        if let Event::Stop = event {
+            self.stopped = true;
+        }
+        std::task::Poll::Ready(Some(event))
+    }
+}
+
+impl EventStream {
+    /// Whether the given item is an input that is discarded because the
+    /// node was stopped already.
+    fn discards(&self, item: &EventItem) -> bool {
+        self.stopped
+            && self.inputs_after_stop == InputsAfterStop::Discard
+            && matches!(
+                item,
+                EventItem::NodeEvent {
+                    event: NodeEvent::Input { .. },
+                    ..
+                }
+            )
     }
 }
 
+/// What happens to the inputs that an [`EventStream`] receives after a
+/// [`Event::Stop`], see [`EventStream::set_inputs_after_stop`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputsAfterStop {
+    /// The inputs are delivered after the `Stop` event.
+    #[default]
+    Drain,
+    /// The inputs are dropped without being delivered. `InputClosed` and
+    /// error events are still delivered.
+    Discard,
+}
+
 impl Drop for EventStream {
     #[tracing::instrument(skip(self), fields(%self.node_id))]
     fn drop(&mut self) {
@@ -233,3 +466,102 @@ impl Drop for EventStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aligned_vec::AVec;
+    use arrow::{array::Array, datatypes::DataType};
+    use dora_message::metadata::{ArrowTypeInfo, Metadata};
+
+    fn received(type_info: ArrowTypeInfo, data: Option<DataMessage>) -> Event {
+        let clock = uhlc::HLC::default();
+        let (ack_channel, _) = flume::bounded(0);
+        EventStream::convert_event_item(EventItem::NodeEvent {
+            event: NodeEvent::Input {
+                id: "tick".to_owned().into(),
+                metadata: Metadata::new(clock.new_timestamp(), type_info),
+                data,
+            },
+            ack_channel,
+        })
+    }
+
+    #[test]
+    fn zero_length_inputs_are_empty_arrays() {
+        // metadata-only messages and empty buffers, as sent by older nodes,
+        // must be indistinguishable for the receiver
+        let empty_buffer = Some(DataMessage::Vec(AVec::from_slice(1, &[])));
+        for data in [None, empty_buffer] {
+            match received(ArrowTypeInfo::byte_array(0), data) {
+                Event::Input { data, .. } => {
+                    assert_eq!(data.data_type(), &DataType::UInt8);
+                    assert!(data.is_empty());
+                }
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+
+        match received(ArrowTypeInfo::empty(), None) {
+            Event::Input { data, .. } => {
+                assert_eq!(data.data_type(), &DataType::Null);
+                assert!(data.is_empty());
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[test]
+    fn shared_memory_inputs_are_not_copied() {
+        let len = 8 << 20;
+        let mut memory = shared_memory_extended::ShmemConf::new()
+            .size(len)
+            .create()
+            .unwrap();
+        unsafe { memory.as_slice_mut()[..4].copy_from_slice(b"dora") };
+
+        let clock = uhlc::HLC::default();
+        let (ack_channel, ack_rx) = flume::bounded(0);
+        let event = EventStream::convert_event_item(EventItem::NodeEvent {
+            event: NodeEvent::Input {
+                id: "image".to_owned().into(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::byte_array(len)),
+                data: Some(DataMessage::SharedMemory {
+                    shared_memory_id: memory.get_os_id().to_owned(),
+                    len,
+                    drop_token: dora_message::common::DropToken::generate(),
+                }),
+            },
+            ack_channel,
+        });
+        let Event::Input { data, .. } = event else {
+            panic!("unexpected event {event:?}");
+        };
+        let bytes = data.as_bytes().unwrap();
+        assert_eq!(bytes.len(), len);
+        assert_eq!(&bytes[..4], b"dora");
+        #[cfg(target_os = "linux")]
+        {
+            // next to the writable mapping of the sender, the region is
+            // mapped a second time without write access
+            let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+            let name = memory.get_os_id().trim_start_matches('/');
+            let permissions: Vec<_> = maps
+                .lines()
+                .filter(|line| line.ends_with(&format!("/dev/shm/{name}")))
+                .filter_map(|line| line.split_whitespace().nth(1))
+                .collect();
+            assert!(permissions.contains(&"r--s"), "{permissions:?}");
+        }
+
+        // the input borrows the mapped region, so writes of the sender are visible
+        unsafe { memory.as_slice_mut()[..4].copy_from_slice(b"rs!!") };
+        assert_eq!(&data.as_bytes().unwrap()[..4], b"rs!!");
+
+        // the drop token is only reported once the data is dropped
+        assert_eq!(ack_rx.try_recv(), Err(flume::TryRecvError::Empty));
+        let copy = data.into_vec().unwrap();
+        assert_eq!(&copy[..4], b"rs!!");
+        assert_eq!(ack_rx.try_recv(), Err(flume::TryRecvError::Disconnected));
+    }
+}
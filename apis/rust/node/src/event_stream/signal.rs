@@ -0,0 +1,141 @@
+//! Converts `SIGINT` and `SIGTERM` into a clean stop of the node.
+//!
+//! Without this, a node that is terminated through a signal (e.g. when pressing
+//! Ctrl-C in the terminal) exits without closing its outputs, which the daemon
+//! treats like a crash. With the handler installed, a signal finishes the
+//! outputs of the node like a drop of the [`DoraNode`][crate::DoraNode] does:
+//! in-flight sends are flushed and the outputs are reported as done to the
+//! daemon. Afterwards, the event stream yields a final
+//! [`Stop`][super::Event::Stop] event, so that the node can clean up. A second
+//! signal aborts the node immediately.
+//!
+//! The handler is only installed if no other handler is set up for these
+//! signals, e.g. by the Python interpreter or by the node itself. Nodes can
+//! also opt out through [`NodeInitOptions::stop_on_signal`][crate::NodeInitOptions::stop_on_signal].
+
+use crate::node::OutputSender;
+use std::sync::{Mutex, OnceLock, PoisonError, Weak};
+
+static HANDLER_INSTALLED: OnceLock<bool> = OnceLock::new();
+static SUBSCRIBERS: Mutex<Subscribers> = Mutex::new(Subscribers::new());
+
+/// Returns a channel that is notified when a termination signal is received,
+/// after the outputs of the given sender were finished.
+///
+/// Installs the signal handler on first use. Returns `None` if another signal
+/// handler is installed already.
+pub(crate) fn subscribe(sender: Weak<OutputSender>) -> Option<flume::Receiver<()>> {
+    let installed = *HANDLER_INSTALLED.get_or_init(|| {
+        if foreign_handler_installed() {
+            tracing::debug!("not converting signals to stop events: custom handler is installed");
+            return false;
+        }
+        let mut received = false;
+        let result = ctrlc::set_handler(move || {
+            if received {
+                tracing::warn!("received second termination signal -> aborting immediately");
+                std::process::abort();
+            }
+            tracing::info!("received termination signal -> stopping node");
+            received = true;
+            // finishing the outputs might take a while, so don't block the
+            // handler thread, which needs to react to a second signal
+            std::thread::spawn(|| {
+                let subscribers = SUBSCRIBERS
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take();
+                subscribers.notify();
+            });
+        });
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::debug!("not converting signals to stop events: {err}");
+                false
+            }
+        }
+    });
+    installed.then(|| {
+        SUBSCRIBERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .subscribe(sender)
+    })
+}
+
+/// Checks whether a handler other than the default is set for `SIGINT` or
+/// `SIGTERM`, which the handler of this module would replace.
+#[cfg(unix)]
+fn foreign_handler_installed() -> bool {
+    [libc::SIGINT, libc::SIGTERM].into_iter().any(|signal| {
+        let mut current = std::mem::MaybeUninit::<libc::sigaction>::uninit();
+        // passing no new action only queries the current one
+        let result = unsafe { libc::sigaction(signal, std::ptr::null(), current.as_mut_ptr()) };
+        result == 0 && unsafe { current.assume_init() }.sa_sigaction != libc::SIG_DFL
+    })
+}
+
+#[cfg(not(unix))]
+fn foreign_handler_installed() -> bool {
+    false
+}
+
+struct Subscriber {
+    /// Finished before the event stream is notified, unless the node was
+    /// dropped already.
+    sender: Weak<OutputSender>,
+    stop: flume::Sender<()>,
+}
+
+struct Subscribers(Vec<Subscriber>);
+
+impl Subscribers {
+    const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    fn subscribe(&mut self, sender: Weak<OutputSender>) -> flume::Receiver<()> {
+        // forget about event streams that were dropped already
+        self.0
+            .retain(|subscriber| !subscriber.stop.is_disconnected());
+        let (stop, rx) = flume::bounded(1);
+        self.0.push(Subscriber { sender, stop });
+        rx
+    }
+
+    fn take(&mut self) -> Self {
+        Self(std::mem::take(&mut self.0))
+    }
+
+    fn notify(self) {
+        for subscriber in self.0 {
+            if let Some(sender) = subscriber.sender.upgrade() {
+                sender.finish();
+            }
+            // fails if the event stream was dropped already
+            let _ = subscriber.stop.try_send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_notifies_subscribers() {
+        let mut subscribers = Subscribers::new();
+        let dropped = subscribers.subscribe(Weak::new());
+        drop(dropped);
+        let first = subscribers.subscribe(Weak::new());
+        assert_eq!(subscribers.0.len(), 1);
+        let second = subscribers.subscribe(Weak::new());
+
+        // notifying must not block on dropped nodes or event streams
+        drop(second);
+        subscribers.take().notify();
+        assert_eq!(first.try_recv(), Ok(()));
+        assert!(subscribers.0.is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use dora_core::{
-    config::NodeId,
+    config::{DataId, NodeId},
     uhlc::{self, Timestamp},
 };
 use dora_message::{
@@ -9,20 +9,35 @@ use dora_message::{
 use eyre::{eyre, Context};
 use flume::RecvTimeoutError;
 use std::{
+    collections::VecDeque,
     sync::Arc,
     time::{Duration, Instant},
 };
 
+use super::input_stream::InputRouter;
 use crate::daemon_connection::DaemonChannel;
 
+/// Interval in which control events are fetched from the daemon while the
+/// node is busy with earlier events.
+const CONTROL_EVENT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Starts the thread that receives the events of the node.
+///
+/// If `control_tx` is set, `Stop` and `InputClosed` events that arrive while
+/// the node is busy are fetched out of order and sent to `control_tx`
+/// instead of waiting behind the pending inputs on `tx`.
 pub fn init(
     node_id: NodeId,
     tx: flume::Sender<EventItem>,
+    control_tx: Option<flume::Sender<EventItem>>,
+    input_router: InputRouter,
     channel: DaemonChannel,
     clock: Arc<uhlc::HLC>,
 ) -> eyre::Result<EventStreamThreadHandle> {
     let node_id_cloned = node_id.clone();
-    let join_handle = std::thread::spawn(|| event_stream_loop(node_id_cloned, tx, channel, clock));
+    let join_handle = std::thread::spawn(|| {
+        event_stream_loop(node_id_cloned, tx, control_tx, input_router, channel, clock)
+    });
     Ok(EventStreamThreadHandle::new(node_id, join_handle))
 }
 
@@ -77,10 +92,12 @@ impl Drop for EventStreamThreadHandle {
     }
 }
 
-#[tracing::instrument(skip(tx, channel, clock))]
+#[tracing::instrument(skip(tx, control_tx, input_router, channel, clock))]
 fn event_stream_loop(
     node_id: NodeId,
     tx: flume::Sender<EventItem>,
+    mut control_tx: Option<flume::Sender<EventItem>>,
+    input_router: InputRouter,
     mut channel: DaemonChannel,
     clock: Arc<uhlc::HLC>,
 ) {
@@ -99,13 +116,13 @@ fn event_stream_loop(
             },
             timestamp: clock.new_timestamp(),
         };
-        let events = match channel.request(&daemon_request) {
+        let mut events = match channel.request(&daemon_request) {
             Ok(DaemonReply::NextEvents(events)) => {
                 if events.is_empty() {
                     tracing::trace!("event stream closed for node `{node_id}`");
                     break Ok(());
                 } else {
-                    events
+                    VecDeque::from(events)
                 }
             }
             Ok(other) => {
@@ -119,31 +136,75 @@ fn event_stream_loop(
                 continue;
             }
         };
-        for Timestamped { inner, timestamp } in events {
-            if let Err(err) = clock.update_with_timestamp(&timestamp) {
-                tracing::warn!("failed to update HLC: {err}");
-            }
-            let drop_token = match &inner {
-                NodeEvent::Input {
-                    data: Some(data), ..
-                } => data.drop_token(),
-                NodeEvent::AllInputsClosed => {
-                    // close the event stream
-                    tx = None;
-                    // skip this internal event
-                    continue;
-                }
-                _ => None,
+        while let Some(event) = events.pop_front() {
+            // messages of `latest` inputs are only fetched right before they are
+            // forwarded, so that they can still be replaced by newer messages
+            let resolved = match event.inner {
+                NodeEvent::LatestAvailable { id } => take_latest(&mut channel, id, &clock),
+                // batches are only a transport optimization, the node sees
+                // the individual messages
+                NodeEvent::InputBatch { id, messages } => messages
+                    .into_iter()
+                    .map(|message| Timestamped {
+                        inner: message.into_event(id.clone()),
+                        timestamp: event.timestamp,
+                    })
+                    .collect(),
+                inner => vec![Timestamped {
+                    inner,
+                    timestamp: event.timestamp,
+                }],
             };
+            for Timestamped { inner, timestamp } in resolved {
+                if let Err(err) = clock.update_with_timestamp(&timestamp) {
+                    tracing::warn!("failed to update HLC: {err}");
+                }
+                let drop_token = match &inner {
+                    NodeEvent::Input {
+                        data: Some(data), ..
+                    } => data.drop_token(),
+                    NodeEvent::AllInputsClosed => {
+                        // close the event stream
+                        tx = None;
+                        control_tx = None;
+                        input_router.close_all();
+                        // skip this internal event
+                        continue;
+                    }
+                    _ => None,
+                };
 
-            if let Some(tx) = tx.as_ref() {
                 let (drop_tx, drop_rx) = flume::bounded(0);
-                match tx.send(EventItem::NodeEvent {
+                let item = EventItem::NodeEvent {
                     event: inner,
                     ack_channel: drop_tx,
-                }) {
-                    Ok(()) => {}
-                    Err(send_error) => {
+                };
+                // inputs with a separate input stream are not sent to `tx`
+                let item = input_router.route(item);
+                if let Some(token) = drop_token {
+                    pending_drop_tokens.push((token, drop_rx, Instant::now(), 1));
+                }
+
+                if let Some(item) = item {
+                    let Some(tx) = tx.as_ref() else {
+                        tracing::warn!(
+                            "dropping event because event `tx` was already closed: `{item:?}`"
+                        );
+                        continue;
+                    };
+                    let result = match control_tx.as_ref() {
+                        Some(control_tx) => send_watching_control_events(
+                            item,
+                            tx,
+                            control_tx,
+                            &mut channel,
+                            &clock,
+                            &input_router,
+                            &mut events,
+                        ),
+                        None => tx.send(item),
+                    };
+                    if let Err(send_error) = result {
                         let event = send_error.into_inner();
                         tracing::trace!(
                             "event channel was closed already, could not forward `{event:?}`"
@@ -152,15 +213,10 @@ fn event_stream_loop(
                         break 'outer Ok(());
                     }
                 }
-
-                if let Some(token) = drop_token {
-                    pending_drop_tokens.push((token, drop_rx, Instant::now(), 1));
-                }
-            } else {
-                tracing::warn!("dropping event because event `tx` was already closed: `{inner:?}`");
             }
         }
     };
+    input_router.close_all();
     if let Err(err) = result {
         if let Some(tx) = tx.as_ref() {
             if let Err(flume::SendError(item)) = tx.send(EventItem::FatalError(err)) {
@@ -187,6 +243,126 @@ fn event_stream_loop(
     }
 }
 
+/// Sends the given item to `tx`, fetching the control events from the daemon
+/// while the node doesn't receive it.
+///
+/// `Stop` events and `InputClosed` events of inputs without pending messages
+/// are sent to `control_tx`, which the event stream checks first. Other
+/// `InputClosed` events are added to the `pending` events after the last
+/// message of their input.
+fn send_watching_control_events(
+    mut item: EventItem,
+    tx: &flume::Sender<EventItem>,
+    control_tx: &flume::Sender<EventItem>,
+    channel: &mut DaemonChannel,
+    clock: &uhlc::HLC,
+    input_router: &InputRouter,
+    pending: &mut VecDeque<Timestamped<NodeEvent>>,
+) -> Result<(), flume::SendError<EventItem>> {
+    loop {
+        match tx.send_timeout(item, CONTROL_EVENT_POLL_INTERVAL) {
+            Ok(()) => return Ok(()),
+            Err(flume::SendTimeoutError::Disconnected(item)) => return Err(flume::SendError(item)),
+            Err(flume::SendTimeoutError::Timeout(unsent)) => item = unsent,
+        }
+        for event in take_control_events(channel, clock) {
+            if let Err(err) = clock.update_with_timestamp(&event.timestamp) {
+                tracing::warn!("failed to update HLC: {err}");
+            }
+            if let NodeEvent::InputClosed { id, .. } = &event.inner {
+                let unsent_message = match &item {
+                    EventItem::NodeEvent { event, .. } => message_input(event) == Some(id),
+                    _ => false,
+                };
+                let last_pending = pending
+                    .iter()
+                    .rposition(|pending| message_input(&pending.inner) == Some(id));
+                match last_pending {
+                    Some(position) => {
+                        pending.insert(position + 1, event);
+                        continue;
+                    }
+                    None if unsent_message => {
+                        pending.push_front(event);
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+            let (ack_channel, _) = flume::bounded(0);
+            let control_item = EventItem::NodeEvent {
+                event: event.inner,
+                ack_channel,
+            };
+            if let Some(control_item) = input_router.route(control_item) {
+                if control_tx.send(control_item).is_err() {
+                    tracing::trace!("control event channel was closed already");
+                }
+            }
+        }
+    }
+}
+
+/// Takes the control events that may pass the queued events from the daemon.
+fn take_control_events(
+    channel: &mut DaemonChannel,
+    clock: &uhlc::HLC,
+) -> Vec<Timestamped<NodeEvent>> {
+    let daemon_request = Timestamped {
+        inner: DaemonRequest::NextControlEvents,
+        timestamp: clock.new_timestamp(),
+    };
+    match channel.request(&daemon_request) {
+        Ok(DaemonReply::NextEvents(events)) => events,
+        Ok(other) => {
+            tracing::warn!("unexpected NextControlEvents reply: {other:?}");
+            Vec::new()
+        }
+        Err(err) => {
+            let err = eyre!(err).wrap_err("failed to take control events");
+            tracing::warn!("{err:?}");
+            Vec::new()
+        }
+    }
+}
+
+/// The input of the given message event, if it is one.
+fn message_input(event: &NodeEvent) -> Option<&DataId> {
+    match event {
+        NodeEvent::Input { id, .. }
+        | NodeEvent::InputBatch { id, .. }
+        | NodeEvent::LatestAvailable { id } => Some(id),
+        _ => None,
+    }
+}
+
+/// Takes the pending message of the given `latest` input from the daemon.
+///
+/// Returns an empty list if there is no message (e.g. because it was already
+/// taken).
+fn take_latest(
+    channel: &mut DaemonChannel,
+    id: DataId,
+    clock: &uhlc::HLC,
+) -> Vec<Timestamped<NodeEvent>> {
+    let daemon_request = Timestamped {
+        inner: DaemonRequest::TakeLatest { id },
+        timestamp: clock.new_timestamp(),
+    };
+    match channel.request(&daemon_request) {
+        Ok(DaemonReply::NextEvents(events)) => events,
+        Ok(other) => {
+            tracing::warn!("unexpected TakeLatest reply: {other:?}");
+            Vec::new()
+        }
+        Err(err) => {
+            let err = eyre!(err).wrap_err("failed to take latest input");
+            tracing::warn!("{err:?}");
+            Vec::new()
+        }
+    }
+}
+
 fn handle_pending_drop_tokens(
     pending_drop_tokens: &mut Vec<(DropToken, flume::Receiver<()>, Instant, u64)>,
     drop_tokens: &mut Vec<DropToken>,
@@ -0,0 +1,197 @@
+//! Handlers that are invoked for every tick of a timer input, see
+//! [`EventStream::on_timer`][super::EventStream::on_timer].
+
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use dora_core::config::DataId;
+use dora_message::metadata::Metadata;
+use futures::{FutureExt, Stream, StreamExt};
+
+use super::{BufferFullPolicy, Input, InputStreamConfig};
+use crate::DoraNode;
+
+/// A tick of a timer input.
+#[derive(Debug)]
+pub struct TimerTick {
+    /// Number of previous ticks that were passed to the handler.
+    ///
+    /// Ticks that were skipped because of back-pressure are not counted.
+    pub count: u64,
+    /// Interval of the timer, as given in the dataflow descriptor.
+    pub interval: Duration,
+    pub metadata: Metadata,
+}
+
+/// Argument of a timer handler.
+pub struct TimerContext {
+    pub tick: TimerTick,
+    node: Arc<Mutex<DoraNode>>,
+}
+
+impl TimerContext {
+    /// Locks the node, e.g. for sending outputs.
+    ///
+    /// The main input loop is blocked from using the node while the returned
+    /// guard is alive, so it should not be held across long `await`s.
+    pub fn node(&self) -> MutexGuard<'_, DoraNode> {
+        self.node.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// What happens with ticks that arrive while the timer handler is still
+/// running, i.e. when it takes longer than the timer interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimerBackpressure {
+    /// Skips the ticks, except for the most recent one, which is handled
+    /// right after the handler finished. The handler is invoked at most once
+    /// per interval this way and doesn't fall behind.
+    #[default]
+    Skip,
+    /// Queues up to the given number of ticks, which are handled one after
+    /// another. The oldest tick is dropped when the queue is full.
+    Queue(usize),
+}
+
+impl TimerBackpressure {
+    pub(super) fn stream_config(self) -> InputStreamConfig {
+        InputStreamConfig {
+            buffer_size: match self {
+                TimerBackpressure::Skip => 1,
+                TimerBackpressure::Queue(len) => len,
+            },
+            policy: BufferFullPolicy::DropOldest,
+        }
+    }
+}
+
+pub(super) fn spawn<F, Fut>(
+    id: DataId,
+    interval: Duration,
+    ticks: super::InputStream,
+    node: Arc<Mutex<DoraNode>>,
+    mut handler: F,
+    errors: flume::Sender<String>,
+) -> eyre::Result<()>
+where
+    F: FnMut(TimerContext) -> Fut + Send + 'static,
+    Fut: Future<Output = eyre::Result<()>>,
+{
+    std::thread::Builder::new()
+        .name(format!("timer-handler-{id}"))
+        .spawn(move || {
+            let handler = move |tick| {
+                handler(TimerContext {
+                    tick,
+                    node: node.clone(),
+                })
+            };
+            futures::executor::block_on(handle_ticks(id, interval, ticks, handler, errors));
+        })?;
+    Ok(())
+}
+
+/// Invokes the handler for every tick until the input is closed or the
+/// handler panics.
+///
+/// Errors and panics of the handler are reported to the event stream.
+async fn handle_ticks<F, Fut>(
+    id: DataId,
+    interval: Duration,
+    mut ticks: impl Stream<Item = Input> + Unpin,
+    mut handler: F,
+    errors: flume::Sender<String>,
+) where
+    F: FnMut(TimerTick) -> Fut,
+    Fut: Future<Output = eyre::Result<()>>,
+{
+    let mut count = 0;
+    while let Some(input) = ticks.next().await {
+        let tick = TimerTick {
+            count,
+            interval,
+            metadata: input.metadata,
+        };
+        count += 1;
+        let result = AssertUnwindSafe(async { handler(tick).await })
+            .catch_unwind()
+            .await;
+        let (error, panicked) = match result {
+            Ok(Ok(())) => continue,
+            Ok(Err(err)) => (format!("timer handler of `{id}` failed: {err:?}"), false),
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic payload");
+                let error = format!("timer handler of `{id}` panicked: {message}");
+                (error, true)
+            }
+        };
+        tracing::error!("{error}");
+        let _ = errors.send(error);
+        if panicked {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_arrow_convert::ArrowData;
+    use dora_core::uhlc;
+    use dora_message::metadata::ArrowTypeInfo;
+
+    #[test]
+    fn handler_errors_and_panics_are_reported() {
+        let clock = uhlc::HLC::default();
+        let id = DataId::from("tick".to_owned());
+        let ticks: Vec<_> = (0..4)
+            .map(|_| Input {
+                id: id.clone(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+                data: ArrowData(arrow::array::new_empty_array(
+                    &arrow::datatypes::DataType::Null,
+                )),
+            })
+            .collect();
+
+        let (errors_tx, errors) = flume::unbounded();
+        let mut handled = Vec::new();
+        let handler = |tick: TimerTick| {
+            handled.push(tick.count);
+            async move {
+                assert_eq!(tick.interval, Duration::from_millis(100));
+                match tick.count {
+                    0 => Err(eyre::eyre!("sensor not ready")),
+                    2 => panic!("state is corrupt"),
+                    _ => Ok(()),
+                }
+            }
+        };
+        futures::executor::block_on(handle_ticks(
+            id,
+            Duration::from_millis(100),
+            futures::stream::iter(ticks),
+            handler,
+            errors_tx,
+        ));
+
+        // the handler is not invoked anymore after a panic
+        assert_eq!(handled, [0, 1, 2]);
+        let errors: Vec<_> = errors.drain().collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("sensor not ready"), "{}", errors[0]);
+        assert!(
+            errors[1].contains("panicked: state is corrupt"),
+            "{}",
+            errors[1]
+        );
+    }
+}
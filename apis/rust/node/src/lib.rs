@@ -13,17 +13,47 @@
 //! dora new project_xyz --kind dataflow
 //! ```
 //!
+//! When the node process receives `SIGINT` or `SIGTERM` (e.g. through Ctrl-C),
+//! its outputs are flushed and reported as done to the daemon, and the event
+//! stream yields a final [`Event::Stop`] so that the node can clean up. This
+//! is skipped if another signal handler is installed already. Nodes can also
+//! opt out through [`NodeInitOptions::stop_on_signal`].
+//!
+//! Panics of the node process are reported to the daemon, so that the result
+//! of the dataflow contains the panic message and backtrace instead of just
+//! the exit code. Nodes with their own panic hook can opt out of this through
+//! [`disable_panic_reporting`].
+//!
 pub use arrow;
 pub use dora_arrow_convert::*;
 pub use dora_core::{self, uhlc};
 pub use dora_message::{
-    metadata::{Metadata, MetadataParameters, Parameter},
+    common::NodeJoinInfo,
+    daemon_to_node::{
+        DropEvent, DropReason, LifecycleEvent, NodeTopology, ObservedMessage, SendOutputError,
+        StopRequestError,
+    },
+    metadata::{
+        set_source_timestamp, Metadata, MetadataParameters, Parameter, CONTENT_HASH_PARAMETER,
+        SOURCE_TIMESTAMP_PARAMETER,
+    },
+    node_to_daemon::EventInterest,
     DataflowId,
 };
-pub use event_stream::{merged, Event, EventStream, MappedInputData, RawData};
+pub use event_stream::{
+    merged, BufferFullPolicy, Event, EventStream, Input, InputStream, InputStreamConfig,
+    InputsAfterStop, MappedInputData, RawData, TimerBackpressure, TimerContext, TimerTick,
+};
 pub use flume::Receiver;
-pub use node::{arrow_utils, DataSample, DoraNode, ZERO_COPY_THRESHOLD};
+pub use node::{
+    arrow_utils, panic_report::disable_panic_reporting, DataSample, DoraNode, NodeInitOptions,
+    Output, OutputRing, OutputSlot, RateLimitStats, RateLimitedOutput, RateLimitedSend,
+    SendFlowStats, SharedMemoryAllocationError, WouldBlock, DEFAULT_MAX_IN_FLIGHT_SENDS,
+    ZERO_COPY_THRESHOLD,
+};
+pub use observer::DoraObserver;
 
 mod daemon_connection;
 mod event_stream;
 mod node;
+mod observer;
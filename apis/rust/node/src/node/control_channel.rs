@@ -1,20 +1,23 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::daemon_connection::DaemonChannel;
+use crate::daemon_connection::{DaemonChannel, MultiplexedChannel};
 use dora_core::{
     config::{DataId, NodeId},
     uhlc::HLC,
 };
 use dora_message::{
-    daemon_to_node::{DaemonCommunication, DaemonReply},
+    daemon_to_node::{DaemonCommunication, DaemonReply, NodeTopology, OutputRingInfo},
     metadata::Metadata,
-    node_to_daemon::{DaemonRequest, DataMessage, Timestamped},
+    node_to_daemon::{DaemonRequest, DataMessage, DropToken, OutputRingId, Timestamped},
     DataflowId,
 };
 use eyre::{bail, eyre, Context};
 
+/// Channel for the requests of a node that are not related to its events.
+///
+/// Can be used by multiple threads at once, see [`MultiplexedChannel`].
 pub(crate) struct ControlChannel {
-    channel: DaemonChannel,
+    channel: MultiplexedChannel,
     clock: Arc<HLC>,
 }
 
@@ -24,6 +27,7 @@ impl ControlChannel {
         dataflow_id: DataflowId,
         node_id: &NodeId,
         daemon_communication: &DaemonCommunication,
+        multiplexed: bool,
         clock: Arc<HLC>,
     ) -> eyre::Result<Self> {
         let channel = match daemon_communication {
@@ -41,7 +45,7 @@ impl ControlChannel {
             }
         };
 
-        Self::init_on_channel(dataflow_id, node_id, channel, clock)
+        Self::init_on_channel(dataflow_id, node_id, channel, multiplexed, clock)
     }
 
     #[tracing::instrument(skip(channel, clock), level = "trace")]
@@ -49,17 +53,23 @@ impl ControlChannel {
         dataflow_id: DataflowId,
         node_id: &NodeId,
         mut channel: DaemonChannel,
+        multiplexed: bool,
         clock: Arc<HLC>,
     ) -> eyre::Result<Self> {
         channel.register(dataflow_id, node_id.clone(), clock.new_timestamp())?;
+        let channel = if multiplexed {
+            MultiplexedChannel::new(channel)?
+        } else {
+            MultiplexedChannel::sequential(channel)
+        };
 
         Ok(Self { channel, clock })
     }
 
-    pub fn report_outputs_done(&mut self) -> eyre::Result<()> {
+    pub fn report_outputs_done(&self) -> eyre::Result<()> {
         let reply = self
             .channel
-            .request(&Timestamped {
+            .request(Timestamped {
                 inner: DaemonRequest::OutputsDone,
                 timestamp: self.clock.new_timestamp(),
             })
@@ -73,10 +83,27 @@ impl ControlChannel {
         Ok(())
     }
 
-    pub fn report_closed_outputs(&mut self, outputs: Vec<DataId>) -> eyre::Result<()> {
+    pub fn report_ready(&self) -> eyre::Result<()> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::Ready,
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to report ready to dora-daemon")?;
+        match reply {
+            DaemonReply::Result(result) => result
+                .map_err(|e| eyre!(e))
+                .wrap_err("failed to receive ready reply from dora-daemon")?,
+            other => bail!("unexpected ready reply: {other:?}"),
+        }
+        Ok(())
+    }
+
+    pub fn report_closed_outputs(&self, outputs: Vec<DataId>) -> eyre::Result<()> {
         let reply = self
             .channel
-            .request(&Timestamped {
+            .request(Timestamped {
                 inner: DaemonRequest::CloseOutputs(outputs),
                 timestamp: self.clock.new_timestamp(),
             })
@@ -90,8 +117,70 @@ impl ControlChannel {
         Ok(())
     }
 
+    pub fn query_topology(&self) -> eyre::Result<NodeTopology> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::QueryTopology,
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to query topology from dora-daemon")?;
+        match reply {
+            DaemonReply::Topology { result } => result
+                .map_err(|e| eyre!(e))
+                .wrap_err("failed to receive topology reply from dora-daemon"),
+            other => bail!("unexpected topology reply: {other:?}"),
+        }
+    }
+
+    pub fn query_subscribers(&self, output_id: DataId) -> eyre::Result<u64> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::QuerySubscribers { output_id },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to query subscribers from dora-daemon")?;
+        match reply {
+            DaemonReply::Subscribers { result } => result
+                .map_err(|e| eyre!(e))
+                .wrap_err("failed to receive subscribers reply from dora-daemon"),
+            other => bail!("unexpected subscribers reply: {other:?}"),
+        }
+    }
+
+    pub fn request_stop(&self, grace_duration: Option<Duration>) -> eyre::Result<()> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::RequestDataflowStop { grace_duration },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send stop request to dora-daemon")?;
+        match reply {
+            DaemonReply::StopRequestResult(result) => result.map_err(eyre::Report::new),
+            other => bail!("unexpected stop request reply: {other:?}"),
+        }
+    }
+
+    pub fn report_node_error(&self, message: String) -> eyre::Result<()> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::NodeError { message },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to report node error to dora-daemon")?;
+        match reply {
+            DaemonReply::Result(result) => result
+                .map_err(|e| eyre!(e))
+                .wrap_err("failed to receive node error reply from dora-daemon"),
+            other => bail!("unexpected node error reply: {other:?}"),
+        }
+    }
+
     pub fn send_message(
-        &mut self,
+        &self,
         output_id: DataId,
         metadata: Metadata,
         data: Option<DataMessage>,
@@ -103,14 +192,103 @@ impl ControlChannel {
         };
         let reply = self
             .channel
-            .request(&Timestamped {
+            .request(Timestamped {
                 inner: request,
                 timestamp: self.clock.new_timestamp(),
             })
             .wrap_err("failed to send SendMessage request to dora-daemon")?;
         match reply {
-            DaemonReply::Empty => Ok(()),
+            DaemonReply::SendOutResult(result) => result.map_err(eyre::Report::new),
             other => bail!("unexpected SendMessage reply: {other:?}"),
         }
     }
+
+    pub fn send_empty_message(&self, output_id: DataId, metadata: Metadata) -> eyre::Result<()> {
+        let request = DaemonRequest::SendEmptyMessage {
+            output_id,
+            metadata,
+        };
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: request,
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send SendEmptyMessage request to dora-daemon")?;
+        match reply {
+            DaemonReply::SendOutResult(result) => result.map_err(eyre::Report::new),
+            other => bail!("unexpected SendEmptyMessage reply: {other:?}"),
+        }
+    }
+
+    pub fn prepare_output_ring(
+        &self,
+        output_id: DataId,
+        slot_len: usize,
+        slots: usize,
+    ) -> eyre::Result<OutputRingInfo> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::PrepareOutputRing {
+                    output_id,
+                    slot_len,
+                    slots,
+                },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send PrepareOutputRing request to dora-daemon")?;
+        match reply {
+            DaemonReply::OutputRing { result } => result.map_err(eyre::Report::new),
+            other => bail!("unexpected PrepareOutputRing reply: {other:?}"),
+        }
+    }
+
+    pub fn send_out_slot(
+        &self,
+        ring_id: OutputRingId,
+        slot_index: usize,
+        valid_len: usize,
+        metadata: Metadata,
+    ) -> eyre::Result<DropToken> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::SendOutSlot {
+                    ring_id,
+                    slot_index,
+                    valid_len,
+                    metadata,
+                },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send SendOutSlot request to dora-daemon")?;
+        match reply {
+            DaemonReply::SendOutSlotResult(result) => result.map_err(eyre::Report::new),
+            other => bail!("unexpected SendOutSlot reply: {other:?}"),
+        }
+    }
+
+    pub fn send_cached(
+        &self,
+        output_id: DataId,
+        metadata: Metadata,
+        hash: String,
+    ) -> eyre::Result<Option<DropToken>> {
+        let reply = self
+            .channel
+            .request(Timestamped {
+                inner: DaemonRequest::SendCached {
+                    output_id,
+                    metadata,
+                    hash,
+                },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send SendCached request to dora-daemon")?;
+        match reply {
+            DaemonReply::SendCachedResult(result) => result.map_err(eyre::Report::new),
+            other => bail!("unexpected SendCached reply: {other:?}"),
+        }
+    }
 }
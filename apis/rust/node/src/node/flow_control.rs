@@ -0,0 +1,211 @@
+//! Bounds the number of send requests that wait for a reply of the daemon.
+//!
+//! Every request that publishes a message holds a permit until the daemon
+//! replied to it. On multiplexed connections, the permits thus map to the
+//! requests that the daemon still works on, e.g. because of a large fan-out,
+//! and not to data in the socket buffers. Threads that send while all
+//! permits are taken wait for a permit, unless they use `try_send`, which
+//! fails with [`WouldBlock`] instead.
+
+use std::{
+    fmt,
+    sync::{Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Default maximum number of send requests of a node that wait for a reply
+/// of the daemon at the same time.
+pub const DEFAULT_MAX_IN_FLIGHT_SENDS: usize = 16;
+
+/// Error returned by the `try_send` methods if the maximum number of send
+/// requests of the node are already waiting for a reply of the daemon.
+///
+/// The error is returned as an [`eyre::Report`], so nodes can drop the
+/// message instead by checking for it:
+///
+/// ```no_run
+/// use dora_node_api::{arrow::array::UInt64Array, DoraNode, MetadataParameters, WouldBlock};
+/// use dora_node_api::dora_core::config::DataId;
+///
+/// # let (mut node, _events) = DoraNode::init_from_env()?;
+/// let output = DataId::from("counter".to_owned());
+/// let data = UInt64Array::from(vec![42]);
+/// if let Err(err) = node.try_send_output(output, MetadataParameters::default(), data) {
+///     match err.downcast_ref::<WouldBlock>() {
+///         Some(_) => eprintln!("daemon is busy, dropping message"),
+///         None => return Err(err),
+///     }
+/// }
+/// # eyre::Ok(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock {
+    /// Maximum number of send requests that wait for a reply at the same
+    /// time, see [`DoraNode::set_max_in_flight_sends`][super::DoraNode::set_max_in_flight_sends].
+    pub max_in_flight: usize,
+}
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sending would block: {} send requests are waiting for a reply of dora-daemon",
+            self.max_in_flight
+        )
+    }
+}
+
+impl std::error::Error for WouldBlock {}
+
+/// Counters of the flow control of the send requests of a node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendFlowStats {
+    /// Sends that had to wait for a permit.
+    pub blocked_sends: u64,
+    /// Total time that sends waited for a permit.
+    pub blocked_time: Duration,
+    /// `try_send` calls that failed with [`WouldBlock`].
+    pub would_block: u64,
+}
+
+pub(super) struct SendPermits {
+    state: Mutex<PermitState>,
+    released: Condvar,
+}
+
+struct PermitState {
+    max_in_flight: usize,
+    in_flight: usize,
+    stats: SendFlowStats,
+}
+
+/// Permit for a single send request, released on drop.
+pub(super) struct SendPermit<'a> {
+    permits: &'a SendPermits,
+}
+
+impl SendPermits {
+    pub(super) fn new(max_in_flight: usize) -> Self {
+        Self {
+            state: Mutex::new(PermitState {
+                max_in_flight: max_in_flight.max(1),
+                in_flight: 0,
+                stats: SendFlowStats::default(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, PermitState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Waits until a permit is free.
+    pub(super) fn acquire(&self) -> SendPermit<'_> {
+        let mut state = self.state();
+        if state.in_flight >= state.max_in_flight {
+            let start = Instant::now();
+            while state.in_flight >= state.max_in_flight {
+                state = self
+                    .released
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner());
+            }
+            state.stats.blocked_sends += 1;
+            state.stats.blocked_time += start.elapsed();
+        }
+        state.in_flight += 1;
+        SendPermit { permits: self }
+    }
+
+    /// Takes a free permit, or fails with [`WouldBlock`] if there is none.
+    pub(super) fn try_acquire(&self) -> Result<SendPermit<'_>, WouldBlock> {
+        let mut state = self.state();
+        if state.in_flight >= state.max_in_flight {
+            state.stats.would_block += 1;
+            return Err(WouldBlock {
+                max_in_flight: state.max_in_flight,
+            });
+        }
+        state.in_flight += 1;
+        Ok(SendPermit { permits: self })
+    }
+
+    /// Sets the number of permits, which is at least one.
+    pub(super) fn set_max_in_flight(&self, max_in_flight: usize) {
+        self.state().max_in_flight = max_in_flight.max(1);
+        self.released.notify_all();
+    }
+
+    pub(super) fn stats(&self) -> SendFlowStats {
+        self.state().stats
+    }
+
+    /// Waits until no permit is in use, i.e. until all send requests were
+    /// replied to. Returns `false` on timeout.
+    pub(super) fn wait_idle(&self, timeout: Duration) -> bool {
+        let (state, _) = self
+            .released
+            .wait_timeout_while(self.state(), timeout, |state| state.in_flight > 0)
+            .unwrap_or_else(|err| err.into_inner());
+        state.in_flight == 0
+    }
+}
+
+impl Drop for SendPermit<'_> {
+    fn drop(&mut self) {
+        self.permits.state().in_flight -= 1;
+        // wakes both a waiting sender and `wait_idle`
+        self.permits.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_wait_for_free_permits() {
+        let permits = SendPermits::new(2);
+        let first = permits.acquire();
+        let _second = permits.acquire();
+        assert_eq!(
+            permits.try_acquire().err(),
+            Some(WouldBlock { max_in_flight: 2 })
+        );
+
+        std::thread::scope(|s| {
+            let blocked = s.spawn(|| drop(permits.acquire()));
+            std::thread::sleep(Duration::from_millis(50));
+            drop(first);
+            blocked.join().unwrap();
+        });
+
+        let stats = permits.stats();
+        assert_eq!(stats.blocked_sends, 1);
+        assert!(stats.blocked_time >= Duration::from_millis(40), "{stats:?}");
+        assert_eq!(stats.would_block, 1);
+
+        // raising the limit frees permits right away
+        permits.set_max_in_flight(3);
+        let _third = permits.acquire();
+        assert!(permits.try_acquire().is_err());
+        assert_eq!(permits.stats().blocked_sends, 1);
+    }
+
+    #[test]
+    fn wait_idle_waits_for_in_flight_sends() {
+        let permits = SendPermits::new(2);
+        assert!(permits.wait_idle(Duration::ZERO));
+
+        let permit = permits.acquire();
+        assert!(!permits.wait_idle(Duration::from_millis(10)));
+
+        std::thread::scope(|s| {
+            let waiting = s.spawn(|| permits.wait_idle(Duration::from_secs(10)));
+            std::thread::sleep(Duration::from_millis(50));
+            drop(permit);
+            assert!(waiting.join().unwrap());
+        });
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{daemon_connection::DaemonChannel, EventStream};
+use crate::{
+    daemon_connection::{local_daemon_address, DaemonChannel},
+    event_stream::signal,
+    EventStream,
+};
 
 use self::{
     arrow_utils::{copy_array_into_sample, required_data_size},
@@ -10,25 +14,27 @@ use arrow::array::Array;
 use dora_core::{
     config::{DataId, NodeId, NodeRunConfig},
     descriptor::Descriptor,
-    topics::{DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT, LOCALHOST},
     uhlc,
 };
 
 use dora_message::{
-    daemon_to_node::{DaemonReply, NodeConfig},
+    common::NodeJoinInfo,
+    daemon_to_node::{env, DaemonReply, NodeConfig, NodeTopology, SendOutputError},
     metadata::{ArrowTypeInfo, Metadata, MetadataParameters},
-    node_to_daemon::{DaemonRequest, DataMessage, DropToken, Timestamped},
+    node_to_daemon::{DaemonRequest, DataMessage, DropToken, EventInterest, Timestamped},
     DataflowId,
 };
 use eyre::{bail, WrapErr};
-use shared_memory_extended::{Shmem, ShmemConf};
+use shared_memory_extended::Shmem;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::BTreeMap,
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tracing::info;
+use uuid::Uuid;
 
 #[cfg(feature = "tracing")]
 use dora_tracing::set_up_tracing;
@@ -36,25 +42,81 @@ use dora_tracing::set_up_tracing;
 pub mod arrow_utils;
 mod control_channel;
 mod drop_stream;
+mod flow_control;
+mod output;
+mod output_ring;
+pub(crate) mod panic_report;
+mod rate_limit;
+
+pub use flow_control::{SendFlowStats, WouldBlock, DEFAULT_MAX_IN_FLIGHT_SENDS};
+pub(crate) use output::OutputSender;
+pub use output::{Output, SharedMemoryAllocationError};
+pub use output_ring::{OutputRing, OutputSlot};
+pub use rate_limit::{RateLimitStats, RateLimitedOutput, RateLimitedSend};
 
 pub const ZERO_COPY_THRESHOLD: usize = 4096;
 
+/// Options for initializing a [`DoraNode`], e.g. through
+/// [`DoraNode::init_from_env_with_options`].
+///
+/// ```no_run
+/// use dora_node_api::{DoraNode, NodeInitOptions};
+///
+/// // the node handles termination signals itself
+/// let options = NodeInitOptions {
+///     stop_on_signal: false,
+///     ..Default::default()
+/// };
+/// let (mut node, mut events) = DoraNode::init_from_env_with_options(options)?;
+/// # eyre::Ok(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeInitOptions {
+    /// Kinds of events that the event stream subscribes to, see
+    /// [`DoraNode::init_from_env_with_event_interest`]. `None` uses the
+    /// default interest of the node.
+    pub event_interest: Option<EventInterest>,
+    /// Stops the node cleanly on `SIGINT` and `SIGTERM`: the outputs are
+    /// flushed and reported as done to the daemon, then the event stream
+    /// yields a final `Stop` event.
+    ///
+    /// The signal handler is process-wide and only installed if no other
+    /// handler is set for these signals yet. Nodes that install their own
+    /// handlers after initialization need to disable this.
+    pub stop_on_signal: bool,
+}
+
+impl Default for NodeInitOptions {
+    fn default() -> Self {
+        Self {
+            event_interest: None,
+            stop_on_signal: true,
+        }
+    }
+}
+
 pub struct DoraNode {
     id: NodeId,
     dataflow_id: DataflowId,
     node_config: NodeRunConfig,
-    control_channel: ControlChannel,
-    clock: Arc<uhlc::HLC>,
-
-    sent_out_shared_memory: HashMap<DropToken, ShmemHandle>,
-    drop_stream: DropStream,
-    cache: VecDeque<ShmemHandle>,
+    /// Shared with the [`Output`] handles of the node.
+    sender: Arc<OutputSender>,
 
     dataflow_descriptor: Descriptor,
+    dataflow_params: BTreeMap<String, String>,
 }
 
 impl DoraNode {
-    /// Initiate a node from environment variables set by `dora-coordinator`
+    /// Initiate a node from environment variables set by `dora-daemon`
+    ///
+    /// Only the [standard environment variables](dora_message::daemon_to_node::env)
+    /// are used, so the same binary works when it is spawned by the daemon
+    /// and when it is started externally as a dynamic node:
+    ///
+    /// - Spawned nodes are initialized from `DORA_NODE_CONFIG`.
+    /// - Otherwise, the node config is requested for the node `DORA_NODE_ID`
+    ///   from the daemon at `DORA_DAEMON_ADDR` (or the default local daemon
+    ///   address if not set).
     ///
     /// ```no_run
     /// use dora_node_api::DoraNode;
@@ -63,20 +125,62 @@ impl DoraNode {
     /// ```
     ///
     pub fn init_from_env() -> eyre::Result<(Self, EventStream)> {
-        let node_config: NodeConfig = {
-            let raw = std::env::var("DORA_NODE_CONFIG").wrap_err(
-                "env variable DORA_NODE_CONFIG must be set. Are you sure your using `dora start`?",
-            )?;
-            serde_yaml::from_str(&raw).context("failed to deserialize operator config")?
-        };
-        #[cfg(feature = "tracing")]
-        set_up_tracing(node_config.node_id.as_ref())
-            .context("failed to set up tracing subscriber")?;
-        Self::init(node_config)
+        Self::init_from_env_with_options(NodeInitOptions::default())
+    }
+
+    /// Like [`Self::init_from_env`], but subscribes to the given kinds of
+    /// events instead of the default interest of the node.
+    ///
+    /// By default, nodes with inputs receive all events. Nodes without inputs
+    /// only receive control events like `Stop`, so that their event stream
+    /// stays open until the dataflow is stopped.
+    ///
+    /// ```no_run
+    /// use dora_node_api::{DoraNode, EventInterest};
+    ///
+    /// // receive inputs and stop events, but no notifications about closed inputs
+    /// let interest = EventInterest::INPUTS | EventInterest::STOP;
+    /// let (mut node, mut events) = DoraNode::init_from_env_with_event_interest(interest)
+    ///     .expect("Could not init node.");
+    /// ```
+    pub fn init_from_env_with_event_interest(
+        interest: EventInterest,
+    ) -> eyre::Result<(Self, EventStream)> {
+        Self::init_from_env_with_options(NodeInitOptions {
+            event_interest: Some(interest),
+            ..Default::default()
+        })
+    }
+
+    /// Like [`Self::init_from_env`], with the given [`NodeInitOptions`].
+    pub fn init_from_env_with_options(
+        options: NodeInitOptions,
+    ) -> eyre::Result<(Self, EventStream)> {
+        if let Ok(raw) = std::env::var(env::DORA_NODE_CONFIG) {
+            let node_config: NodeConfig =
+                serde_yaml::from_str(&raw).context("failed to deserialize node config")?;
+            #[cfg(feature = "tracing")]
+            set_up_tracing(node_config.node_id.as_ref())
+                .context("failed to set up tracing subscriber")?;
+            Self::init_with_options(node_config, options)
+        } else if let Ok(node_id) = std::env::var(env::DORA_NODE_ID) {
+            #[cfg(feature = "tracing")]
+            set_up_tracing(&node_id).context("failed to set up tracing subscriber")?;
+            Self::init_from_node_id_with(NodeId::from(node_id), options)
+        } else {
+            bail!(
+                "env variable {} or {} must be set. Are you sure you're using `dora start`?",
+                env::DORA_NODE_CONFIG,
+                env::DORA_NODE_ID
+            )
+        }
     }
 
     /// Initiate a node from a dataflow id and a node id.
     ///
+    /// The node config is requested from the daemon at `DORA_DAEMON_ADDR`,
+    /// or at the default local daemon address if the variable is not set.
+    ///
     /// ```no_run
     /// use dora_node_api::DoraNode;
     /// use dora_node_api::dora_core::config::NodeId;
@@ -85,12 +189,52 @@ impl DoraNode {
     /// ```
     ///
     pub fn init_from_node_id(node_id: NodeId) -> eyre::Result<(Self, EventStream)> {
+        Self::init_from_node_id_with(node_id, NodeInitOptions::default())
+    }
+
+    /// Initiate a dynamic node from the join info of a `ResolveNode` request
+    /// to the coordinator.
+    ///
+    /// The node config is requested from the daemon that runs the node, with
+    /// the join token of the info. Nodes exchange their events and outputs
+    /// through connections that the daemon only accepts from its own
+    /// machine, so the node still needs to run on the machine of the daemon.
+    /// Use a [`DoraObserver`](crate::DoraObserver) for receiving outputs on
+    /// other machines.
+    pub fn init_from_join_info(info: &NodeJoinInfo) -> eyre::Result<(Self, EventStream)> {
+        let daemon_address = match info.daemon_address {
+            Some(address) => address,
+            None => local_daemon_address()?,
+        };
+        Self::request_node_config(
+            daemon_address,
+            Some(info.token.clone()),
+            info.node_id.clone(),
+            NodeInitOptions::default(),
+        )
+    }
+
+    fn init_from_node_id_with(
+        node_id: NodeId,
+        options: NodeInitOptions,
+    ) -> eyre::Result<(Self, EventStream)> {
         // Make sure that the node is initialized outside of dora start.
-        let daemon_address = (LOCALHOST, DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT).into();
+        let daemon_address = local_daemon_address()?;
+        Self::request_node_config(daemon_address, None, node_id, options)
+    }
 
+    fn request_node_config(
+        daemon_address: SocketAddr,
+        join_token: Option<String>,
+        node_id: NodeId,
+        options: NodeInitOptions,
+    ) -> eyre::Result<(Self, EventStream)> {
         let mut channel =
             DaemonChannel::new_tcp(daemon_address).context("Could not connect to the daemon")?;
         let clock = Arc::new(uhlc::HLC::default());
+        if let Some(token) = join_token {
+            channel.join(token, clock.new_timestamp())?;
+        }
 
         let reply = channel
             .request(&Timestamped {
@@ -101,7 +245,7 @@ impl DoraNode {
         match reply {
             DaemonReply::NodeConfig {
                 result: Ok(node_config),
-            } => Self::init(node_config),
+            } => Self::init_with_options(node_config, options),
             DaemonReply::NodeConfig { result: Err(error) } => {
                 bail!("failed to get node config from daemon: {error}")
             }
@@ -110,7 +254,7 @@ impl DoraNode {
     }
 
     pub fn init_flexible(node_id: NodeId) -> eyre::Result<(Self, EventStream)> {
-        if std::env::var("DORA_NODE_CONFIG").is_ok() {
+        if std::env::var(env::DORA_NODE_CONFIG).is_ok() {
             info!("Skipping {node_id} specified within the node initialization in favor of `DORA_NODE_CONFIG` specified by `dora start`");
             Self::init_from_env()
         } else {
@@ -118,8 +262,33 @@ impl DoraNode {
         }
     }
 
-    #[tracing::instrument]
     pub fn init(node_config: NodeConfig) -> eyre::Result<(Self, EventStream)> {
+        Self::init_with_options(node_config, NodeInitOptions::default())
+    }
+
+    /// Like [`Self::init`], but subscribes to the given kinds of events, see
+    /// [`Self::init_from_env_with_event_interest`].
+    pub fn init_with_event_interest(
+        node_config: NodeConfig,
+        interest: EventInterest,
+    ) -> eyre::Result<(Self, EventStream)> {
+        Self::init_with_options(
+            node_config,
+            NodeInitOptions {
+                event_interest: Some(interest),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Like [`Self::init`], with the given [`NodeInitOptions`].
+    #[tracing::instrument]
+    pub fn init_with_options(
+        node_config: NodeConfig,
+        options: NodeInitOptions,
+    ) -> eyre::Result<(Self, EventStream)> {
+        let multiplexed = node_config.supports_multiplexed_requests();
+        let control_events = node_config.supports_control_event_requests();
         let NodeConfig {
             dataflow_id,
             node_id,
@@ -127,30 +296,57 @@ impl DoraNode {
             daemon_communication,
             dataflow_descriptor,
             dynamic: _,
+            dataflow_instance: _,
+            dataflow_params,
+            drop_token_namespace,
+            daemon_version: _,
         } = node_config;
         let clock = Arc::new(uhlc::HLC::default());
 
-        let event_stream =
-            EventStream::init(dataflow_id, &node_id, &daemon_communication, clock.clone())
-                .wrap_err("failed to init event stream")?;
+        let interest = options
+            .event_interest
+            .unwrap_or_else(|| EventInterest::for_node(!run_config.inputs.is_empty()));
+        let mut event_stream = EventStream::init(
+            dataflow_id,
+            &node_id,
+            &daemon_communication,
+            interest,
+            control_events,
+            clock.clone(),
+        )
+        .wrap_err("failed to init event stream")?;
         let drop_stream =
             DropStream::init(dataflow_id, &node_id, &daemon_communication, clock.clone())
                 .wrap_err("failed to init drop stream")?;
-        let control_channel =
-            ControlChannel::init(dataflow_id, &node_id, &daemon_communication, clock.clone())
-                .wrap_err("failed to init control channel")?;
+        let control_channel = ControlChannel::init(
+            dataflow_id,
+            &node_id,
+            &daemon_communication,
+            multiplexed,
+            clock.clone(),
+        )
+        .wrap_err("failed to init control channel")?;
 
         let node = Self {
             id: node_id,
             dataflow_id,
             node_config: run_config.clone(),
-            control_channel,
-            clock,
-            sent_out_shared_memory: HashMap::new(),
-            drop_stream,
-            cache: VecDeque::new(),
+            sender: Arc::new(OutputSender::new(
+                control_channel,
+                clock,
+                drop_stream,
+                drop_token_namespace,
+                run_config.outputs,
+            )),
             dataflow_descriptor,
+            dataflow_params,
         };
+        panic_report::register(&node.sender);
+        if options.stop_on_signal {
+            if let Some(stop_signal) = signal::subscribe(Arc::downgrade(&node.sender)) {
+                event_stream.set_stop_signal(stop_signal);
+            }
+        }
         Ok((node, event_stream))
     }
 
@@ -166,7 +362,9 @@ impl DoraNode {
     /// let output = DataId::from("output_id".to_owned());
     ///
     /// let data: &[u8] = &[0, 1, 2, 3];
-    /// let parameters = MetadataParameters::default();
+    /// let mut parameters = MetadataParameters::default();
+    /// // typed values, read by receivers through e.g. `metadata.get_int("width")`
+    /// parameters.insert("width".into(), 640.into());
     ///
     /// node.send_output_raw(
     ///    output,
@@ -177,6 +375,19 @@ impl DoraNode {
     ///     }).expect("Could not send output");
     /// ```
     ///
+    /// Sending on an output that is not declared in the node's `outputs`
+    /// returns a [`SendOutputError::OutputNotDeclared`] error, which can be
+    /// retrieved through [`eyre::Report::downcast_ref`].
+    ///
+    /// All messages sent by this node are delivered to each receiver in the
+    /// order in which they were sent, also across different outputs, unless
+    /// the receiving inputs have different `priority` values. If the
+    /// queue of a receiving input is full, the oldest queued messages of that
+    /// input are dropped first; other inputs are not affected. Inputs with
+    /// `latest: true` only keep the newest message, which is read on demand.
+    /// Pending messages of `batch` inputs are sent before newer messages of
+    /// this node to other inputs of the same receiver.
+    ///
     pub fn send_output_raw<F>(
         &mut self,
         output_id: DataId,
@@ -214,6 +425,31 @@ impl DoraNode {
         Ok(())
     }
 
+    /// Like [`Self::send_output`], but fails with a [`WouldBlock`] error
+    /// instead of waiting if too many send requests of the node wait for a
+    /// reply of the daemon, see [`Self::set_max_in_flight_sends`].
+    ///
+    /// Meant for nodes that prefer dropping messages at the source over
+    /// falling behind. The message is not copied if the send would block.
+    pub fn try_send_output(
+        &mut self,
+        output_id: DataId,
+        parameters: MetadataParameters,
+        data: impl Array,
+    ) -> eyre::Result<()> {
+        if !self.node_config.outputs.contains(&output_id) {
+            return Err(SendOutputError::OutputNotDeclared { output_id }.into());
+        }
+        let permit = self.sender.permits.try_acquire()?;
+        let arrow_array = data.to_data();
+        let total_len = required_data_size(&arrow_array);
+        let mut sample = self.allocate_data_sample(total_len)?;
+        let type_info = copy_array_into_sample(&mut sample, &arrow_array);
+        self.sender
+            .send_sample_with_permit(permit, output_id, type_info, parameters, Some(sample))
+            .wrap_err("failed to send output")
+    }
+
     pub fn send_output_bytes(
         &mut self,
         output_id: DataId,
@@ -250,38 +486,85 @@ impl DoraNode {
         parameters: MetadataParameters,
         sample: Option<DataSample>,
     ) -> eyre::Result<()> {
-        self.handle_finished_drop_tokens()?;
-
         if !self.node_config.outputs.contains(&output_id) {
-            eyre::bail!("unknown output");
+            return Err(SendOutputError::OutputNotDeclared { output_id }.into());
         }
-        let metadata = Metadata::from_parameters(self.clock.new_timestamp(), type_info, parameters);
+        self.sender
+            .send_sample(output_id, type_info, parameters, sample)
+    }
 
-        let (data, shmem) = match sample {
-            Some(sample) => sample.finalize(),
-            None => (None, None),
-        };
+    /// Returns a cloneable handle for sending on the given output from
+    /// multiple threads or tasks.
+    ///
+    /// See [`Output`] for details.
+    pub fn output(&self, output_id: DataId) -> eyre::Result<Output> {
+        if !self.node_config.outputs.contains(&output_id) {
+            return Err(SendOutputError::OutputNotDeclared { output_id }.into());
+        }
+        Ok(Output::new(output_id, self.sender.clone()))
+    }
 
-        self.control_channel
-            .send_message(output_id.clone(), metadata, data)
-            .wrap_err_with(|| format!("failed to send output {output_id}"))?;
+    /// Sends the message that the daemon stored under the given content hash
+    /// in its persistent output cache, without copying the data again.
+    ///
+    /// Returns `false` if the cache holds no message with this hash for the
+    /// output, e.g. on the first run or after the entry was evicted. In that
+    /// case, send the data as usual, with the hash set as
+    /// [`CONTENT_HASH_PARAMETER`][crate::CONTENT_HASH_PARAMETER]
+    /// parameter, so that it is cached for the next run.
+    ///
+    /// Only outputs that are listed in the `persistent_outputs` of the node
+    /// are cached.
+    pub fn publish_cached(
+        &mut self,
+        output_id: DataId,
+        parameters: MetadataParameters,
+        hash: &str,
+    ) -> eyre::Result<bool> {
+        self.sender.handle_finished_drop_tokens()?;
 
-        if let Some((shared_memory, drop_token)) = shmem {
-            self.sent_out_shared_memory
-                .insert(drop_token, shared_memory);
+        if !self.node_config.outputs.contains(&output_id) {
+            return Err(SendOutputError::OutputNotDeclared { output_id }.into());
+        }
+        // the type info is replaced by the one of the cached message
+        let metadata = Metadata::from_parameters(
+            self.sender.clock.new_timestamp(),
+            ArrowTypeInfo::empty(),
+            parameters,
+        );
+        metadata
+            .check_source_timestamp()
+            .map_err(|reason| SendOutputError::InvalidSourceTimestamp { reason })?;
+        // acquired before locking the memory, which in-flight sends might need
+        let _permit = self.sender.permits.acquire();
+        // locked during the request, so that the drop token is registered
+        // before another thread can receive it
+        let mut memory = self.sender.memory();
+        let token = self
+            .sender
+            .control_channel
+            .send_cached(output_id.clone(), metadata, hash.to_owned())
+            .wrap_err_with(|| format!("failed to send cached output {output_id}"))?;
+        match token {
+            Some(token) => {
+                memory.pending_slot_tokens.insert(token);
+                Ok(true)
+            }
+            None => Ok(false),
         }
-
-        Ok(())
     }
 
     pub fn close_outputs(&mut self, outputs: Vec<DataId>) -> eyre::Result<()> {
+        self.sender.check_open()?;
         for output_id in &outputs {
             if !self.node_config.outputs.remove(output_id) {
                 eyre::bail!("unknown output {output_id}");
             }
         }
+        self.sender.forget_closed_outputs(&outputs);
 
-        self.control_channel
+        self.sender
+            .control_channel
             .report_closed_outputs(outputs)
             .wrap_err("failed to report closed outputs to daemon")?;
 
@@ -300,74 +583,158 @@ impl DoraNode {
         &self.node_config
     }
 
-    pub fn allocate_data_sample(&mut self, data_len: usize) -> eyre::Result<DataSample> {
-        let data = if data_len >= ZERO_COPY_THRESHOLD {
-            // create shared memory region
-            let shared_memory = self.allocate_shared_memory(data_len)?;
-
-            DataSample {
-                inner: DataSampleInner::Shmem(shared_memory),
-                len: data_len,
-            }
-        } else {
-            let avec: AVec<u8, ConstAlign<128>> = AVec::__from_elem(128, 0, data_len);
+    /// Reports to the daemon that this node finished its initialization.
+    ///
+    /// The daemon already considers a node ready when it subscribes to its
+    /// events, so this is only needed to report readiness explicitly, e.g.
+    /// for nodes without inputs. Stops the `ready_timeout` of the node, if
+    /// any.
+    pub fn notify_ready(&mut self) -> eyre::Result<()> {
+        self.sender
+            .control_channel
+            .report_ready()
+            .wrap_err("failed to report ready to daemon")
+    }
 
-            avec.into()
-        };
+    /// Queries the daemon for the resolved inputs and outputs of this node and
+    /// for the nodes that it is connected to.
+    ///
+    /// In contrast to [`node_config`][Self::node_config], wildcard inputs are
+    /// expanded to the outputs they actually match.
+    pub fn topology(&mut self) -> eyre::Result<NodeTopology> {
+        self.sender
+            .control_channel
+            .query_topology()
+            .wrap_err("failed to query node topology from daemon")
+    }
 
-        Ok(data)
-    }
-
-    fn allocate_shared_memory(&mut self, data_len: usize) -> eyre::Result<ShmemHandle> {
-        let cache_index = self
-            .cache
-            .iter()
-            .enumerate()
-            .rev()
-            .filter(|(_, s)| s.len() >= data_len)
-            .min_by_key(|(_, s)| s.len())
-            .map(|(i, _)| i);
-        let memory = match cache_index {
-            Some(i) => {
-                // we know that this index exists, so we can safely unwrap here
-                self.cache.remove(i).unwrap()
+    /// Returns the number of receivers of the given output, across all
+    /// machines of the dataflow.
+    ///
+    /// Outputs can be sent without receivers, the messages are just dropped
+    /// (persistent outputs are still cached for late subscribers). Nodes that
+    /// produce expensive outputs can use this to skip the work instead. The
+    /// [`Event::SubscribersChanged`][crate::Event::SubscribersChanged] event
+    /// notifies the node when the first receiver appears or the last one
+    /// disappears.
+    pub fn subscriber_count(&mut self, output_id: &DataId) -> eyre::Result<u64> {
+        if !self.node_config.outputs.contains(output_id) {
+            return Err(SendOutputError::OutputNotDeclared {
+                output_id: output_id.clone(),
             }
-            None => ShmemHandle(Box::new(
-                ShmemConf::new()
-                    .size(data_len)
-                    .writable(true)
-                    .create()
-                    .wrap_err("failed to allocate shared memory")?,
-            )),
-        };
-        assert!(memory.len() >= data_len);
+            .into());
+        }
+        self.sender
+            .control_channel
+            .query_subscribers(output_id.clone())
+            .wrap_err_with(|| format!("failed to query subscribers of {output_id}"))
+    }
 
-        Ok(memory)
+    /// Stops the whole dataflow, like `dora stop`.
+    ///
+    /// All nodes of the dataflow, including this one, receive a `Stop`
+    /// event and are killed if they don't exit within the `grace_duration`.
+    /// The node is reported as initiator in the result of the dataflow.
+    ///
+    /// Only nodes with `allowed_to_stop: true` in the dataflow descriptor
+    /// may stop the dataflow. For other nodes, the request fails with a
+    /// [`StopRequestError::PermissionDenied`][crate::StopRequestError::PermissionDenied]
+    /// error, which can be detected through [`eyre::Report::downcast_ref`].
+    pub fn request_stop(&mut self, grace_duration: Option<Duration>) -> eyre::Result<()> {
+        self.sender.control_channel.request_stop(grace_duration)
     }
 
-    fn handle_finished_drop_tokens(&mut self) -> eyre::Result<()> {
+    /// Waits until the given output has at least one receiver.
+    ///
+    /// Returns `false` if there is still no receiver after `timeout`.
+    pub fn wait_for_subscriber(
+        &mut self,
+        output_id: &DataId,
+        timeout: Duration,
+    ) -> eyre::Result<bool> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+        let deadline = Instant::now() + timeout;
         loop {
-            match self.drop_stream.try_recv() {
-                Ok(token) => match self.sent_out_shared_memory.remove(&token) {
-                    Some(region) => self.add_to_cache(region),
-                    None => tracing::warn!("received unknown finished drop token `{token:?}`"),
-                },
-                Err(flume::TryRecvError::Empty) => break,
-                Err(flume::TryRecvError::Disconnected) => {
-                    bail!("event stream was closed before sending all expected drop tokens")
-                }
+            if self.subscriber_count(output_id)? > 0 {
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
             }
+            std::thread::sleep(POLL_INTERVAL.min(deadline - now));
         }
-        Ok(())
     }
 
-    fn add_to_cache(&mut self, memory: ShmemHandle) {
-        const MAX_CACHE_SIZE: usize = 20;
-
-        self.cache.push_back(memory);
-        while self.cache.len() > MAX_CACHE_SIZE {
-            self.cache.pop_front();
+    /// Prepares a ring of `slots` shared memory regions of `slot_len` bytes
+    /// for sending messages on the given output.
+    ///
+    /// The daemon limits the number of slots and the total size of a ring,
+    /// as well as the number of rings per node. The rings of a node are
+    /// freed when it exits or is reloaded.
+    ///
+    /// See [`OutputRing`] for details.
+    pub fn prepare_output_ring(
+        &mut self,
+        output_id: DataId,
+        slot_len: usize,
+        slots: usize,
+    ) -> eyre::Result<OutputRing> {
+        if !self.node_config.outputs.contains(&output_id) {
+            return Err(SendOutputError::OutputNotDeclared { output_id }.into());
         }
+        let info = self
+            .sender
+            .control_channel
+            .prepare_output_ring(output_id.clone(), slot_len, slots)
+            .wrap_err_with(|| format!("failed to prepare output ring for {output_id}"))?;
+        OutputRing::open(info.ring_id, output_id, slot_len, info.slot_ids)
+    }
+
+    /// Allocates a sample of `data_len` bytes, in shared memory if it's at
+    /// least [`ZERO_COPY_THRESHOLD`] bytes large.
+    ///
+    /// Fails with a [`SharedMemoryAllocationError`] if the shared memory
+    /// region can't be created.
+    pub fn allocate_data_sample(&mut self, data_len: usize) -> eyre::Result<DataSample> {
+        self.sender.allocate_sample(data_len)
+    }
+
+    /// Sets whether a failed shared memory allocation is retried once after
+    /// releasing the regions of sent messages that receivers are done with.
+    ///
+    /// Disabled by default, as releasing the regions makes the following
+    /// allocations more expensive.
+    pub fn set_retry_shared_memory_allocation(&mut self, retry: bool) {
+        self.sender.set_retry_shared_memory(retry);
+    }
+
+    /// Returns the number of failed shared memory allocations of this node
+    /// and its [`Output`] handles, including failed attempts that were
+    /// retried.
+    pub fn shared_memory_allocation_failures(&self) -> u64 {
+        self.sender.allocation_failures()
+    }
+
+    /// Sets the maximum number of send requests of this node and its
+    /// [`Output`] handles that wait for a reply of the daemon at the same
+    /// time. Defaults to [`DEFAULT_MAX_IN_FLIGHT_SENDS`], values below one
+    /// are raised to one.
+    ///
+    /// Each send waits for the reply of the daemon, so only nodes that send
+    /// from multiple threads have more than one request in flight. Further
+    /// sends wait until a request finished, while
+    /// [`try_send_output`][Self::try_send_output] and
+    /// [`Output::try_send`] fail with a [`WouldBlock`] error instead.
+    pub fn set_max_in_flight_sends(&mut self, max: usize) {
+        self.sender.permits.set_max_in_flight(max);
+    }
+
+    /// Returns how often and how long sends of this node and its [`Output`]
+    /// handles waited because of [`Self::set_max_in_flight_sends`].
+    pub fn send_flow_stats(&self) -> SendFlowStats {
+        self.sender.permits.stats()
     }
 
     /// Returns the full dataflow descriptor that this node is part of.
@@ -376,58 +743,32 @@ impl DoraNode {
     pub fn dataflow_descriptor(&self) -> &Descriptor {
         &self.dataflow_descriptor
     }
+
+    /// Returns the parameters that the dataflow was started with, e.g.
+    /// through `dora start --param seed=42`.
+    ///
+    /// The map is empty if no parameters were given or if the daemon
+    /// predates start parameters.
+    ///
+    /// ```no_run
+    /// use dora_node_api::DoraNode;
+    ///
+    /// let (node, _events) = DoraNode::init_from_env().expect("Could not init node.");
+    /// let seed: u64 = match node.dataflow_params().get("seed") {
+    ///     Some(seed) => seed.parse().expect("invalid seed"),
+    ///     None => 0,
+    /// };
+    /// ```
+    pub fn dataflow_params(&self) -> &BTreeMap<String, String> {
+        &self.dataflow_params
+    }
 }
 
 impl Drop for DoraNode {
     #[tracing::instrument(skip(self), fields(self.id = %self.id), level = "trace")]
     fn drop(&mut self) {
-        // close all outputs first to notify subscribers as early as possible
-        if let Err(err) = self
-            .control_channel
-            .report_closed_outputs(
-                std::mem::take(&mut self.node_config.outputs)
-                    .into_iter()
-                    .collect(),
-            )
-            .context("failed to close outputs on drop")
-        {
-            tracing::warn!("{err:?}")
-        }
-
-        while !self.sent_out_shared_memory.is_empty() {
-            if self.drop_stream.len() == 0 {
-                tracing::trace!(
-                    "waiting for {} remaining drop tokens",
-                    self.sent_out_shared_memory.len()
-                );
-            }
-
-            match self.drop_stream.recv_timeout(Duration::from_secs(10)) {
-                Ok(token) => {
-                    self.sent_out_shared_memory.remove(&token);
-                }
-                Err(flume::RecvTimeoutError::Disconnected) => {
-                    tracing::warn!(
-                        "finished_drop_tokens channel closed while still waiting for drop tokens; \
-                        closing {} shared memory regions that might still be used",
-                        self.sent_out_shared_memory.len()
-                    );
-                    break;
-                }
-                Err(flume::RecvTimeoutError::Timeout) => {
-                    tracing::warn!(
-                        "timeout while waiting for drop tokens; \
-                        closing {} shared memory regions that might still be used",
-                        self.sent_out_shared_memory.len()
-                    );
-                    break;
-                }
-            }
-        }
-
-        if let Err(err) = self.control_channel.report_outputs_done() {
-            tracing::warn!("{err:?}")
-        }
+        // does nothing if the node was stopped by a signal already
+        self.sender.finish();
     }
 }
 
@@ -437,10 +778,13 @@ pub struct DataSample {
 }
 
 impl DataSample {
-    fn finalize(self) -> (Option<DataMessage>, Option<(ShmemHandle, DropToken)>) {
+    fn finalize(
+        self,
+        drop_token_namespace: Uuid,
+    ) -> (Option<DataMessage>, Option<(ShmemHandle, DropToken)>) {
         match self.inner {
             DataSampleInner::Shmem(shared_memory) => {
-                let drop_token = DropToken::generate();
+                let drop_token = DropToken::generate_in(drop_token_namespace);
                 let data = DataMessage::SharedMemory {
                     shared_memory_id: shared_memory.get_os_id().to_owned(),
                     len: self.len,
@@ -0,0 +1,568 @@
+use super::{
+    arrow_utils::{copy_array_into_sample, required_data_size},
+    control_channel::ControlChannel,
+    drop_stream::DropStream,
+    flow_control::{SendPermit, SendPermits, DEFAULT_MAX_IN_FLIGHT_SENDS},
+    DataSample, DataSampleInner, ShmemHandle, ZERO_COPY_THRESHOLD,
+};
+use aligned_vec::{AVec, ConstAlign};
+use arrow::array::Array;
+use dora_core::{config::DataId, uhlc};
+use dora_message::{
+    daemon_to_node::SendOutputError,
+    metadata::{ArrowTypeInfo, Metadata, MetadataParameters},
+    node_to_daemon::DropToken,
+};
+use eyre::{bail, WrapErr};
+use shared_memory_extended::ShmemConf;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// Handle for sending messages on a single output of a node, created through
+/// [`DoraNode::output`][super::DoraNode::output].
+///
+/// Handles are cheap to clone and can be moved to other threads or tasks,
+/// which then publish concurrently over the connection of the node to the
+/// daemon. The messages of all handles and of the node itself are delivered
+/// in the order in which the daemon received them.
+///
+/// ```no_run
+/// use dora_node_api::{arrow::array::Float64Array, DoraNode, MetadataParameters};
+/// use dora_node_api::dora_core::config::DataId;
+///
+/// let (node, _events) = DoraNode::init_from_env()?;
+/// let pose = node.output(DataId::from("pose".to_owned()))?;
+/// let publisher = std::thread::spawn({
+///     let pose = pose.clone();
+///     move || pose.send(MetadataParameters::default(), Float64Array::from(vec![0.0; 3]))
+/// });
+/// pose.send(MetadataParameters::default(), Float64Array::from(vec![1.0; 3]))?;
+/// publisher.join().unwrap()?;
+/// # eyre::Ok(())
+/// ```
+#[derive(Clone)]
+pub struct Output {
+    output_id: DataId,
+    sender: Arc<OutputSender>,
+}
+
+impl Output {
+    pub(super) fn new(output_id: DataId, sender: Arc<OutputSender>) -> Self {
+        Self { output_id, sender }
+    }
+
+    pub fn id(&self) -> &DataId {
+        &self.output_id
+    }
+
+    /// Sends the given array, like [`DoraNode::send_output`][super::DoraNode::send_output].
+    pub fn send(&self, parameters: MetadataParameters, data: impl Array) -> eyre::Result<()> {
+        let arrow_array = data.to_data();
+        let total_len = required_data_size(&arrow_array);
+        let mut sample = self.sender.allocate_sample(total_len)?;
+        let type_info = copy_array_into_sample(&mut sample, &arrow_array);
+        self.sender
+            .send_sample(self.output_id.clone(), type_info, parameters, Some(sample))
+            .wrap_err("failed to send output")
+    }
+
+    /// Like [`send`][Self::send], but fails with a
+    /// [`WouldBlock`][super::WouldBlock] error instead of waiting if too
+    /// many send requests of the node wait for a reply of the daemon.
+    pub fn try_send(&self, parameters: MetadataParameters, data: impl Array) -> eyre::Result<()> {
+        let permit = self.sender.permits.try_acquire()?;
+        let arrow_array = data.to_data();
+        let total_len = required_data_size(&arrow_array);
+        let mut sample = self.sender.allocate_sample(total_len)?;
+        let type_info = copy_array_into_sample(&mut sample, &arrow_array);
+        self.sender
+            .send_sample_with_permit(
+                permit,
+                self.output_id.clone(),
+                type_info,
+                parameters,
+                Some(sample),
+            )
+            .wrap_err("failed to send output")
+    }
+
+    /// Sends the given bytes as a byte array.
+    pub fn send_bytes(&self, parameters: MetadataParameters, data: &[u8]) -> eyre::Result<()> {
+        let mut sample = self.sender.allocate_sample(data.len())?;
+        sample.copy_from_slice(data);
+        let type_info = ArrowTypeInfo::byte_array(data.len());
+        self.sender
+            .send_sample(self.output_id.clone(), type_info, parameters, Some(sample))
+    }
+}
+
+impl std::fmt::Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Output")
+            .field("output_id", &self.output_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends the messages of a node, shared by the node and its [`Output`]
+/// handles.
+pub(crate) struct OutputSender {
+    pub(super) control_channel: ControlChannel,
+    pub(super) clock: Arc<uhlc::HLC>,
+    pub(super) drop_stream: DropStream,
+    /// Namespace of the drop tokens of sent messages, see
+    /// [`DropToken::generate_in`].
+    drop_token_namespace: Uuid,
+    memory: Mutex<SentMemory>,
+    /// Set when the node is dropped or stopped by a signal, after which the
+    /// handles can't send anymore.
+    closed: AtomicBool,
+    /// Outputs that are not closed yet, reported as closed by
+    /// [`Self::finish`].
+    open_outputs: Mutex<BTreeSet<DataId>>,
+    /// Set once [`Self::finish`] reported the outputs as done.
+    finished: Mutex<bool>,
+    /// Whether a failed shared memory allocation is retried once after
+    /// releasing the regions that receivers are done with.
+    retry_shared_memory: AtomicBool,
+    /// Number of failed attempts to create a shared memory region.
+    allocation_failures: AtomicU64,
+    /// Bounds the send requests that wait for a reply of the daemon.
+    pub(super) permits: SendPermits,
+}
+
+/// Error returned when a shared memory region for an output can't be
+/// created, e.g. because `/dev/shm` is full.
+///
+/// The error is returned as an [`eyre::Report`], so nodes can back off
+/// instead of exiting by checking for it:
+///
+/// ```no_run
+/// use dora_node_api::{DoraNode, SharedMemoryAllocationError};
+///
+/// # let (mut node, _events) = DoraNode::init_from_env()?;
+/// match node.allocate_data_sample(64 * 1024 * 1024) {
+///     Ok(sample) => { /* fill and send the sample */ }
+///     Err(err) => match err.downcast_ref::<SharedMemoryAllocationError>() {
+///         Some(err) => eprintln!("skipping frame: {err}"),
+///         None => return Err(err),
+///     },
+/// }
+/// # eyre::Ok(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedMemoryAllocationError {
+    /// Size of the requested region in bytes.
+    pub requested: usize,
+    /// Bytes of sent messages that are still accessed by receivers.
+    pub in_flight: u64,
+    /// Bytes of regions that receivers are done with, kept for reuse.
+    pub cached: u64,
+    /// Free space of the shared memory filesystem, if known.
+    pub free_space: Option<u64>,
+    /// Error reported by the operating system.
+    pub reason: String,
+}
+
+impl fmt::Display for SharedMemoryAllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to allocate shared memory of {} bytes ({} bytes in flight, {} bytes cached",
+            self.requested, self.in_flight, self.cached
+        )?;
+        if let Some(free_space) = self.free_space {
+            write!(f, ", {free_space} bytes free")?;
+        }
+        write!(f, "): {}", self.reason)
+    }
+}
+
+impl std::error::Error for SharedMemoryAllocationError {}
+
+/// Shared memory of the messages sent by a node.
+#[derive(Default)]
+pub(super) struct SentMemory {
+    /// Regions of sent messages that are still accessed by receivers.
+    pub(super) sent_out_shared_memory: HashMap<DropToken, ShmemHandle>,
+    /// Regions that receivers are done with, for reuse.
+    cache: VecDeque<ShmemHandle>,
+    /// Drop tokens of sent output ring slots and cached messages that are
+    /// still accessed by receivers.
+    pub(super) pending_slot_tokens: HashSet<DropToken>,
+}
+
+impl OutputSender {
+    pub(super) fn new(
+        control_channel: ControlChannel,
+        clock: Arc<uhlc::HLC>,
+        drop_stream: DropStream,
+        drop_token_namespace: Uuid,
+        outputs: BTreeSet<DataId>,
+    ) -> Self {
+        Self {
+            control_channel,
+            clock,
+            drop_stream,
+            drop_token_namespace,
+            memory: Mutex::new(SentMemory::default()),
+            closed: AtomicBool::new(false),
+            open_outputs: Mutex::new(outputs),
+            finished: Mutex::new(false),
+            retry_shared_memory: AtomicBool::new(false),
+            allocation_failures: AtomicU64::new(0),
+            permits: SendPermits::new(DEFAULT_MAX_IN_FLIGHT_SENDS),
+        }
+    }
+
+    pub(super) fn set_retry_shared_memory(&self, retry: bool) {
+        self.retry_shared_memory.store(retry, Ordering::Relaxed);
+    }
+
+    pub(super) fn allocation_failures(&self) -> u64 {
+        self.allocation_failures.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn memory(&self) -> MutexGuard<'_, SentMemory> {
+        self.memory.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(super) fn check_open(&self) -> eyre::Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            bail!("node was stopped already");
+        }
+        Ok(())
+    }
+
+    /// Removes the given outputs from the outputs that [`Self::finish`]
+    /// reports as closed.
+    pub(super) fn forget_closed_outputs(&self, outputs: &[DataId]) {
+        let mut open_outputs = self
+            .open_outputs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        for output_id in outputs {
+            open_outputs.remove(output_id);
+        }
+    }
+
+    /// Stops sending and reports to the daemon that the node is done with
+    /// its outputs.
+    ///
+    /// Flushes the outputs first: waits until the send requests that are in
+    /// flight are replied to and until the receivers released the sent
+    /// shared memory regions. Called when the node is dropped and when it
+    /// receives a termination signal. Only the first call reports to the
+    /// daemon; concurrent calls wait until the report is done.
+    pub(crate) fn finish(&self) {
+        let mut finished = self.finished.lock().unwrap_or_else(|err| err.into_inner());
+        if *finished {
+            return;
+        }
+        self.closed.store(true, Ordering::Release);
+
+        if !self.permits.wait_idle(Duration::from_secs(10)) {
+            tracing::warn!("timeout while waiting for in-flight sends to finish");
+        }
+
+        // close all outputs first to notify subscribers as early as possible
+        let outputs = std::mem::take(
+            &mut *self
+                .open_outputs
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()),
+        );
+        if let Err(err) = self
+            .control_channel
+            .report_closed_outputs(outputs.into_iter().collect())
+            .context("failed to close outputs on drop")
+        {
+            tracing::warn!("{err:?}")
+        }
+
+        let remaining = || self.memory().sent_out_shared_memory.len();
+        while remaining() > 0 {
+            if self.drop_stream.len() == 0 {
+                tracing::trace!("waiting for {} remaining drop tokens", remaining());
+            }
+
+            match self.drop_stream.recv_timeout(Duration::from_secs(10)) {
+                Ok(token) => {
+                    self.memory().sent_out_shared_memory.remove(&token);
+                }
+                Err(flume::RecvTimeoutError::Disconnected) => {
+                    tracing::warn!(
+                        "finished_drop_tokens channel closed while still waiting for drop tokens; \
+                        closing {} shared memory regions that might still be used",
+                        remaining()
+                    );
+                    break;
+                }
+                Err(flume::RecvTimeoutError::Timeout) => {
+                    tracing::warn!(
+                        "timeout while waiting for drop tokens; \
+                        closing {} shared memory regions that might still be used",
+                        remaining()
+                    );
+                    break;
+                }
+            }
+        }
+
+        if let Err(err) = self.control_channel.report_outputs_done() {
+            tracing::warn!("{err:?}")
+        }
+        *finished = true;
+    }
+
+    pub(super) fn allocate_sample(&self, data_len: usize) -> eyre::Result<DataSample> {
+        let data = if data_len >= ZERO_COPY_THRESHOLD {
+            // create shared memory region
+            let shared_memory = self.allocate_shared_memory(data_len)?;
+
+            DataSample {
+                inner: DataSampleInner::Shmem(shared_memory),
+                len: data_len,
+            }
+        } else {
+            let avec: AVec<u8, ConstAlign<128>> = AVec::__from_elem(128, 0, data_len);
+
+            avec.into()
+        };
+
+        Ok(data)
+    }
+
+    fn allocate_shared_memory(&self, data_len: usize) -> eyre::Result<ShmemHandle> {
+        let cached = {
+            let mut memory = self.memory();
+            let cache_index = memory
+                .cache
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, s)| s.len() >= data_len)
+                .min_by_key(|(_, s)| s.len())
+                .map(|(i, _)| i);
+            // we know that this index exists, so we can safely unwrap here
+            cache_index.map(|i| memory.cache.remove(i).unwrap())
+        };
+        let memory = match cached {
+            Some(memory) => memory,
+            None => self.create_shared_memory(data_len)?,
+        };
+        assert!(memory.len() >= data_len);
+
+        Ok(memory)
+    }
+
+    fn create_shared_memory(&self, data_len: usize) -> eyre::Result<ShmemHandle> {
+        let mut result = create_region(data_len);
+        if result.is_err() {
+            self.allocation_failures.fetch_add(1, Ordering::Relaxed);
+            if self.retry_shared_memory.load(Ordering::Relaxed) {
+                // free the regions that receivers are done with, including
+                // the ones whose drop tokens arrived since the last send
+                self.handle_finished_drop_tokens()?;
+                self.memory().cache.clear();
+                result = create_region(data_len);
+                if result.is_err() {
+                    self.allocation_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        result.map_err(|reason| {
+            let error = self.memory().allocation_error(data_len, reason);
+            tracing::warn!("{error}");
+            eyre::Report::new(error)
+        })
+    }
+
+    /// Sends the given sample, without checking whether the output is
+    /// declared.
+    ///
+    /// Waits for a permit if too many send requests wait for a reply.
+    pub(super) fn send_sample(
+        &self,
+        output_id: DataId,
+        type_info: ArrowTypeInfo,
+        parameters: MetadataParameters,
+        sample: Option<DataSample>,
+    ) -> eyre::Result<()> {
+        let permit = self.permits.acquire();
+        self.send_sample_with_permit(permit, output_id, type_info, parameters, sample)
+    }
+
+    /// Like [`Self::send_sample`], with a permit that was acquired already.
+    pub(super) fn send_sample_with_permit(
+        &self,
+        permit: SendPermit<'_>,
+        output_id: DataId,
+        type_info: ArrowTypeInfo,
+        parameters: MetadataParameters,
+        sample: Option<DataSample>,
+    ) -> eyre::Result<()> {
+        self.check_open()?;
+        self.handle_finished_drop_tokens()?;
+
+        let metadata = Metadata::from_parameters(self.clock.new_timestamp(), type_info, parameters);
+        metadata
+            .check_source_timestamp()
+            .map_err(|reason| SendOutputError::InvalidSourceTimestamp { reason })?;
+
+        let (data, shmem) = match sample {
+            Some(sample) if sample.len > 0 => sample.finalize(self.drop_token_namespace),
+            // zero-length messages are sent without a data buffer
+            _ => (None, None),
+        };
+
+        // register the region before sending, as another thread might
+        // receive its drop token before the request returns
+        let drop_token = shmem.map(|(shared_memory, drop_token)| {
+            self.memory()
+                .sent_out_shared_memory
+                .insert(drop_token, shared_memory);
+            drop_token
+        });
+
+        let result = match data {
+            Some(data) => {
+                self.control_channel
+                    .send_message(output_id.clone(), metadata, Some(data))
+            }
+            None => self
+                .control_channel
+                .send_empty_message(output_id.clone(), metadata),
+        }
+        .wrap_err_with(|| format!("failed to send output {output_id}"));
+        drop(permit);
+        if result.is_err() {
+            if let Some(drop_token) = drop_token {
+                self.memory().sent_out_shared_memory.remove(&drop_token);
+            }
+        }
+        result
+    }
+
+    pub(super) fn handle_finished_drop_tokens(&self) -> eyre::Result<()> {
+        loop {
+            match self.drop_stream.try_recv() {
+                Ok(token) => self.memory().handle_drop_token(token),
+                Err(flume::TryRecvError::Empty) => break,
+                Err(flume::TryRecvError::Disconnected) => {
+                    bail!("event stream was closed before sending all expected drop tokens")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SentMemory {
+    pub(super) fn handle_drop_token(&mut self, token: DropToken) {
+        if let Some(region) = self.sent_out_shared_memory.remove(&token) {
+            self.add_to_cache(region);
+        } else if !self.pending_slot_tokens.remove(&token) {
+            tracing::warn!("received unknown finished drop token `{token:?}`");
+        }
+    }
+
+    fn allocation_error(&self, requested: usize, reason: String) -> SharedMemoryAllocationError {
+        SharedMemoryAllocationError {
+            requested,
+            in_flight: self
+                .sent_out_shared_memory
+                .values()
+                .map(|region| region.len() as u64)
+                .sum(),
+            cached: self.cache.iter().map(|region| region.len() as u64).sum(),
+            free_space: shared_memory_free_space(),
+            reason,
+        }
+    }
+
+    fn add_to_cache(&mut self, memory: ShmemHandle) {
+        const MAX_CACHE_SIZE: usize = 20;
+
+        self.cache.push_back(memory);
+        while self.cache.len() > MAX_CACHE_SIZE {
+            self.cache.pop_front();
+        }
+    }
+}
+
+fn create_region(data_len: usize) -> Result<ShmemHandle, String> {
+    ShmemConf::new()
+        .size(data_len)
+        .writable(true)
+        .create()
+        .map(|memory| ShmemHandle(Box::new(memory)))
+        .map_err(|err| err.to_string())
+}
+
+/// Returns the free space of the filesystem that backs shared memory.
+#[cfg(target_os = "linux")]
+fn shared_memory_free_space() -> Option<u64> {
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is a valid C string and `statvfs` initializes the
+    // stats on success
+    let result = unsafe { libc::statvfs(b"/dev/shm\0".as_ptr().cast(), stats.as_mut_ptr()) };
+    if result != 0 {
+        return None;
+    }
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn shared_memory_free_space() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_error_reports_memory_usage() {
+        let mut memory = SentMemory::default();
+        let in_flight = create_region(ZERO_COPY_THRESHOLD).unwrap();
+        memory
+            .sent_out_shared_memory
+            .insert(DropToken::generate(), in_flight);
+        memory.add_to_cache(create_region(2 * ZERO_COPY_THRESHOLD).unwrap());
+
+        // no system has the address space to map this
+        let requested = usize::MAX / 2;
+        let reason = create_region(requested).err().unwrap();
+        let error = memory.allocation_error(requested, reason.clone());
+
+        assert_eq!(error.requested, requested);
+        assert!(error.in_flight >= ZERO_COPY_THRESHOLD as u64);
+        assert!(error.cached >= 2 * ZERO_COPY_THRESHOLD as u64);
+        if cfg!(target_os = "linux") {
+            assert!(error.free_space.is_some());
+        }
+        let message = error.to_string();
+        assert!(message.starts_with(&format!(
+            "failed to allocate shared memory of {requested} bytes ({} bytes in flight",
+            error.in_flight
+        )));
+        assert!(message.ends_with(&reason));
+
+        let report = eyre::Report::new(error.clone());
+        assert_eq!(
+            report.downcast_ref::<SharedMemoryAllocationError>(),
+            Some(&error)
+        );
+    }
+}
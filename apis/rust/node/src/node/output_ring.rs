@@ -0,0 +1,195 @@
+use super::{DoraNode, ShmemHandle};
+use dora_core::config::DataId;
+use dora_message::{
+    daemon_to_node::SendOutputError,
+    metadata::{ArrowTypeInfo, Metadata, MetadataParameters},
+    node_to_daemon::{DropToken, OutputRingId},
+};
+use eyre::{eyre, WrapErr};
+use shared_memory_extended::ShmemConf;
+use std::ops::{Deref, DerefMut};
+
+/// A ring of preallocated shared memory slots for sending messages on an output.
+///
+/// Sending through a ring avoids allocating shared memory for every message,
+/// which is useful for outputs with a high message rate. The slots are reused
+/// in turn. A slot is only handed out again when all receivers are done with
+/// the previous message sent from it, so a sender that is faster than its
+/// receivers waits in [`next_slot`][Self::next_slot].
+///
+/// ```no_run
+/// use dora_node_api::{DoraNode, MetadataParameters};
+/// use dora_node_api::dora_core::config::DataId;
+///
+/// # async fn run() -> eyre::Result<()> {
+/// let (mut node, _events) = DoraNode::init_from_env()?;
+/// let mut ring = node.prepare_output_ring(DataId::from("image".to_owned()), 640 * 480, 4)?;
+/// loop {
+///     let mut slot = ring.next_slot(&mut node).await?;
+///     slot.fill(0);
+///     let len = slot.len();
+///     slot.send(&mut node, len, MetadataParameters::default())?;
+/// }
+/// # }
+/// ```
+pub struct OutputRing {
+    id: OutputRingId,
+    output_id: DataId,
+    slot_len: usize,
+    slots: Vec<RingSlot>,
+    next: usize,
+}
+
+struct RingSlot {
+    memory: ShmemHandle,
+    /// Drop token of the last message that was sent from this slot.
+    last_token: Option<DropToken>,
+}
+
+impl OutputRing {
+    pub(super) fn open(
+        id: OutputRingId,
+        output_id: DataId,
+        slot_len: usize,
+        slot_ids: Vec<String>,
+    ) -> eyre::Result<Self> {
+        let slots = slot_ids
+            .into_iter()
+            .map(|slot_id| {
+                let memory = ShmemConf::new()
+                    .os_id(&slot_id)
+                    .writable(true)
+                    .open()
+                    .wrap_err_with(|| format!("failed to open output ring slot `{slot_id}`"))?;
+                Ok(RingSlot {
+                    memory: ShmemHandle(Box::new(memory)),
+                    last_token: None,
+                })
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self {
+            id,
+            output_id,
+            slot_len,
+            slots,
+            next: 0,
+        })
+    }
+
+    pub fn output_id(&self) -> &DataId {
+        &self.output_id
+    }
+
+    /// Size of each slot in bytes.
+    pub fn slot_len(&self) -> usize {
+        self.slot_len
+    }
+
+    /// Waits until a slot is no longer accessed by any receiver and returns it.
+    pub async fn next_slot(&mut self, node: &mut DoraNode) -> eyre::Result<OutputSlot<'_>> {
+        loop {
+            node.sender.handle_finished_drop_tokens()?;
+
+            let slot_count = self.slots.len();
+            let free = {
+                let memory = node.sender.memory();
+                (0..slot_count)
+                    .map(|offset| (self.next + offset) % slot_count)
+                    .find(|&index| match &self.slots[index].last_token {
+                        Some(token) => !memory.pending_slot_tokens.contains(token),
+                        None => true,
+                    })
+            };
+            if let Some(index) = free {
+                self.next = (index + 1) % slot_count;
+                return Ok(OutputSlot {
+                    ring_id: self.id,
+                    output_id: &self.output_id,
+                    index,
+                    slot: &mut self.slots[index],
+                });
+            }
+
+            // all slots are busy -> wait until the receivers are done with one of them
+            let token = node
+                .sender
+                .drop_stream
+                .recv_async()
+                .await
+                .map_err(|_| eyre!("drop stream was closed while waiting for a free slot"))?;
+            node.sender.memory().handle_drop_token(token);
+        }
+    }
+}
+
+/// A free slot of an [`OutputRing`], dereferences to the full slot memory.
+pub struct OutputSlot<'a> {
+    ring_id: OutputRingId,
+    output_id: &'a DataId,
+    index: usize,
+    slot: &'a mut RingSlot,
+}
+
+impl OutputSlot<'_> {
+    /// Index of the slot in the ring.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Sends the first `valid_len` bytes of the slot as a byte array.
+    pub fn send(
+        self,
+        node: &mut DoraNode,
+        valid_len: usize,
+        parameters: MetadataParameters,
+    ) -> eyre::Result<()> {
+        self.send_typed(
+            node,
+            ArrowTypeInfo::byte_array(valid_len),
+            parameters,
+            valid_len,
+        )
+    }
+
+    /// Sends the first `valid_len` bytes of the slot with the given type info.
+    pub fn send_typed(
+        self,
+        node: &mut DoraNode,
+        type_info: ArrowTypeInfo,
+        parameters: MetadataParameters,
+        valid_len: usize,
+    ) -> eyre::Result<()> {
+        let sender = &node.sender;
+        let metadata =
+            Metadata::from_parameters(sender.clock.new_timestamp(), type_info, parameters);
+        metadata
+            .check_source_timestamp()
+            .map_err(|reason| SendOutputError::InvalidSourceTimestamp { reason })?;
+        // acquired before locking the memory, which in-flight sends might need
+        let _permit = sender.permits.acquire();
+        // locked during the request, so that the drop token is registered
+        // before another thread can receive it
+        let mut memory = sender.memory();
+        let token = sender
+            .control_channel
+            .send_out_slot(self.ring_id, self.index, valid_len, metadata)
+            .wrap_err_with(|| format!("failed to send output {}", self.output_id))?;
+        self.slot.last_token = Some(token);
+        memory.pending_slot_tokens.insert(token);
+        Ok(())
+    }
+}
+
+impl Deref for OutputSlot<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.slot.memory.as_slice() }
+    }
+}
+
+impl DerefMut for OutputSlot<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.slot.memory.as_slice_mut() }
+    }
+}
@@ -0,0 +1,89 @@
+//! Forwards panics of the node process to the daemon.
+//!
+//! Without this, the daemon only sees the exit code of a panicking node
+//! (e.g. `101`). The panic hook that is installed on the first node
+//! initialization sends the panic message and a backtrace to the daemon of
+//! every live node of the process before the panic continues. As the hook is
+//! process-wide, panics of the background threads of the API are reported
+//! the same way. The daemon only uses the first reported error, and only if
+//! the node then exits with an error.
+
+use super::output::OutputSender;
+use std::{
+    backtrace::Backtrace,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Once, PoisonError, Weak,
+    },
+    time::Duration,
+};
+
+/// Maximum time that a panic waits for the report to be sent.
+///
+/// The panicking thread might hold the lock of the daemon connection, so the
+/// report is sent from another thread and abandoned after this time.
+const REPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+static HOOK_INSTALLED: Once = Once::new();
+static REPORTERS: Mutex<Vec<Weak<OutputSender>>> = Mutex::new(Vec::new());
+
+/// Disables the reporting of panics to the daemon.
+///
+/// Nodes that install their own panic hook need to call this function
+/// before initializing the [`DoraNode`][crate::DoraNode]. Hooks that were
+/// installed already stay in place, but stop reporting.
+pub fn disable_panic_reporting() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Reports future panics through the connection of the given node.
+///
+/// Installs the panic hook on first use. The previous hook still runs, so
+/// the panic is printed as usual.
+pub(super) fn register(sender: &Arc<OutputSender>) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    HOOK_INSTALLED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            if !DISABLED.load(Ordering::Relaxed) {
+                report(format!(
+                    "{info}\n\nbacktrace:\n{}",
+                    Backtrace::force_capture()
+                ));
+            }
+        }));
+    });
+    let mut reporters = REPORTERS.lock().unwrap_or_else(PoisonError::into_inner);
+    reporters.retain(|reporter| reporter.strong_count() > 0);
+    reporters.push(Arc::downgrade(sender));
+}
+
+fn report(message: String) {
+    let senders: Vec<_> = REPORTERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    if senders.is_empty() {
+        return;
+    }
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    let spawned = std::thread::Builder::new()
+        .name("dora-panic-report".into())
+        .spawn(move || {
+            for sender in senders {
+                if let Err(err) = sender.control_channel.report_node_error(message.clone()) {
+                    eprintln!("failed to report panic to dora-daemon: {err:?}");
+                }
+            }
+            let _ = done_tx.send(());
+        });
+    if spawned.is_ok() {
+        let _ = done_rx.recv_timeout(REPORT_TIMEOUT);
+    }
+}
@@ -0,0 +1,286 @@
+use super::Output;
+use arrow::array::{make_array, Array, ArrayRef};
+use dora_message::metadata::MetadataParameters;
+use eyre::{bail, Context};
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Sends at most `max_rate_hz` messages per second on an output, coalescing
+/// to the latest message.
+///
+/// A message is sent immediately if the previous message was sent at least
+/// one interval ago. Otherwise, it is scheduled and sent by a background
+/// thread at the next free slot. Scheduled messages are replaced by newer
+/// messages, so the receivers always get the latest value. A scheduled
+/// message that is not sent yet when the `RateLimitedOutput` is dropped is
+/// discarded.
+///
+/// ```no_run
+/// use dora_node_api::{arrow::array::Float64Array, DoraNode, MetadataParameters, RateLimitedOutput};
+/// use dora_node_api::dora_core::config::DataId;
+///
+/// let (node, _events) = DoraNode::init_from_env()?;
+/// let pose = RateLimitedOutput::new(node.output(DataId::from("pose".to_owned()))?, 10.0)?;
+/// for i in 0..1000 {
+///     pose.send(MetadataParameters::default(), Float64Array::from(vec![i as f64; 3]))?;
+/// }
+/// println!("{:?}", pose.stats());
+/// # eyre::Ok(())
+/// ```
+pub struct RateLimitedOutput {
+    output: Output,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// What [`RateLimitedOutput::send`] did with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitedSend {
+    /// The message was sent immediately.
+    Sent,
+    /// The message is sent at the next free slot.
+    Scheduled,
+    /// The message is sent at the next free slot, instead of a previously
+    /// scheduled message, which is dropped.
+    Replaced,
+}
+
+/// Counters of a [`RateLimitedOutput`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+    /// Messages that were passed on to the output, immediately or later.
+    pub sent: u64,
+    /// Messages that were passed on at the next free slot instead of
+    /// immediately, included in `sent`.
+    pub delayed: u64,
+    /// Scheduled messages that were dropped because a newer message was
+    /// sent before their slot.
+    pub superseded: u64,
+    /// Scheduled messages that failed to send.
+    pub failed: u64,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+struct State {
+    limiter: RateLimiter<PendingMessage>,
+    closed: bool,
+}
+
+struct PendingMessage {
+    parameters: MetadataParameters,
+    data: ArrayRef,
+}
+
+impl RateLimitedOutput {
+    pub fn new(output: Output, max_rate_hz: f64) -> eyre::Result<Self> {
+        if max_rate_hz.is_nan() || max_rate_hz <= 0.0 {
+            bail!("rate limit must be positive, got {max_rate_hz}");
+        }
+        let interval = Duration::try_from_secs_f64(1.0 / max_rate_hz)
+            .with_context(|| format!("invalid rate limit {max_rate_hz}"))?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                limiter: RateLimiter::new(interval),
+                closed: false,
+            }),
+            wake: Condvar::new(),
+        });
+        let thread = std::thread::Builder::new()
+            .name(format!("rate-limit-{}", output.id()))
+            .spawn({
+                let output = output.clone();
+                let shared = shared.clone();
+                move || send_scheduled(output, shared)
+            })
+            .context("failed to spawn rate limit thread")?;
+        Ok(Self {
+            output,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    /// Sends the given array now, or schedules it for the next free slot.
+    ///
+    /// Errors of scheduled messages are logged and counted in
+    /// [`RateLimitStats::failed`].
+    pub fn send(
+        &self,
+        parameters: MetadataParameters,
+        data: impl Array,
+    ) -> eyre::Result<RateLimitedSend> {
+        let message = PendingMessage {
+            parameters,
+            data: make_array(data.to_data()),
+        };
+        let (outcome, message) = self.shared.state().limiter.offer(message, Instant::now());
+        match message {
+            Some(message) => self.output.send(message.parameters, message.data)?,
+            None => self.shared.wake.notify_one(),
+        }
+        Ok(outcome)
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        self.shared.state().limiter.stats
+    }
+}
+
+impl Drop for RateLimitedOutput {
+    fn drop(&mut self) {
+        self.shared.state().closed = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!(
+                    "rate limit thread of output `{}` panicked",
+                    self.output.id()
+                );
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Sends the scheduled messages when their slot is due.
+fn send_scheduled(output: Output, shared: Arc<Shared>) {
+    let mut state = shared.state();
+    while !state.closed {
+        let now = Instant::now();
+        if let Some(message) = state.limiter.take_due(now) {
+            drop(state);
+            if let Err(err) = output.send(message.parameters, message.data) {
+                tracing::warn!("failed to send scheduled message: {err:?}");
+                shared.state().limiter.stats.failed += 1;
+            }
+            state = shared.state();
+            continue;
+        }
+        state = match state.limiter.next_due() {
+            Some(due) => {
+                let timeout = due.saturating_duration_since(now);
+                match shared.wake.wait_timeout(state, timeout) {
+                    Ok((state, _)) => state,
+                    Err(err) => err.into_inner().0,
+                }
+            }
+            None => shared
+                .wake
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner()),
+        };
+    }
+}
+
+/// Decides when messages are sent, independent of the actual sending.
+struct RateLimiter<T> {
+    interval: Duration,
+    /// Earliest time at which the next message may be sent.
+    next_slot: Option<Instant>,
+    pending: Option<T>,
+    stats: RateLimitStats,
+}
+
+impl<T> RateLimiter<T> {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_slot: None,
+            pending: None,
+            stats: RateLimitStats::default(),
+        }
+    }
+
+    /// Returns the message back if it should be sent immediately.
+    fn offer(&mut self, message: T, now: Instant) -> (RateLimitedSend, Option<T>) {
+        let slot_free = self.next_slot.map_or(true, |slot| slot <= now);
+        if self.pending.is_none() && slot_free {
+            self.take_slot(now);
+            return (RateLimitedSend::Sent, Some(message));
+        }
+        match self.pending.replace(message) {
+            Some(_) => {
+                self.stats.superseded += 1;
+                (RateLimitedSend::Replaced, None)
+            }
+            None => (RateLimitedSend::Scheduled, None),
+        }
+    }
+
+    /// Takes the scheduled message if its slot is due.
+    fn take_due(&mut self, now: Instant) -> Option<T> {
+        if self.next_due()? > now {
+            return None;
+        }
+        let message = self.pending.take()?;
+        self.take_slot(now);
+        self.stats.delayed += 1;
+        Some(message)
+    }
+
+    /// Time at which the scheduled message is due, if any.
+    fn next_due(&self) -> Option<Instant> {
+        self.pending.as_ref()?;
+        Some(self.next_slot.unwrap_or(Instant::now()))
+    }
+
+    fn take_slot(&mut self, now: Instant) {
+        self.next_slot = Some(now + self.interval);
+        self.stats.sent += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_message_is_sent_at_next_slot() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(100);
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut limiter = RateLimiter::new(interval);
+
+        assert_eq!(limiter.offer(1, at(0)), (RateLimitedSend::Sent, Some(1)));
+        assert_eq!(limiter.offer(2, at(10)), (RateLimitedSend::Scheduled, None));
+        assert_eq!(limiter.offer(3, at(20)), (RateLimitedSend::Replaced, None));
+        assert_eq!(limiter.next_due(), Some(at(100)));
+        assert_eq!(limiter.take_due(at(99)), None);
+        assert_eq!(limiter.take_due(at(100)), Some(3));
+        assert_eq!(limiter.next_due(), None);
+
+        // the slot of the delayed message is taken until 200ms
+        assert_eq!(
+            limiter.offer(4, at(150)),
+            (RateLimitedSend::Scheduled, None)
+        );
+        assert_eq!(limiter.take_due(at(200)), Some(4));
+        assert_eq!(limiter.offer(5, at(400)), (RateLimitedSend::Sent, Some(5)));
+
+        assert_eq!(
+            limiter.stats,
+            RateLimitStats {
+                sent: 4,
+                delayed: 2,
+                superseded: 1,
+                failed: 0,
+            }
+        );
+    }
+}
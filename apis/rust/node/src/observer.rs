@@ -0,0 +1,169 @@
+use crate::daemon_connection::{local_daemon_address, DaemonChannel};
+use dora_core::{
+    config::{DataId, NodeId},
+    uhlc,
+};
+use dora_message::{
+    common::NodeJoinInfo,
+    daemon_to_node::{DaemonReply, ObservedMessage},
+    node_to_daemon::{DaemonRequest, Timestamped},
+    DataflowId,
+};
+use eyre::{bail, eyre, Context};
+use std::{
+    net::{Shutdown, SocketAddr, TcpStream},
+    time::Instant,
+};
+
+/// Receives copies of the messages of a node output, without being part of
+/// the dataflow.
+///
+/// Unlike nodes, observers are not declared in the dataflow and don't
+/// affect it: the output is delivered to its receivers as usual, whether
+/// observers are connected or not. Observers that don't keep up skip
+/// messages, see [`ObservedMessage::skipped`]. The observed node must run
+/// on the machine of the daemon that the observer connects to.
+///
+/// ```no_run
+/// use dora_node_api::{dora_core::config::{DataId, NodeId}, DoraObserver};
+///
+/// let dataflow_id = "01920000-0000-7000-8000-000000000000".parse()?;
+/// let observer = DoraObserver::connect(
+///     dataflow_id,
+///     NodeId::from("camera".to_owned()),
+///     DataId::from("image".to_owned()),
+/// )?;
+/// while let Some(message) = observer.recv() {
+///     let message = message?;
+///     println!("{:?}", message.metadata.type_info.data_type);
+/// }
+/// # eyre::Ok(())
+/// ```
+pub struct DoraObserver {
+    messages: flume::Receiver<eyre::Result<ObservedMessage>>,
+    /// Used to close the connection when the observer is dropped.
+    connection: TcpStream,
+}
+
+impl DoraObserver {
+    /// Connects to the daemon at `DORA_DAEMON_ADDR`, or at the default local
+    /// daemon address if the variable is not set.
+    pub fn connect(
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+    ) -> eyre::Result<Self> {
+        Self::connect_to(local_daemon_address()?, dataflow_id, node_id, output_id)
+    }
+
+    /// Connects to the local listener of the daemon at the given address.
+    pub fn connect_to(
+        daemon_address: SocketAddr,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+    ) -> eyre::Result<Self> {
+        Self::connect_inner(daemon_address, None, dataflow_id, node_id, output_id)
+    }
+
+    /// Connects to the daemon that runs the node of the given join info, as
+    /// returned by a `ResolveNode` request to the coordinator.
+    ///
+    /// Works from any machine if the daemon listens on a non-loopback
+    /// interface. Otherwise, the observer connects to the local daemon like
+    /// [`Self::connect`], which only works on the machine of the node.
+    pub fn connect_with_join_info(info: &NodeJoinInfo, output_id: DataId) -> eyre::Result<Self> {
+        let daemon_address = match info.daemon_address {
+            Some(address) => address,
+            None => local_daemon_address()?,
+        };
+        Self::connect_inner(
+            daemon_address,
+            Some(info.token.clone()),
+            info.dataflow_id,
+            info.node_id.clone(),
+            output_id,
+        )
+    }
+
+    fn connect_inner(
+        daemon_address: SocketAddr,
+        join_token: Option<String>,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+    ) -> eyre::Result<Self> {
+        let stream =
+            TcpStream::connect(daemon_address).wrap_err("Could not connect to the daemon")?;
+        stream.set_nodelay(true).context("failed to set nodelay")?;
+        let connection = stream.try_clone().context("failed to clone TCP stream")?;
+        let mut channel = DaemonChannel::Tcp(stream);
+
+        let clock = uhlc::HLC::default();
+        if let Some(token) = join_token {
+            channel.join(token, clock.new_timestamp())?;
+        }
+        let thread_name = format!("observer-{node_id}/{output_id}");
+        let reply = channel
+            .request(&Timestamped {
+                inner: DaemonRequest::Observe {
+                    dataflow_id,
+                    node_id,
+                    output_id,
+                },
+                timestamp: clock.new_timestamp(),
+            })
+            .wrap_err("failed to send observe request to dora-daemon")?;
+        match reply {
+            DaemonReply::Result(Ok(())) => {}
+            DaemonReply::Result(Err(err)) => bail!("observe request failed: {err}"),
+            other => bail!("unexpected observe reply: {other:?}"),
+        }
+
+        let (tx, messages) = flume::bounded(1);
+        std::thread::Builder::new()
+            .name(thread_name)
+            .spawn(move || receive_loop(channel, tx))
+            .context("failed to spawn observer thread")?;
+        Ok(Self {
+            messages,
+            connection,
+        })
+    }
+
+    /// Waits for the next observed message.
+    ///
+    /// Returns `None` once the connection is closed, e.g. because the
+    /// dataflow finished.
+    pub fn recv(&self) -> Option<eyre::Result<ObservedMessage>> {
+        self.messages.recv().ok()
+    }
+
+    /// Like [`Self::recv`], but also returns `None` if no message arrived
+    /// before the given deadline.
+    pub fn recv_deadline(&self, deadline: Instant) -> Option<eyre::Result<ObservedMessage>> {
+        self.messages.recv_deadline(deadline).ok()
+    }
+}
+
+impl Drop for DoraObserver {
+    fn drop(&mut self) {
+        // stops the receive thread and unregisters the observer
+        let _ = self.connection.shutdown(Shutdown::Both);
+    }
+}
+
+fn receive_loop(mut channel: DaemonChannel, tx: flume::Sender<eyre::Result<ObservedMessage>>) {
+    loop {
+        let message = match channel.receive_reply() {
+            Ok(Some(DaemonReply::ObservedMessage(message))) => Ok(message),
+            Ok(Some(other)) => Err(eyre!("unexpected observer message: {other:?}")),
+            Ok(None) => break,
+            Err(err) => Err(err.wrap_err("failed to receive observed message")),
+        };
+        let failed = message.is_err();
+        if tx.send(message).is_err() || failed {
+            break;
+        }
+    }
+}
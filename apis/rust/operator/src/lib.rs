@@ -21,10 +21,11 @@
 pub use dora_arrow_convert::*;
 pub use dora_operator_api_macros::register_operator;
 pub use dora_operator_api_types as types;
+use std::collections::{BTreeMap, BTreeSet};
 pub use types::DoraStatus;
 use types::{
     arrow::{self, array::Array},
-    Metadata, Output, SendOutput,
+    Metadata, Output, SendOutput, TopologyResult,
 };
 
 pub mod raw;
@@ -66,4 +67,35 @@ impl DoraOutputSender<'_> {
         });
         result.into_result()
     }
+
+    /// Queries the inputs and outputs of the operator and the nodes that it
+    /// is connected to.
+    pub fn topology(&self) -> Result<Topology, String> {
+        let TopologyResult { result, topology } = self.0.query_topology.call();
+        result.into_result()?;
+        serde_json::from_str(&topology).map_err(|err| format!("invalid topology: {err}"))
+    }
+}
+
+/// The position of an operator in the dataflow graph.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[non_exhaustive]
+pub struct Topology {
+    /// Inputs of the operator, mapped to their sources (e.g. `camera/image`).
+    ///
+    /// Inputs with multiple sources list all of them.
+    pub inputs: BTreeMap<String, Vec<String>>,
+    /// Outputs of the operator.
+    pub outputs: BTreeSet<String>,
+    /// Declared message types of the inputs, as given in the `output_types`
+    /// of their sources.
+    #[serde(default)]
+    pub input_types: BTreeMap<String, String>,
+    /// Declared message types of the outputs.
+    #[serde(default)]
+    pub output_types: BTreeMap<String, String>,
+    /// Nodes that send messages to at least one input of the operator.
+    pub upstream: BTreeSet<String>,
+    /// Nodes that receive at least one output of the operator.
+    pub downstream: BTreeSet<String>,
 }
@@ -13,7 +13,7 @@ use arrow::{
 use core::slice;
 use safer_ffi::{
     char_p::{self, char_p_boxed},
-    closure::ArcDynFn1,
+    closure::{ArcDynFn0, ArcDynFn1},
     derive_ReprC, ffi_export,
 };
 use std::{ops::Deref, path::Path};
@@ -124,6 +124,17 @@ pub struct Metadata {
 #[repr(C)]
 pub struct SendOutput {
     pub send_output: ArcDynFn1<DoraResult, Output>,
+    pub query_topology: ArcDynFn0<TopologyResult>,
+}
+
+#[derive_ReprC]
+#[ffi_export]
+#[repr(C)]
+#[derive(Debug)]
+pub struct TopologyResult {
+    pub result: DoraResult,
+    /// The JSON-encoded topology of the operator, empty on errors.
+    pub topology: safer_ffi::String,
 }
 
 #[derive_ReprC]
@@ -1,16 +1,12 @@
 use colored::Colorize;
-use communication_layer_request_reply::{TcpConnection, TcpRequestReplyConnection};
+use dora_coordinator_client::{blocking::CoordinatorClient, ClientError};
+use dora_core::config::{NodeId, OperatorId};
 use dora_core::descriptor::{resolve_path, CoreNodeKind, Descriptor};
-use dora_message::cli_to_coordinator::ControlRequest;
 use dora_message::common::LogMessage;
-use dora_message::coordinator_to_cli::ControlRequestReply;
 use eyre::Context;
 use notify::event::ModifyKind;
 use notify::{Config, Event as NotifyEvent, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::{
-    collections::HashMap,
-    net::{SocketAddr, TcpStream},
-};
+use std::collections::HashMap;
 use std::{path::PathBuf, sync::mpsc, time::Duration};
 use tracing::{error, info};
 use uuid::Uuid;
@@ -19,11 +15,10 @@ use crate::handle_dataflow_result;
 
 pub fn attach_dataflow(
     dataflow: Descriptor,
-    dataflow_path: PathBuf,
+    working_dir: PathBuf,
     dataflow_id: Uuid,
-    session: &mut TcpRequestReplyConnection,
+    session: &mut CoordinatorClient,
     hot_reload: bool,
-    coordinator_socket: SocketAddr,
     log_level: log::LevelFilter,
 ) -> Result<(), eyre::ErrReport> {
     let (tx, rx) = mpsc::sync_channel(2);
@@ -33,30 +28,28 @@ pub fn attach_dataflow(
 
     let nodes = dataflow.resolve_aliases_and_set_defaults()?;
 
-    let working_dir = dataflow_path
-        .canonicalize()
-        .context("failed to canoncialize dataflow path")?
-        .parent()
-        .ok_or_else(|| eyre::eyre!("canonicalized dataflow path has no parent"))?
-        .to_owned();
-
-    for node in nodes {
-        match node.kind {
-            // Reloading Custom Nodes is not supported. See: https://github.com/dora-rs/dora/pull/239#discussion_r1154313139
-            CoreNodeKind::Custom(_cn) => (),
-            CoreNodeKind::Runtime(rn) => {
-                for op in rn.operators.iter() {
-                    if let dora_core::descriptor::OperatorSource::Python(python_source) =
-                        &op.config.source
-                    {
-                        let path = resolve_path(&python_source.source, &working_dir)
-                            .wrap_err_with(|| {
-                                format!("failed to resolve node source `{}`", python_source.source)
-                            })?;
-                        node_path_lookup
-                            .insert(path, (dataflow_id, node.id.clone(), Some(op.id.clone())));
+    // the sources only need to exist locally for hot reloading
+    if hot_reload {
+        for node in nodes {
+            match node.kind {
+                // Reloading Custom Nodes is not supported. See: https://github.com/dora-rs/dora/pull/239#discussion_r1154313139
+                CoreNodeKind::Custom(_cn) => (),
+                CoreNodeKind::Runtime(rn) => {
+                    for op in rn.operators.iter() {
+                        if let dora_core::descriptor::OperatorSource::Python(python_source) =
+                            &op.config.source
+                        {
+                            let path = resolve_path(&python_source.source, &working_dir)
+                                .wrap_err_with(|| {
+                                    format!(
+                                        "failed to resolve node source `{}`",
+                                        python_source.source
+                                    )
+                                })?;
+                            node_path_lookup.insert(path, (node.id.clone(), Some(op.id.clone())));
+                        }
+                        // Reloading non-python operator is not supported. See: https://github.com/dora-rs/dora/pull/239#discussion_r1154313139
                     }
-                    // Reloading non-python operator is not supported. See: https://github.com/dora-rs/dora/pull/239#discussion_r1154313139
                 }
             }
         }
@@ -75,13 +68,12 @@ pub fn attach_dataflow(
             }) = event
             {
                 for path in paths {
-                    if let Some((dataflow_id, node_id, operator_id)) = node_path_lookup.get(&path) {
+                    if let Some((node_id, operator_id)) = node_path_lookup.get(&path) {
                         watcher_tx
-                            .send(AttachEvent::Control(ControlRequest::Reload {
-                                dataflow_id: *dataflow_id,
+                            .send(AttachEvent::Reload {
                                 node_id: node_id.clone(),
                                 operator_id: operator_id.clone(),
-                            }))
+                            })
                             .context("Could not send reload request to the cli loop")
                             .unwrap();
                     }
@@ -110,13 +102,7 @@ pub fn attach_dataflow(
         if ctrlc_sent {
             std::process::abort();
         } else {
-            if ctrlc_tx
-                .send(AttachEvent::Control(ControlRequest::Stop {
-                    dataflow_uuid: dataflow_id,
-                    grace_duration: None,
-                }))
-                .is_err()
-            {
+            if ctrlc_tx.send(AttachEvent::Stop).is_err() {
                 // bail!("failed to report ctrl-c event to dora-daemon");
             }
             ctrlc_sent = true;
@@ -125,35 +111,31 @@ pub fn attach_dataflow(
     .wrap_err("failed to set ctrl-c handler")?;
 
     // subscribe to log messages
-    let mut log_session = TcpConnection {
-        stream: TcpStream::connect(coordinator_socket)
-            .wrap_err("failed to connect to dora coordinator")?,
-    };
-    log_session
-        .send(
-            &serde_json::to_vec(&ControlRequest::LogSubscribe {
-                dataflow_id,
-                level: log_level,
-            })
-            .wrap_err("failed to serialize message")?,
-        )
-        .wrap_err("failed to send log subscribe request to coordinator")?;
+    let log_messages = session
+        .subscribe_logs(dataflow_id, log_level)
+        .wrap_err("failed to subscribe to log messages")?;
     std::thread::spawn(move || {
-        while let Ok(raw) = log_session.receive() {
-            let parsed: eyre::Result<LogMessage> =
-                serde_json::from_slice(&raw).context("failed to parse log message");
-            if tx.send(AttachEvent::Log(parsed)).is_err() {
+        for message in log_messages {
+            if tx.send(AttachEvent::Log(message)).is_err() {
                 break;
             }
         }
     });
 
     loop {
-        let control_request = match rx.recv_timeout(Duration::from_secs(1)) {
-            Err(_err) => ControlRequest::Check {
-                dataflow_uuid: dataflow_id,
-            },
-            Ok(AttachEvent::Control(control_request)) => control_request,
+        let result = match rx.recv_timeout(Duration::from_secs(1)) {
+            Err(_err) => session.result(dataflow_id),
+            Ok(AttachEvent::Reload {
+                node_id,
+                operator_id,
+            }) => {
+                match session.reload(dataflow_id, node_id, operator_id) {
+                    Ok(()) => info!("dataflow {dataflow_id} reloaded"),
+                    Err(err) => error!("failed to reload dataflow {dataflow_id}: {err}"),
+                }
+                continue;
+            }
+            Ok(AttachEvent::Stop) => session.stop(dataflow_id, None).map(Some),
             Ok(AttachEvent::Log(Ok(log_message))) => {
                 let LogMessage {
                     dataflow_id: _,
@@ -189,26 +171,22 @@ pub fn attach_dataflow(
             }
         };
 
-        let reply_raw = session
-            .request(&serde_json::to_vec(&control_request)?)
-            .wrap_err("failed to send request message to coordinator")?;
-        let result: ControlRequestReply =
-            serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
         match result {
-            ControlRequestReply::DataflowStarted { uuid: _ } => (),
-            ControlRequestReply::DataflowStopped { uuid, result } => {
-                info!("dataflow {uuid} stopped");
-                break handle_dataflow_result(result, Some(uuid));
-            }
-            ControlRequestReply::DataflowReloaded { uuid } => {
-                info!("dataflow {uuid} reloaded")
+            Ok(None) => (),
+            Ok(Some(result)) => {
+                info!("dataflow {dataflow_id} stopped");
+                break handle_dataflow_result(result, Some(dataflow_id));
             }
-            other => error!("Received unexpected Coordinator Reply: {:#?}", other),
+            Err(err) => error!("request for dataflow {dataflow_id} failed: {err}"),
         };
     }
 }
 
 enum AttachEvent {
-    Control(ControlRequest),
-    Log(eyre::Result<LogMessage>),
+    Reload {
+        node_id: NodeId,
+        operator_id: Option<OperatorId>,
+    },
+    Stop,
+    Log(Result<LogMessage, ClientError>),
 }
@@ -1,7 +1,5 @@
 use crate::connect_to_coordinator;
-use communication_layer_request_reply::TcpRequestReplyConnection;
-use dora_message::{cli_to_coordinator::ControlRequest, coordinator_to_cli::ControlRequestReply};
-use eyre::{bail, Context};
+use eyre::bail;
 use std::{
     io::{IsTerminal, Write},
     net::SocketAddr,
@@ -39,13 +37,49 @@ pub fn check_environment(coordinator_addr: SocketAddr) -> eyre::Result<()> {
     // check whether daemon is running
     write!(stdout, "Dora Daemon: ")?;
     if session
-        .as_deref_mut()
-        .map(daemon_running)
+        .as_mut()
+        .map(|session| session.daemon_connected())
         .transpose()?
         .unwrap_or(false)
     {
         let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)));
         writeln!(stdout, "ok")?;
+        let _ = stdout.reset();
+
+        if let Some(session) = session.as_mut() {
+            for (machine_id, status) in session.daemon_status()? {
+                let machine = if machine_id.is_empty() {
+                    "default machine".to_owned()
+                } else {
+                    format!("machine `{machine_id}`")
+                };
+                match status.status {
+                    Ok(status) => {
+                        writeln!(stdout, "  {machine}: {status}")?;
+                        for (output, sizes) in &status.message_sizes {
+                            writeln!(stdout, "    sent {sizes} on {output}")?;
+                        }
+                    }
+                    Err(err) => {
+                        let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
+                        writeln!(stdout, "  {machine}: {err}")?;
+                        let _ = stdout.reset();
+                        error_occurred = true;
+                    }
+                }
+                writeln!(stdout, "    host: {}", status.metadata)?;
+                if let Some(health) = status.last_heartbeat {
+                    if health.dropped_messages > 0 {
+                        let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)));
+                    }
+                    writeln!(stdout, "    last heartbeat: {health}")?;
+                    for (input, count) in &health.dropped_inputs {
+                        writeln!(stdout, "      dropped {count} messages for {input}")?;
+                    }
+                    let _ = stdout.reset();
+                }
+            }
+        }
     } else {
         let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
         writeln!(stdout, "not running")?;
@@ -61,17 +95,3 @@ pub fn check_environment(coordinator_addr: SocketAddr) -> eyre::Result<()> {
 
     Ok(())
 }
-
-pub fn daemon_running(session: &mut TcpRequestReplyConnection) -> Result<bool, eyre::ErrReport> {
-    let reply_raw = session
-        .request(&serde_json::to_vec(&ControlRequest::DaemonConnected).unwrap())
-        .wrap_err("failed to send DaemonConnected message")?;
-
-    let reply = serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    let running = match reply {
-        ControlRequestReply::DaemonConnected(running) => running,
-        other => bail!("unexpected reply to daemon connection check: {other:?}"),
-    };
-
-    Ok(running)
-}
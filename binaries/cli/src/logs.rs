@@ -1,33 +1,19 @@
-use communication_layer_request_reply::TcpRequestReplyConnection;
-use dora_message::{cli_to_coordinator::ControlRequest, coordinator_to_cli::ControlRequestReply};
+use dora_coordinator_client::blocking::CoordinatorClient;
 use eyre::{bail, Context, Result};
 use uuid::Uuid;
 
 use bat::{Input, PrettyPrinter};
 
 pub fn logs(
-    session: &mut TcpRequestReplyConnection,
+    session: &mut CoordinatorClient,
     uuid: Option<Uuid>,
     name: Option<String>,
     node: String,
 ) -> Result<()> {
-    let logs = {
-        let reply_raw = session
-            .request(
-                &serde_json::to_vec(&ControlRequest::Logs {
-                    uuid,
-                    name,
-                    node: node.clone(),
-                })
-                .wrap_err("")?,
-            )
-            .wrap_err("failed to send Logs request message")?;
-
-        let reply = serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-        match reply {
-            ControlRequestReply::Logs(logs) => logs,
-            other => bail!("unexpected reply to daemon logs: {other:?}"),
-        }
+    let logs = match (uuid, name) {
+        (Some(uuid), _) => session.logs(uuid, &node, None)?,
+        (None, Some(name)) => session.logs_by_name(name, &node, None)?,
+        (None, None) => bail!("no dataflow given"),
     };
 
     PrettyPrinter::new()
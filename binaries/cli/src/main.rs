@@ -1,19 +1,27 @@
 use attach::attach_dataflow;
 use clap::Parser;
 use colored::Colorize;
-use communication_layer_request_reply::{RequestReplyLayer, TcpLayer, TcpRequestReplyConnection};
 use dora_coordinator::Event;
+use dora_coordinator_client::{blocking::CoordinatorClient, ClientError, StartOptions};
 use dora_core::{
+    config::Rate,
     descriptor::Descriptor,
     topics::{
         DORA_COORDINATOR_PORT_CONTROL_DEFAULT, DORA_COORDINATOR_PORT_DEFAULT,
         DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT,
     },
 };
-use dora_daemon::Daemon;
+use dora_daemon::{
+    journal::JournalConfig, ConnectionLimits, Daemon, DaemonBuilder, DaemonPathsConfig,
+    NodeRegistryConfig, StallConfig, DEFAULT_DROP_WARNING_INTERVAL, DEFAULT_HANDSHAKE_TIMEOUT,
+    DEFAULT_MAX_NODE_CONNECTIONS, DEFAULT_MAX_REQUEST_RATE, DEFAULT_PERSISTENT_CACHE_SIZE,
+    DEFAULT_STALL_THRESHOLD, DEFAULT_UDP_DATAGRAM_SIZE,
+};
 use dora_message::{
-    cli_to_coordinator::ControlRequest,
-    coordinator_to_cli::{ControlRequestReply, DataflowList, DataflowResult, DataflowStatus},
+    cli_to_coordinator::{InstanceKey, RegistryKey},
+    coordinator_to_cli::{DataflowResult, DataflowStatus},
+    daemon_to_daemon::InterDaemonTransport,
+    reconfigure::{InputChange, QueuePolicy},
 };
 #[cfg(feature = "tracing")]
 use dora_tracing::set_up_tracing;
@@ -24,7 +32,7 @@ use formatting::FormatDataflowError;
 use std::{io::Write, net::SocketAddr};
 use std::{
     net::{IpAddr, Ipv4Addr},
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::Duration,
 };
 use tabwriter::TabWriter;
@@ -37,6 +45,7 @@ mod check;
 mod formatting;
 mod graph;
 mod logs;
+mod tap;
 mod template;
 mod up;
 
@@ -52,6 +61,7 @@ struct Args {
 
 /// dora-rs cli client
 #[derive(Debug, clap::Subcommand)]
+#[allow(clippy::large_enum_variant)] // parsed only once
 enum Command {
     /// Check if the coordinator and the daemon is running.
     Check {
@@ -108,14 +118,42 @@ enum Command {
         #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
         coordinator_port: u16,
     },
+    /// Store a dataflow in the registry of the coordinator, so that it can
+    /// be started by name.
+    Register {
+        /// Path to the dataflow descriptor file
+        #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        dataflow: PathBuf,
+        /// Name under which the dataflow is registered
+        #[clap(long)]
+        name: String,
+        /// Version of the registered dataflow, defaults to the version after
+        /// the latest registered one
+        #[clap(long)]
+        version: Option<u32>,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
     /// Start the given dataflow path. Attach a name to the running dataflow by using --name.
     Start {
-        /// Path to the dataflow descriptor file
+        /// Path to the dataflow descriptor file, or `NAME[@VERSION]` of a
+        /// registered dataflow (see `dora register`)
         #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
         dataflow: PathBuf,
         /// Assign a name to the dataflow
         #[clap(long)]
         name: Option<String>,
+        /// Start the dataflow as an instance of `--name`, so that the same
+        /// name can be used by multiple running dataflows.
+        ///
+        /// Without a key, the lowest number that is not used by another
+        /// running instance is chosen.
+        #[clap(long, value_name = "KEY", requires = "name", num_args = 0..=1, default_missing_value = "")]
+        instance: Option<String>,
         /// Address of the dora coordinator
         #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
         coordinator_addr: IpAddr,
@@ -131,6 +169,26 @@ enum Command {
         /// Enable hot reloading (Python only)
         #[clap(long, action)]
         hot_reload: bool,
+        /// Working directory on the given machine, used to resolve relative
+        /// node paths there (e.g. `--machine-working-dir robot=/home/dora/dataflow`)
+        ///
+        /// Machines without entry use the directory of the dataflow file.
+        #[clap(long, value_name = "MACHINE=DIR", value_parser = parse_machine_working_dir)]
+        machine_working_dir: Vec<(String, PathBuf)>,
+        /// Start parameter that nodes can read at runtime (e.g.
+        /// `--param seed=42`), can be given multiple times
+        ///
+        /// The parameters are listed in `dora list` and in the result summary.
+        #[clap(long = "param", value_name = "KEY=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+        /// Write a JSON summary of the result to the given file when the
+        /// dataflow finishes (overrides the `result_file` of the dataflow)
+        #[clap(long, value_name = "PATH")]
+        result_file: Option<PathBuf>,
+        /// Validate the dataflow and print what would be spawned on each
+        /// machine, without starting it
+        #[clap(long, action, conflicts_with_all = ["attach", "detach", "hot_reload"])]
+        dry_run: bool,
     },
     /// Stop the given dataflow UUID. If no id is provided, you will be able to choose between the running dataflows.
     Stop {
@@ -139,6 +197,17 @@ enum Command {
         /// Name of the dataflow that should be stopped
         #[clap(long)]
         name: Option<String>,
+        /// Instance of the named dataflow that should be stopped
+        #[clap(
+            long,
+            value_name = "KEY",
+            requires = "name",
+            conflicts_with = "all_instances"
+        )]
+        instance: Option<String>,
+        /// Stop all running instances of the named dataflow
+        #[clap(long, requires = "name")]
+        all_instances: bool,
         /// Kill the dataflow if it doesn't stop after the given duration
         #[clap(long, value_name = "DURATION")]
         #[arg(value_parser = parse)]
@@ -177,6 +246,154 @@ enum Command {
         #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
         coordinator_port: u16,
     },
+    /// Print the messages of a node output for a while, without affecting
+    /// their delivery.
+    #[command(allow_missing_positional = true)]
+    Tap {
+        /// Identifier of the dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: Option<String>,
+        /// Output to tap
+        #[clap(value_name = "NODE/OUTPUT")]
+        output: String,
+        /// Stop tapping after the given duration
+        #[clap(long, value_name = "DURATION", default_value = "10s")]
+        #[arg(value_parser = parse)]
+        duration: Duration,
+        /// Maximum number of printed messages per second
+        #[clap(long, value_name = "RATE")]
+        max_rate: Option<f64>,
+        /// Receive the messages directly from the daemon of this machine,
+        /// without a coordinator. Requires the UUID of the dataflow.
+        #[clap(long)]
+        local: bool,
+        /// Port number of the local listener of the daemon, used with `--local`
+        #[clap(long, value_name = "PORT", default_value_t = DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT)]
+        daemon_port: u16,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Print the resolved descriptor of a running dataflow.
+    Descriptor {
+        /// Identifier of the running dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: String,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Show what would change when restarting a running dataflow with the
+    /// given (edited) dataflow file.
+    Diff {
+        /// Identifier of the running dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: String,
+        /// Path to the new dataflow descriptor file
+        #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        new: PathBuf,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Move a node of a running dataflow to another machine without
+    /// stopping the dataflow.
+    Migrate {
+        /// Identifier of the running dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: String,
+        /// Node to migrate
+        #[clap(value_name = "NODE")]
+        node: String,
+        /// Machine ID of the target daemon, which must run other nodes of the
+        /// dataflow already (use `""` for the default machine)
+        #[clap(value_name = "MACHINE")]
+        machine: String,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Change the queue settings of an input of a running dataflow without
+    /// restarting its node.
+    Reconfigure {
+        /// Identifier of the running dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: String,
+        /// Input to reconfigure
+        #[clap(long, value_name = "NODE/INPUT", value_parser = parse_input)]
+        input: (String, String),
+        /// Maximum number of pending messages of the input
+        #[clap(long = "queue", value_name = "SIZE")]
+        queue_size: Option<usize>,
+        /// Policy of the input queue: `drop-oldest` or `latest`
+        #[clap(long, value_name = "POLICY")]
+        policy: Option<QueuePolicy>,
+        /// Delivery priority of the input, higher values are delivered first
+        #[clap(long)]
+        priority: Option<u8>,
+        /// Enable `adaptive` downsampling with the given minimum rate (e.g.
+        /// `5Hz`)
+        #[clap(long, value_name = "RATE")]
+        adaptive_min_rate: Option<Rate>,
+        /// Send an `InputTimeout` event to the node when the input is silent
+        /// for this duration
+        #[clap(long, value_name = "DURATION")]
+        #[arg(value_parser = parse)]
+        timeout: Option<Duration>,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Change the log filter of a running daemon without restarting it.
+    LogLevel {
+        /// Machine ID of the daemon (use `""` for the default machine)
+        #[clap(value_name = "MACHINE")]
+        machine: String,
+        /// New log filter, using the `RUST_LOG` syntax (e.g. `info,dora_daemon=trace`)
+        #[clap(value_name = "FILTER")]
+        filter: String,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Print a snapshot of the internal state of a running daemon.
+    Diagnostics {
+        /// Machine ID of the daemon (use `""` for the default machine)
+        #[clap(value_name = "MACHINE")]
+        machine: String,
+        /// Release the drop tokens that are pending for longer than `--gc-age`
+        /// before taking the snapshot
+        #[clap(long)]
+        gc: bool,
+        /// Minimum age of the drop tokens that are released by `--gc`
+        #[clap(long, value_name = "DURATION", default_value = "60s")]
+        #[arg(value_parser = parse)]
+        gc_age: Duration,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
     // Metrics,
     // Stats,
     // Get,
@@ -192,6 +409,25 @@ enum Command {
         /// Local listen port for event such as dynamic node.
         #[clap(long, default_value_t = DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT)]
         local_listen_port: u16,
+        /// Interface that the listener for dynamic nodes binds to.
+        ///
+        /// With a non-loopback interface, external nodes and observers on
+        /// other machines can connect using a join token from the coordinator.
+        #[clap(long, default_value_t = LOCALHOST)]
+        local_listen_interface: IpAddr,
+        /// Preferred transport for sending outputs to other daemons (`tcp` or `zenoh`).
+        ///
+        /// The zenoh transport is only used for dataflows whose daemons all prefer it.
+        /// It requires the `zenoh` feature.
+        #[clap(long, default_value_t = InterDaemonTransport::Tcp)]
+        inter_daemon_transport: InterDaemonTransport,
+        /// Maximum size in bytes of the UDP datagrams of outputs with
+        /// `remote_transport: udp`.
+        ///
+        /// Lower it if the MTU of the network between the machines is smaller
+        /// than the usual 1500 bytes.
+        #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_UDP_DATAGRAM_SIZE)]
+        udp_datagram_size: usize,
         /// Address and port number of the dora coordinator
         #[clap(long, short, default_value_t = LOCALHOST)]
         coordinator_addr: IpAddr,
@@ -203,6 +439,118 @@ enum Command {
         /// Suppresses all log output to stdout.
         #[clap(long)]
         quiet: bool,
+        /// Directory in which a journal of lifecycle events is recorded.
+        #[clap(long, value_name = "DIR")]
+        journal: Option<PathBuf>,
+        /// Size in bytes after which the journal file is rotated.
+        #[clap(long, value_name = "BYTES", default_value_t = 10 * 1024 * 1024)]
+        journal_max_size: u64,
+        /// Pretty-prints the given journal file or directory and exits.
+        #[clap(long, value_name = "PATH")]
+        dump_journal: Option<PathBuf>,
+        /// Minimum number of seconds between two warnings about dropped
+        /// messages of the same input.
+        #[clap(long, value_name = "SECS", default_value_t = DEFAULT_DROP_WARNING_INTERVAL.as_secs())]
+        drop_warning_interval: u64,
+        /// Number of seconds without any delivered message after which a
+        /// dataflow with running nodes is reported as stalled. 0 disables the
+        /// check.
+        #[clap(long, value_name = "SECS", default_value_t = DEFAULT_STALL_THRESHOLD.as_secs())]
+        stall_threshold: u64,
+        /// Reports stalled dataflows to the coordinator, which shows them in
+        /// `dora list`.
+        #[clap(long)]
+        report_stalls: bool,
+        /// Write a JSON summary of the result to the given file when the
+        /// dataflow given in `--run-dataflow` finishes.
+        #[clap(long, value_name = "PATH", requires = "run_dataflow")]
+        result_file: Option<PathBuf>,
+        /// Default working directory for dataflows spawned on this machine.
+        ///
+        /// Relative node paths are resolved against this directory instead
+        /// of the directory of the dataflow file on the machine that started
+        /// the dataflow. A working dir given for this machine on `dora start`
+        /// takes precedence.
+        #[clap(long, value_name = "DIR", conflicts_with = "run_dataflow")]
+        default_working_dir: Option<PathBuf>,
+        /// Maximum number of open connections from nodes to this daemon.
+        ///
+        /// Further connections are closed right away.
+        #[clap(long, value_name = "COUNT", default_value_t = DEFAULT_MAX_NODE_CONNECTIONS)]
+        max_node_connections: usize,
+        /// Seconds after which node connections are closed if they didn't
+        /// send their first request.
+        #[clap(long, value_name = "SECS", default_value_t = DEFAULT_HANDSHAKE_TIMEOUT.as_secs())]
+        node_handshake_timeout: u64,
+        /// Maximum number of requests per second of a node connection.
+        ///
+        /// Connections that exceed the rate are closed.
+        #[clap(long, value_name = "RATE", default_value_t = DEFAULT_MAX_REQUEST_RATE)]
+        max_node_request_rate: u32,
+        /// File in which the PIDs of the spawned nodes are recorded.
+        ///
+        /// Nodes of a previous daemon run that are still listed in this file
+        /// are terminated on startup. Defaults to a file in the state dir
+        /// that is specific to the machine ID.
+        #[clap(long, value_name = "PATH", conflicts_with = "run_dataflow")]
+        node_registry: Option<PathBuf>,
+        /// Keep still running nodes of a previous daemon run alive instead
+        /// of terminating them.
+        ///
+        /// Adopted nodes can't reconnect to the new daemon. Their dataflow
+        /// IDs can't be used again until they exited.
+        #[clap(long, conflicts_with = "run_dataflow")]
+        adopt_orphans: bool,
+        /// Label of this machine, e.g. `gpu` or `camera=usb` (can be repeated)
+        ///
+        /// Nodes can require labels through `deploy.requires` in the dataflow
+        /// descriptor. A label without value is set to `true`.
+        #[clap(long, value_name = "KEY[=VALUE]", conflicts_with = "run_dataflow", value_parser = parse_label)]
+        label: Vec<(String, String)>,
+        /// Fail if dora-coordinator is not reachable within the given duration.
+        ///
+        /// By default, the daemon waits for the coordinator indefinitely,
+        /// retrying the connection with increasing delays.
+        #[clap(long, value_name = "DURATION", conflicts_with = "run_dataflow")]
+        #[arg(value_parser = parse)]
+        require_coordinator_within: Option<Duration>,
+        /// Token to register at dora-coordinator with, if its auth config
+        /// sets a `machine_token`.
+        ///
+        /// Falls back to the `DORA_MACHINE_TOKEN` environment variable,
+        /// which doesn't show up in the process list.
+        #[clap(long, value_name = "TOKEN")]
+        machine_token: Option<String>,
+        /// Directory for persistent state like the node registry.
+        ///
+        /// Defaults to `$XDG_STATE_HOME/dora`, or to `/var/lib/dora` without
+        /// home directory.
+        #[clap(long, value_name = "DIR", conflicts_with = "run_dataflow")]
+        state_dir: Option<PathBuf>,
+        /// Directory for cached files like downloaded nodes.
+        ///
+        /// Defaults to `$XDG_CACHE_HOME/dora`, or to `/var/cache/dora`
+        /// without home directory.
+        #[clap(long, value_name = "DIR", conflicts_with = "run_dataflow")]
+        cache_dir: Option<PathBuf>,
+        /// Maximum size in bytes of the cached messages of persistent outputs.
+        ///
+        /// The cache is kept in the cache dir. When it grows beyond this
+        /// size, the least recently used messages are evicted.
+        #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_PERSISTENT_CACHE_SIZE)]
+        persistent_cache_size: u64,
+        /// Directory for the log and output files of all dataflows.
+        ///
+        /// Defaults to the `out` directory in the working dir of each
+        /// dataflow.
+        #[clap(long, value_name = "DIR", conflicts_with = "run_dataflow")]
+        log_dir: Option<PathBuf>,
+        /// Fail on exit if dataflows, pending messages, spawned nodes, or
+        /// shared memory regions were not cleaned up.
+        ///
+        /// Meant for CI runs that check the daemon for resource leaks.
+        #[clap(long)]
+        strict_shutdown: bool,
     },
     /// Run runtime
     Runtime,
@@ -220,6 +568,15 @@ enum Command {
         /// Port number to bind to for control communication
         #[clap(long, default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
         control_port: u16,
+        /// YAML file with the tokens that control clients must authenticate
+        /// with, and their roles (`read-only`, `operator`, or `admin`).
+        ///
+        /// Clients pass their token through the `DORA_COORDINATOR_TOKEN`
+        /// environment variable. Without this file, all clients that can
+        /// reach the control port may send any request. An optional
+        /// `machine_token` entry is required from daemons on registration.
+        #[clap(long, value_name = "PATH")]
+        auth_config: Option<PathBuf>,
         /// Suppresses all log output to stdout.
         #[clap(long)]
         quiet: bool,
@@ -269,7 +626,10 @@ fn run() -> eyre::Result<()> {
     #[cfg(feature = "tracing")]
     match &args.command {
         Command::Daemon {
-            quiet, machine_id, ..
+            quiet,
+            machine_id,
+            dump_journal: None,
+            ..
         } => {
             let name = "dora-daemon";
             let filename = machine_id
@@ -341,40 +701,276 @@ fn run() -> eyre::Result<()> {
         } => {
             let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
                 .wrap_err("failed to connect to dora coordinator")?;
-            let list = query_running_dataflows(&mut *session)
-                .wrap_err("failed to query running dataflows")?;
             if let Some(dataflow) = dataflow {
                 let uuid = Uuid::parse_str(&dataflow).ok();
                 let name = if uuid.is_some() { None } else { Some(dataflow) };
-                logs::logs(&mut *session, uuid, name, node)?
+                logs::logs(&mut session, uuid, name, node)?
             } else {
-                let active = list.get_active();
+                let active = session
+                    .list()
+                    .wrap_err("failed to query running dataflows")?
+                    .get_active();
                 let uuid = match &active[..] {
                     [] => bail!("No dataflows are running"),
                     [uuid] => uuid.clone(),
                     _ => inquire::Select::new("Choose dataflow to show logs:", active).prompt()?,
                 };
-                logs::logs(&mut *session, Some(uuid.uuid), None, node)?
+                logs::logs(&mut session, Some(uuid.uuid), None, node)?
             }
         }
+        Command::Tap {
+            dataflow,
+            output,
+            duration,
+            max_rate,
+            local,
+            daemon_port,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let Some((node_id, output_id)) = output.split_once('/') else {
+                bail!("expected output in `<node>/<output>` format, got `{output}`");
+            };
+            if local {
+                let Some(dataflow) = dataflow else {
+                    bail!("`--local` requires the UUID of the dataflow");
+                };
+                // names can only be resolved by the coordinator
+                let dataflow_id = Uuid::parse_str(&dataflow)
+                    .wrap_err("`--local` requires the UUID of the dataflow")?;
+                tap::tap_local(
+                    (LOCALHOST, daemon_port).into(),
+                    dataflow_id,
+                    node_id.to_owned().into(),
+                    output_id.to_owned().into(),
+                    duration,
+                    max_rate,
+                )?;
+            } else {
+                let mut session =
+                    connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                        .wrap_err("failed to connect to dora coordinator")?;
+                let dataflow_id = match dataflow {
+                    Some(dataflow) => session.resolve(&dataflow)?,
+                    None => {
+                        let active = session
+                            .list()
+                            .wrap_err("failed to query running dataflows")?
+                            .get_active();
+                        match &active[..] {
+                            [] => bail!("No dataflows are running"),
+                            [d] => d.uuid,
+                            _ => {
+                                inquire::Select::new("Choose dataflow to tap:", active)
+                                    .prompt()?
+                                    .uuid
+                            }
+                        }
+                    }
+                };
+                tap::tap(
+                    &session,
+                    dataflow_id,
+                    node_id.to_owned().into(),
+                    output_id.to_owned().into(),
+                    duration,
+                    max_rate,
+                )?;
+            }
+        }
+        Command::Descriptor {
+            dataflow,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let dataflow_uuid = session.resolve(&dataflow)?;
+            print!("{}", session.descriptor(dataflow_uuid)?);
+        }
+        Command::Diff {
+            dataflow,
+            new,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let descriptor =
+                Descriptor::blocking_read(&new).wrap_err("Failed to read yaml dataflow")?;
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let dataflow_uuid = session.resolve(&dataflow)?;
+            let diff = session.diff(dataflow_uuid, descriptor)?;
+            print!("{diff}");
+        }
+        Command::Migrate {
+            dataflow,
+            node,
+            machine,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let dataflow_uuid = session.resolve(&dataflow)?;
+            let report = session.migrate_node(dataflow_uuid, node.into(), machine)?;
+            print!("{report}");
+            if !report.is_success() {
+                bail!("failed to migrate node");
+            }
+        }
+        Command::Reconfigure {
+            dataflow,
+            input: (node, input),
+            queue_size,
+            policy,
+            priority,
+            adaptive_min_rate,
+            timeout,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let mut change = InputChange::new(node.into(), input.into());
+            change.queue_size = queue_size;
+            change.policy = policy;
+            change.priority = priority;
+            change.adaptive_min_rate = adaptive_min_rate;
+            change.timeout = timeout;
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let dataflow_uuid = session.resolve(&dataflow)?;
+            let report = session.reconfigure(dataflow_uuid, vec![change])?;
+            print!("{report}");
+            if !report.is_success() {
+                bail!("failed to reconfigure input");
+            }
+        }
+        Command::LogLevel {
+            machine,
+            filter,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let previous = session.set_log_level(machine, filter)?;
+            println!("changed log filter (previous filter: `{previous}`)");
+        }
+        Command::Diagnostics {
+            machine,
+            gc,
+            gc_age,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let diagnostics = session.diagnostics(machine, gc.then_some(gc_age))?;
+            print!("{diagnostics}");
+        }
+        Command::Register {
+            dataflow,
+            name,
+            version,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let descriptor =
+                Descriptor::blocking_read(&dataflow).wrap_err("Failed to read yaml dataflow")?;
+            let working_dir = dataflow
+                .canonicalize()
+                .context("failed to canonicalize dataflow path")?
+                .parent()
+                .ok_or_else(|| eyre::eyre!("dataflow path has no parent dir"))?
+                .to_owned();
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                .wrap_err("failed to connect to dora coordinator")?;
+            let entry = session.register(name, version, descriptor, working_dir)?;
+            println!(
+                "registered {}@{} ({})",
+                entry.name, entry.version, entry.hash
+            );
+        }
         Command::Start {
             dataflow,
             name,
+            instance,
             coordinator_addr,
             coordinator_port,
             attach,
             detach,
             hot_reload,
+            machine_working_dir,
+            params,
+            result_file,
+            dry_run,
         } => {
-            let dataflow_descriptor =
+            if let Some(key) = registry_key(&dataflow) {
+                if result_file.is_some() || hot_reload {
+                    bail!("`--result-file` and `--hot-reload` require a dataflow file");
+                }
+                let mut session =
+                    connect_to_coordinator((coordinator_addr, coordinator_port).into())
+                        .wrap_err("failed to connect to dora coordinator")?;
+                let (entry, dataflow_descriptor) = session.fetch_registered(key)?;
+                let instance = instance.map(|key| match key.as_str() {
+                    "" => InstanceKey::Next,
+                    _ => InstanceKey::Explicit(key),
+                });
+                let options = StartOptions {
+                    working_dir: entry.working_dir.clone(),
+                    machine_working_dirs: machine_working_dir.into_iter().collect(),
+                    name,
+                    instance,
+                    params: params.into_iter().collect(),
+                };
+                if dry_run {
+                    let plan = session
+                        .plan(dataflow_descriptor, options)
+                        .wrap_err("failed to plan dataflow")?;
+                    print!("{plan}");
+                    return Ok(());
+                }
+                // start the fetched version, even if a newer one was registered since
+                let key = RegistryKey {
+                    name: entry.name.clone(),
+                    version: Some(entry.version),
+                };
+                let handle = session.start_registered(key, options)?;
+                for (node_id, machine) in handle.assignments {
+                    eprintln!("node `{node_id}` runs on machine `{machine}`");
+                }
+                eprintln!("{}", handle.uuid);
+                if attach_or_detach(attach, detach)? {
+                    attach_dataflow(
+                        dataflow_descriptor,
+                        entry.working_dir,
+                        handle.uuid,
+                        &mut session,
+                        false,
+                        log_level,
+                    )?
+                }
+                return Ok(());
+            }
+
+            let mut dataflow_descriptor =
                 Descriptor::blocking_read(&dataflow).wrap_err("Failed to read yaml dataflow")?;
+            if let Some(path) = result_file {
+                // relative paths are resolved by the coordinator otherwise
+                let path = std::env::current_dir()
+                    .context("failed to get current dir")?
+                    .join(path);
+                dataflow_descriptor.result_file = Some(path);
+            }
             let working_dir = dataflow
                 .canonicalize()
                 .context("failed to canonicalize dataflow path")?
                 .parent()
                 .ok_or_else(|| eyre::eyre!("dataflow path has no parent dir"))?
                 .to_owned();
-            if !coordinator_addr.is_loopback() {
+            // node paths are resolved on the target machines, so we can't
+            // check them locally for remote machines
+            if !coordinator_addr.is_loopback() || !machine_working_dir.is_empty() {
                 dataflow_descriptor.check_in_daemon(&working_dir, &[], true)?;
             } else {
                 dataflow_descriptor
@@ -382,34 +978,36 @@ fn run() -> eyre::Result<()> {
                     .wrap_err("Could not validate yaml")?;
             }
 
-            let coordinator_socket = (coordinator_addr, coordinator_port).into();
-            let mut session = connect_to_coordinator(coordinator_socket)
+            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
                 .wrap_err("failed to connect to dora coordinator")?;
-            let dataflow_id = start_dataflow(
-                dataflow_descriptor.clone(),
-                name,
+            let instance = instance.map(|key| match key.as_str() {
+                "" => InstanceKey::Next,
+                _ => InstanceKey::Explicit(key),
+            });
+            let options = StartOptions {
                 working_dir,
-                &mut *session,
-            )?;
-
-            let attach = match (attach, detach) {
-                (true, true) => eyre::bail!("both `--attach` and `--detach` are given"),
-                (true, false) => true,
-                (false, true) => false,
-                (false, false) => {
-                    println!("attaching to dataflow (use `--detach` to run in background)");
-                    true
-                }
+                machine_working_dirs: machine_working_dir.into_iter().collect(),
+                name,
+                instance,
+                params: params.into_iter().collect(),
             };
+            if dry_run {
+                let plan = session
+                    .plan(dataflow_descriptor, options)
+                    .wrap_err("failed to plan dataflow")?;
+                print!("{plan}");
+                return Ok(());
+            }
+            let dataflow_id =
+                start_dataflow(dataflow_descriptor.clone(), options.clone(), &mut session)?;
 
-            if attach {
+            if attach_or_detach(attach, detach)? {
                 attach_dataflow(
                     dataflow_descriptor,
-                    dataflow,
+                    options.working_dir,
                     dataflow_id,
-                    &mut *session,
+                    &mut session,
                     hot_reload,
-                    coordinator_socket,
                     log_level,
                 )?
             }
@@ -418,7 +1016,7 @@ fn run() -> eyre::Result<()> {
             coordinator_addr,
             coordinator_port,
         } => match connect_to_coordinator((coordinator_addr, coordinator_port).into()) {
-            Ok(mut session) => list(&mut *session)?,
+            Ok(mut session) => list(&mut session)?,
             Err(_) => {
                 bail!("No dora coordinator seems to be running.");
             }
@@ -426,6 +1024,8 @@ fn run() -> eyre::Result<()> {
         Command::Stop {
             uuid,
             name,
+            instance,
+            all_instances,
             grace_duration,
             coordinator_addr,
             coordinator_port,
@@ -433,9 +1033,14 @@ fn run() -> eyre::Result<()> {
             let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
                 .wrap_err("could not connect to dora coordinator")?;
             match (uuid, name) {
-                (Some(uuid), _) => stop_dataflow(uuid, grace_duration, &mut *session)?,
-                (None, Some(name)) => stop_dataflow_by_name(name, grace_duration, &mut *session)?,
-                (None, None) => stop_dataflow_interactive(grace_duration, &mut *session)?,
+                (Some(uuid), _) => stop_dataflow(uuid, grace_duration, &mut session)?,
+                (None, Some(name)) if all_instances => {
+                    stop_all_instances(&name, grace_duration, &mut session)?
+                }
+                (None, Some(name)) => {
+                    stop_dataflow_by_name(name, instance, grace_duration, &mut session)?
+                }
+                (None, None) => stop_dataflow_interactive(grace_duration, &mut session)?,
             }
         }
         Command::Destroy {
@@ -451,8 +1056,12 @@ fn run() -> eyre::Result<()> {
             port,
             control_interface,
             control_port,
+            auth_config,
             quiet,
         } => {
+            let auth = auth_config
+                .map(|path| dora_coordinator::AuthConfig::load(&path))
+                .transpose()?;
             let rt = Builder::new_multi_thread()
                 .enable_all()
                 .build()
@@ -460,9 +1069,13 @@ fn run() -> eyre::Result<()> {
             rt.block_on(async {
                 let bind = SocketAddr::new(interface, port);
                 let bind_control = SocketAddr::new(control_interface, control_port);
-                let (port, task) =
-                    dora_coordinator::start(bind, bind_control, futures::stream::empty::<Event>())
-                        .await?;
+                let (port, task) = dora_coordinator::start(
+                    bind,
+                    bind_control,
+                    auth,
+                    futures::stream::empty::<Event>(),
+                )
+                .await?;
                 if !quiet {
                     println!("Listening for incoming daemon connection on {port}");
                 }
@@ -474,11 +1087,63 @@ fn run() -> eyre::Result<()> {
             coordinator_addr,
             coordinator_port,
             inter_daemon_addr,
+            local_listen_interface,
             local_listen_port,
+            inter_daemon_transport,
+            udp_datagram_size,
             machine_id,
             run_dataflow,
             quiet: _,
+            journal,
+            journal_max_size,
+            dump_journal,
+            drop_warning_interval,
+            stall_threshold,
+            report_stalls,
+            result_file,
+            default_working_dir,
+            max_node_connections,
+            node_handshake_timeout,
+            max_node_request_rate,
+            node_registry,
+            adopt_orphans,
+            label,
+            require_coordinator_within,
+            machine_token,
+            state_dir,
+            cache_dir,
+            persistent_cache_size,
+            log_dir,
+            strict_shutdown,
         } => {
+            if let Some(path) = dump_journal {
+                dora_daemon::journal::dump(&path)?;
+                return Ok(());
+            }
+            let journal = journal.map(|dir| JournalConfig {
+                dir,
+                max_file_size: journal_max_size,
+            });
+            let connection_limits = ConnectionLimits {
+                max_connections: max_node_connections,
+                handshake_timeout: Duration::from_secs(node_handshake_timeout),
+                max_request_rate: max_node_request_rate,
+            };
+            let machine_id = machine_id.unwrap_or_default();
+            let node_registry = NodeRegistryConfig {
+                path: node_registry,
+                adopt_orphans,
+            };
+            let paths = DaemonPathsConfig {
+                state_dir,
+                cache_dir,
+                log_dir,
+                persistent_cache_size: Some(persistent_cache_size),
+            };
+            let stall_detection = (stall_threshold > 0).then(|| StallConfig {
+                threshold: Duration::from_secs(stall_threshold),
+                notify_coordinator: report_stalls,
+            });
             let rt = Builder::new_multi_thread()
                 .enable_all()
                 .build()
@@ -494,14 +1159,39 @@ fn run() -> eyre::Result<()> {
                             );
                         }
 
-                        let result = Daemon::run_dataflow(&dataflow_path).await?;
+                        let result = Daemon::run_dataflow_checked(&dataflow_path, result_file, strict_shutdown).await?;
                         handle_dataflow_result(result, None)
                     }
                     None => {
                         if coordinator_addr == LOCALHOST {
                             tracing::info!("Starting in local mode");
                         }
-                        Daemon::run(SocketAddr::new(coordinator_addr, coordinator_port), machine_id.unwrap_or_default(), inter_daemon_addr, local_listen_port).await
+                        DaemonBuilder::new()
+                            .machine_id(machine_id)
+                            .coordinator(Some(SocketAddr::new(coordinator_addr, coordinator_port)))
+                            .coordinator_timeout(require_coordinator_within)
+                            .machine_token(
+                                machine_token.or_else(|| std::env::var(MACHINE_TOKEN_ENV).ok()),
+                            )
+                            .inter_daemon_addr(inter_daemon_addr)
+                            .inter_daemon_transport(inter_daemon_transport)
+                            .udp_datagram_size(udp_datagram_size)
+                            .local_listen_interface(local_listen_interface)
+                            .local_listen_port(local_listen_port)
+                            .labels(label.into_iter().collect())
+                            .journal(journal)
+                            .drop_warning_interval(Duration::from_secs(drop_warning_interval))
+                            .stall_detection(stall_detection)
+                            .default_working_dir(default_working_dir)
+                            .connection_limits(connection_limits)
+                            .node_registry(node_registry)
+                            .paths(paths)
+                            .handle_ctrlc(true)
+                            .strict_shutdown(strict_shutdown)
+                            .build()
+                            .await?
+                            .wait()
+                            .await
                     }
                 }
             })
@@ -515,38 +1205,85 @@ fn run() -> eyre::Result<()> {
 
 fn start_dataflow(
     dataflow: Descriptor,
-    name: Option<String>,
-    local_working_dir: PathBuf,
-    session: &mut TcpRequestReplyConnection,
+    options: StartOptions,
+    session: &mut CoordinatorClient,
 ) -> Result<Uuid, eyre::ErrReport> {
-    let reply_raw = session
-        .request(
-            &serde_json::to_vec(&ControlRequest::Start {
-                dataflow,
-                name,
-                local_working_dir,
-            })
-            .unwrap(),
-        )
-        .wrap_err("failed to send start dataflow message")?;
-
-    let result: ControlRequestReply =
-        serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    match result {
-        ControlRequestReply::DataflowStarted { uuid } => {
-            eprintln!("{uuid}");
-            Ok(uuid)
+    let handle = session.start(dataflow, options)?;
+    for (node_id, machine) in handle.assignments {
+        eprintln!("node `{node_id}` runs on machine `{machine}`");
+    }
+    eprintln!("{}", handle.uuid);
+    Ok(handle.uuid)
+}
+
+/// Interprets the dataflow argument of `dora start` as `NAME[@VERSION]` of
+/// a registered dataflow, unless it looks like a dataflow file.
+fn registry_key(dataflow: &Path) -> Option<RegistryKey> {
+    let value = dataflow.to_str()?;
+    let is_file = dataflow.exists()
+        || value.contains(std::path::is_separator)
+        || value.ends_with(".yml")
+        || value.ends_with(".yaml");
+    if is_file {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Whether `dora start` should attach to the started dataflow.
+fn attach_or_detach(attach: bool, detach: bool) -> eyre::Result<bool> {
+    match (attach, detach) {
+        (true, true) => eyre::bail!("both `--attach` and `--detach` are given"),
+        (true, false) => Ok(true),
+        (false, true) => Ok(false),
+        (false, false) => {
+            println!("attaching to dataflow (use `--detach` to run in background)");
+            Ok(true)
+        }
+    }
+}
+
+fn parse_machine_working_dir(value: &str) -> eyre::Result<(String, PathBuf)> {
+    let (machine, dir) = value
+        .split_once('=')
+        .ok_or_else(|| eyre::eyre!("expected `<MACHINE>=<DIR>`, got `{value}`"))?;
+    Ok((machine.to_owned(), PathBuf::from(dir)))
+}
+
+fn parse_input(value: &str) -> eyre::Result<(String, String)> {
+    match value.split_once('/') {
+        Some((node, input)) if !node.is_empty() && !input.is_empty() => {
+            Ok((node.to_owned(), input.to_owned()))
         }
-        ControlRequestReply::Error(err) => bail!("{err}"),
-        other => bail!("unexpected start dataflow reply: {other:?}"),
+        _ => eyre::bail!("expected `<NODE>/<INPUT>`, got `{value}`"),
     }
 }
 
+fn parse_param(value: &str) -> eyre::Result<(String, String)> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| eyre::eyre!("expected `<KEY>=<VALUE>`, got `{value}`"))?;
+    if key.is_empty() {
+        eyre::bail!("parameter key must not be empty");
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+fn parse_label(value: &str) -> eyre::Result<(String, String)> {
+    let (key, value) = dora_message::daemon_to_coordinator::parse_label(value);
+    if key.is_empty() {
+        eyre::bail!("label key must not be empty");
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
 fn stop_dataflow_interactive(
     grace_duration: Option<Duration>,
-    session: &mut TcpRequestReplyConnection,
+    session: &mut CoordinatorClient,
 ) -> eyre::Result<()> {
-    let list = query_running_dataflows(session).wrap_err("failed to query running dataflows")?;
+    let list = session
+        .list()
+        .wrap_err("failed to query running dataflows")?;
     let active = list.get_active();
     if active.is_empty() {
         eprintln!("No dataflows are running");
@@ -561,26 +1298,10 @@ fn stop_dataflow_interactive(
 fn stop_dataflow(
     uuid: Uuid,
     grace_duration: Option<Duration>,
-    session: &mut TcpRequestReplyConnection,
+    session: &mut CoordinatorClient,
 ) -> Result<(), eyre::ErrReport> {
-    let reply_raw = session
-        .request(
-            &serde_json::to_vec(&ControlRequest::Stop {
-                dataflow_uuid: uuid,
-                grace_duration,
-            })
-            .unwrap(),
-        )
-        .wrap_err("failed to send dataflow stop message")?;
-    let result: ControlRequestReply =
-        serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    match result {
-        ControlRequestReply::DataflowStopped { uuid, result } => {
-            handle_dataflow_result(result, Some(uuid))
-        }
-        ControlRequestReply::Error(err) => bail!("{err}"),
-        other => bail!("unexpected stop dataflow reply: {other:?}"),
-    }
+    let result = session.stop(uuid, grace_duration)?;
+    handle_dataflow_result(result, Some(uuid))
 }
 
 fn handle_dataflow_result(result: DataflowResult, uuid: Option<Uuid>) -> Result<(), eyre::Error> {
@@ -600,43 +1321,106 @@ fn handle_dataflow_result(result: DataflowResult, uuid: Option<Uuid>) -> Result<
 
 fn stop_dataflow_by_name(
     name: String,
+    instance: Option<String>,
     grace_duration: Option<Duration>,
-    session: &mut TcpRequestReplyConnection,
+    session: &mut CoordinatorClient,
 ) -> Result<(), eyre::ErrReport> {
-    let reply_raw = session
-        .request(
-            &serde_json::to_vec(&ControlRequest::StopByName {
-                name,
-                grace_duration,
-            })
-            .unwrap(),
-        )
-        .wrap_err("failed to send dataflow stop_by_name message")?;
-    let result: ControlRequestReply =
-        serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    match result {
-        ControlRequestReply::DataflowStopped { uuid, result } => {
-            handle_dataflow_result(result, Some(uuid))
+    let (uuid, result) = session.stop_by_name(name, instance, grace_duration)?;
+    handle_dataflow_result(result, Some(uuid))
+}
+
+/// Stops all running dataflows with the given name, one after another.
+///
+/// All instances are stopped, even if some of them fail.
+fn stop_all_instances(
+    name: &str,
+    grace_duration: Option<Duration>,
+    session: &mut CoordinatorClient,
+) -> eyre::Result<()> {
+    let list = session
+        .list()
+        .wrap_err("failed to query running dataflows")?;
+    let instances: Vec<_> = list
+        .get_active()
+        .into_iter()
+        .filter(|d| d.name.as_deref() == Some(name))
+        .collect();
+    if instances.is_empty() {
+        bail!("no running dataflow with name `{name}`");
+    }
+    let mut failed = 0;
+    for instance in &instances {
+        if let Err(err) = stop_dataflow(instance.uuid, grace_duration, session) {
+            eprintln!("{instance}: {err:?}");
+            failed += 1;
         }
-        ControlRequestReply::Error(err) => bail!("{err}"),
-        other => bail!("unexpected stop dataflow reply: {other:?}"),
     }
+    if failed > 0 {
+        bail!("{failed} of {} instances failed", instances.len());
+    }
+    Ok(())
 }
 
-fn list(session: &mut TcpRequestReplyConnection) -> Result<(), eyre::ErrReport> {
-    let list = query_running_dataflows(session)?;
+fn list(session: &mut CoordinatorClient) -> Result<(), eyre::ErrReport> {
+    let list = session.list()?;
+    // only show the instance, machines, and parameters columns if they are used
+    let instances = list.0.iter().any(|entry| entry.id.instance.is_some());
+    let assignments = list.0.iter().any(|entry| !entry.assignments.is_empty());
+    let params = list.0.iter().any(|entry| !entry.params.is_empty());
+    let origins = list.0.iter().any(|entry| entry.origin.is_some());
 
     let mut tw = TabWriter::new(vec![]);
-    tw.write_all(b"UUID\tName\tStatus\n")?;
+    let mut header = "UUID\tName".to_owned();
+    if instances {
+        header.push_str("\tInstance");
+    }
+    header.push_str("\tStatus");
+    if origins {
+        header.push_str("\tDescriptor");
+    }
+    if assignments {
+        header.push_str("\tAssigned nodes");
+    }
+    if params {
+        header.push_str("\tParameters");
+    }
+    tw.write_all(format!("{header}\n").as_bytes())?;
     for entry in list.0 {
         let uuid = entry.id.uuid;
         let name = entry.id.name.unwrap_or_default();
         let status = match entry.status {
+            DataflowStatus::Running if !entry.stalled_on.is_empty() => "Stalled",
             DataflowStatus::Running => "Running",
             DataflowStatus::Finished => "Succeeded",
             DataflowStatus::Failed => "Failed",
         };
-        tw.write_all(format!("{uuid}\t{name}\t{status}\n").as_bytes())?;
+        let mut line = format!("{uuid}\t{name}");
+        if instances {
+            let instance = entry.id.instance.unwrap_or_default();
+            line.push_str(&format!("\t{instance}"));
+        }
+        line.push_str(&format!("\t{status}"));
+        if origins {
+            let origin = entry.origin.map(|o| o.to_string()).unwrap_or_default();
+            line.push_str(&format!("\t{origin}"));
+        }
+        if assignments {
+            let assigned: Vec<_> = entry
+                .assignments
+                .iter()
+                .map(|(node_id, machine)| format!("{node_id}@{machine}"))
+                .collect();
+            line.push_str(&format!("\t{}", assigned.join(", ")));
+        }
+        if params {
+            let params: Vec<_> = entry
+                .params
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            line.push_str(&format!("\t{}", params.join(", ")));
+        }
+        tw.write_all(format!("{line}\n").as_bytes())?;
     }
     tw.flush()?;
     let formatted = String::from_utf8(tw.into_inner()?)?;
@@ -646,23 +1430,17 @@ fn list(session: &mut TcpRequestReplyConnection) -> Result<(), eyre::ErrReport>
     Ok(())
 }
 
-fn query_running_dataflows(session: &mut TcpRequestReplyConnection) -> eyre::Result<DataflowList> {
-    let reply_raw = session
-        .request(&serde_json::to_vec(&ControlRequest::List).unwrap())
-        .wrap_err("failed to send list message")?;
-    let reply: ControlRequestReply =
-        serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    let ids = match reply {
-        ControlRequestReply::DataflowList(list) => list,
-        ControlRequestReply::Error(err) => bail!("{err}"),
-        other => bail!("unexpected list dataflow reply: {other:?}"),
-    };
+/// Environment variable with the token for coordinators with access control.
+const COORDINATOR_TOKEN_ENV: &str = "DORA_COORDINATOR_TOKEN";
 
-    Ok(ids)
-}
+/// Environment variable with the token for coordinators that require one
+/// from daemons.
+const MACHINE_TOKEN_ENV: &str = "DORA_MACHINE_TOKEN";
 
-fn connect_to_coordinator(
-    coordinator_addr: SocketAddr,
-) -> std::io::Result<Box<TcpRequestReplyConnection>> {
-    TcpLayer::new().connect(coordinator_addr)
+fn connect_to_coordinator(coordinator_addr: SocketAddr) -> Result<CoordinatorClient, ClientError> {
+    let mut session = CoordinatorClient::connect(coordinator_addr)?;
+    if let Ok(token) = std::env::var(COORDINATOR_TOKEN_ENV) {
+        session.authenticate(token)?;
+    }
+    Ok(session)
 }
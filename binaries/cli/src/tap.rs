@@ -0,0 +1,121 @@
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use colored::Colorize;
+use dora_coordinator_client::blocking::CoordinatorClient;
+use dora_core::config::{DataId, NodeId};
+use dora_message::coordinator_to_cli::TappedMessage;
+use dora_node_api::DoraObserver;
+use eyre::bail;
+use uuid::Uuid;
+
+/// Payloads up to this size are printed as hex dump.
+const HEX_DUMP_LIMIT: usize = 256;
+
+/// Prints copies of the messages of the given node output until the tap
+/// expires.
+pub fn tap(
+    session: &CoordinatorClient,
+    dataflow_id: Uuid,
+    node_id: NodeId,
+    output_id: DataId,
+    duration: Duration,
+    max_rate: Option<f64>,
+) -> eyre::Result<()> {
+    let messages = session.tap(dataflow_id, node_id, output_id, duration, max_rate)?;
+    // the coordinator closes the connection when the tap expires
+    for message in messages {
+        print!("{}", format_message(&message?));
+    }
+    Ok(())
+}
+
+/// Like [`tap`], but receives the messages directly from the daemon of the
+/// local machine, without a coordinator.
+///
+/// The tapped node must run on the local machine.
+pub fn tap_local(
+    daemon_address: SocketAddr,
+    dataflow_id: Uuid,
+    node_id: NodeId,
+    output_id: DataId,
+    duration: Duration,
+    max_rate: Option<f64>,
+) -> eyre::Result<()> {
+    let min_interval = match max_rate {
+        Some(rate) if rate.is_finite() && rate > 0.0 => Some(Duration::from_secs_f64(1.0 / rate)),
+        Some(rate) => bail!("invalid tap rate `{rate}`, must be positive"),
+        None => None,
+    };
+    let observer = DoraObserver::connect_to(
+        daemon_address,
+        dataflow_id,
+        node_id.clone(),
+        output_id.clone(),
+    )?;
+    let deadline = Instant::now() + duration;
+    let mut last_printed: Option<Instant> = None;
+    while let Some(message) = observer.recv_deadline(deadline) {
+        let message = message?;
+        let now = Instant::now();
+        let rate_limited = min_interval.is_some_and(|interval| {
+            last_printed.is_some_and(|last| now.saturating_duration_since(last) < interval)
+        });
+        if rate_limited {
+            continue;
+        }
+        last_printed = Some(now);
+        if message.skipped > 0 {
+            let note = format!("({} messages skipped by the daemon)", message.skipped);
+            println!("{}", note.dimmed());
+        }
+        let message = TappedMessage::new(
+            dataflow_id,
+            node_id.clone(),
+            output_id.clone(),
+            message.metadata,
+            message.data.as_deref().unwrap_or_default(),
+        );
+        print!("{}", format_message(&message));
+    }
+    Ok(())
+}
+
+fn format_message(message: &TappedMessage) -> String {
+    let mut out = format!(
+        "{} {}/{} {} bytes, type {:?}",
+        message.metadata.timestamp().to_string().dimmed(),
+        message.node_id,
+        message.output_id.to_string().bold(),
+        message.len,
+        message.metadata.type_info.data_type,
+    );
+    if !message.metadata.parameters.is_empty() {
+        let _ = write!(out, ", parameters {:?}", message.metadata.parameters);
+    }
+    out.push('\n');
+    if !message.payload.is_empty() && message.len <= HEX_DUMP_LIMIT {
+        out.push_str(&hex_dump(&message.payload));
+    }
+    out
+}
+
+fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "  {:08x} ", i * 16);
+        for byte in line {
+            let _ = write!(out, " {byte:02x}");
+        }
+        let padding = (16 - line.len()) * 3;
+        let ascii: String = line
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        let _ = writeln!(out, "{:padding$}  |{ascii}|", "");
+    }
+    out
+}
@@ -1,6 +1,6 @@
-use crate::{check::daemon_running, connect_to_coordinator, LOCALHOST};
+use crate::{connect_to_coordinator, LOCALHOST};
+use dora_coordinator_client::ClientError;
 use dora_core::topics::DORA_COORDINATOR_PORT_CONTROL_DEFAULT;
-use dora_message::cli_to_coordinator::ControlRequest;
 use eyre::{bail, Context};
 use std::{fs, net::SocketAddr, path::Path, process::Command, time::Duration};
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
@@ -11,29 +11,32 @@ pub(crate) fn up(config_path: Option<&Path>) -> eyre::Result<()> {
     let coordinator_addr = (LOCALHOST, DORA_COORDINATOR_PORT_CONTROL_DEFAULT).into();
     let mut session = match connect_to_coordinator(coordinator_addr) {
         Ok(session) => session,
-        Err(_) => {
+        Err(ClientError::Connection(_)) => {
             start_coordinator().wrap_err("failed to start dora-coordinator")?;
 
             loop {
                 match connect_to_coordinator(coordinator_addr) {
                     Ok(session) => break session,
-                    Err(_) => {
+                    Err(ClientError::Connection(_)) => {
                         // sleep a bit until the coordinator accepts connections
                         std::thread::sleep(Duration::from_millis(50));
                     }
+                    Err(err) => return Err(err).wrap_err("failed to connect to dora-coordinator"),
                 }
             }
         }
+        // the coordinator is running, but rejected the token
+        Err(err) => return Err(err).wrap_err("failed to connect to dora-coordinator"),
     };
 
-    if !daemon_running(&mut *session)? {
+    if !session.daemon_connected()? {
         start_daemon().wrap_err("failed to start dora-daemon")?;
 
         // wait a bit until daemon is connected
         let mut i = 0;
         const WAIT_S: f32 = 0.1;
         loop {
-            if daemon_running(&mut *session)? {
+            if session.daemon_connected()? {
                 break;
             }
             i += 1;
@@ -56,12 +59,12 @@ pub(crate) fn destroy(
         Ok(mut session) => {
             // send destroy command to dora-coordinator
             session
-                .request(&serde_json::to_vec(&ControlRequest::Destroy).unwrap())
+                .destroy()
                 .wrap_err("failed to send destroy message")?;
             println!("Send destroy command to dora-coordinator");
         }
-        Err(_) => {
-            bail!("Could not connect to dora-coordinator");
+        Err(err) => {
+            bail!("Could not connect to dora-coordinator: {err}");
         }
     }
 
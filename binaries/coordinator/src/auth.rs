@@ -0,0 +1,240 @@
+//! Access control for the control connections of the coordinator.
+//!
+//! Access control is enabled by an auth config file that lists the accepted
+//! tokens and their roles:
+//!
+//! ```yaml
+//! tokens:
+//!   - token: 3f9c...
+//!     role: read-only # or `operator`, `admin`
+//! machine_token: 9a41... # optional
+//! ```
+//!
+//! Every control connection then needs to send a `ControlRequest::Authenticate`
+//! request with one of the tokens before any other request. Each request
+//! needs the role given by `ControlRequest::required_role`. Requests that
+//! are not permitted are answered with `ControlRequestReply::AccessDenied`.
+//!
+//! If a `machine_token` is given, daemons need to register with it and
+//! authenticate their event connection with it, see the `listener` module.
+
+use dora_core::constant_time_eq;
+use dora_message::{
+    cli_to_coordinator::{ControlRequest, Role},
+    coordinator_to_cli::AccessError,
+};
+use eyre::{bail, Context};
+use std::{collections::BTreeSet, path::Path, sync::Arc};
+
+/// Tokens that are accepted on control connections.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    pub tokens: Vec<TokenConfig>,
+    /// Token that daemons need to register with.
+    #[serde(default)]
+    pub machine_token: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub token: String,
+    pub role: Role,
+}
+
+impl AuthConfig {
+    /// Reads the auth config from the given YAML file.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let raw = std::fs::read(path)
+            .with_context(|| format!("failed to read auth config `{}`", path.display()))?;
+        let config: Self = serde_yaml::from_slice(&raw)
+            .with_context(|| format!("failed to parse auth config `{}`", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> eyre::Result<()> {
+        if self.tokens.is_empty() {
+            bail!("auth config must contain at least one token");
+        }
+        let mut tokens = BTreeSet::new();
+        for entry in &self.tokens {
+            if entry.token.is_empty() {
+                bail!("tokens in the auth config must not be empty");
+            }
+            if !tokens.insert(entry.token.as_str()) {
+                bail!("auth config contains a token more than once");
+            }
+        }
+        if self.machine_token.as_deref() == Some("") {
+            bail!("the machine token in the auth config must not be empty");
+        }
+        Ok(())
+    }
+
+    fn role_of(&self, token: &str) -> Option<Role> {
+        // compare all tokens in full, so that the time doesn't depend on the
+        // number of matching characters
+        self.tokens.iter().fold(None, |role, entry| {
+            if constant_time_eq(&entry.token, token) {
+                Some(entry.role)
+            } else {
+                role
+            }
+        })
+    }
+}
+
+/// Access state of a single control connection.
+pub struct Session {
+    config: Option<Arc<AuthConfig>>,
+    role: Option<Role>,
+}
+
+impl Session {
+    /// Without config, all requests are permitted.
+    pub fn new(config: Option<Arc<AuthConfig>>) -> Self {
+        Self { config, role: None }
+    }
+
+    /// Sets the role of the connection to the role of the given token.
+    ///
+    /// An invalid token resets the role, so that later requests fail.
+    pub fn authenticate(&mut self, token: &str) -> Result<Role, AccessError> {
+        let Some(config) = &self.config else {
+            return Ok(Role::Admin);
+        };
+        self.role = config.role_of(token);
+        self.role.ok_or(AccessError::InvalidToken)
+    }
+
+    /// Checks whether the connection may send the given request.
+    pub fn authorize(&self, request: &ControlRequest) -> Result<(), AccessError> {
+        if self.config.is_none() {
+            return Ok(());
+        }
+        let required = request.required_role();
+        match self.role {
+            None => Err(AccessError::Unauthenticated),
+            Some(role) if role < required => Err(AccessError::Forbidden { role, required }),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn config() -> Arc<AuthConfig> {
+        let config: AuthConfig = serde_yaml::from_str(
+            "tokens:
+              - { token: viewer, role: read-only }
+              - { token: ops, role: operator }
+              - { token: root, role: admin }",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        Arc::new(config)
+    }
+
+    fn session(token: &str) -> Session {
+        let mut session = Session::new(Some(config()));
+        session.authenticate(token).unwrap();
+        session
+    }
+
+    fn stop() -> ControlRequest {
+        ControlRequest::Stop {
+            dataflow_uuid: Uuid::nil(),
+            grace_duration: None,
+        }
+    }
+
+    #[test]
+    fn requests_need_their_role() {
+        let forbidden = |role, required| Err(AccessError::Forbidden { role, required });
+
+        let viewer = session("viewer");
+        assert_eq!(viewer.authorize(&ControlRequest::List), Ok(()));
+        assert_eq!(viewer.authorize(&ControlRequest::EventSubscribe), Ok(()));
+        assert_eq!(
+            viewer.authorize(&stop()),
+            forbidden(Role::ReadOnly, Role::Operator)
+        );
+        assert_eq!(
+            viewer.authorize(&ControlRequest::Destroy),
+            forbidden(Role::ReadOnly, Role::Admin)
+        );
+
+        let ops = session("ops");
+        assert_eq!(ops.authorize(&ControlRequest::List), Ok(()));
+        assert_eq!(ops.authorize(&stop()), Ok(()));
+        let set_log_level = ControlRequest::SetLogLevel {
+            machine_id: "A".into(),
+            filter: "debug".into(),
+        };
+        assert_eq!(
+            ops.authorize(&set_log_level),
+            forbidden(Role::Operator, Role::Admin)
+        );
+        assert_eq!(
+            ops.authorize(&ControlRequest::Destroy),
+            forbidden(Role::Operator, Role::Admin)
+        );
+
+        let root = session("root");
+        for request in [ControlRequest::List, stop(), set_log_level] {
+            assert_eq!(root.authorize(&request), Ok(()));
+        }
+        assert_eq!(root.authorize(&ControlRequest::Destroy), Ok(()));
+    }
+
+    #[test]
+    fn unauthenticated_requests_are_rejected() {
+        let mut session = Session::new(Some(config()));
+        assert_eq!(
+            session.authorize(&ControlRequest::List),
+            Err(AccessError::Unauthenticated)
+        );
+        assert_eq!(
+            session.authenticate("wrong"),
+            Err(AccessError::InvalidToken)
+        );
+        assert_eq!(
+            session.authorize(&ControlRequest::List),
+            Err(AccessError::Unauthenticated)
+        );
+
+        // a failed authentication drops the previous role
+        assert_eq!(session.authenticate("root"), Ok(Role::Admin));
+        assert!(session.authenticate("roo").is_err());
+        assert_eq!(
+            session.authorize(&ControlRequest::List),
+            Err(AccessError::Unauthenticated)
+        );
+
+        // without access control, everything is permitted
+        let open = Session::new(None);
+        assert_eq!(open.authorize(&ControlRequest::Destroy), Ok(()));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let parse = |yaml: &str| {
+            serde_yaml::from_str::<AuthConfig>(yaml)
+                .map_err(eyre::Report::from)
+                .and_then(|c| c.validate())
+        };
+        assert!(parse("tokens: []").is_err());
+        assert!(
+            parse("tokens: [{ token: a, role: admin }, { token: a, role: operator }]").is_err()
+        );
+        assert!(parse("tokens: [{ token: a, role: superuser }]").is_err());
+        assert!(parse("tokens: [{ token: a, role: operator }]").is_ok());
+        assert!(parse("{ tokens: [{ token: a, role: operator }], machine_token: '' }").is_err());
+        assert!(parse("{ tokens: [{ token: a, role: operator }], machine_token: b }").is_ok());
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{
+    auth::{AuthConfig, Session},
     tcp_utils::{tcp_receive, tcp_send},
     Event,
 };
+use dora_core::config::{DataId, NodeId};
 use dora_message::{cli_to_coordinator::ControlRequest, coordinator_to_cli::ControlRequestReply};
 use eyre::{eyre, Context};
 use futures::{
@@ -10,7 +12,7 @@ use futures::{
     FutureExt, Stream, StreamExt,
 };
 use futures_concurrency::future::Race;
-use std::{io::ErrorKind, net::SocketAddr};
+use std::{io::ErrorKind, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{
     net::{TcpListener, TcpStream},
     sync::{mpsc, oneshot},
@@ -21,12 +23,18 @@ use uuid::Uuid;
 
 pub(crate) async fn control_events(
     control_listen_addr: SocketAddr,
+    auth: Option<AuthConfig>,
     tasks: &FuturesUnordered<JoinHandle<()>>,
 ) -> eyre::Result<impl Stream<Item = Event>> {
     let (tx, rx) = mpsc::channel(10);
 
     let (finish_tx, mut finish_rx) = mpsc::channel(1);
-    tasks.push(tokio::spawn(listen(control_listen_addr, tx, finish_tx)));
+    tasks.push(tokio::spawn(listen(
+        control_listen_addr,
+        auth.map(Arc::new),
+        tx,
+        finish_tx,
+    )));
     tasks.push(tokio::spawn(async move {
         while let Some(()) = finish_rx.recv().await {}
     }));
@@ -36,6 +44,7 @@ pub(crate) async fn control_events(
 
 async fn listen(
     control_listen_addr: SocketAddr,
+    auth: Option<Arc<AuthConfig>>,
     tx: mpsc::Sender<ControlEvent>,
     _finish_tx: mpsc::Sender<()>,
 ) {
@@ -61,9 +70,16 @@ async fn listen(
             }
         };
         match connection.wrap_err("failed to connect") {
-            Ok((connection, _)) => {
+            Ok((connection, peer)) => {
                 let tx = tx.clone();
-                tokio::spawn(handle_requests(connection, tx, _finish_tx.clone()));
+                let session = Session::new(auth.clone());
+                tokio::spawn(handle_requests(
+                    connection,
+                    peer,
+                    session,
+                    tx,
+                    _finish_tx.clone(),
+                ));
             }
             Err(err) => {
                 if tx.blocking_send(err.into()).is_err() {
@@ -76,6 +92,8 @@ async fn listen(
 
 async fn handle_requests(
     mut connection: TcpStream,
+    peer: SocketAddr,
+    mut session: Session,
     tx: mpsc::Sender<ControlEvent>,
     _finish_tx: mpsc::Sender<()>,
 ) {
@@ -103,6 +121,27 @@ async fn handle_requests(
         let request =
             serde_json::from_slice(&raw).wrap_err("failed to deserialize incoming message");
 
+        let access_reply = match &request {
+            Ok(ControlRequest::Authenticate { token }) => Some(match session.authenticate(token) {
+                Ok(role) => ControlRequestReply::Authenticated { role },
+                Err(err) => {
+                    tracing::warn!("control connection from {peer}: {err}");
+                    ControlRequestReply::AccessDenied(err)
+                }
+            }),
+            Ok(request) => session
+                .authorize(request)
+                .err()
+                .map(ControlRequestReply::AccessDenied),
+            Err(_) => None,
+        };
+        if let Some(reply) = access_reply {
+            match send_reply(&mut connection, &reply).await {
+                Ok(()) => continue,
+                Err(()) => break,
+            }
+        }
+
         if let Ok(ControlRequest::LogSubscribe { dataflow_id, level }) = request {
             let _ = tx
                 .send(ControlEvent::LogSubscribe {
@@ -113,34 +152,41 @@ async fn handle_requests(
                 .await;
             break;
         }
+        if let Ok(ControlRequest::EventSubscribe) = request {
+            let _ = tx.send(ControlEvent::EventSubscribe { connection }).await;
+            break;
+        }
+        let request = match request {
+            Ok(ControlRequest::Tap {
+                dataflow_id,
+                node_id,
+                output_id,
+                duration,
+                max_rate,
+            }) => {
+                let _ = tx
+                    .send(ControlEvent::Tap {
+                        dataflow_id,
+                        node_id,
+                        output_id,
+                        duration,
+                        max_rate,
+                        connection,
+                    })
+                    .await;
+                break;
+            }
+            other => other,
+        };
 
         let result = match request {
             Ok(request) => handle_request(request, &tx).await,
             Err(err) => Err(err),
         };
 
-        let reply = result.unwrap_or_else(|err| ControlRequestReply::Error(format!("{err}")));
-        let serialized: Vec<u8> =
-            match serde_json::to_vec(&reply).wrap_err("failed to serialize ControlRequestReply") {
-                Ok(s) => s,
-                Err(err) => {
-                    tracing::error!("{err:?}");
-                    break;
-                }
-            };
-        match tcp_send(&mut connection, &serialized).await {
-            Ok(()) => {}
-            Err(err) => match err.kind() {
-                ErrorKind::UnexpectedEof => {
-                    tracing::debug!("Control connection closed while trying to send reply");
-                    break;
-                }
-                err => {
-                    let err = eyre!(err).wrap_err("failed to send reply");
-                    tracing::error!("{err}");
-                    break;
-                }
-            },
+        let reply = result.unwrap_or_else(|err| ControlRequestReply::Error(format!("{err:#}")));
+        if send_reply(&mut connection, &reply).await.is_err() {
+            break;
         }
 
         if matches!(reply, ControlRequestReply::CoordinatorStopped) {
@@ -149,6 +195,32 @@ async fn handle_requests(
     }
 }
 
+/// Sends the reply, or logs why it could not be sent.
+async fn send_reply(connection: &mut TcpStream, reply: &ControlRequestReply) -> Result<(), ()> {
+    let serialized: Vec<u8> =
+        match serde_json::to_vec(reply).wrap_err("failed to serialize ControlRequestReply") {
+            Ok(s) => s,
+            Err(err) => {
+                tracing::error!("{err:?}");
+                return Err(());
+            }
+        };
+    match tcp_send(connection, &serialized).await {
+        Ok(()) => Ok(()),
+        Err(err) => match err.kind() {
+            ErrorKind::UnexpectedEof => {
+                tracing::debug!("Control connection closed while trying to send reply");
+                Err(())
+            }
+            err => {
+                let err = eyre!(err).wrap_err("failed to send reply");
+                tracing::error!("{err}");
+                Err(())
+            }
+        },
+    }
+}
+
 async fn handle_request(
     request: ControlRequest,
     tx: &mpsc::Sender<ControlEvent>,
@@ -169,6 +241,7 @@ async fn handle_request(
 }
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ControlEvent {
     IncomingRequest {
         request: ControlRequest,
@@ -179,6 +252,17 @@ pub enum ControlEvent {
         level: log::LevelFilter,
         connection: TcpStream,
     },
+    EventSubscribe {
+        connection: TcpStream,
+    },
+    Tap {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output_id: DataId,
+        duration: Duration,
+        max_rate: Option<f64>,
+        connection: TcpStream,
+    },
     Error(eyre::Report),
 }
 
@@ -0,0 +1,33 @@
+use dora_message::coordinator_to_cli::CoordinatorEvent;
+use eyre::{Context, ContextCompat};
+
+use crate::tcp_utils::tcp_send;
+
+pub struct EventSubscriber {
+    connection: Option<tokio::net::TcpStream>,
+}
+
+impl EventSubscriber {
+    pub fn new(connection: tokio::net::TcpStream) -> Self {
+        Self {
+            connection: Some(connection),
+        }
+    }
+
+    pub async fn send_event(&mut self, event: &CoordinatorEvent) -> eyre::Result<()> {
+        let event = serde_json::to_vec(&event)?;
+        let connection = self.connection.as_mut().context("connection is closed")?;
+        tcp_send(connection, &event)
+            .await
+            .context("failed to send event")?;
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_none()
+    }
+
+    pub fn close(&mut self) {
+        self.connection = None;
+    }
+}
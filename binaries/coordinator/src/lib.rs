@@ -2,46 +2,77 @@ use crate::{
     run::spawn_dataflow,
     tcp_utils::{tcp_receive, tcp_send},
 };
+pub use auth::{AuthConfig, TokenConfig};
 pub use control::ControlEvent;
 use dora_core::{
     config::{NodeId, OperatorId},
-    descriptor::{Descriptor, ResolvedNode},
+    descriptor::{diff_nodes, expand_wildcard_inputs, DataflowDiff, Descriptor, ResolvedNode},
     uhlc::{self, HLC},
 };
 use dora_message::{
-    cli_to_coordinator::ControlRequest,
+    cli_to_coordinator::{ControlRequest, InstanceKey},
+    compat::Envelope,
     coordinator_to_cli::{
-        ControlRequestReply, DataflowIdAndName, DataflowList, DataflowListEntry, DataflowResult,
-        DataflowStatus, LogMessage,
+        ControlRequestReply, CoordinatorEvent, DataflowIdAndName, DataflowList, DataflowListEntry,
+        DataflowOrigin, DataflowResult, DataflowStatus, DataflowSummary, LogMessage, MachineStatus,
+        NodeJoinInfo, TappedMessage,
     },
-    coordinator_to_daemon::{DaemonCoordinatorEvent, RegisterResult, Timestamped},
-    daemon_to_coordinator::{DaemonCoordinatorReply, DataflowDaemonResult},
+    coordinator_to_daemon::{
+        DaemonCoordinatorEvent, DataflowInstance, RegisterResult, TimeSync, Timestamped,
+    },
+    daemon_to_coordinator::{
+        DaemonCoordinatorReply, DaemonHealth, DaemonStatus, DataflowDaemonResult, MachineMetadata,
+        NodeReloadReport,
+    },
+    daemon_to_daemon::InterDaemonTransport,
+    diagnostics::DaemonDiagnostics,
+    reconfigure::{InputChange, InputChangeResult, ReconfigureReport},
 };
+use event_subscriber::EventSubscriber;
 use eyre::{bail, eyre, ContextCompat, WrapErr};
 use futures::{stream::FuturesUnordered, Future, Stream, StreamExt};
 use futures_concurrency::stream::Merge;
 use log_subscriber::LogSubscriber;
+use registry::DescriptorRegistry;
 use run::SpawnedDataflow;
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
     net::SocketAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use tap_subscriber::TapSubscriber;
 use tokio::{net::TcpStream, sync::mpsc, task::JoinHandle};
 use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
 use uuid::Uuid;
 
+mod auth;
 mod control;
+mod event_subscriber;
 mod listener;
 mod log_subscriber;
+mod migrate;
+mod registry;
 mod run;
+mod tap_subscriber;
 mod tcp_utils;
 
+/// Clock skew between two machines above which the coordinator warns.
+const CLOCK_SKEW_WARNING_THRESHOLD: Duration = Duration::from_millis(50);
+
+/// Time without heartbeat after which a daemon is disconnected.
+const DAEMON_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Starts the coordinator.
+///
+/// Without `auth` config, every client that can reach `bind_control` may
+/// send any request. Requests of the `external_events` are always permitted.
+/// If the config contains a machine token, daemons need to register with it.
 pub async fn start(
     bind: SocketAddr,
     bind_control: SocketAddr,
+    auth: Option<AuthConfig>,
     external_events: impl Stream<Item = Event> + Unpin,
 ) -> Result<(u16, impl Future<Output = eyre::Result<()>>), eyre::ErrReport> {
     let listener = listener::create_listener(bind).await?;
@@ -55,8 +86,12 @@ pub async fn start(
             .unwrap_or_else(Event::DaemonConnectError)
     });
 
+    let machine_token = auth
+        .as_ref()
+        .and_then(|auth| auth.machine_token.as_deref())
+        .map(Arc::from);
     let mut tasks = FuturesUnordered::new();
-    let control_events = control::control_events(bind_control, &tasks)
+    let control_events = control::control_events(bind_control, auth, &tasks)
         .await
         .wrap_err("failed to create control events")?;
 
@@ -72,7 +107,7 @@ pub async fn start(
         .merge();
 
     let future = async move {
-        start_inner(events, &tasks).await?;
+        start_inner(events, &tasks, machine_token).await?;
 
         tracing::debug!("coordinator main loop finished, waiting on spawned tasks");
         while let Some(join_result) = tasks.next().await {
@@ -86,24 +121,33 @@ pub async fn start(
     Ok((port, future))
 }
 
-// Resolve the dataflow name.
+// Resolve the dataflow name, optionally restricted to the given instance.
 fn resolve_name(
     name: String,
+    instance: Option<&str>,
     running_dataflows: &HashMap<Uuid, RunningDataflow>,
     archived_dataflows: &HashMap<Uuid, ArchivedDataflow>,
 ) -> eyre::Result<Uuid> {
+    let matches = |n: &Option<String>, i: &Option<String>| {
+        n.as_deref() == Some(name.as_str()) && (instance.is_none() || i.as_deref() == instance)
+    };
     let uuids: Vec<_> = running_dataflows
         .iter()
-        .filter(|(_, v)| v.name.as_deref() == Some(name.as_str()))
+        .filter(|(_, v)| matches(&v.name, &v.instance))
         .map(|(k, _)| k)
         .copied()
         .collect();
     let archived_uuids: Vec<_> = archived_dataflows
         .iter()
-        .filter(|(_, v)| v.name.as_deref() == Some(name.as_str()))
+        .filter(|(_, v)| matches(&v.name, &v.instance))
         .map(|(k, _)| k)
         .copied()
         .collect();
+    if let Some(instance) = instance {
+        if uuids.is_empty() && archived_uuids.is_empty() {
+            bail!("no instance `{instance}` of dataflow `{name}`");
+        }
+    }
 
     if uuids.is_empty() {
         if archived_uuids.is_empty() {
@@ -116,14 +160,44 @@ fn resolve_name(
         }
     } else if let [uuid] = uuids.as_slice() {
         Ok(*uuid)
+    } else if uuids
+        .iter()
+        .any(|uuid| running_dataflows[uuid].instance.is_some())
+    {
+        bail!("multiple instances of dataflow `{name}` are running, select one with `--instance`");
     } else {
         bail!("multiple dataflows found with name `{name}`");
     }
 }
 
+/// Returns the given instance key, or the lowest number that is not used by
+/// a running instance of the dataflow with the given name.
+fn instance_key(
+    name: &str,
+    key: InstanceKey,
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+) -> String {
+    match key {
+        InstanceKey::Explicit(key) => key,
+        InstanceKey::Next => {
+            let used: BTreeSet<&str> = running_dataflows
+                .values()
+                .filter(|d| d.name.as_deref() == Some(name))
+                .filter_map(|d| d.instance.as_deref())
+                .collect();
+            let mut next = 1u64;
+            while used.contains(next.to_string().as_str()) {
+                next += 1;
+            }
+            next.to_string()
+        }
+    }
+}
+
 async fn start_inner(
     events: impl Stream<Item = Event> + Unpin,
     tasks: &FuturesUnordered<JoinHandle<()>>,
+    machine_token: Option<Arc<str>>,
 ) -> eyre::Result<()> {
     let clock = Arc::new(HLC::default());
 
@@ -146,6 +220,9 @@ async fn start_inner(
         HashMap::new();
     let mut archived_dataflows: HashMap<Uuid, ArchivedDataflow> = HashMap::new();
     let mut daemon_connections: HashMap<_, DaemonConnection> = HashMap::new();
+    let mut event_subscribers: Vec<EventSubscriber> = Vec::new();
+    let mut registry = DescriptorRegistry::default();
+    let mut clock_skew_warned = false;
 
     while let Some(event) = events.next().await {
         if event.log() {
@@ -160,6 +237,7 @@ async fn start_inner(
                         connection,
                         events_tx,
                         clock.clone(),
+                        machine_token.clone(),
                     ));
                     tasks.push(task);
                 } else {
@@ -175,14 +253,24 @@ async fn start_inner(
                 DaemonRequest::Register {
                     machine_id,
                     mut connection,
-                    version_check_result,
+                    check_result,
                     listen_port,
+                    inter_daemon_transport,
+                    metadata,
                 } => {
                     let peer_ip = connection
                         .peer_addr()
                         .map(|addr| addr.ip())
                         .map_err(|err| format!("failed to get peer addr of connection: {err}"));
-                    let register_result = version_check_result.and(peer_ip);
+                    let register_result = check_result
+                        .and_then(|()| {
+                            check_registration(
+                                &machine_id,
+                                &metadata,
+                                daemon_connections.get(&machine_id),
+                            )
+                        })
+                        .and(peer_ip);
 
                     let reply: Timestamped<RegisterResult> = Timestamped {
                         inner: match &register_result {
@@ -199,7 +287,10 @@ async fn start_inner(
                                 DaemonConnection {
                                     stream: connection,
                                     listen_socket: (ip, listen_port).into(),
+                                    inter_daemon_transport,
                                     last_heartbeat: Instant::now(),
+                                    last_health: None,
+                                    metadata,
                                 },
                             );
                             if let Some(_previous) = previous {
@@ -207,6 +298,11 @@ async fn start_inner(
                                     "closing previous connection `{machine_id}` on new register"
                                 );
                             }
+                            send_event(
+                                &mut event_subscribers,
+                                &CoordinatorEvent::DaemonConnected { machine_id },
+                            )
+                            .await;
                         }
                         (Err(err), _) => {
                             tracing::warn!("failed to register daemon connection for machine `{machine_id}`: {err}");
@@ -274,21 +370,61 @@ async fn start_inner(
                                 .entry(uuid)
                                 .or_insert_with(|| ArchivedDataflow::from(entry.get()));
                             entry.get_mut().machines.remove(&machine_id);
+                            entry.get_mut().stalled_machines.remove(&machine_id);
                             dataflow_results
                                 .entry(uuid)
                                 .or_default()
                                 .insert(machine_id, result);
                             if entry.get_mut().machines.is_empty() {
                                 let finished_dataflow = entry.remove();
-                                let reply = ControlRequestReply::DataflowStopped {
-                                    uuid,
-                                    result: dataflow_results
-                                        .get(&uuid)
-                                        .map(|r| dataflow_result(r, uuid, &clock))
-                                        .unwrap_or_else(|| {
-                                            DataflowResult::ok_empty(uuid, clock.new_timestamp())
-                                        }),
+                                let mut result = dataflow_results
+                                    .get(&uuid)
+                                    .map(|r| dataflow_result(r, uuid, &clock))
+                                    .unwrap_or_else(|| {
+                                        DataflowResult::ok_empty(uuid, clock.new_timestamp())
+                                    });
+                                let summary = DataflowSummary {
+                                    params: finished_dataflow.params.clone(),
+                                    ..DataflowSummary::new(
+                                        uuid,
+                                        finished_dataflow.name.clone(),
+                                        finished_dataflow.start_time,
+                                        SystemTime::now(),
+                                        dataflow_results
+                                            .get(&uuid)
+                                            .into_iter()
+                                            .flat_map(|r| r.values()),
+                                    )
                                 };
+                                // messages of remote publishers may be lost on the
+                                // way, so they are only reported here
+                                for (source, receiver, edge) in summary.unaccounted_edges() {
+                                    tracing::error!(
+                                        "messages of edge `{source}` -> `{receiver}` of \
+                                        dataflow `{uuid}` are not accounted for: {edge}"
+                                    );
+                                }
+                                if let Some(path) = &finished_dataflow.result_file {
+                                    if let Err(err) = write_result_file(path, &summary) {
+                                        tracing::warn!(
+                                            "failed to write result of dataflow `{uuid}`: {err:?}"
+                                        );
+                                    }
+                                }
+                                result.summary = Some(summary);
+                                send_event(
+                                    &mut event_subscribers,
+                                    &CoordinatorEvent::DataflowFinished {
+                                        id: DataflowIdAndName {
+                                            uuid,
+                                            name: finished_dataflow.name.clone(),
+                                            instance: finished_dataflow.instance.clone(),
+                                        },
+                                        result: result.clone(),
+                                    },
+                                )
+                                .await;
+                                let reply = ControlRequestReply::DataflowStopped { uuid, result };
                                 for sender in finished_dataflow.reply_senders {
                                     let _ = sender.send(Ok(reply.clone()));
                                 }
@@ -299,6 +435,41 @@ async fn start_inner(
                         }
                     }
                 }
+                DataflowEvent::StopRequested {
+                    node_id,
+                    grace_duration,
+                } => {
+                    tracing::info!("node `{node_id}` requested to stop dataflow `{uuid}`");
+                    if let Err(err) = stop_dataflow(
+                        &mut running_dataflows,
+                        uuid,
+                        &mut daemon_connections,
+                        clock.new_timestamp(),
+                        grace_duration,
+                    )
+                    .await
+                    {
+                        tracing::warn!(
+                            "failed to stop dataflow `{uuid}` on request of node `{node_id}`: \
+                            {err:?}"
+                        );
+                    }
+                }
+                DataflowEvent::Stalled { machine_id, idle } => {
+                    tracing::warn!(
+                        "dataflow `{uuid}` is stalled on machine `{machine_id}`: no message \
+                        was delivered for {idle:.0?}"
+                    );
+                    if let Some(dataflow) = running_dataflows.get_mut(&uuid) {
+                        dataflow.stalled_machines.insert(machine_id);
+                    }
+                }
+                DataflowEvent::Recovered { machine_id } => {
+                    tracing::info!("dataflow `{uuid}` recovered on machine `{machine_id}`");
+                    if let Some(dataflow) = running_dataflows.get_mut(&uuid) {
+                        dataflow.stalled_machines.remove(&machine_id);
+                    }
+                }
             },
 
             Event::Control(event) => match event {
@@ -306,45 +477,184 @@ async fn start_inner(
                     request,
                     reply_sender,
                 } => {
+                    // registered dataflows are started like dataflow files
+                    let (request, registered) = match request {
+                        ControlRequest::StartRegistered {
+                            dataflow,
+                            name,
+                            local_working_dir,
+                            machine_working_dirs,
+                            instance,
+                            params,
+                        } => match registry.get(&dataflow) {
+                            Ok((entry, descriptor)) => {
+                                let request = ControlRequest::Start {
+                                    dataflow: descriptor.clone(),
+                                    name,
+                                    local_working_dir,
+                                    machine_working_dirs,
+                                    instance,
+                                    params,
+                                    dry_run: false,
+                                };
+                                (request, Some(entry))
+                            }
+                            Err(err) => {
+                                let _ = reply_sender.send(Err(err));
+                                continue;
+                            }
+                        },
+                        request => (request, None),
+                    };
                     match request {
                         ControlRequest::Start {
                             dataflow,
                             name,
                             local_working_dir,
+                            machine_working_dirs,
+                            instance,
+                            params,
+                            dry_run,
                         } => {
-                            let name = name.or_else(|| names::Generator::default().next());
-
-                            let inner = async {
-                                if let Some(name) = name.as_deref() {
-                                    // check that name is unique
-                                    if running_dataflows
-                                        .values()
-                                        .any(|d: &RunningDataflow| d.name.as_deref() == Some(name))
-                                    {
-                                        bail!("there is already a running dataflow with name `{name}`");
-                                    }
-                                }
-                                let dataflow = start_dataflow(
+                            if dry_run {
+                                // the instance key is resolved, but not reserved
+                                let spawn_instance =
+                                    name.clone().zip(instance).map(|(name, key)| {
+                                        let key = instance_key(&name, key, &running_dataflows);
+                                        DataflowInstance { name, key }
+                                    });
+                                let reply = run::plan_dataflow(
                                     dataflow,
                                     local_working_dir,
+                                    machine_working_dirs,
                                     name,
+                                    spawn_instance,
+                                    params,
+                                    node_counts(&running_dataflows),
                                     &mut daemon_connections,
                                     &clock,
                                 )
-                                .await?;
-                                Ok(dataflow)
-                            };
-                            let reply = inner.await.map(|dataflow| {
-                                let uuid = dataflow.uuid;
-                                running_dataflows.insert(uuid, dataflow);
-                                ControlRequestReply::DataflowStarted { uuid }
+                                .await
+                                .map(ControlRequestReply::DataflowPlan);
+                                let _ = reply_sender.send(reply);
+                            } else {
+                                let inner = async {
+                                    let origin = match registered {
+                                        Some(entry) => DataflowOrigin::from(entry),
+                                        None => registry::unregistered_origin(&dataflow)?,
+                                    };
+                                    let instance = match (&name, instance) {
+                                        (_, None) => None,
+                                        (Some(name), Some(key)) => {
+                                            Some(instance_key(name, key, &running_dataflows))
+                                        }
+                                        (None, Some(_)) => {
+                                            bail!("dataflow instances require a dataflow name")
+                                        }
+                                    };
+                                    let name = name.or_else(|| names::Generator::default().next());
+                                    if let Some(name) = name.as_deref() {
+                                        // check that name and instance are unique
+                                        if running_dataflows.values().any(|d: &RunningDataflow| {
+                                            d.name.as_deref() == Some(name)
+                                                && d.instance == instance
+                                        }) {
+                                            match &instance {
+                                            Some(key) => bail!(
+                                                "there is already a running instance `{key}` of \
+                                                dataflow `{name}`"
+                                            ),
+                                            None => bail!(
+                                                "there is already a running dataflow with name `{name}`"
+                                            ),
+                                        }
+                                        }
+                                    }
+                                    let dataflow = start_dataflow(
+                                        dataflow,
+                                        local_working_dir,
+                                        machine_working_dirs,
+                                        name,
+                                        instance,
+                                        params,
+                                        origin,
+                                        node_counts(&running_dataflows),
+                                        &mut daemon_connections,
+                                        &clock,
+                                    )
+                                    .await?;
+                                    Ok(dataflow)
+                                };
+                                let reply = match inner.await {
+                                    Ok(dataflow) => {
+                                        let uuid = dataflow.uuid;
+                                        let assignments = dataflow.assignments.clone();
+                                        let event = CoordinatorEvent::DataflowStarted {
+                                            id: DataflowIdAndName {
+                                                uuid,
+                                                name: dataflow.name.clone(),
+                                                instance: dataflow.instance.clone(),
+                                            },
+                                            assignments: assignments.clone(),
+                                        };
+                                        running_dataflows.insert(uuid, dataflow);
+                                        send_event(&mut event_subscribers, &event).await;
+                                        Ok(ControlRequestReply::DataflowStarted {
+                                            uuid,
+                                            assignments,
+                                        })
+                                    }
+                                    Err(err) => Err(err),
+                                };
+                                let _ = reply_sender.send(reply);
+                            }
+                        }
+                        ControlRequest::StartRegistered { .. } => {
+                            unreachable!("registered dataflows are started through `Start`")
+                        }
+                        ControlRequest::Register {
+                            name,
+                            version,
+                            dataflow,
+                            working_dir,
+                        } => {
+                            let reply = registry
+                                .register(name, version, dataflow, working_dir)
+                                .map(ControlRequestReply::Registered);
+                            if let Ok(ControlRequestReply::Registered(entry)) = &reply {
+                                tracing::info!(
+                                    "registered dataflow `{}@{}` (hash {})",
+                                    entry.name,
+                                    entry.version,
+                                    entry.hash
+                                );
+                            }
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::ListRegistered => {
+                            let reply = ControlRequestReply::Registry(registry.list());
+                            let _ = reply_sender.send(Ok(reply));
+                        }
+                        ControlRequest::FetchRegistered { dataflow } => {
+                            let reply = registry.get(&dataflow).map(|(entry, descriptor)| {
+                                ControlRequestReply::RegisteredDataflow {
+                                    entry,
+                                    dataflow: descriptor.clone(),
+                                }
                             });
                             let _ = reply_sender.send(reply);
                         }
+                        ControlRequest::DeleteRegistered { dataflow } => {
+                            let reply = registry
+                                .delete(&dataflow)
+                                .map(ControlRequestReply::RegistryEntriesDeleted);
+                            let _ = reply_sender.send(reply);
+                        }
                         ControlRequest::Check { dataflow_uuid } => {
                             let status = match &running_dataflows.get(&dataflow_uuid) {
-                                Some(_) => ControlRequestReply::DataflowStarted {
+                                Some(dataflow) => ControlRequestReply::DataflowStarted {
                                     uuid: dataflow_uuid,
+                                    assignments: dataflow.assignments.clone(),
                                 },
                                 None => ControlRequestReply::DataflowStopped {
                                     uuid: dataflow_uuid,
@@ -386,6 +696,70 @@ async fn start_inner(
                                     });
                             let _ = reply_sender.send(reply);
                         }
+                        ControlRequest::ReloadNode {
+                            dataflow_id,
+                            node_id,
+                        } => {
+                            let reply = reload_node(
+                                &running_dataflows,
+                                dataflow_id,
+                                node_id.clone(),
+                                &mut daemon_connections,
+                                clock.new_timestamp(),
+                            )
+                            .await
+                            .map(|report| {
+                                ControlRequestReply::NodeReloaded {
+                                    uuid: dataflow_id,
+                                    node_id,
+                                    report,
+                                }
+                            });
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::MigrateNode {
+                            dataflow_id,
+                            node_id,
+                            target_machine,
+                        } => {
+                            let reply = migrate::migrate_node(
+                                &mut running_dataflows,
+                                dataflow_id,
+                                node_id.clone(),
+                                target_machine,
+                                &mut daemon_connections,
+                                &clock,
+                            )
+                            .await
+                            .map(|report| {
+                                ControlRequestReply::NodeMigrated {
+                                    uuid: dataflow_id,
+                                    node_id,
+                                    report,
+                                }
+                            });
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::Reconfigure {
+                            dataflow_id,
+                            changes,
+                        } => {
+                            let reply = reconfigure(
+                                &running_dataflows,
+                                dataflow_id,
+                                changes,
+                                &mut daemon_connections,
+                                &clock,
+                            )
+                            .await
+                            .map(|report| {
+                                ControlRequestReply::Reconfigured {
+                                    uuid: dataflow_id,
+                                    report,
+                                }
+                            });
+                            let _ = reply_sender.send(reply);
+                        }
                         ControlRequest::Stop {
                             dataflow_uuid,
                             grace_duration,
@@ -420,8 +794,14 @@ async fn start_inner(
                         }
                         ControlRequest::StopByName {
                             name,
+                            instance,
                             grace_duration,
-                        } => match resolve_name(name, &running_dataflows, &archived_dataflows) {
+                        } => match resolve_name(
+                            name,
+                            instance.as_deref(),
+                            &running_dataflows,
+                            &archived_dataflows,
+                        ) {
                             Ok(dataflow_uuid) => {
                                 if let Some(result) = dataflow_results.get(&dataflow_uuid) {
                                     let reply = ControlRequestReply::DataflowStopped {
@@ -459,7 +839,7 @@ async fn start_inner(
                             let dataflow_uuid = if let Some(uuid) = uuid {
                                 Ok(uuid)
                             } else if let Some(name) = name {
-                                resolve_name(name, &running_dataflows, &archived_dataflows)
+                                resolve_name(name, None, &running_dataflows, &archived_dataflows)
                             } else {
                                 Err(eyre!("No uuid"))
                             };
@@ -483,6 +863,61 @@ async fn start_inner(
                                 }
                             }
                         }
+                        ControlRequest::Descriptor { dataflow_uuid } => {
+                            let reply = retrieve_descriptor(
+                                &running_dataflows,
+                                dataflow_uuid,
+                                &mut daemon_connections,
+                                clock.new_timestamp(),
+                            )
+                            .await
+                            .map(ControlRequestReply::Descriptor);
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::ResolveNode {
+                            uuid,
+                            name,
+                            node_id,
+                        } => {
+                            let dataflow_uuid = match (uuid, name) {
+                                (Some(uuid), _) => Ok(uuid),
+                                (None, Some(name)) => resolve_name(
+                                    name,
+                                    None,
+                                    &running_dataflows,
+                                    &archived_dataflows,
+                                ),
+                                (None, None) => Err(eyre!("No uuid")),
+                            };
+                            let reply = match dataflow_uuid {
+                                Ok(uuid) => resolve_node(
+                                    &running_dataflows,
+                                    &mut daemon_connections,
+                                    uuid,
+                                    node_id,
+                                    clock.new_timestamp(),
+                                )
+                                .await
+                                .map(ControlRequestReply::NodeResolved),
+                                Err(err) => Err(err),
+                            };
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::Diff {
+                            dataflow_uuid,
+                            dataflow,
+                        } => {
+                            let reply = diff_dataflow(
+                                &running_dataflows,
+                                dataflow_uuid,
+                                dataflow,
+                                &mut daemon_connections,
+                                clock.new_timestamp(),
+                            )
+                            .await
+                            .map(ControlRequestReply::Diff);
+                            let _ = reply_sender.send(reply);
+                        }
                         ControlRequest::Destroy => {
                             tracing::info!("Received destroy command");
 
@@ -499,26 +934,48 @@ async fn start_inner(
                         }
                         ControlRequest::List => {
                             let mut dataflows: Vec<_> = running_dataflows.values().collect();
-                            dataflows.sort_by_key(|d| (&d.name, d.uuid));
+                            // list the instances of a dataflow next to each other
+                            dataflows.sort_by_key(|d| {
+                                let instance = d.instance.as_deref();
+                                let number = instance.and_then(|i| i.parse::<u64>().ok());
+                                (&d.name, number, instance, d.uuid)
+                            });
 
                             let running = dataflows.into_iter().map(|d| DataflowListEntry {
                                 id: DataflowIdAndName {
                                     uuid: d.uuid,
                                     name: d.name.clone(),
+                                    instance: d.instance.clone(),
                                 },
                                 status: DataflowStatus::Running,
+                                assignments: d.assignments.clone(),
+                                params: d.params.clone(),
+                                stalled_on: d.stalled_machines.clone(),
+                                origin: Some(d.origin.clone()),
                             });
                             let finished_failed =
                                 dataflow_results.iter().map(|(&uuid, results)| {
-                                    let name =
-                                        archived_dataflows.get(&uuid).and_then(|d| d.name.clone());
-                                    let id = DataflowIdAndName { uuid, name };
+                                    let archived = archived_dataflows.get(&uuid);
+                                    let id = DataflowIdAndName {
+                                        uuid,
+                                        name: archived.and_then(|d| d.name.clone()),
+                                        instance: archived.and_then(|d| d.instance.clone()),
+                                    };
                                     let status = if results.values().all(|r| r.is_ok()) {
                                         DataflowStatus::Finished
                                     } else {
                                         DataflowStatus::Failed
                                     };
-                                    DataflowListEntry { id, status }
+                                    DataflowListEntry {
+                                        id,
+                                        status,
+                                        assignments: BTreeMap::new(),
+                                        params: archived
+                                            .map(|d| d.params.clone())
+                                            .unwrap_or_default(),
+                                        stalled_on: BTreeSet::new(),
+                                        origin: archived.map(|d| d.origin.clone()),
+                                    }
                                 });
 
                             let reply = Ok(ControlRequestReply::DataflowList(DataflowList(
@@ -537,11 +994,56 @@ async fn start_inner(
                             ));
                             let _ = reply_sender.send(reply);
                         }
+                        ControlRequest::DaemonStatus => {
+                            let status = retrieve_daemon_status(
+                                &mut daemon_connections,
+                                clock.new_timestamp(),
+                            )
+                            .await;
+                            let _ =
+                                reply_sender.send(Ok(ControlRequestReply::DaemonStatus(status)));
+                        }
+                        ControlRequest::SetLogLevel { machine_id, filter } => {
+                            let reply = set_daemon_log_level(
+                                &mut daemon_connections,
+                                &machine_id,
+                                filter,
+                                clock.new_timestamp(),
+                            )
+                            .await
+                            .map(|previous| ControlRequestReply::LogLevelSet { previous });
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::Diagnostics { machine_id, gc } => {
+                            let reply = request_daemon_diagnostics(
+                                &mut daemon_connections,
+                                &machine_id,
+                                gc,
+                                clock.new_timestamp(),
+                            )
+                            .await
+                            .map(ControlRequestReply::Diagnostics);
+                            let _ = reply_sender.send(reply);
+                        }
                         ControlRequest::LogSubscribe { .. } => {
                             let _ = reply_sender.send(Err(eyre::eyre!(
                                 "LogSubscribe request should be handled separately"
                             )));
                         }
+                        ControlRequest::EventSubscribe => {
+                            let _ = reply_sender.send(Err(eyre::eyre!(
+                                "EventSubscribe request should be handled separately"
+                            )));
+                        }
+                        ControlRequest::Tap { .. } => {
+                            let _ = reply_sender
+                                .send(Err(eyre::eyre!("Tap request should be handled separately")));
+                        }
+                        ControlRequest::Authenticate { .. } => {
+                            let _ = reply_sender.send(Err(eyre::eyre!(
+                                "Authenticate request should be handled separately"
+                            )));
+                        }
                     }
                 }
                 ControlEvent::Error(err) => tracing::error!("{err:?}"),
@@ -556,6 +1058,47 @@ async fn start_inner(
                             .push(LogSubscriber::new(level, connection));
                     }
                 }
+                ControlEvent::EventSubscribe { connection } => {
+                    event_subscribers.push(EventSubscriber::new(connection));
+                }
+                ControlEvent::Tap {
+                    dataflow_id,
+                    node_id,
+                    output_id,
+                    duration,
+                    max_rate,
+                    mut connection,
+                } => {
+                    let tap_id = Uuid::new_v4();
+                    let result = start_tap(
+                        &running_dataflows,
+                        &mut daemon_connections,
+                        dataflow_id,
+                        &node_id,
+                        DaemonCoordinatorEvent::TapOutput {
+                            tap_id,
+                            dataflow_id,
+                            node_id: node_id.clone(),
+                            output_id,
+                            duration,
+                            max_rate,
+                        },
+                        clock.new_timestamp(),
+                    )
+                    .await;
+                    let reply = match &result {
+                        Ok(()) => ControlRequestReply::TapStarted,
+                        Err(err) => ControlRequestReply::Error(format!("{err:?}")),
+                    };
+                    let sent = tcp_send(&mut connection, &serde_json::to_vec(&reply)?).await;
+                    if let (Ok(()), Ok(())) = (result, sent) {
+                        if let Some(dataflow) = running_dataflows.get_mut(&dataflow_id) {
+                            dataflow
+                                .tap_subscribers
+                                .push(TapSubscriber::new(tap_id, duration, connection));
+                        }
+                    }
+                }
             },
             Event::DaemonHeartbeatInterval => {
                 let mut disconnected = BTreeSet::new();
@@ -566,7 +1109,7 @@ async fn start_inner(
                             connection.last_heartbeat.elapsed()
                         )
                     }
-                    if connection.last_heartbeat.elapsed() > Duration::from_secs(30) {
+                    if connection.last_heartbeat.elapsed() > DAEMON_HEARTBEAT_TIMEOUT {
                         disconnected.insert(machine_id.clone());
                         continue;
                     }
@@ -585,10 +1128,18 @@ async fn start_inner(
                         disconnected.insert(machine_id.clone());
                     }
                 }
+                for dataflow in running_dataflows.values_mut() {
+                    dataflow.tap_subscribers.retain(|s| !s.is_closed());
+                }
                 if !disconnected.is_empty() {
                     tracing::error!("Disconnecting daemons that failed watchdog: {disconnected:?}");
                     for machine_id in disconnected {
                         daemon_connections.remove(&machine_id);
+                        send_event(
+                            &mut event_subscribers,
+                            &CoordinatorEvent::DaemonDisconnected { machine_id },
+                        )
+                        .await;
                     }
                 }
             }
@@ -608,6 +1159,47 @@ async fn start_inner(
                     connection.last_heartbeat = Instant::now();
                 }
             }
+            Event::DaemonHealth {
+                machine_id,
+                health,
+                received,
+            } => {
+                if let Some(connection) = daemon_connections.get_mut(&machine_id) {
+                    if health.dropped_messages > 0 {
+                        tracing::warn!(
+                            "daemon on machine `{machine_id}` dropped {} messages since its \
+                            previous heartbeat because event queues were full",
+                            health.dropped_messages
+                        );
+                    }
+                    if let Some(daemon_sent) = health.wall_clock {
+                        let sync = TimeSync {
+                            daemon_sent,
+                            coordinator_received: received,
+                            coordinator_sent: wall_clock_now(),
+                        };
+                        let result = tokio::time::timeout(
+                            Duration::from_millis(500),
+                            send_time_sync_message(
+                                &mut connection.stream,
+                                sync,
+                                clock.new_timestamp(),
+                            ),
+                        )
+                        .await
+                        .wrap_err("timeout")
+                        .and_then(|r| r);
+                        if let Err(err) = result {
+                            tracing::warn!(
+                                "failed to send time sync message to daemon at `{machine_id}`: \
+                                {err:?}"
+                            );
+                        }
+                    }
+                    connection.last_health = Some(health);
+                }
+                check_clock_skew(&daemon_connections, &mut clock_skew_warned);
+            }
             Event::Log(message) => {
                 if let Some(dataflow) = running_dataflows.get_mut(&message.dataflow_id) {
                     for subscriber in &mut dataflow.log_subscribers {
@@ -623,6 +1215,28 @@ async fn start_inner(
                     dataflow.log_subscribers.retain(|s| !s.is_closed());
                 }
             }
+            Event::Tapped { tap_id, message } => {
+                if let Some(dataflow) = running_dataflows.get_mut(&message.dataflow_id) {
+                    if let Some(subscriber) =
+                        dataflow.tap_subscribers.iter_mut().find(|s| s.id == tap_id)
+                    {
+                        let send_result = tokio::time::timeout(
+                            Duration::from_millis(100),
+                            subscriber.send_message(&message),
+                        );
+                        if !matches!(send_result.await, Ok(Ok(()))) {
+                            subscriber.close();
+                        }
+                    }
+                    dataflow.tap_subscribers.retain(|s| !s.is_closed());
+                }
+            }
+            Event::TapFinished { tap_id } => {
+                // dropping the subscriber closes the connection to the client
+                for dataflow in running_dataflows.values_mut() {
+                    dataflow.tap_subscribers.retain(|s| s.id != tap_id);
+                }
+            }
         }
     }
 
@@ -648,13 +1262,57 @@ fn dataflow_result(
         uuid: dataflow_uuid,
         timestamp: clock.new_timestamp(),
         node_results,
+        summary: None,
+    }
+}
+
+fn write_result_file(path: &Path, summary: &DataflowSummary) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).wrap_err("failed to create directory of result file")?;
     }
+    let json = serde_json::to_vec_pretty(summary).wrap_err("failed to serialize result")?;
+    std::fs::write(path, json)
+        .wrap_err_with(|| format!("failed to write result file `{}`", path.display()))
 }
 
 struct DaemonConnection {
     stream: TcpStream,
     listen_socket: SocketAddr,
+    inter_daemon_transport: InterDaemonTransport,
     last_heartbeat: Instant,
+    /// Health snapshot of the most recent heartbeat.
+    last_health: Option<DaemonHealth>,
+    metadata: MachineMetadata,
+}
+
+/// Rejects the registration of a daemon if another daemon with different
+/// metadata is still connected under the same machine ID.
+///
+/// Registrations with the same metadata replace the previous connection, e.g.
+/// when a daemon is restarted before the coordinator noticed that it exited.
+fn check_registration(
+    machine_id: &str,
+    metadata: &MachineMetadata,
+    previous: Option<&DaemonConnection>,
+) -> Result<(), String> {
+    match previous {
+        Some(previous)
+            if previous.metadata != *metadata
+                && previous.last_heartbeat.elapsed() <= DAEMON_HEARTBEAT_TIMEOUT =>
+        {
+            let machine = if machine_id.is_empty() {
+                "the default machine ID".to_owned()
+            } else {
+                format!("machine ID `{machine_id}`")
+            };
+            Err(format!(
+                "{machine} is already registered by another daemon on \
+                {} (this daemon: {metadata}); choose a different `--machine-id`",
+                previous.metadata
+            ))
+        }
+        _ => Ok(()),
+    }
 }
 
 async fn handle_destroy(
@@ -680,6 +1338,72 @@ async fn handle_destroy(
     Ok(())
 }
 
+async fn send_time_sync_message(
+    connection: &mut TcpStream,
+    sync: TimeSync,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<()> {
+    let message = serde_json::to_vec(&Envelope::new(
+        DaemonCoordinatorEvent::TimeSync(sync),
+        timestamp,
+    ))
+    .context("Could not serialize time sync message")?;
+
+    tcp_send(connection, &message)
+        .await
+        .wrap_err("failed to send time sync message to daemon")
+}
+
+/// Warns when the clocks of two daemons differ by more than
+/// [`CLOCK_SKEW_WARNING_THRESHOLD`], based on their offset estimates.
+fn check_clock_skew(daemon_connections: &HashMap<String, DaemonConnection>, warned: &mut bool) {
+    let offsets: Vec<_> = daemon_connections
+        .iter()
+        .filter_map(|(machine_id, c)| Some((machine_id, c.last_health.as_ref()?.clock_offset?)))
+        .collect();
+    let min = offsets.iter().min_by_key(|(_, o)| o.offset);
+    let max = offsets.iter().max_by_key(|(_, o)| o.offset);
+    let (Some((min_machine, min)), Some((max_machine, max))) = (min, max) else {
+        return;
+    };
+    let skew = Duration::from_nanos(max.offset.abs_diff(min.offset));
+    if skew > CLOCK_SKEW_WARNING_THRESHOLD {
+        if !*warned {
+            tracing::warn!(
+                "clocks of machines `{min_machine}` and `{max_machine}` differ by about \
+                {skew:?}, latencies between them are not accurate"
+            );
+            *warned = true;
+        }
+    } else if *warned {
+        tracing::info!(
+            "clock skew between machines is below {CLOCK_SKEW_WARNING_THRESHOLD:?} again"
+        );
+        *warned = false;
+    }
+}
+
+/// Current wall-clock time in nanoseconds since the Unix epoch.
+fn wall_clock_now() -> u64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Sends the given event to all event subscribers, dropping subscribers
+/// whose connection is closed or too slow.
+async fn send_event(subscribers: &mut Vec<EventSubscriber>, event: &CoordinatorEvent) {
+    for subscriber in subscribers.iter_mut() {
+        let send_result =
+            tokio::time::timeout(Duration::from_millis(100), subscriber.send_event(event)).await;
+        if !matches!(send_result, Ok(Ok(()))) {
+            subscriber.close();
+        }
+    }
+    subscribers.retain(|s| !s.is_closed());
+}
+
 async fn send_heartbeat_message(
     connection: &mut TcpStream,
     timestamp: uhlc::Timestamp,
@@ -697,21 +1421,39 @@ async fn send_heartbeat_message(
 
 struct RunningDataflow {
     name: Option<String>,
+    /// Key of the instance, if the dataflow was started as one of multiple
+    /// instances of `name`.
+    instance: Option<String>,
     uuid: Uuid,
+    /// Start parameters, which are passed to all nodes.
+    params: BTreeMap<String, String>,
+    /// Registry entry or hash of the descriptor.
+    origin: DataflowOrigin,
     /// The IDs of the machines that the dataflow is running on.
     machines: BTreeSet<String>,
     /// IDs of machines that are waiting until all nodes are started.
     pending_machines: BTreeSet<String>,
+    /// IDs of machines that reported the dataflow as stalled.
+    stalled_machines: BTreeSet<String>,
     exited_before_subscribe: Vec<NodeId>,
     nodes: Vec<ResolvedNode>,
+    /// Machines that the coordinator picked for nodes without a fixed machine.
+    assignments: BTreeMap<NodeId, String>,
+    start_time: SystemTime,
+    /// Path of the JSON summary that is written when the dataflow finishes.
+    result_file: Option<PathBuf>,
 
     reply_senders: Vec<tokio::sync::oneshot::Sender<eyre::Result<ControlRequestReply>>>,
 
     log_subscribers: Vec<LogSubscriber>,
+    tap_subscribers: Vec<TapSubscriber>,
 }
 
 struct ArchivedDataflow {
     name: Option<String>,
+    instance: Option<String>,
+    params: BTreeMap<String, String>,
+    origin: DataflowOrigin,
     nodes: Vec<ResolvedNode>,
 }
 
@@ -719,6 +1461,9 @@ impl From<&RunningDataflow> for ArchivedDataflow {
     fn from(dataflow: &RunningDataflow) -> ArchivedDataflow {
         ArchivedDataflow {
             name: dataflow.name.clone(),
+            instance: dataflow.instance.clone(),
+            params: dataflow.params.clone(),
+            origin: dataflow.origin.clone(),
             nodes: dataflow.nodes.clone(),
         }
     }
@@ -824,6 +1569,162 @@ async fn reload_dataflow(
     Ok(())
 }
 
+async fn reload_node(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    dataflow_id: Uuid,
+    node_id: NodeId,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<NodeReloadReport> {
+    let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
+        bail!("No running dataflow found with UUID `{dataflow_id}`")
+    };
+    let machine_id = dataflow
+        .nodes
+        .iter()
+        .find(|node| node.id == node_id)
+        .map(|node| &node.deploy.machine)
+        .wrap_err_with(|| format!("dataflow `{dataflow_id}` has no node `{node_id}`"))?;
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::ReloadNode {
+            dataflow_id,
+            node_id: node_id.clone(),
+        },
+        timestamp,
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send node reload message to daemon")?;
+
+    // the daemon replies once the node is running again
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive node reload reply from daemon")?;
+    let report = match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize node reload reply from daemon")?
+    {
+        DaemonCoordinatorReply::ReloadNodeResult(result) => result
+            .map_err(|e| eyre!(e))
+            .wrap_err_with(|| format!("failed to reload node `{node_id}`"))?,
+        other => bail!("unexpected reply after sending node reload: {other:?}"),
+    };
+    tracing::info!("reloaded node `{dataflow_id}/{node_id}`: {report}");
+
+    Ok(report)
+}
+
+/// Sends the given input changes to the daemons of the nodes.
+///
+/// Changes that fail, e.g. because the node is unknown or its daemon is not
+/// connected, are reported in the result without affecting the others.
+async fn reconfigure(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    dataflow_id: Uuid,
+    changes: Vec<InputChange>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    clock: &HLC,
+) -> eyre::Result<ReconfigureReport> {
+    let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
+        bail!("No running dataflow found with UUID `{dataflow_id}`")
+    };
+    let mut results: Vec<Option<InputChangeResult>> = vec![None; changes.len()];
+    let mut machines: BTreeMap<&String, Vec<(usize, InputChange)>> = BTreeMap::new();
+    for (index, change) in changes.into_iter().enumerate() {
+        match dataflow.nodes.iter().find(|node| node.id == change.node_id) {
+            Some(node) => machines
+                .entry(&node.deploy.machine)
+                .or_default()
+                .push((index, change)),
+            None => {
+                results[index] = Some(InputChangeResult {
+                    result: Err(format!(
+                        "dataflow `{dataflow_id}` has no node `{}`",
+                        change.node_id
+                    )),
+                    node_id: change.node_id,
+                    input_id: change.input_id,
+                });
+            }
+        }
+    }
+
+    for (machine_id, changes) in machines {
+        let (indices, changes): (Vec<_>, Vec<_>) = changes.into_iter().unzip();
+        let machine_results = match reconfigure_on_machine(
+            machine_id,
+            dataflow_id,
+            changes.clone(),
+            daemon_connections,
+            clock.new_timestamp(),
+        )
+        .await
+        {
+            Ok(machine_results) if machine_results.len() == changes.len() => machine_results,
+            Ok(_) => changes
+                .into_iter()
+                .map(|change| InputChangeResult {
+                    node_id: change.node_id,
+                    input_id: change.input_id,
+                    result: Err(format!("incomplete reply of daemon `{machine_id}`")),
+                })
+                .collect(),
+            Err(err) => changes
+                .into_iter()
+                .map(|change| InputChangeResult {
+                    node_id: change.node_id,
+                    input_id: change.input_id,
+                    result: Err(format!("{err:?}")),
+                })
+                .collect(),
+        };
+        for (index, result) in indices.into_iter().zip(machine_results) {
+            results[index] = Some(result);
+        }
+    }
+
+    let report = ReconfigureReport {
+        inputs: results.into_iter().flatten().collect(),
+    };
+    tracing::info!("reconfigured inputs of dataflow `{dataflow_id}`:\n{report}");
+    Ok(report)
+}
+
+async fn reconfigure_on_machine(
+    machine_id: &str,
+    dataflow_id: Uuid,
+    changes: Vec<InputChange>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<Vec<InputChangeResult>> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::Reconfigure {
+            dataflow_id,
+            changes,
+        },
+        timestamp,
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send reconfigure message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive reconfigure reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize reconfigure reply from daemon")?
+    {
+        DaemonCoordinatorReply::ReconfigureResult(result) => result
+            .map_err(|e| eyre!(e))
+            .wrap_err_with(|| format!("failed to reconfigure inputs on machine `{machine_id}`")),
+        other => bail!("unexpected reply after sending reconfigure: {other:?}"),
+    }
+}
+
 async fn retrieve_logs(
     running_dataflows: &HashMap<Uuid, RunningDataflow>,
     archived_dataflows: &HashMap<Uuid, ArchivedDataflow>,
@@ -888,31 +1789,343 @@ async fn retrieve_logs(
     reply_logs.map_err(|err| eyre!(err))
 }
 
+async fn retrieve_descriptor(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    dataflow_id: Uuid,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<String> {
+    let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
+        bail!("No running dataflow found with UUID `{dataflow_id}`")
+    };
+    // all daemons of a dataflow store the full descriptor, so we can ask any of them
+    let Some(machine_id) = dataflow.machines.iter().next() else {
+        bail!("dataflow `{dataflow_id}` is not running on any machine")
+    };
+
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::GetDescriptor { dataflow_id },
+        timestamp,
+    })?;
+
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id.as_str())
+        .wrap_err("no daemon connection")?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send descriptor message to daemon")?;
+
+    // wait for reply
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to retrieve descriptor reply from daemon")?;
+    let descriptor = match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize descriptor reply from daemon")?
+    {
+        DaemonCoordinatorReply::Descriptor(descriptor) => descriptor,
+        other => bail!("unexpected reply after sending descriptor request: {other:?}"),
+    };
+
+    descriptor.map_err(|err| eyre!(err))
+}
+
+/// Compares the nodes of a running dataflow, as stored by its daemons, with
+/// the nodes of the given descriptor.
+async fn diff_dataflow(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    dataflow_id: Uuid,
+    dataflow: Descriptor,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<DataflowDiff> {
+    let running = retrieve_descriptor(
+        running_dataflows,
+        dataflow_id,
+        daemon_connections,
+        timestamp,
+    )
+    .await?;
+    let running: serde_yaml::Value = serde_yaml::from_str(&running)
+        .wrap_err("failed to parse descriptor of running dataflow")?;
+    let running_nodes: Vec<ResolvedNode> = running
+        .get("nodes")
+        .cloned()
+        .map(serde_yaml::from_value)
+        .transpose()
+        .wrap_err("failed to parse nodes of running dataflow")?
+        .unwrap_or_default();
+    let mut nodes = dataflow
+        .resolve_aliases_and_set_defaults()
+        .wrap_err("failed to resolve new dataflow descriptor")?;
+    expand_wildcard_inputs(&mut nodes);
+    Ok(diff_nodes(&running_nodes, &nodes))
+}
+
+async fn retrieve_daemon_status(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> BTreeMap<String, MachineStatus> {
+    let mut status = BTreeMap::new();
+    for (machine_id, connection) in daemon_connections {
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            request_daemon_status(&mut connection.stream, timestamp),
+        )
+        .await
+        .wrap_err("timeout")
+        .and_then(|r| r)
+        .wrap_err_with(|| format!("failed to retrieve status of daemon at `{machine_id}`"));
+        if let Err(err) = &result {
+            tracing::warn!("{err:?}");
+        }
+        status.insert(
+            machine_id.clone(),
+            MachineStatus {
+                status: result.map_err(|err| format!("{err:?}")),
+                last_heartbeat: connection.last_health.clone(),
+                metadata: connection.metadata.clone(),
+            },
+        );
+    }
+    status
+}
+
+async fn request_daemon_status(
+    connection: &mut TcpStream,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<DaemonStatus> {
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::Status,
+        timestamp,
+    })?;
+    tcp_send(connection, &message)
+        .await
+        .wrap_err("failed to send status message to daemon")?;
+    let reply_raw = tcp_receive(connection)
+        .await
+        .wrap_err("failed to receive status reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize status reply from daemon")?
+    {
+        DaemonCoordinatorReply::Status(status) => Ok(status),
+        other => bail!("unexpected reply after sending status request: {other:?}"),
+    }
+}
+
+async fn set_daemon_log_level(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine_id: &str,
+    filter: String,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<String> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::SetLogLevel { filter },
+        timestamp,
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send log level message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive log level reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize log level reply from daemon")?
+    {
+        DaemonCoordinatorReply::SetLogLevelResult(result) => result.map_err(|err| eyre!(err)),
+        other => bail!("unexpected reply after sending log level request: {other:?}"),
+    }
+}
+
+async fn request_daemon_diagnostics(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine_id: &str,
+    gc: Option<Duration>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<DaemonDiagnostics> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::Diagnostics { gc },
+        timestamp,
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send diagnostics message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive diagnostics reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize diagnostics reply from daemon")?
+    {
+        DaemonCoordinatorReply::Diagnostics(diagnostics) => Ok(diagnostics),
+        other => bail!("unexpected reply after sending diagnostics request: {other:?}"),
+    }
+}
+
+/// Sends a `TapOutput` event to the daemon that runs the tapped node.
+async fn start_tap(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    dataflow_id: Uuid,
+    node_id: &NodeId,
+    tap: DaemonCoordinatorEvent,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<()> {
+    let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
+        bail!("No running dataflow found with UUID `{dataflow_id}`")
+    };
+    let machine_id = dataflow
+        .nodes
+        .iter()
+        .find(|node| &node.id == node_id)
+        .map(|node| &node.deploy.machine)
+        .wrap_err_with(|| format!("dataflow `{dataflow_id}` has no node `{node_id}`"))?;
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: tap,
+        timestamp,
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send tap message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive tap reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize tap reply from daemon")?
+    {
+        DaemonCoordinatorReply::TapResult(result) => result.map_err(|err| eyre!(err)),
+        other => bail!("unexpected reply after sending tap request: {other:?}"),
+    }
+}
+
+/// Requests a join token for the given node from the daemon that runs it.
+async fn resolve_node(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    dataflow_id: Uuid,
+    node_id: NodeId,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<NodeJoinInfo> {
+    let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
+        bail!("No running dataflow found with UUID `{dataflow_id}`")
+    };
+    let machine_id = dataflow
+        .nodes
+        .iter()
+        .find(|node| node.id == node_id)
+        .map(|node| node.deploy.machine.clone())
+        .wrap_err_with(|| format!("dataflow `{dataflow_id}` has no node `{node_id}`"))?;
+    let daemon_connection = daemon_connections
+        .get_mut(&machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::MintToken {
+            dataflow_id,
+            node_id: node_id.clone(),
+        },
+        timestamp,
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send mint token message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive mint token reply from daemon")?;
+    let minted = match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize mint token reply from daemon")?
+    {
+        DaemonCoordinatorReply::MintTokenResult(result) => result.map_err(|err| eyre!(err))?,
+        other => bail!("unexpected reply after sending mint token request: {other:?}"),
+    };
+    // a listener on all interfaces is reachable through the address that the
+    // daemon connected from
+    let daemon_address = minted.listen_address.map(|mut addr| {
+        if addr.ip().is_unspecified() {
+            addr.set_ip(daemon_connection.listen_socket.ip());
+        }
+        addr
+    });
+    Ok(NodeJoinInfo {
+        dataflow_id,
+        node_id,
+        machine_id,
+        daemon_address,
+        token: minted.token,
+        valid_for: minted.valid_for,
+    })
+}
+
+/// Number of nodes of the running dataflows on each machine.
+fn node_counts(running_dataflows: &HashMap<Uuid, RunningDataflow>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for node in running_dataflows.values().flat_map(|d| &d.nodes) {
+        *counts.entry(node.deploy.machine.clone()).or_default() += 1;
+    }
+    counts
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn start_dataflow(
     dataflow: Descriptor,
     working_dir: PathBuf,
+    machine_working_dirs: BTreeMap<String, PathBuf>,
     name: Option<String>,
+    instance: Option<String>,
+    params: BTreeMap<String, String>,
+    origin: DataflowOrigin,
+    node_counts: BTreeMap<String, usize>,
     daemon_connections: &mut HashMap<String, DaemonConnection>,
     clock: &HLC,
 ) -> eyre::Result<RunningDataflow> {
+    let result_file = dataflow.result_file.as_ref().map(|p| working_dir.join(p));
+    let spawn_instance = name
+        .clone()
+        .zip(instance.clone())
+        .map(|(name, key)| DataflowInstance { name, key });
     let SpawnedDataflow {
         uuid,
         machines,
         nodes,
-    } = spawn_dataflow(dataflow, working_dir, daemon_connections, clock).await?;
+        assignments,
+    } = spawn_dataflow(
+        dataflow,
+        working_dir,
+        machine_working_dirs,
+        spawn_instance,
+        params.clone(),
+        node_counts,
+        daemon_connections,
+        clock,
+    )
+    .await?;
     Ok(RunningDataflow {
         uuid,
         name,
+        instance,
+        params,
+        origin,
         pending_machines: if machines.len() > 1 {
             machines.clone()
         } else {
             BTreeSet::new()
         },
         exited_before_subscribe: Default::default(),
+        stalled_machines: BTreeSet::new(),
         machines,
         nodes,
+        assignments,
+        start_time: SystemTime::now(),
+        result_file,
         reply_senders: Vec::new(),
         log_subscribers: Vec::new(),
+        tap_subscribers: Vec::new(),
     })
 }
 
@@ -953,13 +2166,32 @@ async fn destroy_daemons(
 pub enum Event {
     NewDaemonConnection(TcpStream),
     DaemonConnectError(eyre::Report),
-    DaemonHeartbeat { machine_id: String },
-    Dataflow { uuid: Uuid, event: DataflowEvent },
+    DaemonHeartbeat {
+        machine_id: String,
+    },
+    DaemonHealth {
+        machine_id: String,
+        health: DaemonHealth,
+        /// Wall-clock time at which the snapshot was received, in
+        /// nanoseconds since the Unix epoch.
+        received: u64,
+    },
+    Dataflow {
+        uuid: Uuid,
+        event: DataflowEvent,
+    },
     Control(ControlEvent),
     Daemon(DaemonRequest),
     DaemonHeartbeatInterval,
     CtrlC,
     Log(LogMessage),
+    Tapped {
+        tap_id: Uuid,
+        message: TappedMessage,
+    },
+    TapFinished {
+        tap_id: Uuid,
+    },
 }
 
 impl Event {
@@ -968,6 +2200,7 @@ impl Event {
     pub fn log(&self) -> bool {
         match self {
             Event::DaemonHeartbeatInterval => false,
+            Event::Tapped { .. } => false,
             _ => true,
         }
     }
@@ -983,15 +2216,26 @@ pub enum DataflowEvent {
         machine_id: String,
         exited_before_subscribe: Vec<NodeId>,
     },
+    /// A node with `allowed_to_stop: true` requested to stop the dataflow.
+    StopRequested {
+        node_id: NodeId,
+        grace_duration: Option<Duration>,
+    },
+    /// The dataflow has running nodes on the machine, but no message flow.
+    Stalled { machine_id: String, idle: Duration },
+    /// Messages flow again on a machine that reported a stall.
+    Recovered { machine_id: String },
 }
 
 #[derive(Debug)]
 pub enum DaemonRequest {
     Register {
-        version_check_result: Result<(), String>,
+        check_result: Result<(), String>,
         machine_id: String,
         connection: TcpStream,
         listen_port: u16,
+        inter_daemon_transport: InterDaemonTransport,
+        metadata: MachineMetadata,
     },
 }
 
@@ -1,6 +1,9 @@
 use crate::{tcp_utils::tcp_receive, DaemonRequest, DataflowEvent, Event};
-use dora_core::uhlc::HLC;
-use dora_message::daemon_to_coordinator::{CoordinatorRequest, DaemonEvent, Timestamped};
+use dora_core::{constant_time_eq, uhlc::HLC};
+use dora_message::{
+    compat::{self, Decoded},
+    daemon_to_coordinator::{CoordinatorRequest, DaemonEvent, Timestamped},
+};
 use eyre::Context;
 use std::{io::ErrorKind, net::SocketAddr, sync::Arc};
 use tokio::{
@@ -18,11 +21,18 @@ pub async fn create_listener(bind: SocketAddr) -> eyre::Result<TcpListener> {
     Ok(socket)
 }
 
+/// Handles the messages of a daemon connection.
+///
+/// If a `machine_token` is configured, daemons need to register with it, and
+/// events are only accepted on connections that were authenticated for the
+/// machine through [`CoordinatorRequest::AuthenticateEvents`].
 pub async fn handle_connection(
     mut connection: TcpStream,
     events_tx: mpsc::Sender<Event>,
     clock: Arc<HLC>,
+    machine_token: Option<Arc<str>>,
 ) {
+    let mut authenticated_machine: Option<String> = None;
     loop {
         // receive the next message and parse it
         let raw = match tcp_receive(&mut connection).await {
@@ -36,10 +46,17 @@ pub async fn handle_connection(
             }
         };
         let message: Timestamped<CoordinatorRequest> =
-            match serde_json::from_slice(&raw).wrap_err("failed to deserialize node message") {
-                Ok(e) => e,
+            match compat::decode(&raw).wrap_err("failed to deserialize node message") {
+                Ok(Decoded::Message(e)) => e,
+                Ok(Decoded::Skipped { error, .. }) => {
+                    tracing::debug!("skipping ignorable message from daemon: {error}");
+                    continue;
+                }
                 Err(err) => {
-                    tracing::warn!("{err:?}");
+                    tracing::warn!(
+                        "{err:?}\n\nThe daemon might be from a newer dora release than the \
+                        coordinator."
+                    );
                     continue;
                 }
             };
@@ -53,13 +70,40 @@ pub async fn handle_connection(
             CoordinatorRequest::Register(register_request) => {
                 let event = DaemonRequest::Register {
                     connection,
-                    version_check_result: register_request.check_version(),
+                    check_result: register_request.check_version().and_then(|()| {
+                        check_machine_token(
+                            machine_token.as_deref(),
+                            register_request.machine_token.as_deref(),
+                        )
+                    }),
                     machine_id: register_request.machine_id,
                     listen_port: register_request.listen_port,
+                    inter_daemon_transport: register_request.inter_daemon_transport,
+                    metadata: register_request.metadata,
                 };
                 let _ = events_tx.send(Event::Daemon(event)).await;
                 break;
             }
+            CoordinatorRequest::AuthenticateEvents {
+                machine_id,
+                machine_token: token,
+            } => {
+                if let Err(err) = check_machine_token(machine_token.as_deref(), Some(&token)) {
+                    tracing::warn!("closing event connection of daemon `{machine_id}`: {err}");
+                    break;
+                }
+                authenticated_machine = Some(machine_id);
+            }
+            CoordinatorRequest::Event { machine_id, .. }
+                if machine_token.is_some()
+                    && authenticated_machine.as_deref() != Some(machine_id.as_str()) =>
+            {
+                tracing::warn!(
+                    "closing connection that sent an event of daemon `{machine_id}` \
+                    without authenticating for it"
+                );
+                break;
+            }
             CoordinatorRequest::Event { machine_id, event } => match event {
                 DaemonEvent::AllNodesReady {
                     dataflow_id,
@@ -94,13 +138,183 @@ pub async fn handle_connection(
                         break;
                     }
                 }
+                DaemonEvent::Health(health) => {
+                    let event = Event::DaemonHealth {
+                        machine_id,
+                        health,
+                        received: crate::wall_clock_now(),
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
                 DaemonEvent::Log(message) => {
                     let event = Event::Log(message);
                     if events_tx.send(event).await.is_err() {
                         break;
                     }
                 }
+                DaemonEvent::Tapped { tap_id, message } => {
+                    let event = Event::Tapped { tap_id, message };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                DaemonEvent::TapFinished { tap_id } => {
+                    let event = Event::TapFinished { tap_id };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                DaemonEvent::StopRequested {
+                    dataflow_id,
+                    node_id,
+                    grace_duration,
+                } => {
+                    let event = Event::Dataflow {
+                        uuid: dataflow_id,
+                        event: DataflowEvent::StopRequested {
+                            node_id,
+                            grace_duration,
+                        },
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                DaemonEvent::DataflowStalled { dataflow_id, idle } => {
+                    let event = Event::Dataflow {
+                        uuid: dataflow_id,
+                        event: DataflowEvent::Stalled { machine_id, idle },
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                DaemonEvent::DataflowRecovered { dataflow_id } => {
+                    let event = Event::Dataflow {
+                        uuid: dataflow_id,
+                        event: DataflowEvent::Recovered { machine_id },
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
             },
         };
     }
 }
+
+fn check_machine_token(expected: Option<&str>, token: Option<&str>) -> Result<(), String> {
+    match (expected, token) {
+        (None, _) => Ok(()),
+        (Some(expected), Some(token)) if constant_time_eq(expected, token) => Ok(()),
+        (Some(_), Some(_)) => Err("invalid machine token".into()),
+        (Some(_), None) => {
+            Err("the coordinator requires a machine token (see `--machine-token`)".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp_utils::tcp_send;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn connect(machine_token: Option<&str>) -> (TcpStream, mpsc::Receiver<Event>) {
+        let listener = create_listener(([127, 0, 0, 1], 0).into()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events_tx, events_rx) = mpsc::channel(1);
+        let machine_token = machine_token.map(Arc::from);
+        tokio::spawn(async move {
+            let (connection, _) = listener.accept().await.unwrap();
+            handle_connection(
+                connection,
+                events_tx,
+                Arc::new(HLC::default()),
+                machine_token,
+            )
+            .await
+        });
+        (TcpStream::connect(addr).await.unwrap(), events_rx)
+    }
+
+    async fn send(connection: &mut TcpStream, request: CoordinatorRequest) {
+        let message = Timestamped {
+            inner: request,
+            timestamp: HLC::default().new_timestamp(),
+        };
+        tcp_send(connection, &serde_json::to_vec(&message).unwrap())
+            .await
+            .unwrap();
+    }
+
+    fn stop_requested(dataflow_id: Uuid) -> CoordinatorRequest {
+        CoordinatorRequest::Event {
+            machine_id: "A".into(),
+            event: DaemonEvent::StopRequested {
+                dataflow_id,
+                node_id: "node".parse().unwrap(),
+                grace_duration: Some(Duration::from_secs(1)),
+            },
+        }
+    }
+
+    fn authenticate(machine_id: &str, machine_token: &str) -> CoordinatorRequest {
+        CoordinatorRequest::AuthenticateEvents {
+            machine_id: machine_id.into(),
+            machine_token: machine_token.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn events_of_unauthenticated_connections_are_ignored() {
+        let (mut connection, mut events) = connect(Some("secret")).await;
+        send(&mut connection, stop_requested(Uuid::new_v4())).await;
+        // the coordinator closes the connection without forwarding the event
+        assert!(events.recv().await.is_none());
+
+        let (mut connection, mut events) = connect(Some("secret")).await;
+        send(&mut connection, authenticate("B", "secret")).await;
+        send(&mut connection, stop_requested(Uuid::new_v4())).await;
+        assert!(events.recv().await.is_none());
+
+        let (mut connection, mut events) = connect(Some("secret")).await;
+        send(&mut connection, authenticate("A", "wrong")).await;
+        send(&mut connection, stop_requested(Uuid::new_v4())).await;
+        assert!(events.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn events_of_authenticated_connections_are_forwarded() {
+        for machine_token in [None, Some("secret")] {
+            let (mut connection, mut events) = connect(machine_token).await;
+            if let Some(machine_token) = machine_token {
+                send(&mut connection, authenticate("A", machine_token)).await;
+            }
+            let dataflow_id = Uuid::new_v4();
+            send(&mut connection, stop_requested(dataflow_id)).await;
+            match events.recv().await {
+                Some(Event::Dataflow {
+                    uuid,
+                    event: DataflowEvent::StopRequested { node_id, .. },
+                }) => {
+                    assert_eq!(uuid, dataflow_id);
+                    assert_eq!(node_id.as_ref(), "node");
+                }
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn register_needs_the_machine_token() {
+        assert_eq!(check_machine_token(None, None), Ok(()));
+        assert_eq!(check_machine_token(None, Some("any")), Ok(()));
+        assert_eq!(check_machine_token(Some("secret"), Some("secret")), Ok(()));
+        assert!(check_machine_token(Some("secret"), Some("wrong")).is_err());
+        assert!(check_machine_token(Some("secret"), None).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Migration of a node of a running dataflow to another machine.
+//!
+//! The new instance is spawned in standby on the target machine first. Then
+//! every machine of the dataflow switches its routes to the new instance,
+//! starting with the source machine and ending with the target machine, so
+//! that the ticks of local timers are never delivered to both instances. The
+//! daemons make sure that outputs in flight during the switch are delivered
+//! to exactly one of the instances. Finally, the old instance is stopped.
+
+use std::{collections::HashMap, time::Instant};
+
+use dora_core::{config::NodeId, uhlc::HLC};
+use dora_message::{
+    coordinator_to_daemon::{DaemonCoordinatorEvent, Timestamped},
+    daemon_to_coordinator::DaemonCoordinatorReply,
+    migration::{MigrationPhase, MigrationPhaseReport, NodeMigrationReport},
+};
+use eyre::{bail, eyre, ContextCompat, WrapErr};
+use uuid::Uuid;
+
+use crate::{
+    tcp_utils::{tcp_receive, tcp_send},
+    DaemonConnection, RunningDataflow,
+};
+
+/// Moves the given node to `target_machine`, reporting the outcome of each
+/// phase.
+///
+/// Returns an error only if the migration could not be started at all. If
+/// the routes could not be switched on all machines, the migration is
+/// reverted and the node keeps running on its current machine.
+pub(crate) async fn migrate_node(
+    running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
+    dataflow_id: Uuid,
+    node_id: NodeId,
+    target_machine: String,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    clock: &HLC,
+) -> eyre::Result<NodeMigrationReport> {
+    let Some(dataflow) = running_dataflows.get_mut(&dataflow_id) else {
+        bail!("No running dataflow found with UUID `{dataflow_id}`")
+    };
+    let node = dataflow
+        .nodes
+        .iter()
+        .find(|node| node.id == node_id)
+        .cloned()
+        .wrap_err_with(|| format!("dataflow `{dataflow_id}` has no node `{node_id}`"))?;
+    let from_machine = node.deploy.machine.clone();
+    if from_machine == target_machine {
+        bail!("node `{node_id}` runs on machine `{target_machine}` already");
+    }
+    if !dataflow.machines.contains(&target_machine) {
+        bail!("machine `{target_machine}` runs no nodes of dataflow `{dataflow_id}`");
+    }
+    for machine in [&from_machine, &target_machine] {
+        if !daemon_connections.contains_key(machine) {
+            bail!("no daemon connected for machine `{machine}`");
+        }
+    }
+    tracing::info!(
+        "migrating node `{dataflow_id}/{node_id}` from machine `{from_machine}` to `{target_machine}`"
+    );
+    let mut report = NodeMigrationReport {
+        from_machine: from_machine.clone(),
+        to_machine: target_machine.clone(),
+        phases: Vec::new(),
+    };
+
+    let started = Instant::now();
+    let mut target_node = node;
+    target_node.deploy.machine = target_machine.clone();
+    let event = DaemonCoordinatorEvent::PrepareNodeMigration {
+        dataflow_id,
+        node: target_node,
+        from_machine: from_machine.clone(),
+    };
+    // the daemon replies once the standby instance subscribed
+    let result = match send_event(daemon_connections, &target_machine, event, clock).await {
+        Ok(DaemonCoordinatorReply::ReloadNodeResult(result)) => {
+            result.map(|_| ()).map_err(|err| eyre!(err))
+        }
+        Ok(other) => Err(eyre!("unexpected reply after sending migration: {other:?}")),
+        Err(err) => Err(err),
+    };
+    if !record(&mut report, MigrationPhase::Spawn, started, result) {
+        // the standby instance might have exited after it was spawned
+        let event = DaemonCoordinatorEvent::FinishNodeMigration {
+            dataflow_id,
+            node_id: node_id.clone(),
+            abort: true,
+        };
+        if let Err(err) = migration_step(daemon_connections, &target_machine, event, clock).await {
+            tracing::warn!("failed to clean up failed migration of node `{node_id}`: {err:?}");
+        }
+        return Ok(report);
+    }
+
+    let started = Instant::now();
+    let mut order = vec![from_machine.clone()];
+    order.extend(
+        dataflow
+            .machines
+            .iter()
+            .filter(|machine| **machine != from_machine && **machine != target_machine)
+            .cloned(),
+    );
+    order.push(target_machine.clone());
+    let mut attempted = Vec::new();
+    let mut result = Ok(());
+    for machine in order {
+        let event = DaemonCoordinatorEvent::SwitchNodeRoutes {
+            dataflow_id,
+            node_id: node_id.clone(),
+            from_machine: from_machine.clone(),
+            to_machine: target_machine.clone(),
+            revert: false,
+        };
+        // the routes of a failed machine might be switched partially
+        attempted.push(machine.clone());
+        result = migration_step(daemon_connections, &machine, event, clock)
+            .await
+            .wrap_err_with(|| format!("failed to switch routes on machine `{machine}`"));
+        if result.is_err() {
+            break;
+        }
+    }
+    if !record(&mut report, MigrationPhase::Switch, started, result) {
+        let started = Instant::now();
+        let mut result = Ok(());
+        for machine in attempted.iter().rev() {
+            let event = DaemonCoordinatorEvent::SwitchNodeRoutes {
+                dataflow_id,
+                node_id: node_id.clone(),
+                from_machine: from_machine.clone(),
+                to_machine: target_machine.clone(),
+                revert: true,
+            };
+            let step = migration_step(daemon_connections, machine, event, clock)
+                .await
+                .wrap_err_with(|| format!("failed to revert routes on machine `{machine}`"));
+            result = result.and(step);
+        }
+        for machine in [&target_machine, &from_machine] {
+            let event = DaemonCoordinatorEvent::FinishNodeMigration {
+                dataflow_id,
+                node_id: node_id.clone(),
+                abort: true,
+            };
+            let step = migration_step(daemon_connections, machine, event, clock)
+                .await
+                .wrap_err_with(|| format!("failed to abort migration on machine `{machine}`"));
+            result = result.and(step);
+        }
+        record(&mut report, MigrationPhase::Revert, started, result);
+        return Ok(report);
+    }
+
+    // the daemon replies once the old instance exited
+    let started = Instant::now();
+    let event = DaemonCoordinatorEvent::StopMigratedNode {
+        dataflow_id,
+        node_id: node_id.clone(),
+    };
+    let result = migration_step(daemon_connections, &from_machine, event, clock).await;
+    record(&mut report, MigrationPhase::StopOld, started, result);
+
+    // all messages are routed to the new instance, so it is confirmed even
+    // if the old instance could not be stopped
+    let started = Instant::now();
+    let mut result = Ok(());
+    for machine in [&target_machine, &from_machine] {
+        let event = DaemonCoordinatorEvent::FinishNodeMigration {
+            dataflow_id,
+            node_id: node_id.clone(),
+            abort: false,
+        };
+        let step = migration_step(daemon_connections, machine, event, clock)
+            .await
+            .wrap_err_with(|| format!("failed to finish migration on machine `{machine}`"));
+        result = result.and(step);
+    }
+    if let Some(node) = dataflow.nodes.iter_mut().find(|node| node.id == node_id) {
+        node.deploy.machine = target_machine.clone();
+    }
+    if let Some(machine) = dataflow.assignments.get_mut(&node_id) {
+        *machine = target_machine;
+    }
+    record(&mut report, MigrationPhase::Confirm, started, result);
+
+    Ok(report)
+}
+
+/// Adds the outcome of a phase to the report and returns whether it
+/// succeeded.
+fn record(
+    report: &mut NodeMigrationReport,
+    phase: MigrationPhase,
+    started: Instant,
+    result: eyre::Result<()>,
+) -> bool {
+    let duration = started.elapsed();
+    match &result {
+        Ok(()) => tracing::info!("migration phase `{phase}` succeeded in {duration:?}"),
+        Err(err) => tracing::warn!("migration phase `{phase}` failed: {err:?}"),
+    }
+    let success = result.is_ok();
+    report.phases.push(MigrationPhaseReport {
+        phase,
+        duration,
+        result: result.map_err(|err| format!("{err:#}")),
+    });
+    success
+}
+
+/// Sends a migration event that is answered with a `NodeMigrationResult`.
+async fn migration_step(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine_id: &str,
+    event: DaemonCoordinatorEvent,
+    clock: &HLC,
+) -> eyre::Result<()> {
+    match send_event(daemon_connections, machine_id, event, clock).await? {
+        DaemonCoordinatorReply::NodeMigrationResult(result) => result.map_err(|err| eyre!(err)),
+        other => bail!("unexpected reply after sending migration event: {other:?}"),
+    }
+}
+
+async fn send_event(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine_id: &str,
+    event: DaemonCoordinatorEvent,
+    clock: &HLC,
+) -> eyre::Result<DaemonCoordinatorReply> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connected for machine `{machine_id}`"))?;
+    let message = serde_json::to_vec(&Timestamped {
+        inner: event,
+        timestamp: clock.new_timestamp(),
+    })?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send migration event to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive migration reply from daemon")?;
+    serde_json::from_slice(&reply_raw).wrap_err("failed to deserialize migration reply from daemon")
+}
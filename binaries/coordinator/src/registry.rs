@@ -0,0 +1,215 @@
+//! Registry of named and versioned dataflow descriptors.
+//!
+//! Registered descriptors can be started by name, and `dora list` shows
+//! which registered version every running dataflow was started from. The
+//! registry is kept in memory, so it is empty after a restart of the
+//! coordinator.
+
+use dora_core::descriptor::Descriptor;
+use dora_message::{
+    cli_to_coordinator::RegistryKey,
+    coordinator_to_cli::{DataflowOrigin, RegistryEntry},
+};
+use eyre::{bail, eyre, Context};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::PathBuf};
+
+#[derive(Default)]
+pub struct DescriptorRegistry {
+    /// Registered descriptors by name and version.
+    entries: BTreeMap<String, BTreeMap<u32, Registration>>,
+}
+
+struct Registration {
+    descriptor: Descriptor,
+    hash: String,
+    working_dir: PathBuf,
+}
+
+impl DescriptorRegistry {
+    /// Stores the descriptor under the given name and version, or under the
+    /// version after the latest one.
+    pub fn register(
+        &mut self,
+        name: String,
+        version: Option<u32>,
+        descriptor: Descriptor,
+        working_dir: PathBuf,
+    ) -> eyre::Result<RegistryEntry> {
+        if name.is_empty() || name.contains('@') {
+            bail!("invalid dataflow name `{name}`: must be non-empty and must not contain `@`");
+        }
+        let hash = descriptor_hash(&descriptor)?;
+        let versions = self.entries.entry(name.clone()).or_default();
+        let version = match version {
+            Some(0) => bail!("dataflow versions start at 1"),
+            Some(version) => version,
+            None => versions.keys().next_back().map_or(1, |latest| latest + 1),
+        };
+        if let Some(existing) = versions.get(&version) {
+            if existing.hash != hash {
+                bail!(
+                    "`{name}@{version}` is registered already with a different descriptor \
+                    (hash {}), register it as a new version instead",
+                    existing.hash
+                );
+            }
+            return Ok(entry(&name, version, existing));
+        }
+        let registration = Registration {
+            descriptor,
+            hash,
+            working_dir,
+        };
+        let entry = entry(&name, version, &registration);
+        versions.insert(version, registration);
+        Ok(entry)
+    }
+
+    /// All registered descriptors, ordered by name and version.
+    pub fn list(&self) -> Vec<RegistryEntry> {
+        self.entries
+            .iter()
+            .flat_map(|(name, versions)| {
+                versions
+                    .iter()
+                    .map(move |(&version, registration)| entry(name, version, registration))
+            })
+            .collect()
+    }
+
+    /// Looks up the given version, or the latest version if the key has no
+    /// version.
+    pub fn get(&self, key: &RegistryKey) -> eyre::Result<(RegistryEntry, &Descriptor)> {
+        let versions = self
+            .entries
+            .get(&key.name)
+            .ok_or_else(|| eyre!("no registered dataflow with name `{}`", key.name))?;
+        let (&version, registration) = match key.version {
+            Some(version) => versions
+                .get_key_value(&version)
+                .ok_or_else(|| eyre!("dataflow `{key}` is not registered"))?,
+            None => versions
+                .last_key_value()
+                .ok_or_else(|| eyre!("no registered dataflow with name `{}`", key.name))?,
+        };
+        Ok((
+            entry(&key.name, version, registration),
+            &registration.descriptor,
+        ))
+    }
+
+    /// Removes the given version, or all versions if the key has no version.
+    pub fn delete(&mut self, key: &RegistryKey) -> eyre::Result<Vec<RegistryEntry>> {
+        let versions = self
+            .entries
+            .get_mut(&key.name)
+            .ok_or_else(|| eyre!("no registered dataflow with name `{}`", key.name))?;
+        let deleted = match key.version {
+            Some(version) => {
+                let registration = versions
+                    .remove(&version)
+                    .ok_or_else(|| eyre!("dataflow `{key}` is not registered"))?;
+                vec![entry(&key.name, version, &registration)]
+            }
+            None => std::mem::take(versions)
+                .into_iter()
+                .map(|(version, registration)| entry(&key.name, version, &registration))
+                .collect(),
+        };
+        if versions.is_empty() {
+            self.entries.remove(&key.name);
+        }
+        Ok(deleted)
+    }
+}
+
+fn entry(name: &str, version: u32, registration: &Registration) -> RegistryEntry {
+    RegistryEntry {
+        name: name.to_owned(),
+        version,
+        hash: registration.hash.clone(),
+        working_dir: registration.working_dir.clone(),
+    }
+}
+
+/// Hash of the descriptor, as described in [`DataflowOrigin`].
+pub fn descriptor_hash(descriptor: &Descriptor) -> eyre::Result<String> {
+    let encoded = serde_json::to_vec(descriptor).context("failed to serialize descriptor")?;
+    Ok(format!("{:x}", Sha256::digest(encoded)))
+}
+
+/// Origin of a dataflow that is started from the given descriptor file.
+pub fn unregistered_origin(descriptor: &Descriptor) -> eyre::Result<DataflowOrigin> {
+    Ok(DataflowOrigin::Unregistered {
+        hash: descriptor_hash(descriptor)?,
+    })
+}
+
+impl From<RegistryEntry> for DataflowOrigin {
+    fn from(entry: RegistryEntry) -> Self {
+        DataflowOrigin::Registered {
+            name: entry.name,
+            version: entry.version,
+            hash: entry.hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(node: &str) -> Descriptor {
+        let yaml = format!("nodes:\n  - id: {node}\n    path: ./{node}\n");
+        Descriptor::parse(yaml.into_bytes()).unwrap()
+    }
+
+    fn key(key: &str) -> RegistryKey {
+        key.parse().unwrap()
+    }
+
+    #[test]
+    fn versions_are_immutable() {
+        let mut registry = DescriptorRegistry::default();
+        let dir = PathBuf::from("/flows");
+        let v1 = registry
+            .register("patrol".into(), None, descriptor("a"), dir.clone())
+            .unwrap();
+        assert_eq!(v1.version, 1);
+        let v3 = registry
+            .register("patrol".into(), Some(3), descriptor("b"), dir.clone())
+            .unwrap();
+        assert_ne!(v1.hash, v3.hash);
+
+        // same descriptor again is fine, a different one is not
+        assert_eq!(
+            registry
+                .register("patrol".into(), Some(3), descriptor("b"), dir.clone())
+                .unwrap(),
+            v3
+        );
+        assert!(registry
+            .register("patrol".into(), Some(3), descriptor("c"), dir.clone())
+            .is_err());
+        assert!(registry
+            .register("patrol@2".into(), None, descriptor("c"), dir.clone())
+            .is_err());
+
+        let next = registry
+            .register("patrol".into(), None, descriptor("c"), dir)
+            .unwrap();
+        assert_eq!(next.version, 4);
+
+        assert_eq!(registry.get(&key("patrol")).unwrap().0, next);
+        assert_eq!(registry.get(&key("patrol@1")).unwrap().0, v1);
+        assert!(registry.get(&key("patrol@2")).is_err());
+        assert_eq!(registry.list().len(), 3);
+
+        assert_eq!(registry.delete(&key("patrol@4")).unwrap(), vec![next]);
+        assert_eq!(registry.get(&key("patrol")).unwrap().0, v3);
+        assert_eq!(registry.delete(&key("patrol")).unwrap(), vec![v1, v3]);
+        assert!(registry.list().is_empty());
+        assert!(registry.delete(&key("patrol")).is_err());
+    }
+}
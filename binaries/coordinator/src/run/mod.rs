@@ -4,43 +4,196 @@ use crate::{
 };
 
 use dora_core::{
-    descriptor::{Descriptor, ResolvedNode},
+    config::NodeId,
+    descriptor::{Descriptor, ResolvedDeploy, ResolvedNode},
     uhlc::HLC,
 };
 use dora_message::{
-    coordinator_to_daemon::{DaemonCoordinatorEvent, SpawnDataflowNodes, Timestamped},
-    daemon_to_coordinator::DaemonCoordinatorReply,
+    coordinator_to_daemon::{
+        DaemonCoordinatorEvent, DataflowInstance, SpawnDataflowNodes, Timestamped,
+    },
+    daemon_to_coordinator::{DaemonCoordinatorReply, MachineMetadata},
+    daemon_to_daemon::InterDaemonTransport,
+    plan::{DataflowPlan, MachinePlan},
 };
 use eyre::{bail, eyre, ContextCompat, WrapErr};
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
+    net::SocketAddr,
     path::PathBuf,
 };
 use uuid::{NoContext, Timestamp, Uuid};
 
+/// Spawns the dataflow on the daemons of all machines that run nodes of it.
+///
+/// The coordinator doesn't access its local file system for this. Relative
+/// node paths are resolved by the daemons, using the working dir given in
+/// `machine_working_dirs`, the default working dir of the daemon, or the
+/// `working_dir` as fallback.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(skip(daemon_connections, clock))]
 pub(super) async fn spawn_dataflow(
     dataflow: Descriptor,
     working_dir: PathBuf,
+    machine_working_dirs: BTreeMap<String, PathBuf>,
+    instance: Option<DataflowInstance>,
+    params: BTreeMap<String, String>,
+    node_counts: BTreeMap<String, usize>,
     daemon_connections: &mut HashMap<String, DaemonConnection>,
     clock: &HLC,
 ) -> eyre::Result<SpawnedDataflow> {
-    let remote_machine_id: Vec<_> = daemon_connections
-        .iter()
-        .filter_map(|(id, c)| {
-            if !c.listen_socket.ip().is_loopback() {
-                Some(id.as_str())
-            } else {
-                None
-            }
-        })
-        .collect();
-    dataflow.check_in_daemon(&working_dir, &remote_machine_id, false)?;
+    let DeployedNodes {
+        nodes,
+        assignments,
+        machines,
+        machine_listen_ports,
+        inter_daemon_transport,
+    } = deploy_nodes(
+        &dataflow,
+        &machine_working_dirs,
+        node_counts,
+        daemon_connections,
+    )?;
+    let uuid = Uuid::new_v7(Timestamp::now(NoContext));
+    tracing::debug!("using {inter_daemon_transport} transport for dataflow `{uuid}`");
 
-    let nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    let mut spawned_machines = Vec::new();
+    for machine in &machines {
+        let spawn_command = SpawnDataflowNodes {
+            dataflow_id: uuid,
+            working_dir: working_dir.clone(),
+            machine_working_dir: machine_working_dirs.get(machine).cloned(),
+            nodes: nodes.clone(),
+            machine_listen_ports: machine_listen_ports.clone(),
+            dataflow_descriptor: dataflow.clone(),
+            inter_daemon_transport,
+            instance: instance.clone(),
+            params: params.clone(),
+            dry_run: false,
+        };
+        let message = serde_json::to_vec(&Timestamped {
+            inner: DaemonCoordinatorEvent::Spawn(spawn_command),
+            timestamp: clock.new_timestamp(),
+        })?;
+
+        tracing::trace!("Spawning dataflow `{uuid}` on machine `{machine}`");
+        let node_working_dirs =
+            match spawn_dataflow_on_machine(daemon_connections, machine, &message).await {
+                Ok(dirs) => dirs,
+                Err(err) => {
+                    // the daemon rolled back its own nodes already
+                    stop_spawned_machines(uuid, &spawned_machines, daemon_connections, clock).await;
+                    return Err(err).wrap_err_with(|| {
+                        format!("failed to spawn dataflow on machine `{machine}`")
+                    });
+                }
+            };
+        spawned_machines.push(machine.clone());
+        for (node_id, dir) in node_working_dirs {
+            tracing::info!(
+                "node `{node_id}` of dataflow `{uuid}` runs in `{}` on machine `{machine}`",
+                dir.display()
+            );
+        }
+    }
+
+    tracing::info!("successfully spawned dataflow `{uuid}`");
+
+    Ok(SpawnedDataflow {
+        uuid,
+        machines,
+        nodes,
+        assignments,
+    })
+}
+
+/// Asks the daemons what they would spawn for the dataflow, without spawning
+/// anything.
+///
+/// Machines are assigned the same way as in [`spawn_dataflow`], but the
+/// assignment is not recorded.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(daemon_connections, clock))]
+pub(super) async fn plan_dataflow(
+    dataflow: Descriptor,
+    working_dir: PathBuf,
+    machine_working_dirs: BTreeMap<String, PathBuf>,
+    name: Option<String>,
+    instance: Option<DataflowInstance>,
+    params: BTreeMap<String, String>,
+    node_counts: BTreeMap<String, usize>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    clock: &HLC,
+) -> eyre::Result<DataflowPlan> {
+    let DeployedNodes {
+        nodes,
+        assignments,
+        machines: machine_ids,
+        machine_listen_ports,
+        inter_daemon_transport,
+    } = deploy_nodes(
+        &dataflow,
+        &machine_working_dirs,
+        node_counts,
+        daemon_connections,
+    )?;
     let uuid = Uuid::new_v7(Timestamp::now(NoContext));
 
+    let mut machines = BTreeMap::new();
+    for machine in machine_ids {
+        let spawn_command = SpawnDataflowNodes {
+            dataflow_id: uuid,
+            working_dir: working_dir.clone(),
+            machine_working_dir: machine_working_dirs.get(&machine).cloned(),
+            nodes: nodes.clone(),
+            machine_listen_ports: machine_listen_ports.clone(),
+            dataflow_descriptor: dataflow.clone(),
+            inter_daemon_transport,
+            instance: instance.clone(),
+            params: params.clone(),
+            dry_run: true,
+        };
+        let message = serde_json::to_vec(&Timestamped {
+            inner: DaemonCoordinatorEvent::Spawn(spawn_command),
+            timestamp: clock.new_timestamp(),
+        })?;
+        let plan = plan_dataflow_on_machine(daemon_connections, &machine, &message)
+            .await
+            .wrap_err_with(|| format!("failed to plan dataflow on machine `{machine}`"))?;
+        machines.insert(machine, plan);
+    }
+
+    Ok(DataflowPlan {
+        name,
+        assignments,
+        inter_daemon_transport,
+        machines,
+        warnings: dataflow.warnings()?,
+    })
+}
+
+/// Resolves the nodes of the dataflow and assigns them to connected machines.
+fn deploy_nodes(
+    dataflow: &Descriptor,
+    machine_working_dirs: &BTreeMap<String, PathBuf>,
+    node_counts: BTreeMap<String, usize>,
+    daemon_connections: &HashMap<String, DaemonConnection>,
+) -> eyre::Result<DeployedNodes> {
+    dataflow.check_without_paths()?;
+
+    let mut nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    let connected_machines = daemon_connections
+        .iter()
+        .map(|(id, connection)| (id.as_str(), &connection.metadata))
+        .collect();
+    let assignments = assign_machines(&mut nodes, &connected_machines, node_counts)?;
+
     let machines: BTreeSet<_> = nodes.iter().map(|n| n.deploy.machine.clone()).collect();
+    for machine in machine_working_dirs.keys() {
+        if !machines.contains(machine) {
+            tracing::warn!("working dir given for machine `{machine}`, which runs no nodes");
+        }
+    }
     let machine_listen_ports = machines
         .iter()
         .map(|m| {
@@ -50,40 +203,172 @@ pub(super) async fn spawn_dataflow(
                 .map(|c| (m.clone(), c.listen_socket))
         })
         .collect::<Result<BTreeMap<_, _>, _>>()?;
+    let inter_daemon_transport = negotiate_transport(&machines, daemon_connections);
 
-    let spawn_command = SpawnDataflowNodes {
-        dataflow_id: uuid,
-        working_dir,
-        nodes: nodes.clone(),
+    Ok(DeployedNodes {
+        nodes,
+        assignments,
+        machines,
         machine_listen_ports,
-        dataflow_descriptor: dataflow,
-    };
-    let message = serde_json::to_vec(&Timestamped {
-        inner: DaemonCoordinatorEvent::Spawn(spawn_command),
-        timestamp: clock.new_timestamp(),
-    })?;
+        inter_daemon_transport,
+    })
+}
 
-    for machine in &machines {
-        tracing::trace!("Spawning dataflow `{uuid}` on machine `{machine}`");
-        spawn_dataflow_on_machine(daemon_connections, machine, &message)
-            .await
-            .wrap_err_with(|| format!("failed to spawn dataflow on machine `{machine}`"))?;
+struct DeployedNodes {
+    nodes: Vec<ResolvedNode>,
+    assignments: BTreeMap<NodeId, String>,
+    machines: BTreeSet<String>,
+    machine_listen_ports: BTreeMap<String, SocketAddr>,
+    inter_daemon_transport: InterDaemonTransport,
+}
+
+/// Replaces machine patterns in the `deploy` config of the given nodes by the
+/// ID of a connected machine that matches the pattern and has all required
+/// labels.
+///
+/// Machines that match the `prefer` pattern are picked first. Of the
+/// candidates, the machine with the fewest nodes is picked, counting the nodes
+/// of other running dataflows (`node_counts`) and of this dataflow. Ties are
+/// broken by machine ID, so that the assignment is deterministic for the same
+/// machines.
+///
+/// Returns the picked machine of each assigned node. No node is assigned if
+/// the constraints of any node can't be met.
+fn assign_machines(
+    nodes: &mut [ResolvedNode],
+    machines: &BTreeMap<&str, &MachineMetadata>,
+    mut node_counts: BTreeMap<String, usize>,
+) -> eyre::Result<BTreeMap<NodeId, String>> {
+    for node in nodes.iter().filter(|n| !n.deploy.is_pattern()) {
+        *node_counts.entry(node.deploy.machine.clone()).or_default() += 1;
     }
 
-    tracing::info!("successfully spawned dataflow `{uuid}`");
+    let mut assignments = BTreeMap::new();
+    let mut unmet = Vec::new();
+    for node in nodes.iter().filter(|n| n.deploy.is_pattern()) {
+        let deploy = &node.deploy;
+        let pattern = match deploy.machine.as_str() {
+            "" => glob::Pattern::new("*"),
+            machine => glob::Pattern::new(machine),
+        }
+        .wrap_err_with(|| format!("invalid machine pattern of node `{}`", node.id))?;
+        let prefer = deploy
+            .prefer
+            .as_deref()
+            .map(glob::Pattern::new)
+            .transpose()
+            .wrap_err_with(|| format!("invalid `prefer` pattern of node `{}`", node.id))?;
 
-    Ok(SpawnedDataflow {
-        uuid,
-        machines,
-        nodes,
-    })
+        let candidates: Vec<&str> = machines
+            .iter()
+            .filter(|(id, metadata)| {
+                pattern.matches(id) && deploy.requires.iter().all(|l| metadata.satisfies(l))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if candidates.is_empty() {
+            unmet.push(format!(
+                "node `{}`: {}",
+                node.id,
+                unmet_constraint(deploy, &pattern, machines)
+            ));
+            continue;
+        }
+        let preferred: Vec<&str> = candidates
+            .iter()
+            .copied()
+            .filter(|id| prefer.as_ref().is_some_and(|p| p.matches(id)))
+            .collect();
+        let pool = if preferred.is_empty() {
+            candidates
+        } else {
+            preferred
+        };
+        let machine = pool
+            .into_iter()
+            .min_by_key(|id| (node_counts.get(*id).copied().unwrap_or(0), *id))
+            .context("no candidate machine")?;
+        *node_counts.entry(machine.to_owned()).or_default() += 1;
+        assignments.insert(node.id.clone(), machine.to_owned());
+    }
+    if !unmet.is_empty() {
+        bail!(
+            "deploy constraints of {} node(s) can't be met by the connected machines:\n  - {}",
+            unmet.len(),
+            unmet.join("\n  - ")
+        );
+    }
+
+    for node in nodes {
+        if let Some(machine) = assignments.get(&node.id) {
+            tracing::debug!("assigning node `{}` to machine `{machine}`", node.id);
+            node.deploy.machine = machine.clone();
+        }
+    }
+    Ok(assignments)
+}
+
+/// Describes which constraint of the given deploy config no connected
+/// machine meets.
+fn unmet_constraint(
+    deploy: &ResolvedDeploy,
+    pattern: &glob::Pattern,
+    machines: &BTreeMap<&str, &MachineMetadata>,
+) -> String {
+    let matching: Vec<_> = machines
+        .iter()
+        .filter(|(id, _)| pattern.matches(id))
+        .map(|(_, metadata)| metadata)
+        .collect();
+    if matching.is_empty() {
+        return if deploy.machine.is_empty() {
+            "no machine is connected".to_owned()
+        } else {
+            format!("no connected machine matches `{}`", deploy.machine)
+        };
+    }
+    let scope = if deploy.machine.is_empty() {
+        "no connected machine".to_owned()
+    } else {
+        format!("no connected machine matching `{}`", deploy.machine)
+    };
+    let missing: Vec<_> = deploy
+        .requires
+        .iter()
+        .filter(|label| !matching.iter().any(|m| m.satisfies(label)))
+        .map(|label| format!("`{label}`"))
+        .collect();
+    if missing.is_empty() {
+        let required: Vec<_> = deploy.requires.iter().map(|l| format!("`{l}`")).collect();
+        format!("{scope} has all of the labels {}", required.join(", "))
+    } else {
+        format!("{scope} has the label {}", missing.join(", "))
+    }
+}
+
+/// Uses zenoh only if all daemons of the dataflow prefer it, and TCP otherwise.
+fn negotiate_transport(
+    machines: &BTreeSet<String>,
+    daemon_connections: &HashMap<String, DaemonConnection>,
+) -> InterDaemonTransport {
+    let all_zenoh = machines.iter().all(|m| {
+        daemon_connections
+            .get(m)
+            .map(|c| c.inter_daemon_transport == InterDaemonTransport::Zenoh)
+            .unwrap_or(false)
+    });
+    if all_zenoh {
+        InterDaemonTransport::Zenoh
+    } else {
+        InterDaemonTransport::Tcp
+    }
 }
 
 async fn spawn_dataflow_on_machine(
     daemon_connections: &mut HashMap<String, DaemonConnection>,
     machine: &str,
     message: &[u8],
-) -> Result<(), eyre::ErrReport> {
+) -> eyre::Result<BTreeMap<NodeId, PathBuf>> {
     let daemon_connection = daemon_connections
         .get_mut(machine)
         .wrap_err_with(|| format!("no daemon connection for machine `{machine}`"))?;
@@ -98,14 +383,221 @@ async fn spawn_dataflow_on_machine(
     {
         DaemonCoordinatorReply::SpawnResult(result) => result
             .map_err(|e| eyre!(e))
-            .wrap_err("daemon returned an error")?,
+            .wrap_err("daemon returned an error"),
+        _ => bail!("unexpected reply"),
+    }
+}
+
+/// Stops the nodes that the given machines started for a dataflow whose
+/// spawn failed on another machine.
+async fn stop_spawned_machines(
+    dataflow_id: Uuid,
+    machines: &[String],
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    clock: &HLC,
+) {
+    for machine in machines {
+        let result =
+            stop_dataflow_on_machine(dataflow_id, machine, daemon_connections, clock).await;
+        match result {
+            Ok(()) => {
+                tracing::info!("stopped dataflow `{dataflow_id}` on machine `{machine}` after failed spawn")
+            }
+            Err(err) => tracing::warn!(
+                "failed to stop dataflow `{dataflow_id}` on machine `{machine}` after failed spawn: {err:?}"
+            ),
+        }
+    }
+}
+
+async fn stop_dataflow_on_machine(
+    dataflow_id: Uuid,
+    machine: &str,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    clock: &HLC,
+) -> eyre::Result<()> {
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::StopDataflow {
+            dataflow_id,
+            grace_duration: None,
+        },
+        timestamp: clock.new_timestamp(),
+    })?;
+    let daemon_connection = daemon_connections
+        .get_mut(machine)
+        .wrap_err_with(|| format!("no daemon connection for machine `{machine}`"))?;
+    tcp_send(&mut daemon_connection.stream, &message)
+        .await
+        .wrap_err("failed to send stop message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive stop reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize stop reply from daemon")?
+    {
+        DaemonCoordinatorReply::StopResult(result) => result
+            .map_err(|e| eyre!(e))
+            .wrap_err("daemon returned an error"),
+        _ => bail!("unexpected reply"),
+    }
+}
+
+async fn plan_dataflow_on_machine(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine: &str,
+    message: &[u8],
+) -> eyre::Result<MachinePlan> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine)
+        .wrap_err_with(|| format!("no daemon connection for machine `{machine}`"))?;
+    tcp_send(&mut daemon_connection.stream, message)
+        .await
+        .wrap_err("failed to send plan message to daemon")?;
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive plan reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize plan reply from daemon")?
+    {
+        DaemonCoordinatorReply::PlanResult(result) => result
+            .map_err(|e| eyre!(e))
+            .wrap_err("daemon returned an error"),
         _ => bail!("unexpected reply"),
     }
-    Ok(())
 }
 
 pub struct SpawnedDataflow {
     pub uuid: Uuid,
     pub machines: BTreeSet<String>,
     pub nodes: Vec<ResolvedNode>,
+    /// Machines that were picked by the coordinator, see [`assign_machines`].
+    pub assignments: BTreeMap<NodeId, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_message::daemon_to_coordinator::parse_label;
+
+    fn machine(labels: &[&str]) -> MachineMetadata {
+        let labels = labels
+            .iter()
+            .map(|label| {
+                let (key, value) = parse_label(label);
+                (key.to_owned(), value.to_owned())
+            })
+            .collect();
+        MachineMetadata::local(None, labels)
+    }
+
+    fn nodes(yaml: &str) -> Vec<ResolvedNode> {
+        Descriptor::parse(yaml.as_bytes().to_vec())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap()
+    }
+
+    #[test]
+    fn nodes_are_spread_over_matching_machines() {
+        let (robot, gpu) = (machine(&[]), machine(&["gpu"]));
+        let machines: BTreeMap<&str, &MachineMetadata> = [
+            ("robot", &robot),
+            ("jetson-1", &gpu),
+            ("jetson-2", &gpu),
+            ("server", &gpu),
+        ]
+        .into();
+        let yaml = r#"
+_unstable_deploy:
+  requires: [gpu]
+  prefer: jetson-*
+nodes:
+  - id: camera
+    path: camera
+    _unstable_deploy:
+      machine: robot
+      requires: []
+  - id: detector-a
+    path: detector
+  - id: detector-b
+    path: detector
+  - id: detector-c
+    path: detector
+  - id: trainer
+    path: trainer
+    _unstable_deploy:
+      prefer: server
+"#;
+        let node_counts: BTreeMap<_, _> = [("jetson-1".to_owned(), 1)].into();
+        let mut first = nodes(yaml);
+        let assignments = assign_machines(&mut first, &machines, node_counts.clone()).unwrap();
+        let expected: BTreeMap<_, _> = [
+            ("detector-a", "jetson-2"),
+            ("detector-b", "jetson-1"),
+            ("detector-c", "jetson-2"),
+            ("trainer", "server"),
+        ]
+        .into_iter()
+        .map(|(node, machine)| (NodeId::from(node.to_owned()), machine.to_owned()))
+        .collect();
+        assert_eq!(assignments, expected);
+        assert_eq!(first[0].deploy.machine, "robot");
+        assert_eq!(first[1].deploy.machine, "jetson-2");
+
+        // same machines -> same assignment
+        let mut second = nodes(yaml);
+        assert_eq!(
+            assign_machines(&mut second, &machines, node_counts).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn unmet_constraints_are_listed() {
+        let (robot, gpu) = (machine(&["camera=usb"]), machine(&["gpu"]));
+        let machines: BTreeMap<&str, &MachineMetadata> =
+            [("robot", &robot), ("server", &gpu)].into();
+        let mut nodes = nodes(
+            r#"
+nodes:
+  - id: camera
+    path: camera
+    _unstable_deploy:
+      requires: [camera=usb]
+  - id: lidar
+    path: lidar
+    _unstable_deploy:
+      requires: [lidar]
+  - id: detector
+    path: detector
+    _unstable_deploy:
+      machine: robot*
+      requires: [gpu]
+  - id: planner
+    path: planner
+    _unstable_deploy:
+      machine: jetson-*
+"#,
+        );
+        let err = assign_machines(&mut nodes, &machines, BTreeMap::new())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("of 3 node(s)"), "{err}");
+        assert!(
+            err.contains("node `lidar`: no connected machine has the label `lidar`"),
+            "{err}"
+        );
+        assert!(
+            err.contains(
+                "node `detector`: no connected machine matching `robot*` has the label `gpu`"
+            ),
+            "{err}"
+        );
+        assert!(
+            err.contains("node `planner`: no connected machine matches `jetson-*`"),
+            "{err}"
+        );
+        // nothing is assigned if any constraint can't be met
+        assert!(nodes[0].deploy.machine.is_empty());
+    }
 }
@@ -0,0 +1,45 @@
+use std::time::{Duration, Instant};
+
+use dora_message::coordinator_to_cli::TappedMessage;
+use eyre::{Context, ContextCompat};
+use uuid::Uuid;
+
+use crate::tcp_utils::tcp_send;
+
+/// Time after the end of a tap until the connection is closed, even if the
+/// daemon didn't report the tap as finished.
+const FINISH_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Client connection of a `dora tap` request.
+pub struct TapSubscriber {
+    pub id: Uuid,
+    until: Instant,
+    connection: Option<tokio::net::TcpStream>,
+}
+
+impl TapSubscriber {
+    pub fn new(id: Uuid, duration: Duration, connection: tokio::net::TcpStream) -> Self {
+        Self {
+            id,
+            until: Instant::now() + duration,
+            connection: Some(connection),
+        }
+    }
+
+    pub async fn send_message(&mut self, message: &TappedMessage) -> eyre::Result<()> {
+        let message = serde_json::to_vec(&message)?;
+        let connection = self.connection.as_mut().context("connection is closed")?;
+        tcp_send(connection, &message)
+            .await
+            .context("failed to send message")?;
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_none() || self.until.elapsed() > FINISH_GRACE_PERIOD
+    }
+
+    pub fn close(&mut self) {
+        self.connection = None;
+    }
+}
@@ -0,0 +1,29 @@
+use std::{path::Path, process::Command};
+
+fn main() {
+    // embed the git commit so that the daemon status identifies the build
+    if let Some(hash) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=DORA_GIT_HASH={hash}");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        // `logs/HEAD` is updated on every commit and checkout
+        for file in ["HEAD", "logs/HEAD"] {
+            let path = Path::new(&git_dir).join(file);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+}
+
+/// Runs the given git command, returns `None` if git is not available or
+/// the crate is not built from a git checkout.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_owned())
+}
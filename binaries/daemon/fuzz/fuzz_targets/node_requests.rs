@@ -0,0 +1,11 @@
+//! Feeds arbitrary bytes to the decoder for node connections.
+//!
+//! Run with `cargo +nightly fuzz run node_requests` from `binaries/daemon`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    dora_daemon::fuzz::decode_node_requests(data);
+});
@@ -0,0 +1,326 @@
+//! Adaptive downsampling of inputs whose receiver can't keep up.
+//!
+//! Inputs with `adaptive: { min_rate: .. }` are checked on every message
+//! before it is enqueued. The controller counts the messages that it lets
+//! through and the messages that are later dropped from the full input queue
+//! in fixed windows. After [`PRESSURE_WINDOWS`] windows with drops, it only
+//! delivers every n-th message, with `n` chosen so that the delivered rate
+//! matches the rate at which the receiver took messages from its queue.
+//!
+//! Once no messages were dropped for a number of windows, `n` is lowered by
+//! one. If drops start again right after such a step, the number of calm
+//! windows before the next step is doubled, so that a receiver that is just
+//! too slow for the next rate doesn't make the input oscillate.
+
+use dora_core::config::{Adaptive, Rate};
+use dora_message::diagnostics::AdaptiveInputStats;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(1);
+/// Fraction of the delivered messages that need to be dropped from the
+/// queue for a window to count as overloaded.
+const DROP_THRESHOLD: f64 = 0.05;
+/// Consecutive overloaded windows before the rate is reduced.
+const PRESSURE_WINDOWS: u32 = 2;
+/// Calm windows before the rate is raised again, doubled on every failed
+/// step up to [`MAX_RECOVERY_WINDOWS`].
+const RECOVERY_WINDOWS: u32 = 4;
+const MAX_RECOVERY_WINDOWS: u32 = 64;
+
+#[derive(Debug)]
+pub struct AdaptiveRate {
+    min_rate: f64,
+    keep_every: u64,
+    /// Number of messages to skip until the next message is kept.
+    skip: u64,
+    window_start: Instant,
+    /// Messages of the current window, before and after downsampling.
+    offered: u64,
+    passed: u64,
+    queue_drops: u64,
+    pressure_windows: u32,
+    calm_windows: u32,
+    recovery_windows: u32,
+    /// Whether the rate was raised and no window since then was overloaded.
+    raised: bool,
+    stats: AdaptiveInputStats,
+}
+
+/// A change of the downsampling, as returned by [`AdaptiveRate::update`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateChange {
+    /// The receiver couldn't keep up, so the rate was reduced.
+    Reduced { keep_every: u64, drop_ratio: f64 },
+    /// No messages were dropped for a while, so the rate was raised.
+    Raised { keep_every: u64 },
+}
+
+impl AdaptiveRate {
+    pub fn new(config: &Adaptive, now: Instant) -> Self {
+        Self {
+            min_rate: config.min_rate.hertz(),
+            keep_every: 1,
+            skip: 0,
+            window_start: now,
+            offered: 0,
+            passed: 0,
+            queue_drops: 0,
+            pressure_windows: 0,
+            calm_windows: 0,
+            recovery_windows: RECOVERY_WINDOWS,
+            raised: false,
+            stats: AdaptiveInputStats {
+                keep_every: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Checks whether the next message should be delivered.
+    pub fn check(&mut self) -> bool {
+        self.offered += 1;
+        if self.skip > 0 {
+            self.skip -= 1;
+            self.stats.downsampled += 1;
+            return false;
+        }
+        self.skip = self.keep_every - 1;
+        self.passed += 1;
+        true
+    }
+
+    /// Records messages that were dropped from the full input queue.
+    pub fn record_queue_drops(&mut self, count: u64) {
+        self.queue_drops += count;
+    }
+
+    /// Closes the current window if it is over and returns the resulting
+    /// change of the rate, if any.
+    pub fn update(&mut self, now: Instant) -> Option<RateChange> {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < WINDOW {
+            return None;
+        }
+        let offered_rate = self.offered as f64 / elapsed.as_secs_f64();
+        let passed = self.passed;
+        // drops of messages of the previous window might be reported late
+        let drops = self.queue_drops.min(passed);
+        self.window_start = now;
+        self.offered = 0;
+        self.passed = 0;
+        self.queue_drops = 0;
+        if passed == 0 {
+            return None;
+        }
+
+        let drop_ratio = drops as f64 / passed as f64;
+        if drop_ratio > DROP_THRESHOLD {
+            self.calm_windows = 0;
+            if self.raised {
+                // the receiver can't keep up with the raised rate, so wait
+                // longer before trying again
+                self.raised = false;
+                self.recovery_windows = (self.recovery_windows * 2).min(MAX_RECOVERY_WINDOWS);
+            }
+            self.pressure_windows += 1;
+            if self.pressure_windows < PRESSURE_WINDOWS {
+                return None;
+            }
+            self.pressure_windows = 0;
+            let consumed = passed - drops;
+            let target =
+                ((self.keep_every * passed) as f64 / consumed.max(1) as f64).round() as u64;
+            let max_keep_every = ((offered_rate / self.min_rate).floor() as u64).max(1);
+            let keep_every = target.min(max_keep_every);
+            if keep_every <= self.keep_every {
+                return None;
+            }
+            self.set_keep_every(keep_every);
+            self.stats.reductions += 1;
+            Some(RateChange::Reduced {
+                keep_every,
+                drop_ratio,
+            })
+        } else {
+            self.pressure_windows = 0;
+            self.calm_windows += 1;
+            if self.raised && self.calm_windows >= PRESSURE_WINDOWS {
+                // the receiver keeps up with the raised rate
+                self.raised = false;
+                self.recovery_windows = RECOVERY_WINDOWS;
+            }
+            if self.keep_every == 1 || self.calm_windows < self.recovery_windows {
+                return None;
+            }
+            self.calm_windows = 0;
+            self.raised = true;
+            let keep_every = self.keep_every - 1;
+            self.set_keep_every(keep_every);
+            self.stats.raises += 1;
+            Some(RateChange::Raised { keep_every })
+        }
+    }
+
+    fn set_keep_every(&mut self, keep_every: u64) {
+        self.keep_every = keep_every;
+        self.skip = 0;
+        self.stats.keep_every = keep_every;
+    }
+
+    /// Changes the rate below which the input is not downsampled. The
+    /// current downsampling is kept, the new rate limits the next reductions.
+    pub fn set_min_rate(&mut self, min_rate: Rate) {
+        self.min_rate = min_rate.hertz();
+    }
+
+    pub fn stats(&self) -> AdaptiveInputStats {
+        self.stats
+    }
+}
+
+impl fmt::Display for RateChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateChange::Reduced {
+                keep_every,
+                drop_ratio,
+            } => write!(
+                f,
+                "receiver can't keep up ({:.0}% of the messages were dropped from its queue), \
+                delivering 1 of every {keep_every} messages",
+                drop_ratio * 100.0
+            ),
+            RateChange::Raised { keep_every: 1 } => f.write_str("back at full rate"),
+            RateChange::Raised { keep_every } => {
+                write!(f, "raised rate to 1 of every {keep_every} messages")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 30);
+    const QUEUE_SIZE: usize = 2;
+
+    /// A 30Hz source and a receiver that takes one message per interval.
+    struct Simulation {
+        controller: AdaptiveRate,
+        now: Instant,
+        queue: usize,
+        next_take: Instant,
+        delivered: u64,
+        dropped: u64,
+        changes: Vec<(Duration, RateChange)>,
+        start: Instant,
+    }
+
+    impl Simulation {
+        fn new() -> Self {
+            let start = Instant::now();
+            let config = Adaptive {
+                min_rate: "5Hz".parse().unwrap(),
+            };
+            Self {
+                controller: AdaptiveRate::new(&config, start),
+                now: start,
+                queue: 0,
+                next_take: start,
+                delivered: 0,
+                dropped: 0,
+                changes: Vec::new(),
+                start,
+            }
+        }
+
+        fn run(&mut self, duration: Duration, receiver_interval: Duration) {
+            let end = self.now + duration;
+            while self.now < end {
+                self.now += SOURCE_INTERVAL;
+                while self.next_take <= self.now {
+                    self.queue = self.queue.saturating_sub(1);
+                    self.next_take += receiver_interval;
+                }
+                if let Some(change) = self.controller.update(self.now) {
+                    self.changes.push((self.now - self.start, change));
+                }
+                if self.controller.check() {
+                    self.delivered += 1;
+                    self.queue += 1;
+                    if self.queue > QUEUE_SIZE {
+                        self.queue -= 1;
+                        self.dropped += 1;
+                        self.controller.record_queue_drops(1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn slow_receiver_settles_without_oscillation() {
+        let mut sim = Simulation::new();
+        let slow = Duration::from_millis(100);
+
+        sim.run(Duration::from_secs(10), slow);
+        assert!(
+            matches!(
+                sim.changes.first(),
+                Some((_, RateChange::Reduced { keep_every: 3, .. }))
+            ),
+            "{:?}",
+            sim.changes
+        );
+
+        // the rate is only raised to probe the receiver, with growing pauses
+        let (delivered, dropped) = (sim.delivered, sim.dropped);
+        sim.run(Duration::from_secs(110), slow);
+        let changes: Vec<_> = sim.changes.iter().map(|(_, change)| *change).collect();
+        assert!(changes.len() <= 10, "{:?}", sim.changes);
+        assert!(changes.iter().all(|change| matches!(
+            change,
+            RateChange::Reduced { keep_every: 3, .. }
+        ) || *change == RateChange::Raised { keep_every: 2 }));
+        let pauses: Vec<_> = sim
+            .changes
+            .windows(2)
+            .filter(|w| matches!(w[0].1, RateChange::Reduced { .. }))
+            .map(|w| w[1].0 - w[0].0)
+            .collect();
+        assert!(pauses.windows(2).all(|p| p[1] > p[0]), "{:?}", sim.changes);
+        let dropped = sim.dropped - dropped;
+        let delivered = sim.delivered - delivered;
+        assert!(dropped * 20 < delivered, "{dropped} of {delivered} dropped");
+        assert_eq!(sim.controller.stats().keep_every, 3);
+
+        // full rate once the receiver is fast enough again
+        sim.changes.clear();
+        sim.run(Duration::from_secs(120), Duration::from_millis(25));
+        assert_eq!(
+            sim.changes.last().map(|(_, change)| *change),
+            Some(RateChange::Raised { keep_every: 1 }),
+            "{:?}",
+            sim.changes
+        );
+        assert!(sim
+            .changes
+            .iter()
+            .all(|(_, change)| matches!(change, RateChange::Raised { .. })));
+        let stats = sim.controller.stats();
+        assert_eq!(stats.keep_every, 1);
+        assert!(stats.downsampled > 0);
+    }
+
+    #[test]
+    fn rate_is_not_reduced_below_min_rate() {
+        let mut sim = Simulation::new();
+        sim.run(Duration::from_secs(10), Duration::from_secs(1));
+        // 30Hz at a min rate of 5Hz
+        assert_eq!(sim.controller.stats().keep_every, 6);
+    }
+}
@@ -0,0 +1,735 @@
+//! Runs the daemon as a library, e.g. embedded in a larger application that
+//! spawns and stops dataflows itself, without a coordinator.
+//!
+//! The daemon runs on a task of its own. The [`DaemonHandle`] sends commands
+//! to it through the same event loop that processes coordinator events, and
+//! reports the results of nodes and dataflows as [`DaemonNotification`]s.
+
+use crate::{
+    coordinator::{self, CoordinatorEvent},
+    inter_daemon,
+    join_tokens::JoinTokens,
+    journal::JournalConfig,
+    local_listener, set_up_ctrlc_handler,
+    stall::StallConfig,
+    udp_transport::{self, UdpTransport, DEFAULT_UDP_DATAGRAM_SIZE},
+    ConnectionLimits, Daemon, DaemonPaths, DaemonPathsConfig, DaemonRunResult, Event,
+    ListenAddresses, NodeConnections, NodeRegistry, NodeRegistryConfig,
+    DEFAULT_DROP_WARNING_INTERVAL,
+};
+use dora_core::{
+    config::NodeId,
+    descriptor::Descriptor,
+    topics::{DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT, LOCALHOST},
+    uhlc::HLC,
+};
+use dora_message::{
+    common::NodeError,
+    coordinator_to_daemon::{DaemonCoordinatorEvent, SpawnDataflowNodes},
+    daemon_to_coordinator::{DaemonCoordinatorReply, DataflowDaemonResult, MachineMetadata},
+    daemon_to_daemon::InterDaemonTransport,
+    node_to_daemon::Timestamped,
+    DataflowId,
+};
+use eyre::{bail, eyre, Context};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinHandle,
+};
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream};
+use uuid::{NoContext, Timestamp, Uuid};
+
+/// Number of notifications that are kept for slow subscribers. Older
+/// notifications are skipped.
+const NOTIFICATION_CAPACITY: usize = 1024;
+
+/// Command that an embedding application sends to the daemon through
+/// [`DaemonBuilder::external_events`].
+#[derive(Debug, Clone)]
+pub enum DaemonCommand {
+    StopDataflow {
+        dataflow_id: DataflowId,
+        /// Time that the nodes get to stop before they are killed.
+        grace_duration: Option<Duration>,
+    },
+    /// Restarts a single node of a running dataflow.
+    ReloadNode {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+}
+
+/// Event that the daemon reports to the subscribers of
+/// [`DaemonHandle::subscribe_events`].
+#[derive(Debug, Clone)]
+pub enum DaemonNotification {
+    /// A local node of the dataflow exited.
+    NodeFinished {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        result: Result<(), NodeError>,
+    },
+    /// All local nodes of the dataflow exited.
+    DataflowFinished {
+        dataflow_id: DataflowId,
+        result: DataflowDaemonResult,
+    },
+}
+
+/// Configures and starts a daemon.
+///
+/// Without a coordinator, the daemon only runs the dataflows that are spawned
+/// through its [`DaemonHandle`], and it doesn't listen for other daemons or
+/// dynamic nodes.
+///
+/// ```no_run
+/// # async fn example() -> eyre::Result<()> {
+/// use dora_daemon::DaemonBuilder;
+///
+/// let daemon = DaemonBuilder::new().machine_id("robot").build().await?;
+/// let descriptor = dora_core::descriptor::Descriptor::read("dataflow.yml".as_ref()).await?;
+/// let dataflow_id = daemon.spawn_dataflow(descriptor, ".".as_ref()).await?;
+/// // ...
+/// daemon.stop_dataflow(dataflow_id).await?;
+/// daemon.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DaemonBuilder {
+    machine_id: String,
+    coordinator: Option<SocketAddr>,
+    coordinator_timeout: Option<Duration>,
+    machine_token: Option<String>,
+    inter_daemon_addr: SocketAddr,
+    inter_daemon_transport: InterDaemonTransport,
+    udp_datagram_size: usize,
+    local_listen_interface: IpAddr,
+    local_listen_port: u16,
+    labels: BTreeMap<String, String>,
+    journal: Option<JournalConfig>,
+    drop_warning_interval: Duration,
+    stall_detection: Option<StallConfig>,
+    default_working_dir: Option<PathBuf>,
+    connection_limits: ConnectionLimits,
+    node_registry: NodeRegistryConfig,
+    paths: Option<DaemonPathsConfig>,
+    handle_ctrlc: bool,
+    strict_shutdown: bool,
+    external_events: Option<BoxStream<'static, DaemonCommand>>,
+}
+
+impl Default for DaemonBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DaemonBuilder {
+    pub fn new() -> Self {
+        Self {
+            machine_id: String::new(),
+            coordinator: None,
+            coordinator_timeout: None,
+            machine_token: None,
+            inter_daemon_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            inter_daemon_transport: InterDaemonTransport::Tcp,
+            udp_datagram_size: DEFAULT_UDP_DATAGRAM_SIZE,
+            local_listen_interface: LOCALHOST,
+            local_listen_port: DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT,
+            labels: BTreeMap::new(),
+            journal: None,
+            drop_warning_interval: DEFAULT_DROP_WARNING_INTERVAL,
+            stall_detection: Some(StallConfig::default()),
+            default_working_dir: None,
+            connection_limits: ConnectionLimits::default(),
+            node_registry: NodeRegistryConfig {
+                path: None,
+                adopt_orphans: false,
+            },
+            paths: None,
+            handle_ctrlc: false,
+            strict_shutdown: false,
+            external_events: None,
+        }
+    }
+
+    pub fn machine_id(mut self, machine_id: impl Into<String>) -> Self {
+        self.machine_id = machine_id.into();
+        self
+    }
+
+    /// Address of the coordinator to register at, or `None` to run without
+    /// coordinator (the default).
+    pub fn coordinator(mut self, addr: Option<SocketAddr>) -> Self {
+        self.coordinator = addr;
+        self
+    }
+
+    /// Gives up if the coordinator is not reachable within the given time.
+    pub fn coordinator_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.coordinator_timeout = timeout;
+        self
+    }
+
+    /// Token to register at the coordinator with, if it requires one.
+    pub fn machine_token(mut self, token: Option<String>) -> Self {
+        self.machine_token = token;
+        self
+    }
+
+    /// Address that other daemons connect to. Only used with a coordinator.
+    pub fn inter_daemon_addr(mut self, addr: SocketAddr) -> Self {
+        self.inter_daemon_addr = addr;
+        self
+    }
+
+    pub fn inter_daemon_transport(mut self, transport: InterDaemonTransport) -> Self {
+        self.inter_daemon_transport = transport;
+        self
+    }
+
+    /// Maximum size of the UDP datagrams of best-effort outputs, see
+    /// `remote_transport` in the dataflow descriptor.
+    ///
+    /// Lower it if datagrams get lost because the MTU of the network between
+    /// the machines is smaller than usual, e.g. because of a VPN.
+    pub fn udp_datagram_size(mut self, size: usize) -> Self {
+        self.udp_datagram_size = size;
+        self
+    }
+
+    /// Port of the listener for dynamic nodes. Only used with a coordinator.
+    pub fn local_listen_port(mut self, port: u16) -> Self {
+        self.local_listen_port = port;
+        self
+    }
+
+    /// Interface of the listener for dynamic nodes, `127.0.0.1` by default.
+    ///
+    /// With a non-loopback interface, external nodes and observers on other
+    /// machines can connect with the join tokens that the coordinator hands
+    /// out for `ResolveNode` requests.
+    pub fn local_listen_interface(mut self, interface: IpAddr) -> Self {
+        self.local_listen_interface = interface;
+        self
+    }
+
+    /// Labels of the machine that are reported to the coordinator.
+    pub fn labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn journal(mut self, journal: Option<JournalConfig>) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    pub fn drop_warning_interval(mut self, interval: Duration) -> Self {
+        self.drop_warning_interval = interval;
+        self
+    }
+
+    /// Warns about dataflows that have running nodes, but no message flow.
+    ///
+    /// Enabled with the default [`StallConfig`] unless set to `None`.
+    pub fn stall_detection(mut self, config: Option<StallConfig>) -> Self {
+        self.stall_detection = config;
+        self
+    }
+
+    /// Working directory for dataflows spawned by the coordinator, instead
+    /// of the working directory of the machine that submitted the dataflow.
+    pub fn default_working_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.default_working_dir = dir;
+        self
+    }
+
+    pub fn connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connection_limits = limits;
+        self
+    }
+
+    /// Configures the registry of spawned nodes. Only used with a coordinator.
+    pub fn node_registry(mut self, config: NodeRegistryConfig) -> Self {
+        self.node_registry = config;
+        self
+    }
+
+    /// Directories for state, cache, and log files.
+    ///
+    /// If not set, the same defaults as for `Daemon::run_dataflow` are used.
+    pub fn paths(mut self, paths: DaemonPathsConfig) -> Self {
+        self.paths = Some(paths);
+        self
+    }
+
+    /// Stops all dataflows on ctrl-c.
+    ///
+    /// Disabled by default, as this installs a process-wide signal handler.
+    pub fn handle_ctrlc(mut self, enabled: bool) -> Self {
+        self.handle_ctrlc = enabled;
+        self
+    }
+
+    /// Checks on exit that all dataflows, drop tokens, registered nodes, and
+    /// shared memory regions of the daemon were cleaned up.
+    ///
+    /// Leftovers make the daemon fail with a [`LeakReport`](crate::LeakReport).
+    /// Meant for CI, as the shared memory regions are tracked for the whole
+    /// lifetime of the daemon.
+    pub fn strict_shutdown(mut self, enabled: bool) -> Self {
+        self.strict_shutdown = enabled;
+        self
+    }
+
+    /// Commands that the embedding application sends to the daemon, e.g.
+    /// when a stop button is pressed.
+    pub fn external_events(
+        mut self,
+        events: impl Stream<Item = DaemonCommand> + Send + 'static,
+    ) -> Self {
+        self.external_events = Some(events.boxed());
+        self
+    }
+
+    /// Starts the daemon on a new task.
+    ///
+    /// With a coordinator, this waits until the daemon is registered. If
+    /// ctrl-c is pressed before, the returned daemon has exited already.
+    pub async fn build(self) -> eyre::Result<DaemonHandle> {
+        if self.inter_daemon_transport == InterDaemonTransport::Zenoh && !cfg!(feature = "zenoh") {
+            bail!("zenoh transport requested, but dora-daemon was built without `zenoh` feature");
+        }
+        udp_transport::check_datagram_size(self.udp_datagram_size)?;
+        let default_working_dir = self
+            .default_working_dir
+            .map(|dir| {
+                dir.canonicalize().wrap_err_with(|| {
+                    format!("default working dir `{}` does not exist", dir.display())
+                })
+            })
+            .transpose()?;
+        let paths = match self.paths {
+            Some(config) => DaemonPaths::open(config)?,
+            None => DaemonPaths::default(),
+        };
+        let clock = Arc::new(HLC::default());
+        let (notifications, _) = broadcast::channel(NOTIFICATION_CAPACITY);
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+
+        let mut events: Vec<BoxStream<'static, Timestamped<Event>>> =
+            vec![UnboundedReceiverStream::new(events_rx).boxed()];
+        let mut ctrlc_events = match self.handle_ctrlc {
+            true => Some(set_up_ctrlc_handler(clock.clone())?),
+            false => None,
+        };
+        if let Some(commands) = self.external_events {
+            let clock = clock.clone();
+            events.push(
+                commands
+                    .map(move |command| command_event(command, &clock))
+                    .boxed(),
+            );
+        }
+
+        let node_connections = NodeConnections::new(self.connection_limits);
+        let join_tokens = JoinTokens::default();
+        let mut listen_addresses = None;
+        let mut udp = None;
+        let mut registry = None;
+        if let Some(coordinator_addr) = self.coordinator {
+            let NodeRegistryConfig {
+                path: registry_path,
+                adopt_orphans,
+            } = self.node_registry;
+            registry = match registry_path.or_else(|| paths.node_registry(&self.machine_id)) {
+                Some(path) => Some(
+                    tokio::task::spawn_blocking(move || NodeRegistry::open(path, adopt_orphans))
+                        .await
+                        .wrap_err("failed to join node registry task")?
+                        .wrap_err("failed to open node registry")?,
+                ),
+                None => {
+                    tracing::warn!(
+                        "node registry is disabled because there is no writable state dir, so \
+                        nodes of previous daemon runs are not detected"
+                    );
+                    None
+                }
+            };
+
+            // spawn inter daemon listen loop
+            let (daemon_tx, daemon_rx) = flume::bounded(10);
+            let listen_port = inter_daemon::spawn_listener_loop(
+                self.inter_daemon_addr,
+                self.machine_id.clone(),
+                daemon_tx.clone(),
+            )
+            .await?;
+            // other daemons send the datagrams of best-effort outputs to the
+            // same port number
+            let udp_addr = SocketAddr::new(self.inter_daemon_addr.ip(), listen_port);
+            udp = match UdpTransport::bind(udp_addr, self.udp_datagram_size, daemon_tx).await {
+                Ok(udp) => Some(udp),
+                Err(err) => {
+                    tracing::warn!(
+                        "failed to set up UDP transport, so best-effort outputs are sent \
+                        over TCP and best-effort outputs of other machines are not \
+                        received: {err:?}"
+                    );
+                    None
+                }
+            };
+            events.push(
+                daemon_rx
+                    .into_stream()
+                    .map(|e| Timestamped {
+                        inner: Event::Daemon(e.inner),
+                        timestamp: e.timestamp,
+                    })
+                    .boxed(),
+            );
+
+            // Spawn local listener loop
+            let (local_tx, local_rx) = flume::bounded(10);
+            let interface = self.local_listen_interface;
+            let local_listen_port = local_listener::spawn_listener_loop(
+                (interface, self.local_listen_port).into(),
+                self.machine_id.clone(),
+                local_tx,
+                node_connections.clone(),
+                join_tokens.clone(),
+            )
+            .await?;
+            // the spawned nodes connect through the loopback interface if the
+            // listener is bound to all interfaces
+            let local_ip = match interface.is_unspecified() {
+                true => LOCALHOST,
+                false => interface,
+            };
+            listen_addresses = Some(ListenAddresses {
+                inter_daemon: (self.inter_daemon_addr.ip(), listen_port).into(),
+                local: (local_ip, local_listen_port).into(),
+                exposed_local: (!interface.is_loopback())
+                    .then(|| (interface, local_listen_port).into()),
+            });
+            events.push(local_rx.into_stream().boxed());
+
+            // connect to the coordinator, which might not be started yet
+            let register = coordinator::register(
+                coordinator_addr,
+                self.machine_id.clone(),
+                listen_port,
+                self.inter_daemon_transport,
+                MachineMetadata::local(sysinfo::System::host_name(), self.labels),
+                self.machine_token.clone(),
+                &clock,
+                self.coordinator_timeout,
+            );
+            let coordinator_events = match &mut ctrlc_events {
+                Some(ctrlc_events) => tokio::select! {
+                    result = register => result.wrap_err("failed to connect to dora-coordinator")?,
+                    Some(_) = ctrlc_events.next() => {
+                        tracing::info!("stopping before dora-coordinator was reachable");
+                        return Ok(DaemonHandle {
+                            events: events_tx,
+                            notifications,
+                            clock,
+                            task: tokio::spawn(async { Ok(DaemonRunResult::new()) }),
+                        });
+                    }
+                },
+                None => register
+                    .await
+                    .wrap_err("failed to connect to dora-coordinator")?,
+            };
+            events.push(
+                coordinator_events
+                    .map(
+                        |Timestamped {
+                             inner: event,
+                             timestamp,
+                         }| Timestamped {
+                            inner: Event::Coordinator(event),
+                            timestamp,
+                        },
+                    )
+                    .boxed(),
+            );
+        }
+        if let Some(ctrlc_events) = ctrlc_events {
+            events.push(ctrlc_events.boxed());
+        }
+
+        let config = DaemonConfig {
+            coordinator_addr: self.coordinator,
+            machine_id: self.machine_id,
+            machine_token: self.machine_token,
+            exit_when_done: None,
+            listen_addresses,
+            udp,
+            journal: self.journal,
+            registry,
+            drop_warning_interval: self.drop_warning_interval,
+            stall_detection: self.stall_detection,
+            default_working_dir,
+            paths,
+            node_connections,
+            clock: clock.clone(),
+            notifications: Some(notifications.clone()),
+            strict_shutdown: self.strict_shutdown,
+            join_tokens,
+        };
+        let task = tokio::spawn(Daemon::run_general(stream::select_all(events), config));
+        Ok(DaemonHandle {
+            events: events_tx,
+            notifications,
+            clock,
+            task,
+        })
+    }
+}
+
+/// Settings of a daemon run, as resolved by [`DaemonBuilder::build`].
+pub(crate) struct DaemonConfig {
+    pub coordinator_addr: Option<SocketAddr>,
+    pub machine_id: String,
+    pub machine_token: Option<String>,
+    /// Exit once these nodes are finished, used for testing and examples.
+    pub exit_when_done: Option<BTreeSet<(Uuid, NodeId)>>,
+    /// Not set when running without coordinator.
+    pub listen_addresses: Option<ListenAddresses>,
+    pub udp: Option<UdpTransport>,
+    pub journal: Option<JournalConfig>,
+    pub registry: Option<NodeRegistry>,
+    pub drop_warning_interval: Duration,
+    pub stall_detection: Option<StallConfig>,
+    pub default_working_dir: Option<PathBuf>,
+    pub paths: DaemonPaths,
+    pub node_connections: NodeConnections,
+    pub clock: Arc<HLC>,
+    pub notifications: Option<broadcast::Sender<DaemonNotification>>,
+    pub strict_shutdown: bool,
+    pub join_tokens: JoinTokens,
+}
+
+impl DaemonConfig {
+    /// Settings of a daemon without coordinator, with all options at their
+    /// defaults.
+    pub fn new(clock: Arc<HLC>) -> Self {
+        Self {
+            coordinator_addr: None,
+            machine_id: String::new(),
+            machine_token: None,
+            exit_when_done: None,
+            listen_addresses: None,
+            udp: None,
+            journal: None,
+            registry: None,
+            drop_warning_interval: DEFAULT_DROP_WARNING_INTERVAL,
+            stall_detection: None,
+            default_working_dir: None,
+            paths: DaemonPaths::default(),
+            node_connections: NodeConnections::new(ConnectionLimits::default()),
+            clock,
+            notifications: None,
+            strict_shutdown: false,
+            join_tokens: JoinTokens::default(),
+        }
+    }
+}
+
+/// Handle of a daemon that was started through [`DaemonBuilder::build`].
+///
+/// Dropping the handle doesn't stop the daemon, use [`Self::shutdown`]
+/// instead.
+pub struct DaemonHandle {
+    events: mpsc::UnboundedSender<Timestamped<Event>>,
+    notifications: broadcast::Sender<DaemonNotification>,
+    clock: Arc<HLC>,
+    task: JoinHandle<eyre::Result<DaemonRunResult>>,
+}
+
+impl DaemonHandle {
+    /// Spawns the given dataflow on this machine and returns its ID.
+    ///
+    /// All nodes of the dataflow run on this machine, independent of their
+    /// `deploy` section. Relative paths of the dataflow are resolved against
+    /// `working_dir`.
+    pub async fn spawn_dataflow(
+        &self,
+        descriptor: Descriptor,
+        working_dir: &Path,
+    ) -> eyre::Result<DataflowId> {
+        let working_dir = working_dir
+            .canonicalize()
+            .wrap_err_with(|| format!("working dir `{}` does not exist", working_dir.display()))?;
+        let spawn_command = local_spawn_command(descriptor, working_dir, false)?;
+        let dataflow_id = spawn_command.dataflow_id;
+        self.spawn(spawn_command).await?;
+        Ok(dataflow_id)
+    }
+
+    /// Stops the nodes of the given dataflow, killing them if they don't
+    /// stop within the default grace duration.
+    ///
+    /// Returns once the stop was sent to the nodes. The dataflow is finished
+    /// once [`DaemonNotification::DataflowFinished`] is reported.
+    pub async fn stop_dataflow(&self, dataflow_id: DataflowId) -> eyre::Result<()> {
+        let event = DaemonCoordinatorEvent::StopDataflow {
+            dataflow_id,
+            grace_duration: None,
+        };
+        match self.request(event).await? {
+            Some(DaemonCoordinatorReply::StopResult(result)) => result.map_err(|err| eyre!(err)),
+            _ => bail!("unexpected stop reply"),
+        }
+    }
+
+    /// Notifications about finished nodes and dataflows, starting with the
+    /// next one.
+    ///
+    /// Notifications are skipped if the subscriber falls behind by more than
+    /// a thousand notifications. The stream ends when the daemon exits.
+    pub fn subscribe_events(
+        &self,
+    ) -> impl Stream<Item = DaemonNotification> + Send + Unpin + 'static {
+        stream::unfold(self.notifications.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(notification) => return Some((notification, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("skipped {skipped} daemon notifications of slow subscriber");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Waits until the daemon exits, e.g. because the coordinator told it to.
+    pub async fn wait(self) -> eyre::Result<()> {
+        self.join().await.map(|_| ())
+    }
+
+    /// Exits the daemon.
+    ///
+    /// Running dataflows should be stopped before, as their nodes are not
+    /// stopped.
+    pub async fn shutdown(self) -> eyre::Result<()> {
+        self.exit().await.map(|_| ())
+    }
+
+    pub(crate) async fn spawn(&self, spawn_command: SpawnDataflowNodes) -> eyre::Result<()> {
+        let event = DaemonCoordinatorEvent::Spawn(spawn_command);
+        match self.request(event).await? {
+            Some(DaemonCoordinatorReply::SpawnResult(result)) => {
+                let node_working_dirs = result.map_err(|err| eyre!(err))?;
+                for (node_id, dir) in node_working_dirs {
+                    tracing::debug!("node `{node_id}` runs in `{}`", dir.display());
+                }
+                Ok(())
+            }
+            _ => bail!("unexpected spawn reply"),
+        }
+    }
+
+    /// Exits the daemon and returns the results of the dataflows that
+    /// finished before.
+    pub(crate) async fn exit(self) -> eyre::Result<DaemonRunResult> {
+        if let Ok(Some(DaemonCoordinatorReply::DestroyResult {
+            notify: Some(notify),
+            ..
+        })) = self.request(DaemonCoordinatorEvent::Destroy).await
+        {
+            let _ = notify.send(());
+        }
+        self.join().await
+    }
+
+    async fn join(self) -> eyre::Result<DaemonRunResult> {
+        self.task.await.wrap_err("daemon task failed")?
+    }
+
+    async fn request(
+        &self,
+        event: DaemonCoordinatorEvent,
+    ) -> eyre::Result<Option<DaemonCoordinatorReply>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.events
+            .send(Timestamped {
+                inner: Event::Coordinator(CoordinatorEvent { event, reply_tx }),
+                timestamp: self.clock.new_timestamp(),
+            })
+            .map_err(|_| eyre!("daemon exited already"))?;
+        reply_rx
+            .await
+            .map_err(|_| eyre!("daemon exited before replying"))
+    }
+}
+
+/// Resolves the nodes of the given dataflow, which run on this machine only.
+pub(crate) fn local_spawn_command(
+    descriptor: Descriptor,
+    working_dir: PathBuf,
+    dry_run: bool,
+) -> eyre::Result<SpawnDataflowNodes> {
+    descriptor.check(&working_dir)?;
+    let nodes = descriptor.resolve_aliases_and_set_defaults()?;
+    Ok(SpawnDataflowNodes {
+        dataflow_id: Uuid::new_v7(Timestamp::now(NoContext)),
+        working_dir,
+        machine_working_dir: None,
+        nodes,
+        machine_listen_ports: BTreeMap::new(),
+        dataflow_descriptor: descriptor,
+        inter_daemon_transport: InterDaemonTransport::Tcp,
+        instance: None,
+        params: BTreeMap::new(),
+        dry_run,
+    })
+}
+
+/// Converts the given command into a coordinator event whose failed reply is
+/// logged.
+fn command_event(command: DaemonCommand, clock: &HLC) -> Timestamped<Event> {
+    let event = match command {
+        DaemonCommand::StopDataflow {
+            dataflow_id,
+            grace_duration,
+        } => DaemonCoordinatorEvent::StopDataflow {
+            dataflow_id,
+            grace_duration,
+        },
+        DaemonCommand::ReloadNode {
+            dataflow_id,
+            node_id,
+        } => DaemonCoordinatorEvent::ReloadNode {
+            dataflow_id,
+            node_id,
+        },
+    };
+    let (reply_tx, reply_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let error = match reply_rx.await {
+            Ok(Some(DaemonCoordinatorReply::StopResult(Err(err))))
+            | Ok(Some(DaemonCoordinatorReply::ReloadNodeResult(Err(err)))) => err,
+            _ => return,
+        };
+        tracing::warn!("external daemon command failed: {error}");
+    });
+    Timestamped {
+        inner: Event::Coordinator(CoordinatorEvent { event, reply_tx }),
+        timestamp: clock.new_timestamp(),
+    }
+}
@@ -0,0 +1,141 @@
+//! Estimation of the offset between the wall clocks of the daemon and the
+//! coordinator.
+//!
+//! The coordinator replies to each heartbeat with the time at which it
+//! received the heartbeat and the time at which it sent the reply. Together
+//! with the send and receive times of the daemon, this gives an NTP-style
+//! offset and round-trip estimate. The estimates are smoothed over multiple
+//! heartbeats to reduce the effect of network jitter.
+//!
+//! The clocks are never adjusted, the estimate is only reported.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dora_message::{coordinator_to_daemon::TimeSync, daemon_to_coordinator::ClockOffset};
+
+/// Weight of a new sample in the smoothed estimate.
+const SMOOTHING_FACTOR: f64 = 0.125;
+
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    estimate: Option<ClockOffset>,
+}
+
+impl ClockSync {
+    pub fn estimate(&self) -> Option<ClockOffset> {
+        self.estimate
+    }
+
+    /// Updates the estimate with a coordinator reply that was received at the
+    /// given wall-clock time, in nanoseconds since the Unix epoch.
+    pub fn add_sample(&mut self, sync: TimeSync, received: u64) {
+        let TimeSync {
+            daemon_sent,
+            coordinator_received,
+            coordinator_sent,
+        } = sync;
+        let [t1, t2, t3, t4] = [
+            daemon_sent,
+            coordinator_received,
+            coordinator_sent,
+            received,
+        ]
+        .map(i128::from);
+        let round_trip = (t4 - t1) - (t3 - t2);
+        if round_trip < 0 {
+            tracing::debug!("ignoring time sync sample with negative round-trip time");
+            return;
+        }
+        let offset = ((t2 - t1) + (t3 - t4)) / 2;
+        let sample = ClockOffset {
+            offset: offset.clamp(i64::MIN.into(), i64::MAX.into()) as i64,
+            round_trip: round_trip.min(u64::MAX.into()) as u64,
+        };
+
+        self.estimate = Some(match self.estimate {
+            None => sample,
+            Some(previous) => ClockOffset {
+                offset: smooth(previous.offset as f64, sample.offset as f64) as i64,
+                round_trip: smooth(previous.round_trip as f64, sample.round_trip as f64) as u64,
+            },
+        });
+    }
+}
+
+fn smooth(previous: f64, sample: f64) -> f64 {
+    previous + (sample - previous) * SMOOTHING_FACTOR
+}
+
+/// Current wall-clock time in nanoseconds since the Unix epoch.
+pub fn wall_clock_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    /// Simulates an exchange with a coordinator whose clock is `offset`
+    /// ahead, with the given one-way delays.
+    fn exchange(daemon_sent: u64, offset: i64, delay_out: u64, delay_back: u64) -> (TimeSync, u64) {
+        let coordinator_received = (daemon_sent + delay_out).saturating_add_signed(offset);
+        let coordinator_sent = coordinator_received + MS;
+        let received = (coordinator_sent + delay_back).saturating_add_signed(-offset);
+        let sync = TimeSync {
+            daemon_sent,
+            coordinator_received,
+            coordinator_sent,
+        };
+        (sync, received)
+    }
+
+    #[test]
+    fn offset_and_round_trip_are_estimated() {
+        let start = 1_700_000_000_000 * MS;
+        let offset = -250 * MS as i64;
+        let mut clock_sync = ClockSync::default();
+        assert_eq!(clock_sync.estimate(), None);
+
+        // symmetric delays give the exact offset
+        let (sync, received) = exchange(start, offset, 5 * MS, 5 * MS);
+        clock_sync.add_sample(sync, received);
+        assert_eq!(
+            clock_sync.estimate(),
+            Some(ClockOffset {
+                offset,
+                round_trip: 10 * MS
+            })
+        );
+
+        // a single asymmetric sample only moves the estimate a little
+        let (sync, received) = exchange(start + 1000 * MS, offset, 85 * MS, 5 * MS);
+        clock_sync.add_sample(sync, received);
+        let estimate = clock_sync.estimate().unwrap();
+        assert_eq!(estimate.offset, offset + 5 * MS as i64);
+        assert_eq!(estimate.round_trip, 20 * MS);
+
+        // samples with a negative round-trip time are ignored
+        let (mut sync, received) = exchange(start + 2000 * MS, offset, 5 * MS, 5 * MS);
+        sync.coordinator_sent += 100 * MS;
+        clock_sync.add_sample(sync, received);
+        assert_eq!(clock_sync.estimate(), Some(estimate));
+
+        // the estimate converges to the new offset after a clock step
+        let new_offset = 40 * MS as i64;
+        for i in 0..100 {
+            let (sync, received) = exchange(start + (3000 + i) * MS, new_offset, 5 * MS, 5 * MS);
+            clock_sync.add_sample(sync, received);
+        }
+        let estimate = clock_sync.estimate().unwrap();
+        assert!(
+            (estimate.offset - new_offset).abs() < MS as i64,
+            "{estimate:?}"
+        );
+        assert!(estimate.round_trip.abs_diff(10 * MS) < MS, "{estimate:?}");
+    }
+}
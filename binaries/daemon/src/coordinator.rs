@@ -5,11 +5,20 @@ use crate::{
 use dora_core::uhlc::HLC;
 use dora_message::{
     common::Timestamped,
+    compat::{self, Decoded},
     coordinator_to_daemon::RegisterResult,
-    daemon_to_coordinator::{CoordinatorRequest, DaemonCoordinatorReply, DaemonRegisterRequest},
+    daemon_to_coordinator::{
+        CoordinatorRequest, DaemonCoordinatorReply, DaemonRegisterRequest, MachineMetadata,
+    },
+    daemon_to_daemon::InterDaemonTransport,
 };
 use eyre::{eyre, Context};
-use std::{io::ErrorKind, net::SocketAddr};
+use rand::Rng;
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 use tokio::{
     net::TcpStream,
     sync::{mpsc, oneshot},
@@ -22,31 +31,67 @@ pub struct CoordinatorEvent {
     pub reply_tx: oneshot::Sender<Option<DaemonCoordinatorReply>>,
 }
 
+/// Delay before the first retry of a failed registration.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(250);
+/// Maximum delay between two registration attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Registers the daemon at the coordinator and returns the stream of
+/// coordinator events.
+///
+/// While the coordinator is not reachable, the registration is retried with
+/// capped exponential backoff, for at most `timeout` if set. Registrations
+/// that the coordinator rejects are not retried.
 pub async fn register(
     addr: SocketAddr,
     machine_id: String,
     listen_port: u16,
+    inter_daemon_transport: InterDaemonTransport,
+    metadata: MachineMetadata,
+    machine_token: Option<String>,
     clock: &HLC,
+    timeout: Option<Duration>,
 ) -> eyre::Result<impl Stream<Item = Timestamped<CoordinatorEvent>>> {
-    let mut stream = TcpStream::connect(addr)
-        .await
-        .wrap_err("failed to connect to dora-coordinator")?;
-    stream
-        .set_nodelay(true)
-        .wrap_err("failed to set TCP_NODELAY")?;
-    let register = serde_json::to_vec(&Timestamped {
-        inner: CoordinatorRequest::Register(DaemonRegisterRequest::new(machine_id, listen_port)),
-        timestamp: clock.new_timestamp(),
-    })?;
-    socket_stream_send(&mut stream, &register)
-        .await
-        .wrap_err("failed to send register request to dora-coordinator")?;
-    let reply_raw = socket_stream_receive(&mut stream)
-        .await
-        .wrap_err("failed to register reply from dora-coordinator")?;
-    let result: Timestamped<RegisterResult> = serde_json::from_slice(&reply_raw)
-        .wrap_err("failed to deserialize dora-coordinator reply")?;
-    result.inner.to_result()?;
+    let start = Instant::now();
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut last_error = None;
+    let (mut stream, result) = loop {
+        let request = DaemonRegisterRequest::new(
+            machine_id.clone(),
+            listen_port,
+            inter_daemon_transport,
+            metadata.clone(),
+            machine_token.clone(),
+        );
+        let err = match send_register_request(addr, request, clock).await {
+            Ok(reply) => break reply,
+            Err(err) => err,
+        };
+        // jitter avoids that all daemons of a machine cluster reconnect at once
+        let sleep = delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        if let Some(timeout) = timeout {
+            if start.elapsed() + sleep > timeout {
+                return Err(err.wrap_err(format!(
+                    "dora-coordinator at {addr} was not reachable within {timeout:?}"
+                )));
+            }
+        }
+        let message = format!("{err:#}");
+        if last_error.is_none() {
+            tracing::info!("dora-coordinator at {addr} is not reachable yet, retrying: {message}");
+        } else if last_error.as_ref() != Some(&message) {
+            tracing::info!("dora-coordinator at {addr} is still not reachable: {message}");
+        } else {
+            tracing::debug!("dora-coordinator at {addr} is still not reachable: {message}");
+        }
+        last_error = Some(message);
+        tokio::time::sleep(sleep).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    };
+    result
+        .inner
+        .to_result()
+        .wrap_err("dora-coordinator rejected the registration")?;
     if let Err(err) = clock.update_with_timestamp(&result.timestamp) {
         tracing::warn!("failed to update timestamp after register: {err}");
     }
@@ -57,12 +102,19 @@ pub async fn register(
     tokio::spawn(async move {
         loop {
             let event = match socket_stream_receive(&mut stream).await {
-                Ok(raw) => match serde_json::from_slice(&raw) {
-                    Ok(event) => event,
+                Ok(raw) => match compat::decode(&raw) {
+                    Ok(Decoded::Message(event)) => event,
+                    Ok(Decoded::Skipped { error, .. }) => {
+                        tracing::debug!("skipping ignorable coordinator event: {error}");
+                        continue;
+                    }
                     Err(err) => {
                         let err =
                             eyre!(err).wrap_err("failed to deserialize incoming coordinator event");
-                        tracing::warn!("{err:?}");
+                        tracing::warn!(
+                            "{err:?}\n\nThe coordinator might be from a newer dora release than \
+                            the daemon."
+                        );
                         continue;
                     }
                 },
@@ -122,3 +174,97 @@ pub async fn register(
 
     Ok(ReceiverStream::new(rx))
 }
+
+/// Connects to the coordinator and sends the given register request.
+///
+/// Returns the connection together with the reply of the coordinator.
+async fn send_register_request(
+    addr: SocketAddr,
+    request: DaemonRegisterRequest,
+    clock: &HLC,
+) -> eyre::Result<(TcpStream, Timestamped<RegisterResult>)> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .wrap_err("failed to connect to dora-coordinator")?;
+    stream
+        .set_nodelay(true)
+        .wrap_err("failed to set TCP_NODELAY")?;
+    let register = serde_json::to_vec(&Timestamped {
+        inner: CoordinatorRequest::Register(request),
+        timestamp: clock.new_timestamp(),
+    })?;
+    socket_stream_send(&mut stream, &register)
+        .await
+        .wrap_err("failed to send register request to dora-coordinator")?;
+    let reply_raw = socket_stream_receive(&mut stream)
+        .await
+        .wrap_err("failed to register reply from dora-coordinator")?;
+    let result = serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize dora-coordinator reply")?;
+    Ok((stream, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn reserve_port() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    async fn register_with_timeout(
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> eyre::Result<impl Stream<Item = Timestamped<CoordinatorEvent>>> {
+        let clock = HLC::default();
+        register(
+            addr,
+            "test".into(),
+            0,
+            InterDaemonTransport::Tcp,
+            MachineMetadata::default(),
+            None,
+            &clock,
+            Some(timeout),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn registration_is_retried_until_coordinator_is_up() {
+        let addr = reserve_port().await;
+        let coordinator = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            let (mut connection, _) = listener.accept().await.unwrap();
+            let request = socket_stream_receive(&mut connection).await.unwrap();
+            let request: Timestamped<CoordinatorRequest> =
+                serde_json::from_slice(&request).unwrap();
+            assert!(matches!(request.inner, CoordinatorRequest::Register(_)));
+            let reply = serde_json::to_vec(&Timestamped {
+                inner: RegisterResult::Ok,
+                timestamp: HLC::default().new_timestamp(),
+            })
+            .unwrap();
+            socket_stream_send(&mut connection, &reply).await.unwrap();
+            connection
+        });
+
+        let result = register_with_timeout(addr, Duration::from_secs(30)).await;
+        assert!(result.is_ok());
+        coordinator.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn registration_fails_after_timeout() {
+        let addr = reserve_port().await;
+        let start = Instant::now();
+        let result = register_with_timeout(addr, Duration::from_secs(1)).await;
+        let Err(err) = result else {
+            panic!("registration without coordinator succeeded")
+        };
+        assert!(format!("{err}").contains("not reachable within"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,122 @@
+//! Event channels of the running dataflows.
+//!
+//! Each dataflow gets its own bounded channel for the events of its nodes,
+//! timers, and transports. The channels are polled fairly by the main loop,
+//! so a dataflow that floods its channel (e.g. with output messages) doesn't
+//! delay the control messages of other dataflows.
+
+use std::collections::{BTreeMap, HashMap};
+
+use dora_message::{common::Timestamped, DataflowId};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt, StreamMap};
+
+use crate::Event;
+
+/// Number of events that are buffered per dataflow.
+const CHANNEL_CAPACITY: usize = 10;
+
+#[derive(Default)]
+pub struct DataflowEvents {
+    senders: HashMap<DataflowId, mpsc::Sender<Timestamped<Event>>>,
+    receivers: StreamMap<DataflowId, ReceiverStream<Timestamped<Event>>>,
+}
+
+impl DataflowEvents {
+    /// Returns the sender of the given dataflow's channel, opening the
+    /// channel on first use.
+    pub fn sender(&mut self, dataflow_id: DataflowId) -> mpsc::Sender<Timestamped<Event>> {
+        let receivers = &mut self.receivers;
+        self.senders
+            .entry(dataflow_id)
+            .or_insert_with(|| {
+                let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+                receivers.insert(dataflow_id, ReceiverStream::new(rx));
+                tx
+            })
+            .clone()
+    }
+
+    /// Closes the channel of the given dataflow once all remaining senders
+    /// are dropped. Events that are still buffered are delivered.
+    pub fn close(&mut self, dataflow_id: DataflowId) {
+        self.senders.remove(&dataflow_id);
+    }
+
+    /// Number of buffered events per open dataflow channel.
+    pub fn depths(&self) -> BTreeMap<DataflowId, usize> {
+        self.senders
+            .iter()
+            .map(|(id, tx)| (*id, tx.max_capacity() - tx.capacity()))
+            .collect()
+    }
+
+    /// Waits for the next event of any dataflow.
+    ///
+    /// Returns `None` right away if no channel is open.
+    pub async fn next(&mut self) -> Option<(DataflowId, Timestamped<Event>)> {
+        self.receivers.next().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dora_core::uhlc::HLC;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn flooding_dataflow_does_not_block_others() {
+        let clock = Arc::new(HLC::default());
+        let mut events = DataflowEvents::default();
+        let flooding = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let flooding_tx = events.sender(flooding);
+        let flood_clock = clock.clone();
+        let flood = tokio::spawn(async move {
+            loop {
+                let event = Timestamped {
+                    inner: Event::HeartbeatInterval,
+                    timestamp: flood_clock.new_timestamp(),
+                };
+                if flooding_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        while events.depths()[&flooding] < CHANNEL_CAPACITY {
+            tokio::task::yield_now().await;
+        }
+
+        events
+            .sender(other)
+            .send(Timestamped {
+                inner: Event::HeartbeatInterval,
+                timestamp: clock.new_timestamp(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(events.depths()[&other], 1);
+
+        let mut flooding_events_before = 0;
+        loop {
+            let (dataflow_id, _) = events.next().await.unwrap();
+            if dataflow_id == other {
+                break;
+            }
+            flooding_events_before += 1;
+        }
+        assert!(
+            flooding_events_before < 32,
+            "event of other dataflow was delayed by {flooding_events_before} events"
+        );
+
+        events.close(flooding);
+        flood.abort();
+        assert!(!events.depths().contains_key(&flooding));
+    }
+}
@@ -0,0 +1,99 @@
+//! Records of dropped messages for the `drop_events` input of a dataflow.
+//!
+//! Every drop that the daemon notices is published as a [`DropEvent`] on the
+//! `dora/drop_events` output, which is delivered to the designated input like
+//! any other output. An overloaded dataflow can drop thousands of messages
+//! per second, so the records are rate limited: drops above the limit are
+//! summed up in the `suppressed` count of the next record instead.
+
+use crate::node_communication::limits::RateLimiter;
+use dora_message::daemon_to_node::{DropEvent, DropReason};
+use std::time::Instant;
+
+/// Maximum number of drop records per second and dataflow, allowing bursts
+/// of up to one second worth of records.
+pub const MAX_DROP_EVENTS_PER_SECOND: u32 = 20;
+
+pub struct DropNotifier {
+    machine: String,
+    limiter: RateLimiter,
+    next_sequence: u64,
+    /// Dropped messages that were not reported because of the rate limit.
+    suppressed: u64,
+}
+
+impl DropNotifier {
+    pub fn new(machine: String, now: Instant) -> Self {
+        Self {
+            machine,
+            limiter: RateLimiter::new(MAX_DROP_EVENTS_PER_SECOND, now),
+            next_sequence: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns the record for the given dropped messages, or `None` if the
+    /// rate limit is exceeded.
+    pub fn record(
+        &mut self,
+        input: String,
+        sources: Vec<String>,
+        reason: DropReason,
+        count: u64,
+        time_ms: u64,
+        now: Instant,
+    ) -> Option<DropEvent> {
+        if !self.limiter.allow(now) {
+            self.suppressed += count;
+            return None;
+        }
+        let event = DropEvent {
+            input,
+            sources,
+            reason,
+            count,
+            machine: self.machine.clone(),
+            sequence: self.next_sequence,
+            time_ms,
+            suppressed: std::mem::take(&mut self.suppressed),
+        };
+        self.next_sequence += 1;
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn drop_storms_are_summed_up() {
+        let start = Instant::now();
+        let mut notifier = DropNotifier::new("robot-pc".into(), start);
+        let mut record = |count, now| {
+            notifier.record(
+                "robot/cmd".into(),
+                vec!["planner/cmd".into()],
+                DropReason::QueueFull,
+                count,
+                0,
+                now,
+            )
+        };
+
+        let burst: Vec<_> = (0..100).filter_map(|_| record(2, start)).collect();
+        assert_eq!(burst.len(), MAX_DROP_EVENTS_PER_SECOND as usize);
+        assert!(burst
+            .iter()
+            .enumerate()
+            .all(|(i, event)| event.sequence == i as u64 && event.suppressed == 0));
+
+        let later = record(1, start + Duration::from_secs(1)).unwrap();
+        assert_eq!(later.sequence, u64::from(MAX_DROP_EVENTS_PER_SECOND));
+        assert_eq!(later.count, 1);
+        assert_eq!(later.suppressed, 2 * (100 - 20));
+        let next = record(1, start + Duration::from_secs(1)).unwrap();
+        assert_eq!(next.suppressed, 0);
+    }
+}
@@ -0,0 +1,122 @@
+//! Tombstones of drop tokens that were freed recently.
+//!
+//! Receivers report drop tokens asynchronously, so a report can arrive after
+//! the token was freed already, e.g. when a receiver flushes its queued drop
+//! tokens while the dataflow is torn down, or when a token was force-released
+//! by the garbage collection. Such reports are expected, so they are only
+//! counted. Tokens that are neither pending nor tombstoned were never issued,
+//! which is still worth a warning.
+
+use dora_message::{common::DropToken, diagnostics::DropTokenReports};
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// How long freed drop tokens are remembered.
+pub const DROP_TOMBSTONE_TTL: Duration = Duration::from_secs(30);
+
+/// Recently freed drop tokens of a single dataflow.
+#[derive(Debug, Default)]
+pub struct DropTombstones {
+    tokens: HashSet<DropToken>,
+    /// Expiry of the tokens, in insertion order.
+    expiry: VecDeque<(Instant, DropToken)>,
+}
+
+impl DropTombstones {
+    /// Remembers the given freed token until [`DROP_TOMBSTONE_TTL`] elapsed.
+    pub fn insert(&mut self, token: DropToken, now: Instant) {
+        self.expire(now);
+        if self.tokens.insert(token) {
+            self.expiry.push_back((now + DROP_TOMBSTONE_TTL, token));
+        }
+    }
+
+    pub fn contains(&mut self, token: &DropToken, now: Instant) -> bool {
+        self.expire(now);
+        self.tokens.contains(token)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Forgets all tokens whose TTL elapsed.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some((expiry, token)) = self.expiry.front() {
+            if *expiry > now {
+                break;
+            }
+            self.tokens.remove(token);
+            self.expiry.pop_front();
+        }
+    }
+}
+
+/// Counts and logs a drop report of a token that is not pending.
+///
+/// Reports of tombstoned tokens and of tokens that were issued in a previous
+/// daemon run are late, all others are unknown.
+pub fn record_report(
+    reports: &mut DropTokenReports,
+    tombstones: Option<&mut DropTombstones>,
+    token: &DropToken,
+    run_id: uuid::Uuid,
+    now: Instant,
+) {
+    let previous_run = !token.namespace().is_nil() && token.namespace() != run_id;
+    if previous_run || tombstones.is_some_and(|t| t.contains(token, now)) {
+        reports.late += 1;
+        tracing::debug!("late drop report for freed token `{token:?}`");
+    } else {
+        reports.unknown += 1;
+        tracing::warn!("unknown drop token `{token:?}`");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tombstones_expire() {
+        let now = Instant::now();
+        let token = DropToken::generate();
+        let mut tombstones = DropTombstones::default();
+        tombstones.insert(token, now);
+        assert!(tombstones.contains(&token, now + DROP_TOMBSTONE_TTL / 2));
+        assert!(!tombstones.contains(&token, now + DROP_TOMBSTONE_TTL));
+        assert!(tombstones.is_empty());
+    }
+
+    #[test]
+    fn reports_are_classified() {
+        let now = Instant::now();
+        let run_id = uuid::Uuid::new_v4();
+        let freed = DropToken::generate_in(run_id);
+        let mut tombstones = DropTombstones::default();
+        tombstones.insert(freed, now);
+
+        let mut reports = DropTokenReports::default();
+        record_report(&mut reports, Some(&mut tombstones), &freed, run_id, now);
+        // issued by the previous run of the daemon
+        let stale = DropToken::generate_in(uuid::Uuid::new_v4());
+        record_report(&mut reports, None, &stale, run_id, now);
+        let never_issued = DropToken::generate_in(run_id);
+        record_report(
+            &mut reports,
+            Some(&mut tombstones),
+            &never_issued,
+            run_id,
+            now,
+        );
+        assert_eq!(
+            reports,
+            DropTokenReports {
+                late: 2,
+                unknown: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Aggregated warnings for inputs that were dropped because the event queue
+//! of the receiver was full.
+//!
+//! An overloaded receiver can drop thousands of messages per second, so a
+//! warning per drop would only add to the load. Instead, the first drop of
+//! an input is reported right away and later drops are summed up and
+//! reported at most once per interval.
+
+use dora_core::config::{DataId, NodeId};
+use dora_message::DataflowId;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_DROP_WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+type InputKey = (DataflowId, NodeId, DataId);
+
+pub struct DropWarnings {
+    interval: Duration,
+    inputs: BTreeMap<InputKey, InputDrops>,
+}
+
+#[derive(Debug, Default)]
+struct InputDrops {
+    /// Drops that were not reported yet.
+    unreported: u64,
+    /// Drops since the last heartbeat, for the health metrics.
+    since_heartbeat: u64,
+    last_report: Option<Instant>,
+}
+
+/// A warning about dropped inputs that is due.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DropReport {
+    pub dataflow_id: DataflowId,
+    pub node_id: NodeId,
+    pub input_id: DataId,
+    pub count: u64,
+    /// Time since the previous report, `None` for the first drops of the input.
+    pub window: Option<Duration>,
+}
+
+impl fmt::Display for DropReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            dataflow_id,
+            node_id,
+            input_id,
+            count,
+            window,
+        } = self;
+        write!(
+            f,
+            "dropped {count} messages for {node_id}/{input_id} (dataflow `{dataflow_id}`)"
+        )?;
+        match window {
+            Some(window) => write!(f, " in the last {window:.0?}")?,
+            None => write!(f, " because its event queue is full")?,
+        }
+        Ok(())
+    }
+}
+
+impl DropWarnings {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Records dropped messages of an input and returns a report if a
+    /// warning is due.
+    pub fn record(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        input_id: DataId,
+        count: u64,
+        now: Instant,
+    ) -> Option<DropReport> {
+        let key = (dataflow_id, node_id, input_id);
+        let drops = self.inputs.entry(key.clone()).or_default();
+        drops.unreported += count;
+        drops.since_heartbeat += count;
+        match drops.last_report {
+            None => Some(Self::report(key, drops, now)),
+            Some(last) if now.duration_since(last) >= self.interval => {
+                Some(Self::report(key, drops, now))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the aggregated reports of all inputs whose interval elapsed.
+    ///
+    /// Needs to be called periodically, so that drops are still reported
+    /// when no further drops happen.
+    pub fn flush(&mut self, now: Instant) -> Vec<DropReport> {
+        let interval = self.interval;
+        self.inputs
+            .iter_mut()
+            .filter(|(_, drops)| {
+                drops.unreported > 0
+                    && !matches!(drops.last_report, Some(last) if now.duration_since(last) < interval)
+            })
+            .map(|(key, drops)| Self::report(key.clone(), drops, now))
+            .collect()
+    }
+
+    /// Forgets the inputs of the given dataflow and returns the reports for
+    /// their unreported drops.
+    pub fn remove_dataflow(&mut self, dataflow_id: DataflowId, now: Instant) -> Vec<DropReport> {
+        let mut reports = Vec::new();
+        self.inputs.retain(|key, drops| {
+            if key.0 != dataflow_id {
+                return true;
+            }
+            if drops.unreported > 0 {
+                reports.push(Self::report(key.clone(), drops, now));
+            }
+            false
+        });
+        reports
+    }
+
+    /// Takes the number of drops per input since the previous call, keyed by
+    /// `<dataflow_id>/<node_id>/<input_id>`.
+    pub fn take_heartbeat_counts(&mut self) -> BTreeMap<String, u64> {
+        self.inputs
+            .iter_mut()
+            .filter(|(_, drops)| drops.since_heartbeat > 0)
+            .map(|((dataflow_id, node_id, input_id), drops)| {
+                (
+                    format!("{dataflow_id}/{node_id}/{input_id}"),
+                    std::mem::take(&mut drops.since_heartbeat),
+                )
+            })
+            .collect()
+    }
+
+    fn report(key: InputKey, drops: &mut InputDrops, now: Instant) -> DropReport {
+        let (dataflow_id, node_id, input_id) = key;
+        let window = drops.last_report.map(|last| now.duration_since(last));
+        drops.last_report = Some(now);
+        DropReport {
+            dataflow_id,
+            node_id,
+            input_id,
+            count: std::mem::take(&mut drops.unreported),
+            window,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_are_aggregated_per_interval() {
+        let interval = Duration::from_secs(5);
+        let mut warnings = DropWarnings::new(interval);
+        let dataflow_id = DataflowId::new_v4();
+        let node_id = NodeId::from("sink".to_owned());
+        let image = DataId::from("image".to_owned());
+        let start = Instant::now();
+        let mut record = |count, offset| {
+            warnings.record(
+                dataflow_id,
+                node_id.clone(),
+                image.clone(),
+                count,
+                start + offset,
+            )
+        };
+
+        // the first drop is reported right away
+        let first = record(2, Duration::ZERO).unwrap();
+        assert_eq!((first.count, first.window), (2, None));
+        // further drops are collected until the interval elapsed
+        assert!(record(10, Duration::from_secs(1)).is_none());
+        assert!(record(20, Duration::from_secs(4)).is_none());
+        let aggregated = record(3, Duration::from_secs(6)).unwrap();
+        assert_eq!(
+            (aggregated.count, aggregated.window),
+            (33, Some(Duration::from_secs(6)))
+        );
+        assert!(record(1, Duration::from_secs(7)).is_none());
+
+        // pending drops are reported by `flush` once the interval elapsed
+        assert!(warnings.flush(start + Duration::from_secs(10)).is_empty());
+        let flushed = warnings.flush(start + Duration::from_secs(11));
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].count, 1);
+        assert!(warnings.flush(start + Duration::from_secs(30)).is_empty());
+
+        let counts = warnings.take_heartbeat_counts();
+        assert_eq!(counts[&format!("{dataflow_id}/sink/image")], 36);
+        assert!(warnings.take_heartbeat_counts().is_empty());
+
+        // other inputs have their own window
+        let other = warnings
+            .record(
+                dataflow_id,
+                node_id,
+                DataId::from("depth".to_owned()),
+                1,
+                start,
+            )
+            .unwrap();
+        assert_eq!(other.window, None);
+        assert!(warnings.remove_dataflow(dataflow_id, start).is_empty());
+        assert!(warnings.inputs.is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+//! Message counts of the edges to the inputs of the local nodes.
+//!
+//! Every message that reaches the daemon for a local input is counted as
+//! sent on its edge and then either as delivered or as dropped. Messages
+//! that are dropped from the input queue of the receiver later are moved
+//! from `delivered` to `dropped`. Lost messages of best-effort outputs of
+//! other machines are counted as sent and dropped once the loss is noticed.
+//! When the dataflow is removed, each edge must balance, otherwise the
+//! bookkeeping of the daemon is broken.
+
+use crate::InputId;
+use dora_message::summary::{EdgeDropReason, EdgeSummary};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default)]
+pub struct EdgeStats {
+    /// Edges by source (`node_id/output_id`) and receiving input.
+    edges: BTreeMap<String, BTreeMap<InputId, EdgeSummary>>,
+}
+
+impl EdgeStats {
+    fn edge(&mut self, source: &str, receiver: &InputId) -> &mut EdgeSummary {
+        // avoid allocating the source for every message
+        if !self.edges.contains_key(source) {
+            self.edges.insert(source.to_owned(), BTreeMap::new());
+        }
+        let receivers = self.edges.get_mut(source).unwrap();
+        receivers.entry(receiver.clone()).or_default()
+    }
+
+    /// Counts a message that was sent on the edge and dropped right away.
+    pub fn record_dropped(&mut self, source: &str, receiver: &InputId, reason: EdgeDropReason) {
+        let edge = self.edge(source, receiver);
+        edge.sent += 1;
+        edge.record_drop(reason, 1);
+    }
+
+    /// Counts messages of a best-effort output that were sent on the edge,
+    /// but lost on the way.
+    pub fn record_lost(&mut self, source: &str, receiver: &InputId, count: u64) {
+        let edge = self.edge(source, receiver);
+        edge.sent += count;
+        edge.record_drop(EdgeDropReason::Lost, count);
+    }
+
+    /// Counts a message that was sent on the edge and passed to the receiver.
+    pub fn record_delivered(&mut self, source: &str, receiver: &InputId) {
+        let edge = self.edge(source, receiver);
+        edge.sent += 1;
+        edge.delivered += 1;
+    }
+
+    /// Moves messages that were passed to the receiver already, but dropped
+    /// afterwards, from `delivered` to `dropped`.
+    ///
+    /// Edges that were never used are ignored, e.g. for inputs that are
+    /// driven by timers.
+    pub fn redeclare_dropped(
+        &mut self,
+        source: &str,
+        receiver: &InputId,
+        reason: EdgeDropReason,
+        count: u64,
+    ) {
+        let Some(edge) = self
+            .edges
+            .get_mut(source)
+            .and_then(|receivers| receivers.get_mut(receiver))
+        else {
+            return;
+        };
+        // saturating, so that a bookkeeping error shows up as discrepancy
+        edge.delivered = edge.delivered.saturating_sub(count);
+        edge.record_drop(reason, count);
+    }
+
+    /// Edges with messages that were neither delivered nor dropped, as
+    /// `(source, receiver, summary)`.
+    pub fn unaccounted(&self) -> impl Iterator<Item = (&str, &InputId, &EdgeSummary)> {
+        self.edges.iter().flat_map(|(source, receivers)| {
+            receivers
+                .iter()
+                .filter(|(_, edge)| edge.unaccounted() != 0)
+                .map(move |(receiver, edge)| (source.as_str(), receiver, edge))
+        })
+    }
+
+    /// The edges in the format of the result summary.
+    pub fn summary(&self) -> BTreeMap<String, BTreeMap<String, EdgeSummary>> {
+        self.edges
+            .iter()
+            .map(|(source, receivers)| {
+                let receivers = receivers
+                    .iter()
+                    .map(|((node_id, input_id), edge)| {
+                        (format!("{node_id}/{input_id}"), edge.clone())
+                    })
+                    .collect();
+                (source.clone(), receivers)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::config::{DataId, NodeId};
+
+    #[test]
+    fn late_drops_keep_edges_balanced() {
+        let sink: InputId = (
+            NodeId::from("sink".to_owned()),
+            DataId::from("image".to_owned()),
+        );
+        let mut stats = EdgeStats::default();
+        for _ in 0..10 {
+            stats.record_delivered("camera/image", &sink);
+        }
+        stats.record_dropped("camera/image", &sink, EdgeDropReason::Filtered);
+        stats.redeclare_dropped("camera/image", &sink, EdgeDropReason::QueueFull, 3);
+        // timer inputs have no edge
+        stats.redeclare_dropped("dora/timer/millis/10", &sink, EdgeDropReason::QueueFull, 1);
+        assert_eq!(stats.unaccounted().count(), 0);
+
+        let summary = stats.summary();
+        let edge = &summary["camera/image"]["sink/image"];
+        assert_eq!(edge.sent, 11);
+        assert_eq!(edge.delivered, 7);
+        assert_eq!(
+            edge.dropped,
+            [
+                (EdgeDropReason::QueueFull, 3),
+                (EdgeDropReason::Filtered, 1)
+            ]
+            .into()
+        );
+        assert_eq!(summary.len(), 1);
+
+        // more drops than deliveries are reported as discrepancy
+        stats.redeclare_dropped("camera/image", &sink, EdgeDropReason::QueueFull, 8);
+        let (source, receiver, edge) = stats.unaccounted().next().unwrap();
+        assert_eq!((source, receiver), ("camera/image", &sink));
+        assert_eq!(edge.unaccounted(), -1);
+    }
+}
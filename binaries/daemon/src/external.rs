@@ -0,0 +1,486 @@
+//! TCP server that makes the `expose` outputs and `external_inputs` of a
+//! dataflow available to processes outside of the dataflow.
+
+use crate::{
+    send_output_to_local_receivers,
+    socket_stream_utils::{socket_stream_receive, socket_stream_send},
+    Daemon, Event,
+};
+use dora_core::{
+    config::{DataId, NodeId},
+    constant_time_eq,
+    topics::LOCALHOST,
+    uhlc::HLC,
+};
+use dora_message::{
+    common::DataMessage,
+    daemon_to_external::{ExternalEndpointsInfo, ExternalReply},
+    external_to_daemon::{ExternalMessage, ExternalRequest},
+    metadata::{ArrowTypeInfo, Metadata},
+    node_to_daemon::Timestamped,
+    DataflowId,
+};
+use eyre::{Context, ContextCompat};
+use futures::{future::RemoteHandle, FutureExt};
+use std::{collections::BTreeSet, io::ErrorKind, net::SocketAddr, sync::Arc};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Sender},
+};
+use uuid::Uuid;
+
+/// Name of the file in `out/<dataflow_id>/` that contains the [`ExternalEndpointsInfo`].
+pub const EXTERNAL_ENDPOINTS_FILE: &str = "external_endpoints.json";
+
+/// Number of messages that are queued for an external subscriber before
+/// messages are skipped.
+pub const EXTERNAL_SUBSCRIBER_QUEUE_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum ExternalEvent {
+    Subscribe {
+        name: String,
+        sender: Sender<ExternalMessage>,
+    },
+    Input {
+        name: String,
+        message: Box<ExternalMessage>,
+    },
+}
+
+/// The output ID of the `dora` pseudo node that messages sent to the
+/// external input with the given name are published on.
+pub fn external_output_id(name: &str) -> DataId {
+    DataId::from(format!("external/{name}"))
+}
+
+impl Daemon {
+    /// Handles a subscription or input message of an external process.
+    pub(crate) async fn handle_external_event(
+        &mut self,
+        dataflow_id: DataflowId,
+        event: ExternalEvent,
+    ) -> eyre::Result<()> {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            tracing::debug!("ignoring external event for finished dataflow `{dataflow_id}`");
+            return Ok(());
+        };
+        match event {
+            ExternalEvent::Subscribe { name, sender } => {
+                let output_id = dataflow
+                    .exposed_outputs
+                    .get(&name)
+                    .wrap_err_with(|| format!("no exposed output `{name}`"))?;
+                dataflow
+                    .external_subscribers
+                    .entry(output_id.clone())
+                    .or_default()
+                    .push(sender);
+            }
+            ExternalEvent::Input { name, message } => {
+                let ExternalMessage { metadata, data } = *message;
+                // restamp the message with the daemon clock
+                let metadata = Metadata::from_parameters(
+                    self.clock.new_timestamp(),
+                    metadata.type_info,
+                    metadata.parameters,
+                );
+                send_output_to_local_receivers(
+                    NodeId::from("dora".to_string()),
+                    external_output_id(&name),
+                    dataflow,
+                    &metadata,
+                    data.map(DataMessage::Vec),
+                    &self.clock,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct ServerContext {
+    dataflow_id: DataflowId,
+    token: String,
+    exposed: BTreeSet<String>,
+    inputs: BTreeSet<String>,
+    events_tx: mpsc::Sender<Timestamped<Event>>,
+    clock: Arc<HLC>,
+}
+
+/// Starts the external endpoint server for the given dataflow.
+///
+/// The server is stopped when the returned handle is dropped.
+pub async fn spawn_server(
+    dataflow_id: DataflowId,
+    exposed: BTreeSet<String>,
+    inputs: BTreeSet<String>,
+    events_tx: mpsc::Sender<Timestamped<Event>>,
+    clock: Arc<HLC>,
+) -> eyre::Result<(ExternalEndpointsInfo, RemoteHandle<()>)> {
+    let listener = TcpListener::bind(SocketAddr::new(LOCALHOST, 0))
+        .await
+        .wrap_err("failed to bind external endpoint listener")?;
+    let address = listener
+        .local_addr()
+        .wrap_err("failed to get local addr of external endpoint listener")?;
+    let token = Uuid::new_v4().to_string();
+
+    let context = Arc::new(ServerContext {
+        dataflow_id,
+        token: token.clone(),
+        exposed,
+        inputs,
+        events_tx,
+        clock,
+    });
+    let (task, handle) = listener_loop(listener, context).remote_handle();
+    tokio::spawn(task);
+
+    Ok((ExternalEndpointsInfo { address, token }, handle))
+}
+
+async fn listener_loop(listener: TcpListener, context: Arc<ServerContext>) {
+    loop {
+        match listener
+            .accept()
+            .await
+            .wrap_err("failed to accept new external connection")
+        {
+            Err(err) => {
+                tracing::info!("{err}");
+            }
+            Ok((connection, _)) => {
+                let context = context.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(connection, &context).await {
+                        tracing::warn!(
+                            "external connection of dataflow `{}` failed: {err:?}",
+                            context.dataflow_id
+                        );
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn handle_connection(mut connection: TcpStream, context: &ServerContext) -> eyre::Result<()> {
+    if let Err(err) = connection.set_nodelay(true) {
+        tracing::warn!("failed to set nodelay for connection: {err}");
+    }
+
+    let raw = socket_stream_receive(&mut connection)
+        .await
+        .wrap_err("failed to receive external request")?;
+    let request: ExternalRequest =
+        bincode::deserialize(&raw).wrap_err("failed to deserialize external request")?;
+
+    match request {
+        ExternalRequest::Subscribe { token, name } => {
+            let check = context.check(&token, &name, &context.exposed, "exposed output");
+            send_reply(&mut connection, &check).await?;
+            if check.is_err() {
+                return Ok(());
+            }
+
+            let (sender, mut receiver) = mpsc::channel(EXTERNAL_SUBSCRIBER_QUEUE_SIZE);
+            if !context
+                .send_event(ExternalEvent::Subscribe { name, sender })
+                .await
+            {
+                return Ok(());
+            }
+            while let Some(message) = receiver.recv().await {
+                let serialized = bincode::serialize(&message)
+                    .wrap_err("failed to serialize external message")?;
+                if let Err(err) = socket_stream_send(&mut connection, &serialized).await {
+                    tracing::debug!("external subscriber disconnected: {err}");
+                    break;
+                }
+            }
+        }
+        ExternalRequest::Publish { token, name } => {
+            let check = context.check(&token, &name, &context.inputs, "external input");
+            send_reply(&mut connection, &check).await?;
+            if check.is_err() {
+                return Ok(());
+            }
+
+            loop {
+                let raw = match socket_stream_receive(&mut connection).await {
+                    Ok(raw) => raw,
+                    Err(err) => match err.kind() {
+                        ErrorKind::UnexpectedEof
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::ConnectionReset => break,
+                        _other => {
+                            return Err(err)
+                                .context("unexpected I/O error while receiving external message")
+                        }
+                    },
+                };
+                let message: Box<ExternalMessage> = bincode::deserialize(&raw)
+                    .wrap_err("failed to deserialize external message")?;
+                let data_len = message.data.as_ref().map_or(0, |data| data.len());
+                check_buffers(&message.metadata.type_info, data_len)
+                    .wrap_err("invalid external message")?;
+                let event = ExternalEvent::Input {
+                    name: name.clone(),
+                    message,
+                };
+                if !context.send_event(event).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl ServerContext {
+    fn check(
+        &self,
+        token: &str,
+        name: &str,
+        names: &BTreeSet<String>,
+        kind: &str,
+    ) -> Result<(), String> {
+        if !constant_time_eq(token, &self.token) {
+            Err("invalid token".into())
+        } else if !names.contains(name) {
+            Err(format!(
+                "no {kind} `{name}` in dataflow `{}`",
+                self.dataflow_id
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns `false` if the daemon is no longer listening for events.
+    async fn send_event(&self, event: ExternalEvent) -> bool {
+        self.events_tx
+            .send(Timestamped {
+                inner: Event::External {
+                    dataflow_id: self.dataflow_id,
+                    event,
+                },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .await
+            .is_ok()
+    }
+}
+
+/// Checks that all buffers described by the given type info lie within the
+/// data of the message.
+///
+/// The receiving nodes slice the data according to the buffer offsets, so
+/// out-of-bounds offsets sent by an external process must not reach them.
+fn check_buffers(type_info: &ArrowTypeInfo, data_len: usize) -> eyre::Result<()> {
+    for buffer in &type_info.buffer_offsets {
+        if buffer
+            .offset
+            .checked_add(buffer.len)
+            .map_or(true, |end| end > data_len)
+        {
+            eyre::bail!(
+                "buffer at offset {} with length {} exceeds the {data_len} bytes of data",
+                buffer.offset,
+                buffer.len
+            );
+        }
+    }
+    for child in &type_info.child_data {
+        check_buffers(child, data_len)?;
+    }
+    Ok(())
+}
+
+async fn send_reply(connection: &mut TcpStream, check: &Result<(), String>) -> eyre::Result<()> {
+    let reply = match check {
+        Ok(()) => ExternalReply::Ok,
+        Err(err) => ExternalReply::Err(err.clone()),
+    };
+    let serialized = bincode::serialize(&reply).wrap_err("failed to serialize external reply")?;
+    socket_stream_send(connection, &serialized)
+        .await
+        .wrap_err("failed to send external reply")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aligned_vec::AVec;
+    use dora_message::metadata::BufferOffset;
+
+    async fn start_server() -> (
+        ExternalEndpointsInfo,
+        RemoteHandle<()>,
+        mpsc::Receiver<Timestamped<Event>>,
+    ) {
+        let (events_tx, events_rx) = mpsc::channel(8);
+        let (info, handle) = spawn_server(
+            Uuid::new_v4(),
+            ["camera".to_owned()].into(),
+            ["command".to_owned()].into(),
+            events_tx,
+            Arc::new(HLC::default()),
+        )
+        .await
+        .unwrap();
+        (info, handle, events_rx)
+    }
+
+    async fn connect(
+        info: &ExternalEndpointsInfo,
+        request: ExternalRequest,
+    ) -> (TcpStream, ExternalReply) {
+        let mut connection = TcpStream::connect(info.address).await.unwrap();
+        let request = bincode::serialize(&request).unwrap();
+        socket_stream_send(&mut connection, &request).await.unwrap();
+        let reply = socket_stream_receive(&mut connection).await.unwrap();
+        (connection, bincode::deserialize(&reply).unwrap())
+    }
+
+    fn message(type_info: ArrowTypeInfo, data: &[u8]) -> ExternalMessage {
+        ExternalMessage {
+            metadata: Metadata::new(HLC::default().new_timestamp(), type_info),
+            data: Some(AVec::from_slice(128, data)),
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_token_and_unknown_names_are_rejected() {
+        let (info, _handle, _events) = start_server().await;
+
+        let (_, reply) = connect(
+            &info,
+            ExternalRequest::Subscribe {
+                token: "wrong".into(),
+                name: "camera".into(),
+            },
+        )
+        .await;
+        assert!(matches!(reply, ExternalReply::Err(err) if err == "invalid token"));
+
+        // tokens of the same length that only differ in the last character
+        let mut almost = info.token.clone();
+        let last = if almost.pop() == Some('0') { '1' } else { '0' };
+        almost.push(last);
+        let (_, reply) = connect(
+            &info,
+            ExternalRequest::Publish {
+                token: almost,
+                name: "command".into(),
+            },
+        )
+        .await;
+        assert!(matches!(reply, ExternalReply::Err(err) if err == "invalid token"));
+
+        let (_, reply) = connect(
+            &info,
+            ExternalRequest::Subscribe {
+                token: info.token.clone(),
+                name: "lidar".into(),
+            },
+        )
+        .await;
+        assert!(
+            matches!(reply, ExternalReply::Err(err) if err.contains("no exposed output `lidar`"))
+        );
+
+        // exposed outputs can't be published to
+        let (_, reply) = connect(
+            &info,
+            ExternalRequest::Publish {
+                token: info.token.clone(),
+                name: "camera".into(),
+            },
+        )
+        .await;
+        assert!(
+            matches!(reply, ExternalReply::Err(err) if err.contains("no external input `camera`"))
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_and_subscribe_round_trip() {
+        let (info, _handle, mut events) = start_server().await;
+
+        let (mut publisher, reply) = connect(
+            &info,
+            ExternalRequest::Publish {
+                token: info.token.clone(),
+                name: "command".into(),
+            },
+        )
+        .await;
+        assert!(matches!(reply, ExternalReply::Ok));
+        let sent = message(ArrowTypeInfo::byte_array(3), &[1, 2, 3]);
+        socket_stream_send(&mut publisher, &bincode::serialize(&sent).unwrap())
+            .await
+            .unwrap();
+        let received = match events.recv().await.unwrap().inner {
+            Event::External {
+                event: ExternalEvent::Input { name, message },
+                ..
+            } => {
+                assert_eq!(name, "command");
+                message
+            }
+            other => panic!("unexpected event {other:?}"),
+        };
+        assert_eq!(received.data.as_deref(), Some(&[1, 2, 3][..]));
+
+        let (mut subscriber, reply) = connect(
+            &info,
+            ExternalRequest::Subscribe {
+                token: info.token.clone(),
+                name: "camera".into(),
+            },
+        )
+        .await;
+        assert!(matches!(reply, ExternalReply::Ok));
+        let sender = match events.recv().await.unwrap().inner {
+            Event::External {
+                event: ExternalEvent::Subscribe { name, sender },
+                ..
+            } => {
+                assert_eq!(name, "camera");
+                sender
+            }
+            other => panic!("unexpected event {other:?}"),
+        };
+        sender.try_send(*received).unwrap();
+        let raw = socket_stream_receive(&mut subscriber).await.unwrap();
+        let forwarded: ExternalMessage = bincode::deserialize(&raw).unwrap();
+        assert_eq!(forwarded.data.as_deref(), Some(&[1, 2, 3][..]));
+    }
+
+    #[tokio::test]
+    async fn out_of_bounds_buffers_close_the_connection() {
+        let (info, _handle, mut events) = start_server().await;
+        let (mut publisher, _) = connect(
+            &info,
+            ExternalRequest::Publish {
+                token: info.token.clone(),
+                name: "command".into(),
+            },
+        )
+        .await;
+        let mut type_info = ArrowTypeInfo::byte_array(3);
+        type_info.buffer_offsets = vec![BufferOffset { offset: 2, len: 64 }];
+        let invalid = message(type_info, &[1, 2, 3]);
+        socket_stream_send(&mut publisher, &bincode::serialize(&invalid).unwrap())
+            .await
+            .unwrap();
+
+        // the server closes the connection without forwarding the message
+        assert!(socket_stream_receive(&mut publisher).await.is_err());
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Batched delivery of inputs with a `batch` config.
+//!
+//! Messages for such inputs are collected by the daemon instead of being sent
+//! to the receiver one by one. The collected messages are sent as a single
+//! `InputBatch` event once the batch holds `max` messages, or once its first
+//! message waited for `max_delay`, which is checked regularly by an
+//! `InputBatchCheck` event. Each message keeps its own data and drop token,
+//! so the receiver reports the drop of every message separately.
+
+use dora_core::{
+    config::{Batch, DataId},
+    uhlc,
+};
+use dora_message::{
+    daemon_to_node::{BatchedInput, NodeEvent},
+    node_to_daemon::{DropToken, Timestamped},
+};
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+/// Shortest interval at which pending batches are checked.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+pub struct InputBatch {
+    max: usize,
+    max_delay: Duration,
+    messages: Vec<BatchedInput>,
+    /// Arrival time of the first pending message.
+    since: Option<Instant>,
+    /// Timestamp of the newest pending message.
+    timestamp: Option<uhlc::Timestamp>,
+}
+
+impl InputBatch {
+    pub fn new(config: &Batch) -> Self {
+        Self {
+            max: config.max.get(),
+            max_delay: config.max_delay,
+            messages: Vec::new(),
+            since: None,
+            timestamp: None,
+        }
+    }
+
+    /// Adds the message of the given `Input` event to the batch.
+    ///
+    /// Returns the `InputBatch` event once the batch is full. Other events
+    /// are not batched and returned unchanged.
+    pub fn push(
+        &mut self,
+        event: Timestamped<NodeEvent>,
+        now: Instant,
+    ) -> Option<Timestamped<NodeEvent>> {
+        let (id, metadata, data, timestamp) = match event {
+            Timestamped {
+                inner: NodeEvent::Input { id, metadata, data },
+                timestamp,
+            } => (id, metadata, data, timestamp),
+            other => return Some(other),
+        };
+        self.since.get_or_insert(now);
+        self.timestamp = Some(timestamp);
+        self.messages.push(BatchedInput { metadata, data });
+        if self.messages.len() >= self.max {
+            self.take(id)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Whether the first pending message waited for `max_delay`.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.since
+            .is_some_and(|since| now.saturating_duration_since(since) >= self.max_delay)
+    }
+
+    /// Takes the pending messages as an `InputBatch` event for the input
+    /// with the given ID.
+    pub fn take(&mut self, id: DataId) -> Option<Timestamped<NodeEvent>> {
+        self.since = None;
+        let timestamp = self.timestamp.take()?;
+        Some(Timestamped {
+            inner: NodeEvent::InputBatch {
+                id,
+                messages: mem::take(&mut self.messages),
+            },
+            timestamp,
+        })
+    }
+
+    /// Drop tokens of the pending messages.
+    pub fn pending_drop_tokens(&self) -> impl Iterator<Item = DropToken> + '_ {
+        self.messages
+            .iter()
+            .filter_map(|message| message.data.as_ref()?.drop_token())
+    }
+
+    /// Interval at which the batch needs to be checked, so that no message
+    /// waits much longer than `max_delay`.
+    pub fn check_interval(&self) -> Duration {
+        (self.max_delay / 2).max(MIN_CHECK_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_message::{
+        metadata::{ArrowTypeInfo, Metadata},
+        node_to_daemon::DataMessage,
+    };
+    use std::num::NonZeroUsize;
+
+    fn input(clock: &uhlc::HLC, drop_token: DropToken) -> Timestamped<NodeEvent> {
+        let timestamp = clock.new_timestamp();
+        Timestamped {
+            inner: NodeEvent::Input {
+                id: DataId::from("imu".to_owned()),
+                metadata: Metadata::new(timestamp, ArrowTypeInfo::empty()),
+                data: Some(DataMessage::SharedMemory {
+                    shared_memory_id: String::new(),
+                    len: 0,
+                    drop_token,
+                }),
+            },
+            timestamp,
+        }
+    }
+
+    fn drop_tokens(event: &Timestamped<NodeEvent>) -> Vec<DropToken> {
+        match &event.inner {
+            NodeEvent::InputBatch { messages, .. } => messages
+                .iter()
+                .filter_map(|message| message.data.as_ref()?.drop_token())
+                .collect(),
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[test]
+    fn batches_are_sent_when_full_or_due() {
+        let clock = uhlc::HLC::default();
+        let mut batch = InputBatch::new(&Batch {
+            max: NonZeroUsize::new(3).unwrap(),
+            max_delay: Duration::from_millis(2),
+        });
+        let start = Instant::now();
+        let tokens: Vec<_> = (0..5).map(|_| DropToken::generate()).collect();
+
+        assert!(!batch.is_due(start + Duration::from_secs(1)));
+        assert!(batch.push(input(&clock, tokens[0]), start).is_none());
+        assert!(batch.push(input(&clock, tokens[1]), start).is_none());
+        assert_eq!(batch.pending_drop_tokens().collect::<Vec<_>>(), tokens[..2]);
+        let full = batch.push(input(&clock, tokens[2]), start).unwrap();
+        assert_eq!(drop_tokens(&full), tokens[..3]);
+        assert_eq!(batch.pending_drop_tokens().count(), 0);
+
+        // the delay starts with the first message of the next batch
+        let later = start + Duration::from_millis(10);
+        assert!(batch.push(input(&clock, tokens[3]), later).is_none());
+        assert!(batch.push(input(&clock, tokens[4]), later).is_none());
+        assert!(!batch.is_due(later + Duration::from_millis(1)));
+        assert!(batch.is_due(later + Duration::from_millis(2)));
+        let due = batch.take(DataId::from("imu".to_owned())).unwrap();
+        assert_eq!(drop_tokens(&due), tokens[3..]);
+        assert!(!batch.is_due(later + Duration::from_secs(1)));
+        assert!(batch.take(DataId::from("imu".to_owned())).is_none());
+
+        // other events are not batched
+        let event = Timestamped {
+            inner: NodeEvent::Stop,
+            timestamp: clock.new_timestamp(),
+        };
+        assert!(matches!(
+            batch.push(event, later).map(|e| e.inner),
+            Some(NodeEvent::Stop)
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+//! Built-in `throttle` and `decimate` filters that can be set on inputs.
+//!
+//! Filters are applied per receiving input before a message is enqueued, so
+//! other receivers of the same output are not affected.
+
+use dora_core::{config::Input, uhlc};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct InputFilter {
+    keep_every: Option<u64>,
+    min_interval: Option<Duration>,
+    /// Number of messages to skip until the next message is kept (`decimate`).
+    skip: u64,
+    last_delivered: Option<Duration>,
+    metrics: DropMetrics,
+}
+
+/// Number of messages that were not delivered to an input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DropMetrics {
+    /// Messages that were suppressed by a `throttle` or `decimate` filter.
+    pub filtered: u64,
+}
+
+impl InputFilter {
+    /// Returns `None` if no filter is configured for the given input.
+    pub fn new(input: &Input) -> Option<Self> {
+        if !input.is_filtered() {
+            return None;
+        }
+        Some(Self {
+            keep_every: input.decimate.as_ref().map(|d| d.keep_every.get()),
+            min_interval: input.throttle.as_ref().map(|t| t.max_rate.interval()),
+            skip: 0,
+            last_delivered: None,
+            metrics: DropMetrics::default(),
+        })
+    }
+
+    /// Checks whether a message with the given timestamp should be delivered.
+    ///
+    /// The `decimate` filter is applied first, then `throttle`.
+    pub fn check(&mut self, timestamp: &uhlc::Timestamp) -> bool {
+        let deliver = self.check_inner(timestamp.get_time().to_duration());
+        if !deliver {
+            self.metrics.filtered += 1;
+        }
+        deliver
+    }
+
+    fn check_inner(&mut self, time: Duration) -> bool {
+        if let Some(keep_every) = self.keep_every {
+            if self.skip > 0 {
+                self.skip -= 1;
+                return false;
+            }
+            self.skip = keep_every - 1;
+        }
+        if let Some(min_interval) = self.min_interval {
+            if let Some(last) = self.last_delivered {
+                if time.saturating_sub(last) < min_interval {
+                    return false;
+                }
+            }
+        }
+        self.last_delivered = Some(time);
+        true
+    }
+
+    pub fn metrics(&self) -> DropMetrics {
+        self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(input: &str) -> InputFilter {
+        let input: Input = serde_yaml::from_str(input).unwrap();
+        InputFilter::new(&input).unwrap()
+    }
+
+    #[test]
+    fn decimate() {
+        let mut filter = filter("{ source: camera/image, decimate: { keep_every: 3 } }");
+        let delivered: Vec<_> = (0..7)
+            .filter(|i| filter.check_inner(Duration::from_millis(*i)))
+            .collect();
+        assert_eq!(delivered, [0, 3, 6]);
+    }
+
+    #[test]
+    fn throttle() {
+        let mut filter = filter("{ source: camera/image, throttle: { max_rate: 10Hz } }");
+        // 30 Hz stream for one second
+        let delivered = (0..30)
+            .filter(|i| filter.check_inner(Duration::from_secs_f64(*i as f64 / 30.0)))
+            .count();
+        assert_eq!(delivered, 10);
+    }
+
+    #[test]
+    fn tiny_rates_are_rejected() {
+        let result: Result<Input, _> =
+            serde_yaml::from_str("{ source: camera/image, throttle: { max_rate: 1e-20Hz } }");
+        assert!(result.is_err());
+
+        let mut filter = filter("{ source: camera/image, throttle: { max_rate: 1e-9Hz } }");
+        assert!(filter.check_inner(Duration::ZERO));
+        assert!(!filter.check_inner(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn unfiltered_input() {
+        let input: Input = serde_yaml::from_str("camera/image").unwrap();
+        assert!(InputFilter::new(&input).is_none());
+    }
+}
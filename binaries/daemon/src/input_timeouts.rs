@@ -0,0 +1,126 @@
+//! Timeouts of inputs with a configured `timeout`.
+//!
+//! The timeout of an input starts when its node subscribes to its events and
+//! is reset by every message that is delivered to the input. Once it
+//! elapses, the node is sent an `InputTimeout` event, which is repeated after
+//! every further `timeout` until the next message arrives. Closed inputs are
+//! no longer watched.
+
+use std::time::{Duration, Instant};
+
+/// Shortest interval at which the timeouts are checked.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+pub struct InputTimeout {
+    timeout: Duration,
+    /// Start of the current silence, `None` until the node subscribed.
+    silent_since: Option<Instant>,
+    /// Number of timeout events that were reported for the current silence.
+    reported: u32,
+}
+
+impl InputTimeout {
+    /// Watches an input with the given timeout, starting at `started`.
+    ///
+    /// If `started` is `None`, the timeout only starts once
+    /// [`start`][Self::start] is called, e.g. when the node subscribes.
+    pub fn new(timeout: Duration, started: Option<Instant>) -> Self {
+        Self {
+            timeout,
+            silent_since: started,
+            reported: 0,
+        }
+    }
+
+    /// Starts the timeout, e.g. because the node subscribed to its events.
+    pub fn start(&mut self, now: Instant) {
+        self.silent_since = Some(now);
+        self.reported = 0;
+    }
+
+    /// Changes the timeout, e.g. through a `Reconfigure` request.
+    ///
+    /// The current silence is kept.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        self.reported = 0;
+    }
+
+    /// Resets the timeout after a message was delivered.
+    pub fn reset(&mut self, now: Instant) {
+        if self.silent_since.is_some() {
+            self.silent_since = Some(now);
+            self.reported = 0;
+        }
+    }
+
+    /// Interval at which [`expired`][Self::expired] needs to be called.
+    pub fn check_interval(&self) -> Duration {
+        (self.timeout / 10).max(MIN_CHECK_INTERVAL)
+    }
+
+    /// Returns the time since the last message if the timeout elapsed since
+    /// the last call.
+    pub fn expired(&mut self, now: Instant) -> Option<Duration> {
+        let silent_since = self.silent_since?;
+        let elapsed = now.saturating_duration_since(silent_since);
+        let due = self.timeout.saturating_mul(self.reported.saturating_add(1));
+        if elapsed < due {
+            return None;
+        }
+        // report once per check, even if multiple timeouts elapsed
+        self.reported = (elapsed.as_nanos() / self.timeout.as_nanos())
+            .try_into()
+            .unwrap_or(u32::MAX);
+        Some(elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts_repeat_until_next_message() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut timeout = InputTimeout::new(Duration::from_millis(100), None);
+        assert_eq!(timeout.check_interval(), Duration::from_millis(10));
+        // not started before the node subscribed
+        assert!(timeout.expired(at(500)).is_none());
+
+        timeout.start(at(500));
+        assert!(timeout.expired(at(590)).is_none());
+        assert_eq!(timeout.expired(at(605)), Some(Duration::from_millis(105)));
+        assert!(timeout.expired(at(650)).is_none());
+        assert!(timeout.expired(at(700)).is_some());
+
+        timeout.reset(at(720));
+        assert!(timeout.expired(at(800)).is_none());
+        assert!(timeout.expired(at(820)).is_some());
+
+        // a delayed check only reports once
+        assert!(timeout.expired(at(1200)).is_some());
+        assert!(timeout.expired(at(1210)).is_none());
+    }
+
+    #[test]
+    fn changed_timeouts_keep_the_current_silence() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut cmd = InputTimeout::new(Duration::from_millis(100), None);
+        cmd.start(at(0));
+        cmd.set_timeout(Duration::from_millis(50));
+        assert_eq!(cmd.check_interval(), Duration::from_millis(5));
+        assert_eq!(cmd.expired(at(60)), Some(Duration::from_millis(60)));
+
+        // newly watched inputs of running nodes start right away
+        let mut pose = InputTimeout::new(Duration::from_millis(100), Some(at(60)));
+        assert_eq!(cmd.expired(at(150)), Some(Duration::from_millis(150)));
+        assert!(pose.expired(at(150)).is_none());
+        assert_eq!(pose.expired(at(160)), Some(Duration::from_millis(100)));
+    }
+}
@@ -1,65 +1,392 @@
+//! TCP connections between the daemons of a dataflow.
+//!
+//! Events are queued per connection and written by a separate task. Outputs
+//! whose data exceeds [`OUTPUT_CHUNK_SIZE`] are split into
+//! [`InterDaemonEvent::OutputChunk`] events, which are reassembled by the
+//! receiving daemon. The task alternates between the outputs of different
+//! nodes, sending one chunk or whole message of each output in turn, so that
+//! large messages don't delay the messages of other outputs by more than a
+//! chunk. Messages of the same output are sent in order. All other events
+//! are only sent after the outputs that were queued before them.
+//!
+//! Each connection starts with an [`InterDaemonHello`], so that events of
+//! daemons with an incompatible message format are not misinterpreted.
+
 use crate::socket_stream_utils::{socket_stream_receive, socket_stream_send};
-use dora_message::{common::Timestamped, daemon_to_daemon::InterDaemonEvent};
+use aligned_vec::{AVec, ConstAlign};
+use dora_core::{
+    config::{DataId, NodeId},
+    uhlc,
+};
+use dora_message::{
+    common::Timestamped,
+    daemon_to_daemon::{InterDaemonEvent, InterDaemonHello, OutputChunk},
+    metadata::Metadata,
+    DataflowId,
+};
 use eyre::{Context, ContextCompat};
-use std::{collections::BTreeMap, io::ErrorKind, net::SocketAddr};
-use tokio::net::{TcpListener, TcpStream};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{Notify, OwnedSemaphorePermit, Semaphore},
+};
+use uuid::Uuid;
+
+/// Outputs with more data are sent in chunks of this size.
+pub const OUTPUT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Number of events that can be queued for a connection before sending
+/// waits for the queue to drain.
+const MAX_QUEUED_EVENTS: usize = 64;
 
 pub struct InterDaemonConnection {
     socket: SocketAddr,
-    connection: Option<TcpStream>,
+    queue: Arc<SendQueue>,
+    /// The writer task is spawned on first use.
+    writer_spawned: bool,
 }
 
 impl InterDaemonConnection {
     pub fn new(socket: SocketAddr) -> Self {
         Self {
             socket,
-            connection: None,
-        }
-    }
-
-    #[tracing::instrument(skip(self), fields(%self.socket))]
-    async fn connect(&mut self) -> eyre::Result<&mut TcpStream> {
-        match &mut self.connection {
-            Some(c) => Ok(c),
-            entry @ None => {
-                let connection = TcpStream::connect(self.socket)
-                    .await
-                    .wrap_err("failed to connect")?;
-                connection
-                    .set_nodelay(true)
-                    .wrap_err("failed to set nodelay")?;
-                Ok(entry.insert(connection))
-            }
+            queue: Arc::new(SendQueue {
+                state: Mutex::new(QueueState::default()),
+                wake: Notify::new(),
+                slots: Arc::new(Semaphore::new(MAX_QUEUED_EVENTS)),
+            }),
+            writer_spawned: false,
         }
     }
 
     pub fn socket(&self) -> SocketAddr {
         self.socket
     }
+
+    /// Queues the given event for sending, waiting while the queue is full.
+    async fn send(&mut self, event: Outgoing) -> eyre::Result<()> {
+        let permit = self
+            .queue
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .context("send queue was closed")?;
+        if !self.writer_spawned {
+            tokio::spawn(write_loop(self.socket, self.queue.clone()));
+            self.writer_spawned = true;
+        }
+        self.queue.state().outgoing.push(event, permit);
+        self.queue.wake.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for InterDaemonConnection {
+    fn drop(&mut self) {
+        // the writer task sends the remaining events before it exits
+        self.queue.state().closed = true;
+        self.queue.wake.notify_one();
+    }
 }
 
-#[tracing::instrument(skip(inter_daemon_connections))]
+/// Queues the given event for sending to the given machines.
+///
+/// Errors that happen while sending are logged by the writer task of the
+/// connection.
+#[tracing::instrument(skip(inter_daemon_connections, event))]
 pub async fn send_inter_daemon_event(
     target_machines: &[String],
     inter_daemon_connections: &mut BTreeMap<String, InterDaemonConnection>,
-    event: &Timestamped<InterDaemonEvent>,
+    event: Timestamped<InterDaemonEvent>,
 ) -> eyre::Result<()> {
-    let message = bincode::serialize(event).wrap_err("failed to serialize InterDaemonEvent")?;
+    let event = Outgoing::new(event)?;
     for target_machine in target_machines {
-        let connection = inter_daemon_connections
+        inter_daemon_connections
             .get_mut(target_machine)
             .wrap_err_with(|| format!("unknown target machine `{target_machine}`"))?
-            .connect()
+            .send(event.clone())
             .await
-            .wrap_err_with(|| format!("failed to connect to machine `{target_machine}`"))?;
-        socket_stream_send(connection, &message)
-            .await
-            .wrap_err_with(|| format!("failed to send event to machine `{target_machine}`"))?;
+            .wrap_err_with(|| format!("failed to queue event for machine `{target_machine}`"))?;
     }
 
     Ok(())
 }
 
+struct SendQueue {
+    state: Mutex<QueueState>,
+    /// Wakes the writer task when events are queued or the connection is
+    /// dropped.
+    wake: Notify,
+    /// Each queued event holds a permit until it is sent completely.
+    slots: Arc<Semaphore>,
+}
+
+impl SendQueue {
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    outgoing: OutgoingQueue,
+    closed: bool,
+}
+
+async fn write_loop(socket: SocketAddr, queue: Arc<SendQueue>) {
+    let mut connection = None;
+    loop {
+        let frame = {
+            let mut state = queue.state();
+            match state.outgoing.next_frame() {
+                Some(frame) => frame,
+                None if state.closed => break,
+                None => {
+                    drop(state);
+                    queue.wake.notified().await;
+                    continue;
+                }
+            }
+        };
+        let result = async {
+            let frame = frame?;
+            let stream = connect(&mut connection, socket).await?;
+            socket_stream_send(stream, &frame)
+                .await
+                .wrap_err("failed to send event")
+        }
+        .await;
+        if let Err(err) = result {
+            tracing::warn!("failed to send event to daemon at {socket}: {err:?}");
+            // reconnect for the next event
+            connection = None;
+        }
+    }
+}
+
+#[tracing::instrument(skip(connection))]
+async fn connect(
+    connection: &mut Option<TcpStream>,
+    socket: SocketAddr,
+) -> eyre::Result<&mut TcpStream> {
+    match connection {
+        Some(c) => Ok(c),
+        entry @ None => {
+            let connection = TcpStream::connect(socket)
+                .await
+                .wrap_err("failed to connect")?;
+            connection
+                .set_nodelay(true)
+                .wrap_err("failed to set nodelay")?;
+            let connection = entry.insert(connection);
+            let hello = bincode::serialize(&InterDaemonHello::current())
+                .wrap_err("failed to serialize InterDaemonHello")?;
+            socket_stream_send(connection, &hello)
+                .await
+                .wrap_err("failed to send hello")?;
+            Ok(connection)
+        }
+    }
+}
+
+/// An event that is ready to be queued for one or more connections.
+#[derive(Clone)]
+enum Outgoing {
+    Output {
+        stream: StreamKey,
+        message: OutputMessage,
+    },
+    /// Any other event, which must not overtake the outputs that were queued
+    /// before it.
+    Barrier(Arc<Vec<u8>>),
+}
+
+type StreamKey = (DataflowId, NodeId, DataId);
+
+#[derive(Clone)]
+enum OutputMessage {
+    /// Serialized event that is sent in a single frame.
+    Whole(Arc<Vec<u8>>),
+    Chunked(Arc<ChunkedOutput>),
+}
+
+struct ChunkedOutput {
+    dataflow_id: DataflowId,
+    node_id: NodeId,
+    output_id: DataId,
+    message_id: Uuid,
+    metadata: Metadata,
+    data: AVec<u8, ConstAlign<128>>,
+    timestamp: uhlc::Timestamp,
+    total: u32,
+}
+
+impl Outgoing {
+    fn new(event: Timestamped<InterDaemonEvent>) -> eyre::Result<Self> {
+        let serialize = |event: &Timestamped<InterDaemonEvent>| {
+            bincode::serialize(event)
+                .map(Arc::new)
+                .wrap_err("failed to serialize InterDaemonEvent")
+        };
+        match event.inner {
+            InterDaemonEvent::Output {
+                dataflow_id,
+                node_id,
+                output_id,
+                metadata,
+                data: Some(data),
+            } if data.len() > OUTPUT_CHUNK_SIZE => {
+                let total = u32::try_from(data.len().div_ceil(OUTPUT_CHUNK_SIZE))
+                    .context("output is too large to be sent in chunks")?;
+                Ok(Outgoing::Output {
+                    stream: (dataflow_id, node_id.clone(), output_id.clone()),
+                    message: OutputMessage::Chunked(Arc::new(ChunkedOutput {
+                        dataflow_id,
+                        node_id,
+                        output_id,
+                        message_id: Uuid::new_v4(),
+                        metadata,
+                        data,
+                        timestamp: event.timestamp,
+                        total,
+                    })),
+                })
+            }
+            InterDaemonEvent::Output {
+                dataflow_id,
+                ref node_id,
+                ref output_id,
+                ..
+            } => Ok(Outgoing::Output {
+                stream: (dataflow_id, node_id.clone(), output_id.clone()),
+                message: OutputMessage::Whole(serialize(&event)?),
+            }),
+            _ => Ok(Outgoing::Barrier(serialize(&event)?)),
+        }
+    }
+}
+
+impl ChunkedOutput {
+    fn chunk_frame(&self, index: u32) -> eyre::Result<Vec<u8>> {
+        let start = index as usize * OUTPUT_CHUNK_SIZE;
+        let end = (start + OUTPUT_CHUNK_SIZE).min(self.data.len());
+        let event = Timestamped {
+            inner: InterDaemonEvent::OutputChunk(OutputChunk {
+                dataflow_id: self.dataflow_id,
+                node_id: self.node_id.clone(),
+                output_id: self.output_id.clone(),
+                message_id: self.message_id,
+                index,
+                total: self.total,
+                len: self.data.len() as u64,
+                metadata: (index == 0).then(|| self.metadata.clone()),
+                data: self.data[start..end].to_vec(),
+            }),
+            timestamp: self.timestamp,
+        };
+        bincode::serialize(&event).wrap_err("failed to serialize output chunk")
+    }
+}
+
+/// Events that wait to be sent on a connection, see the module docs for
+/// the sending order.
+#[derive(Default)]
+struct OutgoingQueue {
+    /// Each epoch ends with an optional barrier event, which is sent after
+    /// all outputs of the epoch.
+    epochs: VecDeque<Epoch>,
+}
+
+#[derive(Default)]
+struct Epoch {
+    /// Served round-robin, one frame at a time.
+    streams: VecDeque<Stream>,
+    barrier: Option<(Arc<Vec<u8>>, OwnedSemaphorePermit)>,
+}
+
+struct Stream {
+    key: StreamKey,
+    /// Never empty.
+    outputs: VecDeque<QueuedOutput>,
+}
+
+struct QueuedOutput {
+    message: OutputMessage,
+    next_index: u32,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl OutgoingQueue {
+    fn push(&mut self, event: Outgoing, permit: OwnedSemaphorePermit) {
+        if !self.epochs.back().is_some_and(|e| e.barrier.is_none()) {
+            self.epochs.push_back(Epoch::default());
+        }
+        let epoch = self.epochs.back_mut().expect("epoch was pushed above");
+        match event {
+            Outgoing::Output { stream, message } => {
+                let output = QueuedOutput {
+                    message,
+                    next_index: 0,
+                    _permit: permit,
+                };
+                match epoch.streams.iter_mut().find(|s| s.key == stream) {
+                    Some(stream) => stream.outputs.push_back(output),
+                    None => epoch.streams.push_back(Stream {
+                        key: stream,
+                        outputs: VecDeque::from([output]),
+                    }),
+                }
+            }
+            Outgoing::Barrier(frame) => epoch.barrier = Some((frame, permit)),
+        }
+    }
+
+    /// Takes the next frame that should be written to the connection.
+    fn next_frame(&mut self) -> Option<eyre::Result<Arc<Vec<u8>>>> {
+        loop {
+            let epoch = self.epochs.front_mut()?;
+            if let Some(mut stream) = epoch.streams.pop_front() {
+                let output = stream.outputs.front_mut()?;
+                let (frame, done) = output.next_frame();
+                if done {
+                    stream.outputs.pop_front();
+                }
+                if !stream.outputs.is_empty() {
+                    epoch.streams.push_back(stream);
+                }
+                return Some(frame);
+            }
+            if let Some(epoch) = self.epochs.pop_front() {
+                if let Some((frame, _permit)) = epoch.barrier {
+                    return Some(Ok(frame));
+                }
+            }
+        }
+    }
+}
+
+impl QueuedOutput {
+    /// Returns the next frame of the message and whether it was the last.
+    fn next_frame(&mut self) -> (eyre::Result<Arc<Vec<u8>>>, bool) {
+        match &self.message {
+            OutputMessage::Whole(frame) => (Ok(frame.clone()), true),
+            OutputMessage::Chunked(output) => {
+                let frame = output.chunk_frame(self.next_index);
+                self.next_index += 1;
+                // the receiver discards messages with missing chunks
+                let done = frame.is_err() || self.next_index >= output.total;
+                (frame.map(Arc::new), done)
+            }
+        }
+    }
+}
+
 pub async fn spawn_listener_loop(
     bind: SocketAddr,
     machine_id: String,
@@ -111,6 +438,10 @@ async fn handle_connection_loop(
     if let Err(err) = connection.set_nodelay(true) {
         tracing::warn!("failed to set nodelay for connection: {err}");
     }
+    if let Err(err) = receive_hello(&mut connection).await {
+        tracing::warn!("{err:?}");
+        return;
+    }
 
     loop {
         match receive_message(&mut connection).await {
@@ -128,6 +459,19 @@ async fn handle_connection_loop(
     }
 }
 
+async fn receive_hello(connection: &mut TcpStream) -> eyre::Result<()> {
+    let raw = socket_stream_receive(connection)
+        .await
+        .context("failed to receive hello of remote daemon")?;
+    let hello: InterDaemonHello = bincode::deserialize(&raw).wrap_err(
+        "failed to deserialize hello of remote daemon, it probably uses an older dora version",
+    )?;
+    hello
+        .check_version()
+        .map_err(|err| eyre::eyre!(err))
+        .wrap_err("rejecting connection of remote daemon")
+}
+
 async fn receive_message(
     connection: &mut TcpStream,
 ) -> eyre::Result<Option<Timestamped<InterDaemonEvent>>> {
@@ -147,3 +491,158 @@ async fn receive_message(
         .wrap_err("failed to deserialize DaemonRequest")
         .map(Some)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reassembly::PartialOutputs;
+    use dora_core::uhlc::HLC;
+    use dora_message::metadata::ArrowTypeInfo;
+
+    fn output(clock: &HLC, output_id: &str, data: &[u8]) -> Timestamped<InterDaemonEvent> {
+        Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id: Uuid::nil(),
+                node_id: NodeId::from("camera".to_owned()),
+                output_id: DataId::from(output_id.to_owned()),
+                metadata: Metadata::new(
+                    clock.new_timestamp(),
+                    ArrowTypeInfo::byte_array(data.len()),
+                ),
+                data: Some(AVec::from_slice(128, data)),
+            },
+            timestamp: clock.new_timestamp(),
+        }
+    }
+
+    fn decode(frame: &[u8]) -> InterDaemonEvent {
+        bincode::deserialize::<Timestamped<InterDaemonEvent>>(frame)
+            .unwrap()
+            .inner
+    }
+
+    #[test]
+    fn large_outputs_are_interleaved_with_other_outputs() {
+        let clock = HLC::default();
+        let slots = Arc::new(Semaphore::new(MAX_QUEUED_EVENTS));
+        let mut queue = OutgoingQueue::default();
+        let mut push = |event| {
+            let permit = slots.clone().try_acquire_owned().unwrap();
+            queue.push(Outgoing::new(event).unwrap(), permit);
+        };
+
+        let large = vec![1; 8 * OUTPUT_CHUNK_SIZE + 1];
+        push(output(&clock, "image", &large));
+        for i in 0..3 {
+            push(output(&clock, "pose", &[i]));
+        }
+        push(Timestamped {
+            inner: InterDaemonEvent::OutputClosed {
+                dataflow_id: Uuid::nil(),
+                node_id: NodeId::from("camera".to_owned()),
+                output_id: DataId::from("pose".to_owned()),
+            },
+            timestamp: clock.new_timestamp(),
+        });
+
+        let mut frames = Vec::new();
+        while let Some(frame) = queue.next_frame() {
+            frames.push(decode(&frame.unwrap()));
+        }
+        assert_eq!(frames.len(), 9 + 3 + 1);
+        let mut chunks = 0;
+        let mut small = Vec::new();
+        for (position, event) in frames.iter().enumerate() {
+            match event {
+                InterDaemonEvent::OutputChunk(chunk) => {
+                    assert_eq!(chunk.index, chunks);
+                    chunks += 1;
+                }
+                InterDaemonEvent::Output { data, .. } => {
+                    small.push(data.as_deref().unwrap()[0]);
+                    // at most one chunk of the large output is sent before
+                    // each small output
+                    assert!(position < 2 * small.len());
+                }
+                InterDaemonEvent::OutputClosed { .. } => assert_eq!(position, frames.len() - 1),
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+        assert_eq!(chunks, 9);
+        assert_eq!(small, [0, 1, 2]);
+        // all events were sent, so their queue slots are free again
+        assert_eq!(slots.available_permits(), MAX_QUEUED_EVENTS);
+    }
+
+    #[tokio::test]
+    async fn chunked_outputs_are_reassembled_by_the_receiver() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let machine = "remote".to_owned();
+        let mut connections = BTreeMap::new();
+        connections.insert(
+            machine.clone(),
+            InterDaemonConnection::new(listener.local_addr().unwrap()),
+        );
+
+        let clock = HLC::default();
+        let large: Vec<u8> = (0..4 * OUTPUT_CHUNK_SIZE)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let targets = [machine];
+        send_inter_daemon_event(&targets, &mut connections, output(&clock, "image", &large))
+            .await
+            .unwrap();
+        send_inter_daemon_event(&targets, &mut connections, output(&clock, "pose", &[42]))
+            .await
+            .unwrap();
+
+        let (mut connection, _) = listener.accept().await.unwrap();
+        receive_hello(&mut connection).await.unwrap();
+        let mut partial = PartialOutputs::default();
+        let mut received_chunks = 0;
+        let reassembled = loop {
+            let event = receive_message(&mut connection).await.unwrap().unwrap();
+            match event.inner {
+                InterDaemonEvent::OutputChunk(chunk) => {
+                    received_chunks += 1;
+                    if let Some(output) = partial.add_chunk(chunk).unwrap() {
+                        break output;
+                    }
+                }
+                InterDaemonEvent::Output { data, .. } => {
+                    assert_eq!(data.as_deref(), Some(&[42][..]));
+                    // the small output was not delayed until the large
+                    // output was sent completely
+                    assert!(received_chunks <= 1);
+                }
+                other => panic!("unexpected event {other:?}"),
+            }
+        };
+        assert_eq!(received_chunks, 4);
+        assert_eq!(reassembled.len, large.len());
+        assert_eq!(
+            &unsafe { reassembled.region.as_slice() }[..reassembled.len],
+            &large[..]
+        );
+    }
+
+    #[tokio::test]
+    async fn connections_of_incompatible_daemons_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut sender = TcpStream::connect(addr).await.unwrap();
+        let (mut receiver, _) = listener.accept().await.unwrap();
+
+        // older daemons start with their first event
+        let clock = HLC::default();
+        let event = bincode::serialize(&output(&clock, "pose", &[42])).unwrap();
+        socket_stream_send(&mut sender, &event).await.unwrap();
+        assert!(receive_hello(&mut receiver).await.is_err());
+
+        let mut sender = TcpStream::connect(addr).await.unwrap();
+        let (mut receiver, _) = listener.accept().await.unwrap();
+        let hello = bincode::serialize(&InterDaemonHello::current()).unwrap();
+        socket_stream_send(&mut sender, &hello).await.unwrap();
+        receive_hello(&mut receiver).await.unwrap();
+    }
+}
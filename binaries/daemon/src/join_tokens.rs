@@ -0,0 +1,97 @@
+//! Short-lived tokens for connections to the local listener of the daemon.
+//!
+//! The local listener accepts dynamic nodes and observers. Connections from
+//! the machine of the daemon are trusted, like the nodes that it spawns.
+//! Connections from other machines first need to send a token that the
+//! daemon minted on request of the coordinator, see
+//! `DaemonCoordinatorEvent::MintToken`. A token is only valid for a single
+//! node and expires after [`JOIN_TOKEN_VALIDITY`], but it can be used for
+//! any number of connections until then.
+
+use dora_core::config::NodeId;
+use dora_message::DataflowId;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Time in which a minted token needs to be used.
+pub const JOIN_TOKEN_VALIDITY: Duration = Duration::from_secs(60);
+
+/// The tokens that the daemon minted, shared with the local listener.
+#[derive(Debug, Clone, Default)]
+pub struct JoinTokens {
+    grants: Arc<Mutex<HashMap<String, Grant>>>,
+}
+
+/// The node that a token was minted for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub dataflow_id: DataflowId,
+    pub node_id: NodeId,
+    expires: Instant,
+}
+
+impl JoinTokens {
+    /// Creates a new token for the given node.
+    pub fn mint(&self, dataflow_id: DataflowId, node_id: NodeId, now: Instant) -> String {
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let mut grants = self.grants.lock().unwrap();
+        grants.retain(|_, grant| grant.expires > now);
+        grants.insert(
+            token.clone(),
+            Grant {
+                dataflow_id,
+                node_id,
+                expires: now + JOIN_TOKEN_VALIDITY,
+            },
+        );
+        token
+    }
+
+    /// Returns the node that the given token was minted for, if the token
+    /// is known and not expired.
+    pub fn check(&self, token: &str, now: Instant) -> Result<Grant, String> {
+        let mut grants = self.grants.lock().unwrap();
+        grants.retain(|_, grant| grant.expires > now);
+        grants
+            .get(token)
+            .cloned()
+            .ok_or_else(|| "unknown or expired join token".to_owned())
+    }
+}
+
+impl Grant {
+    pub fn allows(&self, dataflow_id: Option<DataflowId>, node_id: &NodeId) -> bool {
+        dataflow_id.map_or(true, |id| id == self.dataflow_id) && node_id == &self.node_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_valid_for_their_node_until_they_expire() {
+        let tokens = JoinTokens::default();
+        let now = Instant::now();
+        let dataflow_id = DataflowId::new_v4();
+        let camera = NodeId::from("camera".to_owned());
+        let token = tokens.mint(dataflow_id, camera.clone(), now);
+        let other = tokens.mint(dataflow_id, NodeId::from("plot".to_owned()), now);
+        assert_ne!(token, other);
+
+        let grant = tokens.check(&token, now + Duration::from_secs(1)).unwrap();
+        assert!(grant.allows(Some(dataflow_id), &camera));
+        assert!(grant.allows(None, &camera));
+        assert!(!grant.allows(Some(DataflowId::new_v4()), &camera));
+        assert!(!grant.allows(Some(dataflow_id), &NodeId::from("plot".to_owned())));
+
+        assert!(tokens.check("guess", now).is_err());
+        assert!(tokens.check(&token, now + JOIN_TOKEN_VALIDITY).is_err());
+        // expired tokens are removed
+        assert!(tokens.check(&token, now).is_err());
+    }
+}
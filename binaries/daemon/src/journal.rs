@@ -0,0 +1,432 @@
+//! Append-only journal of daemon lifecycle events for post-mortem debugging.
+//!
+//! Records are written as JSON lines with HLC timestamps. To keep the journal
+//! off the hot path, records are sent through a bounded channel to a
+//! dedicated writer thread. Records are dropped (and counted) if the writer
+//! falls behind. Journal files are rotated once they exceed the configured
+//! size.
+
+use dora_core::{config::NodeId, descriptor::Descriptor, uhlc};
+use dora_message::DataflowId;
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+const JOURNAL_FILE_NAME: &str = "journal";
+const JOURNAL_FILE_EXTENSION: &str = "jsonl";
+/// Number of rotated journal files that are kept in addition to the current one.
+const ROTATED_FILES: usize = 4;
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct JournalConfig {
+    /// Directory in which the journal files are stored.
+    pub dir: PathBuf,
+    /// Size in bytes after which the journal file is rotated.
+    pub max_file_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub timestamp: uhlc::Timestamp,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEvent {
+    DaemonStarted {
+        machine_id: String,
+        version: String,
+    },
+    DaemonStopped {
+        error: Option<String>,
+    },
+    CoordinatorConnected {
+        address: String,
+    },
+    CoordinatorDisconnected {
+        reason: String,
+    },
+    DataflowSpawned {
+        dataflow_id: DataflowId,
+        descriptor_hash: String,
+    },
+    NodeSpawned {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        pid: Option<u32>,
+    },
+    NodeSubscribed {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+    NodeStopped {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        /// `None` if the node finished successfully.
+        error: Option<String>,
+    },
+    MessagesDropped {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        input_id: String,
+        filtered: u64,
+        superseded: u64,
+    },
+    /// Journal records that were dropped because the writer fell behind.
+    RecordsDropped {
+        count: u64,
+    },
+}
+
+impl fmt::Display for JournalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalEvent::DaemonStarted {
+                machine_id,
+                version,
+            } => write!(
+                f,
+                "daemon started (machine `{machine_id}`, version {version})"
+            ),
+            JournalEvent::DaemonStopped { error: None } => write!(f, "daemon stopped"),
+            JournalEvent::DaemonStopped { error: Some(err) } => {
+                write!(f, "daemon stopped with error: {err}")
+            }
+            JournalEvent::CoordinatorConnected { address } => {
+                write!(f, "connected to coordinator at {address}")
+            }
+            JournalEvent::CoordinatorDisconnected { reason } => {
+                write!(f, "disconnected from coordinator: {reason}")
+            }
+            JournalEvent::DataflowSpawned {
+                dataflow_id,
+                descriptor_hash,
+            } => write!(
+                f,
+                "dataflow {dataflow_id} spawned (descriptor {descriptor_hash})"
+            ),
+            JournalEvent::NodeSpawned {
+                dataflow_id,
+                node_id,
+                pid,
+            } => {
+                write!(f, "node {dataflow_id}/{node_id} spawned")?;
+                match pid {
+                    Some(pid) => write!(f, " (pid {pid})"),
+                    None => Ok(()),
+                }
+            }
+            JournalEvent::NodeSubscribed {
+                dataflow_id,
+                node_id,
+            } => write!(f, "node {dataflow_id}/{node_id} subscribed"),
+            JournalEvent::NodeStopped {
+                dataflow_id,
+                node_id,
+                error: None,
+            } => write!(f, "node {dataflow_id}/{node_id} finished successfully"),
+            JournalEvent::NodeStopped {
+                dataflow_id,
+                node_id,
+                error: Some(err),
+            } => write!(f, "node {dataflow_id}/{node_id} failed: {err}"),
+            JournalEvent::MessagesDropped {
+                dataflow_id,
+                node_id,
+                input_id,
+                filtered,
+                superseded,
+            } => write!(
+                f,
+                "input {dataflow_id}/{node_id}/{input_id} dropped messages \
+                (filtered: {filtered}, superseded: {superseded})"
+            ),
+            JournalEvent::RecordsDropped { count } => {
+                write!(f, "{count} journal records were dropped")
+            }
+        }
+    }
+}
+
+/// Owns the writer thread of the journal.
+///
+/// Records are written through [`JournalHandle`]s. Use [`Journal::close`] to
+/// wait until all records are written.
+pub struct Journal {
+    handle: JournalHandle,
+    writer: JoinHandle<()>,
+}
+
+impl Journal {
+    pub fn open(config: JournalConfig, clock: Arc<uhlc::HLC>) -> eyre::Result<Self> {
+        fs::create_dir_all(&config.dir).wrap_err_with(|| {
+            format!(
+                "failed to create journal directory `{}`",
+                config.dir.display()
+            )
+        })?;
+        let file = JournalFile::open(config)?;
+
+        let (sender, receiver) = flume::bounded(CHANNEL_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let dropped = dropped.clone();
+            let clock = clock.clone();
+            std::thread::Builder::new()
+                .name("dora-daemon-journal".into())
+                .spawn(move || {
+                    if let Err(err) = write_records(file, receiver, &dropped, &clock) {
+                        tracing::warn!("journal writer failed: {err:?}");
+                    }
+                })
+                .wrap_err("failed to spawn journal writer thread")?
+        };
+
+        Ok(Self {
+            handle: JournalHandle {
+                sender,
+                dropped,
+                clock,
+            },
+            writer,
+        })
+    }
+
+    pub fn handle(&self) -> JournalHandle {
+        self.handle.clone()
+    }
+
+    pub fn record(&self, event: JournalEvent) {
+        self.handle.record(event)
+    }
+
+    /// Waits until all pending records are written.
+    ///
+    /// All handles need to be dropped before, otherwise this blocks forever.
+    pub fn close(self) {
+        let Self { handle, writer } = self;
+        drop(handle);
+        if writer.join().is_err() {
+            tracing::warn!("journal writer thread panicked");
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JournalHandle {
+    sender: flume::Sender<JournalRecord>,
+    dropped: Arc<AtomicU64>,
+    clock: Arc<uhlc::HLC>,
+}
+
+impl JournalHandle {
+    /// Queues the given event for writing, never blocks.
+    pub fn record(&self, event: JournalEvent) {
+        let record = JournalRecord {
+            timestamp: self.clock.new_timestamp(),
+            event,
+        };
+        if self.sender.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Hash of the dataflow descriptor, to check which version of a dataflow was spawned.
+pub fn descriptor_hash(descriptor: &Descriptor) -> String {
+    let serialized = serde_yaml::to_string(descriptor).unwrap_or_default();
+    let hash = Sha256::digest(serialized.as_bytes());
+    format!("{hash:x}")
+}
+
+fn write_records(
+    mut file: JournalFile,
+    receiver: flume::Receiver<JournalRecord>,
+    dropped: &AtomicU64,
+    clock: &uhlc::HLC,
+) -> eyre::Result<()> {
+    while let Ok(record) = receiver.recv() {
+        file.write(&record)?;
+        for record in receiver.try_iter() {
+            file.write(&record)?;
+        }
+        let count = dropped.swap(0, Ordering::Relaxed);
+        if count > 0 {
+            file.write(&JournalRecord {
+                timestamp: clock.new_timestamp(),
+                event: JournalEvent::RecordsDropped { count },
+            })?;
+        }
+        file.flush()?;
+    }
+    Ok(())
+}
+
+struct JournalFile {
+    config: JournalConfig,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl JournalFile {
+    fn open(config: JournalConfig) -> eyre::Result<Self> {
+        let path = journal_path(&config.dir, 0);
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .wrap_err_with(|| format!("failed to open journal file `{}`", path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            config,
+            writer: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn write(&mut self, record: &JournalRecord) -> eyre::Result<()> {
+        if self.size >= self.config.max_file_size {
+            self.rotate()?;
+        }
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Renames `journal.jsonl` to `journal.1.jsonl`, `journal.1.jsonl` to
+    /// `journal.2.jsonl`, and so on, and starts a new journal file.
+    fn rotate(&mut self) -> eyre::Result<()> {
+        self.flush()?;
+        let dir = &self.config.dir;
+        let oldest = journal_path(dir, ROTATED_FILES);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for index in (0..ROTATED_FILES).rev() {
+            let path = journal_path(dir, index);
+            if path.exists() {
+                fs::rename(&path, journal_path(dir, index + 1))?;
+            }
+        }
+        *self = Self::open(self.config.clone())?;
+        Ok(())
+    }
+}
+
+fn journal_path(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join(format!("{JOURNAL_FILE_NAME}.{JOURNAL_FILE_EXTENSION}")),
+        index => dir.join(format!(
+            "{JOURNAL_FILE_NAME}.{index}.{JOURNAL_FILE_EXTENSION}"
+        )),
+    }
+}
+
+/// Pretty-prints the given journal with timestamps relative to the first record.
+///
+/// If `path` is a journal directory, all journal files in it are printed,
+/// starting with the oldest.
+pub fn dump(path: &Path) -> eyre::Result<()> {
+    let files: Vec<_> = if path.is_dir() {
+        (0..=ROTATED_FILES)
+            .rev()
+            .map(|index| journal_path(path, index))
+            .filter(|path| path.exists())
+            .collect()
+    } else {
+        vec![path.to_owned()]
+    };
+
+    let mut stdout = io::stdout().lock();
+    let mut start = None;
+    for file in files {
+        let reader = BufReader::new(
+            File::open(&file)
+                .wrap_err_with(|| format!("failed to open journal `{}`", file.display()))?,
+        );
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: JournalRecord = serde_json::from_str(&line).wrap_err_with(|| {
+                format!(
+                    "invalid record in line {} of `{}`",
+                    index + 1,
+                    file.display()
+                )
+            })?;
+            let time = record.timestamp.get_time().to_duration();
+            let start = *start.get_or_insert(time);
+            let relative = time.saturating_sub(start);
+            writeln!(
+                stdout,
+                "+{:>10.3}s  {}",
+                relative.as_secs_f64(),
+                record.event
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_files_are_rotated() {
+        let dir = std::env::temp_dir().join(format!("dora-journal-test-{}", uuid::Uuid::new_v4()));
+        let config = JournalConfig {
+            dir: dir.clone(),
+            max_file_size: 200,
+        };
+        let journal = Journal::open(config, Arc::new(uhlc::HLC::default())).unwrap();
+        for _ in 0..20 {
+            journal.record(JournalEvent::CoordinatorConnected {
+                address: "127.0.0.1:53290".into(),
+            });
+        }
+        journal.close();
+
+        let mut records = 0;
+        for index in 0..=ROTATED_FILES {
+            let path = journal_path(&dir, index);
+            assert!(path.exists(), "missing journal file {}", path.display());
+            let content = fs::read_to_string(&path).unwrap();
+            for line in content.lines() {
+                let record: JournalRecord = serde_json::from_str(line).unwrap();
+                assert!(matches!(
+                    record.event,
+                    JournalEvent::CoordinatorConnected { .. }
+                ));
+                records += 1;
+            }
+        }
+        assert!(!journal_path(&dir, ROTATED_FILES + 1).exists());
+        // old records are removed by the rotation
+        assert!(records < 20);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
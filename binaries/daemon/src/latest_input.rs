@@ -0,0 +1,149 @@
+//! Single-slot mailboxes for inputs with `latest: true`.
+//!
+//! Messages for such inputs are not queued. Instead, the daemon keeps only the
+//! newest message and notifies the receiver through a `LatestAvailable` event.
+//! The receiver takes the message on demand, so messages that arrive in the
+//! meantime replace the pending one.
+
+use dora_message::{
+    daemon_to_node::NodeEvent,
+    metadata::{Parameter, SUPERSEDED_PARAMETER},
+    node_to_daemon::{DropToken, Timestamped},
+};
+
+#[derive(Debug, Default)]
+pub struct LatestSlot {
+    pending: Option<Timestamped<NodeEvent>>,
+    /// Number of messages that were replaced since the last `take`.
+    replaced: u64,
+    /// Total number of messages that were replaced by newer messages.
+    superseded: u64,
+}
+
+impl LatestSlot {
+    /// Stores the given message, replacing the pending message.
+    ///
+    /// The receiver needs to be notified if `notify` is set on the result.
+    pub fn put(&mut self, event: Timestamped<NodeEvent>) -> PutResult {
+        let replaced = self.pending.replace(event);
+        if replaced.is_some() {
+            self.replaced += 1;
+            self.superseded += 1;
+        }
+        PutResult {
+            notify: replaced.is_none(),
+            released_token: replaced.and_then(|e| drop_token(&e.inner)),
+        }
+    }
+
+    /// Takes the pending message, if any.
+    pub fn take(&mut self) -> Option<Timestamped<NodeEvent>> {
+        let mut event = self.pending.take()?;
+        let replaced = std::mem::take(&mut self.replaced);
+        if let NodeEvent::Input { metadata, .. } = &mut event.inner {
+            if replaced > 0 {
+                metadata.parameters.insert(
+                    SUPERSEDED_PARAMETER.into(),
+                    Parameter::Integer(replaced.try_into().unwrap_or(i64::MAX)),
+                );
+            }
+        }
+        Some(event)
+    }
+
+    /// Whether a message is waiting to be taken.
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The drop token of the pending message, if any.
+    pub fn pending_drop_token(&self) -> Option<DropToken> {
+        self.pending.as_ref().and_then(|e| drop_token(&e.inner))
+    }
+
+    /// The source of the pending message, if it was tagged with one because
+    /// the input has multiple sources.
+    pub fn pending_source(&self) -> Option<&str> {
+        match &self.pending.as_ref()?.inner {
+            NodeEvent::Input { metadata, .. } => metadata.input_source(),
+            _ => None,
+        }
+    }
+
+    pub fn superseded(&self) -> u64 {
+        self.superseded
+    }
+}
+
+pub struct PutResult {
+    pub notify: bool,
+    /// Drop token of the replaced message, which is no longer accessed by the receiver.
+    pub released_token: Option<DropToken>,
+}
+
+fn drop_token(event: &NodeEvent) -> Option<DropToken> {
+    match event {
+        NodeEvent::Input {
+            data: Some(data), ..
+        } => data.drop_token(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::{config::DataId, uhlc::HLC};
+    use dora_message::{
+        metadata::{ArrowTypeInfo, Metadata},
+        node_to_daemon::DataMessage,
+    };
+
+    fn input(clock: &HLC, drop_token: DropToken) -> Timestamped<NodeEvent> {
+        let timestamp = clock.new_timestamp();
+        Timestamped {
+            inner: NodeEvent::Input {
+                id: DataId::from("pose".to_owned()),
+                metadata: Metadata::new(timestamp, ArrowTypeInfo::empty()),
+                data: Some(DataMessage::SharedMemory {
+                    shared_memory_id: String::new(),
+                    len: 0,
+                    drop_token,
+                }),
+            },
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn only_newest_message_is_kept() {
+        let clock = HLC::default();
+        let tokens: Vec<_> = (0..3).map(|_| DropToken::generate()).collect();
+        let mut slot = LatestSlot::default();
+
+        let first = slot.put(input(&clock, tokens[0]));
+        assert!(first.notify);
+        assert_eq!(first.released_token, None);
+        for pair in tokens.windows(2) {
+            let result = slot.put(input(&clock, pair[1]));
+            assert!(!result.notify);
+            assert_eq!(result.released_token, Some(pair[0]));
+        }
+        assert_eq!(slot.pending_drop_token(), Some(tokens[2]));
+
+        let taken = slot.take().unwrap();
+        let NodeEvent::Input { metadata, .. } = taken.inner else {
+            panic!("expected input event")
+        };
+        assert_eq!(metadata.superseded(), 2);
+        assert!(slot.take().is_none());
+
+        // the next message needs a new notification
+        assert!(slot.put(input(&clock, DropToken::generate())).notify);
+        let NodeEvent::Input { metadata, .. } = slot.take().unwrap().inner else {
+            panic!("expected input event")
+        };
+        assert_eq!(metadata.superseded(), 0);
+        assert_eq!(slot.superseded(), 2);
+    }
+}
@@ -1,63 +1,181 @@
+use adaptive_rate::RateChange;
 use aligned_vec::{AVec, ConstAlign};
+use builder::{local_spawn_command, DaemonConfig};
+pub use builder::{DaemonBuilder, DaemonCommand, DaemonHandle, DaemonNotification};
+use clock_sync::ClockSync;
 use coordinator::CoordinatorEvent;
 use crossbeam::queue::ArrayQueue;
+use dataflow_events::DataflowEvents;
 use dora_core::{
-    config::{DataId, Input, InputMapping, NodeId, OperatorId},
-    descriptor::{runtime_node_inputs, CoreNodeKind, Descriptor, ResolvedNode},
-    topics::LOCALHOST,
+    config::{
+        format_duration, DataId, Input, InputMapping, NodeId, OperatorId, DROP_EVENTS_OUTPUT,
+    },
+    descriptor::{
+        expand_wildcard_inputs, runtime_node_inputs, start_layers, ClockConfig, CoreNodeKind,
+        Descriptor, RemoteTransport, ResolvedNode, StartOrder, LIFECYCLE_INPUT,
+    },
     uhlc::{self, HLC},
 };
 use dora_message::{
-    common::{DataMessage, DropToken, LogLevel, NodeError, NodeErrorCause, NodeExitStatus},
+    common::{
+        DataMessage, DropToken, LogLevel, NodeError, NodeErrorCause, NodeExitStatus,
+        CACHED_FILE_PREFIX,
+    },
+    compat::Envelope,
     coordinator_to_cli::DataflowResult,
-    coordinator_to_daemon::{DaemonCoordinatorEvent, SpawnDataflowNodes},
+    coordinator_to_daemon::{DaemonCoordinatorEvent, DataflowInstance, SpawnDataflowNodes},
     daemon_to_coordinator::{
-        CoordinatorRequest, DaemonCoordinatorReply, DaemonEvent, DataflowDaemonResult, LogMessage,
+        CoordinatorRequest, DaemonCoordinatorReply, DaemonEvent, DaemonHealth, DaemonStatus,
+        DataflowDaemonResult, LogMessage, MintedToken, TappedMessage,
+    },
+    daemon_to_daemon::{InterDaemonEvent, InterDaemonTransport, OutputDatagram},
+    daemon_to_external::ExternalMessage,
+    daemon_to_node::{
+        DaemonReply, DropReason, LifecycleEvent, NodeConfig, NodeDropEvent, NodeEvent,
+        NodeTopology, ObservedMessage, SendOutputError, StopRequestError,
+    },
+    diagnostics::{
+        DaemonDiagnostics, DataflowDiagnostics, DropTokenReports, EntryStats, GcReport,
+        NodeDiagnostics, NodeState,
     },
-    daemon_to_daemon::InterDaemonEvent,
-    daemon_to_node::{DaemonReply, NodeConfig, NodeDropEvent, NodeEvent},
     metadata::{self, ArrowTypeInfo},
-    node_to_daemon::{DynamicNodeEvent, Timestamped},
+    node_to_daemon::{DynamicNodeEvent, EventInterest, OutputRingId, Timestamped},
+    plan::{MachinePlan, NodePlan},
+    summary::{DataflowSummary, EdgeDropReason, InputSummary, OutputSummary, SizeHistogram},
     DataflowId,
 };
-use dora_node_api::Parameter;
+use dora_node_api::{
+    arrow::array::{Array, StringArray},
+    arrow_utils::{copy_array_into_sample, required_data_size},
+    Parameter,
+};
+use drop_events::DropNotifier;
+use drop_tombstones::DropTombstones;
+use drop_warnings::DropWarnings;
+pub use drop_warnings::DEFAULT_DROP_WARNING_INTERVAL;
+use edge_stats::EdgeStats;
+use external::ExternalEvent;
 use eyre::{bail, eyre, Context, ContextCompat, Result};
-use futures::{future, stream, FutureExt, TryFutureExt};
+use futures::{stream, FutureExt};
 use futures_concurrency::stream::Merge;
+use input_batch::InputBatch;
+use input_filter::DropMetrics;
+use input_timeouts::InputTimeout;
 use inter_daemon::InterDaemonConnection;
+use join_tokens::{JoinTokens, JOIN_TOKEN_VALIDITY};
+use journal::{Journal, JournalEvent, JournalHandle};
+use latest_input::PutResult;
+use local_inputs::{close_input, LocalInputs};
 use local_listener::DynamicNodeEventWrapper;
+pub use node_communication::limits::{
+    ConnectionLimits, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_NODE_CONNECTIONS,
+    DEFAULT_MAX_REQUEST_RATE,
+};
+use node_communication::{limits::NodeConnections, InputQueues};
+use node_migration::NodeMigration;
+use node_reload::ReloadingNode;
+use observer::Observer;
+use output_ring::{OutputRing, MAX_RINGS_PER_NODE};
+use paths::DaemonPaths;
+pub use paths::{DaemonPathsConfig, DEFAULT_PERSISTENT_CACHE_SIZE};
 use pending::PendingNodes;
+use persistent_cache::PersistentCache;
+use reassembly::{PartialOutputs, ReassemblyRegion};
+use registry::NodeRegistry;
+pub use registry::{DuplicateDataflowError, NodeRegistryConfig};
+use shared_memory::{DataflowSharedMemory, SharedMemoryUsage};
 use shared_memory_server::ShmemConf;
+use shutdown_check::Leak;
+pub use shutdown_check::LeakReport;
+use sim_clock::SimTimers;
 use socket_stream_utils::socket_stream_send;
+use stall::{StallChange, StallDetector};
+pub use stall::{StallConfig, DEFAULT_STALL_THRESHOLD};
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use subscribers::SubscriberPresence;
 use sysinfo::Pid;
+use tap::OutputTap;
 use tokio::{
     fs::File,
     io::AsyncReadExt,
     net::TcpStream,
     sync::{
+        broadcast,
         mpsc::{self, UnboundedSender},
         oneshot::{self, Sender},
     },
 };
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tracing::{error, warn};
-use uuid::{NoContext, Timestamp, Uuid};
+pub use udp_transport::DEFAULT_UDP_DATAGRAM_SIZE;
+use udp_transport::{DatagramReassembly, DatagramSequences, Reassembled, UdpTransport};
+use uuid::Uuid;
 
+mod adaptive_rate;
+mod builder;
+mod clock_sync;
 mod coordinator;
+mod dataflow_events;
+mod drop_events;
+mod drop_tombstones;
+mod drop_warnings;
+mod edge_stats;
+mod external;
+mod input_batch;
+mod input_filter;
+mod input_timeouts;
 mod inter_daemon;
+mod join_tokens;
+pub mod journal;
+mod latest_input;
+mod local_inputs;
 mod local_listener;
 mod log;
 mod node_communication;
+mod node_migration;
+mod node_reload;
+mod observer;
+mod output_ring;
+mod paths;
 mod pending;
+mod persistent_cache;
+mod raw_node;
+mod reassembly;
+mod reconfigure;
+mod registry;
+mod runtime_dir;
+#[cfg(any(test, feature = "deterministic-scheduling"))]
+pub mod scripted;
+mod shared_memory;
+mod shutdown_check;
+mod sim_clock;
 mod socket_stream_utils;
 mod spawn;
+mod stall;
+mod subscribers;
+mod tap;
+mod udp_transport;
+#[cfg(feature = "zenoh")]
+mod zenoh_transport;
+
+/// Entry points for the fuzz targets in `fuzz/`.
+#[doc(hidden)]
+pub mod fuzz {
+    /// Decodes the node requests in the given bytes like the daemon does for
+    /// socket connections, until the end of the data or the first error.
+    pub fn decode_node_requests(mut data: &[u8]) {
+        while let Ok(Some(_)) =
+            futures::executor::block_on(crate::node_communication::receive_request(&mut data))
+        {
+        }
+    }
+}
 
 #[cfg(feature = "telemetry")]
 use dora_tracing::telemetry::serialize_context;
@@ -67,94 +185,132 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 use crate::pending::DataflowStatus;
 
 const STDERR_LOG_LINES: usize = 10;
+/// Time that nodes get to stop before they are killed, if not configured otherwise.
+const DEFAULT_GRACE_DURATION: Duration = Duration::from_secs(15);
 
 pub struct Daemon {
     running: HashMap<DataflowId, RunningDataflow>,
     working_dir: HashMap<DataflowId, PathBuf>,
+    /// Directories of the log and output files, see [`DaemonPaths::dataflow_dir`].
+    dataflow_dirs: HashMap<DataflowId, PathBuf>,
+    paths: DaemonPaths,
+    /// Working directory for dataflows spawned by the coordinator, instead
+    /// of the working directory of the machine that submitted the dataflow.
+    default_working_dir: Option<PathBuf>,
 
-    events_tx: mpsc::Sender<Timestamped<Event>>,
+    /// Events of the running dataflows, with one channel per dataflow.
+    dataflow_events: DataflowEvents,
 
     coordinator_connection: Option<TcpStream>,
     last_coordinator_heartbeat: Instant,
     inter_daemon_connections: BTreeMap<String, InterDaemonConnection>,
+    /// Opened on first use by a dataflow that uses the zenoh transport.
+    #[cfg(feature = "zenoh")]
+    zenoh: Option<zenoh_transport::ZenohTransport>,
     machine_id: String,
 
     /// used for testing and examples
     exit_when_done: Option<BTreeSet<(Uuid, NodeId)>>,
-    /// used to record dataflow results when `exit_when_done` is used
     dataflow_node_results: BTreeMap<Uuid, BTreeMap<NodeId, Result<(), NodeError>>>,
+    /// used to record dataflow results when `exit_when_done` is used
+    finished_dataflows: DaemonRunResult,
 
     clock: Arc<uhlc::HLC>,
+
+    started: Instant,
+    /// Not set when running a dataflow without coordinator.
+    listen_addresses: Option<ListenAddresses>,
+    /// Socket for best-effort outputs, bound to the inter-daemon address.
+    ///
+    /// Not set when running a dataflow without coordinator, or if the
+    /// socket could not be bound.
+    udp: Option<UdpTransport>,
+    journal: Option<JournalHandle>,
+    /// Not set when running a dataflow without coordinator.
+    registry: Option<NodeRegistry>,
+    /// Number of inputs that were dropped since the last heartbeat.
+    dropped_messages: u64,
+    drop_warnings: DropWarnings,
+    /// Not set if stall detection is disabled.
+    stall_detection: Option<StallConfig>,
+    clock_sync: ClockSync,
+    node_connections: NodeConnections,
+    shared_memory: Arc<SharedMemoryUsage>,
+    /// Messages of persistent outputs, kept across dataflow runs.
+    persistent_cache: PersistentCache,
+    /// Random ID of this daemon run, used as namespace of the drop tokens
+    /// that are generated for it.
+    run_id: Uuid,
+    /// Freed drop tokens of dataflows that were torn down recently.
+    drop_tombstones: HashMap<DataflowId, DropTombstones>,
+    /// Drop reports of tokens that were not pending, of dataflows that were
+    /// removed already.
+    drop_token_reports: DropTokenReports,
+    /// Subscribers of a daemon that was started through a [`DaemonBuilder`].
+    notifications: Option<broadcast::Sender<DaemonNotification>>,
+    /// Check for leftover resources on exit, see [`DaemonBuilder::strict_shutdown`].
+    strict_shutdown: bool,
+    /// Tokens for external nodes and observers on other machines.
+    join_tokens: JoinTokens,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ListenAddresses {
+    /// Address that other daemons connect to.
+    inter_daemon: SocketAddr,
+    /// Address of the listener for dynamic nodes.
+    local: SocketAddr,
+    /// Address of the listener for dynamic nodes if other machines can
+    /// connect to it, i.e. if it is not bound to a loopback interface.
+    exposed_local: Option<SocketAddr>,
 }
 
-type DaemonRunResult = BTreeMap<Uuid, BTreeMap<NodeId, Result<(), NodeError>>>;
+type DaemonRunResult = BTreeMap<Uuid, DataflowDaemonResult>;
 
 impl Daemon {
+    /// Connects to the coordinator at the given address and runs until the
+    /// daemon is stopped.
+    #[deprecated(note = "use `DaemonBuilder`, which supports all daemon options")]
     pub async fn run(
         coordinator_addr: SocketAddr,
         machine_id: String,
         inter_daemon_addr: SocketAddr,
         local_listen_port: u16,
     ) -> eyre::Result<()> {
-        let clock = Arc::new(HLC::default());
-
-        let ctrlc_events = set_up_ctrlc_handler(clock.clone())?;
-
-        // spawn inter daemon listen loop
-        let (events_tx, events_rx) = flume::bounded(10);
-        let listen_port =
-            inter_daemon::spawn_listener_loop(inter_daemon_addr, machine_id.clone(), events_tx)
-                .await?;
-        let daemon_events = events_rx.into_stream().map(|e| Timestamped {
-            inner: Event::Daemon(e.inner),
-            timestamp: e.timestamp,
-        });
-
-        // connect to the coordinator
-        let coordinator_events =
-            coordinator::register(coordinator_addr, machine_id.clone(), listen_port, &clock)
-                .await
-                .wrap_err("failed to connect to dora-coordinator")?
-                .map(
-                    |Timestamped {
-                         inner: event,
-                         timestamp,
-                     }| Timestamped {
-                        inner: Event::Coordinator(event),
-                        timestamp,
-                    },
-                );
+        DaemonBuilder::new()
+            .machine_id(machine_id)
+            .coordinator(Some(coordinator_addr))
+            .inter_daemon_addr(inter_daemon_addr)
+            .local_listen_port(local_listen_port)
+            .handle_ctrlc(true)
+            .build()
+            .await?
+            .wait()
+            .await
+    }
 
-        // Spawn local listener loop
-        let (events_tx, events_rx) = flume::bounded(10);
-        let _listen_port = local_listener::spawn_listener_loop(
-            (LOCALHOST, local_listen_port).into(),
-            machine_id.clone(),
-            events_tx,
-        )
-        .await?;
-        let dynamic_node_events = events_rx.into_stream().map(|e| Timestamped {
-            inner: Event::DynamicNode(e.inner),
-            timestamp: e.timestamp,
-        });
-        Self::run_general(
-            (
-                coordinator_events,
-                ctrlc_events,
-                daemon_events,
-                dynamic_node_events,
-            )
-                .merge(),
-            Some(coordinator_addr),
-            machine_id,
-            None,
-            clock,
-        )
-        .await
-        .map(|_| ())
+    /// Runs the given dataflow without a coordinator and waits until it is
+    /// finished.
+    ///
+    /// The summary of the result is written to `result_file`, or to the
+    /// `result_file` of the dataflow descriptor if not given.
+    ///
+    /// In the tests of this crate, the daemon checks that it released all
+    /// resources when it exits, see [`DaemonBuilder::strict_shutdown`].
+    pub async fn run_dataflow(
+        dataflow_path: &Path,
+        result_file: Option<PathBuf>,
+    ) -> eyre::Result<DataflowResult> {
+        Self::run_dataflow_checked(dataflow_path, result_file, cfg!(test)).await
     }
 
-    pub async fn run_dataflow(dataflow_path: &Path) -> eyre::Result<DataflowResult> {
+    /// Like [`Self::run_dataflow`], with the leak check of
+    /// [`DaemonBuilder::strict_shutdown`] enabled or disabled explicitly.
+    pub async fn run_dataflow_checked(
+        dataflow_path: &Path,
+        result_file: Option<PathBuf>,
+        strict_shutdown: bool,
+    ) -> eyre::Result<DataflowResult> {
         let working_dir = dataflow_path
             .canonicalize()
             .context("failed to canoncialize dataflow path")?
@@ -163,25 +319,23 @@ impl Daemon {
             .to_owned();
 
         let descriptor = Descriptor::read(dataflow_path).await?;
-        descriptor.check(&working_dir)?;
-        let nodes = descriptor.resolve_aliases_and_set_defaults()?;
+        let spawn_command = local_spawn_command(descriptor, working_dir, false)?;
+        Self::run_spawn_command(spawn_command, result_file, strict_shutdown).await
+    }
 
-        let dataflow_id = Uuid::new_v7(Timestamp::now(NoContext));
-        let spawn_command = SpawnDataflowNodes {
-            dataflow_id,
-            working_dir,
-            nodes,
-            machine_listen_ports: BTreeMap::new(),
-            dataflow_descriptor: descriptor,
-        };
+    /// Resolves what [`Self::run_dataflow`] would spawn for the given
+    /// dataflow, without spawning anything.
+    pub async fn plan_dataflow(dataflow_path: &Path) -> eyre::Result<MachinePlan> {
+        let working_dir = dataflow_path
+            .canonicalize()
+            .context("failed to canoncialize dataflow path")?
+            .parent()
+            .ok_or_else(|| eyre::eyre!("canonicalized dataflow path has no parent"))?
+            .to_owned();
 
+        let descriptor = Descriptor::read(dataflow_path).await?;
+        let spawn_command = local_spawn_command(descriptor, working_dir, true)?;
         let clock = Arc::new(HLC::default());
-
-        let exit_when_done = spawn_command
-            .nodes
-            .iter()
-            .map(|n| (spawn_command.dataflow_id, n.id.clone()))
-            .collect();
         let (reply_tx, reply_rx) = oneshot::channel();
         let timestamp = clock.new_timestamp();
         let coordinator_events = stream::once(async move {
@@ -193,71 +347,134 @@ impl Daemon {
                 timestamp,
             }
         });
-        let run_result = Self::run_general(
-            Box::pin(coordinator_events),
-            None,
-            "".to_string(),
-            Some(exit_when_done),
-            clock.clone(),
-        );
+        // the daemon exits right after the plan, as there is nothing to wait for
+        let config = DaemonConfig {
+            exit_when_done: Some(BTreeSet::new()),
+            ..DaemonConfig::new(clock)
+        };
+        Self::run_general(Box::pin(coordinator_events), config).await?;
 
-        let spawn_result = reply_rx
-            .map_err(|err| eyre!("failed to receive spawn result: {err}"))
-            .and_then(|r| async {
-                match r {
-                    Some(DaemonCoordinatorReply::SpawnResult(result)) => {
-                        result.map_err(|err| eyre!(err))
-                    }
-                    _ => Err(eyre!("unexpected spawn reply")),
-                }
-            });
+        match reply_rx.await {
+            Ok(Some(DaemonCoordinatorReply::PlanResult(result))) => {
+                result.map_err(|err| eyre!(err))
+            }
+            Ok(_) => Err(eyre!("unexpected plan reply")),
+            Err(err) => Err(eyre!("failed to receive plan result: {err}")),
+        }
+    }
+
+    /// Spawns the given dataflow without a coordinator and waits until it is finished.
+    async fn run_spawn_command(
+        spawn_command: SpawnDataflowNodes,
+        result_file: Option<PathBuf>,
+        strict_shutdown: bool,
+    ) -> eyre::Result<DataflowResult> {
+        let dataflow_id = spawn_command.dataflow_id;
+        let start_time = SystemTime::now();
+        let result_file = result_file.or_else(|| {
+            let path = spawn_command.dataflow_descriptor.result_file.as_ref()?;
+            Some(spawn_command.working_dir.join(path))
+        });
 
-        let (mut dataflow_results, ()) = future::try_join(run_result, spawn_result).await?;
+        let daemon = DaemonBuilder::new()
+            .strict_shutdown(strict_shutdown)
+            .build()
+            .await?;
+        let mut notifications = daemon.subscribe_events();
+        if let Err(err) = daemon.spawn(spawn_command).await {
+            daemon.exit().await?;
+            return Err(err);
+        }
+        let result = loop {
+            match notifications.next().await {
+                Some(DaemonNotification::DataflowFinished {
+                    dataflow_id: id,
+                    result,
+                }) if id == dataflow_id => break result,
+                Some(_) => {}
+                None => {
+                    daemon.exit().await?;
+                    bail!("daemon exited before dataflow `{dataflow_id}` finished");
+                }
+            }
+        };
+        daemon.exit().await?;
 
+        let summary =
+            DataflowSummary::new(dataflow_id, None, start_time, SystemTime::now(), [&result]);
+        if let Some(path) = result_file {
+            write_result_file(&path, &summary).await?;
+        }
         Ok(DataflowResult {
             uuid: dataflow_id,
-            timestamp: clock.new_timestamp(),
-            node_results: dataflow_results
-                .remove(&dataflow_id)
-                .context("no node results for dataflow_id")?,
+            timestamp: result.timestamp,
+            node_results: result.node_results,
+            summary: Some(summary),
         })
     }
 
     async fn run_general(
         external_events: impl Stream<Item = Timestamped<Event>> + Unpin,
-        coordinator_addr: Option<SocketAddr>,
-        machine_id: String,
-        exit_when_done: Option<BTreeSet<(Uuid, NodeId)>>,
-        clock: Arc<HLC>,
+        mut config: DaemonConfig,
     ) -> eyre::Result<DaemonRunResult> {
+        let clock = config.clock.clone();
+        let journal = config.journal.take().and_then(|journal_config| {
+            let dir = journal_config.dir.clone();
+            match Journal::open(journal_config, clock) {
+                Ok(journal) => Some(journal),
+                Err(err) => {
+                    tracing::warn!(
+                        "journal is disabled because it could not be opened in `{}`: {err:?}",
+                        dir.display()
+                    );
+                    None
+                }
+            }
+        });
+
+        let coordinator_addr = config.coordinator_addr;
         let coordinator_connection = match coordinator_addr {
             Some(addr) => {
-                let stream = TcpStream::connect(addr)
+                let mut stream = TcpStream::connect(addr)
                     .await
                     .wrap_err("failed to connect to dora-coordinator")?;
                 stream
                     .set_nodelay(true)
                     .wrap_err("failed to set TCP_NODELAY")?;
+                if let Some(machine_token) = config.machine_token.clone() {
+                    // ignorable, so that older coordinators skip it
+                    let authenticate = serde_json::to_vec(&Envelope::new(
+                        CoordinatorRequest::AuthenticateEvents {
+                            machine_id: config.machine_id.clone(),
+                            machine_token,
+                        },
+                        config.clock.new_timestamp(),
+                    ))?;
+                    socket_stream_send(&mut stream, &authenticate)
+                        .await
+                        .wrap_err("failed to authenticate at dora-coordinator")?;
+                }
                 Some(stream)
             }
             None => None,
         };
 
-        let (dora_events_tx, dora_events_rx) = mpsc::channel(5);
-        let daemon = Self {
-            running: HashMap::new(),
-            working_dir: HashMap::new(),
-            events_tx: dora_events_tx,
+        let daemon = Self::new(
+            config,
             coordinator_connection,
-            last_coordinator_heartbeat: Instant::now(),
-            inter_daemon_connections: BTreeMap::new(),
-            machine_id,
-            exit_when_done,
-            dataflow_node_results: BTreeMap::new(),
-            clock,
-        };
+            journal.as_ref().map(Journal::handle),
+        );
+        tracing::info!("{}", daemon.status());
+        daemon.journal(JournalEvent::DaemonStarted {
+            machine_id: daemon.machine_id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        });
+        if let Some(addr) = coordinator_addr {
+            daemon.journal(JournalEvent::CoordinatorConnected {
+                address: addr.to_string(),
+            });
+        }
 
-        let dora_events = ReceiverStream::new(dora_events_rx);
         let watchdog_clock = daemon.clock.clone();
         let watchdog_interval = tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(
             Duration::from_secs(5),
@@ -266,96 +483,439 @@ impl Daemon {
             inner: Event::HeartbeatInterval,
             timestamp: watchdog_clock.new_timestamp(),
         });
-        let events = (external_events, dora_events, watchdog_interval).merge();
-        daemon.run_inner(events).await
+        let events = (external_events, watchdog_interval).merge();
+        let result = daemon.run_inner(events).await;
+
+        if let Some(journal) = journal {
+            journal.record(JournalEvent::DaemonStopped {
+                error: result.as_ref().err().map(|err| format!("{err:?}")),
+            });
+            journal.close();
+        }
+        result
+    }
+
+    /// Sets up the state of a daemon that has no running dataflows yet.
+    fn new(
+        config: DaemonConfig,
+        coordinator_connection: Option<TcpStream>,
+        journal: Option<JournalHandle>,
+    ) -> Self {
+        let DaemonConfig {
+            coordinator_addr: _,
+            machine_id,
+            machine_token: _,
+            exit_when_done,
+            listen_addresses,
+            udp,
+            journal: _,
+            registry,
+            drop_warning_interval,
+            stall_detection,
+            default_working_dir,
+            paths,
+            node_connections,
+            clock,
+            notifications,
+            strict_shutdown,
+            join_tokens,
+        } = config;
+        let persistent_cache =
+            PersistentCache::open(paths.persistent_cache_dir(), paths.persistent_cache_size());
+        Self {
+            running: HashMap::new(),
+            working_dir: HashMap::new(),
+            dataflow_dirs: HashMap::new(),
+            paths,
+            default_working_dir,
+            dataflow_events: DataflowEvents::default(),
+            coordinator_connection,
+            last_coordinator_heartbeat: Instant::now(),
+            inter_daemon_connections: BTreeMap::new(),
+            #[cfg(feature = "zenoh")]
+            zenoh: None,
+            machine_id,
+            exit_when_done,
+            dataflow_node_results: BTreeMap::new(),
+            finished_dataflows: BTreeMap::new(),
+            clock,
+            started: Instant::now(),
+            listen_addresses,
+            udp,
+            journal,
+            registry,
+            dropped_messages: 0,
+            drop_warnings: DropWarnings::new(drop_warning_interval),
+            stall_detection,
+            clock_sync: ClockSync::default(),
+            node_connections,
+            shared_memory: match strict_shutdown {
+                true => Arc::new(SharedMemoryUsage::tracking_regions()),
+                false => Default::default(),
+            },
+            persistent_cache,
+            run_id: Uuid::new_v4(),
+            drop_tombstones: HashMap::new(),
+            drop_token_reports: DropTokenReports::default(),
+            notifications,
+            strict_shutdown,
+            join_tokens,
+        }
     }
 
-    #[tracing::instrument(skip(incoming_events, self), fields(%self.machine_id))]
+    #[tracing::instrument(skip(incoming_events, self), fields(machine_id = %self.machine_id))]
     async fn run_inner(
         mut self,
         incoming_events: impl Stream<Item = Timestamped<Event>> + Unpin,
     ) -> eyre::Result<DaemonRunResult> {
         let mut events = incoming_events;
 
-        while let Some(event) = events.next().await {
-            let Timestamped { inner, timestamp } = event;
-            if let Err(err) = self.clock.update_with_timestamp(&timestamp) {
-                tracing::warn!("failed to update HLC with incoming event timestamp: {err}");
+        loop {
+            let event = tokio::select! {
+                Some((_, event)) = self.dataflow_events.next() => event,
+                event = events.next() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+            };
+            match self.handle_event(event).await? {
+                RunStatus::Continue => {}
+                RunStatus::Exit => break,
             }
+        }
 
-            match inner {
-                Event::Coordinator(CoordinatorEvent { event, reply_tx }) => {
-                    let status = self.handle_coordinator_event(event, reply_tx).await?;
+        if self.strict_shutdown {
+            self.check_shutdown().await?;
+        }
 
-                    match status {
-                        RunStatus::Continue => {}
-                        RunStatus::Exit => break,
-                    }
-                }
-                Event::Daemon(event) => {
-                    self.handle_inter_daemon_event(event).await?;
-                }
-                Event::Node {
-                    dataflow_id: dataflow,
-                    node_id,
-                    event,
-                } => self.handle_node_event(event, dataflow, node_id).await?,
-                Event::Dora(event) => match self.handle_dora_event(event).await? {
-                    RunStatus::Continue => {}
-                    RunStatus::Exit => break,
-                },
-                Event::DynamicNode(event) => self.handle_dynamic_node_event(event).await?,
-                Event::HeartbeatInterval => {
-                    if let Some(connection) = &mut self.coordinator_connection {
-                        let msg = serde_json::to_vec(&Timestamped {
-                            inner: CoordinatorRequest::Event {
-                                machine_id: self.machine_id.clone(),
-                                event: DaemonEvent::Heartbeat,
-                            },
-                            timestamp: self.clock.new_timestamp(),
-                        })?;
-                        socket_stream_send(connection, &msg)
-                            .await
-                            .wrap_err("failed to send watchdog message to dora-coordinator")?;
+        Ok(self.finished_dataflows)
+    }
+
+    /// Handles a single event of the main loop, see [`Self::run_inner`].
+    async fn handle_event(&mut self, event: Timestamped<Event>) -> eyre::Result<RunStatus> {
+        let Timestamped { inner, timestamp } = event;
+        if let Err(err) = self.clock.update_with_timestamp(&timestamp) {
+            tracing::warn!("failed to update HLC with incoming event timestamp: {err}");
+        }
+        let check_subscribers = inner.may_change_subscribers();
 
-                        if self.last_coordinator_heartbeat.elapsed() > Duration::from_secs(20) {
-                            bail!("lost connection to coordinator")
+        match inner {
+            Event::Coordinator(CoordinatorEvent { event, reply_tx }) => {
+                if let RunStatus::Exit = self.handle_coordinator_event(event, reply_tx).await? {
+                    return Ok(RunStatus::Exit);
+                }
+            }
+            Event::Daemon(event) => {
+                self.handle_inter_daemon_event(event).await?;
+            }
+            Event::Node {
+                dataflow_id: dataflow,
+                node_id,
+                event,
+            } => self.handle_node_event(event, dataflow, node_id).await?,
+            Event::Dora(event) => {
+                if let RunStatus::Exit = self.handle_dora_event(event).await? {
+                    return Ok(RunStatus::Exit);
+                }
+            }
+            Event::DynamicNode(event) => self.handle_dynamic_node_event(event).await?,
+            Event::External { dataflow_id, event } => {
+                // external processes must not be able to stop the daemon
+                if let Err(err) = self.handle_external_event(dataflow_id, event).await {
+                    tracing::warn!(
+                        "failed to handle external event for dataflow `{dataflow_id}`: {err:?}"
+                    );
+                }
+            }
+            Event::HeartbeatInterval => {
+                let now = Instant::now();
+                for report in self.drop_warnings.flush(now) {
+                    // not part of a dataflow operation, so the IDs are
+                    // added to the event itself
+                    tracing::warn!(
+                        dataflow_id = %report.dataflow_id,
+                        node_id = %report.node_id,
+                        "{report}"
+                    );
+                }
+                self.drop_tombstones.retain(|_, tombstones| {
+                    tombstones.expire(now);
+                    !tombstones.is_empty()
+                });
+                self.finish_expired_taps().await?;
+                self.check_stalled_dataflows(now).await?;
+                for dataflow in self.running.values_mut() {
+                    dataflow.remove_disconnected_observers();
+                    dataflow.drop_released_output_rings();
+                }
+                if let Some(registry) = &mut self.registry {
+                    registry.remove_exited_orphans();
+                }
+                let health = self.health();
+                if let Some(connection) = &mut self.coordinator_connection {
+                    let heartbeat = serde_json::to_vec(&Timestamped {
+                        inner: CoordinatorRequest::Event {
+                            machine_id: self.machine_id.clone(),
+                            event: DaemonEvent::Heartbeat,
+                        },
+                        timestamp: self.clock.new_timestamp(),
+                    })?;
+                    // ignorable, so that older coordinators skip it
+                    let health = serde_json::to_vec(&Envelope::new(
+                        CoordinatorRequest::Event {
+                            machine_id: self.machine_id.clone(),
+                            event: DaemonEvent::Health(health),
+                        },
+                        self.clock.new_timestamp(),
+                    ))?;
+                    for msg in [heartbeat, health] {
+                        if let Err(err) = socket_stream_send(connection, &msg).await {
+                            self.journal(JournalEvent::CoordinatorDisconnected {
+                                reason: err.to_string(),
+                            });
+                            return Err(err)
+                                .wrap_err("failed to send watchdog message to dora-coordinator");
                         }
                     }
-                }
-                Event::CtrlC => {
-                    for dataflow in self.running.values_mut() {
-                        dataflow
-                            .stop_all(&mut self.coordinator_connection, &self.clock, None)
-                            .await?;
+
+                    if self.last_coordinator_heartbeat.elapsed() > Duration::from_secs(20) {
+                        self.journal(JournalEvent::CoordinatorDisconnected {
+                            reason: "heartbeat timeout".into(),
+                        });
+                        bail!("lost connection to coordinator")
                     }
                 }
             }
+            Event::CtrlC => {
+                for dataflow in self.running.values_mut() {
+                    dataflow
+                        .stop_all(&mut self.coordinator_connection, &self.clock, None)
+                        .await?;
+                }
+            }
+        }
+        if check_subscribers {
+            for dataflow in self.running.values_mut() {
+                dataflow.update_subscriber_presence(&self.clock);
+            }
+        }
+        Ok(RunStatus::Continue)
+    }
+
+    /// Fails if resources are left over on exit, see [`DaemonBuilder::strict_shutdown`].
+    async fn check_shutdown(&mut self) -> Result<(), LeakReport> {
+        // the listener tasks of the last nodes might still be dropping their
+        // shared memory regions
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            let report = self.leak_report();
+            if report.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(report);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn leak_report(&mut self) -> LeakReport {
+        let mut leaks = Vec::new();
+        for (&dataflow_id, dataflow) in &self.running {
+            leaks.push(Leak::Dataflow {
+                dataflow_id,
+                age: dataflow.created.elapsed(),
+                running_nodes: dataflow.running_nodes.keys().cloned().collect(),
+            });
+            for (token, info) in &dataflow.pending_drop_tokens {
+                leaks.push(Leak::DropToken {
+                    dataflow_id,
+                    token: *token,
+                    owner: info.owner.clone(),
+                    pending_nodes: info.pending_nodes.iter().cloned().collect(),
+                    age: info.sent.elapsed(),
+                });
+            }
+        }
+        if let Some(registry) = &self.registry {
+            for (dataflow_id, node_id, pid, age) in registry.spawned_nodes() {
+                leaks.push(Leak::RegisteredNode {
+                    dataflow_id,
+                    node_id: node_id.clone(),
+                    pid,
+                    age,
+                });
+            }
+        }
+        for region in self.shared_memory.remaining_regions() {
+            leaks.push(Leak::SharedMemory {
+                dataflow_id: region.dataflow_id,
+                os_id: region.os_id,
+                age: region.created.elapsed(),
+            });
         }
+        LeakReport { leaks }
+    }
 
-        Ok(self.dataflow_node_results)
+    /// Reports the given event to the subscribers of the [`DaemonHandle`], if any.
+    fn notify(&self, notification: DaemonNotification) {
+        if let Some(notifications) = &self.notifications {
+            // there might be no subscribers
+            let _ = notifications.send(notification);
+        }
     }
 
     async fn send_log_message(&mut self, message: LogMessage) -> eyre::Result<()> {
         if let Some(connection) = &mut self.coordinator_connection {
-            let msg = serde_json::to_vec(&Timestamped {
-                inner: CoordinatorRequest::Event {
+            let msg = serde_json::to_vec(&Envelope::new(
+                CoordinatorRequest::Event {
                     machine_id: self.machine_id.clone(),
                     event: DaemonEvent::Log(message),
                 },
-                timestamp: self.clock.new_timestamp(),
-            })?;
+                self.clock.new_timestamp(),
+            ))?;
             socket_stream_send(connection, &msg)
                 .await
                 .wrap_err("failed to send watchdog message to dora-coordinator")?;
 
             if self.last_coordinator_heartbeat.elapsed() > Duration::from_secs(20) {
+                self.journal(JournalEvent::CoordinatorDisconnected {
+                    reason: "heartbeat timeout".into(),
+                });
                 bail!("lost connection to coordinator")
             }
         }
         Ok(())
     }
 
+    /// Starts copying the messages of the given output to the coordinator.
+    fn start_tap(
+        &mut self,
+        tap_id: Uuid,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+        duration: Duration,
+        max_rate: Option<f64>,
+    ) -> eyre::Result<()> {
+        if self.coordinator_connection.is_none() {
+            bail!("daemon is not connected to a coordinator");
+        }
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        let declared = dataflow
+            .declared_outputs
+            .get(&node_id)
+            .wrap_err_with(|| format!("no node `{node_id}` in dataflow `{dataflow_id}`"))?;
+        if !declared.contains(&output_id) {
+            bail!("node `{node_id}` has no output `{output_id}`");
+        }
+        let output_id = OutputId(node_id, output_id);
+        let tap = OutputTap::new(tap_id, output_id, duration, max_rate, Instant::now())?;
+        tracing::info!(
+            "tapping output `{}/{}` of dataflow `{dataflow_id}` for {duration:?}",
+            tap.output_id.0,
+            tap.output_id.1
+        );
+        dataflow.taps.push(tap);
+        Ok(())
+    }
+
+    /// Creates a join token for a node that runs on this machine.
+    fn mint_token(&self, dataflow_id: DataflowId, node_id: NodeId) -> eyre::Result<MintedToken> {
+        let dataflow = self
+            .running
+            .get(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        if !dataflow.running_nodes.contains_key(&node_id) {
+            bail!("node `{node_id}` of dataflow `{dataflow_id}` is not running on this machine");
+        }
+        let token = self.join_tokens.mint(dataflow_id, node_id, Instant::now());
+        Ok(MintedToken {
+            token,
+            listen_address: self.listen_addresses.and_then(|a| a.exposed_local),
+            valid_for: JOIN_TOKEN_VALIDITY,
+        })
+    }
+
+    /// Removes expired output taps and notifies the coordinator about them.
+    async fn finish_expired_taps(&mut self) -> eyre::Result<()> {
+        let now = Instant::now();
+        let mut finished = Vec::new();
+        for dataflow in self.running.values_mut() {
+            dataflow.taps.retain(|tap| {
+                let expired = tap.is_expired(now);
+                if expired {
+                    finished.push(tap.id);
+                }
+                !expired
+            });
+        }
+        let Some(connection) = &mut self.coordinator_connection else {
+            return Ok(());
+        };
+        for tap_id in finished {
+            send_coordinator_event(
+                connection,
+                &self.machine_id,
+                &self.clock,
+                DaemonEvent::TapFinished { tap_id },
+            )
+            .await
+            .wrap_err("failed to send tap finished message to dora-coordinator")?;
+        }
+        Ok(())
+    }
+
+    /// Warns about dataflows whose message flow stopped or resumed, and
+    /// reports them to the coordinator if configured.
+    async fn check_stalled_dataflows(&mut self, now: Instant) -> eyre::Result<()> {
+        let Some(config) = &self.stall_detection else {
+            return Ok(());
+        };
+        let mut events = Vec::new();
+        for (&dataflow_id, dataflow) in &mut self.running {
+            if dataflow.descriptor.idle_ok {
+                continue;
+            }
+            let has_running_nodes = !dataflow.running_nodes.is_empty();
+            match dataflow
+                .stall
+                .check(now, config.threshold, has_running_nodes)
+            {
+                Some(StallChange::Stalled { idle }) => {
+                    tracing::warn!(
+                        "dataflow `{dataflow_id}` has running nodes, but no message was \
+                        delivered for {idle:.0?}"
+                    );
+                    events.push(DaemonEvent::DataflowStalled { dataflow_id, idle });
+                }
+                Some(StallChange::Recovered { stalled_for }) => {
+                    tracing::info!(
+                        "messages flow again in dataflow `{dataflow_id}` after it was stalled \
+                        for {stalled_for:.0?}"
+                    );
+                    events.push(DaemonEvent::DataflowRecovered { dataflow_id });
+                }
+                None => {}
+            }
+        }
+        if !config.notify_coordinator {
+            return Ok(());
+        }
+        let Some(connection) = &mut self.coordinator_connection else {
+            return Ok(());
+        };
+        for event in events {
+            send_coordinator_event(connection, &self.machine_id, &self.clock, event)
+                .await
+                .wrap_err("failed to send stall report to dora-coordinator")?;
+        }
+        Ok(())
+    }
+
     async fn handle_coordinator_event(
         &mut self,
         event: DaemonCoordinatorEvent,
@@ -365,13 +925,24 @@ impl Daemon {
             DaemonCoordinatorEvent::Spawn(SpawnDataflowNodes {
                 dataflow_id,
                 working_dir,
+                machine_working_dir,
                 nodes,
                 machine_listen_ports,
                 dataflow_descriptor,
+                inter_daemon_transport,
+                instance,
+                params,
+                dry_run,
             }) => {
                 match dataflow_descriptor.communication.remote {
                     dora_core::config::RemoteCommunicationConfig::Tcp => {}
                 }
+                // a dry run must not change the connections of running dataflows
+                let machine_listen_ports = if dry_run {
+                    BTreeMap::new()
+                } else {
+                    machine_listen_ports
+                };
                 for (machine_id, socket) in machine_listen_ports {
                     match self.inter_daemon_connections.entry(machine_id) {
                         std::collections::btree_map::Entry::Vacant(entry) => {
@@ -385,25 +956,61 @@ impl Daemon {
                     }
                 }
 
-                // Use the working directory if it exists, otherwise use the working directory where the daemon is spawned
-                let working_dir = if working_dir.exists() {
-                    working_dir
-                } else {
-                    std::env::current_dir().wrap_err("failed to get current working dir")?
-                };
+                // Prefer the working dir that was given for this machine, then the default
+                // working dir of the daemon, then the working dir of the submitting machine.
+                // Local nodes fail to spawn if the chosen dir doesn't exist on this machine.
+                let working_dir = machine_working_dir
+                    .or_else(|| self.default_working_dir.clone())
+                    .unwrap_or(working_dir);
+
+                if dry_run {
+                    let result = self
+                        .plan_local_nodes(
+                            dataflow_id,
+                            working_dir,
+                            nodes,
+                            dataflow_descriptor,
+                            inter_daemon_transport,
+                            instance,
+                            params,
+                        )
+                        .await;
+                    let reply = DaemonCoordinatorReply::PlanResult(
+                        result.map_err(|err| format!("{err:?}")),
+                    );
+                    let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                        error!("could not send `PlanResult` reply from daemon to coordinator")
+                    });
+                    return Ok(match &self.exit_when_done {
+                        Some(exit_when_done) if exit_when_done.is_empty() => RunStatus::Exit,
+                        _ => RunStatus::Continue,
+                    });
+                }
 
                 let result = self
-                    .spawn_dataflow(dataflow_id, working_dir, nodes, dataflow_descriptor)
+                    .spawn_dataflow(
+                        dataflow_id,
+                        working_dir,
+                        nodes,
+                        dataflow_descriptor,
+                        inter_daemon_transport,
+                        instance,
+                        params,
+                    )
                     .await;
-                if let Err(err) = &result {
-                    tracing::error!("{err:?}");
-                }
+                let status = match &result {
+                    Ok(_) => RunStatus::Continue,
+                    Err(err) => {
+                        tracing::error!("{err:?}");
+                        self.handle_failed_spawn(dataflow_id)
+                    }
+                };
                 let reply =
                     DaemonCoordinatorReply::SpawnResult(result.map_err(|err| format!("{err:?}")));
                 let _ = reply_tx.send(Some(reply)).map_err(|_| {
                     error!("could not send `SpawnResult` reply from daemon to coordinator")
                 });
-                RunStatus::Continue
+                status
             }
             DaemonCoordinatorEvent::AllNodesReady {
                 dataflow_id,
@@ -421,7 +1028,8 @@ impl Daemon {
                             .await?;
                         if ready {
                             tracing::info!("coordinator reported that all nodes are ready, starting dataflow `{dataflow_id}`");
-                            dataflow.start(&self.events_tx, &self.clock).await?;
+                            let events_tx = self.dataflow_events.sender(dataflow_id);
+                            dataflow.start(&events_tx, &self.clock).await?;
                         }
                     }
                     None => {
@@ -439,18 +1047,15 @@ impl Daemon {
                 dataflow_id,
                 node_id,
             } => {
-                match self.working_dir.get(&dataflow_id) {
-                    Some(working_dir) => {
-                        let working_dir = working_dir.clone();
+                match self.dataflow_dirs.get(&dataflow_id) {
+                    Some(dataflow_dir) => {
+                        let log_path = log::log_path(dataflow_dir, &node_id);
                         tokio::spawn(async move {
                             let logs = async {
-                                let mut file =
-                                    File::open(log::log_path(&working_dir, &dataflow_id, &node_id))
-                                        .await
-                                        .wrap_err(format!(
-                                            "Could not open log file: {:#?}",
-                                            log::log_path(&working_dir, &dataflow_id, &node_id)
-                                        ))?;
+                                let mut file = File::open(&log_path).await.wrap_err(format!(
+                                    "Could not open log file: {:#?}",
+                                    log_path
+                                ))?;
 
                                 let mut contents = vec![];
                                 file.read_to_end(&mut contents)
@@ -478,6 +1083,19 @@ impl Daemon {
                 }
                 RunStatus::Continue
             }
+            DaemonCoordinatorEvent::GetDescriptor { dataflow_id } => {
+                let result = self
+                    .running
+                    .get(&dataflow_id)
+                    .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))
+                    .and_then(|dataflow| dataflow.resolved_descriptor_yaml());
+                let reply =
+                    DaemonCoordinatorReply::Descriptor(result.map_err(|err| format!("{err:?}")));
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send descriptor reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
             DaemonCoordinatorEvent::ReloadDataflow {
                 dataflow_id,
                 node_id,
@@ -491,16 +1109,103 @@ impl Daemon {
                     .map_err(|_| error!("could not send reload reply from daemon to coordinator"));
                 RunStatus::Continue
             }
-            DaemonCoordinatorEvent::StopDataflow {
+            DaemonCoordinatorEvent::ReloadNode {
                 dataflow_id,
-                grace_duration,
+                node_id,
             } => {
-                let dataflow = self
-                    .running
-                    .get_mut(&dataflow_id)
-                    .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"));
-                let (reply, future) = match dataflow {
-                    Ok(dataflow) => {
+                // on success, the reply is sent once the new node instance is running
+                match self.check_node_reload(dataflow_id, &node_id) {
+                    Ok(()) => self.start_node_reload(dataflow_id, node_id, reply_tx),
+                    Err(err) => {
+                        let reply =
+                            DaemonCoordinatorReply::ReloadNodeResult(Err(format!("{err:?}")));
+                        let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                            error!("could not send node reload reply from daemon to coordinator")
+                        });
+                    }
+                }
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::PrepareNodeMigration {
+                dataflow_id,
+                node,
+                from_machine,
+            } => {
+                // on success, the reply is sent once the new instance subscribed
+                match self.check_node_migration(dataflow_id, &node) {
+                    Ok(()) => {
+                        self.start_node_migration(dataflow_id, node, from_machine, reply_tx)
+                            .await
+                    }
+                    Err(err) => {
+                        let reply =
+                            DaemonCoordinatorReply::ReloadNodeResult(Err(format!("{err:?}")));
+                        let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                            error!("could not send migration reply from daemon to coordinator")
+                        });
+                    }
+                }
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::SwitchNodeRoutes {
+                dataflow_id,
+                node_id,
+                from_machine,
+                to_machine,
+                revert,
+            } => {
+                let result = self
+                    .switch_node_routes(dataflow_id, node_id, from_machine, to_machine, revert)
+                    .await;
+                let reply = DaemonCoordinatorReply::NodeMigrationResult(
+                    result.map_err(|err| format!("{err:?}")),
+                );
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send migration reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::StopMigratedNode {
+                dataflow_id,
+                node_id,
+            } => {
+                // on success, the reply is sent once the old instance exited
+                match self.check_migrated_node_stop(dataflow_id, &node_id) {
+                    Ok(()) => self.stop_migrated_node(dataflow_id, node_id, reply_tx),
+                    Err(err) => {
+                        let reply =
+                            DaemonCoordinatorReply::NodeMigrationResult(Err(format!("{err:?}")));
+                        let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                            error!("could not send migration reply from daemon to coordinator")
+                        });
+                    }
+                }
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::FinishNodeMigration {
+                dataflow_id,
+                node_id,
+                abort,
+            } => {
+                let result = self.finish_node_migration(dataflow_id, &node_id, abort);
+                let reply = DaemonCoordinatorReply::NodeMigrationResult(
+                    result.map_err(|err| format!("{err:?}")),
+                );
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send migration reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::StopDataflow {
+                dataflow_id,
+                grace_duration,
+            } => {
+                let dataflow = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"));
+                let (reply, future) = match dataflow {
+                    Ok(dataflow) => {
                         let future = dataflow.stop_all(
                             &mut self.coordinator_connection,
                             &self.clock,
@@ -542,10 +1247,322 @@ impl Daemon {
                 let _ = reply_tx.send(None);
                 RunStatus::Continue
             }
+            DaemonCoordinatorEvent::TimeSync(sync) => {
+                self.clock_sync
+                    .add_sample(sync, clock_sync::wall_clock_now());
+                let _ = reply_tx.send(None);
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::SetLogLevel { filter } => {
+                #[cfg(feature = "tracing")]
+                let result =
+                    dora_tracing::set_log_filter(&filter).map_err(|err| format!("{err:?}"));
+                #[cfg(not(feature = "tracing"))]
+                let result = Err("dora-daemon was built without `tracing` feature".to_owned());
+                match &result {
+                    Ok(previous) => {
+                        tracing::info!("changed log filter from `{previous}` to `{filter}`")
+                    }
+                    Err(err) => tracing::warn!("failed to change log filter: {err}"),
+                }
+                let reply = DaemonCoordinatorReply::SetLogLevelResult(result);
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send log level reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::TapOutput {
+                tap_id,
+                dataflow_id,
+                node_id,
+                output_id,
+                duration,
+                max_rate,
+            } => {
+                let result = self
+                    .start_tap(tap_id, dataflow_id, node_id, output_id, duration, max_rate)
+                    .map_err(|err| format!("{err:?}"));
+                let reply = DaemonCoordinatorReply::TapResult(result);
+                let _ = reply_tx
+                    .send(Some(reply))
+                    .map_err(|_| error!("could not send tap reply from daemon to coordinator"));
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::Diagnostics { gc } => {
+                let gc = match gc {
+                    Some(older_than) => Some(self.collect_garbage(older_than).await?),
+                    None => None,
+                };
+                let reply = DaemonCoordinatorReply::Diagnostics(self.diagnostics(gc));
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send diagnostics reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::MintToken {
+                dataflow_id,
+                node_id,
+            } => {
+                let result = self
+                    .mint_token(dataflow_id, node_id)
+                    .map_err(|err| format!("{err:?}"));
+                let reply = DaemonCoordinatorReply::MintTokenResult(result);
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send mint token reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::Reconfigure {
+                dataflow_id,
+                changes,
+            } => {
+                let result = self
+                    .reconfigure(dataflow_id, changes)
+                    .await
+                    .map_err(|err| format!("{err:?}"));
+                let reply = DaemonCoordinatorReply::ReconfigureResult(result);
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send reconfigure reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::Status => {
+                let reply = DaemonCoordinatorReply::Status(self.status());
+                let _ = reply_tx
+                    .send(Some(reply))
+                    .map_err(|_| error!("could not send status reply from daemon to coordinator"));
+                RunStatus::Continue
+            }
         };
         Ok(status)
     }
 
+    /// Records the given event in the journal, if enabled.
+    fn journal(&self, event: JournalEvent) {
+        if let Some(journal) = &self.journal {
+            journal.record(event);
+        }
+    }
+
+    /// Health snapshot for the next heartbeat, resets the drop counter.
+    fn health(&mut self) -> DaemonHealth {
+        DaemonHealth {
+            version: DaemonHealth::VERSION,
+            timestamp: self.clock.new_timestamp(),
+            running_dataflows: self.running.len(),
+            running_nodes: self.running.values().map(|d| d.running_nodes.len()).sum(),
+            pending_drop_tokens: self
+                .running
+                .values()
+                .map(|d| d.pending_drop_tokens.len())
+                .sum(),
+            shared_memory_in_flight: self.shared_memory_in_flight(),
+            dropped_messages: std::mem::take(&mut self.dropped_messages),
+            dropped_inputs: self.drop_warnings.take_heartbeat_counts(),
+            wall_clock: Some(clock_sync::wall_clock_now()),
+            clock_offset: self.clock_sync.estimate(),
+            rejected_connections: self.node_connections.take_rejected(),
+            event_queue_depths: self.dataflow_events.depths(),
+        }
+    }
+
+    fn shared_memory_in_flight(&self) -> u64 {
+        self.shared_memory.in_flight()
+    }
+
+    /// Message size histograms of the outputs of all running dataflows.
+    fn message_sizes(&self) -> BTreeMap<String, SizeHistogram> {
+        self.running
+            .values()
+            .flat_map(|dataflow| {
+                dataflow
+                    .output_stats
+                    .iter()
+                    .flat_map(move |(node_id, outputs)| {
+                        outputs.iter().map(move |(output_id, output)| {
+                            (
+                                format!("{}/{node_id}/{output_id}", dataflow.id),
+                                output.message_sizes.clone(),
+                            )
+                        })
+                    })
+            })
+            .collect()
+    }
+
+    fn status(&self) -> DaemonStatus {
+        DaemonStatus {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_hash: option_env!("DORA_GIT_HASH").map(ToOwned::to_owned),
+            machine_id: self.machine_id.clone(),
+            uptime: self.started.elapsed(),
+            running_dataflows: self.running.len(),
+            running_nodes: self.running.values().map(|d| d.running_nodes.len()).sum(),
+            shared_memory_in_flight: self.shared_memory_in_flight(),
+            peak_shared_memory: self.shared_memory.peak(),
+            message_sizes: self.message_sizes(),
+            listen_address: self.listen_addresses.map(|a| a.inter_daemon),
+            clock_offset: self.clock_sync.estimate(),
+            stalled_dataflows: self.stalled_dataflows(),
+        }
+    }
+
+    /// Dataflows that are currently stalled, with the time since their
+    /// last delivered message.
+    fn stalled_dataflows(&self) -> BTreeMap<DataflowId, Duration> {
+        let now = Instant::now();
+        self.running
+            .iter()
+            .filter_map(|(id, dataflow)| Some((*id, dataflow.stall.idle(now)?)))
+            .collect()
+    }
+
+    /// Snapshot of the internal state, for debugging.
+    ///
+    /// Only counts and ages are collected, so this stays fast for dataflows
+    /// with many pending messages.
+    fn diagnostics(&self, gc: Option<GcReport>) -> DaemonDiagnostics {
+        let now = Instant::now();
+        let queue_depths = self.dataflow_events.depths();
+        let dataflows = self
+            .running
+            .iter()
+            .map(|(id, dataflow)| {
+                let queue_depth = queue_depths.get(id).copied().unwrap_or_default();
+                (*id, dataflow.diagnostics(now, queue_depth))
+            })
+            .collect();
+        let mut drop_token_reports = self.drop_token_reports;
+        for dataflow in self.running.values() {
+            drop_token_reports.merge(dataflow.drop_token_reports);
+        }
+
+        let path = |dir: Option<&Path>| match dir {
+            Some(dir) => dir.display().to_string(),
+            None => "-".to_owned(),
+        };
+        let limits = self.node_connections.limits();
+        let config = [
+            (
+                "coordinator",
+                self.coordinator_connection.is_some().to_string(),
+            ),
+            (
+                "listen_address",
+                self.listen_addresses
+                    .map(|a| a.inter_daemon.to_string())
+                    .unwrap_or_else(|| "-".to_owned()),
+            ),
+            (
+                "default_working_dir",
+                path(self.default_working_dir.as_deref()),
+            ),
+            ("state_dir", path(self.paths.state_dir())),
+            ("cache_dir", path(self.paths.cache_dir())),
+            ("log_dir", path(self.paths.log_dir())),
+            (
+                "persistent_cache_size",
+                self.paths.persistent_cache_size().to_string(),
+            ),
+            ("journal", self.journal.is_some().to_string()),
+            ("node_registry", self.registry.is_some().to_string()),
+            (
+                "drop_warning_interval",
+                format!("{:?}", self.drop_warnings.interval()),
+            ),
+            ("max_node_connections", limits.max_connections.to_string()),
+            (
+                "node_handshake_timeout",
+                format!("{:?}", limits.handshake_timeout),
+            ),
+            ("max_node_request_rate", limits.max_request_rate.to_string()),
+            (
+                "udp_datagram_size",
+                self.udp
+                    .as_ref()
+                    .map(|udp| udp.datagram_size().to_string())
+                    .unwrap_or_else(|| "-".to_owned()),
+            ),
+            (
+                "stall_threshold",
+                self.stall_detection
+                    .as_ref()
+                    .map(|config| format!("{:?}", config.threshold))
+                    .unwrap_or_else(|| "-".to_owned()),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect();
+
+        #[cfg(feature = "tracing")]
+        let recent_warnings = dora_tracing::recent_warnings();
+        #[cfg(not(feature = "tracing"))]
+        let recent_warnings = Vec::new();
+
+        DaemonDiagnostics {
+            machine_id: self.machine_id.clone(),
+            uptime: self.started.elapsed(),
+            config,
+            dataflows,
+            shared_memory_in_flight: self.shared_memory.in_flight(),
+            peak_shared_memory: self.shared_memory.peak(),
+            recent_warnings,
+            gc,
+            drop_token_reports,
+        }
+    }
+
+    /// Force-releases the drop tokens of all dataflows that are pending for
+    /// longer than the given duration.
+    async fn collect_garbage(&mut self, older_than: Duration) -> eyre::Result<GcReport> {
+        let mut report = GcReport {
+            older_than,
+            released_drop_tokens: 0,
+            reclaimed_bytes: 0,
+        };
+        for dataflow in self.running.values_mut() {
+            let (released, bytes) = dataflow
+                .release_stale_drop_tokens(older_than, &self.clock)
+                .await?;
+            report.released_drop_tokens += released;
+            report.reclaimed_bytes += bytes;
+        }
+        if report.released_drop_tokens > 0 {
+            tracing::warn!("garbage collection {report}");
+        }
+        Ok(report)
+    }
+
+    /// Delivers an output that was received from another daemon to the local
+    /// receivers.
+    async fn forward_remote_output(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+        metadata: metadata::Metadata,
+        data: Option<DataMessage>,
+    ) -> eyre::Result<()> {
+        let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+            format!("send out failed: no running dataflow with ID `{dataflow_id}`")
+        })?;
+        let data_bytes = send_output_to_local_receivers(
+            node_id.clone(),
+            output_id.clone(),
+            dataflow,
+            &metadata,
+            data,
+            &self.clock,
+        )
+        .await?;
+        if dataflow.clock_source == Some(OutputId(node_id, output_id)) {
+            self.advance_sim_clock(dataflow_id, &metadata, data_bytes.as_ref())
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn handle_inter_daemon_event(&mut self, event: InterDaemonEvent) -> eyre::Result<()> {
         match event {
             InterDaemonEvent::Output {
@@ -555,24 +1572,86 @@ impl Daemon {
                 metadata,
                 data,
             } => {
+                if let Err(err) = self
+                    .forward_remote_output(
+                        dataflow_id,
+                        node_id,
+                        output_id,
+                        metadata,
+                        data.map(DataMessage::Vec),
+                    )
+                    .await
+                    .wrap_err("failed to forward remote output to local receivers")
+                {
+                    tracing::warn!("{err:?}")
+                }
+                Ok(())
+            }
+            InterDaemonEvent::OutputChunk(chunk) => {
+                let dataflow_id = chunk.dataflow_id;
                 let inner = async {
                     let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
                         format!("send out failed: no running dataflow with ID `{dataflow_id}`")
                     })?;
-                    send_output_to_local_receivers(
-                        node_id.clone(),
-                        output_id.clone(),
-                        dataflow,
-                        &metadata,
+                    let Some(output) = dataflow.partial_remote_outputs.add_chunk(chunk)? else {
+                        return Ok(());
+                    };
+                    // the region is freed in `check_drop_token`
+                    self.shared_memory
+                        .track_region(dataflow_id, output.region.os_id());
+                    let drop_token = DropToken::generate_in(self.run_id);
+                    let data = DataMessage::SharedMemory {
+                        shared_memory_id: output.region.os_id().to_owned(),
+                        len: output.len,
+                        drop_token,
+                    };
+                    dataflow
+                        .reassembled_outputs
+                        .insert(drop_token, output.region);
+                    self.forward_remote_output(
+                        dataflow_id,
+                        output.node_id,
+                        output.output_id,
+                        output.metadata,
+                        Some(data),
+                    )
+                    .await
+                };
+                if let Err(err) = inner
+                    .await
+                    .wrap_err("failed to forward chunked remote output to local receivers")
+                {
+                    tracing::warn!("{err:?}")
+                }
+                Ok(())
+            }
+            InterDaemonEvent::OutputDatagram(datagram) => {
+                let dataflow_id = datagram.dataflow_id;
+                let inner = async {
+                    let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+                        format!("send out failed: no running dataflow with ID `{dataflow_id}`")
+                    })?;
+                    let output_id = OutputId(datagram.node_id.clone(), datagram.output_id.clone());
+                    let Reassembled { lost, complete } = dataflow.datagram_reassembly.add(datagram);
+                    if lost > 0 {
+                        dataflow.record_lost(&output_id, lost);
+                    }
+                    let Some((metadata, data)) = complete else {
+                        return Ok(());
+                    };
+                    let OutputId(node_id, output_id) = output_id;
+                    self.forward_remote_output(
+                        dataflow_id,
+                        node_id,
+                        output_id,
+                        metadata,
                         data.map(DataMessage::Vec),
-                        &self.clock,
                     )
-                    .await?;
-                    Result::<_, eyre::Report>::Ok(())
+                    .await
                 };
                 if let Err(err) = inner
                     .await
-                    .wrap_err("failed to forward remote output to local receivers")
+                    .wrap_err("failed to forward best-effort remote output to local receivers")
                 {
                     tracing::warn!("{err:?}")
                 }
@@ -587,8 +1666,11 @@ impl Daemon {
                     let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
                         format!("send out failed: no running dataflow with ID `{dataflow_id}`")
                     })?;
-                    for (receiver_id, input_id) in &inputs {
-                        close_input(dataflow, receiver_id, input_id, &self.clock);
+                    for ((source_id, output_id), receivers) in inputs {
+                        let source = OutputId(source_id, output_id);
+                        for (receiver_id, input_id) in &receivers {
+                            close_input(dataflow, receiver_id, input_id, &source, &self.clock);
+                        }
                     }
                     Result::<(), eyre::Report>::Ok(())
                 };
@@ -600,128 +1682,1297 @@ impl Daemon {
                 }
                 Ok(())
             }
+            InterDaemonEvent::OutputClosed {
+                dataflow_id,
+                node_id,
+                output_id,
+            } => {
+                tracing::debug!(?dataflow_id, %node_id, %output_id, "received OutputClosed event");
+                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+                    tracing::warn!(
+                        "received OutputClosed event for unknown dataflow `{dataflow_id}`"
+                    );
+                    return Ok(());
+                };
+                let source = OutputId(node_id, output_id);
+                let receivers = dataflow.mappings.get(&source).cloned().unwrap_or_default();
+                for (receiver_id, input_id) in &receivers {
+                    close_input(dataflow, receiver_id, input_id, &source, &self.clock);
+                }
+                Ok(())
+            }
+            InterDaemonEvent::NodeRoutesSwitched {
+                dataflow_id,
+                node_id,
+                machine_id,
+                revert,
+            } => {
+                tracing::debug!(
+                    ?dataflow_id,
+                    %node_id,
+                    %machine_id,
+                    revert,
+                    "received NodeRoutesSwitched event"
+                );
+                let migration = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .and_then(|dataflow| dataflow.migration.as_mut())
+                    .filter(|migration| migration.node_id == node_id);
+                match migration {
+                    Some(migration) => migration.set_switched(&machine_id, revert),
+                    None => tracing::warn!(
+                        "received NodeRoutesSwitched event for node `{dataflow_id}/{node_id}`, \
+                        which is not migrated from or to this machine"
+                    ),
+                }
+                Ok(())
+            }
         }
     }
 
+    #[tracing::instrument(skip_all, fields(%dataflow_id))]
     async fn spawn_dataflow(
         &mut self,
-        dataflow_id: uuid::Uuid,
-        working_dir: PathBuf,
-        nodes: Vec<ResolvedNode>,
-        dataflow_descriptor: Descriptor,
+        dataflow_id: uuid::Uuid,
+        working_dir: PathBuf,
+        nodes: Vec<ResolvedNode>,
+        dataflow_descriptor: Descriptor,
+        inter_daemon_transport: InterDaemonTransport,
+        instance: Option<DataflowInstance>,
+        params: BTreeMap<String, String>,
+    ) -> eyre::Result<BTreeMap<NodeId, PathBuf>> {
+        if self.running.contains_key(&dataflow_id) {
+            return Err(DuplicateDataflowError::Running { dataflow_id }.into());
+        }
+        if let Some(registry) = &mut self.registry {
+            registry.check_dataflow_id(dataflow_id)?;
+        }
+
+        let PreparedDataflow {
+            dataflow,
+            node_working_dirs,
+            local_nodes,
+            external_inputs,
+        } = self.prepare_dataflow(
+            dataflow_id,
+            &working_dir,
+            nodes,
+            &dataflow_descriptor,
+            inter_daemon_transport,
+            instance,
+            params,
+        )?;
+        match self.running.entry(dataflow_id) {
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                self.working_dir.insert(dataflow_id, working_dir.clone());
+                self.dataflow_dirs.insert(
+                    dataflow_id,
+                    self.paths
+                        .dataflow_dir(&working_dir, &dataflow_id, dataflow.instance.as_ref()),
+                );
+                entry.insert(dataflow);
+                if let Some(dir) = self.paths.dataflow_runtime_dir(&dataflow_id) {
+                    runtime_dir::create(&dir);
+                }
+            }
+            std::collections::hash_map::Entry::Occupied(_) => {
+                bail!("there is already a running dataflow with ID `{dataflow_id}`")
+            }
+        };
+
+        let result = self
+            .spawn_nodes(
+                dataflow_id,
+                &working_dir,
+                local_nodes,
+                external_inputs,
+                inter_daemon_transport,
+            )
+            .await;
+        if let Err(err) = result {
+            self.roll_back_spawn(dataflow_id);
+            return Err(err);
+        }
+        self.journal(JournalEvent::DataflowSpawned {
+            dataflow_id,
+            descriptor_hash: journal::descriptor_hash(&dataflow_descriptor),
+        });
+        Ok(node_working_dirs)
+    }
+
+    /// Resolves everything that [`Self::spawn_dataflow`] would spawn for the
+    /// given dataflow, without spawning anything.
+    ///
+    /// The state of the dataflow is set up in a scratch `RunningDataflow`,
+    /// which is discarded afterwards.
+    async fn plan_local_nodes(
+        &self,
+        dataflow_id: uuid::Uuid,
+        working_dir: PathBuf,
+        nodes: Vec<ResolvedNode>,
+        dataflow_descriptor: Descriptor,
+        inter_daemon_transport: InterDaemonTransport,
+        instance: Option<DataflowInstance>,
+        params: BTreeMap<String, String>,
+    ) -> eyre::Result<MachinePlan> {
+        let PreparedDataflow { dataflow, .. } = self.prepare_dataflow(
+            dataflow_id,
+            &working_dir,
+            nodes,
+            &dataflow_descriptor,
+            inter_daemon_transport,
+            instance,
+            params,
+        )?;
+
+        let mut node_plans = Vec::new();
+        for (layer, nodes) in dataflow.start_layers.pending.iter().enumerate() {
+            for (node, node_working_dir) in nodes {
+                let command = spawn::node_command(
+                    dataflow_id,
+                    dataflow.instance.as_ref(),
+                    &working_dir,
+                    node_working_dir,
+                    &self.paths,
+                    node,
+                    self.listen_addresses.map(|a| a.local),
+                    true,
+                )
+                .await
+                .wrap_err_with(|| format!("failed to resolve command of node `{}`", node.id))?;
+                node_plans.push(NodePlan {
+                    id: node.id.clone(),
+                    layer: layer + 1,
+                    working_dir: node_working_dir.clone(),
+                    command: command.as_ref().map(spawn::describe_command),
+                    inputs: node_inputs(node)
+                        .into_iter()
+                        .map(|(input_id, input)| {
+                            let sources = input.mappings().map(|m| m.to_string()).collect();
+                            (input_id, sources)
+                        })
+                        .collect(),
+                });
+            }
+        }
+        let timers = dataflow
+            .timers
+            .iter()
+            .map(|(interval, inputs)| {
+                let inputs = inputs
+                    .iter()
+                    .map(|(node_id, input_id)| format!("{node_id}/{input_id}"))
+                    .collect();
+                (*interval, inputs)
+            })
+            .collect();
+        let exposed_outputs = dataflow
+            .exposed_outputs
+            .iter()
+            .map(|(name, OutputId(node_id, output_id))| {
+                (name.clone(), format!("{node_id}/{output_id}"))
+            })
+            .collect();
+        Ok(MachinePlan {
+            working_dir,
+            nodes: node_plans,
+            timers,
+            exposed_outputs,
+        })
+    }
+
+    /// Checks the local nodes of a new dataflow and sets up its state, up to
+    /// the point where the first node would be spawned.
+    ///
+    /// Nothing is spawned and the daemon is not modified, so that this can be
+    /// used for dry runs too.
+    fn prepare_dataflow(
+        &self,
+        dataflow_id: DataflowId,
+        working_dir: &Path,
+        mut nodes: Vec<ResolvedNode>,
+        dataflow_descriptor: &Descriptor,
+        inter_daemon_transport: InterDaemonTransport,
+        instance: Option<DataflowInstance>,
+        params: BTreeMap<String, String>,
+    ) -> eyre::Result<PreparedDataflow> {
+        expand_wildcard_inputs(&mut nodes);
+
+        // check all local nodes first to avoid leaving a partially spawned dataflow behind
+        let mut problems = Vec::new();
+        let mut node_working_dirs = BTreeMap::new();
+        for node in nodes
+            .iter()
+            .filter(|node| node.deploy.machine == self.machine_id)
+        {
+            let node_working_dir = match &node.working_dir {
+                Some(dir) => match dir.resolve(working_dir) {
+                    Ok(dir) => dir,
+                    Err(err) => {
+                        problems.push(format!("node `{}`: invalid working dir: {err}", node.id));
+                        continue;
+                    }
+                },
+                None => working_dir
+                    .canonicalize()
+                    .unwrap_or_else(|_| working_dir.to_owned()),
+            };
+            if !node_working_dir.is_dir() {
+                problems.push(format!(
+                    "node `{}`: working dir `{}` does not exist on machine `{}`",
+                    node.id,
+                    node_working_dir.display(),
+                    self.machine_id
+                ));
+                continue;
+            }
+            problems.extend(spawn::check_node(node, working_dir, &node_working_dir));
+            node_working_dirs.insert(node.id.clone(), node_working_dir);
+        }
+        if !problems.is_empty() {
+            bail!(
+                "cannot spawn dataflow `{dataflow_id}`:\n  - {}",
+                problems.join("\n  - ")
+            );
+        }
+
+        let mut dataflow = RunningDataflow::new(
+            dataflow_id,
+            self.machine_id.clone(),
+            dataflow_descriptor.clone(),
+            nodes.clone(),
+        );
+        dataflow.inter_daemon_transport = inter_daemon_transport;
+        dataflow.instance = instance;
+        dataflow.params = params;
+        dataflow.shared_memory = DataflowSharedMemory::new(self.shared_memory.clone());
+
+        let mut local_nodes = BTreeSet::new();
+        let mut external_inputs = BTreeSet::new();
+        let mut local_spawns = Vec::new();
+        for node in nodes {
+            let local = node.deploy.machine == self.machine_id;
+            if local {
+                local_nodes.insert(node.id.clone());
+                external_inputs.extend(node_inputs(&node).values().flat_map(|input| {
+                    input.mappings().filter_map(|mapping| match mapping {
+                        InputMapping::External { name } => Some(name.clone()),
+                        _ => None,
+                    })
+                }));
+            }
+
+            dataflow.register_inputs(&node, local);
+            if local {
+                if node.kind.dynamic() {
+                    dataflow.dynamic_nodes.insert(node.id.clone());
+                } else if node.detached() {
+                    dataflow.detached_nodes.insert(node.id.clone());
+                } else {
+                    dataflow.pending_nodes.insert(node.id.clone());
+                }
+                if node.subscribe_lifecycle {
+                    dataflow.lifecycle_subscribers.insert(node.id.clone());
+                }
+
+                let node_working_dir = node_working_dirs
+                    .get(&node.id)
+                    .map(PathBuf::as_path)
+                    .unwrap_or(working_dir);
+                local_spawns.push((node, node_working_dir.to_owned()));
+            } else {
+                dataflow.pending_nodes.set_external_nodes(true);
+            }
+        }
+
+        let layers: VecDeque<_> = match dataflow_descriptor.start_order {
+            StartOrder::Parallel => VecDeque::from([local_spawns]),
+            StartOrder::Dependency => {
+                let mut local_spawns: BTreeMap<_, _> = local_spawns
+                    .into_iter()
+                    .map(|spawn| (spawn.0.id.clone(), spawn))
+                    .collect();
+                start_layers(&dataflow.resolved_nodes)
+                    .into_iter()
+                    .map(|layer| {
+                        let layer: Vec<_> = layer
+                            .iter()
+                            .filter_map(|id| local_spawns.remove(id))
+                            .collect();
+                        layer
+                    })
+                    .filter(|layer| !layer.is_empty())
+                    .collect()
+            }
+        };
+        dataflow.start_layers = StartLayers {
+            total: layers.len(),
+            pending: layers,
+            ..Default::default()
+        };
+
+        dataflow.exposed_outputs = dataflow_descriptor
+            .resolve_exposed_outputs()?
+            .into_iter()
+            .filter(|(_, mapping)| local_nodes.contains(&mapping.source))
+            .map(|(name, mapping)| (name, OutputId(mapping.source, mapping.output)))
+            .collect();
+
+        Ok(PreparedDataflow {
+            dataflow,
+            node_working_dirs,
+            local_nodes,
+            external_inputs,
+        })
+    }
+
+    /// Stops waiting for the nodes of a dataflow that failed to spawn.
+    ///
+    /// The spawned nodes of the dataflow were killed already and no
+    /// `SpawnedNodeResult` will arrive for the others, so a daemon with
+    /// `exit_when_done` would wait forever otherwise.
+    fn handle_failed_spawn(&mut self, dataflow_id: DataflowId) -> RunStatus {
+        match &mut self.exit_when_done {
+            Some(exit_when_done) => {
+                exit_when_done.retain(|(id, _)| *id != dataflow_id);
+                if exit_when_done.is_empty() {
+                    tracing::info!("exiting daemon because the dataflow failed to spawn");
+                    RunStatus::Exit
+                } else {
+                    RunStatus::Continue
+                }
+            }
+            None => RunStatus::Continue,
+        }
+    }
+
+    /// Starts the external endpoint server and the inter-daemon transport of
+    /// a prepared dataflow and spawns its first local nodes.
+    async fn spawn_nodes(
+        &mut self,
+        dataflow_id: DataflowId,
+        working_dir: &Path,
+        local_nodes: BTreeSet<NodeId>,
+        external_inputs: BTreeSet<String>,
+        inter_daemon_transport: InterDaemonTransport,
+    ) -> eyre::Result<()> {
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+
+        if !dataflow.exposed_outputs.is_empty() || !external_inputs.is_empty() {
+            let (info, handle) = external::spawn_server(
+                dataflow_id,
+                dataflow.exposed_outputs.keys().cloned().collect(),
+                external_inputs,
+                self.dataflow_events.sender(dataflow_id),
+                self.clock.clone(),
+            )
+            .await
+            .wrap_err("failed to start external endpoint server")?;
+            dataflow._external_server = Some(handle);
+
+            let out_dir =
+                self.paths
+                    .dataflow_dir(working_dir, &dataflow_id, dataflow.instance.as_ref());
+            let info_path = self
+                .paths
+                .create_dataflow_dir(&out_dir)
+                .map(|dir| dir.join(external::EXTERNAL_ENDPOINTS_FILE));
+            match info_path {
+                Some(info_path) => {
+                    if let Err(err) = std::fs::write(&info_path, serde_json::to_vec_pretty(&info)?)
+                    {
+                        tracing::warn!(
+                            "failed to write external endpoint info to `{}`: {err}",
+                            info_path.display()
+                        );
+                    }
+                    tracing::info!(
+                        "external endpoints of dataflow `{dataflow_id}` listening on {} (see {})",
+                        info.address,
+                        info_path.display()
+                    );
+                }
+                None => tracing::info!(
+                    "external endpoints of dataflow `{dataflow_id}` listening on {}",
+                    info.address
+                ),
+            }
+        }
+
+        match inter_daemon_transport {
+            InterDaemonTransport::Tcp => {}
+            InterDaemonTransport::Zenoh => self.set_up_zenoh(dataflow_id, &local_nodes)?,
+        }
+
+        if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+            if !dataflow.detached_nodes.is_empty() {
+                dataflow
+                    .pending_nodes
+                    .report_if_no_local_nodes(&mut self.coordinator_connection, &self.clock)
+                    .await?;
+            }
+        }
+
+        self.spawn_next_layers(dataflow_id).await
+    }
+
+    /// Spawns the next layer of local nodes of the given dataflow.
+    ///
+    /// With a `start_layer_timeout`, the following layer is spawned once all
+    /// nodes of this layer subscribed or exited, or when the timeout elapses.
+    /// Otherwise, all remaining layers are spawned right away.
+    async fn spawn_next_layers(&mut self, dataflow_id: DataflowId) -> eyre::Result<()> {
+        loop {
+            let dataflow = self
+                .running
+                .get_mut(&dataflow_id)
+                .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+            let layers = &mut dataflow.start_layers;
+            let Some(layer) = layers.pending.pop_front() else {
+                return Ok(());
+            };
+            layers.started += 1;
+            let (started, total) = (layers.started, layers.total);
+            let dependency_order = dataflow.descriptor.start_order == StartOrder::Dependency;
+            let layer_nodes: BTreeSet<_> = layer
+                .iter()
+                .filter(|(node, _)| !node.kind.dynamic() && !node.detached())
+                .map(|(node, _)| node.id.clone())
+                .collect();
+            // detached nodes never subscribe, so there is nothing to wait for
+            // in layers that consist of them only
+            let wait = dataflow
+                .descriptor
+                .start_layer_timeout
+                .filter(|_| !dataflow.start_layers.pending.is_empty() && !layer_nodes.is_empty());
+
+            for (node, node_working_dir) in layer {
+                self.spawn_local_node(dataflow_id, node, &node_working_dir)
+                    .await?;
+            }
+            if dependency_order {
+                self.send_log_message(LogMessage {
+                    dataflow_id,
+                    node_id: None,
+                    level: LogLevel::Info,
+                    target: None,
+                    module_path: None,
+                    file: None,
+                    line: None,
+                    message: format!("layer {started}/{total} started"),
+                })
+                .await?;
+            }
+
+            if let Some(timeout) = wait {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    dataflow.start_layers.waiting = layer_nodes;
+                }
+                let events_tx = self.dataflow_events.sender(dataflow_id);
+                let clock = self.clock.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(timeout).await;
+                    let event = Timestamped {
+                        inner: DoraEvent::StartLayerTimeout {
+                            dataflow_id,
+                            layer: started,
+                        }
+                        .into(),
+                        timestamp: clock.new_timestamp(),
+                    };
+                    let _ = events_tx.send(event).await;
+                });
+                return Ok(());
+            }
+        }
+    }
+
+    /// Marks the given node of the current start layer as done and spawns
+    /// the next layer once all of its nodes are done.
+    async fn finish_start_layer_node(&mut self, dataflow_id: DataflowId, node_id: &NodeId) {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return;
+        };
+        let waiting = &mut dataflow.start_layers.waiting;
+        if waiting.remove(node_id) && waiting.is_empty() {
+            self.continue_start_layers(dataflow_id).await;
+        }
+    }
+
+    /// Spawns the remaining start layers of a dataflow whose nodes are
+    /// already running.
+    ///
+    /// Unlike the first layer, later layers are spawned after the spawn
+    /// request was answered, so the dataflow is stopped on errors instead
+    /// of being rolled back.
+    async fn continue_start_layers(&mut self, dataflow_id: DataflowId) {
+        if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+            dataflow.start_layers.waiting.clear();
+            if dataflow.stop_sent {
+                dataflow.start_layers.pending.clear();
+                return;
+            }
+        }
+        if let Err(err) = self.spawn_next_layers(dataflow_id).await {
+            tracing::error!("failed to spawn nodes of dataflow `{dataflow_id}`: {err:?}");
+            if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                dataflow.start_layers.pending.clear();
+                if let Err(err) = dataflow
+                    .stop_all(&mut self.coordinator_connection, &self.clock, None)
+                    .await
+                {
+                    tracing::warn!("failed to stop dataflow `{dataflow_id}`: {err:?}");
+                }
+            }
+        }
+    }
+
+    /// Spawns the given local node, whose inputs were registered already.
+    #[tracing::instrument(skip_all, fields(node_id = %node.id))]
+    async fn spawn_local_node(
+        &mut self,
+        dataflow_id: DataflowId,
+        node: ResolvedNode,
+        node_working_dir: &Path,
+    ) -> eyre::Result<()> {
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        let working_dir = self
+            .working_dir
+            .get(&dataflow_id)
+            .wrap_err_with(|| format!("no working dir for dataflow `{dataflow_id}`"))?;
+        let node_id = node.id.clone();
+        // detached nodes don't need to connect to dora
+        let ready_timeout = node.ready_timeout.filter(|_| !node.detached());
+        let node_stderr_most_recent = dataflow
+            .node_stderr_most_recent
+            .entry(node_id.clone())
+            .or_insert_with(|| Arc::new(ArrayQueue::new(STDERR_LOG_LINES)))
+            .clone();
+        let running_node = spawn::spawn_node(
+            dataflow_id,
+            dataflow.instance.as_ref(),
+            working_dir,
+            node_working_dir,
+            &self.paths,
+            node,
+            self.dataflow_events.sender(dataflow_id),
+            dataflow.descriptor.clone(),
+            dataflow.params.clone(),
+            self.clock.clone(),
+            node_stderr_most_recent,
+            self.listen_addresses.map(|a| a.local),
+            &self.node_connections,
+            self.run_id,
+        )
+        .await
+        .wrap_err_with(|| format!("failed to spawn node `{node_id}`"))?;
+        if let Some(journal) = &self.journal {
+            journal.record(JournalEvent::NodeSpawned {
+                dataflow_id,
+                node_id: node_id.clone(),
+                pid: running_node.pid,
+            });
+        }
+        if let Some(registry) = &mut self.registry {
+            registry.add(
+                dataflow_id,
+                node_id.clone(),
+                running_node.pid,
+                &running_node.node_config.daemon_communication,
+            );
+        }
+        self.shared_memory
+            .track_listener_regions(dataflow_id, &running_node.node_config.daemon_communication);
+        if let Some(timeout) = ready_timeout {
+            dataflow.ready_timeouts.insert(node_id.clone(), timeout);
+            let events_tx = self.dataflow_events.sender(dataflow_id);
+            let clock = self.clock.clone();
+            let node_id = node_id.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                let event = Timestamped {
+                    inner: DoraEvent::ReadyTimeout {
+                        dataflow_id,
+                        node_id,
+                    }
+                    .into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                let _ = events_tx.send(event).await;
+            });
+        }
+        dataflow.running_nodes.insert(node_id, running_node);
+        Ok(())
+    }
+
+    /// Removes the given dataflow together with all of its pending drop tokens,
+    /// output rings, and partially received remote outputs.
+    ///
+    /// The shared memory of the output rings and reassembled remote outputs
+    /// is unmapped when the returned dataflow is dropped.
+    fn remove_dataflow(&mut self, dataflow_id: DataflowId) -> Option<RunningDataflow> {
+        let mut dataflow = self.running.remove(&dataflow_id)?;
+        self.dataflow_events.close(dataflow_id);
+        for report in self
+            .drop_warnings
+            .remove_dataflow(dataflow_id, Instant::now())
+        {
+            tracing::warn!("{report}");
+        }
+        let discarded = dataflow.partial_remote_outputs.discard_all();
+        if discarded > 0 {
+            tracing::warn!(
+                "discarding {discarded} remote outputs of dataflow `{dataflow_id}` that were \
+                not received completely"
+            );
+        }
+        let mut unaccounted = 0;
+        for (source, (node_id, input_id), edge) in dataflow.edge_stats.unaccounted() {
+            tracing::error!(
+                "messages of edge `{source}` -> `{node_id}/{input_id}` of dataflow \
+                `{dataflow_id}` are not accounted for: {edge} ({} unaccounted)",
+                edge.unaccounted()
+            );
+            unaccounted += 1;
+        }
+        debug_assert_eq!(unaccounted, 0, "edge message counts don't add up");
+        if !dataflow.pending_drop_tokens.is_empty() {
+            tracing::debug!(
+                "discarding {} pending drop tokens ({} bytes) of dataflow `{dataflow_id}`",
+                dataflow.pending_drop_tokens.len(),
+                dataflow.shared_memory_in_flight()
+            );
+        }
+        // receivers may still report the tokens of the dataflow while they exit
+        let now = Instant::now();
+        let mut tombstones = std::mem::take(&mut dataflow.drop_tombstones);
+        for token in dataflow.pending_drop_tokens.keys() {
+            tombstones.insert(*token, now);
+        }
+        self.drop_tombstones.insert(dataflow_id, tombstones);
+        self.drop_token_reports.merge(dataflow.drop_token_reports);
+        Some(dataflow)
+    }
+
+    /// Kills the already spawned nodes of a dataflow that failed to spawn
+    /// and removes the dataflow.
+    ///
+    /// Only the nodes of this machine are rolled back. The coordinator stops
+    /// the dataflow on the other machines when it receives the failed spawn
+    /// result.
+    fn roll_back_spawn(&mut self, dataflow_id: DataflowId) {
+        let Some(dataflow) = self.remove_dataflow(dataflow_id) else {
+            return;
+        };
+        self.working_dir.remove(&dataflow_id);
+        self.dataflow_dirs.remove(&dataflow_id);
+        if let Some(dir) = self.paths.dataflow_runtime_dir(&dataflow_id) {
+            runtime_dir::finish(dir, true, dataflow.descriptor.keep_artifacts);
+        }
+
+        let mut system = sysinfo::System::new();
+        system.refresh_processes();
+        for (node_id, node) in &dataflow.running_nodes {
+            let Some(pid) = node.pid else { continue };
+            if let Some(process) = system.process(Pid::from(pid as usize)) {
+                process.kill();
+                tracing::info!(
+                    "killed node `{dataflow_id}/{node_id}` because the dataflow failed to spawn"
+                );
+            }
+        }
+    }
+
+    fn check_node_reload(&self, dataflow_id: DataflowId, node_id: &NodeId) -> eyre::Result<()> {
+        let dataflow = self
+            .running
+            .get(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        let Some(node) = dataflow.running_nodes.get(node_id) else {
+            bail!("no running node `{node_id}` in dataflow `{dataflow_id}` on this machine");
+        };
+        if node.node_config.dynamic || node.pid.is_none() {
+            bail!("dynamic node `{node_id}` cannot be restarted by the daemon");
+        }
+        if dataflow.reloading_nodes.contains_key(node_id) {
+            bail!("node `{node_id}` is already being reloaded");
+        }
+        if !dataflow.pending_nodes.all_nodes_ready() {
+            bail!("dataflow `{dataflow_id}` is not started yet");
+        }
+        if dataflow.stop_sent {
+            bail!("dataflow `{dataflow_id}` is stopping");
+        }
+        Ok(())
+    }
+
+    /// Sends a stop event to the given node and buffers its inputs until it
+    /// is spawned again.
+    ///
+    /// The node is killed if it doesn't stop within the default grace
+    /// duration. It is spawned again when its exit is reported, see
+    /// [`Self::respawn_reloaded_node`].
+    fn start_node_reload(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<Option<DaemonCoordinatorReply>>,
+    ) {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return;
+        };
+        tracing::info!("reloading node `{dataflow_id}/{node_id}`");
+        let queue_sizes = dataflow
+            .resolved_nodes
+            .iter()
+            .find(|node| node.id == node_id)
+            .map(|node| {
+                node_inputs(node)
+                    .into_iter()
+                    .map(|(id, input)| (id, input.queue_size_or_default()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let (reloading, buffer_tx) = ReloadingNode::new(queue_sizes, reply_tx);
+        // the buffered events are filtered like the events of the old instance
+        let interest = dataflow
+            .subscribe_channels
+            .get(&node_id)
+            .map(|channel| channel.interest)
+            .unwrap_or_default();
+        if let Some(channel) = dataflow
+            .subscribe_channels
+            .insert(node_id.clone(), NodeEventSender::new(buffer_tx, interest))
+        {
+            let _ = channel.send(NodeEvent::Stop, &self.clock);
+        }
+        dataflow.reloading_nodes.insert(node_id.clone(), reloading);
+
+        if let Some(pid) = dataflow.running_nodes.get(&node_id).and_then(|n| n.pid) {
+            kill_after_grace_duration(pid, node_id, "for reloading");
+        }
+    }
+
+    /// Spawns a node that stopped for reloading again.
+    ///
+    /// The executable is resolved again, so that a new build is picked up.
+    async fn respawn_reloaded_node(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+    ) -> eyre::Result<()> {
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+
+        // the stopped instance won't report the drop of its inputs anymore, except
+        // for the buffered, `latest`, and batched messages that the new instance takes over
+        dataflow.buffer_reload_events(&self.clock).await?;
+        let kept: BTreeSet<DropToken> = dataflow
+            .reloading_nodes
+            .get(node_id)
+            .into_iter()
+            .flat_map(|reloading| reloading.buffered_drop_tokens())
+            .chain(dataflow.inputs.of_node(node_id).flat_map(|(_, state)| {
+                let latest = state
+                    .latest
+                    .as_ref()
+                    .and_then(|slot| slot.pending_drop_token());
+                let batch = state
+                    .batch
+                    .iter()
+                    .flat_map(|batch| batch.pending_drop_tokens());
+                latest.into_iter().chain(batch)
+            }))
+            .collect();
+        let released: Vec<_> = dataflow
+            .pending_drop_tokens
+            .iter()
+            .filter(|(token, info)| info.pending_nodes.contains(node_id) && !kept.contains(token))
+            .map(|(token, _)| *token)
+            .collect();
+        for token in released {
+            dataflow
+                .release_drop_token(token, node_id, &self.clock)
+                .await?;
+        }
+        dataflow.drop_channels.remove(node_id);
+        dataflow.running_nodes.remove(node_id);
+
+        self.spawn_reloading_node(dataflow_id, node_id)
+            .await
+            .wrap_err_with(|| format!("failed to spawn node `{node_id}` again"))
+    }
+
+    /// Spawns a new instance of a node whose events are buffered by a
+    /// [`ReloadingNode`].
+    async fn spawn_reloading_node(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+    ) -> eyre::Result<()> {
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        let node = dataflow
+            .resolved_nodes
+            .iter()
+            .find(|node| &node.id == node_id)
+            .cloned()
+            .wrap_err_with(|| format!("no node `{node_id}` in dataflow `{dataflow_id}`"))?;
+        let working_dir = self
+            .working_dir
+            .get(&dataflow_id)
+            .cloned()
+            .wrap_err_with(|| format!("no working dir for dataflow `{dataflow_id}`"))?;
+        let node_working_dir = match &node.working_dir {
+            Some(dir) => dir.resolve(&working_dir)?,
+            None => working_dir.clone(),
+        };
+        let node_stderr_most_recent = dataflow
+            .node_stderr_most_recent
+            .entry(node_id.clone())
+            .or_insert_with(|| Arc::new(ArrayQueue::new(STDERR_LOG_LINES)))
+            .clone();
+        let running_node = spawn::spawn_node(
+            dataflow_id,
+            dataflow.instance.as_ref(),
+            &working_dir,
+            &node_working_dir,
+            &self.paths,
+            node,
+            self.dataflow_events.sender(dataflow_id),
+            dataflow.descriptor.clone(),
+            dataflow.params.clone(),
+            self.clock.clone(),
+            node_stderr_most_recent,
+            self.listen_addresses.map(|a| a.local),
+            &self.node_connections,
+            self.run_id,
+        )
+        .await?;
+        if let Some(journal) = &self.journal {
+            journal.record(JournalEvent::NodeSpawned {
+                dataflow_id,
+                node_id: node_id.clone(),
+                pid: running_node.pid,
+            });
+        }
+        if let Some(registry) = &mut self.registry {
+            registry.add(
+                dataflow_id,
+                node_id.clone(),
+                running_node.pid,
+                &running_node.node_config.daemon_communication,
+            );
+        }
+        self.shared_memory
+            .track_listener_regions(dataflow_id, &running_node.node_config.daemon_communication);
+        dataflow.running_nodes.insert(node_id.clone(), running_node);
+        if let Some(reloading) = dataflow.reloading_nodes.get_mut(node_id) {
+            reloading.respawned = true;
+        }
+        Ok(())
+    }
+
+    /// Stops buffering the inputs of a reloading node and reports the error.
+    ///
+    /// The buffered messages are released when the node stop is handled.
+    fn abort_node_reload(&mut self, dataflow_id: DataflowId, node_id: &NodeId, err: String) {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return;
+        };
+        if let Some(reloading) = dataflow.reloading_nodes.remove(node_id) {
+            tracing::warn!("failed to reload node `{dataflow_id}/{node_id}`: {err}");
+            dataflow.subscribe_channels.remove(node_id);
+            reloading.fail(err);
+        }
+    }
+
+    fn check_node_migration(
+        &self,
+        dataflow_id: DataflowId,
+        node: &ResolvedNode,
+    ) -> eyre::Result<()> {
+        let dataflow = self.running.get(&dataflow_id).wrap_err_with(|| {
+            format!("no running dataflow with ID `{dataflow_id}` on this machine")
+        })?;
+        let node_id = &node.id;
+        if dataflow.inter_daemon_transport != InterDaemonTransport::Tcp {
+            bail!("node migration is only supported with the `tcp` inter-daemon transport");
+        }
+        if node.deploy.machine != self.machine_id {
+            bail!("node `{node_id}` is not assigned to this machine");
+        }
+        if node.kind.dynamic() {
+            bail!("dynamic node `{node_id}` cannot be migrated");
+        }
+        let external_inputs = node_inputs(node).values().any(|input| {
+            input
+                .mappings()
+                .any(|mapping| matches!(mapping, InputMapping::External { .. }))
+        });
+        if external_inputs {
+            bail!("node `{node_id}` has external inputs, which cannot be migrated");
+        }
+        if !dataflow.resolved_nodes.iter().any(|n| &n.id == node_id) {
+            bail!("dataflow `{dataflow_id}` has no node `{node_id}`");
+        }
+        if dataflow.running_nodes.contains_key(node_id) {
+            bail!("node `{node_id}` runs on this machine already");
+        }
+        if let Some(migration) = &dataflow.migration {
+            bail!(
+                "node `{}` of dataflow `{dataflow_id}` is being migrated already",
+                migration.node_id
+            );
+        }
+        if !dataflow.pending_nodes.all_nodes_ready() {
+            bail!("dataflow `{dataflow_id}` is not started yet");
+        }
+        if dataflow.stop_sent {
+            bail!("dataflow `{dataflow_id}` is stopping");
+        }
+        Ok(())
+    }
+
+    /// Spawns a standby instance of a node that is migrated to this machine.
+    ///
+    /// The instance only receives the messages of the machines that switched
+    /// their routes already, see [`NodeMigration`]. Like for a reload, the
+    /// coordinator is notified once the instance subscribed.
+    async fn start_node_migration(
+        &mut self,
+        dataflow_id: DataflowId,
+        node: ResolvedNode,
+        from_machine: String,
+        reply_tx: oneshot::Sender<Option<DaemonCoordinatorReply>>,
+    ) {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return;
+        };
+        let node_id = node.id.clone();
+        tracing::info!("migrating node `{dataflow_id}/{node_id}` from machine `{from_machine}`");
+        let queue_sizes = node_inputs(&node)
+            .into_iter()
+            .map(|(id, input)| (id, input.queue_size_or_default()))
+            .collect();
+        let (reloading, buffer_tx) = ReloadingNode::new(queue_sizes, reply_tx);
+        dataflow.subscribe_channels.insert(
+            node_id.clone(),
+            NodeEventSender::new(buffer_tx, Default::default()),
+        );
+        dataflow.reloading_nodes.insert(node_id.clone(), reloading);
+        dataflow.migration = Some(NodeMigration::new(
+            node_id.clone(),
+            from_machine.clone(),
+            self.machine_id.clone(),
+        ));
+
+        let new_intervals: BTreeSet<_> = node_inputs(&node)
+            .values()
+            .flat_map(|input| input.mappings())
+            .filter_map(|mapping| match mapping {
+                InputMapping::Timer { interval } => Some(*interval),
+                _ => None,
+            })
+            .filter(|interval| !dataflow.timers.contains_key(interval))
+            .collect();
+        if let Some(entry) = dataflow.resolved_nodes.iter_mut().find(|n| n.id == node_id) {
+            *entry = node.clone();
+        }
+        dataflow.register_inputs(&node, true);
+        if dataflow.clock_source.is_none() {
+            let events_tx = self.dataflow_events.sender(dataflow_id);
+            for interval in new_intervals {
+                dataflow.start_timer(interval, &events_tx, &self.clock);
+            }
+        }
+        let events_tx = self.dataflow_events.sender(dataflow_id);
+        dataflow.start_input_timeout_checks(&events_tx, &self.clock);
+        dataflow.start_input_batch_checks(&events_tx, &self.clock);
+
+        if let Err(err) = self.spawn_reloading_node(dataflow_id, &node_id).await {
+            let err = format!("{:?}", err.wrap_err("failed to spawn migrated node"));
+            self.abort_node_reload(dataflow_id, &node_id, err);
+            if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                dataflow.unregister_local_inputs(&node_id);
+                dataflow.migration = None;
+                if let Some(node) = dataflow.resolved_nodes.iter_mut().find(|n| n.id == node_id) {
+                    node.deploy.machine = from_machine;
+                }
+            }
+        }
+    }
+
+    /// Moves the routes of the local outputs to the inputs of a migrating
+    /// node to the new instance, or back to the old instance if `revert` is
+    /// set.
+    ///
+    /// The machines of the migration are notified through a
+    /// `NodeRoutesSwitched` event, which is sent after all outputs that were
+    /// routed the old way.
+    async fn switch_node_routes(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        from_machine: String,
+        to_machine: String,
+        revert: bool,
+    ) -> eyre::Result<()> {
+        let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+            format!("no running dataflow with ID `{dataflow_id}` on this machine")
+        })?;
+        if dataflow.inter_daemon_transport != InterDaemonTransport::Tcp {
+            bail!("node migration is only supported with the `tcp` inter-daemon transport");
+        }
+        if self.machine_id == from_machine && !revert && dataflow.migration.is_none() {
+            if !dataflow.running_nodes.contains_key(&node_id) {
+                bail!("node `{node_id}` does not run on this machine");
+            }
+            if dataflow.reloading_nodes.contains_key(&node_id) {
+                bail!("node `{node_id}` is being reloaded");
+            }
+            dataflow.migration = Some(NodeMigration::new(
+                node_id.clone(),
+                from_machine.clone(),
+                to_machine.clone(),
+            ));
+        }
+        if let Some(migration) = &mut dataflow.migration {
+            if migration.node_id != node_id {
+                bail!(
+                    "node `{}` of dataflow `{dataflow_id}` is being migrated already",
+                    migration.node_id
+                );
+            }
+            migration.set_switched(&self.machine_id, revert);
+        }
+        if revert {
+            dataflow.switch_node_routes(&node_id, &to_machine, &from_machine)?;
+        } else {
+            dataflow.switch_node_routes(&node_id, &from_machine, &to_machine)?;
+        }
+
+        let targets: Vec<_> = [from_machine, to_machine]
+            .into_iter()
+            .filter(|machine| *machine != self.machine_id)
+            .collect();
+        let event = Timestamped {
+            inner: InterDaemonEvent::NodeRoutesSwitched {
+                dataflow_id,
+                node_id,
+                machine_id: self.machine_id.clone(),
+                revert,
+            },
+            timestamp: self.clock.new_timestamp(),
+        };
+        inter_daemon::send_inter_daemon_event(&targets, &mut self.inter_daemon_connections, event)
+            .await
+            .wrap_err("failed to notify the machines of the migration")
+    }
+
+    fn check_migrated_node_stop(
+        &self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+    ) -> eyre::Result<()> {
+        let dataflow = self.running.get(&dataflow_id).wrap_err_with(|| {
+            format!("no running dataflow with ID `{dataflow_id}` on this machine")
+        })?;
+        match &dataflow.migration {
+            Some(migration)
+                if &migration.node_id == node_id && migration.from_machine == self.machine_id =>
+            {
+                Ok(())
+            }
+            _ => bail!("node `{node_id}` is not migrated away from this machine"),
+        }
+    }
+
+    /// Sends a stop event to the old instance of a migrated node.
+    ///
+    /// The instance is killed if it doesn't stop within the default grace
+    /// duration. The coordinator is notified once the instance exited.
+    fn stop_migrated_node(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<Option<DaemonCoordinatorReply>>,
+    ) {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return;
+        };
+        let Some(pid) = dataflow.running_nodes.get(&node_id).map(|n| n.pid) else {
+            // the old instance exited already
+            let reply = DaemonCoordinatorReply::NodeMigrationResult(Ok(()));
+            let _ = reply_tx.send(Some(reply));
+            return;
+        };
+        tracing::info!("stopping old instance of migrated node `{dataflow_id}/{node_id}`");
+        dataflow.retired_nodes.insert(node_id.clone());
+        if let Some(channel) = dataflow.subscribe_channels.remove(&node_id) {
+            let _ = channel.send(NodeEvent::Stop, &self.clock);
+        }
+        if let Some(migration) = &mut dataflow.migration {
+            migration.stop_reply = Some((Instant::now(), reply_tx));
+        }
+        if let Some(pid) = pid {
+            kill_after_grace_duration(pid, node_id, "after its migration");
+        }
+    }
+
+    /// Forgets the migration state of the given node.
+    ///
+    /// If the migration is aborted, the standby instance on this machine is
+    /// stopped.
+    fn finish_node_migration(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+        abort: bool,
+    ) -> eyre::Result<()> {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            // the dataflow finished on this machine when the old instance exited
+            return Ok(());
+        };
+        if !dataflow
+            .migration
+            .as_ref()
+            .is_some_and(|migration| &migration.node_id == node_id)
+        {
+            // e.g. if the preparation failed, which cleans up on its own
+            return Ok(());
+        }
+        let Some(migration) = dataflow.migration.take() else {
+            return Ok(());
+        };
+        if !abort || migration.to_machine != self.machine_id {
+            tracing::info!("finished migration of node `{dataflow_id}/{node_id}`");
+            return Ok(());
+        }
+
+        tracing::info!("aborting migration of node `{dataflow_id}/{node_id}`");
+        if let Some(node) = dataflow
+            .resolved_nodes
+            .iter_mut()
+            .find(|n| &n.id == node_id)
+        {
+            node.deploy.machine = migration.from_machine.clone();
+        }
+        let pid = dataflow.running_nodes.get(node_id).map(|n| n.pid);
+        if pid.is_some() {
+            dataflow.retired_nodes.insert(node_id.clone());
+        } else {
+            // the standby instance exited already
+            dataflow.unregister_local_inputs(node_id);
+        }
+        if let Some(channel) = dataflow.subscribe_channels.remove(node_id) {
+            let _ = channel.send(NodeEvent::Stop, &self.clock);
+        }
+        if let Some(reloading) = dataflow.reloading_nodes.remove(node_id) {
+            reloading.fail("migration was aborted".into());
+        }
+        if let Some(Some(pid)) = pid {
+            kill_after_grace_duration(pid, node_id.clone(), "after its aborted migration");
+        }
+        Ok(())
+    }
+
+    /// Declares zenoh publishers for local outputs with remote receivers and
+    /// subscribes to remote outputs with local receivers.
+    #[cfg(feature = "zenoh")]
+    fn set_up_zenoh(
+        &mut self,
+        dataflow_id: DataflowId,
+        local_nodes: &BTreeSet<NodeId>,
     ) -> eyre::Result<()> {
-        let dataflow = RunningDataflow::new(dataflow_id, self.machine_id.clone());
-        let dataflow = match self.running.entry(dataflow_id) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                self.working_dir.insert(dataflow_id, working_dir.clone());
-                entry.insert(dataflow)
-            }
-            std::collections::hash_map::Entry::Occupied(_) => {
-                bail!("there is already a running dataflow with ID `{dataflow_id}`")
-            }
+        let zenoh = match &mut self.zenoh {
+            Some(zenoh) => zenoh,
+            entry @ None => entry.insert(zenoh_transport::ZenohTransport::open_from_env()?),
         };
-
-        let mut log_messages = Vec::new();
-        for node in nodes {
-            let local = node.deploy.machine == self.machine_id;
-
-            let inputs = node_inputs(&node);
-            for (input_id, input) in inputs {
-                if local {
-                    dataflow
-                        .open_inputs
-                        .entry(node.id.clone())
-                        .or_default()
-                        .insert(input_id.clone());
-                    match input.mapping {
-                        InputMapping::User(mapping) => {
-                            dataflow
-                                .mappings
-                                .entry(OutputId(mapping.source, mapping.output))
-                                .or_default()
-                                .insert((node.id.clone(), input_id));
-                        }
-                        InputMapping::Timer { interval } => {
-                            dataflow
-                                .timers
-                                .entry(interval)
-                                .or_default()
-                                .insert((node.id.clone(), input_id));
-                        }
-                    }
-                } else if let InputMapping::User(mapping) = input.mapping {
-                    dataflow
-                        .open_external_mappings
-                        .entry(OutputId(mapping.source, mapping.output))
-                        .or_default()
-                        .entry(node.deploy.machine.clone())
-                        .or_default()
-                        .insert((node.id.clone(), input_id));
-                }
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+
+        // the machines with receivers for the outputs of each local node
+        let mut receiving_machines: BTreeMap<&NodeId, BTreeSet<String>> = BTreeMap::new();
+        for (output_id, machines) in &dataflow.open_external_mappings {
+            if local_nodes.contains(&output_id.0) {
+                let publisher = zenoh.publisher(dataflow_id, &output_id.0, &output_id.1)?;
+                dataflow
+                    .zenoh_publishers
+                    .insert(output_id.clone(), publisher);
+                receiving_machines
+                    .entry(&output_id.0)
+                    .or_default()
+                    .extend(machines.keys().cloned());
             }
-            if local {
-                if node.kind.dynamic() {
-                    dataflow.dynamic_nodes.insert(node.id.clone());
-                } else {
-                    dataflow.pending_nodes.insert(node.id.clone());
-                }
-
-                let node_id = node.id.clone();
-                let node_stderr_most_recent = dataflow
-                    .node_stderr_most_recent
-                    .entry(node.id.clone())
-                    .or_insert_with(|| Arc::new(ArrayQueue::new(STDERR_LOG_LINES)))
-                    .clone();
-                match spawn::spawn_node(
-                    dataflow_id,
-                    &working_dir,
-                    node,
-                    self.events_tx.clone(),
-                    dataflow_descriptor.clone(),
-                    self.clock.clone(),
-                    node_stderr_most_recent,
-                )
-                .await
-                .wrap_err_with(|| format!("failed to spawn node `{node_id}`"))
-                {
-                    Ok(running_node) => {
-                        dataflow.running_nodes.insert(node_id, running_node);
-                    }
-                    Err(err) => {
-                        log_messages.push(LogMessage {
-                            dataflow_id,
-                            node_id: Some(node_id.clone()),
-                            level: LogLevel::Error,
-                            target: None,
-                            module_path: None,
-                            file: None,
-                            line: None,
-                            message: format!("{err:?}"),
-                        });
-                        let messages = dataflow
-                            .pending_nodes
-                            .handle_node_stop(
-                                &node_id,
-                                &mut self.coordinator_connection,
-                                &self.clock,
-                                &mut dataflow.cascading_error_causes,
-                            )
-                            .await?;
-                        log_messages.extend(messages);
-                    }
-                }
-            } else {
-                dataflow.pending_nodes.set_external_nodes(true);
+        }
+        if !receiving_machines.is_empty() {
+            let waits: Vec<_> = receiving_machines
+                .into_iter()
+                .map(|(node_id, machines)| {
+                    zenoh.wait_for_subscribers(dataflow_id, node_id, machines)
+                })
+                .collect();
+            let events_tx = self.dataflow_events.sender(dataflow_id);
+            let clock = self.clock.clone();
+            tokio::spawn(async move {
+                futures::future::join_all(waits).await;
+                let event = Timestamped {
+                    inner: DoraEvent::TransportReady { dataflow_id }.into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                let _ = events_tx.send(event).await;
+            });
+            dataflow.pending_nodes.set_transport_pending();
+        }
+        // group by remote node to keep the order across its outputs
+        let mut remote_outputs: BTreeMap<&NodeId, BTreeSet<DataId>> = BTreeMap::new();
+        for OutputId(node_id, output_id) in dataflow.mappings.keys() {
+            let remote_node = !local_nodes.contains(node_id)
+                && dataflow.resolved_nodes.iter().any(|n| &n.id == node_id);
+            if remote_node {
+                remote_outputs
+                    .entry(node_id)
+                    .or_default()
+                    .insert(output_id.clone());
             }
         }
-
-        for log_message in log_messages {
-            self.send_log_message(log_message).await?;
+        for (node_id, output_ids) in remote_outputs {
+            let handle = zenoh.subscribe(
+                dataflow_id,
+                node_id,
+                output_ids,
+                self.machine_id.clone(),
+                self.dataflow_events.sender(dataflow_id),
+                self.clock.clone(),
+            )?;
+            dataflow._zenoh_subscriptions.push(handle);
         }
-
         Ok(())
     }
 
+    #[cfg(not(feature = "zenoh"))]
+    fn set_up_zenoh(
+        &mut self,
+        _dataflow_id: DataflowId,
+        _local_nodes: &BTreeSet<NodeId>,
+    ) -> eyre::Result<()> {
+        bail!("dataflow uses zenoh transport, but dora-daemon was built without `zenoh` feature")
+    }
+
     async fn handle_dynamic_node_event(
         &mut self,
         event: DynamicNodeEventWrapper,
@@ -781,6 +3032,7 @@ impl Daemon {
         }
     }
 
+    #[tracing::instrument(skip_all, fields(%dataflow_id, %node_id))]
     async fn handle_node_event(
         &mut self,
         event: DaemonNodeEvent,
@@ -790,8 +3042,10 @@ impl Daemon {
         match event {
             DaemonNodeEvent::Subscribe {
                 event_sender,
+                interest,
                 reply_sender,
             } => {
+                let event_sender = NodeEventSender::new(event_sender, interest);
                 let dataflow = self.running.get_mut(&dataflow_id).ok_or_else(|| {
                     format!("subscribe failed: no running dataflow with ID `{dataflow_id}`")
                 });
@@ -800,9 +3054,49 @@ impl Daemon {
                     Err(err) => {
                         let _ = reply_sender.send(DaemonReply::Result(Err(err)));
                     }
+                    Ok(dataflow)
+                        if dataflow
+                            .reloading_nodes
+                            .get(&node_id)
+                            .is_some_and(|reloading| reloading.respawned) =>
+                    {
+                        tracing::info!("reloaded node `{node_id}` is ready");
+                        if let Some(journal) = &self.journal {
+                            journal.record(JournalEvent::NodeSubscribed {
+                                dataflow_id,
+                                node_id: node_id.clone(),
+                            });
+                        }
+                        dataflow
+                            .finish_node_reload(&node_id, &event_sender, &self.clock)
+                            .await?;
+                        Self::subscribe(dataflow, node_id.clone(), event_sender, &self.clock).await;
+                        // the dataflow is running already
+                        let _ = reply_sender.send(DaemonReply::Result(Ok(())));
+                    }
+                    Ok(dataflow) if dataflow.detached_nodes.contains(&node_id) => {
+                        // detached nodes are not part of the start barrier,
+                        // so they don't wait for the other nodes
+                        tracing::debug!("detached node `{node_id}` connected");
+                        Self::subscribe(dataflow, node_id.clone(), event_sender, &self.clock).await;
+                        let _ = reply_sender.send(DaemonReply::Result(Ok(())));
+                    }
                     Ok(dataflow) => {
                         tracing::debug!("node `{node_id}` is ready");
+                        dataflow.mark_ready(&node_id);
+                        if let Some(journal) = &self.journal {
+                            journal.record(JournalEvent::NodeSubscribed {
+                                dataflow_id,
+                                node_id: node_id.clone(),
+                            });
+                        }
                         Self::subscribe(dataflow, node_id.clone(), event_sender, &self.clock).await;
+                        dataflow.send_lifecycle_event(
+                            LifecycleEvent::NodeStarted {
+                                node_id: node_id.clone(),
+                            },
+                            &self.clock,
+                        );
 
                         let status = dataflow
                             .pending_nodes
@@ -819,12 +3113,14 @@ impl Daemon {
                                 tracing::info!(
                                     "all nodes are ready, starting dataflow `{dataflow_id}`"
                                 );
-                                dataflow.start(&self.events_tx, &self.clock).await?;
+                                let events_tx = self.dataflow_events.sender(dataflow_id);
+                                dataflow.start(&events_tx, &self.clock).await?;
                             }
                             DataflowStatus::Pending => {}
                         }
                     }
                 }
+                self.finish_start_layer_node(dataflow_id, &node_id).await;
             }
             DaemonNodeEvent::SubscribeDrop {
                 event_sender,
@@ -852,6 +3148,10 @@ impl Daemon {
                         .running
                         .get_mut(&dataflow_id)
                         .wrap_err_with(|| format!("failed to get downstream nodes: no running dataflow with ID `{dataflow_id}`"))?;
+                    if dataflow.keeps_outputs_open(&node_id) {
+                        // the outputs stay open for the new node instance
+                        return Ok(());
+                    }
                     send_input_closed_events(
                         dataflow,
                         &mut self.inter_daemon_connections,
@@ -868,6 +3168,8 @@ impl Daemon {
             }
             DaemonNodeEvent::OutputsDone { reply_sender } => {
                 let result = match self.running.get_mut(&dataflow_id) {
+                    // the outputs stay open for the new node instance
+                    Some(dataflow) if dataflow.keeps_outputs_open(&node_id) => Ok(()),
                     Some(dataflow) => {
                         Self::handle_outputs_done(dataflow, &mut self.inter_daemon_connections, &node_id, &self.clock)
                     .await
@@ -879,14 +3181,42 @@ impl Daemon {
                     result.map_err(|err| format!("{err:?}")),
                 ));
             }
+            DaemonNodeEvent::Ready { reply_sender } => {
+                let result = match self.running.get_mut(&dataflow_id) {
+                    Some(dataflow) => {
+                        dataflow.mark_ready(&node_id);
+                        Ok(())
+                    }
+                    None => Err(format!(
+                        "ready failed: no running dataflow with ID `{dataflow_id}`"
+                    )),
+                };
+                let _ = reply_sender.send(DaemonReply::Result(result));
+                self.finish_start_layer_node(dataflow_id, &node_id).await;
+            }
             DaemonNodeEvent::SendOut {
                 output_id,
                 metadata,
                 data,
-            } => self
-                .send_out(dataflow_id, node_id, output_id, metadata, data)
-                .await
-                .context("failed to send out")?,
+                reply_sender,
+            } => {
+                let result = match self.running.get(&dataflow_id) {
+                    Some(dataflow) => dataflow.check_output_declared(&node_id, &output_id),
+                    None => Ok(()),
+                };
+                let declared = result.is_ok();
+                // reply early, the node does not need to wait until the message is delivered
+                let _ = reply_sender.send(DaemonReply::SendOutResult(result));
+                if declared {
+                    self.send_out(dataflow_id, node_id, output_id, metadata, data)
+                        .await
+                        .context("failed to send out")?
+                } else {
+                    tracing::warn!(
+                        "node `{node_id}` tried to send on undeclared output `{output_id}`"
+                    );
+                }
+            }
             DaemonNodeEvent::ReportDrop { tokens } => {
                 let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
                     format!(
@@ -895,6 +3225,7 @@ impl Daemon {
                     )
                 });
 
+                let now = Instant::now();
                 match dataflow {
                     Ok(dataflow) => {
                         for token in tokens {
@@ -903,16 +3234,80 @@ impl Daemon {
                                     if info.pending_nodes.remove(&node_id) {
                                         dataflow.check_drop_token(token, &self.clock).await?;
                                     } else {
-                                        tracing::warn!(
+                                        // the node reported the token before
+                                        tracing::debug!(
                                             "node `{node_id}` is not pending for drop token `{token:?}`"
                                         );
+                                        dataflow.drop_token_reports.late += 1;
                                     }
                                 }
-                                None => tracing::warn!("unknown drop token `{token:?}`"),
+                                None => drop_tombstones::record_report(
+                                    &mut dataflow.drop_token_reports,
+                                    Some(&mut dataflow.drop_tombstones),
+                                    &token,
+                                    self.run_id,
+                                    now,
+                                ),
+                            }
+                        }
+                    }
+                    Err(err) => match self.drop_tombstones.get_mut(&dataflow_id) {
+                        // the dataflow was torn down recently
+                        Some(tombstones) => {
+                            for token in tokens {
+                                drop_tombstones::record_report(
+                                    &mut self.drop_token_reports,
+                                    Some(&mut *tombstones),
+                                    &token,
+                                    self.run_id,
+                                    now,
+                                );
                             }
                         }
+                        None => tracing::warn!("{err:?}"),
+                    },
+                }
+            }
+            DaemonNodeEvent::InputsDropped { counts, sources } => {
+                let now = Instant::now();
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    dataflow.count_queue_drops(&node_id, &counts, &sources);
+                    for (input_id, &count) in &counts {
+                        if let Some(adaptive) = dataflow
+                            .inputs
+                            .get_mut(&(node_id.clone(), input_id.clone()))
+                            .and_then(|state| state.adaptive.as_mut())
+                        {
+                            adaptive.record_queue_drops(count);
+                        }
+                    }
+                }
+                let mut input_stats = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .map(|d| d.input_stats.entry(node_id.clone()).or_default());
+                for (input_id, &count) in &counts {
+                    self.dropped_messages += count;
+                    if let Some(input_stats) = &mut input_stats {
+                        input_stats.entry(input_id.clone()).or_default().dropped += count;
+                    }
+                    if let Some(report) = self.drop_warnings.record(
+                        dataflow_id,
+                        node_id.clone(),
+                        input_id.clone(),
+                        count,
+                        now,
+                    ) {
+                        tracing::warn!("{report}");
+                    }
+                }
+                for (input_id, count) in counts {
+                    if let Err(err) = self
+                        .publish_drop_event(dataflow_id, &node_id, &input_id, count, now)
+                        .await
+                    {
+                        tracing::warn!("failed to publish drop event: {err:?}");
                     }
-                    Err(err) => tracing::warn!("{err:?}"),
                 }
             }
             DaemonNodeEvent::EventStreamDropped { reply_sender } => {
@@ -928,6 +3323,171 @@ impl Daemon {
                 let reply = inner.await.map_err(|err| format!("{err:?}"));
                 let _ = reply_sender.send(DaemonReply::Result(reply));
             }
+            DaemonNodeEvent::Observe {
+                output_id,
+                sender,
+                reply_sender,
+            } => {
+                let result = match self.running.get_mut(&dataflow_id) {
+                    Some(dataflow) => dataflow.add_observer(node_id, output_id, sender),
+                    None => Err(format!("no running dataflow with ID `{dataflow_id}`")),
+                };
+                if let Err(err) = &result {
+                    tracing::info!("rejected observer: {err}");
+                }
+                let _ = reply_sender.send(DaemonReply::Result(result));
+            }
+            DaemonNodeEvent::QueryTopology { reply_sender } => {
+                let result = match self.running.get(&dataflow_id) {
+                    Some(dataflow) => dataflow.topology(&node_id),
+                    None => Err(format!("no running dataflow with ID `{dataflow_id}`")),
+                };
+                let _ = reply_sender.send(DaemonReply::Topology { result });
+            }
+            DaemonNodeEvent::QuerySubscribers {
+                output_id,
+                reply_sender,
+            } => {
+                let result = match self.running.get(&dataflow_id) {
+                    Some(dataflow) => dataflow
+                        .check_output_declared(&node_id, &output_id)
+                        .map(|()| dataflow.subscriber_count(&OutputId(node_id, output_id)))
+                        .map_err(|err| err.to_string()),
+                    None => Err(format!("no running dataflow with ID `{dataflow_id}`")),
+                };
+                let _ = reply_sender.send(DaemonReply::Subscribers { result });
+            }
+            DaemonNodeEvent::RequestDataflowStop {
+                grace_duration,
+                reply_sender,
+            } => {
+                let result = self
+                    .request_dataflow_stop(dataflow_id, &node_id, grace_duration)
+                    .await;
+                let _ = reply_sender.send(DaemonReply::StopRequestResult(result));
+            }
+            DaemonNodeEvent::NodeError {
+                message,
+                reply_sender,
+            } => {
+                tracing::error!("node `{dataflow_id}/{node_id}` reported an error: {message}");
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    // later errors are usually consequences of the first one
+                    dataflow
+                        .reported_node_errors
+                        .entry(node_id.clone())
+                        .or_insert(message);
+                }
+                let _ = reply_sender.send(DaemonReply::Result(Ok(())));
+            }
+            DaemonNodeEvent::TakeLatest { id, reply_sender } => {
+                let event = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .and_then(|dataflow| dataflow.inputs.get_mut(&(node_id.clone(), id)))
+                    .and_then(|state| state.latest.as_mut()?.take());
+                let _ = reply_sender.send(DaemonReply::NextEvents(event.into_iter().collect()));
+            }
+            DaemonNodeEvent::PrepareOutputRing {
+                output_id,
+                slot_len,
+                slots,
+                reply_sender,
+            } => {
+                let result = match self.running.get_mut(&dataflow_id) {
+                    Some(dataflow) => dataflow
+                        .check_output_declared(&node_id, &output_id)
+                        .and_then(|()| {
+                            let prepared = dataflow
+                                .output_rings
+                                .values()
+                                .filter(|ring| ring.owner == node_id)
+                                .count();
+                            if prepared >= MAX_RINGS_PER_NODE {
+                                return Err(SendOutputError::AllocationFailed {
+                                    reason: format!(
+                                        "nodes can prepare at most {MAX_RINGS_PER_NODE} output rings"
+                                    ),
+                                });
+                            }
+                            OutputRing::allocate(node_id.clone(), output_id, slot_len, slots)
+                        })
+                        .map(|ring| {
+                            let ring_id = OutputRingId::generate();
+                            let info = ring.info(ring_id);
+                            for os_id in &info.slot_ids {
+                                self.shared_memory.track_region(dataflow_id, os_id);
+                            }
+                            dataflow.output_rings.insert(ring_id, ring);
+                            info
+                        }),
+                    None => Err(SendOutputError::AllocationFailed {
+                        reason: format!("no running dataflow with ID `{dataflow_id}`"),
+                    }),
+                };
+                let _ = reply_sender.send(DaemonReply::OutputRing { result });
+            }
+            DaemonNodeEvent::SendOutSlot {
+                ring_id,
+                slot_index,
+                valid_len,
+                metadata,
+                reply_sender,
+            } => {
+                let prepared = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .and_then(|dataflow| {
+                        let pending_drop_tokens = &dataflow.pending_drop_tokens;
+                        let ring = dataflow
+                            .output_rings
+                            .get_mut(&ring_id)
+                            .filter(|ring| ring.owner == node_id)?;
+                        let result = ring
+                            .publish(ring_id, slot_index, valid_len, self.run_id, |token| {
+                                pending_drop_tokens.contains_key(token)
+                            })
+                            .map(|(data, token)| (ring.output_id.clone(), data, token));
+                        Some(result)
+                    })
+                    .unwrap_or(Err(SendOutputError::UnknownOutputRing { ring_id }));
+                match prepared {
+                    Ok((output_id, data, token)) => {
+                        // reply early, the node does not need to wait until the message is delivered
+                        let _ = reply_sender.send(DaemonReply::SendOutSlotResult(Ok(token)));
+                        self.send_out(dataflow_id, node_id, output_id, metadata, Some(data))
+                            .await
+                            .context("failed to send out slot")?
+                    }
+                    Err(err) => {
+                        let _ = reply_sender.send(DaemonReply::SendOutSlotResult(Err(err)));
+                    }
+                }
+            }
+            DaemonNodeEvent::SendCached {
+                output_id,
+                mut metadata,
+                hash,
+                reply_sender,
+            } => {
+                let cached = self.cached_output(dataflow_id, &node_id, &output_id, &hash);
+                match cached {
+                    Ok(Some((data, type_info, token))) => {
+                        metadata.type_info = type_info;
+                        // keeps the cache entry when the message is stored again
+                        metadata.set(metadata::CONTENT_HASH_PARAMETER, hash);
+                        // reply early, the node does not need to wait until the message is delivered
+                        let _ = reply_sender.send(DaemonReply::SendCachedResult(Ok(Some(token))));
+                        self.send_out(dataflow_id, node_id, output_id, metadata, Some(data))
+                            .await
+                            .context("failed to send out cached message")?
+                    }
+                    result => {
+                        let _ =
+                            reply_sender.send(DaemonReply::SendCachedResult(result.map(|_| None)));
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -942,44 +3502,245 @@ impl Daemon {
             format!("Reload failed: no running dataflow with ID `{dataflow_id}`")
         })?;
         if let Some(channel) = dataflow.subscribe_channels.get(&node_id) {
-            match send_with_timestamp(channel, NodeEvent::Reload { operator_id }, &self.clock) {
+            match channel.send(NodeEvent::Reload { operator_id }, &self.clock) {
                 Ok(()) => {}
                 Err(_) => {
                     dataflow.subscribe_channels.remove(&node_id);
                 }
             }
         }
-        Ok(())
-    }
-
-    async fn send_out(
-        &mut self,
-        dataflow_id: Uuid,
-        node_id: NodeId,
-        output_id: DataId,
-        metadata: dora_message::metadata::Metadata,
-        data: Option<DataMessage>,
-    ) -> Result<(), eyre::ErrReport> {
-        let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
-            format!("send out failed: no running dataflow with ID `{dataflow_id}`")
-        })?;
-        let data_bytes = send_output_to_local_receivers(
-            node_id.clone(),
-            output_id.clone(),
-            dataflow,
-            &metadata,
-            data,
-            &self.clock,
-        )
-        .await?;
+        Ok(())
+    }
+
+    /// Publishes a record of the given dropped messages on the
+    /// `dora/drop_events` output, if the dataflow has a `drop_events` input.
+    ///
+    /// Drops of the `drop_events` input itself are not reported, as their
+    /// records could be dropped again.
+    async fn publish_drop_event(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: &NodeId,
+        input_id: &DataId,
+        count: u64,
+        now: Instant,
+    ) -> eyre::Result<()> {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return Ok(());
+        };
+        let drop_events = OutputId::from_mapping(&InputMapping::DropEvents);
+        if !dataflow.has_receivers(&drop_events) {
+            return Ok(());
+        }
+        let Some(input) = dataflow
+            .resolved_nodes
+            .iter()
+            .find(|n| &n.id == node_id)
+            .and_then(|node| node_inputs(node).remove(input_id))
+        else {
+            return Ok(());
+        };
+        if input
+            .mappings()
+            .any(|mapping| matches!(mapping, InputMapping::DropEvents))
+        {
+            return Ok(());
+        }
+
+        let time_ms = self
+            .clock
+            .new_timestamp()
+            .get_time()
+            .to_system_time()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let Some(event) = dataflow.drop_notifier.record(
+            format!("{node_id}/{input_id}"),
+            input.mappings().map(|m| m.to_string()).collect(),
+            DropReason::QueueFull,
+            count,
+            time_ms,
+            now,
+        ) else {
+            return Ok(());
+        };
+        let (metadata, data) = json_message(&event, &self.clock)?;
+        let OutputId(source, output_id) = drop_events;
+        self.send_out(
+            dataflow_id,
+            source,
+            output_id,
+            metadata,
+            Some(DataMessage::Vec(data)),
+        )
+        .await
+    }
+
+    async fn send_out(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output_id: DataId,
+        metadata: dora_message::metadata::Metadata,
+        data: Option<DataMessage>,
+    ) -> Result<(), eyre::ErrReport> {
+        let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+            format!("send out failed: no running dataflow with ID `{dataflow_id}`")
+        })?;
+        if !dataflow.has_receivers(&OutputId(node_id.clone(), output_id.clone())) {
+            tracing::debug!("output `{node_id}/{output_id}` has no subscribers");
+        }
+        let data_len = match &data {
+            None => 0,
+            Some(DataMessage::SharedMemory { len, .. }) => *len,
+            Some(DataMessage::Vec(v)) => v.len(),
+        };
+        dataflow
+            .output_stats
+            .entry(node_id.clone())
+            .or_default()
+            .entry(output_id.clone())
+            .or_default()
+            .message_sizes
+            .record(data_len as u64);
+        let data_bytes = send_output_to_local_receivers(
+            node_id.clone(),
+            output_id.clone(),
+            dataflow,
+            &metadata,
+            data,
+            &self.clock,
+        )
+        .await?;
+
+        let output_id = OutputId(node_id, output_id);
+        let persistent = dataflow.persistent_outputs.contains(&output_id);
+        if dataflow.clock_source.as_ref() == Some(&output_id) {
+            self.advance_sim_clock(dataflow_id, &metadata, data_bytes.as_ref())
+                .await?;
+        }
+        if let Some(data) = data_bytes.as_ref().filter(|_| persistent) {
+            self.store_persistent_output(&output_id, &metadata, data);
+        }
+        let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+            format!("send out failed: no running dataflow with ID `{dataflow_id}`")
+        })?;
+        let mut receivers_closed = false;
+        if let Some(subscribers) = dataflow.external_subscribers.get_mut(&output_id) {
+            let message = ExternalMessage {
+                metadata: metadata.clone(),
+                data: data_bytes.clone(),
+            };
+            let count = subscribers.len();
+            subscribers.retain(|subscriber| {
+                !matches!(
+                    subscriber.try_send(message.clone()),
+                    Err(mpsc::error::TrySendError::Closed(_))
+                )
+            });
+            receivers_closed |= subscribers.len() < count;
+        }
+        if let Some(observers) = dataflow.observers.get_mut(&output_id) {
+            let count = observers.len();
+            observers.retain_mut(|observer| observer.send(&metadata, data_bytes.as_ref()));
+            receivers_closed |= observers.len() < count;
+        }
+        if receivers_closed {
+            dataflow.update_subscriber_presence(&self.clock);
+        }
+
+        let now = Instant::now();
+        for tap in dataflow.taps.iter_mut() {
+            if !tap.accept(&output_id, now) {
+                continue;
+            }
+            let Some(connection) = &mut self.coordinator_connection else {
+                break;
+            };
+            let message = TappedMessage::new(
+                dataflow_id,
+                output_id.0.clone(),
+                output_id.1.clone(),
+                metadata.clone(),
+                data_bytes.as_deref().unwrap_or_default(),
+            );
+            let event = DaemonEvent::Tapped {
+                tap_id: tap.id,
+                message,
+            };
+            // the tap must not affect the normal delivery of the output
+            if let Err(err) =
+                send_coordinator_event(connection, &self.machine_id, &self.clock, event).await
+            {
+                tracing::warn!("failed to send tapped message to dora-coordinator: {err:?}");
+            }
+        }
+
+        // allows remote receivers to estimate the clock skew between the machines
+        let mut metadata = metadata;
+        if let Some(clock_offset) = self.clock_sync.estimate() {
+            metadata.parameters.insert(
+                metadata::CLOCK_OFFSET_HINT_PARAMETER.into(),
+                Parameter::Integer(clock_offset.offset),
+            );
+        }
+
+        #[cfg(feature = "zenoh")]
+        if let Some(publisher) = dataflow.zenoh_publishers.get(&output_id) {
+            let event = Timestamped {
+                inner: InterDaemonEvent::Output {
+                    dataflow_id,
+                    node_id: output_id.0,
+                    output_id: output_id.1,
+                    metadata,
+                    data: data_bytes,
+                },
+                timestamp: self.clock.new_timestamp(),
+            };
+            return publisher
+                .publish(&event)
+                .await
+                .wrap_err("failed to publish output to remote receivers");
+        }
 
-        let output_id = OutputId(node_id, output_id);
         let remote_receivers: Vec<_> = dataflow
             .open_external_mappings
             .get(&output_id)
             .map(|m| m.keys().cloned().collect())
             .unwrap_or_default();
-        if !remote_receivers.is_empty() {
+        let udp = self
+            .udp
+            .as_ref()
+            .filter(|_| dataflow.best_effort_outputs.contains(&output_id));
+        if let Some(udp) = udp {
+            let timestamp = self.clock.new_timestamp();
+            for machine in &remote_receivers {
+                let Some(connection) = self.inter_daemon_connections.get(machine) else {
+                    tracing::warn!("unknown target machine `{machine}`");
+                    continue;
+                };
+                let header = OutputDatagram {
+                    dataflow_id,
+                    node_id: output_id.0.clone(),
+                    output_id: output_id.1.clone(),
+                    sequence: dataflow.datagram_sequences.next(&output_id, machine),
+                    index: 0,
+                    total: 0,
+                    len: 0,
+                    metadata: Some(metadata.clone()),
+                    data: Vec::new(),
+                };
+                let data = data_bytes.as_deref().unwrap_or_default();
+                let datagrams =
+                    udp_transport::split_message(header, data, timestamp, udp.datagram_size())
+                        .wrap_err("failed to split output into datagrams")?;
+                // best effort, the receiver counts the message as lost
+                if let Err(err) = udp.send(connection.socket(), &datagrams).await {
+                    tracing::debug!("failed to send best-effort output: {err:?}");
+                }
+            }
+        } else if !remote_receivers.is_empty() {
             let event = Timestamped {
                 inner: InterDaemonEvent::Output {
                     dataflow_id,
@@ -993,7 +3754,7 @@ impl Daemon {
             inter_daemon::send_inter_daemon_event(
                 &remote_receivers,
                 &mut self.inter_daemon_connections,
-                &event,
+                event,
             )
             .await
             .wrap_err("failed to forward output to remote receivers")?;
@@ -1002,45 +3763,92 @@ impl Daemon {
         Ok(())
     }
 
+    /// Stops the dataflow on request of one of its nodes.
+    ///
+    /// Dataflows that were started by a coordinator are stopped through the
+    /// coordinator, so that the nodes on all machines are stopped.
+    async fn request_dataflow_stop(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+        grace_duration: Option<Duration>,
+    ) -> Result<(), StopRequestError> {
+        let dataflow =
+            self.running
+                .get_mut(&dataflow_id)
+                .ok_or_else(|| StopRequestError::Failed {
+                    reason: format!("no running dataflow with ID `{dataflow_id}`"),
+                })?;
+        let allowed = dataflow
+            .resolved_nodes
+            .iter()
+            .any(|node| &node.id == node_id && node.allowed_to_stop);
+        if !allowed {
+            tracing::warn!(
+                "node `{dataflow_id}/{node_id}` is not allowed to stop the dataflow -> \
+                ignoring stop request"
+            );
+            return Err(StopRequestError::PermissionDenied {
+                node_id: node_id.clone(),
+            });
+        }
+        tracing::info!("node `{dataflow_id}/{node_id}` requested to stop the dataflow");
+        dataflow.stopped_by.get_or_insert_with(|| node_id.clone());
+
+        let result = match &mut self.coordinator_connection {
+            Some(connection) => {
+                send_coordinator_event(
+                    connection,
+                    &self.machine_id,
+                    &self.clock,
+                    DaemonEvent::StopRequested {
+                        dataflow_id,
+                        node_id: node_id.clone(),
+                        grace_duration,
+                    },
+                )
+                .await
+            }
+            None => {
+                dataflow
+                    .stop_all(
+                        &mut self.coordinator_connection,
+                        &self.clock,
+                        grace_duration,
+                    )
+                    .await
+            }
+        };
+        result.map_err(|err| StopRequestError::Failed {
+            reason: format!("{err:?}"),
+        })
+    }
+
     async fn subscribe(
         dataflow: &mut RunningDataflow,
         node_id: NodeId,
-        event_sender: UnboundedSender<Timestamped<NodeEvent>>,
+        event_sender: NodeEventSender,
         clock: &HLC,
     ) {
         // some inputs might have been closed already -> report those events
-        let closed_inputs = dataflow
-            .mappings
-            .values()
-            .flatten()
-            .filter(|(node, _)| node == &node_id)
-            .map(|(_, input)| input)
-            .filter(|input| {
-                dataflow
-                    .open_inputs
-                    .get(&node_id)
-                    .map(|open_inputs| !open_inputs.contains(*input))
-                    .unwrap_or(true)
-            });
+        let closed_inputs = dataflow.closed_inputs.get(&node_id).into_iter().flatten();
         for input_id in closed_inputs {
-            let _ = send_with_timestamp(
-                &event_sender,
-                NodeEvent::InputClosed {
-                    id: input_id.clone(),
-                },
-                clock,
-            );
+            let _ = event_sender.send(dataflow.input_closed_event(&node_id, input_id), clock);
+        }
+        if dataflow.input_closed_stops.contains(&node_id) {
+            let _ = event_sender.send(NodeEvent::Stop, clock);
         }
         if dataflow.open_inputs(&node_id).is_empty() {
-            let _ = send_with_timestamp(&event_sender, NodeEvent::AllInputsClosed, clock);
+            let _ = event_sender.send(NodeEvent::AllInputsClosed, clock);
         }
 
         // if a stop event was already sent for the dataflow, send it to
         // the newly connected node too
         if dataflow.stop_sent {
-            let _ = send_with_timestamp(&event_sender, NodeEvent::Stop, clock);
+            let _ = event_sender.send(NodeEvent::Stop, clock);
         }
 
+        dataflow.inputs.start_timeouts(&node_id, Instant::now());
         dataflow.subscribe_channels.insert(node_id, event_sender);
     }
 
@@ -1062,7 +3870,16 @@ impl Daemon {
         Ok(())
     }
 
-    async fn handle_node_stop(&mut self, dataflow_id: Uuid, node_id: &NodeId) -> eyre::Result<()> {
+    /// Cleans up after a local node exited.
+    ///
+    /// The outputs of `retired` node instances stay open because another
+    /// instance of the node takes them over.
+    async fn handle_node_stop(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: &NodeId,
+        retired: bool,
+    ) -> eyre::Result<()> {
         let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
             format!("failed to get downstream nodes: no running dataflow with ID `{dataflow_id}`")
         })?;
@@ -1077,33 +3894,128 @@ impl Daemon {
             )
             .await?;
 
-        Self::handle_outputs_done(
-            dataflow,
-            &mut self.inter_daemon_connections,
-            node_id,
-            &self.clock,
-        )
-        .await?;
+        dataflow.free_output_rings(node_id);
+        if retired {
+            dataflow.subscribe_channels.remove(node_id);
+            dataflow.drop_channels.remove(node_id);
+        } else {
+            Self::handle_outputs_done(
+                dataflow,
+                &mut self.inter_daemon_connections,
+                node_id,
+                &self.clock,
+            )
+            .await?;
+        }
+
+        let mut drops: BTreeMap<&DataId, (u64, u64)> = BTreeMap::new();
+        for (input_id, filter) in dataflow
+            .inputs
+            .of_node(node_id)
+            .filter_map(|(input_id, state)| Some((input_id, state.filter.as_ref()?)))
+        {
+            let DropMetrics { filtered } = filter.metrics();
+            if filtered > 0 {
+                tracing::info!("filtered {filtered} messages of input `{node_id}/{input_id}`");
+                drops.entry(input_id).or_default().0 = filtered;
+            }
+        }
+        for (input_id, adaptive) in dataflow
+            .inputs
+            .of_node(node_id)
+            .filter_map(|(input_id, state)| Some((input_id, state.adaptive.as_ref()?)))
+        {
+            let downsampled = adaptive.stats().downsampled;
+            if downsampled > 0 {
+                tracing::info!(
+                    "downsampled {downsampled} messages of input `{node_id}/{input_id}`"
+                );
+                drops.entry(input_id).or_default().0 += downsampled;
+            }
+        }
+        let mut unread_tokens = Vec::new();
+        for (input_id, slot) in dataflow
+            .inputs
+            .of_node(node_id)
+            .filter_map(|(input_id, state)| Some((input_id, state.latest.as_ref()?)))
+        {
+            let superseded = slot.superseded();
+            if superseded > 0 {
+                tracing::info!("superseded {superseded} messages of input `{node_id}/{input_id}`");
+                drops.entry(input_id).or_default().1 = superseded;
+            }
+            // the node will never take the pending message
+            unread_tokens.extend(slot.pending_drop_token());
+        }
+        if let Some(journal) = &self.journal {
+            for (input_id, (filtered, superseded)) in drops {
+                journal.record(JournalEvent::MessagesDropped {
+                    dataflow_id,
+                    node_id: node_id.clone(),
+                    input_id: input_id.to_string(),
+                    filtered,
+                    superseded,
+                });
+            }
+        }
+        // pending batches are never delivered to the exited instance
+        for (input_id, state) in dataflow.inputs.of_node_mut(node_id) {
+            if let Some(batch) = &mut state.batch {
+                unread_tokens.extend(batch.pending_drop_tokens());
+                batch.take(input_id.clone());
+            }
+        }
+        for token in unread_tokens {
+            dataflow
+                .release_drop_token(token, node_id, &self.clock)
+                .await?;
+        }
+        // the node won't report the messages that it didn't drop before it exited
+        dataflow
+            .release_drop_tokens_of(node_id, &self.clock)
+            .await?;
 
         dataflow.running_nodes.remove(node_id);
-        if dataflow
-            .running_nodes
-            .iter()
-            .all(|(_id, n)| n.node_config.dynamic)
+        if retired {
+            dataflow.unregister_local_inputs(node_id);
+        }
+        if dataflow.start_layers.pending.is_empty()
+            && dataflow
+                .running_nodes
+                .iter()
+                .all(|(_id, n)| n.node_config.dynamic)
         {
+            // there are no results if all local nodes were migrated away
+            let node_results = self.dataflow_node_results.remove(&dataflow_id);
+            let keep_artifacts = dataflow.descriptor.keep_artifacts;
             let result = DataflowDaemonResult {
                 timestamp: self.clock.new_timestamp(),
-                node_results: self
-                    .dataflow_node_results
-                    .get(&dataflow.id)
-                    .context("failed to get dataflow node results")?
-                    .clone(),
+                node_results: node_results.unwrap_or_default(),
+                inputs: std::mem::take(&mut dataflow.input_stats),
+                outputs: std::mem::take(&mut dataflow.output_stats),
+                peak_shared_memory: dataflow.shared_memory.peak(),
+                edges: dataflow.edge_stats.summary(),
+                stopped_by: dataflow.stopped_by.clone(),
             };
+            // the results are only kept for the return value when `exit_when_done` is used
+            if self.exit_when_done.is_some() {
+                self.finished_dataflows.insert(dataflow_id, result.clone());
+            }
+            self.notify(DaemonNotification::DataflowFinished {
+                dataflow_id,
+                result: result.clone(),
+            });
 
             tracing::info!(
-                "Dataflow `{dataflow_id}` finished on machine `{}`",
-                self.machine_id
+                "Dataflow `{dataflow_id}` finished on machine `{}`, shared memory peaked at \
+                {} bytes (daemon-wide peak {} bytes)",
+                self.machine_id,
+                result.peak_shared_memory,
+                self.shared_memory.peak()
             );
+            if let Some(dir) = self.paths.dataflow_runtime_dir(&dataflow_id) {
+                runtime_dir::finish(dir, !result.is_ok(), keep_artifacts);
+            }
             if let Some(connection) = &mut self.coordinator_connection {
                 let msg = serde_json::to_vec(&Timestamped {
                     inner: CoordinatorRequest::Event {
@@ -1119,7 +4031,7 @@ impl Daemon {
                     .await
                     .wrap_err("failed to report dataflow finish to dora-coordinator")?;
             }
-            self.running.remove(&dataflow_id);
+            self.remove_dataflow(dataflow_id);
         }
 
         for log_message in log_messages {
@@ -1129,47 +4041,145 @@ impl Daemon {
         Ok(())
     }
 
+    /// Delivers a tick of the timer with the given interval to its subscribers.
+    async fn deliver_timer_tick(
+        &mut self,
+        dataflow_id: Uuid,
+        interval: Duration,
+        metadata: metadata::Metadata,
+    ) -> eyre::Result<()> {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            tracing::warn!("Timer event for unknown dataflow `{dataflow_id}`");
+            return Ok(());
+        };
+
+        let Some(subscribers) = dataflow.timers.get(&interval) else {
+            return Ok(());
+        };
+
+        let source = InputMapping::Timer { interval }.to_string();
+        let mut closed = Vec::new();
+        for (receiver_id, input_id) in subscribers {
+            if dataflow.migration_blocks(receiver_id, None) {
+                continue;
+            }
+            let Some(channel) = dataflow
+                .subscribe_channels
+                .get(receiver_id)
+                .filter(|channel| channel.wants_inputs())
+            else {
+                continue;
+            };
+            if let Some(filter) = dataflow
+                .inputs
+                .get_mut(&(receiver_id.clone(), input_id.clone()))
+                .and_then(|state| state.filter.as_mut())
+            {
+                if !filter.check(&metadata.timestamp()) {
+                    continue;
+                }
+            }
+
+            let event = NodeEvent::Input {
+                id: input_id.clone(),
+                metadata: metadata.clone(),
+                data: None,
+            };
+            let send_result = match dataflow
+                .inputs
+                .get_mut(&(receiver_id.clone(), input_id.clone()))
+                .and_then(|state| state.latest.as_mut())
+            {
+                Some(slot) => {
+                    let PutResult { notify, .. } = slot.put(Timestamped {
+                        inner: event,
+                        timestamp: self.clock.new_timestamp(),
+                    });
+                    if notify {
+                        channel.send(
+                            NodeEvent::LatestAvailable {
+                                id: input_id.clone(),
+                            },
+                            &self.clock,
+                        )
+                    } else {
+                        Ok(())
+                    }
+                }
+                None => channel.send(event, &self.clock),
+            };
+            match send_result {
+                Ok(()) => {
+                    dataflow.stall.record_activity(Instant::now());
+                    count_delivered(
+                        &mut dataflow.input_stats,
+                        &mut dataflow.inputs,
+                        receiver_id,
+                        input_id,
+                        &source,
+                        metadata.timestamp(),
+                    );
+                }
+                Err(_) => {
+                    closed.push(receiver_id);
+                }
+            }
+        }
+        for id in closed {
+            dataflow.subscribe_channels.remove(id);
+        }
+        dataflow.buffer_reload_events(&self.clock).await?;
+        Ok(())
+    }
+
+    /// Advances the timers of a dataflow with an `external` clock to the time
+    /// of the given clock message and delivers all ticks that are due.
+    async fn advance_sim_clock(
+        &mut self,
+        dataflow_id: Uuid,
+        metadata: &metadata::Metadata,
+        data: Option<&AVec<u8, ConstAlign<128>>>,
+    ) -> eyre::Result<()> {
+        let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+            return Ok(());
+        };
+        let now = match sim_clock::parse_time(metadata, data) {
+            Ok(now) => now,
+            Err(err) => {
+                tracing::warn!("ignoring invalid message of clock source: {err:?}");
+                return Ok(());
+            }
+        };
+        let ticks = dataflow
+            .sim_timers
+            .advance(dataflow.timers.keys().copied(), now);
+        for (interval, time) in ticks {
+            let timestamp = uhlc::Timestamp::new(
+                uhlc::NTP64::from(Duration::from_nanos(time)),
+                *self.clock.get_id(),
+            );
+            self.deliver_timer_tick(dataflow_id, interval, timer_tick_metadata(timestamp))
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(dataflow_id = %event.dataflow_id(), node_id = tracing::field::Empty)
+    )]
     async fn handle_dora_event(&mut self, event: DoraEvent) -> eyre::Result<RunStatus> {
+        if let Some(node_id) = event.node_id() {
+            tracing::Span::current().record("node_id", tracing::field::display(node_id));
+        }
         match event {
             DoraEvent::Timer {
                 dataflow_id,
                 interval,
                 metadata,
             } => {
-                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
-                    tracing::warn!("Timer event for unknown dataflow `{dataflow_id}`");
-                    return Ok(RunStatus::Continue);
-                };
-
-                let Some(subscribers) = dataflow.timers.get(&interval) else {
-                    return Ok(RunStatus::Continue);
-                };
-
-                let mut closed = Vec::new();
-                for (receiver_id, input_id) in subscribers {
-                    let Some(channel) = dataflow.subscribe_channels.get(receiver_id) else {
-                        continue;
-                    };
-
-                    let send_result = send_with_timestamp(
-                        channel,
-                        NodeEvent::Input {
-                            id: input_id.clone(),
-                            metadata: metadata.clone(),
-                            data: None,
-                        },
-                        &self.clock,
-                    );
-                    match send_result {
-                        Ok(()) => {}
-                        Err(_) => {
-                            closed.push(receiver_id);
-                        }
-                    }
-                }
-                for id in closed {
-                    dataflow.subscribe_channels.remove(id);
-                }
+                self.deliver_timer_tick(dataflow_id, interval, metadata)
+                    .await?;
             }
             DoraEvent::Logs {
                 dataflow_id,
@@ -1198,8 +4208,7 @@ impl Daemon {
                         continue;
                     };
 
-                    let send_result = send_with_timestamp(
-                        channel,
+                    let send_result = channel.send(
                         NodeEvent::Input {
                             id: input_id.clone(),
                             metadata: metadata.clone(),
@@ -1218,11 +4227,148 @@ impl Daemon {
                     dataflow.subscribe_channels.remove(id);
                 }
             }
+            DoraEvent::NodeLog { message } => {
+                self.send_log_message(message).await?;
+            }
+            DoraEvent::TransportReady { dataflow_id } => {
+                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+                    return Ok(RunStatus::Continue);
+                };
+                let status = dataflow
+                    .pending_nodes
+                    .handle_transport_ready(
+                        &mut self.coordinator_connection,
+                        &self.clock,
+                        &mut dataflow.cascading_error_causes,
+                    )
+                    .await?;
+                if let DataflowStatus::AllNodesReady = status {
+                    let events_tx = self.dataflow_events.sender(dataflow_id);
+                    dataflow.start(&events_tx, &self.clock).await?;
+                }
+            }
+            DoraEvent::InputBatchCheck { dataflow_id } => {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    let now = Instant::now();
+                    dataflow.send_input_batches(|_, batch| batch.is_due(now));
+                }
+            }
+            DoraEvent::InputTimeoutCheck { dataflow_id } => {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    for ((node_id, input_id), elapsed) in
+                        dataflow.inputs.expired_timeouts(Instant::now())
+                    {
+                        if let Some(channel) = dataflow.subscribe_channels.get(&node_id) {
+                            let _ = channel.send(
+                                NodeEvent::InputTimeout {
+                                    id: input_id,
+                                    elapsed,
+                                },
+                                &self.clock,
+                            );
+                        }
+                    }
+                }
+            }
+            DoraEvent::ReadyTimeout {
+                dataflow_id,
+                node_id,
+            } => {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    dataflow.handle_ready_timeout(&node_id);
+                }
+            }
+            DoraEvent::StartLayerTimeout { dataflow_id, layer } => {
+                let Some(dataflow) = self.running.get(&dataflow_id) else {
+                    return Ok(RunStatus::Continue);
+                };
+                let layers = &dataflow.start_layers;
+                if layers.started == layer && !layers.waiting.is_empty() {
+                    let waiting: Vec<_> = layers.waiting.iter().map(|id| id.to_string()).collect();
+                    tracing::warn!(
+                        "nodes of layer {layer}/{} of dataflow `{dataflow_id}` are not ready \
+                        within the `start_layer_timeout` -> starting next layer anyway \
+                        (waiting for {})",
+                        layers.total,
+                        waiting.join(", ")
+                    );
+                    self.continue_start_layers(dataflow_id).await;
+                }
+            }
             DoraEvent::SpawnedNodeResult {
                 dataflow_id,
                 node_id,
                 exit_status,
             } => {
+                if !self.running.contains_key(&dataflow_id) {
+                    // the dataflow was rolled back because it failed to spawn
+                    tracing::debug!("node `{dataflow_id}/{node_id}` of removed dataflow exited");
+                    return Ok(RunStatus::Continue);
+                }
+                // the error belongs to the exited instance, not to a restarted one
+                let reported_error = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .and_then(|dataflow| dataflow.reported_node_errors.remove(&node_id));
+                let reload_state = self.running.get(&dataflow_id).and_then(|dataflow| {
+                    let reloading = dataflow.reloading_nodes.get(&node_id)?;
+                    Some((reloading.respawned, dataflow.stop_sent))
+                });
+                if let Some((respawned, stop_sent)) = reload_state {
+                    // the new instance prepares its own rings
+                    if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                        dataflow.free_output_rings(&node_id);
+                    }
+                    let result = if respawned {
+                        Err(eyre!("node exited before initializing dora connection"))
+                    } else if stop_sent {
+                        Err(eyre!("dataflow was stopped"))
+                    } else {
+                        tracing::info!(
+                            "node `{dataflow_id}/{node_id}` stopped for reloading ({exit_status:?})"
+                        );
+                        self.respawn_reloaded_node(dataflow_id, &node_id).await
+                    };
+                    match result {
+                        Ok(()) => return Ok(RunStatus::Continue),
+                        Err(err) => {
+                            self.abort_node_reload(dataflow_id, &node_id, format!("{err:?}"))
+                        }
+                    }
+                }
+                let retired = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .is_some_and(|dataflow| dataflow.take_retired_instance(&node_id));
+                if retired {
+                    tracing::info!(
+                        "instance of migrated node `{dataflow_id}/{node_id}` exited ({exit_status:?})"
+                    );
+                    self.journal(JournalEvent::NodeStopped {
+                        dataflow_id,
+                        node_id: node_id.clone(),
+                        error: None,
+                    });
+                    if let Some(registry) = &mut self.registry {
+                        registry.remove(dataflow_id, &node_id);
+                    }
+                    let stop_reply = self
+                        .running
+                        .get_mut(&dataflow_id)
+                        .and_then(|dataflow| dataflow.migration.as_mut())
+                        .filter(|migration| migration.node_id == node_id)
+                        .and_then(|migration| migration.stop_reply.take());
+                    if let Some((started, reply_tx)) = stop_reply {
+                        tracing::info!(
+                            "old instance of node `{node_id}` stopped after {:.3}s",
+                            started.elapsed().as_secs_f64()
+                        );
+                        let reply = DaemonCoordinatorReply::NodeMigrationResult(Ok(()));
+                        let _ = reply_tx.send(Some(reply));
+                    }
+                    self.handle_node_stop(dataflow_id, &node_id, true).await?;
+                    return Ok(RunStatus::Continue);
+                }
                 let node_result = match exit_status {
                     NodeExitStatus::Success => {
                         tracing::info!("node {dataflow_id}/{node_id} finished successfully");
@@ -1238,14 +4384,21 @@ impl Daemon {
                         let grace_duration_kill = dataflow
                             .map(|d| d.grace_duration_kills.contains(&node_id))
                             .unwrap_or_default();
+                        let startup_timeout = dataflow
+                            .and_then(|d| d.startup_timeout_kills.get(&node_id))
+                            .copied();
 
-                        let cause = match caused_by_node {
-                            Some(caused_by_node) => {
+                        let cause = match (reported_error, caused_by_node, startup_timeout) {
+                            (Some(message), _, _) => NodeErrorCause::Reported { message },
+                            (None, Some(caused_by_node), _) => {
                                 tracing::info!("marking `{node_id}` as cascading error caused by `{caused_by_node}`");
                                 NodeErrorCause::Cascading { caused_by_node }
                             }
-                            None if grace_duration_kill => NodeErrorCause::GraceDuration,
-                            None => NodeErrorCause::Other {
+                            (None, None, _) if grace_duration_kill => NodeErrorCause::GraceDuration,
+                            (None, None, Some(timeout)) => {
+                                NodeErrorCause::StartupTimeout { timeout }
+                            }
+                            (None, None, None) => NodeErrorCause::Other {
                                 stderr: dataflow
                                     .and_then(|d| d.node_stderr_most_recent.get(&node_id))
                                     .map(|queue| {
@@ -1289,12 +4442,38 @@ impl Daemon {
                 })
                 .await?;
 
+                self.journal(JournalEvent::NodeStopped {
+                    dataflow_id,
+                    node_id: node_id.clone(),
+                    error: node_result.as_ref().err().map(|err| err.to_string()),
+                });
+                if let Some(registry) = &mut self.registry {
+                    registry.remove(dataflow_id, &node_id);
+                }
+                if let Some(dataflow) = self.running.get(&dataflow_id) {
+                    let event = match &node_result {
+                        Ok(()) => LifecycleEvent::NodeStopped {
+                            node_id: node_id.clone(),
+                        },
+                        Err(err) => LifecycleEvent::NodeCrashed {
+                            node_id: node_id.clone(),
+                            error: err.to_string(),
+                        },
+                    };
+                    dataflow.send_lifecycle_event(event, &self.clock);
+                }
+                self.notify(DaemonNotification::NodeFinished {
+                    dataflow_id,
+                    node_id: node_id.clone(),
+                    result: node_result.clone(),
+                });
                 self.dataflow_node_results
                     .entry(dataflow_id)
                     .or_default()
                     .insert(node_id.clone(), node_result);
 
-                self.handle_node_stop(dataflow_id, &node_id).await?;
+                self.finish_start_layer_node(dataflow_id, &node_id).await;
+                self.handle_node_stop(dataflow_id, &node_id, false).await?;
 
                 if let Some(exit_when_done) = &mut self.exit_when_done {
                     exit_when_done.remove(&(dataflow_id, node_id));
@@ -1311,6 +4490,77 @@ impl Daemon {
     }
 }
 
+/// Kills the given node process if it is still running after the default
+/// grace duration.
+fn kill_after_grace_duration(pid: u32, node_id: NodeId, reason: &'static str) {
+    tokio::spawn(async move {
+        tokio::time::sleep(DEFAULT_GRACE_DURATION).await;
+        let mut system = sysinfo::System::new();
+        system.refresh_processes();
+        if let Some(process) = system.process(Pid::from(pid as usize)) {
+            process.kill();
+            warn!(
+                "{node_id} was killed {reason} because it did not stop within {:#?}",
+                DEFAULT_GRACE_DURATION
+            )
+        }
+    });
+}
+
+/// Copies the payload of the given message, mapping its shared memory region
+/// if needed.
+fn read_payload(data: Option<&DataMessage>) -> eyre::Result<AVec<u8, ConstAlign<128>>> {
+    match data {
+        None => Ok(AVec::new(1)),
+        Some(DataMessage::Vec(v)) => Ok(v.clone()),
+        Some(DataMessage::SharedMemory {
+            shared_memory_id,
+            len,
+            ..
+        }) => {
+            if let Some(path) = shared_memory_id.strip_prefix(CACHED_FILE_PREFIX) {
+                return persistent_cache::read_cached_output(path, *len);
+            }
+            let memory = ShmemConf::new()
+                .os_id(shared_memory_id)
+                .open()
+                .wrap_err("failed to map shared memory output")?;
+            Ok(AVec::from_slice(1, &unsafe { memory.as_slice() }[..*len]))
+        }
+    }
+}
+
+/// Encodes the given value as a JSON string array, e.g. a lifecycle event.
+fn json_message(
+    value: &impl serde::Serialize,
+    clock: &HLC,
+) -> eyre::Result<(metadata::Metadata, AVec<u8, ConstAlign<128>>)> {
+    let json = serde_json::to_string(value).wrap_err("failed to serialize message")?;
+    let array = StringArray::from(vec![json]).into_data();
+    let mut data = AVec::__from_elem(128, 0, required_data_size(&array));
+    let type_info = copy_array_into_sample(&mut data, &array);
+    Ok((
+        metadata::Metadata::new(clock.new_timestamp(), type_info),
+        data,
+    ))
+}
+
+fn timer_tick_metadata(timestamp: uhlc::Timestamp) -> metadata::Metadata {
+    let span = tracing::span!(tracing::Level::TRACE, "tick");
+    let _ = span.enter();
+
+    let mut parameters = BTreeMap::new();
+    parameters.insert(
+        "open_telemetry_context".to_string(),
+        #[cfg(feature = "telemetry")]
+        Parameter::String(serialize_context(&span.context())),
+        #[cfg(not(feature = "telemetry"))]
+        Parameter::String("".into()),
+    );
+
+    metadata::Metadata::from_parameters(timestamp, ArrowTypeInfo::empty(), parameters)
+}
+
 async fn send_output_to_local_receivers(
     node_id: NodeId,
     output_id: DataId,
@@ -1323,65 +4573,226 @@ async fn send_output_to_local_receivers(
     let empty_set = BTreeSet::new();
     let output_id = OutputId(node_id, output_id);
     let local_receivers = dataflow.mappings.get(&output_id).unwrap_or(&empty_set);
-    let OutputId(node_id, _) = output_id;
+    let OutputId(node_id, output_id) = output_id;
     let mut closed = Vec::new();
-    for (receiver_id, input_id) in local_receivers {
-        if let Some(channel) = dataflow.subscribe_channels.get(receiver_id) {
-            let item = NodeEvent::Input {
-                id: input_id.clone(),
-                metadata: metadata.clone(),
-                data: data.clone(),
-            };
-            match channel.send(Timestamped {
-                inner: item,
+    let mut released_tokens = Vec::new();
+    let shared_memory_len = match &data {
+        Some(DataMessage::SharedMemory { len, .. }) => *len,
+        _ => 0,
+    };
+    let source = format!("{node_id}/{output_id}");
+    for receiver in local_receivers {
+        let (receiver_id, input_id) = receiver;
+        // not sent on this edge, the instance on the other machine receives it
+        if dataflow.migration_blocks(receiver_id, Some(&node_id)) {
+            continue;
+        }
+        let latest = dataflow
+            .inputs
+            .get(receiver)
+            .is_some_and(|state| state.latest.is_some());
+        if !latest {
+            dataflow.inputs.send_batches_of_sender(
+                &dataflow.subscribe_channels,
+                &dataflow.mappings,
+                &node_id,
+                receiver,
+            );
+        }
+        if let Some(channel) = dataflow
+            .subscribe_channels
+            .get(receiver_id)
+            .filter(|channel| channel.wants_inputs())
+        {
+            if let Some(filter) = dataflow
+                .inputs
+                .get_mut(receiver)
+                .and_then(|state| state.filter.as_mut())
+            {
+                if !filter.check(&timestamp) {
+                    dataflow
+                        .edge_stats
+                        .record_dropped(&source, receiver, EdgeDropReason::Filtered);
+                    dataflow
+                        .input_stats
+                        .entry(receiver_id.clone())
+                        .or_default()
+                        .entry(input_id.clone())
+                        .or_default()
+                        .filtered += 1;
+                    continue;
+                }
+            }
+            if let Some(adaptive) = dataflow
+                .inputs
+                .get_mut(receiver)
+                .and_then(|state| state.adaptive.as_mut())
+            {
+                match adaptive.update(Instant::now()) {
+                    Some(change @ RateChange::Reduced { .. }) => {
+                        tracing::warn!("input `{receiver_id}/{input_id}`: {change}")
+                    }
+                    Some(change @ RateChange::Raised { .. }) => {
+                        tracing::info!("input `{receiver_id}/{input_id}`: {change}")
+                    }
+                    None => {}
+                }
+                if !adaptive.check() {
+                    dataflow.edge_stats.record_dropped(
+                        &source,
+                        receiver,
+                        EdgeDropReason::Downsampled,
+                    );
+                    continue;
+                }
+            }
+            let mut metadata = metadata.clone();
+            if dataflow
+                .inputs
+                .get(receiver)
+                .is_some_and(|state| state.open_sources.is_some())
+            {
+                metadata.parameters.insert(
+                    metadata::INPUT_SOURCE_PARAMETER.into(),
+                    Parameter::String(source.clone()),
+                );
+            }
+            let item = Timestamped {
+                inner: NodeEvent::Input {
+                    id: input_id.clone(),
+                    metadata,
+                    data: data.clone(),
+                },
                 timestamp,
-            }) {
+            };
+            let state = dataflow.inputs.get_mut(receiver);
+            let (latest, batch) = match state {
+                Some(state) => (state.latest.as_mut(), state.batch.as_mut()),
+                None => (None, None),
+            };
+            let send_result = match latest {
+                Some(slot) => {
+                    let replaced_source = slot.pending_source().unwrap_or(&source).to_owned();
+                    let PutResult {
+                        notify,
+                        released_token,
+                    } = slot.put(item);
+                    if !notify {
+                        dataflow.edge_stats.redeclare_dropped(
+                            &replaced_source,
+                            receiver,
+                            EdgeDropReason::Superseded,
+                            1,
+                        );
+                    }
+                    released_tokens.extend(released_token.map(|t| (t, receiver_id.clone())));
+                    if notify {
+                        channel
+                            .send_timestamped(Timestamped {
+                                inner: NodeEvent::LatestAvailable {
+                                    id: input_id.clone(),
+                                },
+                                timestamp,
+                            })
+                            .map_err(|_| ())
+                    } else {
+                        Ok(())
+                    }
+                }
+                None => match batch {
+                    // the pending messages of the batch count as delivered
+                    Some(batch) => match batch.push(item, Instant::now()) {
+                        Some(full) => channel.send_timestamped(full),
+                        None => Ok(()),
+                    },
+                    None => channel.send_timestamped(item).map_err(|_| ()),
+                },
+            };
+            match send_result {
                 Ok(()) => {
+                    dataflow.edge_stats.record_delivered(&source, receiver);
+                    let now = Instant::now();
+                    if let Some(timeout) = dataflow
+                        .inputs
+                        .get_mut(receiver)
+                        .and_then(|state| state.timeout.as_mut())
+                    {
+                        timeout.reset(now);
+                    }
+                    dataflow.stall.record_activity(now);
+                    count_delivered(
+                        &mut dataflow.input_stats,
+                        &mut dataflow.inputs,
+                        receiver_id,
+                        input_id,
+                        &source,
+                        timestamp,
+                    );
                     if let Some(token) = data.as_ref().and_then(|d| d.drop_token()) {
                         dataflow
                             .pending_drop_tokens
                             .entry(token)
-                            .or_insert_with(|| DropTokenInformation {
-                                owner: node_id.clone(),
-                                pending_nodes: Default::default(),
+                            .or_insert_with(|| {
+                                dataflow.shared_memory.add(shared_memory_len);
+                                DropTokenInformation {
+                                    owner: node_id.clone(),
+                                    len: shared_memory_len,
+                                    pending_nodes: Default::default(),
+                                    sent: Instant::now(),
+                                }
                             })
                             .pending_nodes
                             .insert(receiver_id.clone());
                     }
                 }
                 Err(_) => {
+                    dataflow.edge_stats.record_dropped(
+                        &source,
+                        receiver,
+                        EdgeDropReason::ReceiverClosed,
+                    );
                     closed.push(receiver_id);
                 }
             }
+        } else {
+            dataflow
+                .edge_stats
+                .record_dropped(&source, receiver, EdgeDropReason::ReceiverClosed);
         }
     }
+    let receivers_closed = !closed.is_empty();
     for id in closed {
         dataflow.subscribe_channels.remove(id);
     }
-    let (data_bytes, drop_token) = match data {
-        None => (None, None),
-        Some(DataMessage::SharedMemory {
-            shared_memory_id,
-            len,
-            drop_token,
-        }) => {
-            let memory = ShmemConf::new()
-                .os_id(shared_memory_id)
-                .open()
-                .wrap_err("failed to map shared memory output")?;
-            let data = Some(AVec::from_slice(1, &unsafe { memory.as_slice() }[..len]));
-            (data, Some(drop_token))
-        }
-        Some(DataMessage::Vec(v)) => (Some(v), None),
+    if receivers_closed {
+        dataflow.update_subscriber_presence(clock);
+    }
+    dataflow.buffer_reload_events(clock).await?;
+    // replaced messages of `latest` inputs are never delivered
+    for (token, receiver_id) in released_tokens {
+        dataflow
+            .release_drop_token(token, &receiver_id, clock)
+            .await?;
+    }
+    let drop_token = data.as_ref().and_then(|d| d.drop_token());
+    let data_bytes = match data {
+        None => None,
+        Some(DataMessage::Vec(v)) => Some(v),
+        Some(data @ DataMessage::SharedMemory { .. }) => Some(read_payload(Some(&data))?),
     };
     if let Some(token) = drop_token {
         // insert token into `pending_drop_tokens` even if there are no local subscribers
         dataflow
             .pending_drop_tokens
             .entry(token)
-            .or_insert_with(|| DropTokenInformation {
-                owner: node_id.clone(),
-                pending_nodes: Default::default(),
+            .or_insert_with(|| {
+                dataflow.shared_memory.add(shared_memory_len);
+                DropTokenInformation {
+                    owner: node_id.clone(),
+                    len: shared_memory_len,
+                    pending_nodes: Default::default(),
+                    sent: Instant::now(),
+                }
             });
         // check if all local subscribers are finished with the token
         dataflow.check_drop_token(token, clock).await?;
@@ -1389,6 +4800,65 @@ async fn send_output_to_local_receivers(
     Ok(data_bytes)
 }
 
+/// Sends the pending batches of the other inputs of the given receiver that
+/// hold messages of `sender`.
+///
+/// Batches are sent once they are full or due, independent of each other.
+/// Without this, a message of the sender could overtake its older messages
+/// that wait in the batch of another input.
+async fn send_coordinator_event(
+    connection: &mut TcpStream,
+    machine_id: &str,
+    clock: &HLC,
+    event: DaemonEvent,
+) -> eyre::Result<()> {
+    let msg = serde_json::to_vec(&Envelope::new(
+        CoordinatorRequest::Event {
+            machine_id: machine_id.to_owned(),
+            event,
+        },
+        clock.new_timestamp(),
+    ))?;
+    socket_stream_send(connection, &msg).await?;
+    Ok(())
+}
+
+fn count_delivered(
+    input_stats: &mut BTreeMap<NodeId, BTreeMap<DataId, InputSummary>>,
+    inputs: &mut LocalInputs,
+    receiver_id: &NodeId,
+    input_id: &DataId,
+    source: &str,
+    timestamp: uhlc::Timestamp,
+) {
+    inputs
+        .get_or_insert((receiver_id.clone(), input_id.clone()))
+        .last_delivered = Some(timestamp);
+    let input = input_stats
+        .entry(receiver_id.clone())
+        .or_default()
+        .entry(input_id.clone())
+        .or_default();
+    match input.delivered.get_mut(source) {
+        Some(count) => *count += 1,
+        None => {
+            input.delivered.insert(source.to_owned(), 1);
+        }
+    }
+}
+
+async fn write_result_file(path: &Path, summary: &DataflowSummary) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .wrap_err("failed to create directory of result file")?;
+    }
+    let json = serde_json::to_vec_pretty(summary).wrap_err("failed to serialize result")?;
+    tokio::fs::write(path, json)
+        .await
+        .wrap_err_with(|| format!("failed to write result file `{}`", path.display()))
+}
+
 fn node_inputs(node: &ResolvedNode) -> BTreeMap<DataId, Input> {
     match &node.kind {
         CoreNodeKind::Custom(n) => n.run_config.inputs.clone(),
@@ -1405,23 +4875,56 @@ async fn send_input_closed_events<F>(
 where
     F: FnMut(&OutputId) -> bool,
 {
-    let local_node_inputs: BTreeSet<_> = dataflow
+    let local_node_inputs: Vec<_> = dataflow
         .mappings
         .iter()
         .filter(|(k, _)| filter(k))
-        .flat_map(|(_, v)| v)
-        .cloned()
+        .flat_map(|(source, v)| v.iter().map(move |input| (source.clone(), input.clone())))
         .collect();
-    for (receiver_id, input_id) in &local_node_inputs {
-        close_input(dataflow, receiver_id, input_id, clock);
+    for (source, (receiver_id, input_id)) in &local_node_inputs {
+        close_input(dataflow, receiver_id, input_id, source, clock);
     }
 
-    let mut external_node_inputs = BTreeMap::new();
+    let mut external_node_inputs: BTreeMap<_, BTreeMap<_, BTreeSet<_>>> = BTreeMap::new();
     for (output_id, mapping) in &mut dataflow.open_external_mappings {
         if filter(output_id) {
-            external_node_inputs.append(mapping);
+            for (target_machine, inputs) in std::mem::take(mapping) {
+                external_node_inputs
+                    .entry(target_machine)
+                    .or_default()
+                    .entry((output_id.0.clone(), output_id.1.clone()))
+                    .or_default()
+                    .extend(inputs);
+            }
+        }
+    }
+    #[cfg(feature = "zenoh")]
+    if dataflow.inter_daemon_transport == InterDaemonTransport::Zenoh {
+        let closed_outputs: BTreeSet<_> = external_node_inputs
+            .into_values()
+            .flat_map(|inputs| inputs.into_keys())
+            .collect();
+        for (node_id, output_id) in closed_outputs {
+            let output = OutputId(node_id, output_id);
+            let Some(publisher) = dataflow.zenoh_publishers.remove(&output) else {
+                continue;
+            };
+            let event = Timestamped {
+                inner: InterDaemonEvent::OutputClosed {
+                    dataflow_id: dataflow.id,
+                    node_id: output.0,
+                    output_id: output.1,
+                },
+                timestamp: clock.new_timestamp(),
+            };
+            publisher
+                .publish(&event)
+                .await
+                .wrap_err("failed to publish OutputClosed event")?;
         }
+        return Ok(());
     }
+
     if !external_node_inputs.is_empty() {
         for (target_machine, inputs) in external_node_inputs {
             let event = Timestamped {
@@ -1434,7 +4937,7 @@ where
             inter_daemon::send_inter_daemon_event(
                 &[target_machine],
                 inter_daemon_connections,
-                &event,
+                event,
             )
             .await
             .wrap_err("failed to sent InputClosed event to remote receiver")?;
@@ -1443,63 +4946,146 @@ where
     Ok(())
 }
 
-fn close_input(
-    dataflow: &mut RunningDataflow,
-    receiver_id: &NodeId,
-    input_id: &DataId,
-    clock: &HLC,
-) {
-    if let Some(open_inputs) = dataflow.open_inputs.get_mut(receiver_id) {
-        if !open_inputs.remove(input_id) {
-            return;
-        }
-    }
-    if let Some(channel) = dataflow.subscribe_channels.get(receiver_id) {
-        let _ = send_with_timestamp(
-            channel,
-            NodeEvent::InputClosed {
-                id: input_id.clone(),
-            },
-            clock,
-        );
-
-        if dataflow.open_inputs(receiver_id).is_empty() {
-            let _ = send_with_timestamp(channel, NodeEvent::AllInputsClosed, clock);
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 struct RunningNode {
     pid: Option<u32>,
     node_config: NodeConfig,
+    /// Queue settings of the inputs, shared with the listeners of the node.
+    input_queues: InputQueues,
 }
 
 pub struct RunningDataflow {
     id: Uuid,
+    /// Time at which the dataflow was set up on this daemon.
+    created: Instant,
+    /// Machine of this daemon.
+    machine_id: String,
     /// Local nodes that are not started yet
     pending_nodes: PendingNodes,
 
-    subscribe_channels: HashMap<NodeId, UnboundedSender<Timestamped<NodeEvent>>>,
+    /// Event channel of each subscribed local node.
+    ///
+    /// All events for a node go through this single FIFO channel, so messages
+    /// of one sender are delivered in publish order, across all of its outputs.
+    /// Outputs are pushed into the channel right away, without any timeout,
+    /// while the daemon handles the send request of the node. The only
+    /// delayed deliveries are `batch` inputs, see [`send_batches_of_sender`].
+    subscribe_channels: HashMap<NodeId, NodeEventSender>,
     drop_channels: HashMap<NodeId, UnboundedSender<Timestamped<NodeDropEvent>>>,
     mappings: HashMap<OutputId, BTreeSet<InputId>>,
     timers: BTreeMap<Duration, BTreeSet<InputId>>,
+    /// Output that drives the timers, if the dataflow uses an `external` clock.
+    clock_source: Option<OutputId>,
+    /// Timers that are driven by `clock_source`.
+    sim_timers: SimTimers,
     open_inputs: BTreeMap<NodeId, BTreeSet<DataId>>,
+    /// Inputs that were closed already.
+    ///
+    /// Kept to report them to nodes that subscribe after their upstream
+    /// nodes stopped.
+    closed_inputs: BTreeMap<NodeId, BTreeSet<DataId>>,
+    /// Declared outputs of all nodes, as specified in their run config.
+    declared_outputs: BTreeMap<NodeId, BTreeSet<DataId>>,
+    /// Outputs whose messages are stored in the persistent output cache.
+    persistent_outputs: BTreeSet<OutputId>,
+    /// Outputs with `remote_transport: udp`.
+    best_effort_outputs: BTreeSet<OutputId>,
+    /// State of the local inputs that depends on their options.
+    inputs: LocalInputs,
     running_nodes: BTreeMap<NodeId, RunningNode>,
+    /// Local nodes that are restarted through a `ReloadNode` event.
+    reloading_nodes: BTreeMap<NodeId, ReloadingNode>,
+    /// Node that is migrated from or to this machine.
+    migration: Option<NodeMigration>,
+    /// Local node instances that are replaced by an instance on another
+    /// machine, or whose migration was aborted.
+    ///
+    /// Their exit does not close their outputs and is not part of the
+    /// dataflow result.
+    retired_nodes: BTreeSet<NodeId>,
 
     /// List of all dynamic node IDs.
     ///
     /// We want to treat dynamic nodes differently in some cases, so we need
     /// to know which nodes are dynamic.
     dynamic_nodes: BTreeSet<NodeId>,
+    /// Local nodes without inputs and outputs.
+    ///
+    /// They are not part of the start barrier and are only tracked by their
+    /// process, as they might never connect to dora.
+    detached_nodes: BTreeSet<NodeId>,
 
     open_external_mappings: HashMap<OutputId, BTreeMap<String, BTreeSet<InputId>>>,
 
+    /// Rate limits and numbers the records on the `dora/drop_events` output.
+    drop_notifier: DropNotifier,
+    /// Whether the outputs of the subscribed local nodes have receivers.
+    subscriber_presence: SubscriberPresence,
+
+    /// Local outputs that are exposed to external processes, by exposed name.
+    exposed_outputs: BTreeMap<String, OutputId>,
+    /// Subscribers of exposed outputs. Like for observers, messages are
+    /// skipped for a subscriber while its queue is full.
+    external_subscribers: HashMap<OutputId, Vec<mpsc::Sender<ExternalMessage>>>,
+    /// Read-only observers of local outputs, connected through the local
+    /// listener.
+    observers: HashMap<OutputId, Vec<Observer>>,
+    /// Stops the external endpoint server of this dataflow on drop.
+    _external_server: Option<futures::future::RemoteHandle<()>>,
+
+    inter_daemon_transport: InterDaemonTransport,
+    /// Set if the dataflow was started as an instance of a named dataflow.
+    instance: Option<DataflowInstance>,
+    /// Start parameters, which are passed to the nodes in their `NodeConfig`.
+    params: BTreeMap<String, String>,
+    /// Publishers for local outputs with remote receivers (zenoh transport only).
+    #[cfg(feature = "zenoh")]
+    zenoh_publishers: HashMap<OutputId, zenoh_transport::OutputPublisher>,
+    /// Subscriptions to remote outputs, cancelled on drop (zenoh transport only).
+    #[cfg(feature = "zenoh")]
+    _zenoh_subscriptions: Vec<futures::future::RemoteHandle<()>>,
+
     pending_drop_tokens: HashMap<DropToken, DropTokenInformation>,
+    /// Recently freed drop tokens, for telling late drop reports apart from
+    /// unknown tokens.
+    drop_tombstones: DropTombstones,
+    /// Drop reports of tokens that were not pending.
+    drop_token_reports: DropTokenReports,
+    /// Size of the shared memory regions of `pending_drop_tokens` and its
+    /// high-water mark, for the result summary.
+    shared_memory: DataflowSharedMemory,
+    /// Message counts of the local inputs, for the result summary.
+    input_stats: BTreeMap<NodeId, BTreeMap<DataId, InputSummary>>,
+    /// Message counts of the edges to the local inputs, which are checked
+    /// for lost messages when the dataflow is removed.
+    edge_stats: EdgeStats,
+    /// Time of the last message delivery, for detecting stalled dataflows.
+    stall: StallDetector,
+    /// Interval of the task that checks the input timeouts, if started.
+    input_timeout_check_interval: Option<Duration>,
+    /// Interval of the task that checks the input batches, if started.
+    input_batch_check_interval: Option<Duration>,
+    /// Message sizes of the local outputs, for the result summary.
+    output_stats: BTreeMap<NodeId, BTreeMap<DataId, OutputSummary>>,
+    /// Shared memory rings that nodes prepared for sending outputs.
+    output_rings: HashMap<OutputRingId, OutputRing>,
+    /// Rings of exited nodes whose slots are still read by receivers.
+    released_output_rings: Vec<OutputRing>,
+    /// Remote outputs that are received in chunks.
+    partial_remote_outputs: PartialOutputs,
+    /// Sequence numbers of the local best-effort outputs.
+    datagram_sequences: DatagramSequences,
+    /// Best-effort outputs of other machines that are received as datagrams.
+    datagram_reassembly: DatagramReassembly,
+    /// Shared memory of the reassembled remote outputs that receivers still
+    /// access, freed once their drop token is released.
+    reassembled_outputs: HashMap<DropToken, ReassemblyRegion>,
 
     /// Keep handles to all timer tasks of this dataflow to cancel them on drop.
     _timer_handles: Vec<futures::future::RemoteHandle<()>>,
     stop_sent: bool,
+    /// Local node that requested to stop the dataflow, if any.
+    stopped_by: Option<NodeId>,
 
     /// Used in `open_inputs`.
     ///
@@ -1509,31 +5095,548 @@ pub struct RunningDataflow {
     /// Contains the node that caused the error for nodes that experienced a cascading error.
     cascading_error_causes: CascadingErrorCauses,
     grace_duration_kills: Arc<crossbeam_skiplist::SkipSet<NodeId>>,
+    /// Local nodes with `subscribe_lifecycle: true`.
+    lifecycle_subscribers: BTreeSet<NodeId>,
+    /// Local nodes that are spawned in layers, see `start_order`.
+    start_layers: StartLayers,
+    /// Local nodes with a `ready_timeout` that are not ready yet.
+    ready_timeouts: BTreeMap<NodeId, Duration>,
+    /// Nodes that were killed because they were not ready in time.
+    startup_timeout_kills: BTreeMap<NodeId, Duration>,
+    /// Local nodes that were stopped because all of their inputs except
+    /// timers were closed.
+    input_closed_stops: BTreeSet<NodeId>,
 
     node_stderr_most_recent: BTreeMap<NodeId, Arc<ArrayQueue<String>>>,
+    /// First error that each local node reported before exiting, e.g. a
+    /// panic message.
+    reported_node_errors: BTreeMap<NodeId, String>,
+
+    /// The descriptor that this dataflow was spawned from.
+    descriptor: Descriptor,
+    /// All nodes of the dataflow (including remote ones), after alias resolution.
+    resolved_nodes: Vec<ResolvedNode>,
+    /// Active output taps of `dora tap` clients.
+    taps: Vec<OutputTap>,
 }
 
 impl RunningDataflow {
-    fn new(dataflow_id: Uuid, machine_id: String) -> RunningDataflow {
+    fn new(
+        dataflow_id: Uuid,
+        machine_id: String,
+        descriptor: Descriptor,
+        resolved_nodes: Vec<ResolvedNode>,
+    ) -> RunningDataflow {
+        let declared_outputs = resolved_nodes
+            .iter()
+            .map(|node| (node.id.clone(), node.kind.run_config().outputs))
+            .collect();
+        let persistent_outputs = resolved_nodes
+            .iter()
+            .flat_map(|node| {
+                node.persistent_outputs
+                    .iter()
+                    .map(|output| OutputId(node.id.clone(), output.clone()))
+            })
+            .collect();
+        let best_effort_outputs = resolved_nodes
+            .iter()
+            .flat_map(|node| {
+                node.remote_transport
+                    .iter()
+                    .filter(|(_, transport)| **transport == RemoteTransport::Udp)
+                    .map(|(output, _)| OutputId(node.id.clone(), output.clone()))
+            })
+            .collect();
+        // the clock source was already checked when validating the dataflow
+        let clock_source = descriptor
+            .resolve_clock_source()
+            .ok()
+            .flatten()
+            .map(|mapping| OutputId(mapping.source, mapping.output));
         Self {
             id: dataflow_id,
-            pending_nodes: PendingNodes::new(dataflow_id, machine_id),
+            created: Instant::now(),
+            pending_nodes: PendingNodes::new(dataflow_id, machine_id.clone()),
+            drop_notifier: DropNotifier::new(machine_id.clone(), Instant::now()),
+            subscriber_presence: SubscriberPresence::default(),
+            machine_id,
             subscribe_channels: HashMap::new(),
             drop_channels: HashMap::new(),
             mappings: HashMap::new(),
             timers: BTreeMap::new(),
+            clock_source,
+            sim_timers: SimTimers::default(),
             open_inputs: BTreeMap::new(),
+            closed_inputs: BTreeMap::new(),
+            declared_outputs,
+            persistent_outputs,
+            best_effort_outputs,
+            inputs: LocalInputs::default(),
             running_nodes: BTreeMap::new(),
+            reloading_nodes: BTreeMap::new(),
+            migration: None,
+            retired_nodes: BTreeSet::new(),
             dynamic_nodes: BTreeSet::new(),
+            detached_nodes: BTreeSet::new(),
             open_external_mappings: HashMap::new(),
+            exposed_outputs: BTreeMap::new(),
+            external_subscribers: HashMap::new(),
+            observers: HashMap::new(),
+            _external_server: None,
+            inter_daemon_transport: InterDaemonTransport::Tcp,
+            instance: None,
+            params: BTreeMap::new(),
+            #[cfg(feature = "zenoh")]
+            zenoh_publishers: HashMap::new(),
+            #[cfg(feature = "zenoh")]
+            _zenoh_subscriptions: Vec::new(),
             pending_drop_tokens: HashMap::new(),
+            drop_tombstones: DropTombstones::default(),
+            drop_token_reports: DropTokenReports::default(),
+            shared_memory: Default::default(),
+            input_stats: BTreeMap::new(),
+            edge_stats: EdgeStats::default(),
+            stall: StallDetector::new(Instant::now()),
+            input_timeout_check_interval: None,
+            input_batch_check_interval: None,
+            output_stats: BTreeMap::new(),
+            output_rings: HashMap::new(),
+            released_output_rings: Vec::new(),
+            partial_remote_outputs: PartialOutputs::default(),
+            datagram_sequences: DatagramSequences::default(),
+            datagram_reassembly: DatagramReassembly::default(),
+            reassembled_outputs: HashMap::new(),
             _timer_handles: Vec::new(),
             stop_sent: false,
+            stopped_by: None,
             empty_set: BTreeSet::new(),
             cascading_error_causes: Default::default(),
             grace_duration_kills: Default::default(),
+            lifecycle_subscribers: BTreeSet::new(),
+            start_layers: StartLayers::default(),
+            ready_timeouts: BTreeMap::new(),
+            startup_timeout_kills: BTreeMap::new(),
+            input_closed_stops: BTreeSet::new(),
             node_stderr_most_recent: BTreeMap::new(),
+            reported_node_errors: BTreeMap::new(),
+            descriptor,
+            resolved_nodes,
+            taps: Vec::new(),
+        }
+    }
+
+    /// Collects the resolved inputs and outputs of the given node, together
+    /// with the nodes it is connected to.
+    fn topology(&self, node_id: &NodeId) -> Result<NodeTopology, String> {
+        let node = self
+            .resolved_nodes
+            .iter()
+            .find(|n| &n.id == node_id)
+            .ok_or_else(|| format!("unknown node `{node_id}`"))?;
+        let run_config = node.kind.run_config();
+
+        let upstream = run_config
+            .inputs
+            .values()
+            .flat_map(|input| input.mappings())
+            .filter_map(|mapping| match mapping {
+                InputMapping::User(mapping) => Some(mapping.source.clone()),
+                InputMapping::Timer { .. }
+                | InputMapping::External { .. }
+                | InputMapping::DropEvents => None,
+            })
+            .collect();
+        let downstream = self
+            .resolved_nodes
+            .iter()
+            .filter(|other| {
+                node_inputs(other)
+                    .values()
+                    .flat_map(|input| input.mappings())
+                    .any(|mapping| matches!(mapping, InputMapping::User(m) if &m.source == node_id))
+            })
+            .map(|other| other.id.clone())
+            .collect();
+
+        let declared_type = |mapping: &InputMapping| match mapping {
+            InputMapping::User(mapping) => self
+                .resolved_nodes
+                .iter()
+                .find(|n| n.id == mapping.source)
+                .and_then(|source| source.output_types.get(&mapping.output)),
+            InputMapping::Timer { .. }
+            | InputMapping::External { .. }
+            | InputMapping::DropEvents => None,
+        };
+        let input_types = run_config
+            .inputs
+            .iter()
+            .filter_map(|(input_id, input)| {
+                let mut types = input.mappings().map(declared_type);
+                let first = types.next()??;
+                types
+                    .all(|ty| ty == Some(first))
+                    .then(|| (input_id.clone(), first.clone()))
+            })
+            .collect();
+
+        let mut topology = NodeTopology::from_run_config(&run_config, upstream, downstream);
+        topology.input_types = input_types;
+        topology.output_types = node.output_types.clone();
+        Ok(topology)
+    }
+
+    fn check_output_declared(
+        &self,
+        node_id: &NodeId,
+        output_id: &DataId,
+    ) -> Result<(), SendOutputError> {
+        match self.declared_outputs.get(node_id) {
+            Some(outputs) if outputs.contains(output_id) => Ok(()),
+            _ => Err(SendOutputError::OutputNotDeclared {
+                output_id: output_id.clone(),
+            }),
+        }
+    }
+
+    /// Registers an observer for the given output of a local node.
+    fn add_observer(
+        &mut self,
+        node_id: NodeId,
+        output_id: DataId,
+        sender: mpsc::Sender<ObservedMessage>,
+    ) -> Result<(), String> {
+        if !self.running_nodes.contains_key(&node_id) {
+            return Err(format!(
+                "node `{node_id}` of dataflow `{}` is not running on this machine",
+                self.id
+            ));
+        }
+        self.check_output_declared(&node_id, &output_id)
+            .map_err(|err| err.to_string())?;
+        tracing::info!(
+            "observer connected to output `{node_id}/{output_id}` of dataflow `{}`",
+            self.id
+        );
+        self.observers
+            .entry(OutputId(node_id, output_id))
+            .or_default()
+            .push(Observer::new(sender));
+        Ok(())
+    }
+
+    /// Frees the output rings of the given node, e.g. when it exited.
+    ///
+    /// Rings with slots that receivers still read are kept until all their
+    /// messages were dropped, see [`Self::drop_released_output_rings`].
+    fn free_output_rings(&mut self, owner: &NodeId) {
+        let ring_ids: Vec<_> = self
+            .output_rings
+            .iter()
+            .filter(|(_, ring)| &ring.owner == owner)
+            .map(|(ring_id, _)| *ring_id)
+            .collect();
+        for ring_id in ring_ids {
+            if let Some(ring) = self.output_rings.remove(&ring_id) {
+                self.released_output_rings.push(ring);
+            }
+        }
+        self.drop_released_output_rings();
+    }
+
+    /// Unlinks the slots of released output rings that are no longer read.
+    fn drop_released_output_rings(&mut self) {
+        let pending_drop_tokens = &self.pending_drop_tokens;
+        self.released_output_rings
+            .retain(|ring| ring.in_use(|token| pending_drop_tokens.contains_key(token)));
+    }
+
+    fn remove_disconnected_observers(&mut self) {
+        self.observers.retain(|_, observers| {
+            observers.retain(|observer| !observer.is_closed());
+            !observers.is_empty()
+        });
+    }
+
+    /// Number of receivers that the given output currently reaches.
+    ///
+    /// Counts the local receivers that are subscribed to their inputs, the
+    /// remote receivers, and the external subscribers and observers. Outputs
+    /// that are published on a zenoh stream count as one remote receiver,
+    /// as their receivers are not known.
+    fn subscriber_count(&self, output_id: &OutputId) -> u64 {
+        let local = self.mappings.get(output_id).map_or(0, |receivers| {
+            receivers
+                .iter()
+                .filter(|(receiver_id, _)| {
+                    self.subscribe_channels
+                        .get(receiver_id)
+                        .is_some_and(|channel| channel.wants_inputs())
+                })
+                .count()
+        });
+        let remote = self
+            .open_external_mappings
+            .get(output_id)
+            .map_or(0, |machines| {
+                machines.values().map(|receivers| receivers.len()).sum()
+            });
+        #[cfg(feature = "zenoh")]
+        let remote = if self.zenoh_publishers.contains_key(output_id) {
+            remote.max(1)
+        } else {
+            remote
+        };
+        let external = self.external_subscribers.get(output_id).map_or(0, Vec::len);
+        let observers = self.observers.get(output_id).map_or(0, Vec::len);
+        (local + remote + external + observers) as u64
+    }
+
+    /// Sends a `SubscribersChanged` event to the local nodes whose outputs
+    /// gained their first or lost their last receiver.
+    fn update_subscriber_presence(&mut self, clock: &HLC) {
+        let subscribe_channels = &self.subscribe_channels;
+        self.subscriber_presence
+            .retain(|OutputId(node_id, _)| subscribe_channels.contains_key(node_id));
+        let outputs: Vec<_> = self
+            .declared_outputs
+            .iter()
+            .filter(|(node_id, _)| self.subscribe_channels.contains_key(*node_id))
+            .flat_map(|(node_id, outputs)| {
+                outputs
+                    .iter()
+                    .map(|output_id| OutputId(node_id.clone(), output_id.clone()))
+            })
+            .collect();
+        for output_id in outputs {
+            let subscribers = self.subscriber_count(&output_id);
+            if !self.subscriber_presence.update(&output_id, subscribers) {
+                continue;
+            }
+            let OutputId(node_id, output_id) = output_id;
+            if let Some(channel) = self.subscribe_channels.get(&node_id) {
+                let _ = channel.send(
+                    NodeEvent::SubscribersChanged {
+                        output_id,
+                        subscribers,
+                    },
+                    clock,
+                );
+            }
+        }
+    }
+
+    /// Counts lost messages of a best-effort remote output on the edges to
+    /// its local receivers.
+    fn record_lost(&mut self, output_id: &OutputId, count: u64) {
+        let Some(receivers) = self.mappings.get(output_id) else {
+            return;
+        };
+        let source = format!("{}/{}", output_id.0, output_id.1);
+        for receiver in receivers {
+            self.edge_stats.record_lost(&source, receiver, count);
+        }
+    }
+
+    /// Whether any local, external, or remote receiver is subscribed to the given output.
+    fn has_receivers(&self, output_id: &OutputId) -> bool {
+        let local = self
+            .mappings
+            .get(output_id)
+            .is_some_and(|receivers| !receivers.is_empty());
+        let external = self
+            .external_subscribers
+            .get(output_id)
+            .is_some_and(|subscribers| !subscribers.is_empty());
+        let remote = self
+            .open_external_mappings
+            .get(output_id)
+            .is_some_and(|machines| !machines.is_empty());
+        #[cfg(feature = "zenoh")]
+        let remote = remote || self.zenoh_publishers.contains_key(output_id);
+        local || external || remote
+    }
+
+    /// Routes the outputs of this machine to the inputs of the given node to
+    /// `to_machine` instead of `from_machine`.
+    ///
+    /// Local instances of the node keep their routes, the [`NodeMigration`]
+    /// state decides which instance receives a message.
+    fn switch_node_routes(
+        &mut self,
+        node_id: &NodeId,
+        from_machine: &str,
+        to_machine: &str,
+    ) -> eyre::Result<()> {
+        let node = self
+            .resolved_nodes
+            .iter_mut()
+            .find(|node| &node.id == node_id)
+            .wrap_err_with(|| format!("dataflow `{}` has no node `{node_id}`", self.id))?;
+        node.deploy.machine = to_machine.to_owned();
+        let node = node.clone();
+
+        for receivers in self.open_external_mappings.values_mut() {
+            let Some(inputs) = receivers.get_mut(from_machine) else {
+                continue;
+            };
+            let moved: BTreeSet<_> = inputs
+                .iter()
+                .filter(|(receiver, _)| receiver == node_id)
+                .cloned()
+                .collect();
+            if moved.is_empty() {
+                continue;
+            }
+            inputs.retain(|(receiver, _)| receiver != node_id);
+            if inputs.is_empty() {
+                receivers.remove(from_machine);
+            }
+            if to_machine != self.machine_id {
+                receivers
+                    .entry(to_machine.to_owned())
+                    .or_default()
+                    .extend(moved);
+            }
+        }
+
+        if from_machine == self.machine_id {
+            // local outputs were delivered to the local instance before
+            for (input_id, input) in node_inputs(&node) {
+                for mapping in input.mappings() {
+                    let InputMapping::User(mapping) = mapping else {
+                        continue;
+                    };
+                    let local_source = self
+                        .resolved_nodes
+                        .iter()
+                        .any(|n| n.id == mapping.source && n.deploy.machine == self.machine_id);
+                    if local_source {
+                        self.open_external_mappings
+                            .entry(OutputId(mapping.source.clone(), mapping.output.clone()))
+                            .or_default()
+                            .entry(to_machine.to_owned())
+                            .or_default()
+                            .insert((node_id.clone(), input_id.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves messages that were dropped from the input queue of the given
+    /// local node from `delivered` to `dropped` in the edge stats.
+    fn count_queue_drops(
+        &mut self,
+        node_id: &NodeId,
+        counts: &BTreeMap<DataId, u64>,
+        sources: &BTreeMap<DataId, BTreeMap<String, u64>>,
+    ) {
+        for (input_id, &count) in counts {
+            let receiver = (node_id.clone(), input_id.clone());
+            let tagged = sources.get(input_id);
+            for (source, count) in tagged.into_iter().flatten() {
+                self.edge_stats.redeclare_dropped(
+                    source,
+                    &receiver,
+                    EdgeDropReason::QueueFull,
+                    *count,
+                );
+            }
+            let untagged = count.saturating_sub(tagged.map(|t| t.values().sum()).unwrap_or(0));
+            if untagged == 0 {
+                continue;
+            }
+            // messages of inputs with a single source are not tagged
+            let source = self
+                .mappings
+                .iter()
+                .find(|(_, receivers)| receivers.contains(&receiver))
+                .map(|(OutputId(source_node, source_output), _)| {
+                    format!("{source_node}/{source_output}")
+                });
+            if let Some(source) = source {
+                self.edge_stats.redeclare_dropped(
+                    &source,
+                    &receiver,
+                    EdgeDropReason::QueueFull,
+                    untagged,
+                );
+            }
+        }
+    }
+
+    /// Whether messages of the given source node must not be delivered to
+    /// the local instance of a migrating node, because they are routed to
+    /// its instance on the other machine.
+    ///
+    /// Timer ticks are checked without a source.
+    fn migration_blocks(&self, receiver_id: &NodeId, source: Option<&NodeId>) -> bool {
+        let Some(migration) = self
+            .migration
+            .as_ref()
+            .filter(|migration| &migration.node_id == receiver_id)
+        else {
+            return false;
+        };
+        let source_machine = source
+            .and_then(|source| self.resolved_nodes.iter().find(|node| &node.id == source))
+            .map(|node| node.deploy.machine.as_str())
+            .unwrap_or(&self.machine_id);
+        !migration.delivers(&self.machine_id, source_machine)
+    }
+
+    /// Whether the outputs of the given local node stay open when it stops,
+    /// because another instance of the node takes them over.
+    fn keeps_outputs_open(&self, node_id: &NodeId) -> bool {
+        self.reloading_nodes.contains_key(node_id)
+            || self.retired_nodes.contains(node_id)
+            || self.is_standby(node_id)
+    }
+
+    /// Whether the given node is migrated to this machine and its local
+    /// instance is not confirmed yet.
+    fn is_standby(&self, node_id: &NodeId) -> bool {
+        self.migration.as_ref().is_some_and(|migration| {
+            &migration.node_id == node_id && migration.to_machine == self.machine_id
+        })
+    }
+
+    /// Checks whether the exited local instance of the given node was
+    /// replaced by an instance on another machine, or was a standby instance
+    /// of a migration.
+    fn take_retired_instance(&mut self, node_id: &NodeId) -> bool {
+        self.retired_nodes.remove(node_id) || self.is_standby(node_id)
+    }
+
+    /// Serializes the descriptor of this dataflow to YAML, with all nodes in
+    /// their resolved form.
+    fn resolved_descriptor_yaml(&self) -> eyre::Result<String> {
+        #[derive(serde::Serialize)]
+        struct ResolvedDescriptor<'a> {
+            communication: &'a dora_core::config::CommunicationConfig,
+            #[serde(rename = "_unstable_deploy")]
+            deploy: &'a dora_core::descriptor::Deploy,
+            nodes: &'a [ResolvedNode],
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            expose: &'a BTreeMap<String, InputMapping>,
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            external_inputs: &'a BTreeMap<String, String>,
+            #[serde(skip_serializing_if = "ClockConfig::is_wall")]
+            clock: &'a ClockConfig,
         }
+
+        serde_yaml::to_string(&ResolvedDescriptor {
+            communication: &self.descriptor.communication,
+            deploy: &self.descriptor.deploy,
+            nodes: &self.resolved_nodes,
+            expose: &self.descriptor.expose,
+            external_inputs: &self.descriptor.external_inputs,
+            clock: &self.descriptor.clock,
+        })
+        .wrap_err("failed to serialize dataflow descriptor")
     }
 
     async fn start(
@@ -1541,54 +5644,149 @@ impl RunningDataflow {
         events_tx: &mpsc::Sender<Timestamped<Event>>,
         clock: &Arc<HLC>,
     ) -> eyre::Result<()> {
-        for interval in self.timers.keys().copied() {
-            let events_tx = events_tx.clone();
-            let dataflow_id = self.id;
-            let clock = clock.clone();
-            let task = async move {
-                let mut interval_stream = tokio::time::interval(interval);
-                let hlc = HLC::default();
-                loop {
-                    interval_stream.tick().await;
-
-                    let span = tracing::span!(tracing::Level::TRACE, "tick");
-                    let _ = span.enter();
-
-                    let mut parameters = BTreeMap::new();
-                    parameters.insert(
-                        "open_telemetry_context".to_string(),
-                        #[cfg(feature = "telemetry")]
-                        Parameter::String(serialize_context(&span.context())),
-                        #[cfg(not(feature = "telemetry"))]
-                        Parameter::String("".into()),
-                    );
+        // the nodes might have taken a while to spawn
+        self.stall = StallDetector::new(Instant::now());
+        self.start_input_timeout_checks(events_tx, clock);
+        self.start_input_batch_checks(events_tx, clock);
+        if self.clock_source.is_some() {
+            // timers are advanced by the messages of the clock source instead
+            return Ok(());
+        }
+        let intervals: Vec<_> = self.timers.keys().copied().collect();
+        for interval in intervals {
+            self.start_timer(interval, events_tx, clock);
+        }
 
-                    let metadata = metadata::Metadata::from_parameters(
-                        hlc.new_timestamp(),
-                        ArrowTypeInfo::empty(),
-                        parameters,
-                    );
+        Ok(())
+    }
 
-                    let event = Timestamped {
-                        inner: DoraEvent::Timer {
-                            dataflow_id,
-                            interval,
-                            metadata,
-                        }
-                        .into(),
-                        timestamp: clock.new_timestamp(),
-                    };
-                    if events_tx.send(event).await.is_err() {
-                        break;
+    /// Spawns the task that sends the ticks of the timer with the given
+    /// interval.
+    fn start_timer(
+        &mut self,
+        interval: Duration,
+        events_tx: &mpsc::Sender<Timestamped<Event>>,
+        clock: &Arc<HLC>,
+    ) {
+        let events_tx = events_tx.clone();
+        let dataflow_id = self.id;
+        let clock = clock.clone();
+        let task = async move {
+            let mut interval_stream = tokio::time::interval(interval);
+            let hlc = HLC::default();
+            loop {
+                interval_stream.tick().await;
+
+                let metadata = timer_tick_metadata(hlc.new_timestamp());
+
+                let event = Timestamped {
+                    inner: DoraEvent::Timer {
+                        dataflow_id,
+                        interval,
+                        metadata,
                     }
+                    .into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                if events_tx.send(event).await.is_err() {
+                    break;
                 }
-            };
-            let (task, handle) = task.remote_handle();
-            tokio::spawn(task);
-            self._timer_handles.push(handle);
+            }
+        };
+        let (task, handle) = task.remote_handle();
+        tokio::spawn(task);
+        self._timer_handles.push(handle);
+    }
+
+    /// Spawns the task that regularly checks the `timeout` of the inputs,
+    /// unless a task with a short enough interval is running already.
+    ///
+    /// Input timeouts are measured in wall-clock time, also for dataflows
+    /// with an `external` clock.
+    fn start_input_timeout_checks(
+        &mut self,
+        events_tx: &mpsc::Sender<Timestamped<Event>>,
+        clock: &Arc<HLC>,
+    ) {
+        let Some(interval) = self.inputs.timeout_check_interval() else {
+            return;
+        };
+        if self
+            .input_timeout_check_interval
+            .is_some_and(|running| running <= interval)
+        {
+            return;
         }
+        self.input_timeout_check_interval = Some(interval);
+        let events_tx = events_tx.clone();
+        let dataflow_id = self.id;
+        let clock = clock.clone();
+        let task = async move {
+            let mut interval_stream = tokio::time::interval(interval);
+            interval_stream.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval_stream.tick().await;
+                let event = Timestamped {
+                    inner: DoraEvent::InputTimeoutCheck { dataflow_id }.into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                if events_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (task, handle) = task.remote_handle();
+        tokio::spawn(task);
+        self._timer_handles.push(handle);
+    }
 
-        Ok(())
+    /// Spawns the task that regularly sends the input batches whose
+    /// `max_delay` elapsed, unless a task with a short enough interval is
+    /// running already.
+    fn start_input_batch_checks(
+        &mut self,
+        events_tx: &mpsc::Sender<Timestamped<Event>>,
+        clock: &Arc<HLC>,
+    ) {
+        let Some(interval) = self.inputs.batch_check_interval() else {
+            return;
+        };
+        if self
+            .input_batch_check_interval
+            .is_some_and(|running| running <= interval)
+        {
+            return;
+        }
+        self.input_batch_check_interval = Some(interval);
+        let events_tx = events_tx.clone();
+        let dataflow_id = self.id;
+        let clock = clock.clone();
+        let task = async move {
+            let mut interval_stream = tokio::time::interval(interval);
+            interval_stream.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval_stream.tick().await;
+                let event = Timestamped {
+                    inner: DoraEvent::InputBatchCheck { dataflow_id }.into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                if events_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (task, handle) = task.remote_handle();
+        tokio::spawn(task);
+        self._timer_handles.push(handle);
+    }
+
+    /// Sends the pending messages of the selected input batches to their
+    /// receivers.
+    ///
+    /// Receivers that closed their event channel are detected by the next
+    /// delivered message, like for the `InputTimeout` events.
+    fn send_input_batches(&mut self, select: impl FnMut(&InputId, &InputBatch) -> bool) {
+        self.inputs.send_batches(&self.subscribe_channels, select);
     }
 
     async fn stop_all(
@@ -1606,14 +5804,15 @@ impl RunningDataflow {
             )
             .await?;
 
+        self.send_lifecycle_event(LifecycleEvent::DataflowStopping, clock);
         for (_node_id, channel) in self.subscribe_channels.drain() {
-            let _ = send_with_timestamp(&channel, NodeEvent::Stop, clock);
+            let _ = channel.send(NodeEvent::Stop, clock);
         }
 
         let running_nodes = self.running_nodes.clone();
         let grace_duration_kills = self.grace_duration_kills.clone();
         tokio::spawn(async move {
-            let duration = grace_duration.unwrap_or(Duration::from_millis(15000));
+            let duration = grace_duration.unwrap_or(DEFAULT_GRACE_DURATION);
             tokio::time::sleep(duration).await;
             let mut system = sysinfo::System::new();
             system.refresh_processes();
@@ -1635,15 +5834,327 @@ impl RunningDataflow {
         Ok(())
     }
 
+    /// Event that reports the closing of the given input, together with the
+    /// number of messages that were delivered on it.
+    fn input_closed_event(&self, receiver_id: &NodeId, input_id: &DataId) -> NodeEvent {
+        let delivered = self
+            .input_stats
+            .get(receiver_id)
+            .and_then(|inputs| inputs.get(input_id))
+            .map(|input| input.delivered.values().sum())
+            .unwrap_or_default();
+        NodeEvent::InputClosed {
+            id: input_id.clone(),
+            delivered,
+            last_timestamp: self
+                .inputs
+                .get(&(receiver_id.clone(), input_id.clone()))
+                .and_then(|state| state.last_delivered),
+        }
+    }
+
+    /// Delivers the given event on the `dora/lifecycle` input of the nodes
+    /// with `subscribe_lifecycle: true`, except for the node that the event
+    /// is about.
+    fn send_lifecycle_event(&self, event: LifecycleEvent, clock: &HLC) {
+        let about = match &event {
+            LifecycleEvent::NodeStarted { node_id }
+            | LifecycleEvent::NodeStopped { node_id }
+            | LifecycleEvent::NodeCrashed { node_id, .. } => Some(node_id),
+            LifecycleEvent::DataflowStopping => None,
+        };
+        let receivers: Vec<_> = self
+            .lifecycle_subscribers
+            .iter()
+            .filter(|id| Some(*id) != about)
+            .filter_map(|id| self.subscribe_channels.get(id))
+            .filter(|channel| channel.wants_inputs())
+            .collect();
+        if receivers.is_empty() {
+            return;
+        }
+        let (metadata, data) = match json_message(&event, clock) {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!("failed to encode lifecycle event {event:?}: {err:?}");
+                return;
+            }
+        };
+        for channel in receivers {
+            let _ = channel.send(
+                NodeEvent::Input {
+                    id: DataId::from(LIFECYCLE_INPUT.to_owned()),
+                    metadata: metadata.clone(),
+                    data: Some(DataMessage::Vec(data.clone())),
+                },
+                clock,
+            );
+        }
+    }
+
+    /// Stops the `ready_timeout` of the given node, if any.
+    fn mark_ready(&mut self, node_id: &NodeId) {
+        self.ready_timeouts.remove(node_id);
+    }
+
+    /// Kills the given node if it's still not ready.
+    ///
+    /// Its exit is reported as a [`NodeErrorCause::StartupTimeout`] error.
+    fn handle_ready_timeout(&mut self, node_id: &NodeId) {
+        let Some(timeout) = self.ready_timeouts.remove(node_id) else {
+            return;
+        };
+        tracing::error!(
+            "node `{node_id}` was not ready within its `ready_timeout` of {:.1}s -> killing it",
+            timeout.as_secs_f64()
+        );
+        self.startup_timeout_kills.insert(node_id.clone(), timeout);
+        let Some(pid) = self.running_nodes.get(node_id).and_then(|n| n.pid) else {
+            return;
+        };
+        let mut system = sysinfo::System::new();
+        system.refresh_processes();
+        if let Some(process) = system.process(Pid::from(pid as usize)) {
+            process.kill();
+        }
+    }
+
     fn open_inputs(&self, node_id: &NodeId) -> &BTreeSet<DataId> {
         self.open_inputs.get(node_id).unwrap_or(&self.empty_set)
     }
 
+    /// Stops the given node if all of its inputs except timers are closed.
+    ///
+    /// The node is killed if it doesn't exit within the default grace
+    /// duration. Its outputs are closed when it exits, so that the stop
+    /// propagates to the downstream nodes.
+    fn stop_if_inputs_closed(&mut self, node_id: &NodeId, clock: &HLC) {
+        if self.stop_sent || self.input_closed_stops.contains(node_id) {
+            return;
+        }
+        let only_timers_open = self.open_inputs(node_id).iter().all(|input_id| {
+            let input = (node_id.clone(), input_id.clone());
+            self.timers.values().any(|inputs| inputs.contains(&input))
+        });
+        if !only_timers_open {
+            return;
+        }
+
+        tracing::info!("all inputs of node `{node_id}` are closed -> stopping it");
+        self.input_closed_stops.insert(node_id.clone());
+        if let Some(channel) = self.subscribe_channels.get(node_id) {
+            let _ = channel.send(NodeEvent::Stop, clock);
+        }
+        let Some(pid) = self.running_nodes.get(node_id).and_then(|n| n.pid) else {
+            return;
+        };
+        let node_id = node_id.clone();
+        let grace_duration_kills = self.grace_duration_kills.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(DEFAULT_GRACE_DURATION).await;
+            let mut system = sysinfo::System::new();
+            system.refresh_processes();
+            if let Some(process) = system.process(Pid::from(pid as usize)) {
+                grace_duration_kills.insert(node_id.clone());
+                process.kill();
+                warn!(
+                    "{node_id} was killed due to not stopping within the {:#?} grace period \
+                    after all of its inputs were closed",
+                    DEFAULT_GRACE_DURATION
+                )
+            }
+        });
+    }
+
+    /// Removes the given node from the pending nodes of a drop token, e.g.
+    /// because the message was replaced before the node received it.
+    async fn release_drop_token(
+        &mut self,
+        token: DropToken,
+        node_id: &NodeId,
+        clock: &HLC,
+    ) -> eyre::Result<()> {
+        if let Some(info) = self.pending_drop_tokens.get_mut(&token) {
+            if info.pending_nodes.remove(node_id) {
+                self.check_drop_token(token, clock).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases the drop tokens that are pending for longer than the given
+    /// duration, e.g. because a receiver hangs.
+    ///
+    /// The memory of a released message might be reused by its sender, so
+    /// receivers that still access it can read invalid data.
+    ///
+    /// Returns the number of released tokens and their total size.
+    async fn release_stale_drop_tokens(
+        &mut self,
+        older_than: Duration,
+        clock: &HLC,
+    ) -> eyre::Result<(usize, u64)> {
+        let stale: Vec<_> = self
+            .pending_drop_tokens
+            .iter()
+            .filter(|(_, info)| info.sent.elapsed() >= older_than)
+            .map(|(token, info)| (*token, info.len as u64))
+            .collect();
+        let mut reclaimed = 0;
+        for (token, len) in &stale {
+            if let Some(info) = self.pending_drop_tokens.get_mut(token) {
+                info.pending_nodes.clear();
+            }
+            self.check_drop_token(*token, clock).await?;
+            reclaimed += len;
+        }
+        Ok((stale.len(), reclaimed))
+    }
+
+    fn diagnostics(&self, now: Instant, event_queue_depth: usize) -> DataflowDiagnostics {
+        let mut pending_per_node: BTreeMap<&NodeId, usize> = BTreeMap::new();
+        let mut pending_drop_tokens = EntryStats::default();
+        for info in self.pending_drop_tokens.values() {
+            pending_drop_tokens.add(info.len as u64, now.duration_since(info.sent));
+            for node_id in &info.pending_nodes {
+                *pending_per_node.entry(node_id).or_default() += 1;
+            }
+        }
+        let mut output_rings = EntryStats::default();
+        for ring in self.output_rings.values() {
+            output_rings.add(ring.size(), now.duration_since(ring.allocated()));
+        }
+        let nodes = self
+            .running_nodes
+            .iter()
+            .map(|(node_id, node)| {
+                let state = if self.reloading_nodes.contains_key(node_id) {
+                    NodeState::Reloading
+                } else if self.pending_nodes.is_pending(node_id) {
+                    NodeState::Pending
+                } else {
+                    NodeState::Running
+                };
+                let diagnostics = NodeDiagnostics {
+                    state,
+                    pid: node.pid,
+                    open_inputs: self.open_inputs.get(node_id).cloned().unwrap_or_default(),
+                    pending_drop_tokens: pending_per_node.get(node_id).copied().unwrap_or(0),
+                    ready_timeout: self.ready_timeouts.get(node_id).copied(),
+                };
+                (node_id.clone(), diagnostics)
+            })
+            .collect();
+        DataflowDiagnostics {
+            instance: self.instance.as_ref().map(|i| i.label()),
+            nodes,
+            pending_drop_tokens,
+            output_rings,
+            event_queue_depth,
+            drop_token_reports: self.drop_token_reports,
+            partial_remote_outputs: self.partial_remote_outputs.stats(now),
+            discarded_remote_outputs: self.partial_remote_outputs.discarded(),
+            best_effort_outputs: self.datagram_reassembly.stats(),
+            stalled_for: self.stall.idle(now),
+            adaptive_inputs: self
+                .inputs
+                .iter()
+                .filter_map(|((node_id, input_id), state)| {
+                    let adaptive = state.adaptive.as_ref()?;
+                    Some((format!("{node_id}/{input_id}"), adaptive.stats()))
+                })
+                .collect(),
+            reconfigured_inputs: self
+                .inputs
+                .iter()
+                .filter_map(|((node_id, input_id), state)| {
+                    let settings = state.reconfigured.clone()?;
+                    Some((format!("{node_id}/{input_id}"), settings))
+                })
+                .collect(),
+        }
+    }
+
+    /// Releases all drop tokens that are still pending on the given node.
+    async fn release_drop_tokens_of(&mut self, node_id: &NodeId, clock: &HLC) -> eyre::Result<()> {
+        let tokens: Vec<_> = self
+            .pending_drop_tokens
+            .iter()
+            .filter(|(_, info)| info.pending_nodes.contains(node_id))
+            .map(|(token, _)| *token)
+            .collect();
+        for token in tokens {
+            self.release_drop_token(token, node_id, clock).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the messages for reloading nodes into their bounded buffers.
+    async fn buffer_reload_events(&mut self, clock: &HLC) -> eyre::Result<()> {
+        let mut dropped = Vec::new();
+        for (node_id, reloading) in &mut self.reloading_nodes {
+            dropped.extend(
+                reloading
+                    .buffer_events()
+                    .into_iter()
+                    .map(|token| (token, node_id.clone())),
+            );
+        }
+        for (token, node_id) in dropped {
+            self.release_drop_token(token, &node_id, clock).await?;
+        }
+        Ok(())
+    }
+
+    /// Delivers the messages that were buffered while the given node was
+    /// reloaded to its new instance.
+    async fn finish_node_reload(
+        &mut self,
+        node_id: &NodeId,
+        event_sender: &NodeEventSender,
+        clock: &HLC,
+    ) -> eyre::Result<()> {
+        let Some(mut reloading) = self.reloading_nodes.remove(node_id) else {
+            return Ok(());
+        };
+        for token in reloading.buffer_events() {
+            self.release_drop_token(token, node_id, clock).await?;
+        }
+        for event in reloading.finish() {
+            let _ = event_sender.send_timestamped(event);
+        }
+        // pending messages of `latest` inputs are kept in their slots
+        for (input_id, _) in self
+            .inputs
+            .of_node(node_id)
+            .filter(|(_, state)| state.latest.as_ref().is_some_and(|slot| slot.has_pending()))
+        {
+            let _ = event_sender.send(
+                NodeEvent::LatestAvailable {
+                    id: input_id.clone(),
+                },
+                clock,
+            );
+        }
+        Ok(())
+    }
+
+    /// Size of the shared memory regions that receivers still have access to.
+    fn shared_memory_in_flight(&self) -> u64 {
+        self.shared_memory.in_flight()
+    }
+
     async fn check_drop_token(&mut self, token: DropToken, clock: &HLC) -> eyre::Result<()> {
         match self.pending_drop_tokens.entry(token) {
             std::collections::hash_map::Entry::Occupied(entry) => {
                 if entry.get().pending_nodes.is_empty() {
                     let (drop_token, info) = entry.remove_entry();
+                    self.drop_tombstones.insert(drop_token, Instant::now());
+                    self.shared_memory.sub(info.len);
+                    // reassembled remote outputs are owned by the daemon
+                    if self.reassembled_outputs.remove(&drop_token).is_some() {
+                        return Ok(());
+                    }
                     let result = match self.drop_channels.get_mut(&info.owner) {
                         Some(channel) => send_with_timestamp(
                             channel,
@@ -1672,16 +6183,43 @@ impl RunningDataflow {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct OutputId(NodeId, DataId);
 type InputId = (NodeId, DataId);
 
+impl OutputId {
+    /// The output that the given input mapping is connected to.
+    ///
+    /// Timer, external, and drop event mappings are represented as outputs of a `dora`
+    /// pseudo node.
+    fn from_mapping(mapping: &InputMapping) -> Self {
+        match mapping {
+            InputMapping::User(mapping) => Self(mapping.source.clone(), mapping.output.clone()),
+            InputMapping::Timer { interval } => Self(
+                mapping.source().clone(),
+                DataId::from(format!("timer/{}", format_duration(*interval))),
+            ),
+            InputMapping::External { name } => {
+                Self(mapping.source().clone(), external::external_output_id(name))
+            }
+            InputMapping::DropEvents => Self(
+                mapping.source().clone(),
+                DataId::from(DROP_EVENTS_OUTPUT.to_owned()),
+            ),
+        }
+    }
+}
+
 struct DropTokenInformation {
     /// The node that created the associated drop token.
     owner: NodeId,
+    /// Size of the shared memory region of the associated message.
+    len: usize,
     /// Contains the set of pending nodes that still have access to the input
     /// associated with a drop token.
     pending_nodes: BTreeSet<NodeId>,
+    /// Time at which the message was sent, for diagnostics.
+    sent: Instant,
 }
 
 #[derive(Debug)]
@@ -1695,10 +6233,52 @@ pub enum Event {
     Daemon(InterDaemonEvent),
     Dora(DoraEvent),
     DynamicNode(DynamicNodeEventWrapper),
+    External {
+        dataflow_id: DataflowId,
+        event: ExternalEvent,
+    },
     HeartbeatInterval,
     CtrlC,
 }
 
+impl Event {
+    /// Whether handling the event may change the number of receivers of an
+    /// output, e.g. because a node subscribed or stopped.
+    ///
+    /// Messages are too frequent to check all outputs after each of them.
+    /// Receivers that turn out to be closed while a message is delivered are
+    /// checked right away instead.
+    fn may_change_subscribers(&self) -> bool {
+        match self {
+            Event::Node { event, .. } => matches!(
+                event,
+                DaemonNodeEvent::Subscribe { .. }
+                    | DaemonNodeEvent::EventStreamDropped { .. }
+                    | DaemonNodeEvent::CloseOutputs { .. }
+                    | DaemonNodeEvent::OutputsDone { .. }
+                    | DaemonNodeEvent::Observe { .. }
+            ),
+            Event::Daemon(event) => !matches!(
+                event,
+                InterDaemonEvent::Output { .. }
+                    | InterDaemonEvent::OutputChunk(_)
+                    | InterDaemonEvent::OutputDatagram(_)
+            ),
+            Event::Dora(event) => !matches!(
+                event,
+                DoraEvent::Timer { .. }
+                    | DoraEvent::Logs { .. }
+                    | DoraEvent::NodeLog { .. }
+                    | DoraEvent::InputTimeoutCheck { .. }
+                    | DoraEvent::InputBatchCheck { .. }
+            ),
+            Event::External { event, .. } => matches!(event, ExternalEvent::Subscribe { .. }),
+            Event::Coordinator(_) | Event::DynamicNode(_) | Event::HeartbeatInterval => true,
+            Event::CtrlC => false,
+        }
+    }
+}
+
 impl From<DoraEvent> for Event {
     fn from(event: DoraEvent) -> Self {
         Event::Dora(event)
@@ -1710,8 +6290,12 @@ pub enum DaemonNodeEvent {
     OutputsDone {
         reply_sender: oneshot::Sender<DaemonReply>,
     },
+    Ready {
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
     Subscribe {
         event_sender: UnboundedSender<Timestamped<NodeEvent>>,
+        interest: EventInterest,
         reply_sender: oneshot::Sender<DaemonReply>,
     },
     SubscribeDrop {
@@ -1726,13 +6310,70 @@ pub enum DaemonNodeEvent {
         output_id: DataId,
         metadata: metadata::Metadata,
         data: Option<DataMessage>,
+        reply_sender: oneshot::Sender<DaemonReply>,
     },
     ReportDrop {
         tokens: Vec<DropToken>,
     },
+    /// Inputs of the node were dropped because its event queue was full.
+    /// Inputs that were dropped because the event queue of the node was full.
+    InputsDropped {
+        counts: BTreeMap<DataId, u64>,
+        /// Dropped messages of inputs with multiple sources, by the source
+        /// that they are tagged with.
+        sources: BTreeMap<DataId, BTreeMap<String, u64>>,
+    },
     EventStreamDropped {
         reply_sender: oneshot::Sender<DaemonReply>,
     },
+    QueryTopology {
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    QuerySubscribers {
+        output_id: DataId,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    RequestDataflowStop {
+        grace_duration: Option<Duration>,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    /// The node reported a fatal error, e.g. a panic, before exiting.
+    NodeError {
+        message: String,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    TakeLatest {
+        id: DataId,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    PrepareOutputRing {
+        output_id: DataId,
+        slot_len: usize,
+        slots: usize,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    SendOutSlot {
+        ring_id: OutputRingId,
+        slot_index: usize,
+        valid_len: usize,
+        metadata: metadata::Metadata,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    SendCached {
+        output_id: DataId,
+        metadata: metadata::Metadata,
+        hash: String,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    /// Registers a read-only observer for an output of the node.
+    ///
+    /// Sent by the local listener on behalf of an observer, not by the node
+    /// itself.
+    Observe {
+        output_id: DataId,
+        sender: mpsc::Sender<ObservedMessage>,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
 }
 
 #[derive(Debug)]
@@ -1753,6 +6394,82 @@ pub enum DoraEvent {
         node_id: NodeId,
         exit_status: NodeExitStatus,
     },
+    /// Parsed log entry of a node that is forwarded to the coordinator.
+    NodeLog { message: LogMessage },
+    /// The `ready_timeout` of a spawned node elapsed.
+    ReadyTimeout {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+    /// The `start_layer_timeout` of the given start layer (counted from 1)
+    /// elapsed.
+    StartLayerTimeout {
+        dataflow_id: DataflowId,
+        layer: usize,
+    },
+    /// Checks whether the `timeout` of an input of the dataflow elapsed.
+    InputTimeoutCheck { dataflow_id: DataflowId },
+    /// Sends the pending input batches of the dataflow whose `max_delay`
+    /// elapsed.
+    InputBatchCheck { dataflow_id: DataflowId },
+    /// The remote receivers of the local outputs of the dataflow are
+    /// connected (zenoh transport only).
+    TransportReady { dataflow_id: DataflowId },
+}
+
+impl DoraEvent {
+    fn dataflow_id(&self) -> DataflowId {
+        match self {
+            DoraEvent::Timer { dataflow_id, .. }
+            | DoraEvent::Logs { dataflow_id, .. }
+            | DoraEvent::SpawnedNodeResult { dataflow_id, .. }
+            | DoraEvent::ReadyTimeout { dataflow_id, .. }
+            | DoraEvent::StartLayerTimeout { dataflow_id, .. }
+            | DoraEvent::InputTimeoutCheck { dataflow_id }
+            | DoraEvent::InputBatchCheck { dataflow_id }
+            | DoraEvent::TransportReady { dataflow_id } => *dataflow_id,
+            DoraEvent::NodeLog { message } => message.dataflow_id,
+        }
+    }
+
+    /// The node that the event is about, if any.
+    fn node_id(&self) -> Option<&NodeId> {
+        match self {
+            DoraEvent::Logs { output_id, .. } => Some(&output_id.0),
+            DoraEvent::SpawnedNodeResult { node_id, .. }
+            | DoraEvent::ReadyTimeout { node_id, .. } => Some(node_id),
+            DoraEvent::NodeLog { message } => message.node_id.as_ref(),
+            DoraEvent::Timer { .. }
+            | DoraEvent::StartLayerTimeout { .. }
+            | DoraEvent::InputTimeoutCheck { .. }
+            | DoraEvent::InputBatchCheck { .. }
+            | DoraEvent::TransportReady { .. } => None,
+        }
+    }
+}
+
+/// Local nodes of a dataflow that were not spawned yet, grouped into the
+/// layers of its `start_order`.
+#[derive(Default)]
+struct StartLayers {
+    /// Layers that are not spawned yet, with the working dir of each node.
+    pending: VecDeque<Vec<(ResolvedNode, PathBuf)>>,
+    /// Nodes of the most recently spawned layer that neither subscribed nor
+    /// exited yet, if the next layer waits for them.
+    waiting: BTreeSet<NodeId>,
+    /// Number of layers that were spawned already.
+    started: usize,
+    total: usize,
+}
+
+/// State of a new dataflow before its nodes are spawned, see
+/// [`Daemon::prepare_dataflow`].
+struct PreparedDataflow {
+    dataflow: RunningDataflow,
+    /// The resolved absolute working directory of each local node.
+    node_working_dirs: BTreeMap<NodeId, PathBuf>,
+    local_nodes: BTreeSet<NodeId>,
+    external_inputs: BTreeSet<String>,
 }
 
 #[must_use]
@@ -1761,6 +6478,47 @@ enum RunStatus {
     Exit,
 }
 
+/// Event channel of a subscribed node.
+///
+/// Events of kinds that the node is not interested in are not sent.
+struct NodeEventSender {
+    sender: UnboundedSender<Timestamped<NodeEvent>>,
+    interest: EventInterest,
+}
+
+impl NodeEventSender {
+    fn new(sender: UnboundedSender<Timestamped<NodeEvent>>, interest: EventInterest) -> Self {
+        Self { sender, interest }
+    }
+
+    /// Checked before input messages are queued, so that no drop tokens are
+    /// tracked for messages that the node doesn't receive.
+    fn wants_inputs(&self) -> bool {
+        self.interest.contains(EventInterest::INPUTS)
+    }
+
+    /// Fails if the event channel of the node is closed.
+    fn send(&self, event: NodeEvent, clock: &HLC) -> Result<(), ()> {
+        self.send_timestamped(Timestamped {
+            inner: event,
+            timestamp: clock.new_timestamp(),
+        })
+    }
+
+    fn send_timestamped(&self, event: Timestamped<NodeEvent>) -> Result<(), ()> {
+        if !self.interest.wants(&event.inner) {
+            return Ok(());
+        }
+        self.sender.send(event).map_err(|_| ())
+    }
+}
+
+impl From<UnboundedSender<Timestamped<NodeEvent>>> for NodeEventSender {
+    fn from(sender: UnboundedSender<Timestamped<NodeEvent>>) -> Self {
+        Self::new(sender, EventInterest::ALL)
+    }
+}
+
 fn send_with_timestamp<T>(
     sender: &UnboundedSender<Timestamped<T>>,
     event: T,
@@ -1774,7 +6532,7 @@ fn send_with_timestamp<T>(
 
 fn set_up_ctrlc_handler(
     clock: Arc<HLC>,
-) -> Result<impl Stream<Item = Timestamped<Event>>, eyre::ErrReport> {
+) -> Result<impl Stream<Item = Timestamped<Event>> + Unpin, eyre::ErrReport> {
     let (ctrlc_tx, ctrlc_rx) = mpsc::channel(1);
 
     let mut ctrlc_sent = false;
@@ -1821,3 +6579,6 @@ impl CascadingErrorCauses {
         self.caused_by.entry(affected_node).or_insert(causing_node);
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,381 @@
+//! Runtime state of the inputs of the local nodes of a dataflow.
+//!
+//! Some input options need state while the dataflow runs, e.g. the pending
+//! message of a `latest` input or the collected messages of a `batch`. All
+//! state of an input is kept in a single [`InputState`], so that it is set up
+//! when the node is registered and removed together with the node.
+//!
+//! This module also registers the inputs of the nodes of a
+//! [`RunningDataflow`] and closes them once their sources are done.
+
+use crate::{
+    adaptive_rate::AdaptiveRate, input_batch::InputBatch, input_filter::InputFilter,
+    input_timeouts::InputTimeout, latest_input::LatestSlot, node_inputs, InputId, NodeEventSender,
+    OutputId, RunningDataflow,
+};
+use dora_core::{
+    config::{DataId, Input, InputMapping, NodeId},
+    descriptor::ResolvedNode,
+    uhlc::{self, HLC},
+};
+use dora_message::{
+    daemon_to_daemon::InterDaemonTransport, daemon_to_node::NodeEvent, reconfigure::InputSettings,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Default)]
+pub struct LocalInputs {
+    inputs: BTreeMap<InputId, InputState>,
+}
+
+#[derive(Debug, Default)]
+pub struct InputState {
+    /// Open sources of inputs that are mapped to more than one output (fan-in).
+    pub open_sources: Option<BTreeSet<OutputId>>,
+    /// Set for inputs with a `throttle` or `decimate` filter.
+    pub filter: Option<InputFilter>,
+    /// Set for inputs with `adaptive` downsampling.
+    pub adaptive: Option<AdaptiveRate>,
+    /// Pending message of inputs with `latest: true`.
+    pub latest: Option<LatestSlot>,
+    /// Pending messages of inputs with a `batch` config.
+    pub batch: Option<InputBatch>,
+    /// Set for open inputs with a `timeout`.
+    pub timeout: Option<InputTimeout>,
+    /// Settings that were changed through `Reconfigure` events, for the
+    /// diagnostics.
+    pub reconfigured: Option<InputSettings>,
+    /// Timestamp of the last message that was delivered to the input.
+    pub last_delivered: Option<uhlc::Timestamp>,
+}
+
+impl InputState {
+    fn new(input: &Input, now: Instant) -> Self {
+        Self {
+            open_sources: input
+                .is_fan_in()
+                .then(|| input.mappings().map(OutputId::from_mapping).collect()),
+            filter: InputFilter::new(input),
+            adaptive: input
+                .adaptive
+                .as_ref()
+                .map(|adaptive| AdaptiveRate::new(adaptive, now)),
+            latest: input.latest.then(LatestSlot::default),
+            batch: input.batch.as_ref().map(InputBatch::new),
+            timeout: input
+                .timeout
+                .map(|timeout| InputTimeout::new(timeout, None)),
+            reconfigured: None,
+            last_delivered: None,
+        }
+    }
+}
+
+impl LocalInputs {
+    /// Sets up the state of the given input of a local node, replacing any
+    /// previous state.
+    ///
+    /// Timeouts only start once [`start_timeouts`][Self::start_timeouts] is
+    /// called for the node.
+    pub fn register(&mut self, id: InputId, input: &Input, now: Instant) {
+        self.inputs.insert(id, InputState::new(input, now));
+    }
+
+    pub fn get(&self, id: &InputId) -> Option<&InputState> {
+        self.inputs.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &InputId) -> Option<&mut InputState> {
+        self.inputs.get_mut(id)
+    }
+
+    /// State of the given input, which is created if the input is not
+    /// registered.
+    pub fn get_or_insert(&mut self, id: InputId) -> &mut InputState {
+        self.inputs.entry(id).or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&InputId, &InputState)> {
+        self.inputs.iter()
+    }
+
+    /// The inputs of the given node.
+    pub fn of_node<'a>(
+        &'a self,
+        node_id: &'a NodeId,
+    ) -> impl Iterator<Item = (&'a DataId, &'a InputState)> {
+        self.inputs
+            .iter()
+            .filter(move |((receiver, _), _)| receiver == node_id)
+            .map(|((_, input_id), state)| (input_id, state))
+    }
+
+    pub fn of_node_mut<'a>(
+        &'a mut self,
+        node_id: &'a NodeId,
+    ) -> impl Iterator<Item = (&'a DataId, &'a mut InputState)> {
+        self.inputs
+            .iter_mut()
+            .filter(move |((receiver, _), _)| receiver == node_id)
+            .map(|((_, input_id), state)| (input_id, state))
+    }
+
+    /// Removes the state of all inputs of the given node.
+    pub fn remove_node(&mut self, node_id: &NodeId) {
+        self.inputs.retain(|(receiver, _), _| receiver != node_id);
+    }
+
+    /// Starts the timeouts of all inputs of the given node, e.g. because it
+    /// subscribed to its events.
+    pub fn start_timeouts(&mut self, node_id: &NodeId, now: Instant) {
+        for (_, state) in self.of_node_mut(node_id) {
+            if let Some(timeout) = &mut state.timeout {
+                timeout.start(now);
+            }
+        }
+    }
+
+    /// Interval at which [`expired_timeouts`][Self::expired_timeouts] needs
+    /// to be called, or `None` if no input has a timeout.
+    pub fn timeout_check_interval(&self) -> Option<Duration> {
+        self.inputs
+            .values()
+            .filter_map(|state| state.timeout.as_ref())
+            .map(|timeout| timeout.check_interval())
+            .min()
+    }
+
+    /// Returns the inputs whose timeout elapsed since the last call, together
+    /// with the time since their last message.
+    pub fn expired_timeouts(&mut self, now: Instant) -> Vec<(InputId, Duration)> {
+        self.inputs
+            .iter_mut()
+            .filter_map(|(id, state)| {
+                let elapsed = state.timeout.as_mut()?.expired(now)?;
+                Some((id.clone(), elapsed))
+            })
+            .collect()
+    }
+
+    /// Interval at which the batches need to be checked for an elapsed
+    /// `max_delay`, or `None` if no input has a batch.
+    pub fn batch_check_interval(&self) -> Option<Duration> {
+        self.inputs
+            .values()
+            .filter_map(|state| state.batch.as_ref())
+            .map(|batch| batch.check_interval())
+            .min()
+    }
+
+    /// Sends the pending messages of the selected input batches to their
+    /// receivers.
+    ///
+    /// Receivers that closed their event channel are detected by the next
+    /// delivered message, like for the `InputTimeout` events.
+    pub fn send_batches(
+        &mut self,
+        channels: &HashMap<NodeId, NodeEventSender>,
+        mut select: impl FnMut(&InputId, &InputBatch) -> bool,
+    ) {
+        for (input, state) in &mut self.inputs {
+            let Some(batch) = &mut state.batch else {
+                continue;
+            };
+            if !select(input, batch) {
+                continue;
+            }
+            let (receiver_id, input_id) = input;
+            let Some(event) = batch.take(input_id.clone()) else {
+                continue;
+            };
+            if let Some(channel) = channels.get(receiver_id) {
+                let _ = channel.send_timestamped(event);
+            }
+        }
+    }
+
+    /// Sends the pending batches of the other inputs of `receiver` that are
+    /// fed by `sender`, before a message of `sender` is delivered to
+    /// `receiver`.
+    ///
+    /// This keeps the messages of a sender in publish order, across all of
+    /// its outputs.
+    pub fn send_batches_of_sender(
+        &mut self,
+        channels: &HashMap<NodeId, NodeEventSender>,
+        mappings: &HashMap<OutputId, BTreeSet<InputId>>,
+        sender: &NodeId,
+        receiver: &InputId,
+    ) {
+        let (receiver_id, _) = receiver;
+        self.send_batches(channels, |input, batch| {
+            &input.0 == receiver_id
+                && input != receiver
+                && !batch.is_empty()
+                && mappings
+                    .iter()
+                    .any(|(OutputId(source, _), inputs)| source == sender && inputs.contains(input))
+        });
+    }
+}
+
+impl RunningDataflow {
+    /// Registers the inputs of the given node in the mappings of this dataflow.
+    ///
+    /// Inputs of remote nodes are only tracked so that they can be closed later.
+    pub(crate) fn register_inputs(&mut self, node: &ResolvedNode, local: bool) {
+        let inputs = node_inputs(node);
+        for (input_id, input) in inputs {
+            if local {
+                self.open_inputs
+                    .entry(node.id.clone())
+                    .or_default()
+                    .insert(input_id.clone());
+                self.inputs
+                    .register((node.id.clone(), input_id.clone()), &input, Instant::now());
+                for mapping in input.mappings() {
+                    match mapping {
+                        InputMapping::User(_)
+                        | InputMapping::External { .. }
+                        | InputMapping::DropEvents => {
+                            self.mappings
+                                .entry(OutputId::from_mapping(mapping))
+                                .or_default()
+                                .insert((node.id.clone(), input_id.clone()));
+                        }
+                        InputMapping::Timer { interval } => {
+                            self.timers
+                                .entry(*interval)
+                                .or_default()
+                                .insert((node.id.clone(), input_id.clone()));
+                        }
+                    }
+                }
+            } else {
+                // drop events are published by the daemon on which the drop
+                // happened, which forwards them over TCP only
+                let forward_drop_events = self.inter_daemon_transport == InterDaemonTransport::Tcp;
+                for mapping in input.mappings().filter(|m| match m {
+                    InputMapping::User(_) => true,
+                    InputMapping::DropEvents => forward_drop_events,
+                    _ => false,
+                }) {
+                    self.open_external_mappings
+                        .entry(OutputId::from_mapping(mapping))
+                        .or_default()
+                        .entry(node.deploy.machine.clone())
+                        .or_default()
+                        .insert((node.id.clone(), input_id.clone()));
+                }
+            }
+        }
+    }
+
+    /// Removes the local input state of a node whose instance on this
+    /// machine exited, but which keeps running on another machine.
+    pub(crate) fn unregister_local_inputs(&mut self, node_id: &NodeId) {
+        let other_node = |(receiver, _): &InputId| receiver != node_id;
+        for receivers in self.mappings.values_mut() {
+            receivers.retain(other_node);
+        }
+        self.mappings.retain(|_, receivers| !receivers.is_empty());
+        for receivers in self.timers.values_mut() {
+            receivers.retain(other_node);
+        }
+        self.timers.retain(|_, receivers| !receivers.is_empty());
+        self.open_inputs.remove(node_id);
+        self.closed_inputs.remove(node_id);
+        self.inputs.remove_node(node_id);
+    }
+}
+
+/// Closes the given input of a local node for messages of `source`.
+///
+/// Inputs with several sources stay open until all of their sources are
+/// closed. The node is notified, and stopped if it was its last open input.
+pub(crate) fn close_input(
+    dataflow: &mut RunningDataflow,
+    receiver_id: &NodeId,
+    input_id: &DataId,
+    source: &OutputId,
+    clock: &HLC,
+) {
+    let key = (receiver_id.clone(), input_id.clone());
+    if let Some(open_sources) = dataflow
+        .inputs
+        .get_mut(&key)
+        .and_then(|state| state.open_sources.as_mut())
+    {
+        // inputs with multiple sources stay open until all sources are closed
+        open_sources.remove(source);
+        if !open_sources.is_empty() {
+            return;
+        }
+    }
+    if let Some(open_inputs) = dataflow.open_inputs.get_mut(receiver_id) {
+        if !open_inputs.remove(input_id) {
+            return;
+        }
+    }
+    if let Some(state) = dataflow.inputs.get_mut(&key) {
+        state.timeout = None;
+    }
+    // the pending messages still arrive before the input is closed
+    dataflow.send_input_batches(|input, _| input == &key);
+    dataflow
+        .closed_inputs
+        .entry(receiver_id.clone())
+        .or_default()
+        .insert(input_id.clone());
+    if let Some(channel) = dataflow.subscribe_channels.get(receiver_id) {
+        let _ = channel.send(dataflow.input_closed_event(receiver_id, input_id), clock);
+    }
+    // the stop needs to arrive before `AllInputsClosed`, which closes the event stream
+    dataflow.stop_if_inputs_closed(receiver_id, clock);
+    if let Some(channel) = dataflow.subscribe_channels.get(receiver_id) {
+        if dataflow.open_inputs(receiver_id).is_empty() {
+            let _ = channel.send(NodeEvent::AllInputsClosed, clock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_is_removed_together_with_the_node() {
+        let robot = NodeId::from("robot".to_owned());
+        let planner = NodeId::from("planner".to_owned());
+        let cmd: InputId = (robot.clone(), DataId::from("cmd".to_owned()));
+        let plan: InputId = (planner.clone(), DataId::from("plan".to_owned()));
+        let mut input = Input::new("joystick/cmd".parse::<InputMapping>().unwrap());
+        input.latest = true;
+        input.timeout = Some(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let mut inputs = LocalInputs::default();
+        inputs.register(cmd.clone(), &input, start);
+        inputs.register(plan.clone(), &input, start);
+        assert!(inputs.get(&cmd).unwrap().latest.is_some());
+        assert!(inputs.get(&cmd).unwrap().batch.is_none());
+        assert_eq!(
+            inputs.timeout_check_interval(),
+            Some(Duration::from_millis(10))
+        );
+
+        // timeouts only start once the node subscribed
+        inputs.start_timeouts(&robot, start);
+        let expired = inputs.expired_timeouts(start + Duration::from_millis(150));
+        assert_eq!(expired, [(cmd.clone(), Duration::from_millis(150))]);
+
+        inputs.remove_node(&robot);
+        assert!(inputs.get(&cmd).is_none());
+        assert_eq!(inputs.of_node(&planner).count(), 1);
+        inputs.remove_node(&planner);
+        assert_eq!(inputs.timeout_check_interval(), None);
+    }
+}
@@ -1,14 +1,24 @@
-use crate::socket_stream_utils::{socket_stream_receive, socket_stream_send};
+use crate::{
+    join_tokens::{Grant, JoinTokens},
+    node_communication::limits::NodeConnections,
+    observer::OBSERVER_QUEUE_SIZE,
+    socket_stream_utils::{socket_stream_receive, socket_stream_send},
+    DaemonNodeEvent, Event,
+};
+use dora_core::config::{DataId, NodeId};
 use dora_message::{
     daemon_to_node::DaemonReply,
     node_to_daemon::{DaemonRequest, DynamicNodeEvent, Timestamped},
+    DataflowId,
 };
 use eyre::Context;
-use std::{io::ErrorKind, net::SocketAddr};
+use std::{io::ErrorKind, net::SocketAddr, time::Instant};
 use tokio::{
+    io::AsyncReadExt,
     net::{TcpListener, TcpStream},
-    sync::oneshot,
+    sync::{mpsc, oneshot},
 };
+use tracing::Instrument;
 
 #[derive(Debug)]
 pub struct DynamicNodeEventWrapper {
@@ -19,7 +29,9 @@ pub struct DynamicNodeEventWrapper {
 pub async fn spawn_listener_loop(
     bind: SocketAddr,
     machine_id: String,
-    events_tx: flume::Sender<Timestamped<DynamicNodeEventWrapper>>,
+    events_tx: flume::Sender<Timestamped<Event>>,
+    connections: NodeConnections,
+    join_tokens: JoinTokens,
 ) -> eyre::Result<u16> {
     let socket = match TcpListener::bind(bind).await {
         Ok(socket) => socket,
@@ -32,17 +44,23 @@ pub async fn spawn_listener_loop(
         .wrap_err("failed to get local addr of socket")?
         .port();
 
-    tokio::spawn(async move {
-        listener_loop(socket, events_tx).await;
-        tracing::debug!("Local listener loop finished for machine `{machine_id}`");
-    });
+    let span = tracing::info_span!("local_listener", %machine_id);
+    tokio::spawn(
+        async move {
+            listener_loop(socket, events_tx, connections, join_tokens).await;
+            tracing::debug!("Local listener loop finished for machine `{machine_id}`");
+        }
+        .instrument(span),
+    );
 
     Ok(listen_port)
 }
 
 async fn listener_loop(
     listener: TcpListener,
-    events_tx: flume::Sender<Timestamped<DynamicNodeEventWrapper>>,
+    events_tx: flume::Sender<Timestamped<Event>>,
+    connections: NodeConnections,
+    join_tokens: JoinTokens,
 ) {
     loop {
         match listener
@@ -53,34 +71,119 @@ async fn listener_loop(
             Err(err) => {
                 tracing::info!("{err}");
             }
-            Ok((connection, _)) => {
-                tokio::spawn(handle_connection_loop(connection, events_tx.clone()));
+            Ok((connection, peer)) => {
+                // closes the connection if there are too many
+                let Some(permit) = connections.admit() else {
+                    continue;
+                };
+                let events_tx = events_tx.clone();
+                let connections = connections.clone();
+                let access = Access {
+                    local: peer.ip().is_loopback(),
+                    grant: None,
+                    join_tokens: join_tokens.clone(),
+                };
+                tokio::spawn(
+                    async move {
+                        handle_connection_loop(connection, events_tx, connections, access).await;
+                        drop(permit);
+                    }
+                    .in_current_span(),
+                );
             }
         }
     }
 }
 
+/// Which requests a connection is allowed to send, see [`crate::join_tokens`].
+struct Access {
+    /// Connections from the machine of the daemon don't need a join token.
+    local: bool,
+    /// Set by a `Join` request.
+    grant: Option<Grant>,
+    join_tokens: JoinTokens,
+}
+
+impl Access {
+    fn check(&self, dataflow_id: Option<DataflowId>, node_id: &NodeId) -> Result<(), String> {
+        match &self.grant {
+            Some(grant) if grant.allows(dataflow_id, node_id) => Ok(()),
+            Some(grant) => Err(format!(
+                "join token is only valid for node `{}/{}`",
+                grant.dataflow_id, grant.node_id
+            )),
+            None if self.local => Ok(()),
+            None => Err("connections from other machines need to send a join token first".into()),
+        }
+    }
+}
+
 async fn handle_connection_loop(
     mut connection: TcpStream,
-    events_tx: flume::Sender<Timestamped<DynamicNodeEventWrapper>>,
+    events_tx: flume::Sender<Timestamped<Event>>,
+    connections: NodeConnections,
+    mut access: Access,
 ) {
     if let Err(err) = connection.set_nodelay(true) {
         tracing::warn!("failed to set nodelay for connection: {err}");
     }
 
+    let mut rate_limiter = connections.rate_limiter();
+    let mut first = true;
     loop {
-        match receive_message(&mut connection).await {
+        let message = if std::mem::take(&mut first) {
+            let timeout = connections.handshake_timeout();
+            match tokio::time::timeout(timeout, receive_message(&mut connection)).await {
+                Ok(message) => message,
+                Err(_) => {
+                    connections.record_handshake_timeout();
+                    break;
+                }
+            }
+        } else {
+            receive_message(&mut connection).await
+        };
+        if matches!(message, Ok(Some(_))) && !rate_limiter.allow(Instant::now()) {
+            connections.record_rate_limited();
+            break;
+        }
+        match message {
+            Ok(Some(Timestamped {
+                inner: DaemonRequest::Join { token },
+                ..
+            })) => {
+                let result = access.join_tokens.check(&token, Instant::now());
+                let joined = result.is_ok();
+                access.grant = result.as_ref().ok().cloned();
+                let reply = DaemonReply::Result(result.map(|_| ()));
+                if let Err(err) = send_reply(&mut connection, &reply).await {
+                    tracing::warn!("failed to send join reply: {err:?}");
+                    break;
+                }
+                if !joined {
+                    break;
+                }
+            }
             Ok(Some(Timestamped {
                 inner: DaemonRequest::NodeConfig { node_id },
                 timestamp,
             })) => {
+                if let Err(err) = access.check(None, &node_id) {
+                    let reply = DaemonReply::NodeConfig { result: Err(err) };
+                    if let Ok(serialized) = serde_json::to_vec(&reply) {
+                        let _ = socket_stream_send(&mut connection, &serialized).await;
+                    }
+                    break;
+                }
                 let (reply_tx, reply_rx) = oneshot::channel();
                 if events_tx
                     .send_async(Timestamped {
-                        inner: DynamicNodeEventWrapper {
-                            event: DynamicNodeEvent::NodeConfig { node_id },
+                        inner: Event::DynamicNode(DynamicNodeEventWrapper {
+                            event: DynamicNodeEvent::NodeConfig {
+                                node_id: node_id.clone(),
+                            },
                             reply_tx,
-                        },
+                        }),
                         timestamp,
                     })
                     .await
@@ -88,10 +191,19 @@ async fn handle_connection_loop(
                 {
                     break;
                 }
-                let Ok(reply) = reply_rx.await else {
+                let Ok(mut reply) = reply_rx.await else {
                     tracing::warn!("daemon sent no reply");
                     continue;
                 };
+                // the node ID might be used by more than one dataflow
+                if let Some(DaemonReply::NodeConfig { result }) = &mut reply {
+                    let dataflow_id = result.as_ref().map(|config| config.dataflow_id);
+                    if let Ok(dataflow_id) = dataflow_id {
+                        if let Err(err) = access.check(Some(dataflow_id), &node_id) {
+                            *result = Err(err);
+                        }
+                    }
+                }
                 if let Some(reply) = reply {
                     let serialized = match serde_json::to_vec(&reply)
                         .wrap_err("failed to serialize DaemonReply")
@@ -108,6 +220,34 @@ async fn handle_connection_loop(
                     };
                 }
             }
+            Ok(Some(Timestamped {
+                inner:
+                    DaemonRequest::Observe {
+                        dataflow_id,
+                        node_id,
+                        output_id,
+                    },
+                timestamp,
+            })) => {
+                if let Err(err) = access.check(Some(dataflow_id), &node_id) {
+                    let _ = send_reply(&mut connection, &DaemonReply::Result(Err(err))).await;
+                    break;
+                }
+                // the connection is used for the observed messages from now on
+                if let Err(err) = observe(
+                    connection,
+                    &events_tx,
+                    dataflow_id,
+                    node_id,
+                    output_id,
+                    timestamp,
+                )
+                .await
+                {
+                    tracing::warn!("observer connection failed: {err:?}");
+                }
+                break;
+            }
             Ok(None) => break,
             Err(err) => {
                 tracing::warn!("{err:?}");
@@ -120,6 +260,77 @@ async fn handle_connection_loop(
     }
 }
 
+/// Registers an observer for the given output and forwards the observed
+/// messages until either side closes the connection.
+async fn observe(
+    mut connection: TcpStream,
+    events_tx: &flume::Sender<Timestamped<Event>>,
+    dataflow_id: DataflowId,
+    node_id: NodeId,
+    output_id: DataId,
+    timestamp: dora_core::uhlc::Timestamp,
+) -> eyre::Result<()> {
+    let (sender, mut messages) = mpsc::channel(OBSERVER_QUEUE_SIZE);
+    let (reply_sender, reply) = oneshot::channel();
+    let event = Event::Node {
+        dataflow_id,
+        node_id,
+        event: DaemonNodeEvent::Observe {
+            output_id,
+            sender,
+            reply_sender,
+        },
+    };
+    if events_tx
+        .send_async(Timestamped {
+            inner: event,
+            timestamp,
+        })
+        .await
+        .is_err()
+    {
+        return Ok(());
+    }
+    let reply = reply
+        .await
+        .unwrap_or_else(|_| DaemonReply::Result(Err("daemon sent no reply".into())));
+    let registered = matches!(reply, DaemonReply::Result(Ok(())));
+    send_reply(&mut connection, &reply).await?;
+    if !registered {
+        return Ok(());
+    }
+
+    let (mut reader, mut writer) = connection.split();
+    let mut buf = [0; 64];
+    loop {
+        let message = tokio::select! {
+            message = messages.recv() => match message {
+                Some(message) => message,
+                // the dataflow finished
+                None => break,
+            },
+            // observers don't send anything after the `Observe` request
+            read = reader.read(&mut buf) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(_) => continue,
+            },
+        };
+        send_reply(&mut writer, &DaemonReply::ObservedMessage(message)).await?;
+    }
+    // dropping `messages` removes the observer
+    Ok(())
+}
+
+async fn send_reply(
+    connection: &mut (impl tokio::io::AsyncWrite + Unpin),
+    reply: &DaemonReply,
+) -> eyre::Result<()> {
+    let serialized = bincode::serialize(reply).wrap_err("failed to serialize DaemonReply")?;
+    socket_stream_send(connection, &serialized)
+        .await
+        .wrap_err("failed to send reply to observer")
+}
+
 async fn receive_message(
     connection: &mut TcpStream,
 ) -> eyre::Result<Option<Timestamped<DaemonRequest>>> {
@@ -1,9 +1,206 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use dora_core::config::NodeId;
+use dora_message::{
+    common::{LogLevel, LogMessage},
+    coordinator_to_daemon::DataflowInstance,
+    DataflowId,
+};
+use serde_json::Value;
 use uuid::Uuid;
 
-pub fn log_path(working_dir: &Path, dataflow_id: &Uuid, node_id: &NodeId) -> PathBuf {
-    let dataflow_dir = working_dir.join("out").join(dataflow_id.to_string());
+/// Tracing target of the re-emitted log entries of nodes with
+/// `log_format: json`.
+pub const NODE_LOG_TARGET: &str = "dora_node";
+
+/// Directory for the log and output files of a dataflow in the given `out`
+/// directory, see [`DaemonPaths::dataflow_dir`](crate::paths::DaemonPaths::dataflow_dir).
+///
+/// For dataflow instances, the directory name is prefixed with the
+/// [label](DataflowInstance::label) of the instance.
+pub fn dataflow_dir(
+    out_dir: &Path,
+    dataflow_id: &Uuid,
+    instance: Option<&DataflowInstance>,
+) -> PathBuf {
+    let name = match instance {
+        Some(instance) => format!("{}_{dataflow_id}", instance.label()),
+        None => dataflow_id.to_string(),
+    };
+    out_dir.join(name)
+}
+
+pub fn log_path(dataflow_dir: &Path, node_id: &NodeId) -> PathBuf {
     dataflow_dir.join(format!("log_{node_id}.txt"))
 }
+
+/// A structured log line of a node with `log_format: json`.
+#[derive(Debug, PartialEq)]
+pub struct NodeLogEntry {
+    pub level: LogLevel,
+    pub message: String,
+    /// Target (e.g. module or logger name) given by the node.
+    pub target: Option<String>,
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl NodeLogEntry {
+    /// Parses a JSON object with a `level` and a `message` (or `msg`).
+    ///
+    /// The entries of a nested `fields` object and all other keys, except
+    /// `timestamp`, are collected as fields. Returns `None` if the line is
+    /// not in this format.
+    pub fn parse(line: &str) -> Option<Self> {
+        let Ok(Value::Object(mut object)) = serde_json::from_str(line.trim()) else {
+            return None;
+        };
+        let level = parse_level(object.get("level")?.as_str()?)?;
+        object.remove("level");
+        let mut fields = match object.remove("fields") {
+            Some(Value::Object(fields)) => fields,
+            Some(other) => {
+                object.insert("fields".into(), other);
+                Default::default()
+            }
+            None => Default::default(),
+        };
+        let message = ["message", "msg"]
+            .into_iter()
+            .find_map(|key| object.remove(key))
+            .or_else(|| fields.remove("message"))?;
+        let message = match message {
+            Value::String(message) => message,
+            other => other.to_string(),
+        };
+        let target = match object.remove("target") {
+            Some(Value::String(target)) => Some(target),
+            Some(other) => {
+                object.insert("target".into(), other);
+                None
+            }
+            None => None,
+        };
+        object.remove("timestamp");
+        fields.extend(object);
+
+        Some(Self {
+            level,
+            message,
+            target,
+            fields: fields.into_iter().collect(),
+        })
+    }
+
+    /// Re-emits the entry as tracing event of the daemon.
+    pub fn emit(&self, dataflow_id: DataflowId, node_id: &NodeId) {
+        let node_target = self.target.as_deref();
+        let fields = self.fields_string();
+        let fields = fields.as_deref();
+        let message = &self.message;
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(
+                    target: NODE_LOG_TARGET,
+                    $level,
+                    %dataflow_id,
+                    %node_id,
+                    node_target,
+                    fields,
+                    "{message}"
+                )
+            };
+        }
+        match self.level {
+            LogLevel::Error => emit!(tracing::Level::ERROR),
+            LogLevel::Warn => emit!(tracing::Level::WARN),
+            LogLevel::Info => emit!(tracing::Level::INFO),
+            LogLevel::Debug => emit!(tracing::Level::DEBUG),
+            LogLevel::Trace => emit!(tracing::Level::TRACE),
+        }
+    }
+
+    /// Converts the entry into a log message for the coordinator.
+    pub fn to_log_message(&self, dataflow_id: DataflowId, node_id: &NodeId) -> LogMessage {
+        let message = match self.fields_string() {
+            Some(fields) => format!("{} ({fields})", self.message),
+            None => self.message.clone(),
+        };
+        LogMessage {
+            dataflow_id,
+            node_id: Some(node_id.clone()),
+            level: self.level,
+            target: self.target.clone(),
+            module_path: None,
+            file: None,
+            line: None,
+            message,
+        }
+    }
+
+    fn fields_string(&self) -> Option<String> {
+        if self.fields.is_empty() {
+            return None;
+        }
+        let fields: Vec<_> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        Some(fields.join(" "))
+    }
+}
+
+fn parse_level(level: &str) -> Option<LogLevel> {
+    let level = match level.to_ascii_lowercase().as_str() {
+        "trace" => LogLevel::Trace,
+        "debug" => LogLevel::Debug,
+        "info" => LogLevel::Info,
+        "warn" | "warning" => LogLevel::Warn,
+        "error" | "critical" | "fatal" => LogLevel::Error,
+        _ => return None,
+    };
+    Some(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_log_lines_are_parsed() {
+        let entry = NodeLogEntry::parse(
+            r#"{"level":"WARNING","msg":"queue full","target":"camera","timestamp":"now","size":10}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.level, LogLevel::Warn);
+        assert_eq!(entry.message, "queue full");
+        assert_eq!(entry.target.as_deref(), Some("camera"));
+        assert_eq!(entry.fields, [("size".to_owned(), Value::from(10))].into());
+
+        // format of `tracing-subscriber`
+        let entry = NodeLogEntry::parse(
+            r#"{"level":"ERROR","fields":{"message":"failed","frame":3},"target":"detector"}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.level, LogLevel::Error);
+        assert_eq!(entry.message, "failed");
+        let node_id = NodeId::from("detector".to_owned());
+        let message = entry.to_log_message(DataflowId::nil(), &node_id);
+        assert_eq!(message.message, "failed (frame=3)");
+        assert_eq!(message.node_id, Some(node_id));
+
+        // lines in other formats are not parsed
+        for line in [
+            "plain text",
+            r#"{"message":"no level"}"#,
+            r#"{"level":"verbose","message":"unknown level"}"#,
+            r#"{"level":"info"}"#,
+            r#"["info","not an object"]"#,
+        ] {
+            assert_eq!(NodeLogEntry::parse(line), None, "{line}");
+        }
+    }
+}
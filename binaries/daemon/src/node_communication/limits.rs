@@ -0,0 +1,233 @@
+//! Limits for the socket connections that nodes open to the daemon.
+//!
+//! The listeners accept connections from any local process, so a port
+//! scanner or a misbehaving script could otherwise make the daemon spawn an
+//! unbounded number of connection tasks. Connections that exceed a limit are
+//! closed and counted, they are not logged individually.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use dora_message::daemon_to_coordinator::RejectedConnections;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Every node opens up to four connections, so this allows a few thousand
+/// nodes per daemon.
+pub const DEFAULT_MAX_NODE_CONNECTIONS: usize = 16384;
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Well above the request rate of nodes that send small messages at a high
+/// frequency.
+pub const DEFAULT_MAX_REQUEST_RATE: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Maximum number of open node connections of the daemon.
+    pub max_connections: usize,
+    /// Time in which a new connection needs to send its first request.
+    pub handshake_timeout: Duration,
+    /// Maximum number of requests per second and connection.
+    pub max_request_rate: u32,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_MAX_NODE_CONNECTIONS,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_request_rate: DEFAULT_MAX_REQUEST_RATE,
+        }
+    }
+}
+
+/// Enforces the [`ConnectionLimits`] across all node listeners of a daemon.
+#[derive(Clone)]
+pub struct NodeConnections {
+    limits: ConnectionLimits,
+    permits: Arc<Semaphore>,
+    rejected: Arc<RejectionCounters>,
+}
+
+#[derive(Default)]
+struct RejectionCounters {
+    limit_reached: AtomicU64,
+    handshake_timeout: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+impl NodeConnections {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            permits: Arc::new(Semaphore::new(limits.max_connections)),
+            rejected: Default::default(),
+        }
+    }
+
+    /// Returns a permit that needs to be kept while the accepted connection
+    /// is open, or `None` if the connection should be closed right away.
+    pub fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        let permit = self.permits.clone().try_acquire_owned().ok();
+        if permit.is_none() {
+            self.rejected.limit_reached.fetch_add(1, Ordering::Relaxed);
+        }
+        permit
+    }
+
+    pub fn limits(&self) -> ConnectionLimits {
+        self.limits
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        self.limits.handshake_timeout
+    }
+
+    pub fn record_handshake_timeout(&self) {
+        self.rejected
+            .handshake_timeout
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.limits.max_request_rate, Instant::now())
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.rejected.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the number of rejected connections since the previous call.
+    pub fn take_rejected(&self) -> RejectedConnections {
+        let take = |counter: &AtomicU64| counter.swap(0, Ordering::Relaxed);
+        RejectedConnections {
+            limit_reached: take(&self.rejected.limit_reached),
+            handshake_timeout: take(&self.rejected.handshake_timeout),
+            rate_limited: take(&self.rejected.rate_limited),
+        }
+    }
+}
+
+/// Token bucket that allows bursts of up to one second worth of requests.
+pub struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(max_rate: u32, now: Instant) -> Self {
+        let rate = f64::from(max_rate.max(1));
+        Self {
+            rate,
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    /// Returns `false` if the request exceeds the rate limit.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_rate_limited() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(10, start);
+        // bursts of up to one second worth of requests are allowed
+        assert!((0..10).all(|_| limiter.allow(start)));
+        assert!(!limiter.allow(start));
+        // the budget recovers over time
+        assert!(limiter.allow(start + Duration::from_millis(100)));
+        assert!(!limiter.allow(start + Duration::from_millis(110)));
+        let later = start + Duration::from_secs(60);
+        assert!((0..10).all(|_| limiter.allow(later)));
+        assert!(!limiter.allow(later));
+    }
+
+    #[test]
+    fn connections_above_the_limit_are_rejected() {
+        let connections = NodeConnections::new(ConnectionLimits {
+            max_connections: 2,
+            ..Default::default()
+        });
+        let first = connections.admit().unwrap();
+        let _second = connections.admit().unwrap();
+        assert!(connections.admit().is_none());
+        drop(first);
+        assert!(connections.admit().is_some());
+
+        connections.record_handshake_timeout();
+        let rejected = connections.take_rejected();
+        assert_eq!(rejected.limit_reached, 1);
+        assert_eq!(rejected.handshake_timeout, 1);
+        assert!(connections.take_rejected().is_empty());
+    }
+
+    #[tokio::test]
+    async fn listener_closes_excess_and_idle_connections() {
+        use tokio::{io::AsyncReadExt, net::TcpStream};
+
+        let connections = NodeConnections::new(ConnectionLimits {
+            max_connections: 1,
+            handshake_timeout: Duration::from_millis(200),
+            ..Default::default()
+        });
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (daemon_tx, _daemon_rx) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(super::super::tcp::listener_loop(
+            listener,
+            daemon_tx,
+            Default::default(),
+            Arc::new(dora_core::uhlc::HLC::default()),
+            connections.clone(),
+        ));
+
+        let read_until_closed = |mut stream: TcpStream| async move {
+            let mut buf = [0; 1];
+            tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .expect("connection was not closed")
+        };
+        let idle = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // the second connection exceeds the limit
+        let excess = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_until_closed(excess).await.unwrap(), 0);
+        // the first one never sends a request
+        assert_eq!(read_until_closed(idle).await.unwrap(), 0);
+
+        let rejected = connections.take_rejected();
+        assert_eq!(rejected.limit_reached, 1);
+        assert_eq!(rejected.handshake_timeout, 1);
+        // the permit of the idle connection is released after the connection
+        // was closed
+        let mut permit = None;
+        for _ in 0..50 {
+            permit = connections.admit();
+            if permit.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(permit.is_some());
+    }
+}
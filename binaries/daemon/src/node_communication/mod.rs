@@ -1,47 +1,98 @@
-use crate::{DaemonNodeEvent, Event};
+use crate::{socket_stream_utils::socket_stream_receive_limited, DaemonNodeEvent, Event};
 use dora_core::{
     config::{DataId, LocalCommunicationConfig, NodeId},
     topics::LOCALHOST,
     uhlc,
 };
 use dora_message::{
-    common::{DropToken, Timestamped},
+    common::{DataMessage, DropToken, Timestamped},
     daemon_to_node::{DaemonCommunication, DaemonReply, NodeDropEvent, NodeEvent},
+    metadata::Metadata,
     node_to_daemon::DaemonRequest,
     DataflowId,
 };
 use eyre::{eyre, Context};
 use futures::{future, task, Future};
+use limits::NodeConnections;
 use shared_memory_server::{ShmemConf, ShmemServer};
 use std::{
+    cmp::Reverse,
     collections::{BTreeMap, VecDeque},
+    io::ErrorKind,
     mem,
-    sync::Arc,
+    sync::{Arc, RwLock, RwLockReadGuard},
     task::Poll,
+    time::Instant,
 };
 #[cfg(unix)]
 use tokio::net::UnixListener;
 use tokio::{
+    io::AsyncRead,
     net::TcpListener,
     sync::{
         mpsc::{self, UnboundedReceiver},
         oneshot,
     },
 };
+use tracing::Instrument;
 
 // TODO unify and avoid duplication;
+pub mod limits;
 pub mod shmem;
 pub mod tcp;
 #[cfg(unix)]
 pub mod unix_domain;
 
+/// Queue settings of the inputs of a node.
+///
+/// Clones share the settings, so that changes through [`InputQueues::set`]
+/// apply to all listeners of the node while it runs.
+#[derive(Debug, Clone, Default)]
+pub struct InputQueues {
+    settings: Arc<RwLock<QueueSettings>>,
+}
+
+#[derive(Debug, Default)]
+pub struct QueueSettings {
+    /// Maximum number of pending messages per input.
+    pub sizes: BTreeMap<DataId, usize>,
+    /// Delivery priority per input, higher values are delivered first.
+    pub priorities: BTreeMap<DataId, u8>,
+}
+
+impl InputQueues {
+    pub fn new(sizes: BTreeMap<DataId, usize>, priorities: BTreeMap<DataId, u8>) -> Self {
+        Self {
+            settings: Arc::new(RwLock::new(QueueSettings { sizes, priorities })),
+        }
+    }
+
+    /// Changes the queue size and priority of the given input.
+    pub fn set(&self, input_id: DataId, size: usize, priority: u8) {
+        let mut settings = self.settings.write().unwrap();
+        settings.sizes.insert(input_id.clone(), size);
+        settings.priorities.insert(input_id, priority);
+    }
+
+    /// The current settings. The listeners of the node can't apply changes
+    /// while the returned guard is held.
+    pub fn settings(&self) -> RwLockReadGuard<'_, QueueSettings> {
+        self.settings.read().unwrap()
+    }
+}
+
+/// Starts listening for the connections of the given node.
+///
+/// The listener tasks run in the current span, so their log messages carry
+/// the dataflow and node IDs of the span in which the node is spawned.
 pub async fn spawn_listener_loop(
     dataflow_id: &DataflowId,
     node_id: &NodeId,
     daemon_tx: &mpsc::Sender<Timestamped<Event>>,
     config: LocalCommunicationConfig,
-    queue_sizes: BTreeMap<DataId, usize>,
+    input_queues: InputQueues,
     clock: Arc<uhlc::HLC>,
+    connections: &NodeConnections,
 ) -> eyre::Result<DaemonCommunication> {
     match config {
         LocalCommunicationConfig::Tcp => {
@@ -59,10 +110,14 @@ pub async fn spawn_listener_loop(
 
             let event_loop_node_id = format!("{dataflow_id}/{node_id}");
             let daemon_tx = daemon_tx.clone();
-            tokio::spawn(async move {
-                tcp::listener_loop(socket, daemon_tx, queue_sizes, clock).await;
-                tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
-            });
+            let connections = connections.clone();
+            tokio::spawn(
+                async move {
+                    tcp::listener_loop(socket, daemon_tx, input_queues, clock, connections).await;
+                    tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
+                }
+                .in_current_span(),
+            );
 
             Ok(DaemonCommunication::Tcp { socket_addr })
         }
@@ -92,9 +147,11 @@ pub async fn spawn_listener_loop(
                 let server = unsafe { ShmemServer::new(daemon_control_region) }
                     .wrap_err("failed to create control server")?;
                 let daemon_tx = daemon_tx.clone();
-                let queue_sizes = queue_sizes.clone();
+                let input_queues = input_queues.clone();
                 let clock = clock.clone();
-                tokio::spawn(shmem::listener_loop(server, daemon_tx, queue_sizes, clock));
+                tokio::spawn(
+                    shmem::listener_loop(server, daemon_tx, input_queues, clock).in_current_span(),
+                );
             }
 
             {
@@ -102,12 +159,15 @@ pub async fn spawn_listener_loop(
                     .wrap_err("failed to create events server")?;
                 let event_loop_node_id = format!("{dataflow_id}/{node_id}");
                 let daemon_tx = daemon_tx.clone();
-                let queue_sizes = queue_sizes.clone();
+                let input_queues = input_queues.clone();
                 let clock = clock.clone();
-                tokio::task::spawn(async move {
-                    shmem::listener_loop(server, daemon_tx, queue_sizes, clock).await;
-                    tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
-                });
+                tokio::task::spawn(
+                    async move {
+                        shmem::listener_loop(server, daemon_tx, input_queues, clock).await;
+                        tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
+                    }
+                    .in_current_span(),
+                );
             }
 
             {
@@ -115,12 +175,15 @@ pub async fn spawn_listener_loop(
                     .wrap_err("failed to create drop server")?;
                 let drop_loop_node_id = format!("{dataflow_id}/{node_id}");
                 let daemon_tx = daemon_tx.clone();
-                let queue_sizes = queue_sizes.clone();
+                let input_queues = input_queues.clone();
                 let clock = clock.clone();
-                tokio::task::spawn(async move {
-                    shmem::listener_loop(server, daemon_tx, queue_sizes, clock).await;
-                    tracing::debug!("drop listener loop finished for `{drop_loop_node_id}`");
-                });
+                tokio::task::spawn(
+                    async move {
+                        shmem::listener_loop(server, daemon_tx, input_queues, clock).await;
+                        tracing::debug!("drop listener loop finished for `{drop_loop_node_id}`");
+                    }
+                    .in_current_span(),
+                );
             }
 
             {
@@ -129,12 +192,15 @@ pub async fn spawn_listener_loop(
                 let drop_loop_node_id = format!("{dataflow_id}/{node_id}");
                 let daemon_tx = daemon_tx.clone();
                 let clock = clock.clone();
-                tokio::task::spawn(async move {
-                    shmem::listener_loop(server, daemon_tx, queue_sizes, clock).await;
-                    tracing::debug!(
-                        "events close listener loop finished for `{drop_loop_node_id}`"
-                    );
-                });
+                tokio::task::spawn(
+                    async move {
+                        shmem::listener_loop(server, daemon_tx, input_queues, clock).await;
+                        tracing::debug!(
+                            "events close listener loop finished for `{drop_loop_node_id}`"
+                        );
+                    }
+                    .in_current_span(),
+                );
             }
 
             Ok(DaemonCommunication::Shmem {
@@ -163,10 +229,15 @@ pub async fn spawn_listener_loop(
 
             let event_loop_node_id = format!("{dataflow_id}/{node_id}");
             let daemon_tx = daemon_tx.clone();
-            tokio::spawn(async move {
-                unix_domain::listener_loop(socket, daemon_tx, queue_sizes, clock).await;
-                tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
-            });
+            let connections = connections.clone();
+            tokio::spawn(
+                async move {
+                    unix_domain::listener_loop(socket, daemon_tx, input_queues, clock, connections)
+                        .await;
+                    tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
+                }
+                .in_current_span(),
+            );
 
             Ok(DaemonCommunication::UnixDomain { socket_file })
         }
@@ -184,30 +255,68 @@ struct Listener {
     subscribed_events: Option<UnboundedReceiver<Timestamped<NodeEvent>>>,
     subscribed_drop_events: Option<UnboundedReceiver<Timestamped<NodeDropEvent>>>,
     queue: VecDeque<Box<Option<Timestamped<NodeEvent>>>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    input_queues: InputQueues,
     clock: Arc<uhlc::HLC>,
 }
 
+/// Whether the given `event` may be delivered before the `earlier` event.
+///
+/// `Stop` passes all events except other `Stop` events, so that the node
+/// sees it even when a backlog of inputs is queued. `InputClosed` passes the
+/// messages of other inputs, but not the messages of its own input, so that
+/// it is still the last event of its input.
+fn passes(event: &NodeEvent, earlier: &NodeEvent) -> bool {
+    match (event, earlier) {
+        (NodeEvent::Stop, NodeEvent::Stop) => false,
+        (NodeEvent::Stop, _) => true,
+        (
+            NodeEvent::InputClosed { id, .. },
+            NodeEvent::Input { id: earlier_id, .. }
+            | NodeEvent::InputBatch { id: earlier_id, .. }
+            | NodeEvent::LatestAvailable { id: earlier_id },
+        ) => id != earlier_id,
+        _ => false,
+    }
+}
+
 impl Listener {
+    /// Handles the requests of a node connection.
+    ///
+    /// The `connections` limits are only enforced for socket connections,
+    /// as only those can be opened by arbitrary processes.
     pub(crate) async fn run<C: Connection>(
         mut connection: C,
         daemon_tx: mpsc::Sender<Timestamped<Event>>,
-        queue_sizes: BTreeMap<DataId, usize>,
+        input_queues: InputQueues,
         hlc: Arc<uhlc::HLC>,
+        connections: Option<NodeConnections>,
     ) {
         // receive the first message
-        let message = match connection
-            .receive_message()
-            .await
-            .wrap_err("failed to receive register message")
-        {
+        let message = match &connections {
+            Some(connections) => {
+                match tokio::time::timeout(
+                    connections.handshake_timeout(),
+                    connection.receive_message(),
+                )
+                .await
+                {
+                    Ok(message) => message,
+                    Err(_) => {
+                        connections.record_handshake_timeout();
+                        return;
+                    }
+                }
+            }
+            None => connection.receive_message().await,
+        };
+        let message = match message.wrap_err("failed to receive register message") {
             Ok(Some(m)) => m,
             Ok(None) => {
                 tracing::info!("channel disconnected before register message");
                 return;
             } // disconnected
             Err(err) => {
-                tracing::info!("{err:?}");
+                tracing::warn!("closing node connection after protocol error: {err:?}");
                 return;
             }
         };
@@ -233,12 +342,12 @@ impl Listener {
                             daemon_tx,
                             subscribed_events: None,
                             subscribed_drop_events: None,
-                            queue_sizes,
+                            input_queues,
                             queue: VecDeque::new(),
                             clock: hlc.clone(),
                         };
                         match listener
-                            .run_inner(connection)
+                            .run_inner(connection, connections)
                             .await
                             .wrap_err("listener failed")
                         {
@@ -270,7 +379,12 @@ impl Listener {
         }
     }
 
-    async fn run_inner<C: Connection>(&mut self, mut connection: C) -> eyre::Result<()> {
+    async fn run_inner<C: Connection>(
+        &mut self,
+        mut connection: C,
+        connections: Option<NodeConnections>,
+    ) -> eyre::Result<()> {
+        let mut rate_limiter = connections.as_ref().map(|c| c.rate_limiter());
         loop {
             let mut next_message = connection.receive_message();
             let message = loop {
@@ -287,6 +401,17 @@ impl Listener {
                 self.handle_events().await?;
             };
 
+            let rate_limited = matches!(message, Ok(Some(_)))
+                && rate_limiter
+                    .as_mut()
+                    .is_some_and(|limiter| !limiter.allow(Instant::now()));
+            if rate_limited {
+                if let Some(connections) = &connections {
+                    connections.record_rate_limited();
+                }
+                break; // disconnect
+            }
+
             match message.wrap_err("failed to receive DaemonRequest") {
                 Ok(Some(message)) => {
                     if let Err(err) = self.handle_message(message, &mut connection).await {
@@ -294,7 +419,13 @@ impl Listener {
                     }
                 }
                 Err(err) => {
-                    tracing::warn!("{err:?}");
+                    // the rest of the stream can't be interpreted reliably
+                    tracing::warn!(
+                        "closing connection of node {}/{} after protocol error: {err:?}",
+                        self.dataflow_id,
+                        self.node_id
+                    );
+                    break;
                 }
                 Ok(None) => {
                     break; // disconnected
@@ -318,46 +449,156 @@ impl Listener {
 
     #[tracing::instrument(skip(self), fields(%self.node_id), level = "trace")]
     async fn drop_oldest_inputs(&mut self) -> Result<(), eyre::ErrReport> {
-        let mut queue_size_remaining = self.queue_sizes.clone();
-        let mut dropped = 0;
+        let mut queue_size_remaining = self.input_queues.settings().sizes.clone();
+        let mut dropped: BTreeMap<DataId, u64> = BTreeMap::new();
+        let mut sources: BTreeMap<DataId, BTreeMap<String, u64>> = BTreeMap::new();
         let mut drop_tokens = Vec::new();
 
-        // iterate over queued events, newest first
-        for event in self.queue.iter_mut().rev() {
-            let Some(Timestamped {
-                inner: NodeEvent::Input { id, data, .. },
-                ..
-            }) = event.as_mut()
-            else {
-                continue;
-            };
+        // counts the given message against the queue size of its input,
+        // returns whether it should be kept
+        let mut keep = |id: &DataId, metadata: &Metadata, data: &Option<DataMessage>| {
             match queue_size_remaining.get_mut(id) {
                 Some(0) => {
-                    dropped += 1;
+                    *dropped.entry(id.clone()).or_default() += 1;
+                    if let Some(source) = metadata.input_source() {
+                        *sources
+                            .entry(id.clone())
+                            .or_default()
+                            .entry(source.to_owned())
+                            .or_default() += 1;
+                    }
                     if let Some(drop_token) = data.as_ref().and_then(|d| d.drop_token()) {
                         drop_tokens.push(drop_token);
                     }
-                    *event.as_mut() = None;
+                    false
                 }
                 Some(size_remaining) => {
                     *size_remaining = size_remaining.saturating_sub(1);
+                    true
                 }
                 None => {
                     tracing::warn!("no queue size known for received input `{id}`");
+                    true
                 }
             }
+        };
+
+        // iterate over queued events, newest first
+        for event in self.queue.iter_mut().rev() {
+            let Some(Timestamped { inner, .. }) = event.as_mut() else {
+                continue;
+            };
+            let keep_event = match inner {
+                NodeEvent::Input { id, data, metadata } => keep(id, metadata, data),
+                NodeEvent::InputBatch { id, messages } => {
+                    // batched messages count individually, the oldest are dropped first
+                    let mut kept: Vec<_> = messages
+                        .iter()
+                        .rev()
+                        .map(|message| keep(id, &message.metadata, &message.data))
+                        .collect();
+                    kept.reverse();
+                    let mut kept = kept.into_iter();
+                    messages.retain(|_| kept.next().unwrap_or(true));
+                    !messages.is_empty()
+                }
+                _ => continue,
+            };
+            if !keep_event {
+                *event.as_mut() = None;
+            }
         }
         self.report_drop_tokens(drop_tokens).await?;
 
-        if dropped > 0 {
-            tracing::debug!(
-                "dropped {dropped} inputs of node `{}` because event queue was too full",
-                self.node_id
-            );
+        if !dropped.is_empty() {
+            // the daemon aggregates the drops into rate-limited warnings
+            let event = Event::Node {
+                dataflow_id: self.dataflow_id,
+                node_id: self.node_id.clone(),
+                event: DaemonNodeEvent::InputsDropped {
+                    counts: dropped,
+                    sources,
+                },
+            };
+            let event = Timestamped {
+                inner: event,
+                timestamp: self.clock.new_timestamp(),
+            };
+            self.daemon_tx
+                .send(event)
+                .await
+                .map_err(|_| eyre!("failed to report dropped inputs to daemon"))?;
         }
         Ok(())
     }
 
+    /// Takes all queued events for sending them to the node.
+    ///
+    /// Events of inputs with a higher priority are moved before the events of
+    /// other inputs. Control events are moved before the events that they
+    /// may pass, see [`passes`]. Other events that don't belong to an input
+    /// (e.g. `Reload`) are never passed by other events. Events of equal
+    /// priority keep their arrival order.
+    fn take_queued_events(&mut self) -> Vec<Timestamped<NodeEvent>> {
+        let mut events: Vec<_> = mem::take(&mut self.queue)
+            .into_iter()
+            .filter_map(|e| *e)
+            .collect();
+        let settings = self.input_queues.settings();
+        let priorities = &settings.priorities;
+        if priorities.values().any(|&p| p > 0) {
+            let priority = |event: &Timestamped<NodeEvent>| match &event.inner {
+                NodeEvent::Input { id, .. }
+                | NodeEvent::InputBatch { id, .. }
+                | NodeEvent::InputClosed { id, .. }
+                | NodeEvent::LatestAvailable { id }
+                | NodeEvent::InputTimeout { id, .. } => {
+                    Some(priorities.get(id).copied().unwrap_or_default())
+                }
+                NodeEvent::Stop
+                | NodeEvent::Reload { .. }
+                | NodeEvent::AllInputsClosed
+                | NodeEvent::SubscribersChanged { .. } => None,
+            };
+            for segment in events.split_mut(|event| priority(event).is_none()) {
+                // stable sort -> events of equal priority stay in order
+                segment.sort_by_key(|event| Reverse(priority(event)));
+            }
+        }
+        for i in 0..events.len() {
+            let target = events[..i]
+                .iter()
+                .rposition(|earlier| !passes(&events[i].inner, &earlier.inner))
+                .map_or(0, |position| position + 1);
+            events[target..=i].rotate_right(1);
+        }
+        events
+    }
+
+    /// Takes the queued control events that can be delivered before all
+    /// other queued events, see [`passes`].
+    fn take_control_events(&mut self) -> Vec<Timestamped<NodeEvent>> {
+        let mut events = Vec::new();
+        for i in 0..self.queue.len() {
+            let (earlier, rest) = self.queue.make_contiguous().split_at_mut(i);
+            let Some(event) = &*rest[0] else {
+                continue;
+            };
+            if !matches!(event.inner, NodeEvent::Stop | NodeEvent::InputClosed { .. }) {
+                continue;
+            }
+            let passes_all = earlier
+                .iter()
+                .filter_map(|earlier| Option::as_ref(earlier))
+                .all(|earlier| passes(&event.inner, &earlier.inner));
+            if passes_all {
+                events.extend(rest[0].take());
+            }
+        }
+        self.queue.retain(|event| event.is_some());
+        events
+    }
+
     #[tracing::instrument(skip(self, connection), fields(%self.dataflow_id, %self.node_id), level = "trace")]
     async fn handle_message<C: Connection>(
         &mut self,
@@ -369,6 +610,32 @@ impl Listener {
             tracing::warn!("failed to update HLC: {err}");
         }
         match message.inner {
+            DaemonRequest::Multiplexed {
+                request_id,
+                request,
+            } => {
+                let mut connection = MultiplexedConnection {
+                    inner: connection,
+                    request_id,
+                };
+                self.handle_request(*request, &mut connection).await
+            }
+            request => self.handle_request(request, connection).await,
+        }
+    }
+
+    async fn handle_request<C: Connection>(
+        &mut self,
+        request: DaemonRequest,
+        connection: &mut C,
+    ) -> eyre::Result<()> {
+        match request {
+            DaemonRequest::Multiplexed { .. } => {
+                let reply = DaemonReply::Result(Err("nested multiplexed request".into()));
+                self.send_reply(reply, connection)
+                    .await
+                    .wrap_err("failed to send multiplexed reply")?;
+            }
             DaemonRequest::Register { .. } => {
                 let reply = DaemonReply::Result(Err("unexpected register message".into()));
                 self.send_reply(reply, connection)
@@ -381,6 +648,22 @@ impl Listener {
                     .await
                     .wrap_err("failed to send register reply")?;
             }
+            DaemonRequest::Observe { .. } => {
+                let reply = DaemonReply::Result(Err(
+                    "observers must connect to the local listener of the daemon".into(),
+                ));
+                self.send_reply(reply, connection)
+                    .await
+                    .wrap_err("failed to send observe reply")?;
+            }
+            DaemonRequest::Join { .. } => {
+                let reply = DaemonReply::Result(Err(
+                    "join tokens are only accepted by the local listener of the daemon".into(),
+                ));
+                self.send_reply(reply, connection)
+                    .await
+                    .wrap_err("failed to send join reply")?;
+            }
             DaemonRequest::OutputsDone => {
                 let (reply_sender, reply) = oneshot::channel();
                 self.process_daemon_event(
@@ -390,6 +673,15 @@ impl Listener {
                 )
                 .await?
             }
+            DaemonRequest::Ready => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::Ready { reply_sender },
+                    Some(reply),
+                    connection,
+                )
+                .await?
+            }
             DaemonRequest::CloseOutputs(outputs) => {
                 let (reply_sender, reply) = oneshot::channel();
                 self.process_daemon_event(
@@ -407,19 +699,37 @@ impl Listener {
                 metadata,
                 data,
             } => {
+                let (reply_sender, reply) = oneshot::channel();
                 let event = crate::DaemonNodeEvent::SendOut {
                     output_id,
                     metadata,
                     data,
+                    reply_sender,
+                };
+                self.process_daemon_event(event, Some(reply), connection)
+                    .await?;
+            }
+            DaemonRequest::SendEmptyMessage {
+                output_id,
+                metadata,
+            } => {
+                let (reply_sender, reply) = oneshot::channel();
+                let event = crate::DaemonNodeEvent::SendOut {
+                    output_id,
+                    metadata,
+                    data: None,
+                    reply_sender,
                 };
-                self.process_daemon_event(event, None, connection).await?;
+                self.process_daemon_event(event, Some(reply), connection)
+                    .await?;
             }
-            DaemonRequest::Subscribe => {
+            DaemonRequest::Subscribe { interest } => {
                 let (tx, rx) = mpsc::unbounded_channel();
                 let (reply_sender, reply) = oneshot::channel();
                 self.process_daemon_event(
                     DaemonNodeEvent::Subscribe {
                         event_sender: tx,
+                        interest,
                         reply_sender,
                     },
                     Some(reply),
@@ -446,10 +756,7 @@ impl Listener {
                 self.report_drop_tokens(drop_tokens).await?;
 
                 // try to take the queued events first
-                let queued_events: Vec<_> = mem::take(&mut self.queue)
-                    .into_iter()
-                    .filter_map(|e| *e)
-                    .collect();
+                let queued_events = self.take_queued_events();
                 let reply = if queued_events.is_empty() {
                     match self.subscribed_events.as_mut() {
                         // wait for next event
@@ -471,6 +778,12 @@ impl Listener {
                     .await
                     .wrap_err_with(|| format!("failed to send NextEvent reply: {reply:?}"))?;
             }
+            DaemonRequest::NextControlEvents => {
+                let events = self.take_control_events();
+                self.send_reply(DaemonReply::NextEvents(events), connection)
+                    .await
+                    .wrap_err("failed to send NextControlEvents reply")?;
+            }
             DaemonRequest::ReportDropTokens { drop_tokens } => {
                 self.report_drop_tokens(drop_tokens).await?;
 
@@ -505,6 +818,116 @@ impl Listener {
                 )
                 .await?;
             }
+            DaemonRequest::QueryTopology => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::QueryTopology { reply_sender },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::QuerySubscribers { output_id } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::QuerySubscribers {
+                        output_id,
+                        reply_sender,
+                    },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::RequestDataflowStop { grace_duration } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::RequestDataflowStop {
+                        grace_duration,
+                        reply_sender,
+                    },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::NodeError { message } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::NodeError {
+                        message,
+                        reply_sender,
+                    },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::TakeLatest { id } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::TakeLatest { id, reply_sender },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::PrepareOutputRing {
+                output_id,
+                slot_len,
+                slots,
+            } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::PrepareOutputRing {
+                        output_id,
+                        slot_len,
+                        slots,
+                        reply_sender,
+                    },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::SendOutSlot {
+                ring_id,
+                slot_index,
+                valid_len,
+                metadata,
+            } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::SendOutSlot {
+                        ring_id,
+                        slot_index,
+                        valid_len,
+                        metadata,
+                        reply_sender,
+                    },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
+            DaemonRequest::SendCached {
+                output_id,
+                metadata,
+                hash,
+            } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::SendCached {
+                        output_id,
+                        metadata,
+                        hash,
+                        reply_sender,
+                    },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
         }
         Ok(())
     }
@@ -592,8 +1015,992 @@ impl Listener {
     }
 }
 
+/// Maximum length of a request that nodes send through a socket connection.
+///
+/// Requests only carry small messages inline; larger message data is sent
+/// through shared memory.
+const MAX_REQUEST_LEN: u64 = 16 * 1024 * 1024;
+
+/// Receives and decodes the next request of a node through a socket.
+///
+/// Returns `Ok(None)` if the connection was closed. Malformed requests
+/// result in an error, after which the connection should be closed.
+pub(crate) async fn receive_request(
+    connection: &mut (impl AsyncRead + Unpin),
+) -> eyre::Result<Option<Timestamped<DaemonRequest>>> {
+    let raw = match socket_stream_receive_limited(connection, MAX_REQUEST_LEN).await {
+        Ok(raw) => raw,
+        Err(err) => match err.kind() {
+            ErrorKind::UnexpectedEof
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset => return Ok(None),
+            ErrorKind::InvalidData => return Err(err).context("invalid DaemonRequest frame"),
+            _other => {
+                return Err(err)
+                    .context("unexpected I/O error while trying to receive DaemonRequest")
+            }
+        },
+    };
+    bincode::deserialize(&raw)
+        .wrap_err("failed to deserialize DaemonRequest")
+        .map(Some)
+}
+
 #[async_trait::async_trait]
-trait Connection {
+trait Connection: Send {
     async fn receive_message(&mut self) -> eyre::Result<Option<Timestamped<DaemonRequest>>>;
     async fn send_reply(&mut self, message: DaemonReply) -> eyre::Result<()>;
 }
+
+/// Tags the reply to a [`DaemonRequest::Multiplexed`] request with its ID.
+struct MultiplexedConnection<'a, C> {
+    inner: &'a mut C,
+    request_id: u64,
+}
+
+#[async_trait::async_trait]
+impl<C: Connection> Connection for MultiplexedConnection<'_, C> {
+    async fn receive_message(&mut self) -> eyre::Result<Option<Timestamped<DaemonRequest>>> {
+        self.inner.receive_message().await
+    }
+
+    async fn send_reply(&mut self, message: DaemonReply) -> eyre::Result<()> {
+        let reply = match message {
+            // requests without reply stay without reply
+            DaemonReply::Empty => DaemonReply::Empty,
+            reply => DaemonReply::Multiplexed {
+                request_id: self.request_id,
+                reply: Box::new(reply),
+            },
+        };
+        self.inner.send_reply(reply).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_message::metadata::{ArrowTypeInfo, Metadata};
+
+    #[tokio::test]
+    async fn full_queue_drops_oldest_inputs() {
+        let clock = Arc::new(uhlc::HLC::default());
+        let (daemon_tx, mut daemon_rx) = mpsc::channel(10);
+        let pose = DataId::from("pose".to_owned());
+        let status = DataId::from("status".to_owned());
+        let mut listener = Listener {
+            dataflow_id: DataflowId::new_v4(),
+            node_id: NodeId::from("planner".to_owned()),
+            daemon_tx,
+            subscribed_events: None,
+            subscribed_drop_events: None,
+            queue: VecDeque::new(),
+            input_queues: InputQueues::new(
+                [(pose.clone(), 2), (status.clone(), 10)].into(),
+                BTreeMap::new(),
+            ),
+            clock: clock.clone(),
+        };
+        // alternating inputs, as sent by a single node
+        for i in 0..10 {
+            let id = if i % 2 == 0 { &pose } else { &status };
+            listener.queue.push_back(Box::new(Some(Timestamped {
+                inner: NodeEvent::Input {
+                    id: id.clone(),
+                    metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+                    data: None,
+                },
+                timestamp: clock.new_timestamp(),
+            })));
+        }
+
+        listener.drop_oldest_inputs().await.unwrap();
+
+        // only the oldest `pose` inputs are dropped, the order is unchanged
+        let remaining: Vec<_> = listener
+            .queue
+            .iter()
+            .filter_map(|event| match event.as_ref() {
+                Some(Timestamped {
+                    inner: NodeEvent::Input { id, .. },
+                    ..
+                }) => Some(id.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            remaining,
+            ["status", "status", "status", "pose", "status", "pose", "status"]
+        );
+        match daemon_rx.try_recv().unwrap().inner {
+            Event::Node {
+                event: DaemonNodeEvent::InputsDropped { counts, sources },
+                ..
+            } => {
+                assert_eq!(counts, [(DataId::from("pose".to_owned()), 3)].into());
+                assert!(sources.is_empty());
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_oldest_batched_inputs() {
+        let clock = Arc::new(uhlc::HLC::default());
+        let (daemon_tx, mut daemon_rx) = mpsc::channel(10);
+        let imu = DataId::from("imu".to_owned());
+        let mut listener = Listener {
+            dataflow_id: DataflowId::new_v4(),
+            node_id: NodeId::from("filter".to_owned()),
+            daemon_tx,
+            subscribed_events: None,
+            subscribed_drop_events: None,
+            queue: VecDeque::new(),
+            input_queues: InputQueues::new([(imu.clone(), 4)].into(), BTreeMap::new()),
+            clock: clock.clone(),
+        };
+        let tokens: Vec<_> = (0..6).map(|_| DropToken::generate()).collect();
+        let message = |token: &DropToken| BatchedInput {
+            metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+            data: Some(DataMessage::SharedMemory {
+                shared_memory_id: String::new(),
+                len: 0,
+                drop_token: *token,
+            }),
+        };
+        for batch in tokens.chunks(3) {
+            listener.queue.push_back(Box::new(Some(Timestamped {
+                inner: NodeEvent::InputBatch {
+                    id: imu.clone(),
+                    messages: batch.iter().map(message).collect(),
+                },
+                timestamp: clock.new_timestamp(),
+            })));
+        }
+
+        listener.drop_oldest_inputs().await.unwrap();
+
+        // the messages count individually, so the first batch is trimmed
+        let remaining: Vec<_> = listener
+            .queue
+            .iter()
+            .filter_map(|event| match event.as_ref() {
+                Some(Timestamped {
+                    inner: NodeEvent::InputBatch { messages, .. },
+                    ..
+                }) => Some(
+                    messages
+                        .iter()
+                        .filter_map(|m| m.data.as_ref()?.drop_token())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(remaining, [&tokens[2..3], &tokens[3..]]);
+        match daemon_rx.try_recv().unwrap().inner {
+            Event::Node {
+                event: DaemonNodeEvent::ReportDrop { tokens: dropped },
+                ..
+            } => assert_eq!(dropped, [tokens[1], tokens[0]]),
+            other => panic!("unexpected event {other:?}"),
+        }
+        match daemon_rx.try_recv().unwrap().inner {
+            Event::Node {
+                event: DaemonNodeEvent::InputsDropped { counts, .. },
+                ..
+            } => assert_eq!(counts, [(imu, 2)].into()),
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn multiplexed_replies_are_tagged() {
+        struct RecordingConnection(Vec<DaemonReply>);
+
+        #[async_trait::async_trait]
+        impl Connection for RecordingConnection {
+            async fn receive_message(
+                &mut self,
+            ) -> eyre::Result<Option<Timestamped<DaemonRequest>>> {
+                Ok(None)
+            }
+
+            async fn send_reply(&mut self, message: DaemonReply) -> eyre::Result<()> {
+                self.0.push(message);
+                Ok(())
+            }
+        }
+
+        let clock = Arc::new(uhlc::HLC::default());
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+        let mut listener = Listener {
+            dataflow_id: DataflowId::new_v4(),
+            node_id: NodeId::from("planner".to_owned()),
+            daemon_tx,
+            subscribed_events: None,
+            subscribed_drop_events: None,
+            queue: VecDeque::new(),
+            input_queues: InputQueues::default(),
+            clock: clock.clone(),
+        };
+        let mut connection = RecordingConnection(Vec::new());
+        let requests = [
+            (
+                7,
+                DaemonRequest::NextEvent {
+                    drop_tokens: Vec::new(),
+                },
+            ),
+            (
+                8,
+                DaemonRequest::Multiplexed {
+                    request_id: 9,
+                    request: Box::new(DaemonRequest::Ready),
+                },
+            ),
+            // has no reply
+            (
+                10,
+                DaemonRequest::ReportDropTokens {
+                    drop_tokens: Vec::new(),
+                },
+            ),
+        ];
+        for (request_id, request) in requests {
+            let message = Timestamped {
+                inner: DaemonRequest::Multiplexed {
+                    request_id,
+                    request: Box::new(request),
+                },
+                timestamp: clock.new_timestamp(),
+            };
+            listener
+                .handle_message(message, &mut connection)
+                .await
+                .unwrap();
+        }
+
+        let replies: Vec<_> = connection
+            .0
+            .iter()
+            .map(|reply| match reply {
+                DaemonReply::Multiplexed { request_id, reply } => match reply.as_ref() {
+                    DaemonReply::Result(Err(_)) => Some(*request_id),
+                    other => panic!("unexpected reply {other:?}"),
+                },
+                DaemonReply::Empty => None,
+                other => panic!("untagged reply {other:?}"),
+            })
+            .collect();
+        assert_eq!(replies, [Some(7), Some(8), None]);
+    }
+
+    #[tokio::test]
+    async fn priority_inputs_are_not_starved() {
+        let clock = Arc::new(uhlc::HLC::default());
+        let (daemon_tx, _daemon_rx) = mpsc::channel(1000);
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let lidar = DataId::from("lidar".to_owned());
+        let command = DataId::from("command".to_owned());
+        let mut listener = Listener {
+            dataflow_id: DataflowId::new_v4(),
+            node_id: NodeId::from("planner".to_owned()),
+            daemon_tx,
+            subscribed_events: Some(events_rx),
+            subscribed_drop_events: None,
+            queue: VecDeque::new(),
+            input_queues: InputQueues::new(
+                [(lidar.clone(), 10), (command.clone(), 10)].into(),
+                [(command.clone(), 1)].into(),
+            ),
+            clock: clock.clone(),
+        };
+        let input = |id: &DataId| Timestamped {
+            inner: NodeEvent::Input {
+                id: id.clone(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+                data: None,
+            },
+            timestamp: clock.new_timestamp(),
+        };
+
+        // a chatty `lidar` input and a rare `command` input; the node only
+        // requests new events after every 500 messages
+        let mut received = Vec::new();
+        for i in 0..10_000 {
+            let id = if i % 100 == 99 { &command } else { &lidar };
+            events_tx.send(input(id)).unwrap();
+            if i % 500 == 499 {
+                listener.handle_events().await.unwrap();
+                let events = listener.take_queued_events();
+                let ids: Vec<_> = events
+                    .iter()
+                    .map(|event| match &event.inner {
+                        NodeEvent::Input { id, .. } => id.clone(),
+                        other => panic!("unexpected event {other:?}"),
+                    })
+                    .collect();
+                // commands are delivered before the pending lidar messages
+                let first_lidar = ids.iter().position(|id| id == &lidar).unwrap();
+                assert!(ids[first_lidar..].iter().all(|id| id == &lidar));
+                received.extend(ids);
+            }
+        }
+
+        let commands = received.iter().filter(|id| *id == &command).count();
+        assert_eq!(commands, 100, "no command may be dropped");
+        assert!(received.len() < 10_000, "lidar messages should be dropped");
+
+        // `Stop` passes all queued inputs
+        events_tx.send(input(&lidar)).unwrap();
+        events_tx
+            .send(Timestamped {
+                inner: NodeEvent::Stop,
+                timestamp: clock.new_timestamp(),
+            })
+            .unwrap();
+        events_tx.send(input(&command)).unwrap();
+        listener.handle_events().await.unwrap();
+        let events = listener.take_queued_events();
+        assert!(matches!(events[0].inner, NodeEvent::Stop));
+        assert!(matches!(&events[1].inner, NodeEvent::Input { id, .. } if id == &command));
+        assert!(matches!(&events[2].inner, NodeEvent::Input { id, .. } if id == &lidar));
+    }
+
+    #[tokio::test]
+    async fn control_events_pass_queued_inputs() {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+        let clock = Arc::new(uhlc::HLC::default());
+        let lidar = DataId::from("lidar".to_owned());
+        let camera = DataId::from("camera".to_owned());
+        let mut listener = Listener {
+            dataflow_id: DataflowId::new_v4(),
+            node_id: NodeId::from("node".to_owned()),
+            daemon_tx,
+            subscribed_events: Some(events_rx),
+            subscribed_drop_events: None,
+            queue: VecDeque::new(),
+            input_queues: InputQueues::new(
+                [(lidar.clone(), 10), (camera.clone(), 10)].into(),
+                BTreeMap::new(),
+            ),
+            clock: clock.clone(),
+        };
+        let send = |inner| {
+            events_tx
+                .send(Timestamped {
+                    inner,
+                    timestamp: clock.new_timestamp(),
+                })
+                .unwrap()
+        };
+        let input = |id: &DataId| NodeEvent::Input {
+            id: id.clone(),
+            metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+            data: None,
+        };
+        let closed = |id: &DataId| NodeEvent::InputClosed {
+            id: id.clone(),
+            delivered: 2,
+            last_timestamp: None,
+        };
+        let summary = |events: Vec<Timestamped<NodeEvent>>| -> Vec<String> {
+            events
+                .into_iter()
+                .map(|event| match event.inner {
+                    NodeEvent::Input { id, .. } => id.to_string(),
+                    NodeEvent::InputClosed { id, .. } => format!("closed {id}"),
+                    other => format!("{other:?}"),
+                })
+                .collect()
+        };
+
+        send(input(&lidar));
+        send(input(&camera));
+        send(input(&lidar));
+        send(closed(&camera));
+        send(NodeEvent::Stop);
+        send(NodeEvent::AllInputsClosed);
+        listener.handle_events().await.unwrap();
+        assert_eq!(
+            summary(listener.take_queued_events()),
+            [
+                "Stop",
+                "lidar",
+                "camera",
+                "closed camera",
+                "lidar",
+                "AllInputsClosed"
+            ]
+        );
+
+        // control events can be taken without the queued inputs
+        send(input(&lidar));
+        send(input(&camera));
+        send(closed(&camera));
+        send(closed(&lidar));
+        send(NodeEvent::Stop);
+        listener.handle_events().await.unwrap();
+        assert_eq!(summary(listener.take_control_events()), ["Stop"]);
+        assert_eq!(
+            summary(listener.take_queued_events()),
+            ["lidar", "camera", "closed camera", "closed lidar"]
+        );
+
+        send(input(&camera));
+        send(closed(&lidar));
+        listener.handle_events().await.unwrap();
+        assert_eq!(summary(listener.take_control_events()), ["closed lidar"]);
+        assert_eq!(summary(listener.take_queued_events()), ["camera"]);
+    }
+
+    mod protocol {
+        use super::*;
+        use arrow_schema::{DataType, Field};
+        use dora_message::{
+            common::{DataMessage, OutputRingId},
+            coordinator_to_daemon::{DaemonCoordinatorEvent, TimeSync},
+            daemon_to_node::{BatchedInput, ObservedMessage, SendOutputError, StopRequestError},
+            metadata::{ArrowTypeInfo, BufferOffset, Metadata, Parameter},
+            node_to_daemon::{EventInterest, NodeRegisterRequest},
+        };
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+        use std::time::Duration;
+
+        fn string(rng: &mut StdRng) -> String {
+            let len = rng.gen_range(0..12);
+            (0..len).map(|_| rng.gen::<char>()).collect()
+        }
+
+        fn data_id(rng: &mut StdRng) -> DataId {
+            DataId::from(string(rng))
+        }
+
+        fn node_id(rng: &mut StdRng) -> NodeId {
+            NodeId::from(string(rng))
+        }
+
+        fn type_info(rng: &mut StdRng, depth: usize) -> ArrowTypeInfo {
+            let child_data: Vec<_> = if depth > 0 && rng.gen_bool(0.3) {
+                (0..rng.gen_range(1..3))
+                    .map(|_| type_info(rng, depth - 1))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let data_type = match child_data.first() {
+                Some(child) => {
+                    DataType::List(Field::new("item", child.data_type.clone(), true).into())
+                }
+                None => [
+                    DataType::Null,
+                    DataType::UInt8,
+                    DataType::Float64,
+                    DataType::Utf8,
+                ]
+                .choose(rng)
+                .unwrap()
+                .clone(),
+            };
+            ArrowTypeInfo {
+                data_type,
+                len: rng.gen(),
+                null_count: rng.gen(),
+                validity: rng
+                    .gen_bool(0.5)
+                    .then(|| (0..rng.gen_range(0..8)).map(|_| rng.gen()).collect()),
+                offset: rng.gen(),
+                buffer_offsets: (0..rng.gen_range(0..3))
+                    .map(|_| BufferOffset {
+                        offset: rng.gen(),
+                        len: rng.gen(),
+                    })
+                    .collect(),
+                child_data,
+            }
+        }
+
+        fn parameter(rng: &mut StdRng) -> Parameter {
+            match rng.gen_range(0..8) {
+                0 => Parameter::Bool(rng.gen()),
+                1 => Parameter::Integer(rng.gen()),
+                2 => Parameter::String(string(rng)),
+                3 => Parameter::Float(rng.gen()),
+                4 => Parameter::Bytes((0..rng.gen_range(0..8)).map(|_| rng.gen()).collect()),
+                5 => Parameter::ListInt((0..rng.gen_range(0..4)).map(|_| rng.gen()).collect()),
+                6 => Parameter::ListFloat((0..rng.gen_range(0..4)).map(|_| rng.gen()).collect()),
+                _ => Parameter::ListString((0..rng.gen_range(0..4)).map(|_| string(rng)).collect()),
+            }
+        }
+
+        fn metadata(rng: &mut StdRng, clock: &uhlc::HLC) -> Metadata {
+            let parameters = (0..rng.gen_range(0..4))
+                .map(|_| (string(rng), parameter(rng)))
+                .collect();
+            Metadata::from_parameters(clock.new_timestamp(), type_info(rng, 3), parameters)
+        }
+
+        fn drop_tokens(rng: &mut StdRng) -> Vec<DropToken> {
+            (0..rng.gen_range(0..4))
+                .map(|_| DropToken::generate())
+                .collect()
+        }
+
+        fn request(rng: &mut StdRng, clock: &uhlc::HLC) -> DaemonRequest {
+            match rng.gen_range(0..25) {
+                0 => DaemonRequest::Register(NodeRegisterRequest::new(
+                    DataflowId::new_v4(),
+                    node_id(rng),
+                )),
+                1 => DaemonRequest::Subscribe {
+                    interest: if rng.gen() {
+                        EventInterest::ALL
+                    } else {
+                        EventInterest::CONTROL
+                    },
+                },
+                2 => DaemonRequest::SendMessage {
+                    output_id: data_id(rng),
+                    metadata: metadata(rng, clock),
+                    data: match rng.gen_range(0..3) {
+                        0 => None,
+                        1 => Some(DataMessage::Vec(aligned_vec::AVec::from_slice(
+                            128,
+                            &(0..rng.gen_range(0..64))
+                                .map(|_| rng.gen())
+                                .collect::<Vec<u8>>(),
+                        ))),
+                        _ => Some(DataMessage::SharedMemory {
+                            shared_memory_id: string(rng),
+                            len: rng.gen(),
+                            drop_token: DropToken::generate(),
+                        }),
+                    },
+                },
+                3 => DaemonRequest::SendEmptyMessage {
+                    output_id: data_id(rng),
+                    metadata: metadata(rng, clock),
+                },
+                4 => DaemonRequest::CloseOutputs(
+                    (0..rng.gen_range(0..4)).map(|_| data_id(rng)).collect(),
+                ),
+                5 => DaemonRequest::OutputsDone,
+                6 => DaemonRequest::NextEvent {
+                    drop_tokens: drop_tokens(rng),
+                },
+                7 => DaemonRequest::ReportDropTokens {
+                    drop_tokens: drop_tokens(rng),
+                },
+                8 => DaemonRequest::SubscribeDrop,
+                9 => DaemonRequest::NextFinishedDropTokens,
+                10 => DaemonRequest::EventStreamDropped,
+                11 => DaemonRequest::NodeConfig {
+                    node_id: node_id(rng),
+                },
+                12 => DaemonRequest::QueryTopology,
+                13 => DaemonRequest::TakeLatest { id: data_id(rng) },
+                14 => DaemonRequest::PrepareOutputRing {
+                    output_id: data_id(rng),
+                    slot_len: rng.gen(),
+                    slots: rng.gen(),
+                },
+                15 => DaemonRequest::Ready,
+                16 => DaemonRequest::SendOutSlot {
+                    ring_id: OutputRingId::generate(),
+                    slot_index: rng.gen(),
+                    valid_len: rng.gen(),
+                    metadata: metadata(rng, clock),
+                },
+                17 => DaemonRequest::NextControlEvents,
+                18 => DaemonRequest::SendCached {
+                    output_id: data_id(rng),
+                    metadata: metadata(rng, clock),
+                    hash: string(rng),
+                },
+                19 => DaemonRequest::Observe {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                    output_id: data_id(rng),
+                },
+                20 => DaemonRequest::QuerySubscribers {
+                    output_id: data_id(rng),
+                },
+                21 => DaemonRequest::RequestDataflowStop {
+                    grace_duration: rng
+                        .gen_bool(0.5)
+                        .then(|| Duration::from_millis(rng.gen_range(0..100_000))),
+                },
+                22 => DaemonRequest::NodeError {
+                    message: string(rng),
+                },
+                23 => DaemonRequest::Join { token: string(rng) },
+                _ => DaemonRequest::Multiplexed {
+                    request_id: rng.gen(),
+                    request: Box::new(DaemonRequest::SendEmptyMessage {
+                        output_id: data_id(rng),
+                        metadata: metadata(rng, clock),
+                    }),
+                },
+            }
+        }
+
+        fn send_output_error(rng: &mut StdRng) -> SendOutputError {
+            match rng.gen_range(0..6) {
+                0 => SendOutputError::OutputNotDeclared {
+                    output_id: data_id(rng),
+                },
+                1 => SendOutputError::UnknownOutputRing {
+                    ring_id: OutputRingId::generate(),
+                },
+                2 => SendOutputError::InvalidSlot {
+                    ring_id: OutputRingId::generate(),
+                    slot_index: rng.gen(),
+                    valid_len: rng.gen(),
+                },
+                3 => SendOutputError::SlotBusy {
+                    ring_id: OutputRingId::generate(),
+                    slot_index: rng.gen(),
+                },
+                4 => SendOutputError::OutputNotPersistent {
+                    output_id: data_id(rng),
+                },
+                _ => SendOutputError::AllocationFailed {
+                    reason: string(rng),
+                },
+            }
+        }
+
+        fn reply(rng: &mut StdRng, clock: &uhlc::HLC) -> DaemonReply {
+            match rng.gen_range(0..11) {
+                0 => DaemonReply::Result(if rng.gen() { Ok(()) } else { Err(string(rng)) }),
+                1 => DaemonReply::PreparedMessage {
+                    shared_memory_id: string(rng),
+                },
+                2 => DaemonReply::NextEvents(
+                    (0..rng.gen_range(0..4))
+                        .map(|_| Timestamped {
+                            inner: match rng.gen_range(0..8) {
+                                0 => NodeEvent::Stop,
+                                1 => NodeEvent::Input {
+                                    id: data_id(rng),
+                                    metadata: metadata(rng, clock),
+                                    data: None,
+                                },
+                                2 => NodeEvent::InputClosed {
+                                    id: data_id(rng),
+                                    delivered: rng.gen(),
+                                    last_timestamp: rng
+                                        .gen_bool(0.5)
+                                        .then(|| clock.new_timestamp()),
+                                },
+                                3 => NodeEvent::AllInputsClosed,
+                                4 => NodeEvent::SubscribersChanged {
+                                    output_id: data_id(rng),
+                                    subscribers: rng.gen(),
+                                },
+                                5 => NodeEvent::InputTimeout {
+                                    id: data_id(rng),
+                                    elapsed: Duration::from_millis(rng.gen_range(0..10_000)),
+                                },
+                                6 => NodeEvent::InputBatch {
+                                    id: data_id(rng),
+                                    messages: (0..rng.gen_range(0..4))
+                                        .map(|_| BatchedInput {
+                                            metadata: metadata(rng, clock),
+                                            data: None,
+                                        })
+                                        .collect(),
+                                },
+                                _ => NodeEvent::LatestAvailable { id: data_id(rng) },
+                            },
+                            timestamp: clock.new_timestamp(),
+                        })
+                        .collect(),
+                ),
+                3 => DaemonReply::NextDropEvents(
+                    (0..rng.gen_range(0..4))
+                        .map(|_| Timestamped {
+                            inner: NodeDropEvent::OutputDropped {
+                                drop_token: DropToken::generate(),
+                            },
+                            timestamp: clock.new_timestamp(),
+                        })
+                        .collect(),
+                ),
+                4 => DaemonReply::SendOutResult(if rng.gen() {
+                    Ok(())
+                } else {
+                    Err(send_output_error(rng))
+                }),
+                5 => DaemonReply::SendOutSlotResult(if rng.gen() {
+                    Ok(DropToken::generate())
+                } else {
+                    Err(send_output_error(rng))
+                }),
+                6 => DaemonReply::SendCachedResult(if rng.gen() {
+                    Ok(rng.gen_bool(0.5).then(DropToken::generate))
+                } else {
+                    Err(send_output_error(rng))
+                }),
+                7 => DaemonReply::ObservedMessage(ObservedMessage {
+                    metadata: metadata(rng, clock),
+                    data: rng.gen_bool(0.5).then(|| {
+                        aligned_vec::AVec::from_slice(
+                            128,
+                            &(0..rng.gen_range(0..64))
+                                .map(|_| rng.gen())
+                                .collect::<Vec<u8>>(),
+                        )
+                    }),
+                    skipped: rng.gen(),
+                }),
+                8 => DaemonReply::Subscribers {
+                    result: if rng.gen() {
+                        Ok(rng.gen())
+                    } else {
+                        Err(string(rng))
+                    },
+                },
+                9 => DaemonReply::StopRequestResult(match rng.gen_range(0..3) {
+                    0 => Ok(()),
+                    1 => Err(StopRequestError::PermissionDenied {
+                        node_id: node_id(rng),
+                    }),
+                    _ => Err(StopRequestError::Failed {
+                        reason: string(rng),
+                    }),
+                }),
+                _ => DaemonReply::Multiplexed {
+                    request_id: rng.gen(),
+                    reply: Box::new(DaemonReply::SendOutResult(Ok(()))),
+                },
+            }
+        }
+
+        fn coordinator_event(rng: &mut StdRng) -> DaemonCoordinatorEvent {
+            // `Spawn` is left out as it requires a valid dataflow descriptor
+            match rng.gen_range(0..16) {
+                0 => DaemonCoordinatorEvent::AllNodesReady {
+                    dataflow_id: DataflowId::new_v4(),
+                    exited_before_subscribe: (0..rng.gen_range(0..3))
+                        .map(|_| node_id(rng))
+                        .collect(),
+                },
+                1 => DaemonCoordinatorEvent::StopDataflow {
+                    dataflow_id: DataflowId::new_v4(),
+                    grace_duration: rng.gen_bool(0.5).then(|| {
+                        Duration::new(rng.gen_range(0..1000), rng.gen_range(0..1_000_000_000))
+                    }),
+                },
+                2 => DaemonCoordinatorEvent::ReloadDataflow {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                    operator_id: rng.gen_bool(0.5).then(|| string(rng).into()),
+                },
+                3 => DaemonCoordinatorEvent::ReloadNode {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                },
+                4 => DaemonCoordinatorEvent::Logs {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                },
+                5 => DaemonCoordinatorEvent::GetDescriptor {
+                    dataflow_id: DataflowId::new_v4(),
+                },
+                6 => DaemonCoordinatorEvent::Destroy,
+                7 => DaemonCoordinatorEvent::Heartbeat,
+                8 => DaemonCoordinatorEvent::TimeSync(TimeSync {
+                    daemon_sent: rng.gen(),
+                    coordinator_received: rng.gen(),
+                    coordinator_sent: rng.gen(),
+                }),
+                9 => DaemonCoordinatorEvent::SetLogLevel {
+                    filter: string(rng),
+                },
+                10 => DaemonCoordinatorEvent::Diagnostics {
+                    gc: rng
+                        .gen_bool(0.5)
+                        .then(|| Duration::from_millis(rng.gen_range(0..100_000))),
+                },
+                12 => DaemonCoordinatorEvent::SwitchNodeRoutes {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                    from_machine: string(rng),
+                    to_machine: string(rng),
+                    revert: rng.gen(),
+                },
+                13 => DaemonCoordinatorEvent::StopMigratedNode {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                },
+                14 => DaemonCoordinatorEvent::FinishNodeMigration {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                    abort: rng.gen(),
+                },
+                15 => DaemonCoordinatorEvent::MintToken {
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                },
+                _ => DaemonCoordinatorEvent::TapOutput {
+                    tap_id: uuid::Uuid::new_v4(),
+                    dataflow_id: DataflowId::new_v4(),
+                    node_id: node_id(rng),
+                    output_id: data_id(rng),
+                    duration: Duration::from_millis(rng.gen_range(0..100_000)),
+                    // exactly representable, so that the JSON encoding is lossless
+                    max_rate: rng
+                        .gen_bool(0.5)
+                        .then(|| f64::from(rng.gen_range(0..4000)) / 4.0),
+                },
+            }
+        }
+
+        fn frame(request: &Timestamped<DaemonRequest>) -> Vec<u8> {
+            let raw = bincode::serialize(request).unwrap();
+            let mut frame = (raw.len() as u64).to_le_bytes().to_vec();
+            frame.extend(raw);
+            frame
+        }
+
+        #[tokio::test]
+        async fn random_requests_round_trip() {
+            let clock = uhlc::HLC::default();
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..500 {
+                let request = Timestamped {
+                    inner: request(&mut rng, &clock),
+                    timestamp: clock.new_timestamp(),
+                };
+                let encoded = frame(&request);
+                let decoded = receive_request(&mut encoded.as_slice())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(frame(&decoded), encoded, "{request:?}");
+            }
+        }
+
+        #[test]
+        fn random_replies_round_trip() {
+            let clock = uhlc::HLC::default();
+            let mut rng = StdRng::seed_from_u64(1);
+            for _ in 0..500 {
+                let reply = reply(&mut rng, &clock);
+                let encoded = bincode::serialize(&reply).unwrap();
+                let decoded: DaemonReply = bincode::deserialize(&encoded).unwrap();
+                assert_eq!(bincode::serialize(&decoded).unwrap(), encoded, "{reply:?}");
+            }
+        }
+
+        #[test]
+        fn random_coordinator_events_round_trip() {
+            let clock = uhlc::HLC::default();
+            let mut rng = StdRng::seed_from_u64(2);
+            for _ in 0..500 {
+                let event = Timestamped {
+                    inner: coordinator_event(&mut rng),
+                    timestamp: clock.new_timestamp(),
+                };
+                let encoded = serde_json::to_vec(&event).unwrap();
+                let decoded: Timestamped<DaemonCoordinatorEvent> =
+                    serde_json::from_slice(&encoded).unwrap();
+                assert_eq!(serde_json::to_vec(&decoded).unwrap(), encoded, "{event:?}");
+            }
+        }
+
+        #[tokio::test]
+        async fn corrupted_requests_are_rejected_without_panic() {
+            let clock = uhlc::HLC::default();
+            let mut rng = StdRng::seed_from_u64(3);
+            for _ in 0..2000 {
+                let request = Timestamped {
+                    inner: request(&mut rng, &clock),
+                    timestamp: clock.new_timestamp(),
+                };
+                let mut encoded = frame(&request);
+                match rng.gen_range(0..3) {
+                    0 => encoded.truncate(rng.gen_range(0..encoded.len())),
+                    1 => {
+                        for _ in 0..rng.gen_range(1..4) {
+                            let index = rng.gen_range(0..encoded.len());
+                            encoded[index] = rng.gen();
+                        }
+                    }
+                    _ => {
+                        // corrupt the payload only, keeping the length prefix
+                        let index = rng.gen_range(8..encoded.len());
+                        encoded[index..].iter_mut().for_each(|b| *b = rng.gen());
+                    }
+                }
+                // must return (with any result) instead of panicking or aborting
+                let _ = receive_request(&mut encoded.as_slice()).await;
+            }
+        }
+
+        #[tokio::test]
+        async fn malformed_frames_are_protocol_errors() {
+            // length prefix far beyond the limit
+            let mut huge = u64::MAX.to_le_bytes().to_vec();
+            huge.extend([0; 16]);
+            assert!(receive_request(&mut huge.as_slice()).await.is_err());
+
+            // a truncated frame is treated as a closed connection
+            let truncated = [100, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3];
+            assert!(receive_request(&mut truncated.as_slice())
+                .await
+                .unwrap()
+                .is_none());
+
+            // deeply nested type info, which would overflow the stack
+            let clock = uhlc::HLC::default();
+            let mut type_info = ArrowTypeInfo::empty();
+            for _ in 0..10_000 {
+                let mut outer = ArrowTypeInfo::empty();
+                outer.child_data.push(type_info);
+                type_info = outer;
+            }
+            let request = Timestamped {
+                inner: DaemonRequest::SendEmptyMessage {
+                    output_id: "out".to_owned().into(),
+                    metadata: Metadata::new(clock.new_timestamp(), type_info),
+                },
+                timestamp: clock.new_timestamp(),
+            };
+            // serializing and dropping recurses too, so do it on a large stack
+            let nested = std::thread::Builder::new()
+                .stack_size(256 * 1024 * 1024)
+                .spawn(move || frame(&request))
+                .unwrap()
+                .join()
+                .unwrap();
+            let err = receive_request(&mut nested.as_slice()).await.unwrap_err();
+            assert!(format!("{err:?}").contains("nesting depth"), "{err:?}");
+
+            // deeply nested multiplexed requests
+            let nested = std::thread::Builder::new()
+                .stack_size(256 * 1024 * 1024)
+                .spawn(move || {
+                    let mut request = DaemonRequest::Ready;
+                    for request_id in 0..10_000 {
+                        request = DaemonRequest::Multiplexed {
+                            request_id,
+                            request: Box::new(request),
+                        };
+                    }
+                    frame(&Timestamped {
+                        inner: request,
+                        timestamp: clock.new_timestamp(),
+                    })
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+            let err = receive_request(&mut nested.as_slice()).await.unwrap_err();
+            assert!(format!("{err:?}").contains("nesting depth"), "{err:?}");
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::sync::Arc;
 
-use super::{Connection, Listener};
+use super::{Connection, InputQueues, Listener};
 use crate::Event;
-use dora_core::{config::DataId, uhlc::HLC};
+use dora_core::uhlc::HLC;
 use dora_message::{
     common::Timestamped, daemon_to_node::DaemonReply, node_to_daemon::DaemonRequest,
 };
@@ -14,7 +14,7 @@ use tokio::sync::{mpsc, oneshot};
 pub async fn listener_loop(
     mut server: ShmemServer<Timestamped<DaemonRequest>, DaemonReply>,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    input_queues: InputQueues,
     clock: Arc<HLC>,
 ) {
     let (tx, rx) = flume::bounded(0);
@@ -39,7 +39,7 @@ pub async fn listener_loop(
         }
     });
     let connection = ShmemConnection(tx);
-    Listener::run(connection, daemon_tx, queue_sizes, clock).await
+    Listener::run(connection, daemon_tx, input_queues, clock, None).await
 }
 
 enum Operation {
@@ -1,11 +1,8 @@
-use std::{collections::BTreeMap, io::ErrorKind, sync::Arc};
+use std::sync::Arc;
 
-use super::{Connection, Listener};
-use crate::{
-    socket_stream_utils::{socket_stream_receive, socket_stream_send},
-    Event,
-};
-use dora_core::{config::DataId, uhlc::HLC};
+use super::{limits::NodeConnections, receive_request, Connection, InputQueues, Listener};
+use crate::{socket_stream_utils::socket_stream_send, Event};
+use dora_core::uhlc::HLC;
 use dora_message::{
     common::Timestamped, daemon_to_node::DaemonReply, node_to_daemon::DaemonRequest,
 };
@@ -14,13 +11,15 @@ use tokio::{
     net::{TcpListener, TcpStream},
     sync::mpsc,
 };
+use tracing::Instrument;
 
-#[tracing::instrument(skip(listener, daemon_tx, clock), level = "trace")]
+#[tracing::instrument(skip(listener, daemon_tx, clock, connections), level = "trace")]
 pub async fn listener_loop(
     listener: TcpListener,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    input_queues: InputQueues,
     clock: Arc<HLC>,
+    connections: NodeConnections,
 ) {
     loop {
         match listener
@@ -32,29 +31,54 @@ pub async fn listener_loop(
                 tracing::info!("{err}");
             }
             Ok((connection, _)) => {
-                tokio::spawn(handle_connection_loop(
-                    connection,
-                    daemon_tx.clone(),
-                    queue_sizes.clone(),
-                    clock.clone(),
-                ));
+                // closes the connection if there are too many
+                let Some(permit) = connections.admit() else {
+                    continue;
+                };
+                let daemon_tx = daemon_tx.clone();
+                let input_queues = input_queues.clone();
+                let clock = clock.clone();
+                let connections = connections.clone();
+                tokio::spawn(
+                    async move {
+                        handle_connection_loop(
+                            connection,
+                            daemon_tx,
+                            input_queues,
+                            clock,
+                            connections,
+                        )
+                        .await;
+                        drop(permit);
+                    }
+                    // keeps the IDs of the dataflow and node of the listener
+                    .in_current_span(),
+                );
             }
         }
     }
 }
 
-#[tracing::instrument(skip(connection, daemon_tx, clock), level = "trace")]
+#[tracing::instrument(skip(connection, daemon_tx, clock, connections), level = "trace")]
 async fn handle_connection_loop(
     connection: TcpStream,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    input_queues: InputQueues,
     clock: Arc<HLC>,
+    connections: NodeConnections,
 ) {
     if let Err(err) = connection.set_nodelay(true) {
         tracing::warn!("failed to set nodelay for connection: {err}");
     }
 
-    Listener::run(TcpConnection(connection), daemon_tx, queue_sizes, clock).await
+    Listener::run(
+        TcpConnection(connection),
+        daemon_tx,
+        input_queues,
+        clock,
+        Some(connections),
+    )
+    .await
 }
 
 struct TcpConnection(TcpStream);
@@ -62,21 +86,7 @@ struct TcpConnection(TcpStream);
 #[async_trait::async_trait]
 impl Connection for TcpConnection {
     async fn receive_message(&mut self) -> eyre::Result<Option<Timestamped<DaemonRequest>>> {
-        let raw = match socket_stream_receive(&mut self.0).await {
-            Ok(raw) => raw,
-            Err(err) => match err.kind() {
-                ErrorKind::UnexpectedEof
-                | ErrorKind::ConnectionAborted
-                | ErrorKind::ConnectionReset => return Ok(None),
-                _other => {
-                    return Err(err)
-                        .context("unexpected I/O error while trying to receive DaemonRequest")
-                }
-            },
-        };
-        bincode::deserialize(&raw)
-            .wrap_err("failed to deserialize DaemonRequest")
-            .map(Some)
+        receive_request(&mut self.0).await
     }
 
     async fn send_reply(&mut self, message: DaemonReply) -> eyre::Result<()> {
@@ -0,0 +1,100 @@
+//! State of a node that is migrated between the machines of a dataflow.
+//!
+//! During a migration, the node runs on both machines: the old instance on
+//! `from_machine` and the new instance on `to_machine`. Each machine of the
+//! dataflow switches its routes to the new instance on its own, one after
+//! the other, and then sends a `NodeRoutesSwitched` event to both machines of
+//! the migration. Since the event is sent on the same connection as the
+//! outputs, the receivers know for each message which instance it was
+//! routed to, so that no message is delivered to both instances.
+
+use std::{collections::BTreeSet, time::Instant};
+
+use dora_core::config::NodeId;
+use dora_message::daemon_to_coordinator::DaemonCoordinatorReply;
+use tokio::sync::oneshot;
+
+pub struct NodeMigration {
+    pub node_id: NodeId,
+    pub from_machine: String,
+    pub to_machine: String,
+    /// Machines whose outputs are routed to the new instance already.
+    switched: BTreeSet<String>,
+    /// Reply for the `StopMigratedNode` event, sent once the old instance
+    /// exited.
+    pub stop_reply: Option<(Instant, oneshot::Sender<Option<DaemonCoordinatorReply>>)>,
+}
+
+impl NodeMigration {
+    pub fn new(node_id: NodeId, from_machine: String, to_machine: String) -> Self {
+        Self {
+            node_id,
+            from_machine,
+            to_machine,
+            switched: BTreeSet::new(),
+            stop_reply: None,
+        }
+    }
+
+    /// Records that the given machine switched its routes, or switched them
+    /// back if `revert` is set.
+    pub fn set_switched(&mut self, machine_id: &str, revert: bool) {
+        if revert {
+            self.switched.remove(machine_id);
+        } else {
+            self.switched.insert(machine_id.to_owned());
+        }
+    }
+
+    /// Whether the instance on `local_machine` should receive the messages
+    /// of a source on `source_machine`.
+    pub fn delivers(&self, local_machine: &str, source_machine: &str) -> bool {
+        let switched = self.switched.contains(source_machine);
+        if local_machine == self.to_machine {
+            switched
+        } else {
+            !switched
+        }
+    }
+}
+
+impl Drop for NodeMigration {
+    fn drop(&mut self) {
+        if let Some((_, reply_tx)) = self.stop_reply.take() {
+            let reply = DaemonCoordinatorReply::NodeMigrationResult(Err(
+                "migration state was removed before the old instance exited".into(),
+            ));
+            let _ = reply_tx.send(Some(reply));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_message_is_delivered_to_one_instance() {
+        let mut migration =
+            NodeMigration::new(NodeId::from("node".to_owned()), "a".into(), "b".into());
+        let sources = ["a", "b", "c"];
+        let check = |migration: &NodeMigration, expected_on_b: &[&str]| {
+            for source in sources {
+                let on_a = migration.delivers("a", source);
+                let on_b = migration.delivers("b", source);
+                assert_ne!(on_a, on_b, "source machine `{source}`");
+                assert_eq!(on_b, expected_on_b.contains(&source));
+            }
+        };
+
+        check(&migration, &[]);
+        migration.set_switched("a", false);
+        check(&migration, &["a"]);
+        migration.set_switched("c", false);
+        migration.set_switched("b", false);
+        check(&migration, &["a", "b", "c"]);
+
+        migration.set_switched("b", true);
+        check(&migration, &["a", "c"]);
+    }
+}
@@ -0,0 +1,234 @@
+//! State of nodes that are restarted through a `ReloadNode` event.
+//!
+//! While a node is reloaded, its event channel is replaced by a buffer
+//! channel, so that the message delivery code does not need to know about
+//! reloads. Messages that arrive while the node is down are kept up to the
+//! queue size of their input (dropping the oldest first) and are delivered to
+//! the new node instance once it subscribes.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Instant,
+};
+
+use dora_core::config::DataId;
+use dora_message::{
+    daemon_to_coordinator::{DaemonCoordinatorReply, NodeReloadReport},
+    daemon_to_node::NodeEvent,
+    node_to_daemon::{DropToken, Timestamped},
+};
+use tokio::sync::{mpsc, oneshot};
+
+pub struct ReloadingNode {
+    started: Instant,
+    /// Set once the new node instance was spawned.
+    pub respawned: bool,
+    events: mpsc::UnboundedReceiver<Timestamped<NodeEvent>>,
+    buffer: VecDeque<Timestamped<NodeEvent>>,
+    queue_sizes: BTreeMap<DataId, usize>,
+    dropped: usize,
+    reply_tx: Option<oneshot::Sender<Option<DaemonCoordinatorReply>>>,
+}
+
+impl ReloadingNode {
+    /// Creates the reload state, together with the sender that replaces the
+    /// event channel of the node.
+    pub fn new(
+        queue_sizes: BTreeMap<DataId, usize>,
+        reply_tx: oneshot::Sender<Option<DaemonCoordinatorReply>>,
+    ) -> (Self, mpsc::UnboundedSender<Timestamped<NodeEvent>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let reloading = Self {
+            started: Instant::now(),
+            respawned: false,
+            events: rx,
+            buffer: VecDeque::new(),
+            queue_sizes,
+            dropped: 0,
+            reply_tx: Some(reply_tx),
+        };
+        (reloading, tx)
+    }
+
+    /// Moves newly arrived messages into the buffer and drops the oldest
+    /// messages of inputs that exceed their queue size.
+    ///
+    /// Returns the drop tokens of the dropped messages.
+    pub fn buffer_events(&mut self) -> Vec<DropToken> {
+        while let Ok(event) = self.events.try_recv() {
+            match event.inner {
+                NodeEvent::Input { .. } => self.buffer.push_back(event),
+                NodeEvent::InputBatch { id, messages } => {
+                    self.buffer
+                        .extend(messages.into_iter().map(|message| Timestamped {
+                            inner: message.into_event(id.clone()),
+                            timestamp: event.timestamp,
+                        }));
+                }
+                // other events are sent again when the new instance subscribes
+                _ => {}
+            }
+        }
+
+        let mut excess: BTreeMap<&DataId, usize> = BTreeMap::new();
+        for event in &self.buffer {
+            if let NodeEvent::Input { id, .. } = &event.inner {
+                *excess.entry(id).or_default() += 1;
+            }
+        }
+        let mut excess: BTreeMap<DataId, usize> = excess
+            .into_iter()
+            .filter_map(|(id, count)| {
+                let size = *self.queue_sizes.get(id)?;
+                (count > size).then(|| (id.clone(), count - size))
+            })
+            .collect();
+        if excess.is_empty() {
+            return Vec::new();
+        }
+
+        self.dropped += excess.values().sum::<usize>();
+        let mut dropped_tokens = Vec::new();
+        self.buffer.retain(|event| match &event.inner {
+            NodeEvent::Input { id, data, .. } => match excess.get_mut(id) {
+                Some(remaining) if *remaining > 0 => {
+                    *remaining -= 1;
+                    dropped_tokens.extend(data.as_ref().and_then(|d| d.drop_token()));
+                    false
+                }
+                _ => true,
+            },
+            _ => true,
+        });
+        dropped_tokens
+    }
+
+    /// Drop tokens of the buffered messages.
+    pub fn buffered_drop_tokens(&self) -> impl Iterator<Item = DropToken> + '_ {
+        self.buffer.iter().filter_map(|event| match &event.inner {
+            NodeEvent::Input {
+                data: Some(data), ..
+            } => data.drop_token(),
+            _ => None,
+        })
+    }
+
+    /// Takes the buffered messages for delivering them to the new node
+    /// instance and reports the successful reload.
+    ///
+    /// Call [`buffer_events`][Self::buffer_events] before to not miss any
+    /// messages.
+    pub fn finish(mut self) -> Vec<Timestamped<NodeEvent>> {
+        let buffered = std::mem::take(&mut self.buffer);
+        let report = NodeReloadReport {
+            down_time: self.started.elapsed(),
+            buffered_messages: buffered.len(),
+            dropped_messages: self.dropped,
+        };
+        self.reply(Ok(report));
+        buffered.into()
+    }
+
+    /// Reports that the reload failed.
+    pub fn fail(mut self, err: String) {
+        self.reply(Err(err));
+    }
+
+    fn reply(&mut self, result: Result<NodeReloadReport, String>) {
+        if let Some(reply_tx) = self.reply_tx.take() {
+            let reply = DaemonCoordinatorReply::ReloadNodeResult(result);
+            if reply_tx.send(Some(reply)).is_err() {
+                tracing::error!("could not send node reload reply from daemon to coordinator");
+            }
+        }
+    }
+}
+
+impl Drop for ReloadingNode {
+    fn drop(&mut self) {
+        // the coordinator waits for a reply
+        self.reply(Err("reload was aborted".into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::uhlc::HLC;
+    use dora_message::{
+        metadata::{ArrowTypeInfo, Metadata},
+        node_to_daemon::DataMessage,
+    };
+
+    fn input(clock: &HLC, id: &str, drop_token: DropToken) -> Timestamped<NodeEvent> {
+        let timestamp = clock.new_timestamp();
+        Timestamped {
+            inner: NodeEvent::Input {
+                id: DataId::from(id.to_owned()),
+                metadata: Metadata::new(timestamp, ArrowTypeInfo::empty()),
+                data: Some(DataMessage::SharedMemory {
+                    shared_memory_id: String::new(),
+                    len: 0,
+                    drop_token,
+                }),
+            },
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn oldest_messages_are_dropped_beyond_queue_size() {
+        let clock = HLC::default();
+        let queue_sizes = [("image", 2), ("tick", 10)]
+            .into_iter()
+            .map(|(id, size)| (DataId::from(id.to_owned()), size))
+            .collect();
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        let (mut reloading, tx) = ReloadingNode::new(queue_sizes, reply_tx);
+
+        let images: Vec<_> = (0..5).map(|_| DropToken::generate()).collect();
+        let tick = DropToken::generate();
+        for token in &images[..3] {
+            tx.send(input(&clock, "image", *token)).unwrap();
+        }
+        tx.send(input(&clock, "tick", tick)).unwrap();
+        assert_eq!(reloading.buffer_events(), vec![images[0]]);
+        for token in &images[3..] {
+            tx.send(input(&clock, "image", *token)).unwrap();
+        }
+        assert_eq!(reloading.buffer_events(), images[1..3].to_vec());
+        // stop events of the old instance are not delivered to the new one
+        tx.send(Timestamped {
+            inner: NodeEvent::Stop,
+            timestamp: clock.new_timestamp(),
+        })
+        .unwrap();
+        assert!(reloading.buffer_events().is_empty());
+
+        let mut kept: Vec<_> = reloading.buffered_drop_tokens().collect();
+        kept.sort();
+        let mut expected = vec![tick, images[3], images[4]];
+        expected.sort();
+        assert_eq!(kept, expected);
+
+        let events = reloading.finish();
+        assert_eq!(events.len(), 3);
+        let Ok(Some(DaemonCoordinatorReply::ReloadNodeResult(Ok(report)))) = reply_rx.try_recv()
+        else {
+            panic!("expected successful reload reply")
+        };
+        assert_eq!(report.buffered_messages, 3);
+        assert_eq!(report.dropped_messages, 3);
+    }
+
+    #[test]
+    fn aborted_reload_is_reported() {
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        let (reloading, _tx) = ReloadingNode::new(BTreeMap::new(), reply_tx);
+        drop(reloading);
+        assert!(matches!(
+            reply_rx.try_recv(),
+            Ok(Some(DaemonCoordinatorReply::ReloadNodeResult(Err(_))))
+        ));
+    }
+}
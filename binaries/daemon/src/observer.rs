@@ -0,0 +1,79 @@
+//! Read-only observers of outputs, which connect through the local listener
+//! of the daemon (e.g. `dora tap --local`).
+//!
+//! Observers are not part of the dataflow graph: they never hold drop
+//! tokens, they are not counted as open inputs, and they don't delay the
+//! nodes of the dataflow. Each observer has a bounded queue. Messages are
+//! skipped for an observer while its queue is full.
+
+use aligned_vec::{AVec, ConstAlign};
+use dora_message::{daemon_to_node::ObservedMessage, metadata::Metadata};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Number of messages that are queued for an observer before messages are
+/// skipped.
+pub const OBSERVER_QUEUE_SIZE: usize = 16;
+
+pub struct Observer {
+    sender: mpsc::Sender<ObservedMessage>,
+    /// Messages that were skipped since the last queued message.
+    skipped: u64,
+}
+
+impl Observer {
+    pub fn new(sender: mpsc::Sender<ObservedMessage>) -> Self {
+        Self { sender, skipped: 0 }
+    }
+
+    /// Queues a copy of the given message, or skips it if the queue is full.
+    ///
+    /// Returns `false` if the observer disconnected.
+    pub fn send(&mut self, metadata: &Metadata, data: Option<&AVec<u8, ConstAlign<128>>>) -> bool {
+        match self.sender.try_reserve() {
+            Ok(permit) => {
+                permit.send(ObservedMessage {
+                    metadata: metadata.clone(),
+                    data: data.cloned(),
+                    skipped: std::mem::take(&mut self.skipped),
+                });
+                true
+            }
+            Err(TrySendError::Full(())) => {
+                self.skipped += 1;
+                true
+            }
+            Err(TrySendError::Closed(())) => false,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::uhlc::HLC;
+    use dora_message::metadata::ArrowTypeInfo;
+
+    #[test]
+    fn full_queues_skip_messages() {
+        let clock = HLC::default();
+        let metadata = Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut observer = Observer::new(sender);
+
+        assert!(observer.send(&metadata, None));
+        assert!(observer.send(&metadata, None));
+        assert!(observer.send(&metadata, None));
+        assert_eq!(receiver.try_recv().unwrap().skipped, 0);
+
+        assert!(observer.send(&metadata, None));
+        assert_eq!(receiver.try_recv().unwrap().skipped, 2);
+
+        drop(receiver);
+        assert!(observer.is_closed());
+        assert!(!observer.send(&metadata, None));
+    }
+}
@@ -0,0 +1,249 @@
+//! Rings of preallocated shared memory slots for sending outputs.
+//!
+//! Nodes that send many messages at a high rate can prepare a ring of
+//! shared memory regions once and then publish the slots of the ring in turn,
+//! instead of allocating a region per message. A slot is busy as long as the
+//! drop token of the last message sent from it is pending, which gives the
+//! sender natural backpressure.
+
+use dora_core::config::{DataId, NodeId};
+use dora_message::{
+    daemon_to_node::{OutputRingInfo, SendOutputError},
+    node_to_daemon::{DataMessage, DropToken, OutputRingId},
+};
+use shared_memory_server::{Shmem, ShmemConf};
+use std::time::Instant;
+use uuid::Uuid;
+
+/// Maximum number of slots of a single ring.
+pub const MAX_SLOTS: usize = 256;
+/// Maximum total size of the slots of a single ring, in bytes.
+pub const MAX_RING_SIZE: usize = 1024 * 1024 * 1024;
+/// Maximum number of rings that a node can prepare.
+pub const MAX_RINGS_PER_NODE: usize = 16;
+
+pub struct OutputRing {
+    pub owner: NodeId,
+    pub output_id: DataId,
+    slot_len: usize,
+    slots: Vec<Slot>,
+    allocated: Instant,
+}
+
+struct Slot {
+    memory: ShmemHandle,
+    /// Drop token of the last message that was sent from this slot.
+    last_token: Option<DropToken>,
+}
+
+impl OutputRing {
+    pub fn allocate(
+        owner: NodeId,
+        output_id: DataId,
+        slot_len: usize,
+        slots: usize,
+    ) -> Result<Self, SendOutputError> {
+        if slot_len == 0 || slots == 0 {
+            return Err(SendOutputError::AllocationFailed {
+                reason: "output rings need at least one slot of non-zero length".into(),
+            });
+        }
+        if slots > MAX_SLOTS {
+            return Err(SendOutputError::AllocationFailed {
+                reason: format!("output rings can have at most {MAX_SLOTS} slots"),
+            });
+        }
+        if slot_len.saturating_mul(slots) > MAX_RING_SIZE {
+            return Err(SendOutputError::AllocationFailed {
+                reason: format!("output rings can have at most {MAX_RING_SIZE} bytes"),
+            });
+        }
+        let slots = (0..slots)
+            .map(|_| {
+                let memory = ShmemConf::new()
+                    .size(slot_len)
+                    .writable(true)
+                    .create()
+                    .map_err(|err| SendOutputError::AllocationFailed {
+                        reason: err.to_string(),
+                    })?;
+                Ok(Slot {
+                    memory: ShmemHandle(Box::new(memory)),
+                    last_token: None,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            owner,
+            output_id,
+            slot_len,
+            slots,
+            allocated: Instant::now(),
+        })
+    }
+
+    pub fn info(&self, ring_id: OutputRingId) -> OutputRingInfo {
+        OutputRingInfo {
+            ring_id,
+            slot_ids: self
+                .slots
+                .iter()
+                .map(|s| s.memory.0.get_os_id().to_owned())
+                .collect(),
+        }
+    }
+
+    /// Total size of the shared memory regions of the ring.
+    pub fn size(&self) -> u64 {
+        (self.slot_len * self.slots.len()) as u64
+    }
+
+    pub fn allocated(&self) -> Instant {
+        self.allocated
+    }
+
+    /// Whether receivers might still access one of the slots.
+    ///
+    /// The `is_pending` function reports whether receivers still access the
+    /// message of the given drop token.
+    pub fn in_use(&self, is_pending: impl Fn(&DropToken) -> bool) -> bool {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.last_token.as_ref())
+            .any(is_pending)
+    }
+
+    /// Creates the message for sending the given slot.
+    ///
+    /// The `is_pending` function reports whether receivers still access the
+    /// message of the given drop token. The new drop token is generated in
+    /// the given namespace, see [`DropToken::generate_in`].
+    pub fn publish(
+        &mut self,
+        ring_id: OutputRingId,
+        slot_index: usize,
+        valid_len: usize,
+        token_namespace: Uuid,
+        is_pending: impl Fn(&DropToken) -> bool,
+    ) -> Result<(DataMessage, DropToken), SendOutputError> {
+        let slot_len = self.slot_len;
+        let slot = self
+            .slots
+            .get_mut(slot_index)
+            .filter(|_| valid_len <= slot_len)
+            .ok_or(SendOutputError::InvalidSlot {
+                ring_id,
+                slot_index,
+                valid_len,
+            })?;
+        if slot.last_token.as_ref().is_some_and(is_pending) {
+            return Err(SendOutputError::SlotBusy {
+                ring_id,
+                slot_index,
+            });
+        }
+
+        let drop_token = DropToken::generate_in(token_namespace);
+        slot.last_token = Some(drop_token);
+        let message = DataMessage::SharedMemory {
+            shared_memory_id: slot.memory.0.get_os_id().to_owned(),
+            len: valid_len,
+            drop_token,
+        };
+        Ok((message, drop_token))
+    }
+}
+
+struct ShmemHandle(Box<Shmem>);
+
+// the daemon only passes the region IDs around, it never accesses the memory itself
+unsafe impl Send for ShmemHandle {}
+unsafe impl Sync for ShmemHandle {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_busy_until_dropped() {
+        let ring_id = OutputRingId::generate();
+        let mut ring = OutputRing::allocate(
+            NodeId::from("sender".to_owned()),
+            DataId::from("out".to_owned()),
+            64,
+            2,
+        )
+        .unwrap();
+        let info = ring.info(ring_id);
+        assert_eq!(info.slot_ids.len(), 2);
+
+        let (message, token) = ring
+            .publish(ring_id, 0, 16, Uuid::nil(), |_| false)
+            .unwrap();
+        let DataMessage::SharedMemory {
+            shared_memory_id,
+            len,
+            ..
+        } = message
+        else {
+            panic!("expected shared memory message")
+        };
+        assert_eq!(shared_memory_id, info.slot_ids[0]);
+        assert_eq!(len, 16);
+
+        // the slot is busy while the token is pending
+        let result = ring.publish(ring_id, 0, 16, Uuid::nil(), |t| *t == token);
+        assert_eq!(
+            result.unwrap_err(),
+            SendOutputError::SlotBusy {
+                ring_id,
+                slot_index: 0
+            }
+        );
+        // other slots can still be used
+        assert!(ring
+            .publish(ring_id, 1, 64, Uuid::nil(), |t| *t == token)
+            .is_ok());
+        // the slot is free again once the token was dropped
+        let (_, next_token) = ring
+            .publish(ring_id, 0, 16, Uuid::nil(), |_| false)
+            .unwrap();
+        assert_ne!(next_token, token);
+
+        assert!(matches!(
+            ring.publish(ring_id, 1, 65, Uuid::nil(), |_| false),
+            Err(SendOutputError::InvalidSlot { .. })
+        ));
+        assert!(matches!(
+            ring.publish(ring_id, 2, 1, Uuid::nil(), |_| false),
+            Err(SendOutputError::InvalidSlot { .. })
+        ));
+        assert!(ring.in_use(|t| *t == next_token));
+        assert!(!ring.in_use(|_| false));
+    }
+
+    #[test]
+    fn ring_size_is_limited() {
+        let allocate = |slot_len, slots| {
+            OutputRing::allocate(
+                NodeId::from("sender".to_owned()),
+                DataId::from("out".to_owned()),
+                slot_len,
+                slots,
+            )
+        };
+        assert!(matches!(
+            allocate(64, MAX_SLOTS + 1),
+            Err(SendOutputError::AllocationFailed { .. })
+        ));
+        assert!(matches!(
+            allocate(MAX_RING_SIZE / 2 + 1, 2),
+            Err(SendOutputError::AllocationFailed { .. })
+        ));
+        assert!(matches!(
+            allocate(usize::MAX, MAX_SLOTS),
+            Err(SendOutputError::AllocationFailed { .. })
+        ));
+        assert!(allocate(64, MAX_SLOTS).is_ok());
+    }
+}
@@ -0,0 +1,292 @@
+//! Directories that the daemon writes to.
+//!
+//! The daemon keeps its files in separate state, cache, and log directories,
+//! so that it also works on machines whose application partition is
+//! read-only. Explicitly configured directories must be writable. If a
+//! default directory is not writable, the features that need it are disabled
+//! with a warning instead.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use dora_message::{coordinator_to_daemon::DataflowInstance, DataflowId};
+use eyre::eyre;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::log;
+
+/// Directories given on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct DaemonPathsConfig {
+    /// Directory for persistent state, e.g. the node registry.
+    ///
+    /// Defaults to `$XDG_STATE_HOME/dora` (`~/.local/state/dora`), or to
+    /// `/var/lib/dora` if there is no home directory.
+    pub state_dir: Option<PathBuf>,
+    /// Directory for cached files, e.g. downloaded nodes.
+    ///
+    /// Defaults to `$XDG_CACHE_HOME/dora` (`~/.cache/dora`), or to
+    /// `/var/cache/dora` if there is no home directory.
+    pub cache_dir: Option<PathBuf>,
+    /// Directory for the log and output files of all dataflows.
+    ///
+    /// Defaults to the `out` directory in the working dir of each dataflow.
+    pub log_dir: Option<PathBuf>,
+    /// Maximum total size of the persistent output cache in the cache dir,
+    /// in bytes.
+    ///
+    /// Defaults to [`DEFAULT_PERSISTENT_CACHE_SIZE`].
+    pub persistent_cache_size: Option<u64>,
+}
+
+/// Default size limit of the persistent output cache (8 GiB).
+pub const DEFAULT_PERSISTENT_CACHE_SIZE: u64 = 8 * 1024 * 1024 * 1024;
+
+/// Writable directories of the daemon, see [`DaemonPathsConfig`].
+///
+/// Directories that are not writable are `None`.
+#[derive(Debug, Clone, Default)]
+pub struct DaemonPaths {
+    state_dir: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    log_dir: Option<PathBuf>,
+    persistent_cache_size: u64,
+}
+
+impl DaemonPaths {
+    /// Creates the configured directories and checks that they are writable.
+    ///
+    /// Fails if an explicitly configured directory is not writable.
+    pub fn open(config: DaemonPathsConfig) -> eyre::Result<Self> {
+        let DaemonPathsConfig {
+            state_dir,
+            cache_dir,
+            log_dir,
+            persistent_cache_size,
+        } = config;
+        Ok(Self {
+            state_dir: check_dir(
+                "state",
+                "--state-dir",
+                state_dir,
+                default_dir("XDG_STATE_HOME", ".local/state", "/var/lib/dora"),
+            )?,
+            cache_dir: check_dir(
+                "cache",
+                "--cache-dir",
+                cache_dir,
+                default_dir("XDG_CACHE_HOME", ".cache", "/var/cache/dora"),
+            )?,
+            log_dir: check_dir("log", "--log-dir", log_dir, None)?,
+            persistent_cache_size: persistent_cache_size.unwrap_or(DEFAULT_PERSISTENT_CACHE_SIZE),
+        })
+    }
+
+    pub fn state_dir(&self) -> Option<&Path> {
+        self.state_dir.as_deref()
+    }
+
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    pub fn log_dir(&self) -> Option<&Path> {
+        self.log_dir.as_deref()
+    }
+
+    /// Directory of the persistent output cache.
+    pub fn persistent_cache_dir(&self) -> Option<PathBuf> {
+        Some(self.cache_dir.as_ref()?.join("persistent-outputs"))
+    }
+
+    pub fn persistent_cache_size(&self) -> u64 {
+        self.persistent_cache_size
+    }
+
+    /// Default location of the node registry, specific to the machine ID so
+    /// that multiple daemons can run on the same host.
+    pub fn node_registry(&self, machine_id: &str) -> Option<PathBuf> {
+        let name = if machine_id.is_empty() {
+            "daemon-nodes.json".to_owned()
+        } else {
+            let machine_id: String = machine_id
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("daemon-nodes-{machine_id}.json")
+        };
+        Some(self.state_dir.as_ref()?.join(name))
+    }
+
+    /// Path to which the node at the given URL is downloaded.
+    ///
+    /// Downloads are cached per URL. Without cache dir, nodes are downloaded
+    /// to the temp directory and not reused across daemon runs.
+    pub fn download_path(&self, url: &str, file_name: &Path) -> PathBuf {
+        let dir = match &self.cache_dir {
+            Some(dir) => dir.join("downloads"),
+            None => std::env::temp_dir()
+                .join("dora-downloads")
+                .join(std::process::id().to_string()),
+        };
+        let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+        dir.join(&hash[..16]).join(file_name)
+    }
+
+    /// Directory for the log and output files of the given dataflow.
+    pub fn dataflow_dir(
+        &self,
+        working_dir: &Path,
+        dataflow_id: &DataflowId,
+        instance: Option<&DataflowInstance>,
+    ) -> PathBuf {
+        let out_dir = match &self.log_dir {
+            Some(dir) => dir.clone(),
+            None => working_dir.join("out"),
+        };
+        log::dataflow_dir(&out_dir, dataflow_id, instance)
+    }
+
+    /// Runtime directory of the given dataflow, see [`crate::runtime_dir`].
+    ///
+    /// Without state dir, dataflows have no runtime directory.
+    pub fn dataflow_runtime_dir(&self, dataflow_id: &DataflowId) -> Option<PathBuf> {
+        Some(
+            self.state_dir
+                .as_ref()?
+                .join("dataflows")
+                .join(dataflow_id.to_string()),
+        )
+    }
+
+    /// Creates the given dataflow dir if it doesn't exist yet.
+    ///
+    /// Returns `None` with a warning if the directory can't be created, e.g.
+    /// because the working dir is on a read-only file system.
+    pub fn create_dataflow_dir(&self, dataflow_dir: &Path) -> Option<PathBuf> {
+        match fs::create_dir_all(dataflow_dir) {
+            Ok(()) => Some(dataflow_dir.to_owned()),
+            Err(err) => {
+                let hint = if self.log_dir.is_none() {
+                    ", set --log-dir to a writable directory"
+                } else {
+                    ""
+                };
+                tracing::warn!(
+                    "failed to create dataflow dir `{}` ({err}), so node logs and output \
+                    files are not written{hint}",
+                    dataflow_dir.display()
+                );
+                None
+            }
+        }
+    }
+}
+
+fn check_dir(
+    kind: &str,
+    flag: &str,
+    configured: Option<PathBuf>,
+    default: Option<PathBuf>,
+) -> eyre::Result<Option<PathBuf>> {
+    if let Some(dir) = configured {
+        check_writable(&dir).map_err(|err| {
+            eyre!(
+                "{kind} dir `{}` is not writable ({err}), set {flag} to a writable directory",
+                dir.display()
+            )
+        })?;
+        return Ok(Some(dir));
+    }
+    let Some(dir) = default else {
+        return Ok(None);
+    };
+    match check_writable(&dir) {
+        Ok(()) => Ok(Some(dir)),
+        Err(err) => {
+            tracing::warn!(
+                "{kind} dir `{}` is not writable ({err}), set {flag} to a writable directory; \
+                features that need it are disabled",
+                dir.display()
+            );
+            Ok(None)
+        }
+    }
+}
+
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".write-test-{}", Uuid::new_v4()));
+    fs::write(&probe, [])?;
+    fs::remove_file(&probe)
+}
+
+/// Returns the given XDG base directory, or the fallback if there is no home
+/// directory.
+fn default_dir(xdg_var: &str, home_subdir: &str, fallback: &str) -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(xdg_var).filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("dora"));
+    }
+    let home = if cfg!(windows) {
+        std::env::var_os("USERPROFILE")
+    } else {
+        std::env::var_os("HOME")
+    };
+    match home.filter(|home| !home.is_empty()) {
+        Some(home) => Some(PathBuf::from(home).join(home_subdir).join("dora")),
+        None if cfg!(windows) => None,
+        None => Some(PathBuf::from(fallback)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwritable_dirs() {
+        let file = std::env::temp_dir().join(format!("dora-paths-test-{}", Uuid::new_v4()));
+        fs::write(&file, []).unwrap();
+        let unwritable = file.join("log");
+
+        let err = DaemonPaths::open(DaemonPathsConfig {
+            log_dir: Some(unwritable.clone()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("set --log-dir"), "{err}");
+
+        // default dirs are disabled instead
+        assert_eq!(
+            check_dir("state", "--state-dir", None, Some(unwritable.clone())).unwrap(),
+            None
+        );
+
+        let paths = DaemonPaths::default();
+        let dataflow_dir = paths.dataflow_dir(&file, &DataflowId::nil(), None);
+        assert_eq!(
+            dataflow_dir,
+            file.join("out").join(DataflowId::nil().to_string())
+        );
+        assert_eq!(paths.create_dataflow_dir(&dataflow_dir), None);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn downloads_are_cached_per_url() {
+        let cache_dir = std::env::temp_dir().join("dora-cache");
+        let paths = DaemonPaths {
+            cache_dir: Some(cache_dir.clone()),
+            ..Default::default()
+        };
+        let file_name = Path::new("node");
+        let a = paths.download_path("https://example.com/a", file_name);
+        assert!(a.starts_with(cache_dir.join("downloads")));
+        assert!(a.ends_with("node"));
+        assert_eq!(a, paths.download_path("https://example.com/a", file_name));
+        assert_ne!(a, paths.download_path("https://example.com/b", file_name));
+    }
+}
@@ -22,6 +22,9 @@ pub struct PendingNodes {
     local_nodes: HashSet<NodeId>,
     /// Whether there are external nodes for this dataflow.
     external_nodes: bool,
+    /// Whether the inter-daemon transport still waits for the remote
+    /// receivers of local outputs, see [`Self::handle_transport_ready`].
+    transport_pending: bool,
 
     /// Used to synchronize node starts.
     ///
@@ -44,6 +47,7 @@ impl PendingNodes {
             machine_id,
             local_nodes: HashSet::new(),
             external_nodes: false,
+            transport_pending: false,
             waiting_subscribers: HashMap::new(),
             exited_before_subscribe: Default::default(),
             reported_init_to_coordinator: false,
@@ -58,6 +62,23 @@ impl PendingNodes {
         self.external_nodes = value;
     }
 
+    /// Delays the ready report to the coordinator until
+    /// [`Self::handle_transport_ready`] is called.
+    pub fn set_transport_pending(&mut self) {
+        self.transport_pending = true;
+    }
+
+    /// Whether the given local node did not subscribe yet or is waiting for
+    /// the other nodes.
+    pub fn is_pending(&self, node_id: &NodeId) -> bool {
+        self.local_nodes.contains(node_id) || self.waiting_subscribers.contains_key(node_id)
+    }
+
+    /// Whether all local nodes subscribed and their subscriptions were answered.
+    pub fn all_nodes_ready(&self) -> bool {
+        self.local_nodes.is_empty() && self.waiting_subscribers.is_empty()
+    }
+
     pub async fn handle_node_subscription(
         &mut self,
         node_id: NodeId,
@@ -118,6 +139,41 @@ impl PendingNodes {
         Ok(Vec::new())
     }
 
+    /// Reports this daemon as ready to the coordinator if none of its local
+    /// nodes need to subscribe, e.g. because they are all detached.
+    ///
+    /// Otherwise, the report is sent once the last local node subscribed.
+    pub async fn report_if_no_local_nodes(
+        &mut self,
+        coordinator_connection: &mut Option<TcpStream>,
+        clock: &HLC,
+    ) -> eyre::Result<()> {
+        if self.local_nodes.is_empty()
+            && self.external_nodes
+            && !self.transport_pending
+            && !self.reported_init_to_coordinator
+        {
+            self.report_nodes_ready(coordinator_connection, clock.new_timestamp())
+                .await?;
+            self.reported_init_to_coordinator = true;
+        }
+        Ok(())
+    }
+
+    /// Called once the remote receivers of the local outputs are connected.
+    ///
+    /// Reports this daemon as ready if all local nodes subscribed already.
+    pub async fn handle_transport_ready(
+        &mut self,
+        coordinator_connection: &mut Option<TcpStream>,
+        clock: &HLC,
+        cascading_errors: &mut CascadingErrorCauses,
+    ) -> eyre::Result<DataflowStatus> {
+        self.transport_pending = false;
+        self.update_dataflow_status(coordinator_connection, clock, cascading_errors)
+            .await
+    }
+
     pub async fn handle_external_all_nodes_ready(
         &mut self,
         exited_before_subscribe: Vec<NodeId>,
@@ -139,7 +195,7 @@ impl PendingNodes {
         clock: &HLC,
         cascading_errors: &mut CascadingErrorCauses,
     ) -> eyre::Result<DataflowStatus> {
-        if self.local_nodes.is_empty() {
+        if self.local_nodes.is_empty() && !self.transport_pending {
             if self.external_nodes {
                 if !self.reported_init_to_coordinator {
                     self.report_nodes_ready(coordinator_connection, clock.new_timestamp())
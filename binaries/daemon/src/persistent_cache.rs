@@ -0,0 +1,509 @@
+//! Cache of the messages of persistent outputs, kept across dataflow runs.
+//!
+//! Each entry is a file in the cache directory of the daemon, which receivers
+//! map directly, next to a JSON file with the arrow type info of the message.
+//! There is at most one entry per output: storing a message with a different
+//! content hash replaces the previous entry. When the cache exceeds its size
+//! limit, the least recently used entries are evicted.
+//!
+//! Replaced and evicted files are only deleted once no receiver accesses them
+//! anymore, i.e. once the drop tokens of the messages that were sent from
+//! them are no longer pending.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use aligned_vec::{AVec, ConstAlign};
+use dora_core::config::{DataId, NodeId};
+use dora_message::{
+    common::{DataMessage, DropToken, CACHED_FILE_PREFIX},
+    daemon_to_node::SendOutputError,
+    metadata::{ArrowTypeInfo, Metadata},
+    DataflowId,
+};
+use eyre::{bail, Context, ContextCompat};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{Daemon, OutputId};
+
+/// Maximum length of a content hash.
+const MAX_HASH_LEN: usize = 128;
+
+pub struct PersistentCache {
+    /// `None` if there is no writable cache dir.
+    dir: Option<PathBuf>,
+    max_size: u64,
+    entries: BTreeMap<(NodeId, DataId), Entry>,
+    /// Replaced or evicted entries that receivers might still access.
+    retired: Vec<Entry>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct EntryInfo {
+    node_id: NodeId,
+    output_id: DataId,
+    hash: String,
+    len: usize,
+    type_info: ArrowTypeInfo,
+}
+
+struct Entry {
+    info: EntryInfo,
+    path: PathBuf,
+    last_use: SystemTime,
+    /// Drop tokens of the messages that were sent from this entry.
+    tokens: Vec<DropToken>,
+}
+
+impl PersistentCache {
+    /// Opens the cache in the given directory, loading the entries of
+    /// previous daemon runs.
+    pub fn open(dir: Option<PathBuf>, max_size: u64) -> Self {
+        let mut cache = Self {
+            dir,
+            max_size,
+            entries: BTreeMap::new(),
+            retired: Vec::new(),
+        };
+        if let Some(dir) = &cache.dir {
+            for entry in load_entries(dir) {
+                let key = (entry.info.node_id.clone(), entry.info.output_id.clone());
+                match cache.entries.get(&key) {
+                    Some(existing) if existing.last_use >= entry.last_use => entry.remove(),
+                    _ => {
+                        if let Some(previous) = cache.entries.insert(key, entry) {
+                            previous.remove();
+                        }
+                    }
+                }
+            }
+        }
+        cache.evict(|_| false);
+        cache
+    }
+
+    /// Total size of the cached messages, including retired ones.
+    pub fn size(&self) -> u64 {
+        self.entries
+            .values()
+            .chain(&self.retired)
+            .map(|e| e.info.len as u64)
+            .sum()
+    }
+
+    /// Creates a message that is backed by the cached entry of the given
+    /// output, if its content hash matches.
+    ///
+    /// Returns the message together with its type info and drop token, which
+    /// is generated in the given namespace.
+    pub fn get(
+        &mut self,
+        node_id: &NodeId,
+        output_id: &DataId,
+        hash: &str,
+        token_namespace: Uuid,
+    ) -> Option<(DataMessage, ArrowTypeInfo, DropToken)> {
+        let entry = self
+            .entries
+            .get_mut(&(node_id.clone(), output_id.clone()))
+            .filter(|entry| entry.info.hash == hash)?;
+        entry.touch();
+        let drop_token = DropToken::generate_in(token_namespace);
+        entry.tokens.push(drop_token);
+        let message = DataMessage::SharedMemory {
+            shared_memory_id: format!("{CACHED_FILE_PREFIX}{}", entry.path.display()),
+            len: entry.info.len,
+            drop_token,
+        };
+        Some((message, entry.info.type_info.clone(), drop_token))
+    }
+
+    /// Stores a message of the given output, replacing the previous entry of
+    /// the output if its hash differs.
+    ///
+    /// Without `hash`, the SHA-256 hash of the data is used. The
+    /// `is_pending` function reports whether receivers still access the
+    /// message of the given drop token.
+    pub fn store(
+        &mut self,
+        node_id: &NodeId,
+        output_id: &DataId,
+        hash: Option<&str>,
+        type_info: &ArrowTypeInfo,
+        data: &[u8],
+        is_pending: impl Fn(&DropToken) -> bool,
+    ) -> eyre::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let hash = match hash {
+            Some(hash) => {
+                check_hash(hash)?;
+                hash.to_owned()
+            }
+            None => format!("{:x}", Sha256::digest(data)),
+        };
+        let key = (node_id.clone(), output_id.clone());
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.info.hash == hash {
+                entry.touch();
+                return Ok(());
+            }
+        }
+        if data.len() as u64 > self.max_size {
+            bail!(
+                "message of {} bytes exceeds the size limit of the persistent output cache",
+                data.len()
+            );
+        }
+
+        let info = EntryInfo {
+            node_id: node_id.clone(),
+            output_id: output_id.clone(),
+            hash,
+            len: data.len(),
+            type_info: type_info.clone(),
+        };
+        let entry_dir = dir.join(output_dir_name(node_id, output_id));
+        let path = entry_dir.join(format!("{}.bin", info.hash));
+        // a retired entry with the same content must not delete the new file
+        self.retired.retain(|entry| entry.path != path);
+        fs::create_dir_all(&entry_dir)
+            .wrap_err_with(|| format!("failed to create `{}`", entry_dir.display()))?;
+        fs::write(&path, data).wrap_err_with(|| format!("failed to write `{}`", path.display()))?;
+        let entry = Entry {
+            path,
+            info,
+            last_use: SystemTime::now(),
+            tokens: Vec::new(),
+        };
+        if let Err(err) = fs::write(entry.info_path(), serde_json::to_vec(&entry.info)?) {
+            entry.remove();
+            return Err(err).wrap_err("failed to write persistent cache entry info");
+        }
+        tracing::debug!(
+            "stored {} bytes of `{node_id}/{output_id}` in persistent output cache (hash `{}`)",
+            entry.info.len,
+            entry.info.hash
+        );
+
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.retired.push(previous);
+        }
+        self.evict(is_pending);
+        Ok(())
+    }
+
+    /// Deletes unused retired entries and evicts the least recently used
+    /// entries until the cache fits into its size limit.
+    fn evict(&mut self, is_pending: impl Fn(&DropToken) -> bool) {
+        let mut retired = std::mem::take(&mut self.retired);
+        for mut entry in retired.drain(..) {
+            if entry.release(&is_pending) {
+                entry.remove();
+            } else {
+                self.retired.push(entry);
+            }
+        }
+
+        let mut by_last_use: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_use, key.clone()))
+            .collect();
+        by_last_use.sort();
+        for (_, key) in by_last_use {
+            if self.size() <= self.max_size {
+                break;
+            }
+            if let Some(mut entry) = self.entries.remove(&key) {
+                tracing::info!(
+                    "evicting `{}/{}` from persistent output cache",
+                    key.0,
+                    key.1
+                );
+                if entry.release(&is_pending) {
+                    entry.remove();
+                } else {
+                    self.retired.push(entry);
+                }
+            }
+        }
+    }
+}
+
+impl Daemon {
+    /// Looks up the cached message of the given output, for a `SendCached`
+    /// request of its node.
+    ///
+    /// Returns `Ok(None)` if the cache has no entry with the given hash, so
+    /// that the node sends the message itself.
+    pub(crate) fn cached_output(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+        output_id: &DataId,
+        hash: &str,
+    ) -> Result<Option<(DataMessage, ArrowTypeInfo, DropToken)>, SendOutputError> {
+        let Some(dataflow) = self.running.get(&dataflow_id) else {
+            return Ok(None);
+        };
+        dataflow.check_output_declared(node_id, output_id)?;
+        let persistent = dataflow
+            .persistent_outputs
+            .contains(&OutputId(node_id.clone(), output_id.clone()));
+        if !persistent {
+            return Err(SendOutputError::OutputNotPersistent {
+                output_id: output_id.clone(),
+            });
+        }
+        Ok(self
+            .persistent_cache
+            .get(node_id, output_id, hash, self.run_id))
+    }
+
+    /// Stores the given message of a persistent output in the cache.
+    ///
+    /// Failures are only logged, as the message was delivered already.
+    pub(crate) fn store_persistent_output(
+        &mut self,
+        output_id: &OutputId,
+        metadata: &Metadata,
+        data: &[u8],
+    ) {
+        let running = &self.running;
+        if let Err(err) = self.persistent_cache.store(
+            &output_id.0,
+            &output_id.1,
+            metadata.content_hash(),
+            &metadata.type_info,
+            data,
+            |token| {
+                running
+                    .values()
+                    .any(|d| d.pending_drop_tokens.contains_key(token))
+            },
+        ) {
+            tracing::warn!(
+                "failed to store `{}/{}` in persistent output cache: {err:?}",
+                output_id.0,
+                output_id.1
+            );
+        }
+    }
+}
+
+/// Reads the first `len` bytes of a cached output file, see
+/// [`CACHED_FILE_PREFIX`].
+pub fn read_cached_output(path: &str, len: usize) -> eyre::Result<AVec<u8, ConstAlign<128>>> {
+    let data = fs::read(path).wrap_err_with(|| format!("failed to read cached output `{path}`"))?;
+    let data = data
+        .get(..len)
+        .context("cached output file is shorter than the message")?;
+    Ok(AVec::from_slice(1, data))
+}
+
+impl Entry {
+    fn info_path(&self) -> PathBuf {
+        self.path.with_extension("json")
+    }
+
+    /// Updates the last use time, also on disk for the next daemon run.
+    fn touch(&mut self) {
+        self.last_use = SystemTime::now();
+        if let Err(err) = fs::File::options()
+            .write(true)
+            .open(self.info_path())
+            .and_then(|file| file.set_modified(self.last_use))
+        {
+            tracing::debug!("failed to update last use of persistent cache entry: {err}");
+        }
+    }
+
+    /// Forgets the drop tokens that are no longer pending and returns
+    /// whether the entry is unused.
+    fn release(&mut self, is_pending: impl Fn(&DropToken) -> bool) -> bool {
+        self.tokens.retain(|token| is_pending(token));
+        self.tokens.is_empty()
+    }
+
+    fn remove(self) {
+        for path in [self.info_path(), self.path] {
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("failed to remove `{}`: {err}", path.display());
+                }
+            }
+        }
+    }
+}
+
+/// Loads the entries of the given cache dir, skipping invalid ones.
+fn load_entries(dir: &Path) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let Ok(output_dirs) = fs::read_dir(dir) else {
+        return entries;
+    };
+    for file in output_dirs
+        .flatten()
+        .filter_map(|output_dir| fs::read_dir(output_dir.path()).ok())
+        .flatten()
+        .flatten()
+    {
+        let info_path = file.path();
+        if info_path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let loaded = fs::read(&info_path)
+            .map_err(eyre::Report::from)
+            .and_then(|info| Ok(serde_json::from_slice::<EntryInfo>(&info)?));
+        let entry = loaded.ok().map(|info| Entry {
+            path: info_path.with_extension("bin"),
+            last_use: file
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            info,
+            tokens: Vec::new(),
+        });
+        match entry {
+            Some(entry)
+                if fs::metadata(&entry.path).is_ok_and(|m| m.len() == entry.info.len as u64) =>
+            {
+                entries.push(entry);
+            }
+            _ => {
+                tracing::warn!(
+                    "removing invalid persistent cache entry `{}`",
+                    info_path.display()
+                );
+                let _ = fs::remove_file(info_path.with_extension("bin"));
+                let _ = fs::remove_file(&info_path);
+            }
+        }
+    }
+    entries
+}
+
+/// Content hashes are used as file names, so they are restricted to a safe
+/// set of characters.
+fn check_hash(hash: &str) -> eyre::Result<()> {
+    if hash.is_empty()
+        || hash.len() > MAX_HASH_LEN
+        || !hash
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "invalid content hash `{hash}`: expected 1 to {MAX_HASH_LEN} ASCII letters, digits, \
+            `-` or `_`"
+        );
+    }
+    Ok(())
+}
+
+fn output_dir_name(node_id: &NodeId, output_id: &DataId) -> String {
+    let hash = format!("{:x}", Sha256::digest(format!("{node_id}/{output_id}")));
+    hash[..16].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("dora-persistent-cache-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn read(message: &DataMessage) -> Vec<u8> {
+        let DataMessage::SharedMemory {
+            shared_memory_id,
+            len,
+            ..
+        } = message
+        else {
+            panic!("expected cached file message")
+        };
+        let path = shared_memory_id.strip_prefix(CACHED_FILE_PREFIX).unwrap();
+        let data = fs::read(path).unwrap();
+        data[..*len].to_vec()
+    }
+
+    #[test]
+    fn entries_are_kept_across_runs() {
+        let dir = temp_cache_dir();
+        let node = NodeId::from("mapper".to_owned());
+        let output = DataId::from("map".to_owned());
+        let type_info = ArrowTypeInfo::byte_array(4);
+
+        let mut cache = PersistentCache::open(Some(dir.clone()), 1024);
+        assert!(cache.get(&node, &output, "v1", Uuid::nil()).is_none());
+        cache
+            .store(&node, &output, Some("v1"), &type_info, b"abcd", |_| false)
+            .unwrap();
+        let (message, cached_type, _) = cache.get(&node, &output, "v1", Uuid::nil()).unwrap();
+        assert_eq!(read(&message), b"abcd");
+        assert_eq!(cached_type, type_info);
+        drop(cache);
+
+        // the entry survives a daemon restart
+        let mut cache = PersistentCache::open(Some(dir.clone()), 1024);
+        let (message, _, token) = cache.get(&node, &output, "v1", Uuid::nil()).unwrap();
+        assert_eq!(read(&message), b"abcd");
+
+        // a different hash invalidates the entry, but the file is kept
+        // while receivers still access it
+        cache
+            .store(&node, &output, Some("v2"), &type_info, b"efgh", |t| {
+                *t == token
+            })
+            .unwrap();
+        assert!(cache.get(&node, &output, "v1", Uuid::nil()).is_none());
+        assert_eq!(read(&message), b"abcd");
+        assert_eq!(cache.size(), 8);
+        cache.evict(|_| false);
+        assert_eq!(cache.size(), 4);
+
+        assert!(cache
+            .store(&node, &output, Some("../v3"), &type_info, b"ijkl", |_| {
+                false
+            })
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let dir = temp_cache_dir();
+        let node = NodeId::from("mapper".to_owned());
+        let [a, b, c] = ["a", "b", "c"].map(|id| DataId::from(id.to_owned()));
+        let type_info = ArrowTypeInfo::byte_array(4);
+
+        let mut cache = PersistentCache::open(Some(dir.clone()), 8);
+        for output in [&a, &b] {
+            cache
+                .store(&node, output, None, &type_info, b"data", |_| false)
+                .unwrap();
+        }
+        let hash = format!("{:x}", Sha256::digest(b"data"));
+        assert!(cache.get(&node, &a, &hash, Uuid::nil()).is_some());
+
+        cache
+            .store(&node, &c, None, &type_info, b"data", |_| false)
+            .unwrap();
+        assert!(cache.get(&node, &a, &hash, Uuid::nil()).is_some());
+        assert!(cache.get(&node, &b, &hash, Uuid::nil()).is_none());
+        assert!(cache.get(&node, &c, &hash, Uuid::nil()).is_some());
+        assert_eq!(cache.size(), 8);
+
+        assert!(cache
+            .store(&node, &a, None, &type_info, &[0; 9], |_| false)
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
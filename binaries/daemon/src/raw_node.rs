@@ -0,0 +1,310 @@
+//! Support for `raw` nodes, i.e. executables that don't use the dora API.
+//!
+//! The daemon runs a small codec for each raw node, which connects to the
+//! daemon through the regular node API and translates between dora messages
+//! and the stdin/stdout of the spawned process.
+
+use crate::socket_stream_utils::{socket_stream_receive, socket_stream_send};
+use dora_core::{config::DataId, descriptor::RawFraming};
+use dora_message::daemon_to_node::NodeConfig;
+use dora_node_api::{
+    arrow::{
+        array::{Array, AsArray, StringArray},
+        datatypes::{DataType, UInt8Type},
+    },
+    DoraNode, Event, EventStream, MetadataParameters, NodeInitOptions,
+};
+use eyre::{bail, Context};
+use std::io::ErrorKind;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{ChildStdin, ChildStdout},
+    sync::{mpsc, oneshot},
+};
+
+/// Starts the codec for a raw node as a separate task.
+///
+/// Inputs are written to `stdin` until the event stream is closed, a `Stop`
+/// event is received, or the process exits (signaled through `child_exited`).
+/// If the node has an output, `stdout` is read until it is closed.
+///
+/// The returned channel is notified when the codec is finished.
+pub fn spawn_codec(
+    node_config: NodeConfig,
+    framing: RawFraming,
+    stdin: ChildStdin,
+    stdout: Option<ChildStdout>,
+    child_exited: oneshot::Receiver<()>,
+) -> oneshot::Receiver<()> {
+    let (finished_tx, finished_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let node_id = node_config.node_id.clone();
+        let result = run_codec(node_config, framing, stdin, stdout, child_exited).await;
+        if let Err(err) = result {
+            tracing::warn!("codec of raw node `{node_id}` failed: {err:?}");
+        }
+        let _ = finished_tx.send(());
+    });
+    finished_rx
+}
+
+async fn run_codec(
+    node_config: NodeConfig,
+    framing: RawFraming,
+    mut stdin: ChildStdin,
+    stdout: Option<ChildStdout>,
+    child_exited: oneshot::Receiver<()>,
+) -> eyre::Result<()> {
+    let output_id = node_config.run_config.outputs.iter().next().cloned();
+    // stdout is only read if there is an output to send the messages on
+    let stdout = stdout.filter(|_| output_id.is_some());
+    // the codec runs inside the daemon, which handles signals itself
+    let options = NodeInitOptions {
+        stop_on_signal: false,
+        ..Default::default()
+    };
+    // the blocking parts of the node API are kept off the runtime threads
+    let (node, mut events) =
+        tokio::task::spawn_blocking(move || DoraNode::init_with_options(node_config, options))
+            .await
+            .wrap_err("failed to join node init task")?
+            .wrap_err("failed to connect raw node to daemon")?;
+    let (frames_tx, frames_rx) = mpsc::channel(1);
+    let sender = tokio::task::spawn_blocking(move || match output_id {
+        Some(output_id) => send_outputs(node, output_id, frames_rx, framing),
+        None => Ok(()),
+    });
+
+    let inputs = async {
+        let result = tokio::select! {
+            result = forward_inputs(&mut events, &mut stdin, framing) => result,
+            _ = child_exited => Ok(()),
+        };
+        // closing stdin signals the end of the input to the process
+        drop(stdin);
+        result
+    };
+    let outputs = async {
+        match stdout {
+            Some(stdout) => read_outputs(BufReader::new(stdout), frames_tx, framing).await,
+            None => Ok(()),
+        }
+    };
+    let (inputs, outputs) = futures::join!(inputs, outputs);
+    let sent = sender.await.wrap_err("failed to join output task")?;
+    // dropping the event stream waits for a reply of the daemon
+    tokio::task::spawn_blocking(move || drop(events))
+        .await
+        .wrap_err("failed to join event stream drop task")?;
+    inputs.wrap_err("failed to forward inputs")?;
+    outputs.wrap_err("failed to read outputs")?;
+    sent.wrap_err("failed to send outputs")?;
+    Ok(())
+}
+
+async fn forward_inputs(
+    events: &mut EventStream,
+    stdin: &mut ChildStdin,
+    framing: RawFraming,
+) -> eyre::Result<()> {
+    while let Some(event) = events.recv_async().await {
+        match event {
+            Event::Input { id, data, .. } => {
+                let messages = match input_messages(data.as_ref()) {
+                    Ok(messages) => messages,
+                    Err(err) => {
+                        tracing::warn!("ignoring message on input `{id}`: {err}");
+                        continue;
+                    }
+                };
+                for message in messages {
+                    match write_frame(stdin, &message, framing).await {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                            tracing::debug!("raw node closed its stdin");
+                            return Ok(());
+                        }
+                        Err(err) => return Err(err).wrap_err("failed to write to stdin"),
+                    }
+                }
+            }
+            Event::Stop => break,
+            Event::Error(err) => tracing::warn!("error event for raw node: {err}"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads the messages from stdout and passes them to [`send_outputs`].
+async fn read_outputs(
+    mut stdout: impl AsyncBufRead + Unpin,
+    frames: mpsc::Sender<Vec<u8>>,
+    framing: RawFraming,
+) -> eyre::Result<()> {
+    while let Some(message) = read_frame(&mut stdout, framing)
+        .await
+        .wrap_err("failed to read from stdout")?
+    {
+        if frames.send(message).await.is_err() {
+            // sending failed, which is reported by `send_outputs`
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the messages read from stdout, blocking the current thread.
+///
+/// The node is dropped at the end, which reports its outputs as done.
+fn send_outputs(
+    mut node: DoraNode,
+    output_id: DataId,
+    mut frames: mpsc::Receiver<Vec<u8>>,
+    framing: RawFraming,
+) -> eyre::Result<()> {
+    while let Some(message) = frames.blocking_recv() {
+        let parameters = MetadataParameters::default();
+        match framing {
+            RawFraming::LengthPrefixed => {
+                node.send_output_bytes(output_id.clone(), parameters, message.len(), &message)?
+            }
+            RawFraming::Text => {
+                let line = String::from_utf8_lossy(&message);
+                node.send_output(
+                    output_id.clone(),
+                    parameters,
+                    StringArray::from(vec![line.as_ref()]),
+                )?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Splits the data of an input into the messages that are written to stdin.
+///
+/// Byte arrays are written as a single message, string and binary arrays as
+/// one message per entry.
+fn input_messages(data: &dyn Array) -> eyre::Result<Vec<Vec<u8>>> {
+    let messages = match data.data_type() {
+        DataType::Null => Vec::new(),
+        DataType::UInt8 => vec![data.as_primitive::<UInt8Type>().values().to_vec()],
+        DataType::Utf8 => collect(data.as_string::<i32>().iter().flatten()),
+        DataType::LargeUtf8 => collect(data.as_string::<i64>().iter().flatten()),
+        DataType::Binary => collect(data.as_binary::<i32>().iter().flatten()),
+        DataType::LargeBinary => collect(data.as_binary::<i64>().iter().flatten()),
+        other => bail!("unsupported data type `{other}` (expected bytes or strings)"),
+    };
+    Ok(messages)
+}
+
+fn collect<T: AsRef<[u8]>>(values: impl Iterator<Item = T>) -> Vec<Vec<u8>> {
+    values.map(|v| v.as_ref().to_vec()).collect()
+}
+
+async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &[u8],
+    framing: RawFraming,
+) -> std::io::Result<()> {
+    match framing {
+        RawFraming::LengthPrefixed => socket_stream_send(writer, message).await,
+        RawFraming::Text => {
+            writer.write_all(message).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await
+        }
+    }
+}
+
+/// Reads the next message, returns `None` when the end of the stream is reached.
+async fn read_frame(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    framing: RawFraming,
+) -> std::io::Result<Option<Vec<u8>>> {
+    match framing {
+        RawFraming::LengthPrefixed => match socket_stream_receive(reader).await {
+            Ok(message) => Ok(Some(message)),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        },
+        RawFraming::Text => {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line).await? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with(b"\n") {
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+            }
+            Ok(Some(line))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_node_api::arrow::array::UInt8Array;
+
+    async fn roundtrip(messages: &[&[u8]], framing: RawFraming) -> Vec<Vec<u8>> {
+        let mut buffer = Vec::new();
+        for message in messages {
+            write_frame(&mut buffer, message, framing).await.unwrap();
+        }
+        let mut reader = buffer.as_slice();
+        let mut received = Vec::new();
+        while let Some(message) = read_frame(&mut reader, framing).await.unwrap() {
+            received.push(message);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn length_prefixed_framing() {
+        let messages: &[&[u8]] = &[b"foo", b"", b"bar\nbaz"];
+        assert_eq!(
+            roundtrip(messages, RawFraming::LengthPrefixed).await,
+            messages
+        );
+    }
+
+    #[tokio::test]
+    async fn text_framing() {
+        let messages: &[&[u8]] = &[b"foo", b"bar baz"];
+        assert_eq!(roundtrip(messages, RawFraming::Text).await, messages);
+
+        // last line might not be terminated
+        let mut reader: &[u8] = b"a\r\nb";
+        assert_eq!(
+            read_frame(&mut reader, RawFraming::Text).await.unwrap(),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(
+            read_frame(&mut reader, RawFraming::Text).await.unwrap(),
+            Some(b"b".to_vec())
+        );
+        assert_eq!(
+            read_frame(&mut reader, RawFraming::Text).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn input_data_types() {
+        let bytes = UInt8Array::from(vec![1, 2, 3]);
+        assert_eq!(input_messages(&bytes).unwrap(), [vec![1, 2, 3]]);
+
+        let strings = StringArray::from(vec!["a", "bc"]);
+        assert_eq!(
+            input_messages(&strings).unwrap(),
+            [b"a".to_vec(), b"bc".to_vec()]
+        );
+
+        let floats = dora_node_api::arrow::array::Float32Array::from(vec![1.0]);
+        assert!(input_messages(&floats).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Reassembly of remote outputs that other daemons sent in chunks, see
+//! [`InterDaemonEvent::OutputChunk`].
+//!
+//! The chunks of a message are copied into a shared memory region, which is
+//! delivered to the local receivers once the last chunk arrived. Messages
+//! whose chunks don't arrive in order, e.g. because a chunk got lost on a
+//! broken connection, are discarded.
+//!
+//! [`InterDaemonEvent::OutputChunk`]: dora_message::daemon_to_daemon::InterDaemonEvent::OutputChunk
+
+use dora_core::config::{DataId, NodeId};
+use dora_message::{daemon_to_daemon::OutputChunk, diagnostics::EntryStats, metadata::Metadata};
+use eyre::{bail, Context};
+use shared_memory_server::{Shmem, ShmemConf};
+use std::{collections::HashMap, time::Instant};
+use uuid::Uuid;
+
+/// The remote outputs of a dataflow that are being reassembled.
+#[derive(Default)]
+pub struct PartialOutputs {
+    partial: HashMap<Uuid, PartialOutput>,
+    /// Messages that were discarded before all of their chunks arrived.
+    discarded: u64,
+}
+
+struct PartialOutput {
+    node_id: NodeId,
+    output_id: DataId,
+    metadata: Metadata,
+    region: ReassemblyRegion,
+    len: usize,
+    /// Number of bytes that were copied into the region.
+    received: usize,
+    next_index: u32,
+    total: u32,
+    started: Instant,
+}
+
+/// A message whose chunks all arrived.
+pub struct ReassembledOutput {
+    pub node_id: NodeId,
+    pub output_id: DataId,
+    pub metadata: Metadata,
+    pub region: ReassemblyRegion,
+    pub len: usize,
+}
+
+impl PartialOutputs {
+    /// Copies the given chunk into the region of its message.
+    ///
+    /// Returns the message once its last chunk arrived. Chunks of discarded
+    /// messages are ignored.
+    pub fn add_chunk(&mut self, chunk: OutputChunk) -> eyre::Result<Option<ReassembledOutput>> {
+        let OutputChunk {
+            dataflow_id: _,
+            node_id,
+            output_id,
+            message_id,
+            index,
+            total,
+            len,
+            metadata,
+            data,
+        } = chunk;
+
+        let mut partial = match (self.partial.remove(&message_id), metadata) {
+            (None, Some(metadata)) if index == 0 => {
+                let len = usize::try_from(len).context("chunked message is too large")?;
+                PartialOutput {
+                    node_id,
+                    output_id,
+                    metadata,
+                    region: ReassemblyRegion::allocate(len)?,
+                    len,
+                    received: 0,
+                    next_index: 0,
+                    total,
+                    started: Instant::now(),
+                }
+            }
+            (Some(partial), None) if index == partial.next_index && total == partial.total => {
+                partial
+            }
+            (Some(_), _) => {
+                self.discarded += 1;
+                bail!("discarding remote output `{node_id}/{output_id}` because chunk {index} of {total} arrived out of order");
+            }
+            // the message was discarded already
+            (None, _) => return Ok(None),
+        };
+
+        let end = partial.received + data.len();
+        if end > partial.len {
+            self.discarded += 1;
+            bail!(
+                "discarding remote output `{node_id}/{output_id}` because its chunks exceed \
+                the announced length of {} bytes",
+                partial.len
+            );
+        }
+        // SAFETY: the region is not shared with receivers before all chunks arrived
+        unsafe { partial.region.0.as_slice_mut() }
+        [partial.received..end].copy_from_slice(&data);
+        partial.received = end;
+        partial.next_index += 1;
+
+        if partial.next_index < partial.total {
+            self.partial.insert(message_id, partial);
+            return Ok(None);
+        }
+        if partial.received != partial.len {
+            self.discarded += 1;
+            bail!(
+                "discarding remote output `{node_id}/{output_id}` because its chunks are shorter \
+                than the announced length of {} bytes",
+                partial.len
+            );
+        }
+        Ok(Some(ReassembledOutput {
+            node_id: partial.node_id,
+            output_id: partial.output_id,
+            metadata: partial.metadata,
+            region: partial.region,
+            len: partial.len,
+        }))
+    }
+
+    /// Discards all partial messages, e.g. because the dataflow stopped.
+    ///
+    /// Returns the number of discarded messages.
+    pub fn discard_all(&mut self) -> usize {
+        let count = self.partial.len();
+        self.partial.clear();
+        self.discarded += count as u64;
+        count
+    }
+
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub fn stats(&self, now: Instant) -> EntryStats {
+        let mut stats = EntryStats::default();
+        for partial in self.partial.values() {
+            stats.add(partial.len as u64, now.duration_since(partial.started));
+        }
+        stats
+    }
+}
+
+/// Shared memory region of a reassembled message.
+pub struct ReassemblyRegion(Box<Shmem>);
+
+impl ReassemblyRegion {
+    fn allocate(len: usize) -> eyre::Result<Self> {
+        let memory = ShmemConf::new()
+            .size(len)
+            .writable(true)
+            .create()
+            .wrap_err("failed to allocate shared memory for remote output")?;
+        Ok(Self(Box::new(memory)))
+    }
+
+    pub fn os_id(&self) -> &str {
+        self.0.get_os_id()
+    }
+
+    #[cfg(test)]
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+// the daemon only writes to the region while it is not shared with receivers
+unsafe impl Send for ReassemblyRegion {}
+unsafe impl Sync for ReassemblyRegion {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::uhlc::HLC;
+    use dora_message::metadata::ArrowTypeInfo;
+
+    fn split(message_id: Uuid, data: &[u8], chunk_size: usize) -> Vec<OutputChunk> {
+        let clock = HLC::default();
+        let metadata = Metadata::new(clock.new_timestamp(), ArrowTypeInfo::byte_array(data.len()));
+        let total = data.len().div_ceil(chunk_size) as u32;
+        data.chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| OutputChunk {
+                dataflow_id: Uuid::nil(),
+                node_id: NodeId::from("camera".to_owned()),
+                output_id: DataId::from("image".to_owned()),
+                message_id,
+                index: index as u32,
+                total,
+                len: data.len() as u64,
+                metadata: (index == 0).then(|| metadata.clone()),
+                data: chunk.to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_are_reassembled_into_shared_memory() {
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let mut partial = PartialOutputs::default();
+        let mut chunks = split(Uuid::new_v4(), &data, 4096).into_iter();
+
+        assert!(partial.add_chunk(chunks.next().unwrap()).unwrap().is_none());
+        assert!(partial.add_chunk(chunks.next().unwrap()).unwrap().is_none());
+        assert_eq!(partial.stats(Instant::now()).count, 1);
+        let output = partial.add_chunk(chunks.next().unwrap()).unwrap().unwrap();
+
+        assert_eq!(output.len, data.len());
+        assert_eq!(
+            &unsafe { output.region.as_slice() }[..output.len],
+            &data[..]
+        );
+        assert_eq!(partial.stats(Instant::now()).count, 0);
+        assert_eq!(partial.discarded(), 0);
+    }
+
+    #[test]
+    fn messages_with_missing_chunks_are_discarded() {
+        let data = vec![7; 10_000];
+        let mut partial = PartialOutputs::default();
+        let mut chunks = split(Uuid::new_v4(), &data, 4096).into_iter();
+
+        partial.add_chunk(chunks.next().unwrap()).unwrap();
+        // the second chunk got lost
+        chunks.next();
+        assert!(partial.add_chunk(chunks.next().unwrap()).is_err());
+        assert_eq!(partial.discarded(), 1);
+        assert_eq!(partial.stats(Instant::now()).count, 0);
+
+        // the remaining chunks of a stopped dataflow are counted too
+        for chunk in split(Uuid::new_v4(), &data, 4096).into_iter().take(2) {
+            partial.add_chunk(chunk).unwrap();
+        }
+        assert_eq!(partial.discard_all(), 1);
+        assert_eq!(partial.discarded(), 2);
+    }
+}
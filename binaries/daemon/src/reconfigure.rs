@@ -0,0 +1,195 @@
+//! Changes to the input options of running nodes, see
+//! `DaemonCoordinatorEvent::Reconfigure`.
+
+use crate::{
+    adaptive_rate::AdaptiveRate, input_timeouts::InputTimeout, latest_input::LatestSlot, Daemon,
+    RunningDataflow,
+};
+use dora_core::{
+    config::{Adaptive, DataId, Input},
+    descriptor::{check_input_options, CoreNodeKind, ResolvedNode},
+};
+use dora_message::{
+    common::DropToken,
+    reconfigure::{InputChange, InputChangeResult, InputSettings, QueuePolicy},
+    DataflowId,
+};
+use eyre::{bail, ContextCompat};
+use std::time::Instant;
+
+impl Daemon {
+    /// Applies the given input changes to the local nodes of a dataflow, see
+    /// `DaemonCoordinatorEvent::Reconfigure`.
+    ///
+    /// Each change is applied on its own, so failed changes are reported in
+    /// their result without affecting the others.
+    pub(crate) async fn reconfigure(
+        &mut self,
+        dataflow_id: DataflowId,
+        changes: Vec<InputChange>,
+    ) -> eyre::Result<Vec<InputChangeResult>> {
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        let now = Instant::now();
+        let mut results = Vec::new();
+        for change in changes {
+            let result = match dataflow.reconfigure_input(&change, now) {
+                Ok((settings, released_token)) => {
+                    if let Some(token) = released_token {
+                        dataflow
+                            .release_drop_token(token, &change.node_id, &self.clock)
+                            .await?;
+                    }
+                    tracing::info!(
+                        "reconfigured input `{}/{}` of dataflow `{dataflow_id}`: {settings}",
+                        change.node_id,
+                        change.input_id
+                    );
+                    Ok(settings)
+                }
+                Err(err) => Err(format!("{err:?}")),
+            };
+            results.push(InputChangeResult {
+                node_id: change.node_id,
+                input_id: change.input_id,
+                result,
+            });
+        }
+        let events_tx = self.dataflow_events.sender(dataflow_id);
+        dataflow.start_input_timeout_checks(&events_tx, &self.clock);
+        dataflow.buffer_reload_events(&self.clock).await?;
+        Ok(results)
+    }
+}
+
+impl RunningDataflow {
+    /// Applies the given change to an input of a local node.
+    ///
+    /// The change is also applied to the resolved node, so that it is kept
+    /// when the node is reloaded. Returns the effective settings of the
+    /// input, together with the drop token of a pending `latest` message
+    /// that could not be handed to the node and needs to be released.
+    pub(crate) fn reconfigure_input(
+        &mut self,
+        change: &InputChange,
+        now: Instant,
+    ) -> eyre::Result<(InputSettings, Option<DropToken>)> {
+        let InputChange {
+            node_id,
+            input_id,
+            queue_size,
+            policy,
+            priority,
+            adaptive_min_rate,
+            timeout,
+        } = change;
+        let Some(running) = self.running_nodes.get(node_id) else {
+            bail!("node `{node_id}` is not running on this machine");
+        };
+        let input = self
+            .resolved_nodes
+            .iter_mut()
+            .find(|node| &node.id == node_id)
+            .and_then(|node| node_input_mut(node, input_id))
+            .wrap_err_with(|| format!("node `{node_id}` has no input `{input_id}`"))?;
+
+        let mut updated = input.clone();
+        if let Some(queue_size) = *queue_size {
+            if queue_size == 0 {
+                bail!("queue size must be at least 1");
+            }
+            updated.queue_size = Some(queue_size);
+        }
+        if let Some(policy) = policy {
+            updated.latest = *policy == QueuePolicy::Latest;
+        }
+        if let Some(priority) = *priority {
+            updated.priority = priority;
+        }
+        if let Some(min_rate) = *adaptive_min_rate {
+            updated.adaptive = Some(Adaptive { min_rate });
+        }
+        if let Some(timeout) = *timeout {
+            updated.timeout = Some(timeout);
+        }
+        check_input_options(&updated, &format!("{node_id}/{input_id}"))?;
+
+        let id = (node_id.clone(), input_id.clone());
+        running.input_queues.set(
+            input_id.clone(),
+            updated.queue_size_or_default(),
+            updated.priority,
+        );
+        let state = self.inputs.get_or_insert(id);
+        let mut released_token = None;
+        if updated.latest && !input.latest {
+            state.latest = Some(LatestSlot::default());
+        } else if !updated.latest {
+            if let Some(mut slot) = state.latest.take() {
+                // deliver the pending message as a regular input instead
+                let token = slot.pending_drop_token();
+                let sent = match (slot.take(), self.subscribe_channels.get(node_id)) {
+                    (Some(event), Some(channel)) => channel.send_timestamped(event).is_ok(),
+                    _ => false,
+                };
+                if !sent {
+                    released_token = token;
+                }
+            }
+        }
+        if let Some(adaptive) = &updated.adaptive {
+            match &mut state.adaptive {
+                Some(rate) => rate.set_min_rate(adaptive.min_rate),
+                None => state.adaptive = Some(AdaptiveRate::new(adaptive, now)),
+            }
+        }
+        if let Some(timeout) = updated.timeout {
+            // closed inputs are no longer watched
+            let open = self
+                .open_inputs
+                .get(node_id)
+                .is_some_and(|inputs| inputs.contains(input_id));
+            if open {
+                let started = self.subscribe_channels.contains_key(node_id).then_some(now);
+                match &mut state.timeout {
+                    Some(watched) => watched.set_timeout(timeout),
+                    None => state.timeout = Some(InputTimeout::new(timeout, started)),
+                }
+            }
+        }
+
+        let settings = InputSettings {
+            queue_size: updated.queue_size_or_default(),
+            policy: if updated.latest {
+                QueuePolicy::Latest
+            } else {
+                QueuePolicy::DropOldest
+            },
+            priority: updated.priority,
+            adaptive_min_rate: updated.adaptive.as_ref().map(|adaptive| adaptive.min_rate),
+            timeout: updated.timeout,
+        };
+        *input = updated;
+        state.reconfigured = Some(settings.clone());
+        Ok((settings, released_token))
+    }
+}
+
+/// The config of a single input of the given node, with the same IDs as in
+/// [`node_inputs`](crate::node_inputs).
+fn node_input_mut<'a>(node: &'a mut ResolvedNode, input_id: &DataId) -> Option<&'a mut Input> {
+    match &mut node.kind {
+        CoreNodeKind::Custom(n) => n.run_config.inputs.get_mut(input_id),
+        CoreNodeKind::Runtime(n) => {
+            let (operator_id, input_id) = input_id.split_once('/')?;
+            n.operators
+                .iter_mut()
+                .find(|operator| operator.id.as_ref() == operator_id)?
+                .config
+                .inputs
+                .get_mut(input_id)
+        }
+    }
+}
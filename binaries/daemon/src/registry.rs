@@ -0,0 +1,452 @@
+//! Registry of the node processes that were spawned by the daemon.
+//!
+//! The registry is persisted to a file, so that a restarted daemon can find
+//! the nodes of a previous run that are still alive, e.g. after a crash.
+//! These orphaned nodes are terminated on startup, or kept running until
+//! they exit by themselves if the daemon was started with `--adopt-orphans`.
+//! Their dataflow IDs can't be used for new dataflows while they are alive.
+//!
+//! The registry file is locked while a daemon uses it, so that two daemons
+//! never share the same registry.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use dora_core::config::NodeId;
+use dora_message::{daemon_to_node::DaemonCommunication, DataflowId};
+use eyre::{bail, Context};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use shared_memory_server::ShmemConf;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessStatus, System};
+
+/// Time that terminated orphans get to exit before their shared memory is
+/// cleaned up.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct NodeRegistryConfig {
+    /// File in which the registry is stored.
+    ///
+    /// Defaults to a file in the state dir of the daemon.
+    pub path: Option<PathBuf>,
+    /// Keep still running nodes of previous daemon runs alive instead of
+    /// terminating them.
+    pub adopt_orphans: bool,
+}
+
+/// Error when spawning a dataflow with an ID that is already in use.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DuplicateDataflowError {
+    /// A dataflow with this ID is running on the daemon.
+    Running { dataflow_id: DataflowId },
+    /// Nodes of a dataflow with this ID were spawned by a previous daemon
+    /// run and are still alive.
+    Orphaned {
+        dataflow_id: DataflowId,
+        nodes: Vec<(NodeId, u32)>,
+    },
+}
+
+impl fmt::Display for DuplicateDataflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DuplicateDataflowError::Running { dataflow_id } => {
+                write!(f, "dataflow `{dataflow_id}` is already running")
+            }
+            DuplicateDataflowError::Orphaned { dataflow_id, nodes } => {
+                let nodes: Vec<_> = nodes
+                    .iter()
+                    .map(|(node_id, pid)| format!("`{node_id}` (pid {pid})"))
+                    .collect();
+                write!(
+                    f,
+                    "nodes of dataflow `{dataflow_id}` from a previous daemon run are still \
+                    running: {}",
+                    nodes.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DuplicateDataflowError {}
+
+pub struct NodeRegistry {
+    path: PathBuf,
+    /// Exclusively locked while the registry is open.
+    _lock: File,
+    /// Nodes spawned by this daemon run.
+    nodes: Vec<RegisteredNode>,
+    /// Still running nodes of previous daemon runs.
+    orphans: Vec<RegisteredNode>,
+    system: System,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    nodes: Vec<RegisteredNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RegisteredNode {
+    dataflow_id: DataflowId,
+    node_id: NodeId,
+    #[serde(flatten)]
+    process: ProcessInfo,
+    /// Shared memory regions that the daemon created for the node.
+    #[serde(default)]
+    shared_memory: Vec<String>,
+}
+
+/// Identifies a process, also if its PID is reused after it exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ProcessInfo {
+    pid: u32,
+    /// In seconds since the Unix epoch.
+    start_time: u64,
+}
+
+impl ProcessInfo {
+    fn of(system: &mut System, pid: u32) -> Option<Self> {
+        let sys_pid = Pid::from(pid as usize);
+        if !system.refresh_process_specifics(sys_pid, ProcessRefreshKind::new()) {
+            return None;
+        }
+        let process = system.process(sys_pid)?;
+        if process.status() == ProcessStatus::Zombie {
+            return None;
+        }
+        Some(Self {
+            pid,
+            start_time: process.start_time(),
+        })
+    }
+
+    fn is_alive(&self, system: &mut System) -> bool {
+        Self::of(system, self.pid).as_ref() == Some(self)
+    }
+}
+
+impl NodeRegistry {
+    /// Opens the registry and handles the still running nodes of the
+    /// previous daemon run.
+    ///
+    /// Blocks until terminated orphans exited.
+    pub fn open(path: PathBuf, adopt_orphans: bool) -> eyre::Result<Self> {
+        let lock = lock(&path)?;
+        let mut system = System::new();
+
+        let previous = match fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid node registry `{}`: {err}", path.display());
+                RegistryFile::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => RegistryFile::default(),
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to read node registry `{}`", path.display()))
+            }
+        };
+        let mut orphans = Vec::new();
+        for node in previous.nodes {
+            if !node.process.is_alive(&mut system) {
+                continue;
+            }
+            let RegisteredNode {
+                dataflow_id,
+                node_id,
+                process,
+                shared_memory,
+            } = &node;
+            let pid = process.pid;
+            if adopt_orphans {
+                tracing::warn!(
+                    "node `{dataflow_id}/{node_id}` (pid {pid}) of a previous daemon run is \
+                    still running, keeping it until it exits"
+                );
+                orphans.push(node);
+                continue;
+            }
+            match terminate(&mut system, process) {
+                Ok(()) => {
+                    tracing::info!(
+                        "terminated node `{dataflow_id}/{node_id}` (pid {pid}) of a previous \
+                        daemon run"
+                    );
+                    remove_shared_memory(shared_memory);
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "failed to terminate node `{dataflow_id}/{node_id}` (pid {pid}) of a \
+                        previous daemon run: {err:?}"
+                    );
+                    orphans.push(node);
+                }
+            }
+        }
+
+        let registry = Self {
+            path,
+            _lock: lock,
+            nodes: Vec::new(),
+            orphans,
+            system,
+        };
+        registry.save()?;
+        Ok(registry)
+    }
+
+    pub fn add(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        pid: Option<u32>,
+        communication: &DaemonCommunication,
+    ) {
+        let Some(process) = pid.and_then(|pid| ProcessInfo::of(&mut self.system, pid)) else {
+            return;
+        };
+        let shared_memory = match communication {
+            DaemonCommunication::Shmem {
+                daemon_control_region_id,
+                daemon_drop_region_id,
+                daemon_events_region_id,
+                daemon_events_close_region_id,
+            } => vec![
+                daemon_control_region_id.clone(),
+                daemon_drop_region_id.clone(),
+                daemon_events_region_id.clone(),
+                daemon_events_close_region_id.clone(),
+            ],
+            _ => Vec::new(),
+        };
+        self.nodes.push(RegisteredNode {
+            dataflow_id,
+            node_id,
+            process,
+            shared_memory,
+        });
+        self.save_or_warn();
+    }
+
+    pub fn remove(&mut self, dataflow_id: DataflowId, node_id: &NodeId) {
+        let len = self.nodes.len();
+        self.nodes
+            .retain(|n| !(n.dataflow_id == dataflow_id && &n.node_id == node_id));
+        if self.nodes.len() != len {
+            self.save_or_warn();
+        }
+    }
+
+    /// Nodes of this daemon run that were not removed yet, with their PID
+    /// and the time since their process started.
+    pub fn spawned_nodes(&self) -> impl Iterator<Item = (DataflowId, &NodeId, u32, Duration)> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.nodes.iter().map(move |node| {
+            let age = Duration::from_secs(now.saturating_sub(node.process.start_time));
+            (node.dataflow_id, &node.node_id, node.process.pid, age)
+        })
+    }
+
+    /// Fails if nodes of a previous daemon run with the given dataflow ID
+    /// are still running.
+    pub fn check_dataflow_id(
+        &mut self,
+        dataflow_id: DataflowId,
+    ) -> Result<(), DuplicateDataflowError> {
+        self.remove_exited_orphans();
+        let nodes: Vec<_> = self
+            .orphans
+            .iter()
+            .filter(|n| n.dataflow_id == dataflow_id)
+            .map(|n| (n.node_id.clone(), n.process.pid))
+            .collect();
+        if nodes.is_empty() {
+            Ok(())
+        } else {
+            Err(DuplicateDataflowError::Orphaned { dataflow_id, nodes })
+        }
+    }
+
+    pub fn remove_exited_orphans(&mut self) {
+        let len = self.orphans.len();
+        let system = &mut self.system;
+        self.orphans.retain(|n| n.process.is_alive(system));
+        if self.orphans.len() != len {
+            self.save_or_warn();
+        }
+    }
+
+    fn save_or_warn(&self) {
+        if let Err(err) = self.save() {
+            tracing::warn!("{err:?}");
+        }
+    }
+
+    fn save(&self) -> eyre::Result<()> {
+        let file = RegistryFile {
+            nodes: self.orphans.iter().chain(&self.nodes).cloned().collect(),
+        };
+        write_atomically(&self.path, &serde_json::to_vec_pretty(&file)?)
+            .wrap_err_with(|| format!("failed to write node registry `{}`", self.path.display()))
+    }
+}
+
+/// Locks the registry at the given path, failing if another daemon holds
+/// the lock.
+///
+/// The lock is released when the returned file is closed, which also
+/// happens if the daemon crashes.
+fn lock(path: &Path) -> eyre::Result<File> {
+    let lock_path = path.with_extension("lock");
+    if let Some(parent) = lock_path.parent() {
+        fs::create_dir_all(parent)
+            .wrap_err_with(|| format!("failed to create `{}`", parent.display()))?;
+    }
+    let file = File::create(&lock_path)
+        .wrap_err_with(|| format!("failed to create `{}`", lock_path.display()))?;
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(file),
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => bail!(
+            "node registry `{}` is used by another daemon",
+            path.display()
+        ),
+        Err(err) => {
+            Err(err).wrap_err_with(|| format!("failed to lock node registry `{}`", path.display()))
+        }
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+/// Kills the given process and waits until it exited.
+fn terminate(system: &mut System, process: &ProcessInfo) -> eyre::Result<()> {
+    let killed = system
+        .process(Pid::from(process.pid as usize))
+        .is_some_and(|p| p.kill());
+    if !killed {
+        bail!("failed to send kill signal");
+    }
+    let start = Instant::now();
+    while process.is_alive(system) {
+        if start.elapsed() > TERMINATE_TIMEOUT {
+            bail!("process did not exit after kill signal");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Removes shared memory regions that a previous daemon run created.
+///
+/// Regions that were removed already are skipped.
+fn remove_shared_memory(os_ids: &[String]) {
+    for os_id in os_ids {
+        match ShmemConf::new().os_id(os_id).open() {
+            Ok(mut memory) => {
+                // the region is removed when the owner drops it
+                memory.set_owner(true);
+                tracing::debug!("removing shared memory `{os_id}`");
+            }
+            Err(err) => tracing::debug!("skipping shared memory `{os_id}`: {err}"),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn registry_path() -> PathBuf {
+        std::env::temp_dir().join(format!("dora-registry-test-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    fn write_registry(path: &Path, nodes: &[(u32, Vec<String>)]) {
+        let mut system = System::new();
+        let file = RegistryFile {
+            nodes: nodes
+                .iter()
+                .map(|(pid, shared_memory)| RegisteredNode {
+                    dataflow_id: DataflowId::nil(),
+                    node_id: NodeId::from("camera".to_owned()),
+                    process: ProcessInfo::of(&mut system, *pid).unwrap(),
+                    shared_memory: shared_memory.clone(),
+                })
+                .collect(),
+        };
+        fs::write(path, serde_json::to_vec(&file).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn orphans_are_terminated_or_adopted() {
+        let path = registry_path();
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("60")
+            .spawn()
+            .unwrap();
+        let recorded = ShmemConf::new().size(64).create().unwrap();
+        let unrelated = ShmemConf::new().size(64).create().unwrap();
+        write_registry(
+            &path,
+            &[(child.id(), vec![recorded.get_os_id().to_owned()])],
+        );
+        let mut registry = NodeRegistry::open(path.clone(), false).unwrap();
+        assert!(child.try_wait().unwrap().is_some());
+        assert_eq!(registry.check_dataflow_id(DataflowId::nil()), Ok(()));
+        let exists = |os_id: &str| ShmemConf::new().os_id(os_id).open().is_ok();
+        assert!(!exists(recorded.get_os_id()));
+        assert!(exists(unrelated.get_os_id()));
+        drop(registry);
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("60")
+            .spawn()
+            .unwrap();
+        write_registry(&path, &[(child.id(), Vec::new())]);
+        let mut registry = NodeRegistry::open(path.clone(), true).unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+        assert_eq!(
+            registry.check_dataflow_id(DataflowId::nil()),
+            Err(DuplicateDataflowError::Orphaned {
+                dataflow_id: DataflowId::nil(),
+                nodes: vec![(NodeId::from("camera".to_owned()), child.id())],
+            })
+        );
+        assert!(registry.check_dataflow_id(DataflowId::new_v4()).is_ok());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(registry.check_dataflow_id(DataflowId::nil()).is_ok());
+        drop(registry);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+
+    #[test]
+    fn registry_of_running_daemon_is_not_opened() {
+        let path = registry_path();
+        let registry = NodeRegistry::open(path.clone(), false).unwrap();
+        assert!(NodeRegistry::open(path.clone(), false).is_err());
+        drop(registry);
+        assert!(NodeRegistry::open(path.clone(), false).is_ok());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+}
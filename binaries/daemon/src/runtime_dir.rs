@@ -0,0 +1,122 @@
+//! Runtime directories of the running dataflows.
+//!
+//! Each dataflow gets a runtime directory at `<state_dir>/dataflows/<uuid>`
+//! on every machine that it runs on, see
+//! [`DaemonPaths::dataflow_runtime_dir`](crate::paths::DaemonPaths::dataflow_runtime_dir).
+//! The nodes find it in the `DORA_DATAFLOW_DIR` environment variable, and
+//! `TMPDIR` points to its `tmp` subdirectory, so that temporary files,
+//! pipes, and caches of the nodes don't pile up in the system temp dir.
+//!
+//! The directory is removed when the dataflow finishes successfully. It is
+//! kept for post-mortem inspection if a node failed, or if the dataflow sets
+//! `keep_artifacts: true`.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Delays before the retries of a failed removal.
+///
+/// Removing the directory fails if a process still holds one of its files
+/// open, e.g. a child process of a node that was killed.
+const REMOVE_RETRY_DELAYS: [Duration; 4] = [
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+];
+
+/// Creates the given runtime dir and its `tmp` subdirectory.
+///
+/// Failures are only logged, nodes then don't get the runtime dir.
+pub fn create(dir: &Path) {
+    if let Err(err) = std::fs::create_dir_all(tmp_dir(dir)) {
+        tracing::warn!(
+            "failed to create dataflow runtime dir `{}` ({err}), so nodes use the \
+            system temp dir",
+            dir.display()
+        );
+    }
+}
+
+/// Directory to which `TMPDIR` points.
+pub fn tmp_dir(dir: &Path) -> PathBuf {
+    dir.join("tmp")
+}
+
+/// Removes the runtime dir of a finished dataflow, or keeps it and logs its
+/// path.
+pub fn finish(dir: PathBuf, failed: bool, keep_artifacts: bool) {
+    if !dir.exists() {
+        return;
+    }
+    if failed {
+        tracing::warn!(
+            "dataflow failed, runtime dir `{}` is kept for post-mortem",
+            dir.display()
+        );
+    } else if keep_artifacts {
+        tracing::info!("keeping dataflow runtime dir `{}`", dir.display());
+    } else {
+        remove(dir);
+    }
+}
+
+/// Removes the given runtime dir, retrying in the background if that fails.
+fn remove(dir: PathBuf) {
+    match remove_dir(&dir) {
+        Ok(()) => return,
+        Err(err) => tracing::debug!(
+            "failed to remove dataflow runtime dir `{}` ({err}), retrying",
+            dir.display()
+        ),
+    }
+    tokio::spawn(async move {
+        let mut result = Ok(());
+        for delay in REMOVE_RETRY_DELAYS {
+            tokio::time::sleep(delay).await;
+            result = remove_dir(&dir);
+            if result.is_ok() {
+                return;
+            }
+        }
+        if let Err(err) = result {
+            tracing::warn!(
+                "failed to remove dataflow runtime dir `{}`: {err}",
+                dir.display()
+            );
+        }
+    });
+}
+
+fn remove_dir(dir: &Path) -> io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn runtime_dirs_are_kept_or_removed() {
+        let dir = std::env::temp_dir().join(format!("dora-runtime-dir-test-{}", Uuid::new_v4()));
+        create(&dir);
+        std::fs::write(tmp_dir(&dir).join("scratch"), [1, 2, 3]).unwrap();
+
+        finish(dir.clone(), true, false);
+        assert!(tmp_dir(&dir).join("scratch").exists());
+        finish(dir.clone(), false, true);
+        assert!(dir.exists());
+
+        finish(dir.clone(), false, false);
+        assert!(!dir.exists());
+        // already removed
+        finish(dir, false, false);
+    }
+}
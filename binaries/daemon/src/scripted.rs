@@ -0,0 +1,584 @@
+//! Deterministic scheduling of the daemon event loop, for tests.
+//!
+//! The main loop of the daemon handles the events of nodes, timers, and the
+//! coordinator in the order in which their merged streams happen to be
+//! polled. Races such as a `SendOut` that arrives before the receiver
+//! subscribed, or a drop report that arrives after the dataflow was removed,
+//! therefore depend on timing and are hard to reproduce.
+//!
+//! A [`ScriptedDaemon`] polls no streams. It handles the steps of an
+//! [`EventScript`] one by one, in exactly the given order. Events that the
+//! daemon generates itself, e.g. timer ticks, stay in the channels of their
+//! dataflows until a [`Step::Internal`] or [`Step::DrainInternal`] handles
+//! them. Dataflows are added without spawning their nodes, see
+//! [`ScriptedDaemon::add_dataflow`], so that the script stands in for them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use dora_core::{config::NodeId, descriptor::Descriptor, uhlc::HLC};
+use dora_message::{
+    common::Timestamped,
+    daemon_to_daemon::InterDaemonTransport,
+    daemon_to_node::{DaemonCommunication, NodeConfig},
+    diagnostics::DropTokenReports,
+    DataflowId,
+};
+use eyre::eyre;
+use futures::FutureExt;
+use uuid::Uuid;
+
+use crate::{
+    builder::DaemonConfig, spawn, Daemon, DaemonNodeEvent, Event, PreparedDataflow, RunStatus,
+    RunningDataflow, RunningNode,
+};
+
+/// Time that a [`Step::Internal`] waits for the next internal event.
+const INTERNAL_EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A step of an [`EventScript`].
+pub enum Step {
+    /// Handles the given event, as if it was received from a node, the
+    /// coordinator, or another daemon.
+    Event(Event),
+    /// Waits for the next event that the daemon generated itself, e.g. a
+    /// timer tick, and handles it. Fails if none arrives.
+    Internal,
+    /// Handles the events that the daemon generated itself and that are
+    /// pending already, without waiting for more.
+    DrainInternal,
+}
+
+/// The steps that a [`ScriptedDaemon`] handles, in order.
+#[derive(Default)]
+pub struct EventScript {
+    steps: VecDeque<Step>,
+}
+
+impl EventScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, step: Step) -> &mut Self {
+        self.steps.push_back(step);
+        self
+    }
+
+    pub fn event(&mut self, event: Event) -> &mut Self {
+        self.push(Step::Event(event))
+    }
+
+    /// Adds an event of the given node of the given dataflow.
+    pub fn node_event(
+        &mut self,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        event: DaemonNodeEvent,
+    ) -> &mut Self {
+        self.event(Event::Node {
+            dataflow_id,
+            node_id,
+            event,
+        })
+    }
+
+    pub fn internal(&mut self) -> &mut Self {
+        self.push(Step::Internal)
+    }
+
+    pub fn drain_internal(&mut self) -> &mut Self {
+        self.push(Step::DrainInternal)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+/// A daemon without coordinator that handles the steps of an
+/// [`EventScript`] instead of polling its event streams.
+pub struct ScriptedDaemon {
+    daemon: Daemon,
+    script: EventScript,
+    exited: bool,
+}
+
+impl ScriptedDaemon {
+    pub fn new(script: EventScript) -> Self {
+        let daemon = Daemon::new(DaemonConfig::new(Arc::new(HLC::default())), None, None);
+        Self {
+            daemon,
+            script,
+            exited: false,
+        }
+    }
+
+    /// The remaining steps, e.g. to add steps that depend on the outcome of
+    /// the previous ones.
+    pub fn script(&mut self) -> &mut EventScript {
+        &mut self.script
+    }
+
+    /// Sets up the given dataflow like for a spawn request, but marks its
+    /// nodes as running without spawning them.
+    ///
+    /// The nodes are checked like for a spawn, so their `path` needs to be
+    /// `shell`, `dynamic`, or an existing executable. They subscribe, send
+    /// outputs, and exit through the events of the script.
+    pub fn add_dataflow(&mut self, descriptor: Descriptor) -> eyre::Result<DataflowId> {
+        let dataflow_id = Uuid::new_v4();
+        let nodes = descriptor.resolve_aliases_and_set_defaults()?;
+        let working_dir = std::env::temp_dir();
+        let daemon = &mut self.daemon;
+        let PreparedDataflow { mut dataflow, .. } = daemon.prepare_dataflow(
+            dataflow_id,
+            &working_dir,
+            nodes,
+            &descriptor,
+            InterDaemonTransport::Tcp,
+            None,
+            BTreeMap::new(),
+        )?;
+
+        let layers = std::mem::take(&mut dataflow.start_layers.pending);
+        dataflow.start_layers.started = dataflow.start_layers.total;
+        for (node, _) in layers.into_iter().flatten() {
+            let node_config = NodeConfig {
+                dataflow_id,
+                node_id: node.id.clone(),
+                run_config: node.kind.run_config(),
+                // the node has no connection, its requests are part of the script
+                daemon_communication: DaemonCommunication::Tcp {
+                    socket_addr: ([127, 0, 0, 1], 0).into(),
+                },
+                dataflow_descriptor: descriptor.clone(),
+                dynamic: node.kind.dynamic(),
+                dataflow_instance: None,
+                dataflow_params: BTreeMap::new(),
+                drop_token_namespace: daemon.run_id,
+                daemon_version: Some(dora_message::current_crate_version()),
+            };
+            let running_node = RunningNode {
+                pid: None,
+                input_queues: spawn::input_queues(&node),
+                node_config,
+            };
+            dataflow.running_nodes.insert(node.id, running_node);
+        }
+        daemon.working_dir.insert(dataflow_id, working_dir);
+        daemon.running.insert(dataflow_id, dataflow);
+        Ok(dataflow_id)
+    }
+
+    /// Handles the next step of the script.
+    ///
+    /// Returns `false` if the script is finished or if the daemon exited.
+    pub async fn step(&mut self) -> eyre::Result<bool> {
+        if self.exited {
+            return Ok(false);
+        }
+        let Some(step) = self.script.steps.pop_front() else {
+            return Ok(false);
+        };
+        match step {
+            Step::Event(event) => {
+                let timestamp = self.daemon.clock.new_timestamp();
+                self.handle(Timestamped {
+                    inner: event,
+                    timestamp,
+                })
+                .await?;
+            }
+            Step::Internal => {
+                let next = tokio::time::timeout(
+                    INTERNAL_EVENT_TIMEOUT,
+                    self.daemon.dataflow_events.next(),
+                )
+                .await
+                .map_err(|_| eyre!("no internal event within {INTERNAL_EVENT_TIMEOUT:?}"))?;
+                let (_, event) = next.ok_or_else(|| eyre!("no running dataflow sends events"))?;
+                self.handle(event).await?;
+            }
+            Step::DrainInternal => {
+                while let Some(Some((_, event))) = self.daemon.dataflow_events.next().now_or_never()
+                {
+                    self.handle(event).await?;
+                    if self.exited {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(!self.exited)
+    }
+
+    /// Handles the steps of the script until it is finished or the daemon
+    /// exited.
+    pub async fn run(&mut self) -> eyre::Result<()> {
+        while self.step().await? {}
+        Ok(())
+    }
+
+    pub fn inspect(&self) -> Inspector<'_> {
+        Inspector {
+            daemon: &self.daemon,
+        }
+    }
+
+    async fn handle(&mut self, event: Timestamped<Event>) -> eyre::Result<()> {
+        match self.daemon.handle_event(event).await? {
+            RunStatus::Continue => {}
+            RunStatus::Exit => self.exited = true,
+        }
+        Ok(())
+    }
+}
+
+/// Read-only view of the state of a [`ScriptedDaemon`] between two steps.
+pub struct Inspector<'a> {
+    daemon: &'a Daemon,
+}
+
+impl<'a> Inspector<'a> {
+    /// The dataflow with the given ID, if it wasn't removed yet.
+    pub fn dataflow(&self, dataflow_id: &DataflowId) -> Option<DataflowInspector<'a>> {
+        self.daemon
+            .running
+            .get(dataflow_id)
+            .map(|dataflow| DataflowInspector { dataflow })
+    }
+
+    /// Drop reports of tokens that were not pending, of the dataflows that
+    /// were removed already.
+    pub fn removed_drop_token_reports(&self) -> DropTokenReports {
+        self.daemon.drop_token_reports
+    }
+}
+
+pub struct DataflowInspector<'a> {
+    dataflow: &'a RunningDataflow,
+}
+
+impl DataflowInspector<'_> {
+    /// Nodes that didn't exit yet.
+    pub fn running_nodes(&self) -> BTreeSet<NodeId> {
+        self.dataflow.running_nodes.keys().cloned().collect()
+    }
+
+    /// Nodes whose event channel is open.
+    pub fn subscribed_nodes(&self) -> BTreeSet<NodeId> {
+        self.dataflow.subscribe_channels.keys().cloned().collect()
+    }
+
+    /// Number of messages that wait for drop reports of their receivers.
+    pub fn pending_drop_tokens(&self) -> usize {
+        self.dataflow.pending_drop_tokens.len()
+    }
+
+    /// Drop reports of tokens that were not pending.
+    pub fn drop_token_reports(&self) -> DropTokenReports {
+        self.dataflow.drop_token_reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::config::DataId;
+    use dora_message::{
+        common::NodeExitStatus,
+        daemon_to_node::{DaemonReply, NodeDropEvent, NodeEvent},
+        metadata::{ArrowTypeInfo, Metadata},
+        node_to_daemon::{DataMessage, DropToken, EventInterest},
+    };
+    use shared_memory_server::{Shmem, ShmemConf};
+    use tokio::sync::{
+        mpsc::{self, UnboundedReceiver},
+        oneshot,
+    };
+
+    use crate::DoraEvent;
+
+    const DATAFLOW: &str = r#"
+nodes:
+  - id: camera
+    path: shell
+    args: camera
+    outputs:
+      - image
+  - id: plot
+    path: shell
+    args: plot
+    inputs:
+      image: camera/image
+"#;
+
+    fn node(id: &str) -> NodeId {
+        NodeId::from(id.to_owned())
+    }
+
+    fn scripted_dataflow(yaml: &str) -> (ScriptedDaemon, DataflowId) {
+        let mut daemon = ScriptedDaemon::new(EventScript::new());
+        let descriptor = Descriptor::parse(yaml.as_bytes().to_vec()).unwrap();
+        let dataflow_id = daemon.add_dataflow(descriptor).unwrap();
+        (daemon, dataflow_id)
+    }
+
+    /// Channels of a scripted node.
+    struct Subscription {
+        events: UnboundedReceiver<Timestamped<NodeEvent>>,
+        drops: UnboundedReceiver<Timestamped<NodeDropEvent>>,
+        reply: oneshot::Receiver<DaemonReply>,
+    }
+
+    impl Subscription {
+        fn inputs(&mut self) -> Vec<DataId> {
+            let mut inputs = Vec::new();
+            while let Ok(event) = self.events.try_recv() {
+                if let NodeEvent::Input { id, .. } = event.inner {
+                    inputs.push(id);
+                }
+            }
+            inputs
+        }
+
+        fn dropped_tokens(&mut self) -> Vec<DropToken> {
+            let mut tokens = Vec::new();
+            while let Ok(event) = self.drops.try_recv() {
+                match event.inner {
+                    NodeDropEvent::OutputDropped { drop_token } => tokens.push(drop_token),
+                }
+            }
+            tokens
+        }
+    }
+
+    /// Adds the steps with which the given node subscribes to its events and
+    /// drop events.
+    fn subscribe(script: &mut EventScript, dataflow_id: DataflowId, node_id: &str) -> Subscription {
+        let (drop_sender, drops) = mpsc::unbounded_channel();
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let (reply_sender, reply) = oneshot::channel();
+        script
+            .node_event(
+                dataflow_id,
+                node(node_id),
+                DaemonNodeEvent::SubscribeDrop {
+                    event_sender: drop_sender,
+                    reply_sender: oneshot::channel().0,
+                },
+            )
+            .node_event(
+                dataflow_id,
+                node(node_id),
+                DaemonNodeEvent::Subscribe {
+                    event_sender,
+                    interest: EventInterest::ALL,
+                    reply_sender,
+                },
+            );
+        Subscription {
+            events,
+            drops,
+            reply,
+        }
+    }
+
+    /// Adds a step that sends the given shared memory region on the `image`
+    /// output of `camera`, and returns the drop token of the message.
+    fn send_image(script: &mut EventScript, dataflow_id: DataflowId, memory: &Shmem) -> DropToken {
+        let drop_token = DropToken::generate();
+        let timestamp = HLC::default().new_timestamp();
+        script.node_event(
+            dataflow_id,
+            node("camera"),
+            DaemonNodeEvent::SendOut {
+                output_id: DataId::from("image".to_owned()),
+                metadata: Metadata::new(timestamp, ArrowTypeInfo::empty()),
+                data: Some(DataMessage::SharedMemory {
+                    shared_memory_id: memory.get_os_id().to_owned(),
+                    len: memory.len(),
+                    drop_token,
+                }),
+                reply_sender: oneshot::channel().0,
+            },
+        );
+        drop_token
+    }
+
+    fn report_drop(
+        script: &mut EventScript,
+        dataflow_id: DataflowId,
+        node_id: &str,
+        tokens: Vec<DropToken>,
+    ) {
+        script.node_event(
+            dataflow_id,
+            node(node_id),
+            DaemonNodeEvent::ReportDrop { tokens },
+        );
+    }
+
+    fn exit(script: &mut EventScript, dataflow_id: DataflowId, node_id: &str) {
+        script.event(Event::Dora(DoraEvent::SpawnedNodeResult {
+            dataflow_id,
+            node_id: node(node_id),
+            exit_status: NodeExitStatus::Success,
+        }));
+    }
+
+    #[tokio::test]
+    async fn send_out_before_subscribe_is_not_delivered() {
+        let (mut daemon, dataflow_id) = scripted_dataflow(DATAFLOW);
+        let memory = ShmemConf::new().size(64).create().unwrap();
+        let mut camera = subscribe(daemon.script(), dataflow_id, "camera");
+        let drop_token = send_image(daemon.script(), dataflow_id, &memory);
+        let mut plot = subscribe(daemon.script(), dataflow_id, "plot");
+        daemon.run().await.unwrap();
+
+        // the message had no receivers, so it was freed right away
+        let dataflow = daemon.inspect().dataflow(&dataflow_id).unwrap();
+        assert_eq!(dataflow.pending_drop_tokens(), 0);
+        assert_eq!(camera.dropped_tokens(), [drop_token]);
+        assert!(plot.inputs().is_empty());
+        // both nodes subscribed, so the dataflow started
+        assert!(matches!(
+            plot.reply.try_recv(),
+            Ok(DaemonReply::Result(Ok(())))
+        ));
+        assert!(matches!(
+            camera.reply.try_recv(),
+            Ok(DaemonReply::Result(Ok(())))
+        ));
+    }
+
+    #[tokio::test]
+    async fn late_subscriber_learns_about_closed_outputs() {
+        let (mut daemon, dataflow_id) = scripted_dataflow(DATAFLOW);
+        let _camera = subscribe(daemon.script(), dataflow_id, "camera");
+        daemon.script().node_event(
+            dataflow_id,
+            node("camera"),
+            DaemonNodeEvent::OutputsDone {
+                reply_sender: oneshot::channel().0,
+            },
+        );
+        let mut plot = subscribe(daemon.script(), dataflow_id, "plot");
+        daemon.run().await.unwrap();
+
+        match plot.events.try_recv().unwrap().inner {
+            NodeEvent::InputClosed { id, .. } => assert_eq!(id.as_str(), "image"),
+            other => panic!("unexpected event {other:?}"),
+        }
+        assert!(matches!(
+            plot.events.try_recv().unwrap().inner,
+            NodeEvent::Stop
+        ));
+    }
+
+    #[tokio::test]
+    async fn exit_of_receiver_releases_pending_delivery() {
+        let (mut daemon, dataflow_id) = scripted_dataflow(DATAFLOW);
+        let memory = ShmemConf::new().size(64).create().unwrap();
+        let mut camera = subscribe(daemon.script(), dataflow_id, "camera");
+        let mut plot = subscribe(daemon.script(), dataflow_id, "plot");
+        let drop_token = send_image(daemon.script(), dataflow_id, &memory);
+        daemon.run().await.unwrap();
+        assert_eq!(plot.inputs(), [DataId::from("image".to_owned())]);
+        let dataflow = daemon.inspect().dataflow(&dataflow_id).unwrap();
+        assert_eq!(dataflow.pending_drop_tokens(), 1);
+        assert!(camera.dropped_tokens().is_empty());
+
+        // the receiver exits before it reports the message as dropped
+        exit(daemon.script(), dataflow_id, "plot");
+        daemon.run().await.unwrap();
+        let dataflow = daemon.inspect().dataflow(&dataflow_id).unwrap();
+        assert_eq!(dataflow.running_nodes(), [node("camera")].into());
+        assert_eq!(dataflow.pending_drop_tokens(), 0);
+        assert_eq!(camera.dropped_tokens(), [drop_token]);
+
+        // the report of the exiting node arrives afterwards
+        report_drop(daemon.script(), dataflow_id, "plot", vec![drop_token]);
+        daemon.run().await.unwrap();
+        let dataflow = daemon.inspect().dataflow(&dataflow_id).unwrap();
+        assert_eq!(dataflow.drop_token_reports().late, 1);
+        assert_eq!(dataflow.drop_token_reports().unknown, 0);
+        assert!(camera.dropped_tokens().is_empty());
+    }
+
+    #[tokio::test]
+    async fn duplicate_drop_reports_are_late() {
+        let (mut daemon, dataflow_id) = scripted_dataflow(DATAFLOW);
+        let memory = ShmemConf::new().size(64).create().unwrap();
+        let mut camera = subscribe(daemon.script(), dataflow_id, "camera");
+        let _plot = subscribe(daemon.script(), dataflow_id, "plot");
+        let drop_token = send_image(daemon.script(), dataflow_id, &memory);
+        report_drop(daemon.script(), dataflow_id, "plot", vec![drop_token]);
+        report_drop(daemon.script(), dataflow_id, "plot", vec![drop_token]);
+        daemon.run().await.unwrap();
+
+        let dataflow = daemon.inspect().dataflow(&dataflow_id).unwrap();
+        assert_eq!(dataflow.pending_drop_tokens(), 0);
+        assert_eq!(dataflow.drop_token_reports().late, 1);
+        assert_eq!(dataflow.drop_token_reports().unknown, 0);
+        // the owner is informed only once
+        assert_eq!(camera.dropped_tokens(), [drop_token]);
+    }
+
+    #[tokio::test]
+    async fn drop_reports_after_dataflow_removal_are_counted() {
+        let (mut daemon, dataflow_id) = scripted_dataflow(DATAFLOW);
+        let memory = ShmemConf::new().size(64).create().unwrap();
+        let _camera = subscribe(daemon.script(), dataflow_id, "camera");
+        let _plot = subscribe(daemon.script(), dataflow_id, "plot");
+        let drop_token = send_image(daemon.script(), dataflow_id, &memory);
+        exit(daemon.script(), dataflow_id, "camera");
+        exit(daemon.script(), dataflow_id, "plot");
+        daemon.run().await.unwrap();
+        assert!(daemon.inspect().dataflow(&dataflow_id).is_none());
+
+        // reports of nodes that are still exiting don't fail the daemon
+        report_drop(
+            daemon.script(),
+            dataflow_id,
+            "plot",
+            vec![drop_token, DropToken::generate()],
+        );
+        let mut late = subscribe(daemon.script(), dataflow_id, "plot");
+        daemon.run().await.unwrap();
+        let reports = daemon.inspect().removed_drop_token_reports();
+        assert_eq!(reports.late, 1);
+        assert_eq!(reports.unknown, 1);
+        assert!(matches!(
+            late.reply.try_recv(),
+            Ok(DaemonReply::Result(Err(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn internal_events_are_handled_when_scripted() {
+        let (mut daemon, dataflow_id) = scripted_dataflow(
+            r#"
+nodes:
+  - id: plot
+    path: shell
+    args: plot
+    inputs:
+      tick: dora/timer/millis/10
+"#,
+        );
+        let mut plot = subscribe(daemon.script(), dataflow_id, "plot");
+        daemon.run().await.unwrap();
+
+        // the timer is running, but its ticks wait for the script
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(plot.inputs().is_empty());
+        daemon.script().internal();
+        daemon.run().await.unwrap();
+        assert_eq!(plot.inputs(), [DataId::from("tick".to_owned())]);
+    }
+}
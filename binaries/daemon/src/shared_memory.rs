@@ -0,0 +1,207 @@
+//! Bookkeeping of the shared memory regions that are in flight.
+//!
+//! A region is in flight from the time its output is sent until all
+//! receivers dropped it. The daemon tracks the total size of these regions
+//! and its high-water mark, per dataflow and across all dataflows, e.g. to
+//! find out how large `/dev/shm` needs to be for a dataflow.
+//!
+//! With [`SharedMemoryUsage::tracking_regions`], the daemon also remembers
+//! the regions that it created, so that regions that were never unlinked
+//! can be reported when it exits.
+
+use dora_message::{daemon_to_node::DaemonCommunication, DataflowId};
+use shared_memory_server::ShmemConf;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// Shared memory usage across all dataflows of the daemon.
+#[derive(Debug, Default)]
+pub struct SharedMemoryUsage {
+    in_flight: AtomicU64,
+    peak: AtomicU64,
+    /// Regions created by the daemon, `None` if they are not tracked.
+    created: Option<Mutex<Vec<CreatedRegion>>>,
+}
+
+/// A shared memory region that the daemon created for a dataflow.
+#[derive(Debug, Clone)]
+pub struct CreatedRegion {
+    pub os_id: String,
+    pub dataflow_id: DataflowId,
+    pub created: Instant,
+}
+
+impl SharedMemoryUsage {
+    /// Usage that also keeps track of the regions that are passed to
+    /// [`Self::track_region`].
+    pub fn tracking_regions() -> Self {
+        Self {
+            created: Some(Mutex::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Remembers a region that the daemon created, if regions are tracked.
+    pub fn track_region(&self, dataflow_id: DataflowId, os_id: &str) {
+        if let Some(created) = &self.created {
+            created.lock().unwrap().push(CreatedRegion {
+                os_id: os_id.to_owned(),
+                dataflow_id,
+                created: Instant::now(),
+            });
+        }
+    }
+
+    /// Remembers the regions of the shared memory listener of a node.
+    pub fn track_listener_regions(
+        &self,
+        dataflow_id: DataflowId,
+        communication: &DaemonCommunication,
+    ) {
+        if let DaemonCommunication::Shmem {
+            daemon_control_region_id,
+            daemon_drop_region_id,
+            daemon_events_region_id,
+            daemon_events_close_region_id,
+        } = communication
+        {
+            for os_id in [
+                daemon_control_region_id,
+                daemon_drop_region_id,
+                daemon_events_region_id,
+                daemon_events_close_region_id,
+            ] {
+                self.track_region(dataflow_id, os_id);
+            }
+        }
+    }
+
+    /// The tracked regions that still exist, i.e. that were not unlinked.
+    pub fn remaining_regions(&self) -> Vec<CreatedRegion> {
+        let Some(created) = &self.created else {
+            return Vec::new();
+        };
+        let mut created = created.lock().unwrap();
+        created.retain(|region| ShmemConf::new().os_id(&region.os_id).open().is_ok());
+        created.clone()
+    }
+
+    /// Total size of the regions that are in flight, in bytes.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Highest value of [`Self::in_flight`] since the daemon started.
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, len: u64) {
+        let in_flight = self.in_flight.fetch_add(len, Ordering::Relaxed) + len;
+        self.peak.fetch_max(in_flight, Ordering::Relaxed);
+    }
+
+    fn sub(&self, len: u64) {
+        self.in_flight.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+/// Shared memory usage of a single dataflow.
+///
+/// All changes are also applied to the daemon-wide [`SharedMemoryUsage`].
+/// The regions that are still in flight when the dataflow is dropped are
+/// subtracted from the daemon-wide usage.
+#[derive(Debug, Default)]
+pub struct DataflowSharedMemory {
+    in_flight: u64,
+    peak: u64,
+    daemon: Arc<SharedMemoryUsage>,
+}
+
+impl DataflowSharedMemory {
+    pub fn new(daemon: Arc<SharedMemoryUsage>) -> Self {
+        Self {
+            in_flight: 0,
+            peak: 0,
+            daemon,
+        }
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight
+    }
+
+    pub fn peak(&self) -> u64 {
+        self.peak
+    }
+
+    /// Records that a region of the given size was sent.
+    pub fn add(&mut self, len: usize) {
+        let len = len as u64;
+        self.in_flight += len;
+        self.peak = self.peak.max(self.in_flight);
+        self.daemon.add(len);
+    }
+
+    /// Records that a region of the given size was dropped by all receivers.
+    pub fn sub(&mut self, len: usize) {
+        let len = (len as u64).min(self.in_flight);
+        self.in_flight -= len;
+        self.daemon.sub(len);
+    }
+}
+
+impl Drop for DataflowSharedMemory {
+    fn drop(&mut self) {
+        self.daemon.sub(self.in_flight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daemon_usage_spans_dataflows() {
+        let daemon = Arc::new(SharedMemoryUsage::default());
+        let mut a = DataflowSharedMemory::new(daemon.clone());
+        let mut b = DataflowSharedMemory::new(daemon.clone());
+
+        a.add(100);
+        b.add(50);
+        a.sub(100);
+        b.add(20);
+        assert_eq!((a.in_flight(), a.peak()), (0, 100));
+        assert_eq!((b.in_flight(), b.peak()), (70, 70));
+        assert_eq!((daemon.in_flight(), daemon.peak()), (70, 150));
+
+        // regions of finished dataflows are no longer in flight
+        drop(b);
+        assert_eq!((daemon.in_flight(), daemon.peak()), (0, 150));
+    }
+
+    #[test]
+    fn unlinked_regions_are_not_remaining() {
+        let dataflow_id = DataflowId::new_v4();
+        let untracked = SharedMemoryUsage::default();
+        let tracking = SharedMemoryUsage::tracking_regions();
+        let region = ShmemConf::new().size(64).create().unwrap();
+        untracked.track_region(dataflow_id, region.get_os_id());
+        tracking.track_region(dataflow_id, region.get_os_id());
+
+        assert!(untracked.remaining_regions().is_empty());
+        let remaining = tracking.remaining_regions();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].os_id, region.get_os_id());
+        assert_eq!(remaining[0].dataflow_id, dataflow_id);
+
+        // the owner unlinks the region when it is dropped
+        drop(region);
+        assert!(tracking.remaining_regions().is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+//! Report of the resources that are left over when the daemon exits.
+//!
+//! With [`DaemonBuilder::strict_shutdown`](crate::DaemonBuilder::strict_shutdown),
+//! the daemon checks on a normal exit that all dataflows were torn down, that
+//! all spawned nodes were removed from the node registry, and that all
+//! shared memory regions that it created were unlinked. Leftovers make the
+//! daemon fail with a [`LeakReport`], so that bugs in the teardown paths are
+//! noticed in tests.
+
+use dora_core::config::NodeId;
+use dora_message::{common::DropToken, DataflowId};
+use std::{fmt, time::Duration};
+
+/// A resource that should have been released before the daemon exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Leak {
+    /// The dataflow is still in the list of running dataflows.
+    Dataflow {
+        dataflow_id: DataflowId,
+        age: Duration,
+        running_nodes: Vec<NodeId>,
+    },
+    /// A message of a still running dataflow that was not dropped by all
+    /// of its receivers.
+    DropToken {
+        dataflow_id: DataflowId,
+        token: DropToken,
+        owner: NodeId,
+        pending_nodes: Vec<NodeId>,
+        age: Duration,
+    },
+    /// A node that is still listed in the node registry.
+    RegisteredNode {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        pid: u32,
+        age: Duration,
+    },
+    /// A shared memory region created by the daemon that was not unlinked.
+    SharedMemory {
+        dataflow_id: DataflowId,
+        os_id: String,
+        age: Duration,
+    },
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Leak::Dataflow {
+                dataflow_id,
+                age,
+                running_nodes,
+            } => {
+                write!(
+                    f,
+                    "dataflow `{dataflow_id}` (age {age:.1?}) is still running"
+                )?;
+                if !running_nodes.is_empty() {
+                    let nodes: Vec<_> = running_nodes.iter().map(|n| n.to_string()).collect();
+                    write!(f, " with nodes {}", nodes.join(", "))?;
+                }
+                Ok(())
+            }
+            Leak::DropToken {
+                dataflow_id,
+                token,
+                owner,
+                pending_nodes,
+                age,
+            } => {
+                let nodes: Vec<_> = pending_nodes.iter().map(|n| n.to_string()).collect();
+                write!(
+                    f,
+                    "drop token {token:?} of node `{dataflow_id}/{owner}` (age {age:.1?}) is \
+                    still pending on nodes {}",
+                    nodes.join(", ")
+                )
+            }
+            Leak::RegisteredNode {
+                dataflow_id,
+                node_id,
+                pid,
+                age,
+            } => write!(
+                f,
+                "node `{dataflow_id}/{node_id}` (pid {pid}, age {age:.1?}) is still in the \
+                node registry"
+            ),
+            Leak::SharedMemory {
+                dataflow_id,
+                os_id,
+                age,
+            } => write!(
+                f,
+                "shared memory region `{os_id}` of dataflow `{dataflow_id}` (age {age:.1?}) \
+                was not unlinked"
+            ),
+        }
+    }
+}
+
+/// The resources that were left over when the daemon exited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    pub leaks: Vec<Leak>,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.leaks.is_empty()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "daemon exited with {} leftover resource(s):",
+            self.leaks.len()
+        )?;
+        for leak in &self.leaks {
+            write!(f, "\n  - {leak}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LeakReport {}
@@ -0,0 +1,177 @@
+//! Timers that are driven by an external clock, e.g. the simulation time of
+//! a simulator.
+//!
+//! Instead of waiting for the wall clock, the timers of a dataflow with an
+//! `external` clock advance whenever the clock source sends a new time. All
+//! ticks whose deadline was passed are fired at once, in time order, and are
+//! stamped with their deadline instead of the time at which they are
+//! delivered. This way, the timer inputs follow the simulation, no matter
+//! whether it runs faster or slower than real time.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use aligned_vec::{AVec, ConstAlign};
+use dora_message::metadata::Metadata;
+use dora_node_api::{arrow::array::make_array, ArrowData, RawData};
+use eyre::Context;
+
+/// Maximum number of ticks that a single timer fires on one clock update.
+///
+/// Protects the daemon against clock sources that jump far ahead, e.g. when
+/// a simulation is reset to a later start time. Missed ticks beyond this
+/// limit are skipped.
+const MAX_CATCH_UP_TICKS: u64 = 1000;
+
+#[derive(Debug, Default)]
+pub struct SimTimers {
+    /// Most recent time of the clock source, in nanoseconds.
+    now: Option<u64>,
+    /// Deadline of the next tick of each timer, in nanoseconds.
+    next_ticks: BTreeMap<Duration, u64>,
+}
+
+impl SimTimers {
+    /// Advances the clock to the given time (in nanoseconds) and returns the
+    /// ticks that are due, sorted by their deadline.
+    ///
+    /// Like the wall-clock timers, a timer fires its first tick right away.
+    /// Times that are not after the previous time are ignored, so timers
+    /// don't fire while the clock is paused or after it jumped backwards.
+    pub fn advance(
+        &mut self,
+        intervals: impl IntoIterator<Item = Duration>,
+        now: u64,
+    ) -> Vec<(Duration, u64)> {
+        match self.now {
+            Some(previous) if now <= previous => {
+                if now < previous {
+                    tracing::debug!(
+                        "ignoring clock update to {now}ns, which is before {previous}ns"
+                    );
+                }
+                return Vec::new();
+            }
+            _ => self.now = Some(now),
+        }
+
+        let mut ticks = Vec::new();
+        for interval in intervals {
+            let step = u64::try_from(interval.as_nanos())
+                .unwrap_or(u64::MAX)
+                .max(1);
+            let next = self.next_ticks.entry(interval).or_insert(now);
+            let mut fired = 0;
+            while *next <= now && fired < MAX_CATCH_UP_TICKS {
+                ticks.push((interval, *next));
+                *next = next.saturating_add(step);
+                fired += 1;
+            }
+            if *next <= now {
+                let skipped = (now - *next) / step + 1;
+                tracing::warn!(
+                    "clock jumped ahead, skipping {skipped} ticks of `dora/timer` \
+                    with interval {interval:?}"
+                );
+                *next = next.saturating_add(skipped.saturating_mul(step));
+            }
+        }
+        ticks.sort_by_key(|&(interval, time)| (time, interval));
+        ticks
+    }
+}
+
+/// Reads the time from a message of the clock source.
+///
+/// The message needs to contain a single `u64` value, in nanoseconds.
+pub fn parse_time(
+    metadata: &Metadata,
+    data: Option<&AVec<u8, ConstAlign<128>>>,
+) -> eyre::Result<u64> {
+    let raw = match data {
+        Some(data) => RawData::Vec(data.clone()),
+        None => RawData::Empty,
+    };
+    let array = raw
+        .into_arrow_array(&metadata.type_info)
+        .wrap_err("failed to read clock message as arrow array")?;
+    u64::try_from(&ArrowData(make_array(array)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn count(ticks: &[(Duration, u64)], interval: Duration) -> usize {
+        ticks.iter().filter(|(i, _)| *i == interval).count()
+    }
+
+    #[test]
+    fn ticks_follow_scripted_clock() {
+        let fast = Duration::from_millis(10);
+        let slow = Duration::from_millis(25);
+        let mut timers = SimTimers::default();
+
+        // first update fires all timers right away
+        let ticks = timers.advance([fast, slow], 1000 * MS);
+        assert_eq!(ticks, vec![(fast, 1000 * MS), (slow, 1000 * MS)]);
+
+        // paused clock fires nothing
+        assert!(timers.advance([fast, slow], 1000 * MS).is_empty());
+
+        // deadlines that are exactly reached fire
+        let ticks = timers.advance([fast, slow], 1010 * MS);
+        assert_eq!(ticks, vec![(fast, 1010 * MS)]);
+
+        // forward jump catches up on all missed ticks, in time order
+        let ticks = timers.advance([fast, slow], 1055 * MS);
+        assert_eq!(count(&ticks, fast), 4);
+        assert_eq!(count(&ticks, slow), 2);
+        let times: Vec<_> = ticks.iter().map(|(_, time)| *time).collect();
+        assert_eq!(
+            times,
+            [1020, 1025, 1030, 1040, 1050, 1050]
+                .map(|t| t * MS)
+                .to_vec()
+        );
+
+        // backward jump is ignored and doesn't reset the deadlines
+        assert!(timers.advance([fast, slow], 500 * MS).is_empty());
+        let ticks = timers.advance([fast, slow], 1059 * MS);
+        assert!(ticks.is_empty());
+        let ticks = timers.advance([fast, slow], 1060 * MS);
+        assert_eq!(ticks, vec![(fast, 1060 * MS)]);
+    }
+
+    #[test]
+    fn parse_u64_time() {
+        use dora_node_api::{
+            arrow::array::Array,
+            arrow_utils::{copy_array_into_sample, required_data_size},
+            uhlc, IntoArrow,
+        };
+
+        let array = (1_500 * MS).into_arrow().to_data();
+        let mut data = AVec::__from_elem(128, 0, required_data_size(&array));
+        let type_info = copy_array_into_sample(&mut data, &array);
+        let metadata = Metadata::new(uhlc::HLC::default().new_timestamp(), type_info);
+        assert_eq!(parse_time(&metadata, Some(&data)).unwrap(), 1_500 * MS);
+
+        assert!(parse_time(&metadata, None).is_err());
+    }
+
+    #[test]
+    fn catch_up_is_limited() {
+        let interval = Duration::from_millis(1);
+        let mut timers = SimTimers::default();
+        timers.advance([interval], 0);
+
+        let ticks = timers.advance([interval], 5000 * MS);
+        assert_eq!(ticks.len() as u64, MAX_CATCH_UP_TICKS);
+
+        // skipped ticks are not fired later
+        let ticks = timers.advance([interval], 5001 * MS);
+        assert_eq!(ticks, vec![(interval, 5001 * MS)]);
+    }
+}
@@ -1,5 +1,11 @@
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+/// Number of bytes that are allocated up front for receiving a message.
+///
+/// The length prefix is sent by the peer, so larger buffers are only grown
+/// as the data actually arrives.
+const MAX_PREALLOCATED_LEN: u64 = 64 * 1024;
+
 pub async fn socket_stream_send(
     connection: &mut (impl AsyncWrite + Unpin),
     message: &[u8],
@@ -13,13 +19,37 @@ pub async fn socket_stream_send(
 
 pub async fn socket_stream_receive(
     connection: &mut (impl AsyncRead + Unpin),
+) -> std::io::Result<Vec<u8>> {
+    socket_stream_receive_limited(connection, u64::MAX).await
+}
+
+/// Receives a message of at most `max_len` bytes.
+///
+/// Fails with [`InvalidData`][std::io::ErrorKind::InvalidData] if the length
+/// prefix exceeds the limit. The connection should be closed in this case
+/// since the remaining data can't be interpreted anymore.
+pub async fn socket_stream_receive_limited(
+    connection: &mut (impl AsyncRead + Unpin),
+    max_len: u64,
 ) -> std::io::Result<Vec<u8>> {
     let reply_len = {
         let mut raw = [0; 8];
         connection.read_exact(&mut raw).await?;
-        u64::from_le_bytes(raw) as usize
+        u64::from_le_bytes(raw)
     };
-    let mut reply = vec![0; reply_len];
-    connection.read_exact(&mut reply).await?;
+    if reply_len > max_len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("message length {reply_len} exceeds limit of {max_len} bytes"),
+        ));
+    }
+    let mut reply = Vec::with_capacity(reply_len.min(MAX_PREALLOCATED_LEN) as usize);
+    (&mut *connection)
+        .take(reply_len)
+        .read_to_end(&mut reply)
+        .await?;
+    if (reply.len() as u64) < reply_len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
     Ok(reply)
 }
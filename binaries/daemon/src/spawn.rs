@@ -1,23 +1,29 @@
 use crate::{
-    log, node_communication::spawn_listener_loop, node_inputs, DoraEvent, Event, OutputId,
-    RunningNode,
+    log,
+    node_communication::{limits::NodeConnections, spawn_listener_loop, InputQueues},
+    node_inputs,
+    paths::DaemonPaths,
+    raw_node, runtime_dir, DoraEvent, Event, OutputId, RunningNode,
 };
 use aligned_vec::{AVec, ConstAlign};
 use crossbeam::queue::ArrayQueue;
 use dora_arrow_convert::IntoArrow;
 use dora_core::{
-    config::DataId,
+    adjust_shared_library_path,
+    config::{DataId, NodeId},
     descriptor::{
-        resolve_path, source_is_url, Descriptor, OperatorDefinition, OperatorSource, PythonSource,
-        ResolvedNode, DYNAMIC_SOURCE, SHELL_SOURCE,
+        resolve_path, source_is_url, CoreNodeKind, Descriptor, LogFormat, OperatorDefinition,
+        OperatorSource, PythonSource, ResolvedNode, DYNAMIC_SOURCE, SHELL_SOURCE,
     },
     get_python_path,
     uhlc::HLC,
 };
 use dora_download::download_file;
 use dora_message::{
-    daemon_to_coordinator::{DataMessage, NodeExitStatus, Timestamped},
-    daemon_to_node::{NodeConfig, RuntimeConfig},
+    coordinator_to_daemon::DataflowInstance,
+    daemon_to_coordinator::{DataMessage, LogLevel, NodeExitStatus, Timestamped},
+    daemon_to_node::{env, NodeConfig, RuntimeConfig},
+    plan::NodeCommand,
     DataflowId,
 };
 use dora_node_api::{
@@ -27,7 +33,9 @@ use dora_node_api::{
 };
 use eyre::{ContextCompat, WrapErr};
 use std::{
+    collections::BTreeMap,
     env::consts::EXE_EXTENSION,
+    net::SocketAddr,
     path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
@@ -38,139 +46,194 @@ use tokio::{
     sync::{mpsc, oneshot},
 };
 use tracing::error;
+use uuid::Uuid;
+
+/// Checks that the executables and files required to spawn the given node
+/// exist, without spawning anything.
+///
+/// Node sources are resolved against the `working_dir` of the dataflow and
+/// operator sources against the `node_working_dir`, which is the working
+/// directory of the runtime process.
+///
+/// Returns a description of each problem that was found.
+pub fn check_node(node: &ResolvedNode, working_dir: &Path, node_working_dir: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    let node_id = &node.id;
+    match &node.kind {
+        CoreNodeKind::Custom(n) => {
+            if n.raw {
+                if let Err(err) = n.check_raw_config() {
+                    problems.push(format!("node `{node_id}`: {err}"));
+                }
+            }
+            match n.source.as_str() {
+                DYNAMIC_SOURCE | SHELL_SOURCE => {}
+                // downloaded when the node is spawned
+                source if source_is_url(source) => {}
+                source => match resolve_path(source, working_dir) {
+                    Ok(path) if path.extension().is_some_and(|ext| ext == "py") => {
+                        if let Err(err) = get_python_path() {
+                            problems.push(format!("node `{node_id}`: {err}"));
+                        }
+                    }
+                    Ok(path) => {
+                        if let Err(err) = check_executable(&path) {
+                            problems.push(format!("node `{node_id}`: {err}"));
+                        }
+                    }
+                    Err(_) => problems.push(format!(
+                        "node `{node_id}`: could not find executable `{source}`"
+                    )),
+                },
+            }
+        }
+        CoreNodeKind::Runtime(n) => {
+            for operator in &n.operators {
+                let operator_id = &operator.id;
+                let (kind, source) = match &operator.config.source {
+                    OperatorSource::SharedLibrary(source) => ("shared library", source),
+                    OperatorSource::Python(python) => ("Python operator", &python.source),
+                    OperatorSource::Wasm(source) => ("WASM operator", source),
+                };
+                if source_is_url(source) {
+                    // downloaded by the runtime
+                    continue;
+                }
+                let path = match &operator.config.source {
+                    OperatorSource::SharedLibrary(_) => {
+                        match adjust_shared_library_path(Path::new(source)) {
+                            Ok(path) => path,
+                            Err(err) => {
+                                problems.push(format!("operator `{node_id}/{operator_id}`: {err}"));
+                                continue;
+                            }
+                        }
+                    }
+                    _ => PathBuf::from(source),
+                };
+                if !node_working_dir.join(&path).exists() {
+                    problems.push(format!(
+                        "operator `{node_id}/{operator_id}`: no {kind} at `{}`",
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
 
-/// clock is required for generating timestamps when dropping messages early because queue is full
-pub async fn spawn_node(
-    dataflow_id: DataflowId,
-    working_dir: &Path,
-    node: ResolvedNode,
-    daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    dataflow_descriptor: Descriptor,
-    clock: Arc<HLC>,
-    node_stderr_most_recent: Arc<ArrayQueue<String>>,
-) -> eyre::Result<RunningNode> {
-    let node_id = node.id.clone();
-    tracing::debug!("Spawning node `{dataflow_id}/{node_id}`");
-
-    let queue_sizes = node_inputs(&node)
-        .into_iter()
-        .map(|(k, v)| (k, v.queue_size.unwrap_or(10)))
-        .collect();
-    let daemon_communication = spawn_listener_loop(
-        &dataflow_id,
-        &node_id,
-        &daemon_tx,
-        dataflow_descriptor.communication.local,
-        queue_sizes,
-        clock.clone(),
-    )
-    .await?;
-    let send_stdout_to = node
-        .send_stdout_as()
-        .context("Could not resolve `send_stdout_as` configuration")?;
+fn check_executable(path: &Path) -> eyre::Result<()> {
+    let metadata = std::fs::metadata(path)
+        .wrap_err_with(|| format!("failed to read metadata of `{}`", path.display()))?;
+    if !metadata.is_file() {
+        eyre::bail!("`{}` is not a file", path.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            eyre::bail!("`{}` is not executable", path.display());
+        }
+    }
+    Ok(())
+}
 
-    let node_config = NodeConfig {
-        dataflow_id,
-        node_id: node_id.clone(),
-        run_config: node.kind.run_config(),
-        daemon_communication,
-        dataflow_descriptor,
-        dynamic: node.kind.dynamic(),
-    };
+/// Sets the standard environment variables that identify the node and the daemon.
+///
+/// See [`dora_message::daemon_to_node::env`].
+fn set_node_env(
+    command: &mut tokio::process::Command,
+    dataflow_id: DataflowId,
+    node_id: &NodeId,
+    instance: Option<&DataflowInstance>,
+    daemon_addr: Option<SocketAddr>,
+) {
+    command.env(env::DORA_DATAFLOW_ID, dataflow_id.to_string());
+    command.env(env::DORA_NODE_ID, node_id.to_string());
+    if let Some(instance) = instance {
+        command.env(env::DORA_DATAFLOW_INSTANCE, instance.to_string());
+    }
+    if let Some(addr) = daemon_addr {
+        command.env(env::DORA_DAEMON_ADDR, addr.to_string());
+    }
+}
 
-    let mut child = match node.kind {
-        dora_core::descriptor::CoreNodeKind::Custom(n) => {
-            let mut command = match n.source.as_str() {
-                DYNAMIC_SOURCE => {
-                    return Ok(RunningNode {
-                        pid: None,
-                        node_config,
-                    });
-                }
-                SHELL_SOURCE => {
-                    if cfg!(target_os = "windows") {
-                        let mut cmd = tokio::process::Command::new("cmd");
-                        cmd.args(["/C", &n.args.clone().unwrap_or_default()]);
-                        cmd
-                    } else {
-                        let mut cmd = tokio::process::Command::new("sh");
-                        cmd.args(["-c", &n.args.clone().unwrap_or_default()]);
-                        cmd
-                    }
+/// Builds the command that runs the given node, without spawning it.
+///
+/// Returns `None` for dynamic nodes, which are started by the user. The
+/// serialized node configuration is added by [`spawn_node`], as it contains
+/// the communication channel of the node. With `dry_run`, nodes with a URL
+/// source are not downloaded.
+#[allow(clippy::too_many_arguments)]
+pub async fn node_command(
+    dataflow_id: DataflowId,
+    instance: Option<&DataflowInstance>,
+    working_dir: &Path,
+    node_working_dir: &Path,
+    paths: &DaemonPaths,
+    node: &ResolvedNode,
+    daemon_addr: Option<SocketAddr>,
+    dry_run: bool,
+) -> eyre::Result<Option<tokio::process::Command>> {
+    let node_id = &node.id;
+    let mut command = match &node.kind {
+        CoreNodeKind::Custom(n) => match n.source.as_str() {
+            DYNAMIC_SOURCE => return Ok(None),
+            SHELL_SOURCE => {
+                if cfg!(target_os = "windows") {
+                    let mut cmd = tokio::process::Command::new("cmd");
+                    cmd.args(["/C", &n.args.clone().unwrap_or_default()]);
+                    cmd
+                } else {
+                    let mut cmd = tokio::process::Command::new("sh");
+                    cmd.args(["-c", &n.args.clone().unwrap_or_default()]);
+                    cmd
                 }
-                source => {
-                    let resolved_path = if source_is_url(source) {
-                        // try to download the shared library
-                        let target_path = Path::new("build")
-                            .join(node_id.to_string())
-                            .with_extension(EXE_EXTENSION);
+            }
+            source => {
+                let resolved_path = if source_is_url(source) {
+                    // try to download the shared library
+                    let file_name =
+                        PathBuf::from(node_id.to_string()).with_extension(EXE_EXTENSION);
+                    let target_path = paths.download_path(source, &file_name);
+                    if !dry_run {
                         download_file(source, &target_path)
                             .await
                             .wrap_err("failed to download custom node")?;
-                        target_path.clone()
-                    } else {
-                        resolve_path(source, working_dir).wrap_err_with(|| {
-                            format!("failed to resolve node source `{}`", source)
-                        })?
-                    };
-
-                    // If extension is .py, use python to run the script
-                    let mut cmd = match resolved_path.extension().map(|ext| ext.to_str()) {
-                        Some(Some("py")) => {
-                            let python = get_python_path().context("Could not get python path")?;
+                    }
+                    target_path
+                } else {
+                    resolve_path(source, working_dir)
+                        .wrap_err_with(|| format!("failed to resolve node source `{}`", source))?
+                };
+
+                // If extension is .py, use python to run the script
+                let mut cmd = match resolved_path.extension().map(|ext| ext.to_str()) {
+                    Some(Some("py")) => {
+                        let python = get_python_path().context("Could not get python path")?;
+                        if !dry_run {
                             tracing::info!("spawning: {:?} {}", &python, resolved_path.display());
-                            let mut cmd = tokio::process::Command::new(&python);
-                            cmd.arg(&resolved_path);
-                            cmd
                         }
-                        _ => {
+                        let mut cmd = tokio::process::Command::new(&python);
+                        cmd.arg(&resolved_path);
+                        cmd
+                    }
+                    _ => {
+                        if !dry_run {
                             tracing::info!("spawning: {}", resolved_path.display());
-                            tokio::process::Command::new(&resolved_path)
                         }
-                    };
-
-                    if let Some(args) = &n.args {
-                        cmd.args(args.split_ascii_whitespace());
+                        tokio::process::Command::new(&resolved_path)
                     }
-                    cmd
-                }
-            };
-
-            command.current_dir(working_dir);
-            command.stdin(Stdio::null());
+                };
 
-            command.env(
-                "DORA_NODE_CONFIG",
-                serde_yaml::to_string(&node_config.clone())
-                    .wrap_err("failed to serialize node config")?,
-            );
-            // Injecting the env variable defined in the `yaml` into
-            // the node runtime.
-            if let Some(envs) = node.env {
-                for (key, value) in envs {
-                    command.env(key, value.to_string());
-                }
-            }
-            if let Some(envs) = n.envs {
-                // node has some inner env variables -> add them too
-                for (key, value) in envs {
-                    command.env(key, value.to_string());
+                if let Some(args) = &n.args {
+                    cmd.args(args.split_ascii_whitespace());
                 }
+                cmd
             }
-            command
-                .stdin(Stdio::null())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .wrap_err_with(move || {
-                    format!(
-                        "failed to run `{}` with args `{}`",
-                        n.source,
-                        n.args.as_deref().unwrap_or_default(),
-                    )
-                })?
-        }
-        dora_core::descriptor::CoreNodeKind::Runtime(n) => {
+        },
+        CoreNodeKind::Runtime(n) => {
             let python_operators: Vec<&OperatorDefinition> = n
                 .operators
                 .iter()
@@ -182,7 +245,7 @@ pub async fn spawn_node(
                 .iter()
                 .any(|x| !matches!(x.config.source, OperatorSource::Python { .. }));
 
-            let mut command = if !python_operators.is_empty() && !other_operators {
+            if !python_operators.is_empty() && !other_operators {
                 // Use python to spawn runtime if there is a python operator
 
                 // TODO: Handle multi-operator runtime once sub-interpreter is supported
@@ -234,25 +297,183 @@ pub async fn spawn_node(
                 cmd
             } else {
                 eyre::bail!("Runtime can not mix Python Operator with other type of operator.");
-            };
-            command.current_dir(working_dir);
+            }
+        }
+    };
+
+    command.current_dir(node_working_dir);
+    set_node_env(&mut command, dataflow_id, node_id, instance, daemon_addr);
+    // the runtime dir is created when the dataflow is spawned
+    if let Some(dir) = paths
+        .dataflow_runtime_dir(&dataflow_id)
+        .filter(|dir| dry_run || dir.is_dir())
+    {
+        command.env("TMPDIR", runtime_dir::tmp_dir(&dir));
+        command.env(env::DORA_DATAFLOW_DIR, dir);
+    }
+    // Injecting the env variable defined in the `yaml` into
+    // the node runtime.
+    if let Some(envs) = &node.env {
+        for (key, value) in envs {
+            command.env(key, value.to_string());
+        }
+    }
+    if let CoreNodeKind::Custom(n) = &node.kind {
+        if let Some(envs) = &n.envs {
+            // node has some inner env variables -> add them too
+            for (key, value) in envs {
+                command.env(key, value.to_string());
+            }
+        }
+    }
+    Ok(Some(command))
+}
+
+/// Describes the given node command for a dataflow plan.
+pub fn describe_command(command: &tokio::process::Command) -> NodeCommand {
+    let command = command.as_std();
+    NodeCommand {
+        program: PathBuf::from(command.get_program()),
+        args: command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        env: command
+            .get_envs()
+            .filter_map(|(key, value)| {
+                Some((
+                    key.to_string_lossy().into_owned(),
+                    value?.to_string_lossy().into_owned(),
+                ))
+            })
+            .collect(),
+    }
+}
 
+/// Queue settings of the inputs of the given node.
+pub fn input_queues(node: &ResolvedNode) -> InputQueues {
+    let inputs = node_inputs(node);
+    InputQueues::new(
+        inputs
+            .iter()
+            .map(|(k, v)| (k.clone(), v.queue_size_or_default()))
+            .collect(),
+        inputs.into_iter().map(|(k, v)| (k, v.priority)).collect(),
+    )
+}
+
+/// clock is required for generating timestamps when dropping messages early because queue is full
+#[allow(clippy::too_many_arguments)]
+pub async fn spawn_node(
+    dataflow_id: DataflowId,
+    instance: Option<&DataflowInstance>,
+    working_dir: &Path,
+    node_working_dir: &Path,
+    paths: &DaemonPaths,
+    node: ResolvedNode,
+    daemon_tx: mpsc::Sender<Timestamped<Event>>,
+    dataflow_descriptor: Descriptor,
+    dataflow_params: BTreeMap<String, String>,
+    clock: Arc<HLC>,
+    node_stderr_most_recent: Arc<ArrayQueue<String>>,
+    daemon_addr: Option<SocketAddr>,
+    node_connections: &NodeConnections,
+    drop_token_namespace: Uuid,
+) -> eyre::Result<RunningNode> {
+    let node_id = node.id.clone();
+    tracing::debug!("Spawning node `{dataflow_id}/{node_id}`");
+
+    let input_queues = input_queues(&node);
+    let daemon_communication = spawn_listener_loop(
+        &dataflow_id,
+        &node_id,
+        &daemon_tx,
+        dataflow_descriptor.communication.local,
+        input_queues.clone(),
+        clock.clone(),
+        node_connections,
+    )
+    .await?;
+    let send_stdout_to = node
+        .send_stdout_as()
+        .context("Could not resolve `send_stdout_as` configuration")?;
+    let log_format = node.log_format;
+
+    let node_config = NodeConfig {
+        dataflow_id,
+        node_id: node_id.clone(),
+        run_config: node.kind.run_config(),
+        daemon_communication,
+        dataflow_descriptor,
+        dynamic: node.kind.dynamic(),
+        dataflow_instance: instance.map(|i| i.to_string()),
+        dataflow_params,
+        drop_token_namespace,
+        daemon_version: Some(dora_message::current_crate_version()),
+    };
+
+    let raw_framing = match &node.kind {
+        dora_core::descriptor::CoreNodeKind::Custom(n) if n.raw => {
+            n.check_raw_config()
+                .wrap_err_with(|| format!("invalid raw node configuration of `{node_id}`"))?;
+            Some(n.framing.unwrap_or_default())
+        }
+        _ => None,
+    };
+
+    let Some(mut command) = node_command(
+        dataflow_id,
+        instance,
+        working_dir,
+        node_working_dir,
+        paths,
+        &node,
+        daemon_addr,
+        false,
+    )
+    .await?
+    else {
+        return Ok(RunningNode {
+            pid: None,
+            node_config,
+            input_queues,
+        });
+    };
+
+    let mut child = match node.kind {
+        dora_core::descriptor::CoreNodeKind::Custom(n) => {
+            command.env(
+                env::DORA_NODE_CONFIG,
+                serde_yaml::to_string(&node_config.clone())
+                    .wrap_err("failed to serialize node config")?,
+            );
+            let stdin = match raw_framing {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            };
+            command
+                .stdin(stdin)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .wrap_err_with(move || {
+                    format!(
+                        "failed to run `{}` with args `{}`",
+                        n.source,
+                        n.args.as_deref().unwrap_or_default(),
+                    )
+                })?
+        }
+        dora_core::descriptor::CoreNodeKind::Runtime(n) => {
             let runtime_config = RuntimeConfig {
                 node: node_config.clone(),
                 operators: n.operators,
             };
             command.env(
-                "DORA_RUNTIME_CONFIG",
+                env::DORA_RUNTIME_CONFIG,
                 serde_yaml::to_string(&runtime_config)
                     .wrap_err("failed to serialize runtime config")?,
             );
-            // Injecting the env variable defined in the `yaml` into
-            // the node runtime.
-            if let Some(envs) = node.env {
-                for (key, value) in envs {
-                    command.env(key, value.to_string());
-                }
-            }
 
             command
                 .stdin(Stdio::null())
@@ -266,27 +487,65 @@ pub async fn spawn_node(
         }
     };
 
-    let dataflow_dir: PathBuf = working_dir.join("out").join(dataflow_id.to_string());
-    if !dataflow_dir.exists() {
-        std::fs::create_dir_all(&dataflow_dir).context("could not create dataflow_dir")?;
-    }
+    // the node runs without log file if the directory is not writable
+    let dataflow_dir = paths.dataflow_dir(working_dir, &dataflow_id, instance);
+    let mut file = match paths.create_dataflow_dir(&dataflow_dir) {
+        Some(dir) => {
+            let log_path = log::log_path(&dir, &node_id);
+            match File::create(&log_path).await {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    tracing::warn!(
+                        "failed to create log file `{}` ({err}), so the logs of node \
+                        `{node_id}` are not written to disk",
+                        log_path.display()
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
     let (tx, mut rx) = mpsc::channel(10);
-    let mut file = File::create(log::log_path(working_dir, &dataflow_id, &node_id))
-        .await
-        .expect("Failed to create log file");
-    let mut child_stdout =
-        tokio::io::BufReader::new(child.stdout.take().expect("failed to take stdout"));
     let pid = child.id().context(
         "Could not get the pid for the just spawned node and indicate that there is an error",
     )?;
+
+    // raw nodes exchange messages through stdin and stdout
+    let (child_exited_tx, child_exited_rx) = oneshot::channel();
+    let raw_codec_finished = match raw_framing {
+        Some(framing) => {
+            let stdin = child.stdin.take().context("failed to take stdin")?;
+            // stdout is only used for messages if there is an output to send them on
+            let stdout = if node_config.run_config.outputs.is_empty() {
+                None
+            } else {
+                child.stdout.take()
+            };
+            Some(raw_node::spawn_codec(
+                node_config.clone(),
+                framing,
+                stdin,
+                stdout,
+                child_exited_rx,
+            ))
+        }
+        None => None,
+    };
+
     let running_node = RunningNode {
         pid: Some(pid),
         node_config,
+        input_queues,
     };
     let stdout_tx = tx.clone();
 
     // Stdout listener stream
+    let child_stdout = child.stdout.take().map(tokio::io::BufReader::new);
     tokio::spawn(async move {
+        let Some(mut child_stdout) = child_stdout else {
+            return;
+        };
         let mut buffer = String::new();
         let mut finished = false;
         while !finished {
@@ -316,11 +575,13 @@ pub async fn spawn_node(
                 }
             };
 
-            if buffer.contains("TRACE")
-                || buffer.contains("INFO")
-                || buffer.contains("DEBUG")
-                || buffer.contains("WARN")
-                || buffer.contains("ERROR")
+            // JSON log lines are parsed one by one
+            if log_format == LogFormat::Text
+                && (buffer.contains("TRACE")
+                    || buffer.contains("INFO")
+                    || buffer.contains("DEBUG")
+                    || buffer.contains("WARN")
+                    || buffer.contains("ERROR"))
             {
                 // tracing output, potentially multi-line -> keep reading following lines
                 // until double-newline
@@ -393,6 +654,11 @@ pub async fn spawn_node(
     let (log_finish_tx, log_finish_rx) = oneshot::channel();
     tokio::spawn(async move {
         let exit_status = NodeExitStatus::from(child.wait().await);
+        let _ = child_exited_tx.send(());
+        if let Some(codec_finished) = raw_codec_finished {
+            // report the node as finished only after its outputs were closed
+            let _ = codec_finished.await;
+        }
         let _ = log_finish_rx.await;
         let event = DoraEvent::SpawnedNodeResult {
             dataflow_id,
@@ -441,22 +707,58 @@ pub async fn spawn_node(
                 let _ = daemon_tx_log.send(event).await;
             }
 
-            let _ = file
-                .write_all(message.as_bytes())
-                .await
-                .map_err(|err| error!("Could not log {message} to file due to {err}"));
-            let formatted = message.lines().fold(String::default(), |mut output, line| {
-                output.push_str("      ");
-                output.push_str(line);
-                output.push('\n');
-                output
-            });
-            tracing::trace!("{dataflow_id}/{} logged:\n{formatted}", node.id.clone());
+            if let Some(file) = &mut file {
+                let _ = file
+                    .write_all(message.as_bytes())
+                    .await
+                    .map_err(|err| error!("Could not log {message} to file due to {err}"));
+            }
+            // parsed JSON log lines are re-emitted, all other lines are forwarded as they are
+            let plain = match log_format {
+                LogFormat::Text => message.clone(),
+                LogFormat::Json => {
+                    let mut plain = String::new();
+                    for line in message.lines() {
+                        match log::NodeLogEntry::parse(line) {
+                            Some(entry) => {
+                                entry.emit(dataflow_id, &node.id);
+                                if entry.level == LogLevel::Error {
+                                    let event = DoraEvent::NodeLog {
+                                        message: entry.to_log_message(dataflow_id, &node.id),
+                                    };
+                                    let event = Timestamped {
+                                        inner: event.into(),
+                                        timestamp: uhlc.new_timestamp(),
+                                    };
+                                    let _ = daemon_tx_log.send(event).await;
+                                }
+                            }
+                            None if line.trim().is_empty() => {}
+                            None => {
+                                plain.push_str(line);
+                                plain.push('\n');
+                            }
+                        }
+                    }
+                    plain
+                }
+            };
+            if !plain.is_empty() {
+                let formatted = plain.lines().fold(String::default(), |mut output, line| {
+                    output.push_str("      ");
+                    output.push_str(line);
+                    output.push('\n');
+                    output
+                });
+                tracing::trace!("{dataflow_id}/{} logged:\n{formatted}", node.id.clone());
+            }
             // Make sure that all data has been synced to disk.
-            let _ = file
-                .sync_all()
-                .await
-                .map_err(|err| error!("Could not sync logs to file due to {err}"));
+            if let Some(file) = &file {
+                let _ = file
+                    .sync_all()
+                    .await
+                    .map_err(|err| error!("Could not sync logs to file due to {err}"));
+            }
         }
         let _ = log_finish_tx
             .send(())
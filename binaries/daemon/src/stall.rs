@@ -0,0 +1,132 @@
+//! Detection of dataflows in which no messages flow anymore.
+//!
+//! Every message or timer tick that is delivered to a local node counts as
+//! activity of its dataflow. A dataflow that still has running nodes, but
+//! saw no activity for longer than the configured threshold, is considered
+//! stalled until the next activity. Dataflows with `idle_ok: true` are not
+//! checked.
+
+use std::time::{Duration, Instant};
+
+/// Default time without any message flow after which a dataflow is
+/// considered stalled.
+pub const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Configuration of the stall detection of the daemon.
+#[derive(Debug, Clone)]
+pub struct StallConfig {
+    /// Time without any delivered message after which a dataflow with running
+    /// nodes is considered stalled.
+    pub threshold: Duration,
+    /// Whether stalls and recoveries are reported to the coordinator.
+    pub notify_coordinator: bool,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_STALL_THRESHOLD,
+            notify_coordinator: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StallDetector {
+    last_activity: Instant,
+    stalled_since: Option<Instant>,
+}
+
+/// A change of the stall state, as returned by [`StallDetector::check`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum StallChange {
+    /// The dataflow saw no activity for `idle`.
+    Stalled { idle: Duration },
+    /// Messages flow again after the dataflow was stalled for `stalled_for`.
+    Recovered { stalled_for: Duration },
+}
+
+impl StallDetector {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last_activity: now,
+            stalled_since: None,
+        }
+    }
+
+    /// Records that a message or timer tick was delivered.
+    pub fn record_activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// Updates the stall state and returns it if it changed.
+    ///
+    /// Dataflows without running nodes are never considered stalled. When
+    /// the last node of a stalled dataflow exits, the stall ends silently,
+    /// as the dataflow is finished on this machine.
+    pub fn check(
+        &mut self,
+        now: Instant,
+        threshold: Duration,
+        has_running_nodes: bool,
+    ) -> Option<StallChange> {
+        if !has_running_nodes {
+            self.stalled_since = None;
+            return None;
+        }
+        let idle = now.saturating_duration_since(self.last_activity);
+        match (self.stalled_since, idle > threshold) {
+            (None, true) => {
+                self.stalled_since = Some(now);
+                Some(StallChange::Stalled { idle })
+            }
+            (Some(since), false) => {
+                self.stalled_since = None;
+                Some(StallChange::Recovered {
+                    stalled_for: self.last_activity.saturating_duration_since(since),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Time since the last activity, if the dataflow is currently stalled.
+    pub fn idle(&self, now: Instant) -> Option<Duration> {
+        self.stalled_since
+            .map(|_| now.saturating_duration_since(self.last_activity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stall_is_reported_once_until_recovery() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let threshold = Duration::from_secs(60);
+
+        let mut detector = StallDetector::new(start);
+        assert_eq!(detector.check(at(30), threshold, true), None);
+        // finished dataflows are not stalled
+        assert_eq!(detector.check(at(90), threshold, false), None);
+        assert_eq!(
+            detector.check(at(90), threshold, true),
+            Some(StallChange::Stalled {
+                idle: Duration::from_secs(90)
+            })
+        );
+        assert_eq!(detector.check(at(95), threshold, true), None);
+        assert_eq!(detector.idle(at(100)), Some(Duration::from_secs(100)));
+
+        detector.record_activity(at(110));
+        assert_eq!(
+            detector.check(at(115), threshold, true),
+            Some(StallChange::Recovered {
+                stalled_for: Duration::from_secs(20)
+            })
+        );
+        assert_eq!(detector.idle(at(115)), None);
+    }
+}
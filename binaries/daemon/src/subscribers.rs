@@ -0,0 +1,66 @@
+//! Notifications about whether the outputs of local nodes have receivers.
+//!
+//! A node is sent a `SubscribersChanged` event when the number of receivers
+//! of one of its outputs changes from zero to nonzero or back. The first
+//! number after the node subscribed is not reported, as the node can query
+//! it through a `QuerySubscribers` request.
+
+use crate::OutputId;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default)]
+pub struct SubscriberPresence {
+    /// Whether the output had receivers when it was last checked.
+    has_subscribers: BTreeMap<OutputId, bool>,
+}
+
+impl SubscriberPresence {
+    /// Records the current number of receivers of the given output.
+    ///
+    /// Returns whether the node needs to be notified, i.e. whether the output
+    /// gained its first receiver or lost its last one.
+    pub fn update(&mut self, output_id: &OutputId, subscribers: u64) -> bool {
+        let has_subscribers = subscribers > 0;
+        match self.has_subscribers.get_mut(output_id) {
+            Some(previous) => std::mem::replace(previous, has_subscribers) != has_subscribers,
+            None => {
+                self.has_subscribers
+                    .insert(output_id.clone(), has_subscribers);
+                false
+            }
+        }
+    }
+
+    /// Forgets the outputs of nodes for which `keep` returns `false`, e.g.
+    /// because they stopped or are restarted.
+    pub fn retain(&mut self, mut keep: impl FnMut(&OutputId) -> bool) {
+        self.has_subscribers.retain(|output_id, _| keep(output_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::config::{DataId, NodeId};
+
+    #[test]
+    fn only_transitions_are_reported() {
+        let image = OutputId(
+            NodeId::from("camera".to_owned()),
+            DataId::from("image".to_owned()),
+        );
+        let mut presence = SubscriberPresence::default();
+
+        assert!(!presence.update(&image, 0));
+        assert!(presence.update(&image, 1));
+        assert!(!presence.update(&image, 2));
+        assert!(!presence.update(&image, 1));
+        assert!(presence.update(&image, 0));
+        assert!(!presence.update(&image, 0));
+
+        // restarted nodes query the number again
+        presence.retain(|_| false);
+        assert!(!presence.update(&image, 3));
+        assert!(presence.update(&image, 0));
+    }
+}
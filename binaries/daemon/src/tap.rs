@@ -0,0 +1,111 @@
+//! Output taps, which copy the messages of an output to the coordinator for
+//! live debugging (`dora tap`).
+//!
+//! A tap is an additional, passive consumer: it never holds drop tokens and
+//! it is not part of the input queues of the receiving nodes. Messages are
+//! skipped when they exceed the rate limit of the tap.
+
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::OutputId;
+
+pub struct OutputTap {
+    pub id: Uuid,
+    pub output_id: OutputId,
+    until: Instant,
+    min_interval: Option<Duration>,
+    last_sent: Option<Instant>,
+}
+
+impl OutputTap {
+    pub fn new(
+        id: Uuid,
+        output_id: OutputId,
+        duration: Duration,
+        max_rate: Option<f64>,
+        now: Instant,
+    ) -> eyre::Result<Self> {
+        let min_interval = match max_rate {
+            Some(rate) if rate.is_finite() && rate > 0.0 => {
+                Some(Duration::from_secs_f64(1.0 / rate))
+            }
+            Some(rate) => eyre::bail!("invalid tap rate `{rate}`, must be positive"),
+            None => None,
+        };
+        Ok(Self {
+            id,
+            output_id,
+            until: now + duration,
+            min_interval,
+            last_sent: None,
+        })
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.until
+    }
+
+    /// Returns `true` if a message of the given output should be copied.
+    pub fn accept(&mut self, output_id: &OutputId, now: Instant) -> bool {
+        if &self.output_id != output_id || self.is_expired(now) {
+            return false;
+        }
+        let rate_limited = self.min_interval.is_some_and(|interval| {
+            self.last_sent
+                .is_some_and(|last| now.saturating_duration_since(last) < interval)
+        });
+        if rate_limited {
+            return false;
+        }
+        self.last_sent = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(node: &str, output: &str) -> OutputId {
+        OutputId(node.to_owned().into(), output.to_owned().into())
+    }
+
+    #[test]
+    fn taps_are_rate_limited_and_expire() {
+        let start = Instant::now();
+        let mut tap = OutputTap::new(
+            Uuid::nil(),
+            output("camera", "image"),
+            Duration::from_secs(1),
+            Some(10.0),
+            start,
+        )
+        .unwrap();
+
+        assert!(!tap.accept(&output("camera", "depth"), start));
+        assert!(tap.accept(&output("camera", "image"), start));
+        assert!(!tap.accept(
+            &output("camera", "image"),
+            start + Duration::from_millis(50)
+        ));
+        assert!(tap.accept(
+            &output("camera", "image"),
+            start + Duration::from_millis(100)
+        ));
+
+        let end = start + Duration::from_secs(1);
+        assert!(tap.is_expired(end));
+        assert!(!tap.accept(&output("camera", "image"), end));
+
+        assert!(OutputTap::new(
+            Uuid::nil(),
+            output("camera", "image"),
+            Duration::from_secs(1),
+            Some(0.0),
+            start,
+        )
+        .is_err());
+    }
+}
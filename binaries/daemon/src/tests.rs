@@ -0,0 +1,2115 @@
+use super::*;
+use dora_message::reconfigure::{InputChange, QueuePolicy};
+
+const FAN_IN_DATAFLOW: &str = r#"
+nodes:
+  - id: joystick
+    path: joystick
+    outputs:
+      - cmd
+  - id: planner
+    path: planner
+    outputs:
+      - cmd
+  - id: robot
+    path: robot
+    inputs:
+      command: [joystick/cmd, planner/cmd]
+"#;
+
+fn fan_in_dataflow() -> RunningDataflow {
+    let descriptor = Descriptor::parse(FAN_IN_DATAFLOW.as_bytes().to_vec()).unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow =
+        RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes.clone());
+    for node in &nodes {
+        dataflow.register_inputs(node, true);
+    }
+    dataflow
+}
+
+async fn send_output(dataflow: &mut RunningDataflow, source: &str, clock: &HLC) {
+    let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+    send_output_to_local_receivers(
+        NodeId::from(source.to_owned()),
+        DataId::from("cmd".to_owned()),
+        dataflow,
+        &metadata,
+        None,
+        clock,
+    )
+    .await
+    .unwrap();
+}
+
+async fn close_outputs_of(dataflow: &mut RunningDataflow, source: &str, clock: &HLC) {
+    let source = NodeId::from(source.to_owned());
+    send_input_closed_events(
+        dataflow,
+        &mut BTreeMap::new(),
+        |OutputId(node_id, _)| node_id == &source,
+        clock,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn fan_in_delivery() {
+    // (sources that send a message, whether the input of `robot` is
+    // subscribed to them, expected sources of the received messages)
+    let cases: [(&[&str], bool, &[&str]); 2] = [
+        (
+            &["joystick", "planner", "joystick"],
+            true,
+            &["joystick/cmd", "planner/cmd", "joystick/cmd"],
+        ),
+        // declared outputs without receivers are not sent anywhere
+        (&["planner"], false, &[]),
+    ];
+    for (sources, subscribed, expected) in cases {
+        let clock = HLC::default();
+        let mut dataflow = fan_in_dataflow();
+        let robot = NodeId::from("robot".to_owned());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        dataflow.subscribe_channels.insert(robot.clone(), tx.into());
+        if !subscribed {
+            dataflow.mappings.clear();
+        }
+
+        let cmd = DataId::from("cmd".to_owned());
+        for source in sources {
+            let output = OutputId(NodeId::from(source.to_string()), cmd.clone());
+            assert_eq!(dataflow.check_output_declared(&output.0, &cmd), Ok(()));
+            assert_eq!(dataflow.has_receivers(&output), subscribed);
+            send_output(&mut dataflow, source, &clock).await;
+        }
+
+        for expected_source in expected {
+            match rx.try_recv().unwrap().inner {
+                NodeEvent::Input { id, metadata, .. } => {
+                    assert_eq!(id.as_str(), "command");
+                    assert_eq!(metadata.input_source(), Some(*expected_source));
+                }
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+        assert!(rx.try_recv().is_err());
+
+        // deliveries are counted per source of the input
+        let command = DataId::from("command".to_owned());
+        for source in ["joystick/cmd", "planner/cmd"] {
+            let delivered = dataflow
+                .input_stats
+                .get(&robot)
+                .and_then(|inputs| inputs.get(&command))
+                .and_then(|input| input.delivered.get(source).copied());
+            let count = expected.iter().filter(|s| **s == source).count() as u64;
+            assert_eq!(delivered.unwrap_or_default(), count, "{source}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn filtered_messages_are_counted_per_receiver() {
+    let clock = HLC::default();
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: joystick
+    path: joystick
+    outputs:
+      - cmd
+  - id: logger
+    path: logger
+    inputs:
+      cmd: { source: joystick/cmd, decimate: { keep_every: 3 } }
+  - id: robot
+    path: robot
+    inputs:
+      cmd: joystick/cmd
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow =
+        RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes.clone());
+    for node in &nodes {
+        dataflow.register_inputs(node, true);
+    }
+    let mut receivers = BTreeMap::new();
+    for id in ["logger", "robot"] {
+        let (tx, rx) = mpsc::unbounded_channel();
+        dataflow
+            .subscribe_channels
+            .insert(NodeId::from(id.to_owned()), tx.into());
+        receivers.insert(id, rx);
+    }
+
+    for _ in 0..6 {
+        send_output(&mut dataflow, "joystick", &clock).await;
+    }
+
+    let mut received = |id| {
+        let rx = receivers.get_mut(id).unwrap();
+        std::iter::from_fn(|| rx.try_recv().ok()).count()
+    };
+    assert_eq!(received("logger"), 2);
+    assert_eq!(received("robot"), 6);
+    let stats = |id: &str| {
+        dataflow.input_stats[&NodeId::from(id.to_owned())][&DataId::from("cmd".to_owned())].clone()
+    };
+    assert_eq!(stats("logger").filtered, 4);
+    assert_eq!(stats("logger").delivered["joystick/cmd"], 2);
+    assert_eq!(stats("robot").filtered, 0);
+}
+
+#[tokio::test]
+async fn migrated_node_gets_messages_of_unswitched_machines() {
+    let clock = HLC::default();
+    let descriptor = Descriptor::parse(FAN_IN_DATAFLOW.as_bytes().to_vec()).unwrap();
+    let mut nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    for node in &mut nodes {
+        node.deploy.machine = if node.id.as_ref() == "planner" {
+            "c"
+        } else {
+            "a"
+        }
+        .into();
+    }
+    let mut dataflow = RunningDataflow::new(Uuid::new_v4(), "a".into(), descriptor, nodes.clone());
+    for node in &nodes {
+        dataflow.register_inputs(node, node.deploy.machine == "a");
+    }
+    let robot = NodeId::from("robot".to_owned());
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    dataflow.subscribe_channels.insert(robot.clone(), tx.into());
+
+    // migrate `robot` from this machine to machine `b`
+    let mut migration = NodeMigration::new(robot.clone(), "a".into(), "b".into());
+    migration.set_switched("a", false);
+    dataflow.migration = Some(migration);
+    dataflow.switch_node_routes(&robot, "a", "b").unwrap();
+    let joystick_cmd = OutputId(
+        NodeId::from("joystick".to_owned()),
+        DataId::from("cmd".to_owned()),
+    );
+    assert!(dataflow.open_external_mappings[&joystick_cmd]["b"]
+        .contains(&(robot.clone(), DataId::from("command".to_owned()))));
+
+    // local outputs are forwarded to the new instance now, outputs of
+    // machine `c` are delivered locally until it switched too
+    send_output(&mut dataflow, "joystick", &clock).await;
+    send_output(&mut dataflow, "planner", &clock).await;
+    dataflow
+        .migration
+        .as_mut()
+        .unwrap()
+        .set_switched("c", false);
+    send_output(&mut dataflow, "planner", &clock).await;
+
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::Input { metadata, .. } => {
+            assert_eq!(metadata.input_source(), Some("planner/cmd"));
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn typed_parameters_are_passed_through() {
+    let clock = HLC::default();
+    let mut dataflow = fan_in_dataflow();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    dataflow
+        .subscribe_channels
+        .insert(NodeId::from("robot".to_owned()), tx.into());
+
+    let mut metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::byte_array(3));
+    metadata.set("width", 640);
+    metadata.set("scale", 0.5);
+    metadata.set("encoding", "rgb8");
+    metadata.set("roi", vec![1i64, 2, 3, 4]);
+    metadata.set("mask", vec![0u8, 255]);
+    send_output_to_local_receivers(
+        NodeId::from("joystick".to_owned()),
+        DataId::from("cmd".to_owned()),
+        &mut dataflow,
+        &metadata,
+        Some(DataMessage::Vec(AVec::from_slice(1, &[1, 2, 3]))),
+        &clock,
+    )
+    .await
+    .unwrap();
+
+    // events are sent to nodes in bincode format
+    let event = rx.try_recv().unwrap();
+    let event: Timestamped<NodeEvent> =
+        bincode::deserialize(&bincode::serialize(&event).unwrap()).unwrap();
+    let NodeEvent::Input {
+        metadata: received, ..
+    } = event.inner
+    else {
+        panic!("unexpected event {:?}", event.inner);
+    };
+    for (key, value) in &metadata.parameters {
+        assert_eq!(received.parameters.get(key), Some(value), "{key}");
+    }
+    assert_eq!(received.get_int("width"), Some(640));
+    assert_eq!(received.get_float("scale"), Some(0.5));
+    assert_eq!(received.get_str("encoding"), Some("rgb8"));
+    assert_eq!(received.get_int_list("roi"), Some(&[1, 2, 3, 4][..]));
+    assert_eq!(received.get_bytes("mask"), Some(&[0, 255][..]));
+    assert_eq!(received.get_float("width"), None);
+}
+
+#[tokio::test]
+async fn alternating_outputs_keep_publish_order() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: tracker
+    path: tracker
+    outputs:
+      - pose
+      - status
+  - id: planner
+    path: planner
+    inputs:
+      pose: tracker/pose
+      status: tracker/status
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow = RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes);
+    for node in dataflow.resolved_nodes.clone() {
+        dataflow.register_inputs(&node, true);
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    dataflow
+        .subscribe_channels
+        .insert(NodeId::from("planner".to_owned()), tx.into());
+
+    const MESSAGES: u32 = 10_000;
+    let clock = HLC::default();
+    for i in 0..MESSAGES {
+        let output = if i % 2 == 0 { "pose" } else { "status" };
+        let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        send_output_to_local_receivers(
+            NodeId::from("tracker".to_owned()),
+            DataId::from(output.to_owned()),
+            &mut dataflow,
+            &metadata,
+            Some(DataMessage::Vec(AVec::from_slice(1, &i.to_le_bytes()))),
+            &clock,
+        )
+        .await
+        .unwrap();
+    }
+
+    for i in 0..MESSAGES {
+        match rx.try_recv().unwrap().inner {
+            NodeEvent::Input {
+                id,
+                data: Some(DataMessage::Vec(data)),
+                ..
+            } => {
+                let expected = if i % 2 == 0 { "pose" } else { "status" };
+                assert_eq!(id.as_str(), expected);
+                assert_eq!(u32::from_le_bytes(data[..].try_into().unwrap()), i);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn batched_inputs_keep_publish_order() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: tracker
+    path: tracker
+    outputs:
+      - pose
+      - status
+  - id: camera
+    path: camera
+    outputs:
+      - image
+  - id: planner
+    path: planner
+    inputs:
+      pose:
+        source: tracker/pose
+        batch: { max: 10, max_delay: 1s }
+      status: tracker/status
+      image:
+        source: camera/image
+        batch: { max: 10, max_delay: 1s }
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow = RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes);
+    for node in dataflow.resolved_nodes.clone() {
+        dataflow.register_inputs(&node, true);
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    dataflow
+        .subscribe_channels
+        .insert(NodeId::from("planner".to_owned()), tx.into());
+
+    let clock = HLC::default();
+    let sends = [
+        ("tracker", "pose"),
+        ("tracker", "pose"),
+        ("camera", "image"),
+        ("tracker", "pose"),
+        ("tracker", "status"),
+    ];
+    for (node, output) in sends {
+        let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        send_output_to_local_receivers(
+            NodeId::from(node.to_owned()),
+            DataId::from(output.to_owned()),
+            &mut dataflow,
+            &metadata,
+            None,
+            &clock,
+        )
+        .await
+        .unwrap();
+    }
+
+    // the pending poses are sent before the newer status
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::InputBatch { id, messages } => {
+            assert_eq!(id.as_str(), "pose");
+            assert_eq!(messages.len(), 3);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::Input { id, .. } => assert_eq!(id.as_str(), "status"),
+        other => panic!("unexpected event {other:?}"),
+    }
+    // messages of other senders stay batched
+    assert!(rx.try_recv().is_err());
+    dataflow.send_input_batches(|_, _| true);
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::InputBatch { id, messages } => {
+            assert_eq!(id.as_str(), "image");
+            assert_eq!(messages.len(), 1);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn fan_in_input_closes_with_last_source() {
+    // the receiver subscribes before its sources exit, or only afterwards
+    for subscribe_late in [false, true] {
+        let clock = HLC::default();
+        let mut dataflow = fan_in_dataflow();
+        let robot = NodeId::from("robot".to_owned());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut sender = Some(NodeEventSender::from(tx));
+        if !subscribe_late {
+            let sender = sender.take().unwrap();
+            Daemon::subscribe(&mut dataflow, robot.clone(), sender, &clock).await;
+        }
+
+        // input stays open as long as one of its sources is still open
+        close_outputs_of(&mut dataflow, "joystick", &clock).await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(dataflow.open_inputs(&robot).len(), 1);
+
+        let mut sent = None;
+        if !subscribe_late {
+            send_output(&mut dataflow, "planner", &clock).await;
+            match rx.try_recv().unwrap().inner {
+                NodeEvent::Input { metadata, .. } => sent = Some(metadata.timestamp()),
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+
+        close_outputs_of(&mut dataflow, "planner", &clock).await;
+        if let Some(sender) = sender {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Daemon::subscribe(&mut dataflow, robot.clone(), sender, &clock).await;
+        }
+
+        // the fan-in input is reported as closed exactly once
+        match rx.try_recv().unwrap().inner {
+            NodeEvent::InputClosed {
+                id,
+                delivered,
+                last_timestamp,
+            } => {
+                assert_eq!(id.as_str(), "command");
+                assert_eq!(delivered, u64::from(sent.is_some()));
+                assert_eq!(last_timestamp, sent);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+        assert!(matches!(rx.try_recv().unwrap().inner, NodeEvent::Stop));
+        assert!(matches!(
+            rx.try_recv().unwrap().inner,
+            NodeEvent::AllInputsClosed
+        ));
+        assert!(rx.try_recv().is_err());
+        assert!(dataflow.open_inputs(&robot).is_empty());
+    }
+}
+
+#[tokio::test]
+async fn timer_inputs_do_not_keep_nodes_running() {
+    let clock = HLC::default();
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: camera
+    path: camera
+    outputs:
+      - image
+  - id: detector
+    path: detector
+    inputs:
+      image: camera/image
+      tick: dora/timer/millis/100
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow =
+        RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes.clone());
+    for node in &nodes {
+        dataflow.register_inputs(node, true);
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let detector = NodeId::from("detector".to_owned());
+    dataflow
+        .subscribe_channels
+        .insert(detector.clone(), tx.into());
+
+    close_outputs_of(&mut dataflow, "camera", &clock).await;
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::InputClosed { id, .. } => assert_eq!(id.as_str(), "image"),
+        other => panic!("unexpected event {other:?}"),
+    }
+    // the timer input is still open, but the node has nothing to process
+    assert!(matches!(rx.try_recv().unwrap().inner, NodeEvent::Stop));
+    assert!(rx.try_recv().is_err());
+    assert!(dataflow.input_closed_stops.contains(&detector));
+}
+
+#[tokio::test]
+async fn events_are_filtered_by_interest() {
+    let clock = HLC::default();
+    let mut dataflow = fan_in_dataflow();
+    let robot = NodeId::from("robot".to_owned());
+    let joystick = NodeId::from("joystick".to_owned());
+
+    // the event stream of a pure source is not closed right away
+    let (tx, mut source_rx) = mpsc::unbounded_channel();
+    let sender = NodeEventSender::new(tx, EventInterest::CONTROL);
+    Daemon::subscribe(&mut dataflow, joystick.clone(), sender, &clock).await;
+    assert!(source_rx.try_recv().is_err());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let sender = NodeEventSender::new(tx, EventInterest::STOP);
+    Daemon::subscribe(&mut dataflow, robot.clone(), sender, &clock).await;
+    assert!(rx.try_recv().is_err());
+
+    // inputs are not delivered, so the sender doesn't wait for drop tokens
+    let memory = ShmemConf::new().size(64).create().unwrap();
+    let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+    send_output_to_local_receivers(
+        joystick,
+        DataId::from("cmd".to_owned()),
+        &mut dataflow,
+        &metadata,
+        Some(DataMessage::SharedMemory {
+            shared_memory_id: memory.get_os_id().to_owned(),
+            len: 64,
+            drop_token: DropToken::generate(),
+        }),
+        &clock,
+    )
+    .await
+    .unwrap();
+    assert!(dataflow.pending_drop_tokens.is_empty());
+    assert!(rx.try_recv().is_err());
+
+    close_outputs_of(&mut dataflow, "joystick", &clock).await;
+    close_outputs_of(&mut dataflow, "planner", &clock).await;
+    assert!(matches!(rx.try_recv().unwrap().inner, NodeEvent::Stop));
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn lifecycle_events_are_delivered_to_subscribers() {
+    use dora_node_api::{arrow::array::AsArray, RawData};
+
+    let clock = HLC::default();
+    let mut dataflow = fan_in_dataflow();
+    let robot = NodeId::from("robot".to_owned());
+    let joystick = NodeId::from("joystick".to_owned());
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    dataflow.subscribe_channels.insert(robot.clone(), tx.into());
+    dataflow.lifecycle_subscribers.insert(robot.clone());
+
+    let crashed = LifecycleEvent::NodeCrashed {
+        node_id: joystick,
+        error: "exited with code 1".into(),
+    };
+    dataflow.send_lifecycle_event(crashed.clone(), &clock);
+    // subscribers are not informed about themselves
+    dataflow.send_lifecycle_event(LifecycleEvent::NodeStarted { node_id: robot }, &clock);
+    dataflow.send_lifecycle_event(LifecycleEvent::DataflowStopping, &clock);
+
+    let mut received = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        let NodeEvent::Input {
+            id,
+            metadata,
+            data: Some(DataMessage::Vec(data)),
+        } = event.inner
+        else {
+            panic!("unexpected event {:?}", event.inner);
+        };
+        assert_eq!(id.as_str(), LIFECYCLE_INPUT);
+        let array = RawData::Vec(data)
+            .into_arrow_array(&metadata.type_info)
+            .unwrap();
+        let json = dora_node_api::arrow::array::make_array(array)
+            .as_string::<i32>()
+            .value(0)
+            .to_owned();
+        received.push(serde_json::from_str::<LifecycleEvent>(&json).unwrap());
+    }
+    assert_eq!(received, [crashed, LifecycleEvent::DataflowStopping]);
+}
+
+#[tokio::test]
+async fn pending_drop_tokens_are_released() {
+    enum Release {
+        /// The receiver exits without reporting the message as dropped.
+        ReceiverExited,
+        /// The receiver doesn't report the message as dropped in time.
+        Stale,
+    }
+
+    for release in [Release::ReceiverExited, Release::Stale] {
+        let clock = HLC::default();
+        let mut dataflow = fan_in_dataflow();
+        let robot = NodeId::from("robot".to_owned());
+        let joystick = NodeId::from("joystick".to_owned());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        dataflow.subscribe_channels.insert(robot.clone(), tx.into());
+        let (drop_tx, mut drop_rx) = mpsc::unbounded_channel();
+        dataflow.drop_channels.insert(joystick.clone(), drop_tx);
+
+        let memory = ShmemConf::new().size(64).create().unwrap();
+        let drop_token = DropToken::generate();
+        let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        send_output_to_local_receivers(
+            joystick,
+            DataId::from("cmd".to_owned()),
+            &mut dataflow,
+            &metadata,
+            Some(DataMessage::SharedMemory {
+                shared_memory_id: memory.get_os_id().to_owned(),
+                len: 64,
+                drop_token,
+            }),
+            &clock,
+        )
+        .await
+        .unwrap();
+        assert_eq!(dataflow.shared_memory_in_flight(), 64);
+        let diagnostics = dataflow.diagnostics(Instant::now(), 0);
+        assert_eq!(diagnostics.pending_drop_tokens.count, 1);
+        assert_eq!(diagnostics.pending_drop_tokens.bytes, 64);
+
+        match release {
+            Release::ReceiverExited => {
+                dataflow
+                    .release_drop_tokens_of(&robot, &clock)
+                    .await
+                    .unwrap();
+            }
+            Release::Stale => {
+                let released = dataflow
+                    .release_stale_drop_tokens(Duration::from_secs(60), &clock)
+                    .await
+                    .unwrap();
+                assert_eq!(released, (0, 0));
+                let released = dataflow
+                    .release_stale_drop_tokens(Duration::ZERO, &clock)
+                    .await
+                    .unwrap();
+                assert_eq!(released, (1, 64));
+            }
+        }
+        assert!(dataflow.pending_drop_tokens.is_empty());
+        assert_eq!(dataflow.shared_memory_in_flight(), 0);
+        assert_eq!(dataflow.shared_memory.peak(), 64);
+        match drop_rx.try_recv().unwrap().inner {
+            NodeDropEvent::OutputDropped { drop_token: token } => assert_eq!(token, drop_token),
+        }
+    }
+}
+
+#[test]
+fn output_rings_of_exited_nodes_are_freed() {
+    let mut dataflow = fan_in_dataflow();
+    let robot = NodeId::from("robot".to_owned());
+    let joystick = NodeId::from("joystick".to_owned());
+    let allocate = |owner: &NodeId| {
+        OutputRing::allocate(owner.clone(), DataId::from("cmd".to_owned()), 64, 2).unwrap()
+    };
+    let joystick_ring = OutputRingId::generate();
+    let robot_ring = OutputRingId::generate();
+    dataflow
+        .output_rings
+        .insert(joystick_ring, allocate(&joystick));
+    dataflow.output_rings.insert(robot_ring, allocate(&robot));
+
+    // the robot still reads a slot of the joystick's ring
+    let ring = dataflow.output_rings.get_mut(&joystick_ring).unwrap();
+    let slot_ids = ring.info(joystick_ring).slot_ids;
+    let (_, token) = ring
+        .publish(joystick_ring, 0, 64, Uuid::nil(), |_| false)
+        .unwrap();
+    dataflow.pending_drop_tokens.insert(
+        token,
+        DropTokenInformation {
+            owner: joystick.clone(),
+            len: 64,
+            pending_nodes: [robot.clone()].into(),
+            sent: Instant::now(),
+        },
+    );
+
+    let exists = |os_id: &String| ShmemConf::new().os_id(os_id).open().is_ok();
+    dataflow.free_output_rings(&joystick);
+    assert_eq!(
+        dataflow.output_rings.keys().collect::<Vec<_>>(),
+        [&robot_ring]
+    );
+    assert!(slot_ids.iter().all(exists));
+
+    // the slots are unlinked once the message was dropped
+    dataflow.pending_drop_tokens.clear();
+    dataflow.drop_released_output_rings();
+    assert!(dataflow.released_output_rings.is_empty());
+    assert!(!slot_ids.iter().any(exists));
+}
+
+#[tokio::test]
+async fn latest_input_only_delivers_newest() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: tracker
+    path: tracker
+    outputs:
+      - pose
+  - id: planner
+    path: planner
+    inputs:
+      pose:
+        source: tracker/pose
+        latest: true
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow = RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes);
+    for node in dataflow.resolved_nodes.clone() {
+        dataflow.register_inputs(&node, true);
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let planner = NodeId::from("planner".to_owned());
+    let pose = DataId::from("pose".to_owned());
+    dataflow
+        .subscribe_channels
+        .insert(planner.clone(), tx.into());
+
+    let clock = HLC::default();
+    let mut timestamps = Vec::new();
+    for _ in 0..3 {
+        let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        timestamps.push(metadata.timestamp());
+        send_output_to_local_receivers(
+            NodeId::from("tracker".to_owned()),
+            pose.clone(),
+            &mut dataflow,
+            &metadata,
+            None,
+            &clock,
+        )
+        .await
+        .unwrap();
+    }
+
+    // only a single notification is sent for the pending message
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::LatestAvailable { id } => assert_eq!(id, pose),
+        other => panic!("unexpected event {other:?}"),
+    }
+    assert!(rx.try_recv().is_err());
+
+    let slot = dataflow
+        .inputs
+        .get_mut(&(planner, pose.clone()))
+        .and_then(|state| state.latest.as_mut())
+        .unwrap();
+    match slot.take().unwrap().inner {
+        NodeEvent::Input { id, metadata, .. } => {
+            assert_eq!(id, pose);
+            assert_eq!(metadata.timestamp(), timestamps[2]);
+            assert_eq!(metadata.superseded(), 2);
+        }
+        other => panic!("unexpected event {other:?}"),
+    }
+    assert_eq!(slot.superseded(), 2);
+}
+
+#[tokio::test]
+async fn inputs_are_reconfigured_while_running() {
+    use dora_message::daemon_to_node::DaemonCommunication;
+
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: camera
+    path: camera
+    outputs:
+      - image
+  - id: sink
+    path: sink
+    inputs:
+      image: camera/image
+      tick: dora/timer/millis/100
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow_id = Uuid::new_v4();
+    let mut dataflow = RunningDataflow::new(dataflow_id, String::new(), descriptor.clone(), nodes);
+    for node in dataflow.resolved_nodes.clone() {
+        dataflow.register_inputs(&node, true);
+    }
+    let sink = NodeId::from("sink".to_owned());
+    let image = DataId::from("image".to_owned());
+    let sink_node = |dataflow: &RunningDataflow| {
+        dataflow
+            .resolved_nodes
+            .iter()
+            .find(|node| node.id == sink)
+            .cloned()
+            .unwrap()
+    };
+    let run_config = sink_node(&dataflow).kind.run_config();
+    let input_queues = InputQueues::new([(image.clone(), 10)].into(), BTreeMap::new());
+    dataflow.running_nodes.insert(
+        sink.clone(),
+        RunningNode {
+            pid: None,
+            node_config: NodeConfig {
+                dataflow_id,
+                node_id: sink.clone(),
+                run_config,
+                daemon_communication: DaemonCommunication::Tcp {
+                    socket_addr: ([127, 0, 0, 1], 0).into(),
+                },
+                dataflow_descriptor: descriptor,
+                dynamic: false,
+                dataflow_instance: None,
+                dataflow_params: BTreeMap::new(),
+                drop_token_namespace: Uuid::nil(),
+                daemon_version: None,
+            },
+            input_queues: input_queues.clone(),
+        },
+    );
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    dataflow.subscribe_channels.insert(sink.clone(), tx.into());
+    let now = Instant::now();
+
+    let mut change = InputChange::new(sink.clone(), image.clone());
+    change.queue_size = Some(2);
+    change.policy = Some(QueuePolicy::Latest);
+    change.priority = Some(3);
+    let (settings, _) = dataflow.reconfigure_input(&change, now).unwrap();
+    assert_eq!(settings.queue_size, 2);
+    assert_eq!(settings.policy, QueuePolicy::Latest);
+    assert_eq!(input_queues.settings().sizes[&image], 2);
+    assert_eq!(input_queues.settings().priorities[&image], 3);
+    // the change is kept for reloads of the node
+    let input = &node_inputs(&sink_node(&dataflow))[&image];
+    assert!(input.latest);
+    assert_eq!(input.queue_size, Some(2));
+
+    let clock = HLC::default();
+    let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+    send_output_to_local_receivers(
+        NodeId::from("camera".to_owned()),
+        image.clone(),
+        &mut dataflow,
+        &metadata,
+        None,
+        &clock,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        rx.try_recv().unwrap().inner,
+        NodeEvent::LatestAvailable { .. }
+    ));
+
+    // the pending message is delivered when switching back
+    change.policy = Some(QueuePolicy::DropOldest);
+    let (settings, released_token) = dataflow.reconfigure_input(&change, now).unwrap();
+    assert_eq!(settings.policy, QueuePolicy::DropOldest);
+    assert!(released_token.is_none());
+    assert!(dataflow
+        .inputs
+        .iter()
+        .all(|(_, state)| state.latest.is_none()));
+    match rx.try_recv().unwrap().inner {
+        NodeEvent::Input { id, .. } => assert_eq!(id, image),
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let mut tick = InputChange::new(sink.clone(), DataId::from("tick".to_owned()));
+    tick.timeout = Some(Duration::from_secs(1));
+    assert!(dataflow.reconfigure_input(&tick, now).is_err());
+    let mut empty_queue = InputChange::new(sink.clone(), image.clone());
+    empty_queue.queue_size = Some(0);
+    assert!(dataflow.reconfigure_input(&empty_queue, now).is_err());
+    let unknown = InputChange::new(sink, DataId::from("depth".to_owned()));
+    assert!(dataflow.reconfigure_input(&unknown, now).is_err());
+    let remote = InputChange::new(NodeId::from("camera".to_owned()), image);
+    assert!(dataflow.reconfigure_input(&remote, now).is_err());
+
+    let diagnostics = dataflow.diagnostics(now, 0);
+    assert_eq!(
+        diagnostics.reconfigured_inputs.keys().collect::<Vec<_>>(),
+        ["sink/image"]
+    );
+}
+
+#[tokio::test]
+async fn batched_input_is_delivered_in_batches() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: imu
+    path: imu
+    outputs:
+      - sample
+  - id: filter
+    path: filter
+    inputs:
+      sample:
+        source: imu/sample
+        batch: { max: 2, max_delay: 1s }
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let mut dataflow = RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes);
+    for node in dataflow.resolved_nodes.clone() {
+        dataflow.register_inputs(&node, true);
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let filter = NodeId::from("filter".to_owned());
+    let sample = DataId::from("sample".to_owned());
+    dataflow.subscribe_channels.insert(filter, tx.into());
+
+    let clock = HLC::default();
+    let mut timestamps = Vec::new();
+    for _ in 0..3 {
+        let metadata = metadata::Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        timestamps.push(metadata.timestamp());
+        send_output_to_local_receivers(
+            NodeId::from("imu".to_owned()),
+            sample.clone(),
+            &mut dataflow,
+            &metadata,
+            None,
+            &clock,
+        )
+        .await
+        .unwrap();
+    }
+    let batch_timestamps = |event: Timestamped<NodeEvent>| match event.inner {
+        NodeEvent::InputBatch { id, messages } => {
+            assert_eq!(id, sample);
+            messages
+                .iter()
+                .map(|m| m.metadata.timestamp())
+                .collect::<Vec<_>>()
+        }
+        other => panic!("unexpected event {other:?}"),
+    };
+
+    // the full batch is sent right away, the last message stays pending
+    assert_eq!(batch_timestamps(rx.try_recv().unwrap()), timestamps[..2]);
+    assert!(rx.try_recv().is_err());
+    dataflow.send_input_batches(|_, batch| batch.is_due(Instant::now()));
+    assert!(rx.try_recv().is_err());
+
+    dataflow.send_input_batches(|_, _| true);
+    assert_eq!(batch_timestamps(rx.try_recv().unwrap()), timestamps[2..]);
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn undeclared_outputs_are_rejected() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: camera
+    path: camera
+    outputs:
+      - image
+      - depth
+  - id: viewer
+    path: viewer
+    inputs:
+      image: camera/image
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow = RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes);
+    let camera = NodeId::from("camera".to_owned());
+
+    let typo = DataId::from("imgae".to_owned());
+    assert_eq!(
+        dataflow.check_output_declared(&camera, &typo),
+        Err(SendOutputError::OutputNotDeclared { output_id: typo })
+    );
+    // outputs of other nodes are not declared for this node
+    let viewer = NodeId::from("viewer".to_owned());
+    let image = DataId::from("image".to_owned());
+    assert!(dataflow.check_output_declared(&viewer, &image).is_err());
+}
+
+#[test]
+fn topology_of_fan_in_node() {
+    let dataflow = fan_in_dataflow();
+    let node_ids = |ids: &[&str]| -> BTreeSet<NodeId> {
+        ids.iter().map(|id| NodeId::from(id.to_string())).collect()
+    };
+
+    let robot = dataflow
+        .topology(&NodeId::from("robot".to_owned()))
+        .unwrap();
+    assert_eq!(robot.upstream, node_ids(&["joystick", "planner"]));
+    assert!(robot.downstream.is_empty());
+    let sources: Vec<_> = robot.inputs[&DataId::from("command".to_owned())]
+        .iter()
+        .map(|m| m.to_string())
+        .collect();
+    assert_eq!(sources, ["joystick/cmd", "planner/cmd"]);
+
+    let joystick = dataflow
+        .topology(&NodeId::from("joystick".to_owned()))
+        .unwrap();
+    assert!(joystick.upstream.is_empty());
+    assert_eq!(joystick.downstream, node_ids(&["robot"]));
+
+    assert!(dataflow
+        .topology(&NodeId::from("unknown".to_owned()))
+        .is_err());
+}
+
+#[test]
+fn topology_contains_declared_types() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: camera
+    path: camera
+    outputs:
+      - image
+      - depth
+    output_types:
+      image: sensor_msgs/Image
+  - id: recorder
+    path: recorder
+    inputs:
+      image: camera/image
+      depth: camera/depth
+      tick: dora/timer/millis/100
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow = RunningDataflow::new(Uuid::new_v4(), String::new(), descriptor, nodes);
+    let data_id = |id: &str| DataId::from(id.to_owned());
+
+    let camera = dataflow
+        .topology(&NodeId::from("camera".to_owned()))
+        .unwrap();
+    assert_eq!(
+        camera.output_types,
+        [(data_id("image"), "sensor_msgs/Image".to_owned())].into()
+    );
+
+    let recorder = dataflow
+        .topology(&NodeId::from("recorder".to_owned()))
+        .unwrap();
+    assert_eq!(
+        recorder.input_types,
+        [(data_id("image"), "sensor_msgs/Image".to_owned())].into()
+    );
+    assert!(recorder.output_types.is_empty());
+}
+
+#[test]
+fn resolved_descriptor_yaml_round_trips() {
+    #[derive(serde::Deserialize)]
+    struct ResolvedDescriptor {
+        communication: dora_core::config::CommunicationConfig,
+        nodes: Vec<ResolvedNode>,
+    }
+
+    let dataflow = fan_in_dataflow();
+    let yaml = dataflow.resolved_descriptor_yaml().unwrap();
+    let parsed: ResolvedDescriptor = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        serde_yaml::to_string(&parsed.nodes).unwrap(),
+        serde_yaml::to_string(&dataflow.resolved_nodes).unwrap()
+    );
+    assert_eq!(
+        serde_yaml::to_string(&parsed.communication).unwrap(),
+        serde_yaml::to_string(&dataflow.descriptor.communication).unwrap()
+    );
+    // the fan-in input keeps both of its sources
+    let robot = parsed
+        .nodes
+        .iter()
+        .find(|node| node.id == NodeId::from("robot".to_owned()))
+        .unwrap();
+    let sources: Vec<_> = node_inputs(robot)[&DataId::from("command".to_owned())]
+        .mappings()
+        .map(|m| m.to_string())
+        .collect();
+    assert_eq!(sources, ["joystick/cmd", "planner/cmd"]);
+}
+
+fn temp_working_dir() -> PathBuf {
+    let working_dir = std::env::temp_dir().join(format!("dora-spawn-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&working_dir).unwrap();
+    working_dir
+}
+
+async fn spawn_in_dir(dataflow: &str, working_dir: &Path) -> eyre::Result<DataflowResult> {
+    let descriptor = Descriptor::parse(dataflow.as_bytes().to_vec()).unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    Daemon::run_spawn_command(
+        SpawnDataflowNodes {
+            dataflow_id: Uuid::new_v4(),
+            working_dir: working_dir.to_owned(),
+            machine_working_dir: None,
+            nodes,
+            machine_listen_ports: BTreeMap::new(),
+            dataflow_descriptor: descriptor,
+            inter_daemon_transport: InterDaemonTransport::Tcp,
+            instance: None,
+            params: BTreeMap::new(),
+            dry_run: false,
+        },
+        None,
+        true,
+    )
+    .await
+}
+
+async fn spawn_in_temp_dir(dataflow: &str) -> eyre::Result<DataflowResult> {
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(dataflow, &working_dir).await;
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    result
+}
+
+/// Node options of a process that writes its PID to the given file in
+/// its working dir and then runs for a long time.
+///
+/// The shell `exec`s the sleep, so the written PID is the one that the
+/// daemon records for the node in `running_nodes`.
+#[cfg(unix)]
+fn long_running_node(pid_file: &str) -> String {
+    format!("path: shell\n    args: \"echo $$ > {pid_file} && exec sleep 1200\"")
+}
+
+/// Returns the PID that a [`long_running_node`] wrote to the given file,
+/// or `None` if the node was never started.
+#[cfg(unix)]
+fn node_pid(working_dir: &Path, pid_file: &str) -> Option<Pid> {
+    let pid = std::fs::read_to_string(working_dir.join(pid_file)).ok()?;
+    Some(Pid::from(pid.trim().parse::<usize>().unwrap()))
+}
+
+/// Waits until the process with the given PID exited, returns whether
+/// it did before the timeout.
+#[cfg(unix)]
+async fn wait_for_exit(pid: Pid) -> bool {
+    for _ in 0..50 {
+        let mut system = sysinfo::System::new();
+        system.refresh_process(pid);
+        match system.process(pid) {
+            Some(process) if process.status() != sysinfo::ProcessStatus::Zombie => {}
+            _ => return true,
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn missing_executable_prevents_spawn() {
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(
+        &format!(
+            r#"
+nodes:
+  - id: good
+    {}
+  - id: missing
+    path: ./does-not-exist
+"#,
+            long_running_node("good.pid")
+        ),
+        &working_dir,
+    )
+    .await;
+    let good_pid = node_pid(&working_dir, "good.pid");
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    let err = format!("{:?}", result.unwrap_err());
+    assert!(
+        err.contains("node `missing`: could not find executable"),
+        "{err}"
+    );
+    assert!(!err.contains("node `good`"), "{err}");
+    assert_eq!(good_pid, None, "node `good` was started");
+}
+
+#[tokio::test]
+async fn missing_working_dir_prevents_spawn() {
+    let working_dir = std::env::temp_dir().join(format!("dora-missing-{}", Uuid::new_v4()));
+    let result = spawn_in_dir(
+        r#"
+nodes:
+  - id: node
+    path: shell
+    args: "true"
+"#,
+        &working_dir,
+    )
+    .await;
+    let err = format!("{:?}", result.unwrap_err());
+    assert!(
+        err.contains("node `node`: working dir") && err.contains("does not exist"),
+        "unexpected error: {err}"
+    );
+    assert!(!working_dir.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn embedded_daemon_reports_finished_dataflow() {
+    let working_dir = temp_working_dir();
+    let descriptor = Descriptor::parse(
+        "nodes:\n  - id: sleeper\n    path: shell\n    args: \"sleep 0.25\"\n"
+            .as_bytes()
+            .to_vec(),
+    )
+    .unwrap();
+    let daemon = DaemonBuilder::new().build().await.unwrap();
+    let mut notifications = daemon.subscribe_events();
+    let dataflow_id = daemon
+        .spawn_dataflow(descriptor, &working_dir)
+        .await
+        .unwrap();
+
+    let sleeper = NodeId::from("sleeper".to_owned());
+    let timeout = Duration::from_secs(30);
+    let Some(DaemonNotification::NodeFinished {
+        dataflow_id: id,
+        node_id,
+        result,
+    }) = tokio::time::timeout(timeout, notifications.next())
+        .await
+        .unwrap()
+    else {
+        panic!("expected node result first");
+    };
+    assert_eq!((id, &node_id), (dataflow_id, &sleeper));
+    assert!(result.is_ok());
+    let Some(DaemonNotification::DataflowFinished {
+        dataflow_id: id,
+        result,
+    }) = tokio::time::timeout(timeout, notifications.next())
+        .await
+        .unwrap()
+    else {
+        panic!("expected dataflow result");
+    };
+    assert_eq!(id, dataflow_id);
+    assert!(result.node_results[&sleeper].is_ok());
+
+    daemon.shutdown().await.unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn plan_does_not_spawn_nodes() {
+    let working_dir = temp_working_dir();
+    let dataflow_path = working_dir.join("dataflow.yml");
+    std::fs::write(
+        &dataflow_path,
+        format!(
+            r#"
+start_order: dependency
+nodes:
+  - id: source
+    {}
+    inputs:
+      tick: dora/timer/millis/100
+    outputs:
+      - value
+  - id: sink
+    {}
+    inputs:
+      value: source/value
+"#,
+            long_running_node("source.pid"),
+            long_running_node("sink.pid"),
+        ),
+    )
+    .unwrap();
+
+    let plan = Daemon::plan_dataflow(&dataflow_path).await;
+    let pids = [
+        node_pid(&working_dir, "source.pid"),
+        node_pid(&working_dir, "sink.pid"),
+    ];
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    let plan = plan.unwrap();
+    assert_eq!(pids, [None, None], "nodes were started");
+
+    let layers: Vec<_> = plan
+        .nodes
+        .iter()
+        .map(|n| (n.id.to_string(), n.layer))
+        .collect();
+    assert_eq!(layers, [("source".into(), 1), ("sink".into(), 2)]);
+    let sink = &plan.nodes[1];
+    assert!(sink
+        .command
+        .as_ref()
+        .unwrap()
+        .args
+        .iter()
+        .any(|a| a.contains("sink.pid")));
+    assert_eq!(
+        sink.inputs[&DataId::from("value".to_owned())],
+        ["source/value"]
+    );
+    assert_eq!(plan.timers[&Duration::from_millis(100)], ["source/tick"]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn missing_node_working_dir_prevents_spawn() {
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(
+        &format!(
+            r#"
+nodes:
+  - id: good
+    {}
+    working_dir: {{ path: created, create: true }}
+  - id: misplaced
+    {}
+    working_dir: does-not-exist
+"#,
+            long_running_node("good.pid"),
+            long_running_node("misplaced.pid"),
+        ),
+        &working_dir,
+    )
+    .await;
+    let good_pid = node_pid(&working_dir.join("created"), "good.pid");
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    let err = format!("{:?}", result.unwrap_err());
+    assert!(
+        err.contains("node `misplaced`: invalid working dir: directory"),
+        "{err}"
+    );
+    assert!(!err.contains("node `good`"), "{err}");
+    assert_eq!(good_pid, None, "node `good` was started");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn nodes_run_in_resolved_working_dirs() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: plain
+    path: shell
+    args: "pwd > plain.txt"
+  - id: nested
+    path: shell
+    args: "pwd > nested.txt"
+    working_dir: { path: out/nested, create: true }
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow_id = Uuid::new_v4();
+    let default_working_dir = temp_working_dir().canonicalize().unwrap();
+    let clock = Arc::new(HLC::default());
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let spawn = Timestamped {
+        inner: Event::Coordinator(CoordinatorEvent {
+            event: DaemonCoordinatorEvent::Spawn(SpawnDataflowNodes {
+                dataflow_id,
+                // the working dir of the submitting machine is ignored
+                working_dir: default_working_dir.join("elsewhere"),
+                machine_working_dir: None,
+                nodes,
+                machine_listen_ports: BTreeMap::new(),
+                dataflow_descriptor: descriptor,
+                inter_daemon_transport: InterDaemonTransport::Tcp,
+                instance: None,
+                params: BTreeMap::new(),
+                dry_run: false,
+            }),
+            reply_tx,
+        }),
+        timestamp: clock.new_timestamp(),
+    };
+    let plain = NodeId::from("plain".to_owned());
+    let nested = NodeId::from("nested".to_owned());
+    let exit_when_done = [(dataflow_id, plain.clone()), (dataflow_id, nested.clone())].into();
+
+    let config = DaemonConfig {
+        exit_when_done: Some(exit_when_done),
+        default_working_dir: Some(default_working_dir.clone()),
+        ..DaemonConfig::new(clock)
+    };
+    let run = Daemon::run_general(Box::pin(stream::once(async { spawn })), config);
+    let result = tokio::time::timeout(Duration::from_secs(30), run).await;
+    let read = |path: &str| {
+        std::fs::read_to_string(default_working_dir.join(path)).map(|s| s.trim().to_owned())
+    };
+    let (plain_cwd, nested_cwd) = (read("plain.txt"), read("out/nested/nested.txt"));
+    std::fs::remove_dir_all(&default_working_dir).unwrap();
+    let node_results = result.expect("daemon did not exit").unwrap();
+    assert!(node_results[&dataflow_id].is_ok());
+
+    let nested_dir = default_working_dir.join("out").join("nested");
+    assert_eq!(plain_cwd.unwrap(), default_working_dir.to_str().unwrap());
+    assert_eq!(nested_cwd.unwrap(), nested_dir.to_str().unwrap());
+    match reply_rx.await.unwrap() {
+        Some(DaemonCoordinatorReply::SpawnResult(Ok(dirs))) => {
+            assert_eq!(
+                dirs,
+                [(nested, nested_dir), (plain, default_working_dir)].into()
+            );
+        }
+        other => panic!("unexpected spawn reply: {other:?}"),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn dataflow_instances_use_prefixed_out_dirs() {
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: env-dump
+    path: shell
+    args: "printenv DORA_DATAFLOW_INSTANCE > instance.txt"
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow_id = Uuid::new_v4();
+    let working_dir = temp_working_dir().canonicalize().unwrap();
+    let clock = Arc::new(HLC::default());
+    let (reply_tx, _reply_rx) = oneshot::channel();
+    let spawn = Timestamped {
+        inner: Event::Coordinator(CoordinatorEvent {
+            event: DaemonCoordinatorEvent::Spawn(SpawnDataflowNodes {
+                dataflow_id,
+                working_dir: working_dir.clone(),
+                machine_working_dir: None,
+                nodes,
+                machine_listen_ports: BTreeMap::new(),
+                dataflow_descriptor: descriptor,
+                inter_daemon_transport: InterDaemonTransport::Tcp,
+                instance: Some(DataflowInstance {
+                    name: "sweep".into(),
+                    key: "1".into(),
+                }),
+                params: BTreeMap::new(),
+                dry_run: false,
+            }),
+            reply_tx,
+        }),
+        timestamp: clock.new_timestamp(),
+    };
+    let exit_when_done = [(dataflow_id, NodeId::from("env-dump".to_owned()))].into();
+
+    let config = DaemonConfig {
+        exit_when_done: Some(exit_when_done),
+        ..DaemonConfig::new(clock)
+    };
+    let run = Daemon::run_general(Box::pin(stream::once(async { spawn })), config);
+    let result = tokio::time::timeout(Duration::from_secs(30), run).await;
+    let instance = std::fs::read_to_string(working_dir.join("instance.txt"));
+    let log_file = working_dir
+        .join("out")
+        .join(format!("sweep-1_{dataflow_id}"))
+        .join("log_env-dump.txt");
+    let log_file_exists = log_file.exists();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    let node_results = result.expect("daemon did not exit").unwrap();
+    assert!(node_results[&dataflow_id].is_ok());
+    assert_eq!(instance.unwrap().trim(), "sweep#1");
+    assert!(log_file_exists);
+}
+
+/// Collects the fields of all events, merged with the fields of the
+/// spans that they were emitted in.
+#[derive(Clone, Default)]
+struct CapturedEvents(Arc<std::sync::Mutex<Vec<BTreeMap<String, String>>>>);
+
+#[derive(Default)]
+struct Fields(BTreeMap<String, String>);
+
+impl tracing::field::Visit for Fields {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_owned(), format!("{value:?}"));
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for CapturedEvents
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        for span in ctx.event_scope(event).into_iter().flatten() {
+            if let Some(span_fields) = span.extensions().get::<Fields>() {
+                for (name, value) in &span_fields.0 {
+                    fields
+                        .0
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+        }
+        self.0.lock().unwrap().push(fields.0);
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn log_events_carry_dataflow_node_and_machine_ids() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: quiet
+    path: shell
+    args: "true"
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow_id = Uuid::new_v4();
+    let working_dir = temp_working_dir();
+    let clock = Arc::new(HLC::default());
+    let (reply_tx, _reply_rx) = oneshot::channel();
+    let spawn = Timestamped {
+        inner: Event::Coordinator(CoordinatorEvent {
+            event: DaemonCoordinatorEvent::Spawn(SpawnDataflowNodes {
+                dataflow_id,
+                working_dir: working_dir.clone(),
+                machine_working_dir: None,
+                nodes,
+                machine_listen_ports: BTreeMap::new(),
+                dataflow_descriptor: descriptor,
+                inter_daemon_transport: InterDaemonTransport::Tcp,
+                instance: None,
+                params: BTreeMap::new(),
+                dry_run: false,
+            }),
+            reply_tx,
+        }),
+        timestamp: clock.new_timestamp(),
+    };
+    let exit_when_done = [(dataflow_id, NodeId::from("quiet".to_owned()))].into();
+
+    let config = DaemonConfig {
+        machine_id: "machine-a".into(),
+        exit_when_done: Some(exit_when_done),
+        ..DaemonConfig::new(clock)
+    };
+    let run = Daemon::run_general(Box::pin(stream::once(async { spawn })), config);
+    let result = tokio::time::timeout(Duration::from_secs(30), run).await;
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    let node_results = result.expect("daemon did not exit").unwrap();
+    assert!(node_results[&dataflow_id].is_ok());
+
+    let events = captured.0.lock().unwrap();
+    let event = |message: &str| {
+        events
+            .iter()
+            .find(|fields| fields.get("message").is_some_and(|m| m.contains(message)))
+            .unwrap_or_else(|| panic!("no event `{message}` in {events:#?}"))
+    };
+    // emitted while spawning the dataflow and on the exit of the node
+    for message in ["Spawning node", "finished successfully"] {
+        let fields = event(message);
+        assert_eq!(
+            fields.get("machine_id").map(String::as_str),
+            Some("machine-a")
+        );
+        assert_eq!(fields.get("dataflow_id"), Some(&dataflow_id.to_string()));
+        assert_eq!(fields.get("node_id").map(String::as_str), Some("quiet"));
+    }
+}
+
+#[tokio::test]
+async fn failed_spawn_ends_daemon_run() {
+    // passes the checks before spawning, but the download fails on spawn
+    let descriptor = Descriptor::parse(
+        r#"
+nodes:
+  - id: unreachable
+    path: http://127.0.0.1:1/node
+"#
+        .as_bytes()
+        .to_vec(),
+    )
+    .unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    let dataflow_id = Uuid::new_v4();
+    let working_dir = temp_working_dir();
+    let clock = Arc::new(HLC::default());
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let spawn = Timestamped {
+        inner: Event::Coordinator(CoordinatorEvent {
+            event: DaemonCoordinatorEvent::Spawn(SpawnDataflowNodes {
+                dataflow_id,
+                working_dir: working_dir.clone(),
+                machine_working_dir: None,
+                nodes,
+                machine_listen_ports: BTreeMap::new(),
+                dataflow_descriptor: descriptor,
+                inter_daemon_transport: InterDaemonTransport::Tcp,
+                instance: None,
+                params: BTreeMap::new(),
+                dry_run: false,
+            }),
+            reply_tx,
+        }),
+        timestamp: clock.new_timestamp(),
+    };
+    let exit_when_done = [(dataflow_id, NodeId::from("unreachable".to_owned()))].into();
+
+    let config = DaemonConfig {
+        exit_when_done: Some(exit_when_done),
+        ..DaemonConfig::new(clock)
+    };
+    let run = Daemon::run_general(Box::pin(stream::once(async { spawn })), config);
+    let result = tokio::time::timeout(Duration::from_secs(30), run).await;
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    let node_results = result.expect("daemon did not exit").unwrap();
+    assert!(!node_results.contains_key(&dataflow_id));
+
+    match reply_rx.await.unwrap() {
+        Some(DaemonCoordinatorReply::SpawnResult(Err(err))) => {
+            assert!(err.contains("failed to spawn node `unreachable`"), "{err}")
+        }
+        other => panic!("unexpected spawn reply {other:?}"),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn failed_spawn_kills_started_nodes() {
+    let working_dir = temp_working_dir();
+    // the invalid `expose` entry is only detected after the nodes are spawned
+    let result = spawn_in_dir(
+        &format!(
+            r#"
+nodes:
+  - id: good
+    {}
+    outputs:
+      - tick
+expose:
+  tick: dora/timer/secs/1
+"#,
+            long_running_node("good.pid")
+        ),
+        &working_dir,
+    )
+    .await;
+    let err = format!("{:?}", result.unwrap_err());
+    assert!(err.contains("exposed output `tick`"), "{err}");
+
+    // a node that survived the rollback writes its PID eventually, while
+    // a node that was killed early might not have written it
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    if let Some(pid) = node_pid(&working_dir, "good.pid") {
+        assert!(wait_for_exit(pid).await, "node `good` is still running");
+    }
+    std::fs::remove_dir_all(&working_dir).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn result_summary_is_written_to_result_file() {
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(
+        r#"
+nodes:
+  - id: failing
+    path: shell
+    args: "exit 3"
+result_file: out/result.json
+"#,
+        &working_dir,
+    )
+    .await
+    .unwrap();
+    let written = std::fs::read(working_dir.join("out/result.json")).unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+
+    let summary: DataflowSummary = serde_json::from_slice(&written).unwrap();
+    assert_eq!(result.summary.as_ref(), Some(&summary));
+    assert_eq!(summary.dataflow_id, result.uuid);
+    assert!(summary.start_time_ms <= summary.end_time_ms);
+    let node = &summary.nodes[&NodeId::from("failing".to_owned())];
+    assert!(!node.success);
+    assert_eq!(node.exit_code, Some(3));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn nodes_are_killed_after_ready_timeout() {
+    let result = spawn_in_temp_dir(
+        r#"
+nodes:
+  - id: hanging
+    path: shell
+    args: "exec sleep 30"
+    ready_timeout: 200ms
+"#,
+    )
+    .await
+    .unwrap();
+    let error = result.node_results[&NodeId::from("hanging".to_owned())]
+        .as_ref()
+        .unwrap_err();
+    assert!(matches!(
+        error.cause,
+        NodeErrorCause::StartupTimeout { timeout } if timeout == Duration::from_millis(200)
+    ));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn layers_are_spawned_in_dependency_order() {
+    let working_dir = temp_working_dir();
+    spawn_in_dir(
+        r#"
+start_order: dependency
+start_layer_timeout: 10s
+nodes:
+  - id: sink
+    path: shell
+    args: "echo sink >> order.txt"
+    inputs:
+      value: source/value
+  - id: source
+    path: shell
+    args: "sleep 0.2 && echo source >> order.txt"
+    outputs:
+      - value
+"#,
+        &working_dir,
+    )
+    .await
+    .unwrap();
+    let order = std::fs::read_to_string(working_dir.join("order.txt")).unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    assert_eq!(order.lines().collect::<Vec<_>>(), ["source", "sink"]);
+}
+
+#[test]
+fn ready_nodes_are_not_timed_out() {
+    let mut dataflow = fan_in_dataflow();
+    let [joystick, planner] = ["joystick", "planner"].map(|id| NodeId::from(id.to_owned()));
+    let timeout = Duration::from_secs(5);
+    dataflow.ready_timeouts.insert(joystick.clone(), timeout);
+    dataflow.ready_timeouts.insert(planner.clone(), timeout);
+
+    dataflow.mark_ready(&joystick);
+    dataflow.handle_ready_timeout(&joystick);
+    dataflow.handle_ready_timeout(&planner);
+    assert_eq!(
+        dataflow.startup_timeout_kills,
+        BTreeMap::from([(planner, timeout)])
+    );
+    assert!(dataflow.ready_timeouts.is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn spawned_nodes_get_standard_env_variables() {
+    const DATAFLOW: &str = r#"
+nodes:
+  - id: env-dump
+    path: shell
+    args: "printenv DORA_NODE_CONFIG > node_config.yaml && printenv DORA_NODE_ID DORA_DATAFLOW_ID > ids.txt"
+    inputs:
+      tick: dora/timer/millis/100
+    outputs:
+      - out
+"#;
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(DATAFLOW, &working_dir).await.unwrap();
+    let node_config = std::fs::read_to_string(working_dir.join("node_config.yaml")).unwrap();
+    let ids = std::fs::read_to_string(working_dir.join("ids.txt")).unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    assert!(result.node_results.values().all(|r| r.is_ok()));
+
+    let node_config: NodeConfig = serde_yaml::from_str(&node_config).unwrap();
+    let descriptor = Descriptor::parse(DATAFLOW.as_bytes().to_vec()).unwrap();
+    let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+    assert_eq!(node_config.node_id, nodes[0].id);
+    assert_eq!(node_config.dataflow_id, result.uuid);
+    assert_eq!(node_config.run_config, nodes[0].kind.run_config());
+    assert_eq!(
+        ids.lines().collect::<Vec<_>>(),
+        ["env-dump".to_owned(), result.uuid.to_string()]
+    );
+}
+
+/// Node of `ctrl_c_stops_node_cleanly`, run through the test binary.
+///
+/// Sends a message, interrupts itself like Ctrl-C does, and checks that
+/// the signal stops the node instead of killing it.
+#[cfg(unix)]
+#[test]
+#[ignore = "spawned as a node by `ctrl_c_stops_node_cleanly`"]
+fn ctrl_c_node() {
+    if std::env::var(dora_message::daemon_to_node::env::DORA_NODE_CONFIG).is_err() {
+        return;
+    }
+    let (mut node, mut events) = dora_node_api::DoraNode::init_from_env().unwrap();
+    let out = DataId::from("out".to_owned());
+    node.send_output_bytes(out.clone(), Default::default(), 3, &[1, 2, 3])
+        .unwrap();
+
+    let pid = std::process::id().to_string();
+    let kill = std::process::Command::new("kill")
+        .args(["-INT", &pid])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    loop {
+        match events.recv() {
+            Some(dora_node_api::Event::Stop) => break,
+            Some(_) => continue,
+            None => panic!("event stream ended without stop event"),
+        }
+    }
+    // the outputs were reported as done before the stop event
+    let err = node
+        .send_output_bytes(out, Default::default(), 3, &[1, 2, 3])
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("stopped already"), "{err:?}");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn ctrl_c_stops_node_cleanly() {
+    let test_binary = std::env::current_exe().unwrap();
+    let result = spawn_in_temp_dir(&format!(
+        r#"
+nodes:
+  - id: interrupted
+    path: {}
+    args: "--exact tests::ctrl_c_node --ignored --nocapture"
+    outputs:
+      - out
+"#,
+        test_binary.display()
+    ))
+    .await
+    .unwrap();
+    let interrupted = NodeId::from("interrupted".to_owned());
+    assert!(
+        result.node_results[&interrupted].is_ok(),
+        "{:?}",
+        result.node_results
+    );
+}
+
+/// Node of `stop_requests_need_permission`, which is not allowed to stop
+/// the dataflow.
+#[test]
+#[ignore]
+fn denied_stop_request_node() {
+    if std::env::var(dora_message::daemon_to_node::env::DORA_NODE_CONFIG).is_err() {
+        return;
+    }
+    let (mut node, mut events) = dora_node_api::DoraNode::init_from_env().unwrap();
+    let err = node.request_stop(None).unwrap_err();
+    match err.downcast_ref::<dora_node_api::StopRequestError>() {
+        Some(dora_node_api::StopRequestError::PermissionDenied { node_id }) => {
+            assert_eq!(node_id.as_ref(), "observer")
+        }
+        other => panic!("unexpected error {other:?}: {err:?}"),
+    }
+    // tell the controller that it can stop the dataflow now
+    node.send_output_bytes(
+        DataId::from("denied".to_owned()),
+        Default::default(),
+        0,
+        &[],
+    )
+    .unwrap();
+    loop {
+        match events.recv() {
+            Some(dora_node_api::Event::Stop) => break,
+            Some(_) => continue,
+            None => panic!("event stream ended without stop event"),
+        }
+    }
+}
+
+/// Node of `stop_requests_need_permission` with `allowed_to_stop: true`.
+#[test]
+#[ignore]
+fn allowed_stop_request_node() {
+    if std::env::var(dora_message::daemon_to_node::env::DORA_NODE_CONFIG).is_err() {
+        return;
+    }
+    let (mut node, mut events) = dora_node_api::DoraNode::init_from_env().unwrap();
+    loop {
+        match events.recv() {
+            Some(dora_node_api::Event::Input { id, .. }) if id.as_str() == "denied" => {
+                node.request_stop(None).unwrap();
+            }
+            Some(dora_node_api::Event::Stop) => break,
+            Some(_) => continue,
+            None => panic!("event stream ended without stop event"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn stop_requests_need_permission() {
+    let test_binary = std::env::current_exe().unwrap();
+    let result = spawn_in_temp_dir(&format!(
+        r#"
+nodes:
+  - id: observer
+    path: {test_binary}
+    args: "--exact tests::denied_stop_request_node --ignored --nocapture"
+    outputs:
+      - denied
+  - id: controller
+    path: {test_binary}
+    args: "--exact tests::allowed_stop_request_node --ignored --nocapture"
+    allowed_to_stop: true
+    inputs:
+      denied: observer/denied
+"#,
+        test_binary = test_binary.display()
+    ))
+    .await
+    .unwrap();
+    assert!(
+        result.node_results.values().all(|r| r.is_ok()),
+        "{:?}",
+        result.node_results
+    );
+    // the node that stopped the dataflow is reported as initiator
+    let summary = result.summary.unwrap();
+    assert_eq!(
+        summary.stopped_by,
+        Some(NodeId::from("controller".to_owned()))
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn raw_nodes_exchange_messages() {
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(
+        r#"
+nodes:
+  - id: producer
+    path: shell
+    args: "echo hello && echo world"
+    raw: true
+    framing: text
+    outputs:
+      - lines
+  - id: consumer
+    path: shell
+    args: "cat > received.txt"
+    raw: true
+    framing: text
+    inputs:
+      lines: producer/lines
+"#,
+        &working_dir,
+    )
+    .await
+    .unwrap();
+    let received = std::fs::read_to_string(working_dir.join("received.txt")).unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    assert!(
+        result.node_results.values().all(|r| r.is_ok()),
+        "{:?}",
+        result.node_results
+    );
+    assert_eq!(received, "hello\nworld\n");
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn spawned_nodes_get_standard_env_variables() {
+    let working_dir = temp_working_dir();
+    let result = spawn_in_dir(
+        r#"
+nodes:
+  - id: env-dump
+    path: shell
+    args: "echo %DORA_NODE_ID%> ids.txt && echo %DORA_DATAFLOW_ID%>> ids.txt"
+    inputs:
+      tick: dora/timer/millis/100
+    outputs:
+      - out
+"#,
+        &working_dir,
+    )
+    .await
+    .unwrap();
+    let ids = std::fs::read_to_string(working_dir.join("ids.txt")).unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    assert!(result.node_results.values().all(|r| r.is_ok()));
+    assert_eq!(
+        ids.lines().map(str::trim).collect::<Vec<_>>(),
+        ["env-dump".to_owned(), result.uuid.to_string()]
+    );
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn batch_file_sources_without_extension() {
+    let working_dir = temp_working_dir();
+    std::fs::write(
+        working_dir.join("node.bat"),
+        "@echo off\r\necho %DORA_NODE_ID%> id.txt\r\n",
+    )
+    .unwrap();
+    let result = spawn_in_dir(
+        r#"
+nodes:
+  - id: batch-node
+    path: ./node
+    inputs:
+      tick: dora/timer/millis/100
+"#,
+        &working_dir,
+    )
+    .await
+    .unwrap();
+    let id = std::fs::read_to_string(working_dir.join("id.txt")).unwrap();
+    std::fs::remove_dir_all(&working_dir).unwrap();
+    assert!(result.node_results.values().all(|r| r.is_ok()));
+    assert_eq!(id.trim(), "batch-node");
+}
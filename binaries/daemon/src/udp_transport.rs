@@ -0,0 +1,443 @@
+//! Best-effort transport for outputs with `remote_transport: udp`.
+//!
+//! The sending daemon splits each message into datagrams of at most the
+//! configured datagram size and sends them to the inter-daemon address of
+//! the receiving machine, i.e. to the UDP port with the same number as its
+//! TCP listener. Each datagram is a serialized
+//! [`InterDaemonEvent::OutputDatagram`], which carries the sequence number
+//! of the message on the output and the index of the datagram within the
+//! message. All other outputs and events still use the TCP connections.
+//!
+//! The receiving daemon reassembles the messages of each output. The
+//! datagrams of a message may arrive in any order, but messages are
+//! delivered in order: once a message is complete, all older messages of
+//! the output that are still incomplete are counted as lost. At most
+//! [`REORDER_WINDOW`] messages per output are reassembled at the same time,
+//! older ones are counted as lost when a newer message starts.
+
+use crate::OutputId;
+use aligned_vec::{AVec, ConstAlign};
+use dora_core::{
+    config::{DataId, NodeId},
+    uhlc,
+};
+use dora_message::{
+    common::Timestamped,
+    daemon_to_daemon::{InterDaemonEvent, OutputDatagram},
+    diagnostics::DatagramStats,
+    metadata::Metadata,
+};
+use eyre::{bail, Context};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::Arc,
+};
+use tokio::net::UdpSocket;
+
+/// Default maximum size of the datagrams, which fits into the common
+/// Ethernet MTU of 1500 bytes together with the IP and UDP headers.
+pub const DEFAULT_UDP_DATAGRAM_SIZE: usize = 1400;
+
+/// Smallest datagram size that leaves room for the data of typical messages
+/// next to their metadata.
+const MIN_DATAGRAM_SIZE: usize = 512;
+
+/// Largest datagram size that UDP over IPv4 supports.
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// Number of messages per output that are reassembled at the same time.
+const REORDER_WINDOW: u64 = 16;
+
+/// Checks that the given datagram size leaves room for the data of the
+/// messages, but is not larger than UDP allows.
+pub fn check_datagram_size(size: usize) -> eyre::Result<()> {
+    if !(MIN_DATAGRAM_SIZE..=MAX_DATAGRAM_SIZE).contains(&size) {
+        bail!(
+            "UDP datagram size must be between {MIN_DATAGRAM_SIZE} and {MAX_DATAGRAM_SIZE} \
+            bytes, got {size}"
+        );
+    }
+    Ok(())
+}
+
+pub struct UdpTransport {
+    socket: Arc<UdpSocket>,
+    datagram_size: usize,
+}
+
+impl UdpTransport {
+    /// Binds a UDP socket to the given address and forwards the datagrams
+    /// that it receives to `events_tx`.
+    ///
+    /// The socket is also used for sending datagrams to other daemons.
+    pub async fn bind(
+        addr: SocketAddr,
+        datagram_size: usize,
+        events_tx: flume::Sender<Timestamped<InterDaemonEvent>>,
+    ) -> eyre::Result<Self> {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .wrap_err_with(|| format!("failed to bind UDP socket to {addr}"))?;
+        let socket = Arc::new(socket);
+        tokio::spawn(receive_loop(socket.clone(), events_tx));
+        Ok(Self {
+            socket,
+            datagram_size,
+        })
+    }
+
+    pub fn datagram_size(&self) -> usize {
+        self.datagram_size
+    }
+
+    /// Sends the given datagrams to the daemon with the given inter-daemon
+    /// address.
+    pub async fn send(&self, target: SocketAddr, datagrams: &[Vec<u8>]) -> eyre::Result<()> {
+        for datagram in datagrams {
+            self.socket
+                .send_to(datagram, target)
+                .await
+                .wrap_err_with(|| format!("failed to send datagram to {target}"))?;
+        }
+        Ok(())
+    }
+}
+
+async fn receive_loop(
+    socket: Arc<UdpSocket>,
+    events_tx: flume::Sender<Timestamped<InterDaemonEvent>>,
+) {
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        let len = match socket.recv_from(&mut buffer).await {
+            Ok((len, _)) => len,
+            Err(err) => {
+                // e.g. an ICMP port unreachable reply to a previous send
+                tracing::debug!("failed to receive datagram: {err}");
+                continue;
+            }
+        };
+        let event: Timestamped<InterDaemonEvent> = match bincode::deserialize(&buffer[..len]) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::warn!("failed to deserialize datagram: {err}");
+                continue;
+            }
+        };
+        if !matches!(event.inner, InterDaemonEvent::OutputDatagram(_)) {
+            tracing::warn!("ignoring unexpected datagram: {:?}", event.inner);
+            continue;
+        }
+        if events_tx.send_async(event).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Splits a message into serialized datagrams of at most `datagram_size`
+/// bytes.
+///
+/// The `index`, `total`, `len`, and `data` fields of the given header are
+/// overwritten.
+pub fn split_message(
+    header: OutputDatagram,
+    data: &[u8],
+    timestamp: uhlc::Timestamp,
+    datagram_size: usize,
+) -> eyre::Result<Vec<Vec<u8>>> {
+    let serialize = |datagram: OutputDatagram| {
+        bincode::serialize(&Timestamped {
+            inner: InterDaemonEvent::OutputDatagram(datagram),
+            timestamp,
+        })
+        .wrap_err("failed to serialize output datagram")
+    };
+    // integers are encoded with a fixed size, so the overhead doesn't depend
+    // on the field values
+    let overhead = serialize(OutputDatagram {
+        data: Vec::new(),
+        ..header.clone()
+    })?
+    .len();
+    let Some(payload) = datagram_size.checked_sub(overhead).filter(|p| *p > 0) else {
+        bail!(
+            "header of `{}/{}` doesn't fit into a datagram of {datagram_size} bytes",
+            header.node_id,
+            header.output_id
+        );
+    };
+    let total = u32::try_from(data.len().div_ceil(payload).max(1))
+        .context("message is too large to be sent as datagrams")?;
+    let mut metadata = header.metadata.clone();
+    (0..total)
+        .map(|index| {
+            let start = index as usize * payload;
+            let end = (start + payload).min(data.len());
+            serialize(OutputDatagram {
+                index,
+                total,
+                len: data.len() as u64,
+                metadata: metadata.take(),
+                data: data[start..end].to_vec(),
+                ..header.clone()
+            })
+        })
+        .collect()
+}
+
+/// Sequence numbers of the best-effort outputs of the local nodes, counted
+/// separately for each receiving machine.
+#[derive(Default)]
+pub struct DatagramSequences(HashMap<(OutputId, String), u64>);
+
+impl DatagramSequences {
+    pub fn next(&mut self, output_id: &OutputId, machine: &str) -> u64 {
+        let sequence = self
+            .0
+            .entry((output_id.clone(), machine.to_owned()))
+            .or_default();
+        let next = *sequence;
+        *sequence += 1;
+        next
+    }
+}
+
+/// The messages of best-effort outputs of other machines that are being
+/// reassembled.
+#[derive(Default)]
+pub struct DatagramReassembly {
+    outputs: HashMap<(NodeId, DataId), OutputWindow>,
+}
+
+struct OutputWindow {
+    /// Messages with lower sequence numbers were either delivered or
+    /// counted as lost.
+    next: u64,
+    partial: BTreeMap<u64, PartialMessage>,
+    stats: DatagramStats,
+}
+
+struct PartialMessage {
+    metadata: Option<Metadata>,
+    len: u64,
+    datagrams: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+/// Result of adding a datagram to the [`DatagramReassembly`].
+#[derive(Debug, Default)]
+pub struct Reassembled {
+    /// Number of older messages of the output that were found to be lost.
+    pub lost: u64,
+    /// The message of the datagram, if it is complete now.
+    pub complete: Option<(Metadata, Option<AVec<u8, ConstAlign<128>>>)>,
+}
+
+impl DatagramReassembly {
+    pub fn add(&mut self, datagram: OutputDatagram) -> Reassembled {
+        let OutputDatagram {
+            dataflow_id: _,
+            node_id,
+            output_id,
+            sequence,
+            index,
+            total,
+            len,
+            metadata,
+            data,
+        } = datagram;
+        let window = self
+            .outputs
+            .entry((node_id, output_id))
+            .or_insert_with(|| OutputWindow {
+                next: sequence,
+                partial: BTreeMap::new(),
+                stats: DatagramStats::default(),
+            });
+
+        let mut result = Reassembled::default();
+        if sequence < window.next {
+            if window.next - sequence <= REORDER_WINDOW {
+                // late datagram of a message that was delivered or lost already
+                return result;
+            }
+            // the sequence numbers started over, e.g. because the node was
+            // migrated to another machine
+            window.next = sequence;
+            window.partial.clear();
+        }
+        if sequence >= window.next + REORDER_WINDOW {
+            result.lost += window.skip_to(sequence + 1 - REORDER_WINDOW);
+        }
+
+        let partial = window
+            .partial
+            .entry(sequence)
+            .or_insert_with(|| PartialMessage {
+                metadata: None,
+                len,
+                datagrams: vec![None; total as usize],
+                missing: total as usize,
+            });
+        let Some(slot) = partial.datagrams.get_mut(index as usize) else {
+            tracing::warn!("ignoring datagram with invalid index {index} (total {total})");
+            return result;
+        };
+        if slot.is_none() {
+            *slot = Some(data);
+            partial.missing -= 1;
+        }
+        if index == 0 {
+            partial.metadata = metadata;
+        }
+        if partial.missing > 0 {
+            return result;
+        }
+
+        let partial = window
+            .partial
+            .remove(&sequence)
+            .expect("partial message was inserted above");
+        result.lost += window.skip_to(sequence);
+        window.next = sequence + 1;
+        match partial.assemble() {
+            Some(message) => {
+                window.stats.received += 1;
+                result.complete = Some(message);
+            }
+            None => {
+                tracing::warn!("discarding malformed message of best-effort output");
+                window.stats.lost += 1;
+                result.lost += 1;
+            }
+        }
+        result
+    }
+
+    /// Message counts of the outputs, by `node_id/output_id`.
+    pub fn stats(&self) -> BTreeMap<String, DatagramStats> {
+        self.outputs
+            .iter()
+            .map(|((node_id, output_id), window)| (format!("{node_id}/{output_id}"), window.stats))
+            .collect()
+    }
+}
+
+impl OutputWindow {
+    /// Counts all messages before `next` that are still incomplete as lost.
+    fn skip_to(&mut self, next: u64) -> u64 {
+        let lost = next.saturating_sub(self.next);
+        self.partial = self.partial.split_off(&next);
+        self.next = self.next.max(next);
+        self.stats.lost += lost;
+        lost
+    }
+}
+
+impl PartialMessage {
+    fn assemble(self) -> Option<(Metadata, Option<AVec<u8, ConstAlign<128>>>)> {
+        let metadata = self.metadata?;
+        if self.len == 0 {
+            return Some((metadata, None));
+        }
+        let mut data = AVec::with_capacity(128, usize::try_from(self.len).ok()?);
+        for datagram in self.datagrams {
+            data.extend_from_slice(&datagram?);
+        }
+        (data.len() as u64 == self.len).then_some((metadata, Some(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::uhlc::HLC;
+    use dora_message::metadata::ArrowTypeInfo;
+    use uuid::Uuid;
+
+    fn datagrams(clock: &HLC, sequence: u64, data: &[u8]) -> Vec<OutputDatagram> {
+        let header = OutputDatagram {
+            dataflow_id: Uuid::nil(),
+            node_id: NodeId::from("camera".to_owned()),
+            output_id: DataId::from("preview".to_owned()),
+            sequence,
+            index: 0,
+            total: 0,
+            len: 0,
+            metadata: Some(Metadata::new(
+                clock.new_timestamp(),
+                ArrowTypeInfo::byte_array(data.len()),
+            )),
+            data: Vec::new(),
+        };
+        split_message(header, data, clock.new_timestamp(), 512)
+            .unwrap()
+            .into_iter()
+            .map(|datagram| {
+                assert!(datagram.len() <= 512);
+                match bincode::deserialize::<Timestamped<InterDaemonEvent>>(&datagram)
+                    .unwrap()
+                    .inner
+                {
+                    InterDaemonEvent::OutputDatagram(datagram) => datagram,
+                    other => panic!("unexpected event {other:?}"),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn incomplete_messages_are_counted_as_lost() {
+        let clock = HLC::default();
+        let frame: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let mut reassembly = DatagramReassembly::default();
+
+        // datagrams of a message may arrive in any order
+        let mut first = datagrams(&clock, 0, &frame);
+        assert!(first.len() > 10);
+        first.reverse();
+        let last = first.pop().unwrap();
+        for datagram in first {
+            assert!(reassembly.add(datagram).complete.is_none());
+        }
+        let (_, data) = reassembly.add(last).complete.unwrap();
+        assert_eq!(data.as_deref(), Some(&frame[..]));
+
+        // message 1 misses a datagram, message 2 is lost completely
+        let mut second = datagrams(&clock, 1, &frame);
+        second.remove(3);
+        for datagram in second {
+            assert!(reassembly.add(datagram).complete.is_none());
+        }
+        let mut lost = 0;
+        for datagram in datagrams(&clock, 3, &[42]) {
+            let result = reassembly.add(datagram);
+            lost += result.lost;
+            assert!(result.complete.is_some());
+        }
+        assert_eq!(lost, 2);
+
+        // late datagrams of older messages are ignored
+        for datagram in datagrams(&clock, 2, &frame) {
+            let result = reassembly.add(datagram);
+            assert_eq!(result.lost, 0);
+            assert!(result.complete.is_none());
+        }
+
+        // incomplete messages are dropped once they leave the window
+        let mut incomplete = datagrams(&clock, 4, &frame);
+        incomplete.pop();
+        for datagram in incomplete {
+            reassembly.add(datagram);
+        }
+        let result = reassembly
+            .add(datagrams(&clock, 4 + REORDER_WINDOW, &[]).remove(0))
+            .complete;
+        assert!(result.is_some());
+
+        let stats = reassembly.stats()["camera/preview"];
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.lost, 2 + REORDER_WINDOW);
+    }
+}
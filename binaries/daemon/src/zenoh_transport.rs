@@ -0,0 +1,410 @@
+//! Inter-daemon transport that publishes outputs through zenoh.
+//!
+//! Each output `(dataflow_id, node_id, output_id)` is mapped to the key
+//! expression `dora/<dataflow_id>/<node_id>/<output_id>`. The daemon of the
+//! sending node publishes [`InterDaemonEvent`]s on this key expression.
+//!
+//! Daemons with receivers subscribe to each output that they need, so the
+//! other outputs of a node never cross the network to them.
+//!
+//! Events are queued and published by a separate task, in queue order, so a
+//! slow peer doesn't block the daemon. All publishers of a daemon share this
+//! task, one reliable session, and one priority, so zenoh transmits the events
+//! in publish order, across all outputs. The subscriptions to the outputs of a
+//! node push the received samples into one shared channel, in the order in
+//! which zenoh delivers them, so the receiving daemon forwards them in the
+//! order in which the node sent them.
+//!
+//! Next to each subscription, the receiving daemon declares a queryable that
+//! answers with its machine ID. The sending daemon queries it before it
+//! reports its nodes as ready (see [`ZenohTransport::wait_for_subscribers`]),
+//! so no early outputs are lost while the subscription is still propagating.
+
+use crate::Event;
+use dora_core::{
+    config::{DataId, NodeId},
+    uhlc::HLC,
+};
+use dora_message::{common::Timestamped, daemon_to_daemon::InterDaemonEvent, DataflowId};
+use eyre::{eyre, Context};
+use futures::{future::RemoteHandle, FutureExt};
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use zenoh::{
+    prelude::{
+        r#async::AsyncResolve, sync::SyncResolve, Config, Priority, Sample, SessionDeclarations,
+        SplitBuffer,
+    },
+    publication::CongestionControl,
+    query::{ConsolidationMode, QueryTarget},
+};
+
+/// Path of a zenoh configuration file that is used instead of the default
+/// peer configuration.
+const ZENOH_CONFIG_ENV: &str = "ZENOH_CONFIG";
+
+/// Number of events that can be queued for publishing before publishing
+/// waits for the queue to drain.
+const MAX_QUEUED_EVENTS: usize = 64;
+
+/// Number of received samples of a node that can be queued before zenoh waits
+/// for the daemon to catch up.
+const MAX_RECEIVED_SAMPLES: usize = 256;
+
+/// How long [`ZenohTransport::wait_for_subscribers`] waits for the remote
+/// subscribers before giving up.
+const SUBSCRIBER_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const SUBSCRIBER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct ZenohTransport {
+    session: Arc<zenoh::Session>,
+    publish_queue: mpsc::Sender<QueuedEvent>,
+}
+
+type QueuedEvent = (Arc<zenoh::publication::Publisher<'static>>, Vec<u8>);
+
+impl ZenohTransport {
+    pub fn open(config: Config) -> eyre::Result<Self> {
+        let session = zenoh::open(config)
+            .res_sync()
+            .map_err(|err| eyre!(err))
+            .wrap_err("failed to open zenoh session")?
+            .into_arc();
+        let (publish_queue, events) = mpsc::channel(MAX_QUEUED_EVENTS);
+        // not cancelled on drop, so that the queued events are still sent
+        tokio::spawn(publish_loop(events));
+        Ok(Self {
+            session,
+            publish_queue,
+        })
+    }
+
+    /// Opens a zenoh session in peer mode, or with the configuration file
+    /// given through the `ZENOH_CONFIG` environment variable.
+    pub fn open_from_env() -> eyre::Result<Self> {
+        let config = match std::env::var_os(ZENOH_CONFIG_ENV) {
+            Some(path) => Config::from_file(&path)
+                .map_err(|err| eyre!(err))
+                .wrap_err_with(|| format!("failed to read zenoh config from {path:?}"))?,
+            None => zenoh::config::peer(),
+        };
+        Self::open(config)
+    }
+
+    pub fn publisher(
+        &self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+        output_id: &DataId,
+    ) -> eyre::Result<OutputPublisher> {
+        let publisher = self
+            .session
+            .declare_publisher(key_expr(dataflow_id, node_id, output_id))
+            .congestion_control(CongestionControl::Block)
+            .priority(Priority::RealTime)
+            .res_sync()
+            .map_err(|err| eyre!(err))
+            .wrap_err_with(|| format!("failed to declare publisher for `{node_id}/{output_id}`"))?;
+        Ok(OutputPublisher {
+            publisher: Arc::new(publisher),
+            queue: self.publish_queue.clone(),
+        })
+    }
+
+    /// Waits until all of the given machines subscribed to the outputs of
+    /// the given node, see [`Self::subscribe`].
+    ///
+    /// Gives up with a warning after [`SUBSCRIBER_WAIT_TIMEOUT`].
+    pub fn wait_for_subscribers(
+        &self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+        machines: BTreeSet<String>,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let session = self.session.clone();
+        let node_id = node_id.clone();
+        let key = ready_key_expr(dataflow_id, &node_id);
+        async move {
+            let deadline = Instant::now() + SUBSCRIBER_WAIT_TIMEOUT;
+            let mut missing = machines;
+            loop {
+                let replies = session
+                    .get(key.as_str())
+                    .target(QueryTarget::All)
+                    .consolidation(ConsolidationMode::None)
+                    .res_async()
+                    .await;
+                match replies {
+                    Ok(replies) => {
+                        while let Ok(reply) = replies.recv_async().await {
+                            if let Ok(sample) = reply.sample {
+                                let machine = sample.value.payload.contiguous();
+                                missing.remove(String::from_utf8_lossy(&machine).as_ref());
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!("failed to query zenoh subscribers of `{node_id}`: {err}")
+                    }
+                }
+                if missing.is_empty() {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    tracing::warn!(
+                        "machines {missing:?} did not subscribe to the outputs of `{node_id}` \
+                        within {SUBSCRIBER_WAIT_TIMEOUT:?}, starting anyway"
+                    );
+                    break;
+                }
+                tokio::time::sleep(SUBSCRIBER_POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Subscribes to the given outputs of a node and forwards all received
+    /// events to the daemon.
+    ///
+    /// Each output is subscribed separately. The subscriptions push their
+    /// samples into a single channel, so that the events are forwarded in the
+    /// order in which the node sent them, also across outputs. The given
+    /// machine ID is reported to [`Self::wait_for_subscribers`] once the
+    /// subscriptions are declared.
+    ///
+    /// The subscriptions are cancelled when the returned handle is dropped.
+    pub fn subscribe(
+        &self,
+        dataflow_id: DataflowId,
+        node_id: &NodeId,
+        output_ids: BTreeSet<DataId>,
+        machine_id: String,
+        events_tx: mpsc::Sender<Timestamped<Event>>,
+        clock: Arc<HLC>,
+    ) -> eyre::Result<RemoteHandle<()>> {
+        let (samples_tx, samples) = flume::bounded(MAX_RECEIVED_SAMPLES);
+        let subscribers = output_ids
+            .iter()
+            .map(|output_id| {
+                let samples_tx = samples_tx.clone();
+                self.session
+                    .declare_subscriber(key_expr(dataflow_id, node_id, output_id))
+                    .reliable()
+                    // called in the order in which zenoh receives the samples
+                    .callback(move |sample: Sample| {
+                        let _ = samples_tx.send(sample);
+                    })
+                    .res_sync()
+                    .map_err(|err| eyre!(err))
+                    .wrap_err_with(|| format!("failed to subscribe to `{node_id}/{output_id}`"))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        // declared after the subscribers, so it only becomes visible to the
+        // sending daemon once the subscriptions are known too
+        let readiness = self
+            .session
+            .declare_queryable(ready_key_expr(dataflow_id, node_id))
+            .res_sync()
+            .map_err(|err| eyre!(err))
+            .wrap_err_with(|| format!("failed to declare readiness of `{node_id}` subscription"))?;
+
+        let task = async move {
+            // cancels the subscriptions when the task is dropped
+            let _subscribers = subscribers;
+            loop {
+                let sample = tokio::select! {
+                    sample = samples.recv_async() => match sample {
+                        Ok(sample) => sample,
+                        Err(_) => break,
+                    },
+                    Ok(query) = readiness.recv_async() => {
+                        let reply = Sample::new(query.key_expr().clone(), machine_id.clone());
+                        if let Err(err) = query.reply(Ok(reply)).res_async().await {
+                            tracing::warn!("failed to answer zenoh readiness query: {err}");
+                        }
+                        continue;
+                    }
+                };
+                let event: Timestamped<InterDaemonEvent> =
+                    match bincode::deserialize(&sample.value.payload.contiguous()) {
+                        Ok(event) => event,
+                        Err(err) => {
+                            tracing::warn!("failed to deserialize zenoh sample: {err}");
+                            continue;
+                        }
+                    };
+                if let Err(err) = clock.update_with_timestamp(&event.timestamp) {
+                    tracing::warn!("failed to update HLC with zenoh sample timestamp: {err}");
+                }
+                let event = Timestamped {
+                    inner: Event::Daemon(event.inner),
+                    timestamp: event.timestamp,
+                };
+                if events_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (task, handle) = task.remote_handle();
+        tokio::spawn(task);
+        Ok(handle)
+    }
+}
+
+pub struct OutputPublisher {
+    publisher: Arc<zenoh::publication::Publisher<'static>>,
+    queue: mpsc::Sender<QueuedEvent>,
+}
+
+impl OutputPublisher {
+    /// Queues the given event for publishing, waiting while the queue is full.
+    pub async fn publish(&self, event: &Timestamped<InterDaemonEvent>) -> eyre::Result<()> {
+        let message = bincode::serialize(event).wrap_err("failed to serialize InterDaemonEvent")?;
+        self.queue
+            .send((self.publisher.clone(), message))
+            .await
+            .map_err(|_| eyre!("zenoh publish task exited"))
+    }
+}
+
+async fn publish_loop(mut events: mpsc::Receiver<QueuedEvent>) {
+    while let Some((publisher, message)) = events.recv().await {
+        if let Err(err) = publisher.put(message).res_async().await {
+            tracing::warn!("failed to publish event through zenoh: {err}");
+        }
+    }
+}
+
+fn key_expr(dataflow_id: DataflowId, node_id: &NodeId, output_id: &DataId) -> String {
+    format!("dora/{dataflow_id}/{node_id}/{output_id}")
+}
+
+/// Key expression of the readiness queryables of the subscriptions to the
+/// outputs of the given node.
+fn ready_key_expr(dataflow_id: DataflowId, node_id: &NodeId) -> String {
+    format!("dora-ready/{dataflow_id}/{node_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aligned_vec::AVec;
+    use dora_message::metadata::{ArrowTypeInfo, Metadata};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn peer_config(listen: Option<u16>, connect: Option<u16>) -> Config {
+        let mut config = zenoh::config::peer();
+        config.scouting.multicast.set_enabled(Some(false)).unwrap();
+        if let Some(port) = listen {
+            config.listen.endpoints = vec![format!("tcp/127.0.0.1:{port}").parse().unwrap()];
+        }
+        if let Some(port) = connect {
+            config.connect.endpoints = vec![format!("tcp/127.0.0.1:{port}").parse().unwrap()];
+        }
+        config
+    }
+
+    async fn next_event(events_rx: &mut mpsc::Receiver<Timestamped<Event>>) -> Event {
+        tokio::time::timeout(Duration::from_secs(10), events_rx.recv())
+            .await
+            .expect("timeout while waiting for zenoh event")
+            .expect("event channel closed")
+            .inner
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ordered_delivery_between_peers() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let sender = ZenohTransport::open(peer_config(Some(port), None)).unwrap();
+        let receiver = ZenohTransport::open(peer_config(None, Some(port))).unwrap();
+
+        let dataflow_id = Uuid::new_v4();
+        let node_id = NodeId::from("camera".to_string());
+        let image = DataId::from("image".to_string());
+        let depth = DataId::from("depth".to_string());
+        let clock = Arc::new(HLC::default());
+        let (events_tx, mut events_rx) = mpsc::channel(1000);
+        let _subscription = receiver
+            .subscribe(
+                dataflow_id,
+                &node_id,
+                [image.clone(), depth.clone()].into(),
+                "receiver".into(),
+                events_tx,
+                clock.clone(),
+            )
+            .unwrap();
+        // outputs that are not subscribed must not be delivered
+        let other_id = DataId::from("ir".to_string());
+        let other = sender.publisher(dataflow_id, &node_id, &other_id).unwrap();
+        let image_publisher = sender.publisher(dataflow_id, &node_id, &image).unwrap();
+        let depth_publisher = sender.publisher(dataflow_id, &node_id, &depth).unwrap();
+        // returns once the subscription reached the sending peer
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            sender.wait_for_subscribers(dataflow_id, &node_id, ["receiver".to_owned()].into()),
+        )
+        .await
+        .expect("subscriber did not become ready");
+
+        let event = |output_id: &DataId, i: u8| Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id,
+                node_id: node_id.clone(),
+                output_id: output_id.clone(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::byte_array(1)),
+                data: Some(AVec::from_slice(128, &[i])),
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        const MESSAGES: u8 = 200;
+        for i in 0..MESSAGES {
+            // alternate between the outputs of the node
+            image_publisher.publish(&event(&image, i)).await.unwrap();
+            other.publish(&event(&other_id, i)).await.unwrap();
+            depth_publisher.publish(&event(&depth, i)).await.unwrap();
+        }
+        image_publisher
+            .publish(&Timestamped {
+                inner: InterDaemonEvent::OutputClosed {
+                    dataflow_id,
+                    node_id: node_id.clone(),
+                    output_id: image.clone(),
+                },
+                timestamp: clock.new_timestamp(),
+            })
+            .await
+            .unwrap();
+
+        for i in 0..MESSAGES {
+            for expected_output in [&image, &depth] {
+                match next_event(&mut events_rx).await {
+                    Event::Daemon(InterDaemonEvent::Output {
+                        output_id,
+                        metadata,
+                        data,
+                        ..
+                    }) => {
+                        assert_eq!(&output_id, expected_output);
+                        assert_eq!(metadata.type_info.len, 1);
+                        assert_eq!(data.as_deref(), Some(&[i][..]));
+                    }
+                    other => panic!("unexpected event {other:?}"),
+                }
+            }
+        }
+        assert!(matches!(
+            next_event(&mut events_rx).await,
+            Event::Daemon(InterDaemonEvent::OutputClosed { .. })
+        ));
+    }
+}
@@ -4,7 +4,7 @@ use dora_core::{
     config::{DataId, OperatorId},
     descriptor::OperatorConfig,
 };
-use dora_message::daemon_to_node::{NodeConfig, RuntimeConfig};
+use dora_message::daemon_to_node::{env, NodeConfig, NodeTopology, RuntimeConfig};
 use dora_metrics::init_meter_provider;
 use dora_node_api::{DoraNode, Event};
 use eyre::{bail, Context, Result};
@@ -27,8 +27,8 @@ mod operator;
 
 pub fn main() -> eyre::Result<()> {
     let config: RuntimeConfig = {
-        let raw = std::env::var("DORA_RUNTIME_CONFIG")
-            .wrap_err("env variable DORA_RUNTIME_CONFIG must be set")?;
+        let raw = std::env::var(env::DORA_RUNTIME_CONFIG)
+            .wrap_err_with(|| format!("env variable {} must be set", env::DORA_RUNTIME_CONFIG))?;
         serde_yaml::from_str(&raw).context("failed to deserialize operator config")?
     };
     let RuntimeConfig {
@@ -123,7 +123,10 @@ async fn run(
     init_done: oneshot::Receiver<Result<()>>,
 ) -> eyre::Result<()> {
     #[cfg(feature = "metrics")]
-    let _meter_provider = init_meter_provider(config.node_id.to_string());
+    let _meter_provider = init_meter_provider(match &config.dataflow_instance {
+        Some(instance) => format!("{instance}/{}", config.node_id),
+        None => config.node_id.to_string(),
+    });
     init_done
         .await
         .wrap_err("the `init_done` channel was closed unexpectedly")?
@@ -209,6 +212,21 @@ async fn run(
                             tracing::warn!("output sample requested, but operator {operator_id} exited already");
                         }
                     }
+                    OperatorEvent::QueryTopology { reply } => {
+                        let result;
+                        (node, result) = tokio::task::spawn_blocking(move || {
+                            let result = node.topology();
+                            (node, result)
+                        })
+                        .await
+                        .wrap_err("failed to wait for topology task")?;
+                        let topology = result.map(|t| operator_topology(t, &operator_id));
+                        if reply.send(topology).is_err() {
+                            tracing::warn!(
+                                "topology requested, but operator {operator_id} exited already"
+                            );
+                        }
+                    }
                     OperatorEvent::Output {
                         output_id,
                         type_info,
@@ -274,7 +292,11 @@ async fn run(
                     tracing::warn!("{err}");
                 }
             }
-            RuntimeEvent::Event(Event::InputClosed { id }) => {
+            RuntimeEvent::Event(Event::InputClosed {
+                id,
+                delivered,
+                last_timestamp,
+            }) => {
                 let Some((operator_id, input_id)) = id.as_str().split_once('/') else {
                     tracing::warn!("received InputClosed event for non-operator input {id}");
                     continue;
@@ -289,6 +311,8 @@ async fn run(
                 if let Err(err) = operator_channel
                     .send_async(Event::InputClosed {
                         id: input_id.clone(),
+                        delivered,
+                        last_timestamp,
                     })
                     .await
                     .wrap_err_with(|| {
@@ -311,6 +335,9 @@ async fn run(
                 }
             }
             RuntimeEvent::Event(Event::Error(err)) => eyre::bail!("received error event: {err}"),
+            RuntimeEvent::Event(Event::SubscribersChanged { .. } | Event::InputTimeout { .. }) => {
+                // operators have no API for subscriber presence and input timeouts
+            }
             RuntimeEvent::Event(other) => {
                 tracing::warn!("received unknown event `{other:?}`");
             }
@@ -326,6 +353,33 @@ fn operator_output_id(operator_id: &OperatorId, output_id: &DataId) -> DataId {
     DataId::from(format!("{operator_id}/{output_id}"))
 }
 
+/// Restricts the topology of the runtime node to the inputs and outputs of
+/// the given operator, with the operator prefix removed from their IDs.
+fn operator_topology(topology: NodeTopology, operator_id: &OperatorId) -> NodeTopology {
+    let prefix = format!("{operator_id}/");
+    let strip = |id: DataId| {
+        id.as_str()
+            .strip_prefix(&prefix)
+            .map(|id| DataId::from(id.to_owned()))
+    };
+    fn strip_keys<V>(
+        map: BTreeMap<DataId, V>,
+        strip: impl Fn(DataId) -> Option<DataId>,
+    ) -> BTreeMap<DataId, V> {
+        map.into_iter()
+            .filter_map(|(id, value)| Some((strip(id)?, value)))
+            .collect()
+    }
+    NodeTopology {
+        inputs: strip_keys(topology.inputs, strip),
+        outputs: topology.outputs.into_iter().filter_map(strip).collect(),
+        input_types: strip_keys(topology.input_types, strip),
+        output_types: strip_keys(topology.output_types, strip),
+        upstream: topology.upstream,
+        downstream: topology.downstream,
+    }
+}
+
 #[derive(Debug)]
 enum RuntimeEvent {
     Operator {
@@ -2,7 +2,7 @@ use dora_core::{
     config::{DataId, NodeId},
     descriptor::{Descriptor, OperatorDefinition, OperatorSource},
 };
-use dora_message::metadata::ArrowTypeInfo;
+use dora_message::{daemon_to_node::NodeTopology, metadata::ArrowTypeInfo};
 use dora_node_api::{DataSample, Event, MetadataParameters};
 use eyre::{Context, Result};
 use std::any::Any;
@@ -82,6 +82,11 @@ pub enum OperatorEvent {
         parameters: MetadataParameters,
         data: Option<DataSample>,
     },
+    /// Requests the inputs and outputs of the operator and the nodes that
+    /// it is connected to.
+    QueryTopology {
+        reply: oneshot::Sender<eyre::Result<NodeTopology>>,
+    },
     Error(eyre::Error),
     Panic(Box<dyn Any + Send>),
     Finished {
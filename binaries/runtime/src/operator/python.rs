@@ -72,6 +72,9 @@ pub fn run(
     let send_output = SendOutputCallback {
         events_tx: events_tx.clone(),
     };
+    let topology = TopologyCallback {
+        events_tx: events_tx.clone(),
+    };
 
     let init_operator = move |py: Python| {
         if let Some(parent_path) = path_parent {
@@ -105,6 +108,7 @@ pub fn run(
             "dataflow_descriptor",
             pythonize::pythonize(py, dataflow_descriptor)?,
         )?;
+        operator.setattr("topology", topology)?;
 
         Result::<_, eyre::Report>::Ok(Py::from(operator))
     };
@@ -285,12 +289,17 @@ struct SendOutputCallback {
     events_tx: Sender<OperatorEvent>,
 }
 
+#[pyclass]
+struct TopologyCallback {
+    events_tx: Sender<OperatorEvent>,
+}
+
 #[allow(unsafe_op_in_unsafe_fn)]
 mod callback_impl {
 
     use crate::operator::OperatorEvent;
 
-    use super::SendOutputCallback;
+    use super::{SendOutputCallback, TopologyCallback};
     use aligned_vec::{AVec, ConstAlign};
     use arrow::{array::ArrayData, pyarrow::FromPyArrow};
     use dora_message::metadata::ArrowTypeInfo;
@@ -394,4 +403,23 @@ mod callback_impl {
             Ok(())
         }
     }
+
+    /// Returns the inputs and outputs of the operator, together with the IDs
+    /// of the upstream and downstream nodes and the declared types.
+    /// `e.g.:  self.topology()["inputs"]`
+    #[pymethods]
+    impl TopologyCallback {
+        fn __call__(&self, py: Python) -> Result<PyObject> {
+            let topology = py.allow_threads(|| {
+                let (reply, topology) = oneshot::channel();
+                self.events_tx
+                    .blocking_send(OperatorEvent::QueryTopology { reply })
+                    .map_err(|_| eyre!("failed to send topology request to runtime"))?;
+                topology
+                    .blocking_recv()
+                    .wrap_err("failed to receive topology")?
+            })?;
+            Ok(pythonize::pythonize(py, &topology).map(|x| x.unbind())?)
+        }
+    }
 }
@@ -11,8 +11,9 @@ use dora_node_api::{
     Event, Parameter,
 };
 use dora_operator_api_types::{
-    safer_ffi::closure::ArcDynFn1, DoraDropOperator, DoraInitOperator, DoraInitResult, DoraOnEvent,
-    DoraResult, DoraStatus, Metadata, OnEventResult, Output, SendOutput,
+    safer_ffi::closure::{ArcDynFn0, ArcDynFn1},
+    DoraDropOperator, DoraInitOperator, DoraInitResult, DoraOnEvent, DoraResult, DoraStatus,
+    Metadata, OnEventResult, Output, SendOutput, TopologyResult,
 };
 use eyre::{bail, eyre, Context, Result};
 use libloading::Symbol;
@@ -82,6 +83,17 @@ pub fn run(
     Ok(())
 }
 
+fn query_topology(events_tx: &Sender<OperatorEvent>) -> eyre::Result<String> {
+    let (reply, topology) = oneshot::channel();
+    events_tx
+        .blocking_send(OperatorEvent::QueryTopology { reply })
+        .map_err(|_| eyre!("runtime process closed unexpectedly"))?;
+    let topology = topology
+        .blocking_recv()
+        .wrap_err("failed to receive topology")??;
+    serde_json::to_string(&topology).wrap_err("failed to serialize topology")
+}
+
 struct SharedLibraryOperator<'lib> {
     incoming_events: flume::Receiver<Event>,
     events_tx: Sender<OperatorEvent>,
@@ -111,6 +123,18 @@ impl<'lib> SharedLibraryOperator<'lib> {
 
         let _ = init_done.send(Ok(()));
 
+        let topology_events_tx = self.events_tx.clone();
+        let query_topology_closure = Arc::new(move || match query_topology(&topology_events_tx) {
+            Ok(topology) => TopologyResult {
+                result: DoraResult::SUCCESS,
+                topology: topology.into(),
+            },
+            Err(err) => TopologyResult {
+                result: DoraResult::from_error(format!("{err:?}")),
+                topology: String::new().into(),
+            },
+        });
+
         let send_output_closure = Arc::new(move |output: Output| {
             let Output {
                 id: output_id,
@@ -215,7 +239,7 @@ impl<'lib> SharedLibraryOperator<'lib> {
                         error: None,
                     }
                 }
-                Event::InputClosed { id: input_id } => dora_operator_api_types::RawEvent {
+                Event::InputClosed { id: input_id, .. } => dora_operator_api_types::RawEvent {
                     input_closed: Some(input_id.to_string().into()),
                     input: None,
                     stop: false,
@@ -239,6 +263,7 @@ impl<'lib> SharedLibraryOperator<'lib> {
 
             let send_output = SendOutput {
                 send_output: ArcDynFn1::new(send_output_closure.clone()),
+                query_topology: ArcDynFn0::new(query_topology_closure.clone()),
             };
             let OnEventResult {
                 result: DoraResult { error },
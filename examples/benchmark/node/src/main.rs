@@ -1,15 +1,17 @@
 use dora_node_api::{self, dora_core::config::DataId, DoraNode};
-use eyre::{Context, ContextCompat, Error};
+use eyre::ContextCompat;
 use rand::Rng;
 use std::collections::HashMap;
-use std::time::Duration;
-use tracing_subscriber::Layer;
+use std::time::{Duration, Instant};
 
 fn main() -> eyre::Result<()> {
-    set_up_tracing().wrap_err("failed to set up tracing subscriber")?;
-
     let latency = DataId::from("latency".to_owned());
     let throughput = DataId::from("throughput".to_owned());
+    let rate_send = DataId::from("rate_send".to_owned());
+    let rate_ring = DataId::from("rate_ring".to_owned());
+    let rate_small = DataId::from("rate_small".to_owned());
+    let rate_batched = DataId::from("rate_batched".to_owned());
+    let large = DataId::from("large".to_owned());
 
     let (mut node, _events) = DoraNode::init_from_env()?;
     let sizes = [
@@ -69,16 +71,91 @@ fn main() -> eyre::Result<()> {
         }
     }
 
+    // wait a bit to ensure that all throughput messages reached their target
+    std::thread::sleep(Duration::from_secs(2));
+
+    // finally compare the normal send path with output rings at a fixed rate
+    let rate_sizes = [4096, 10 * 4096, 100 * 4096];
+    for size in rate_sizes {
+        let data = data.get(&size).wrap_err(eyre::Report::msg(format!(
+            "data not found for size {}",
+            size
+        )))?;
+
+        let mut pacer = Pacer::new(RATE_INTERVAL);
+        for _ in 0..RATE_MESSAGES {
+            pacer.wait();
+            node.send_output_raw(rate_send.clone(), Default::default(), data.len(), |out| {
+                out.copy_from_slice(data);
+            })?;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+
+        let mut ring = node.prepare_output_ring(rate_ring.clone(), size, 8)?;
+        let mut pacer = Pacer::new(RATE_INTERVAL);
+        for _ in 0..RATE_MESSAGES {
+            pacer.wait();
+            let mut slot = futures::executor::block_on(ring.next_slot(&mut node))?;
+            slot.copy_from_slice(data);
+            slot.send(&mut node, data.len(), Default::default())?;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    // small messages at a fixed rate, delivered one by one or in batches
+    let data = data.get(&64).wrap_err("data not found for size 64")?;
+    for output in [&rate_small, &rate_batched] {
+        let mut pacer = Pacer::new(RATE_INTERVAL);
+        for _ in 0..SMALL_RATE_MESSAGES {
+            pacer.wait();
+            node.send_output_raw(output.clone(), Default::default(), data.len(), |out| {
+                out.copy_from_slice(data);
+            })?;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    // large buffers at full speed, which the receiver should not copy
+    for size in LARGE_SIZES {
+        for i in 0..LARGE_MESSAGES {
+            node.send_output_raw(large.clone(), Default::default(), size, |out| {
+                out.fill(i as u8);
+            })?;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
     Ok(())
 }
 
-fn set_up_tracing() -> eyre::Result<()> {
-    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+/// Send interval of the fixed rate phase (10 kHz).
+const RATE_INTERVAL: Duration = Duration::from_micros(100);
+const RATE_MESSAGES: usize = 1000;
+/// Enough small messages to measure the CPU time of the receiver.
+const SMALL_RATE_MESSAGES: usize = 10_000;
+
+const LARGE_SIZES: [usize; 2] = [16 << 20, 64 << 20];
+const LARGE_MESSAGES: usize = 50;
+
+/// Keeps a fixed send rate without accumulating the delay of each send.
+struct Pacer {
+    next: Instant,
+    interval: Duration,
+}
+
+impl Pacer {
+    fn new(interval: Duration) -> Self {
+        Self {
+            next: Instant::now(),
+            interval,
+        }
+    }
 
-    let stdout_log = tracing_subscriber::fmt::layer()
-        .pretty()
-        .with_filter(tracing::metadata::LevelFilter::DEBUG);
-    let subscriber = tracing_subscriber::Registry::default().with(stdout_log);
-    tracing::subscriber::set_global_default(subscriber)
-        .context("failed to set tracing global subscriber")
+    fn wait(&mut self) {
+        let now = Instant::now();
+        if let Some(remaining) = self.next.checked_duration_since(now) {
+            std::thread::sleep(remaining);
+        }
+        self.next += self.interval;
+    }
 }
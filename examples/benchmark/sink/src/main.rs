@@ -1,51 +1,64 @@
-use dora_node_api::{self, DoraNode, Event};
-use eyre::Context;
-use std::time::{Duration, Instant};
-use tracing_subscriber::Layer;
+use dora_node_api::{self, ArrowData, DoraNode, Event};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
 
 fn main() -> eyre::Result<()> {
-    set_up_tracing().wrap_err("failed to set up tracing subscriber")?;
-
     let (_node, mut events) = DoraNode::init_from_env()?;
 
-    // latency is tested first
-    let mut latency = true;
-
+    let mut current_id = String::new();
     let mut current_size = 0;
     let mut n = 0;
     let mut start = Instant::now();
+    let mut cpu_start = cpu_time();
     let mut latencies = Vec::new();
-
-    println!("Latency:");
+    let mut comparison = Comparison::default();
+    let mut large = LargeBuffers::default();
 
     while let Some(event) = events.recv() {
         match event {
             Event::Input { id, metadata, data } => {
-                // check if new size bracket
+                if id.as_str() == "large" {
+                    large.add(&data)?;
+                    continue;
+                }
+                let mode = match id.as_str() {
+                    "latency" => Mode::Latency,
+                    "throughput" => Mode::Throughput,
+                    "rate_send" | "rate_ring" | "rate_small" | "rate_batched" => Mode::Rate,
+                    other => {
+                        eprintln!("Ignoring unexpected input `{other}`");
+                        continue;
+                    }
+                };
+
+                // check if new phase or size bracket
                 let data_len = data.len();
-                if data_len != current_size {
+                if id.as_str() != current_id || data_len != current_size {
                     if n > 0 {
-                        record_results(start, current_size, n, latencies, latency);
+                        let cpu = cpu_used(cpu_start);
+                        let avg_latency = record_results(
+                            start,
+                            cpu,
+                            current_size,
+                            n,
+                            latencies,
+                            Mode::of(&current_id),
+                        );
+                        comparison.add(&current_id, current_size, avg_latency, cpu);
+                    }
+                    if id.as_str() != current_id {
+                        println!("{}:", mode.title(&id));
+                        current_id = id.to_string();
                     }
                     current_size = data_len;
                     n = 0;
                     start = Instant::now();
+                    cpu_start = cpu_time();
                     latencies = Vec::new();
                 }
 
-                match id.as_str() {
-                    "latency" if latency => {}
-                    "throughput" if latency => {
-                        latency = false;
-                        println!("Throughput:");
-                    }
-                    "throughput" => {}
-                    other => {
-                        eprintln!("Ignoring unexpected input `{other}`");
-                        continue;
-                    }
-                }
-
                 n += 1;
                 latencies.push(
                     metadata
@@ -56,43 +69,191 @@ fn main() -> eyre::Result<()> {
                         .unwrap_or_default(),
                 );
             }
-            Event::InputClosed { id } => {
+            Event::InputClosed { id, .. } => {
                 println!("Input `{id}` was closed");
             }
             other => eprintln!("Received unexpected input: {other:?}"),
         }
     }
 
-    record_results(start, current_size, n, latencies, latency);
+    if n > 0 {
+        let cpu = cpu_used(cpu_start);
+        let avg_latency = record_results(
+            start,
+            cpu,
+            current_size,
+            n,
+            latencies,
+            Mode::of(&current_id),
+        );
+        comparison.add(&current_id, current_size, avg_latency, cpu);
+    }
+    comparison.print();
+    large.print();
 
     Ok(())
 }
 
+/// Results of the `large` phase, per message size.
+///
+/// Shared memory inputs are borrowed instead of copied, so accessing their
+/// data should take a constant time, independent of the message size.
+#[derive(Default)]
+struct LargeBuffers(BTreeMap<usize, LargeResult>);
+
+struct LargeResult {
+    messages: u32,
+    access: Duration,
+    /// Time to copy a single message, for comparison.
+    copy: Duration,
+    first: Instant,
+    last: Instant,
+}
+
+impl LargeBuffers {
+    fn add(&mut self, data: &ArrowData) -> eyre::Result<()> {
+        let start = Instant::now();
+        let bytes = data.as_bytes()?;
+        std::hint::black_box((bytes.first(), bytes.last()));
+        let access = start.elapsed();
+
+        let result = self.0.entry(bytes.len()).or_insert_with(|| {
+            let start = Instant::now();
+            std::hint::black_box(bytes.to_vec());
+            LargeResult {
+                messages: 0,
+                access: Duration::ZERO,
+                copy: start.elapsed(),
+                first: start,
+                last: start,
+            }
+        });
+        result.messages += 1;
+        result.access += access;
+        result.last = Instant::now();
+        Ok(())
+    }
+
+    fn print(&self) {
+        println!("Large buffers (borrowed from shared memory):");
+        for (size, result) in &self.0 {
+            let elapsed = result.last.duration_since(result.first).as_secs_f64();
+            let bytes = (*size as f64) * f64::from(result.messages.saturating_sub(1));
+            println!(
+                "size {size:<#8x}: {:.2} GB/s, {:?} average access time \
+                (copying a message takes {:?})",
+                bytes / elapsed.max(f64::EPSILON) / 1e9,
+                result.access / result.messages,
+                result.copy
+            );
+        }
+    }
+}
+
+/// Results of the `rate_send` and `rate_ring` phases, per message size.
+#[derive(Default)]
+struct Comparison(BTreeMap<usize, [Option<RateResult>; 2]>);
+
+#[derive(Clone, Copy)]
+struct RateResult {
+    avg_latency: Duration,
+    cpu: Option<Duration>,
+}
+
+impl Comparison {
+    fn add(&mut self, id: &str, size: usize, avg_latency: Duration, cpu: Option<Duration>) {
+        let index = match id {
+            "rate_send" => 0,
+            "rate_ring" => 1,
+            _ => return,
+        };
+        self.0.entry(size).or_default()[index] = Some(RateResult { avg_latency, cpu });
+    }
+
+    fn print(&self) {
+        println!("10 kHz (output ring compared to send_output):");
+        for (size, results) in &self.0 {
+            let [Some(send), Some(ring)] = results else {
+                continue;
+            };
+            let cpu = match (send.cpu, ring.cpu) {
+                (Some(send), Some(ring)) => format!(", CPU time {send:?} -> {ring:?}"),
+                _ => String::new(),
+            };
+            println!(
+                "size {size:<#8x}: average latency {:?} -> {:?}{cpu}",
+                send.avg_latency, ring.avg_latency
+            );
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Latency,
+    Throughput,
+    /// Messages sent at a fixed rate, we're interested in both latency and
+    /// whether the rate could be sustained.
+    Rate,
+}
+
+impl Mode {
+    fn of(id: &str) -> Self {
+        match id {
+            "latency" => Mode::Latency,
+            "throughput" => Mode::Throughput,
+            _ => Mode::Rate,
+        }
+    }
+
+    fn title(self, id: &str) -> &'static str {
+        match (self, id) {
+            (Mode::Latency, _) => "Latency",
+            (Mode::Throughput, _) => "Throughput",
+            (Mode::Rate, "rate_ring") => "10 kHz (output ring)",
+            (Mode::Rate, "rate_small") => "10 kHz (small messages)",
+            (Mode::Rate, "rate_batched") => "10 kHz (small messages, batched input)",
+            (Mode::Rate, _) => "10 kHz (send_output)",
+        }
+    }
+}
+
 fn record_results(
     start: Instant,
+    cpu: Option<Duration>,
     current_size: usize,
     n: u32,
     latencies: Vec<Duration>,
-    latency: bool,
-) {
-    let msg = if latency {
-        let avg_latency = latencies.iter().sum::<Duration>() / n;
-        format!("size {current_size:<#8x}: {avg_latency:?}")
-    } else {
-        let duration = start.elapsed();
-        let msg_per_sec = n as f64 / duration.as_secs_f64();
-        format!("size {current_size:<#8x}: {msg_per_sec:.0} messages per second")
+    mode: Mode,
+) -> Duration {
+    let avg_latency = latencies.iter().sum::<Duration>() / n;
+    let msg_per_sec = n as f64 / start.elapsed().as_secs_f64();
+    let msg = match mode {
+        Mode::Latency => format!("size {current_size:<#8x}: {avg_latency:?}"),
+        Mode::Throughput => {
+            format!("size {current_size:<#8x}: {msg_per_sec:.0} messages per second")
+        }
+        Mode::Rate => format!(
+            "size {current_size:<#8x}: {avg_latency:?} average latency, \
+            {msg_per_sec:.0} messages per second{}",
+            cpu.map(|cpu| format!(", {cpu:?} CPU time"))
+                .unwrap_or_default()
+        ),
     };
     println!("{msg}");
+    avg_latency
 }
 
-fn set_up_tracing() -> eyre::Result<()> {
-    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+/// CPU time that the process used so far, only available on Linux.
+fn cpu_time() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // skip the command name, which might contain spaces
+    let fields: Vec<_> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    // `utime` and `stime`, in clock ticks of 10ms on practically all systems
+    let ticks = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    Some(Duration::from_millis(ticks * 10))
+}
 
-    let stdout_log = tracing_subscriber::fmt::layer()
-        .pretty()
-        .with_filter(tracing::metadata::LevelFilter::DEBUG);
-    let subscriber = tracing_subscriber::Registry::default().with(stdout_log);
-    tracing::subscriber::set_global_default(subscriber)
-        .context("failed to set tracing global subscriber")
+fn cpu_used(since: Option<Duration>) -> Option<Duration> {
+    Some(cpu_time()?.saturating_sub(since?))
 }
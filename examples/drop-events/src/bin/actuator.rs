@@ -0,0 +1,24 @@
+use dora_node_api::{self, DoraNode, Event};
+use std::time::Duration;
+
+fn main() -> eyre::Result<()> {
+    let (_node, mut events) = DoraNode::init_from_env()?;
+
+    let mut handled = 0;
+    while let Some(event) = events.recv() {
+        match event {
+            Event::Input { id, .. } if id.as_str() == "command" => {
+                // simulates a slow device
+                std::thread::sleep(Duration::from_millis(50));
+                handled += 1;
+            }
+            Event::Input { id, .. } => eprintln!("Ignoring unexpected input `{id}`"),
+            Event::Stop => println!("Received manual stop"),
+            Event::InputClosed { id, .. } => println!("Input `{id}` was closed"),
+            other => eprintln!("Received unexpected input: {other:?}"),
+        }
+    }
+    println!("actuator handled {handled} commands");
+
+    Ok(())
+}
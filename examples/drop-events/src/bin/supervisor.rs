@@ -0,0 +1,58 @@
+use dora_node_api::{self, DoraNode, DropEvent, Event, LifecycleEvent};
+use eyre::Context;
+use std::collections::BTreeMap;
+
+fn main() -> eyre::Result<()> {
+    let (_node, mut events) = DoraNode::init_from_env()?;
+
+    let mut next_sequence: BTreeMap<String, u64> = BTreeMap::new();
+    let mut dropped = 0;
+    while let Some(event) = events.recv() {
+        match event {
+            Event::Input { id, data, .. } => match id.as_str() {
+                "drops" => {
+                    let json: &str = TryFrom::try_from(&data).context("expected string message")?;
+                    let drop: DropEvent =
+                        serde_json::from_str(json).context("invalid drop event")?;
+                    println!(
+                        "ALARM: {} message(s) of `{}` (from {}) dropped: {:?}",
+                        drop.count,
+                        drop.input,
+                        drop.sources.join(", "),
+                        drop.reason
+                    );
+                    if drop.suppressed > 0 {
+                        println!("  {} more drop(s) were not reported", drop.suppressed);
+                    }
+                    let expected = next_sequence.entry(drop.machine.clone()).or_default();
+                    if drop.sequence > *expected {
+                        println!(
+                            "  missed {} drop record(s) of the supervisor itself",
+                            drop.sequence - *expected
+                        );
+                    }
+                    *expected = drop.sequence + 1;
+                    dropped += drop.count + drop.suppressed;
+                }
+                "dora/lifecycle" => {
+                    let json: &str = TryFrom::try_from(&data).context("expected string message")?;
+                    let event: LifecycleEvent =
+                        serde_json::from_str(json).context("invalid lifecycle event")?;
+                    if matches!(&event, LifecycleEvent::NodeStopped { node_id } if node_id.as_ref() == "actuator")
+                    {
+                        break;
+                    }
+                }
+                other => eprintln!("Ignoring unexpected input `{other}`"),
+            },
+            Event::Stop => {
+                println!("Received manual stop");
+                break;
+            }
+            other => eprintln!("Received unexpected input: {other:?}"),
+        }
+    }
+    println!("supervisor saw {dropped} dropped messages");
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use aligned_vec::AVec;
+use dora_core::uhlc::HLC;
+use dora_message::{
+    daemon_to_external::{ExternalEndpointsInfo, ExternalReply},
+    external_to_daemon::{ExternalMessage, ExternalRequest},
+    metadata::{ArrowTypeInfo, Metadata},
+};
+use eyre::{bail, Context, ContextCompat};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+fn main() -> eyre::Result<()> {
+    let info_path: PathBuf = std::env::args()
+        .nth(1)
+        .context("usage: external-endpoints-example-client <external_endpoints.json>")?
+        .into();
+    let info: ExternalEndpointsInfo = serde_json::from_slice(
+        &std::fs::read(&info_path)
+            .with_context(|| format!("failed to read `{}`", info_path.display()))?,
+    )
+    .context("failed to parse external endpoint info")?;
+
+    let mut subscriber = connect(
+        &info,
+        ExternalRequest::Subscribe {
+            token: info.token.clone(),
+            name: "random".into(),
+        },
+    )?;
+    for _ in 0..10 {
+        let message: ExternalMessage = bincode::deserialize(&receive_frame(&mut subscriber)?)?;
+        let len = message.data.map(|d| d.len()).unwrap_or_default();
+        println!("received `random` output with {len} bytes");
+    }
+
+    let mut publisher = connect(
+        &info,
+        ExternalRequest::Publish {
+            token: info.token.clone(),
+            name: "command".into(),
+        },
+    )?;
+    let hlc = HLC::default();
+    for i in 0..5 {
+        let data = format!("external command {i}").into_bytes();
+        let message = ExternalMessage {
+            metadata: Metadata::new(hlc.new_timestamp(), ArrowTypeInfo::byte_array(data.len())),
+            data: Some(AVec::from_slice(128, &data)),
+        };
+        send_frame(&mut publisher, &bincode::serialize(&message)?)?;
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
+
+fn connect(info: &ExternalEndpointsInfo, request: ExternalRequest) -> eyre::Result<TcpStream> {
+    let mut connection =
+        TcpStream::connect(info.address).context("failed to connect to external endpoint")?;
+    send_frame(&mut connection, &bincode::serialize(&request)?)?;
+    match bincode::deserialize(&receive_frame(&mut connection)?)? {
+        ExternalReply::Ok => Ok(connection),
+        ExternalReply::Err(err) => bail!("request was rejected: {err}"),
+    }
+}
+
+fn send_frame(connection: &mut TcpStream, message: &[u8]) -> eyre::Result<()> {
+    connection.write_all(&(message.len() as u64).to_le_bytes())?;
+    connection.write_all(message)?;
+    Ok(())
+}
+
+fn receive_frame(connection: &mut TcpStream) -> eyre::Result<Vec<u8>> {
+    let mut len = [0; 8];
+    connection.read_exact(&mut len)?;
+    let mut message = vec![0; u64::from_le_bytes(len) as usize];
+    connection.read_exact(&mut message)?;
+    Ok(message)
+}
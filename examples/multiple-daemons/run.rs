@@ -49,6 +49,7 @@ async fn main() -> eyre::Result<()> {
     let (_coordinator_port, coordinator) = dora_coordinator::start(
         coordinator_bind,
         coordinator_control_bind,
+        None,
         ReceiverStream::new(coordinator_events_rx),
     )
     .await?;
@@ -134,14 +135,18 @@ async fn start_dataflow(
             request: ControlRequest::Start {
                 dataflow: dataflow_descriptor,
                 local_working_dir: working_dir,
+                machine_working_dirs: Default::default(),
                 name: None,
+                instance: None,
+                params: Default::default(),
+                dry_run: false,
             },
             reply_sender,
         }))
         .await?;
     let result = reply.await??;
     let uuid = match result {
-        ControlRequestReply::DataflowStarted { uuid } => uuid,
+        ControlRequestReply::DataflowStarted { uuid, .. } => uuid,
         ControlRequestReply::Error(err) => bail!("{err}"),
         other => bail!("unexpected start dataflow reply: {other:?}"),
     };
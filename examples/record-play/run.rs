@@ -0,0 +1,106 @@
+use arrow::{array::AsArray, datatypes::UInt64Type};
+use dora_tracing::set_up_tracing;
+use eyre::{bail, Context, ContextCompat};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::path::{Path, PathBuf};
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    set_up_tracing("record-play-runner").wrap_err("failed to set up tracing subscriber")?;
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    std::env::set_current_dir(root.join(file!()).parent().unwrap())
+        .wrap_err("failed to set working dir")?;
+
+    for dir in ["out", "recording"] {
+        if Path::new(dir).exists() {
+            std::fs::remove_dir_all(dir).wrap_err("failed to clean up old recordings")?;
+        }
+    }
+
+    let record = Path::new("record.yml");
+    build_dataflow(record).await?;
+    run_dataflow(record).await?;
+    // `play.yml` replays the recording from the `recording` directory
+    std::fs::rename(single_recording()?, "recording").wrap_err("failed to move recording")?;
+
+    let play = Path::new("play.yml");
+    build_dataflow(play).await?;
+    run_dataflow(play).await?;
+
+    let recorded = read_payloads(Path::new("recording/random.parquet"))?;
+    let played = read_payloads(&single_recording()?.join("random.parquet"))?;
+    if recorded.is_empty() {
+        bail!("nothing was recorded");
+    }
+    if recorded != played {
+        bail!(
+            "played back messages differ from the recording \
+            (recorded {} messages, played back {})",
+            recorded.len(),
+            played.len()
+        );
+    }
+    println!("played back all {} recorded messages", recorded.len());
+
+    Ok(())
+}
+
+/// Returns the `out/<DATAFLOW_ID>` directory written by `dora-record`.
+fn single_recording() -> eyre::Result<PathBuf> {
+    let mut dirs = std::fs::read_dir("out")
+        .wrap_err("failed to read `out` dir")?
+        .map(|entry| entry.map(|e| e.path()))
+        .filter(|path| path.as_ref().map_or(true, |p| p.is_dir()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match dirs.len() {
+        1 => Ok(dirs.remove(0)),
+        other => bail!("expected a single recording in `out`, found {other}"),
+    }
+}
+
+/// Reads the payload column of the given recording file.
+fn read_payloads(path: &Path) -> eyre::Result<Vec<Vec<u64>>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open `{}`", path.display()))?;
+    let mut payloads = Vec::new();
+    for batch in ParquetRecordBatchReaderBuilder::try_new(file)?.build()? {
+        let batch = batch?;
+        let list = batch
+            .column_by_name("random")
+            .context("no `random` column")?
+            .as_list::<i32>();
+        for value in list.iter() {
+            let value = value.context("missing payload")?;
+            payloads.push(value.as_primitive::<UInt64Type>().values().to_vec());
+        }
+    }
+    Ok(payloads)
+}
+
+async fn build_dataflow(dataflow: &Path) -> eyre::Result<()> {
+    let cargo = std::env::var("CARGO").unwrap();
+    let mut cmd = tokio::process::Command::new(&cargo);
+    cmd.arg("run");
+    cmd.arg("--package").arg("dora-cli");
+    cmd.arg("--").arg("build").arg(dataflow);
+    if !cmd.status().await?.success() {
+        bail!("failed to build dataflow");
+    };
+    Ok(())
+}
+
+async fn run_dataflow(dataflow: &Path) -> eyre::Result<()> {
+    let cargo = std::env::var("CARGO").unwrap();
+    let mut cmd = tokio::process::Command::new(&cargo);
+    cmd.arg("run");
+    cmd.arg("--package").arg("dora-cli");
+    cmd.arg("--")
+        .arg("daemon")
+        .arg("--run-dataflow")
+        .arg(dataflow);
+    if !cmd.status().await?.success() {
+        bail!("failed to run dataflow");
+    };
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use dora_node_api::{self, DoraNode, Event};
+use eyre::{bail, Context};
+
+fn main() -> eyre::Result<()> {
+    let min_messages: u64 = match std::env::var("MIN_MESSAGES") {
+        Ok(min) => min.parse().context("invalid MIN_MESSAGES")?,
+        Err(_) => 0,
+    };
+
+    let (_node, mut events) = DoraNode::init_from_env()?;
+
+    let (mut values, mut averages) = (0u64, 0u64);
+    while let Some(event) = events.recv() {
+        match event {
+            Event::Input { id, data, .. } => {
+                let number: f64 = TryFrom::try_from(&data).context("expected f64 value")?;
+                match id.as_str() {
+                    "value" => {
+                        values += 1;
+                        println!("value {number:.3}");
+                    }
+                    "average" => {
+                        averages += 1;
+                        println!("running average {number:.3}");
+                    }
+                    other => eprintln!("Ignoring unexpected input `{other}`"),
+                }
+                if !(0.0..=1.0).contains(&number) {
+                    bail!("`{id}` is out of range: {number}");
+                }
+            }
+            Event::InputClosed { id, .. } => println!("Input `{id}` was closed"),
+            Event::Stop => println!("Received stop"),
+            other => eprintln!("Received unexpected event: {other:?}"),
+        }
+    }
+
+    println!("received {values} values and {averages} averages");
+    if values < min_messages || averages < min_messages {
+        bail!("expected at least {min_messages} messages per input");
+    }
+    Ok(())
+}
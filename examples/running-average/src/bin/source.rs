@@ -0,0 +1,38 @@
+use dora_node_api::{self, dora_core::config::DataId, DoraNode, Event, IntoArrow};
+use eyre::Context;
+
+fn main() -> eyre::Result<()> {
+    let count: u64 = match std::env::var("VALUE_COUNT") {
+        Ok(count) => count.parse().context("invalid VALUE_COUNT")?,
+        Err(_) => 100,
+    };
+
+    let (node, mut events) = DoraNode::init_from_env()?;
+    let value = node.output(DataId::from("value".to_owned()))?;
+
+    let mut sent = 0;
+    while sent < count {
+        let Some(event) = events.recv() else {
+            break;
+        };
+        match event {
+            Event::Input { id, metadata, .. } => match id.as_str() {
+                "tick" => {
+                    let random: f64 = rand::random();
+                    value.send(metadata.parameters, random.into_arrow())?;
+                    sent += 1;
+                }
+                other => eprintln!("Ignoring unexpected input `{other}`"),
+            },
+            Event::Stop => {
+                println!("Received stop after {sent} values");
+                break;
+            }
+            other => eprintln!("Received unexpected event: {other:?}"),
+        }
+    }
+    println!("sent {sent} values");
+
+    // dropping the node closes the `value` output
+    Ok(())
+}
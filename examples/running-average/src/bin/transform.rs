@@ -0,0 +1,29 @@
+use dora_node_api::{self, dora_core::config::DataId, DoraNode, Event, IntoArrow};
+use eyre::Context;
+
+fn main() -> eyre::Result<()> {
+    let (node, mut events) = DoraNode::init_from_env()?;
+    let average = node.output(DataId::from("average".to_owned()))?;
+
+    let mut count = 0u64;
+    let mut mean = 0.0;
+    while let Some(event) = events.recv() {
+        match event {
+            Event::Input { id, metadata, data } => match id.as_str() {
+                "value" => {
+                    let value: f64 = TryFrom::try_from(&data).context("expected f64 value")?;
+                    count += 1;
+                    mean += (value - mean) / count as f64;
+                    average.send(metadata.parameters, mean.into_arrow())?;
+                }
+                other => eprintln!("Ignoring unexpected input `{other}`"),
+            },
+            Event::InputClosed { id, .. } => println!("Input `{id}` was closed"),
+            Event::Stop => println!("Received stop"),
+            other => eprintln!("Received unexpected event: {other:?}"),
+        }
+    }
+    println!("averaged {count} values, mean {mean:.3}");
+
+    Ok(())
+}
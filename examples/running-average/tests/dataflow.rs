@@ -0,0 +1,29 @@
+//! Runs `dataflow.yml` with the node binaries of this package.
+//!
+//! Cargo builds the binaries into `target/debug` before running the test,
+//! which is where the paths in the dataflow point to.
+
+use dora_daemon::Daemon;
+use std::path::Path;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dataflow_runs_to_completion() -> eyre::Result<()> {
+    let dataflow = Path::new(env!("CARGO_MANIFEST_DIR")).join("dataflow.yml");
+    let result = Daemon::run_dataflow(&dataflow, None).await?;
+
+    for (node_id, node_result) in &result.node_results {
+        if let Err(err) = node_result {
+            panic!("node `{node_id}` failed: {err}");
+        }
+    }
+    assert_eq!(result.node_results.len(), 3);
+
+    // the logger fails itself if it received too few messages, the summary
+    // shows that the values were fanned out to both receivers
+    let summary = result.summary.expect("no summary");
+    let edges = &summary.edges["source/value"];
+    for receiver in ["average/value", "logger/value"] {
+        assert_eq!(edges[receiver].sent, 100, "{receiver}");
+    }
+    Ok(())
+}
@@ -28,7 +28,7 @@ fn main() -> eyre::Result<()> {
             Event::Stop => {
                 println!("Received manual stop");
             }
-            Event::InputClosed { id } => {
+            Event::InputClosed { id, .. } => {
                 println!("Input `{id}` was closed");
             }
             other => eprintln!("Received unexpected input: {other:?}"),
@@ -30,7 +30,7 @@ fn main() -> eyre::Result<()> {
                 other => eprintln!("ignoring unexpected input {other}"),
             },
             Event::Stop => {}
-            Event::InputClosed { id } => {
+            Event::InputClosed { id, .. } => {
                 println!("input `{id}` was closed");
                 if *id == "random" {
                     println!("`random` input was closed -> exiting");
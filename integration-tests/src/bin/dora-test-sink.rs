@@ -0,0 +1,47 @@
+//! Counts the messages of all its inputs and writes a [`SinkReport`] when
+//! it exits.
+//!
+//! Sleeps for `SINK_DELAY_MS` after each message and exits after
+//! `SINK_MAX_INPUTS` messages, if set.
+
+use dora_integration_tests::{env_var, SinkReport};
+use dora_node_api::{DoraNode, Event};
+use std::time::Duration;
+
+fn main() -> eyre::Result<()> {
+    let delay = env_var("SINK_DELAY_MS")?.map(Duration::from_millis);
+    let max_inputs: Option<u64> = env_var("SINK_MAX_INPUTS")?;
+
+    let (_node, mut events) = DoraNode::init_from_env()?;
+    let mut report = SinkReport::default();
+    while let Some(event) = events.recv() {
+        match event {
+            Event::Input { data, .. } => {
+                report.received += 1;
+                report.total_len += data.len() as u64;
+                if data.is_empty() {
+                    report.zero_length += 1;
+                } else if let Ok(bytes) = data.as_bytes() {
+                    if bytes.iter().any(|b| *b != bytes[0]) {
+                        report.corrupted += 1;
+                    }
+                }
+                if let Some(delay) = delay {
+                    std::thread::sleep(delay);
+                }
+                if max_inputs.is_some_and(|max| report.received >= max) {
+                    break;
+                }
+            }
+            Event::InputClosed { delivered, .. } => {
+                report.closed_inputs += 1;
+                report.delivered += delivered;
+            }
+            Event::Stop => report.stopped = true,
+            Event::Error(err) => eprintln!("received error event: {err}"),
+            _ => {}
+        }
+    }
+
+    report.write()
+}
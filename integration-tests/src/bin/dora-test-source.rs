@@ -0,0 +1,26 @@
+//! Sends `SOURCE_COUNT` messages of `SOURCE_LEN` bytes on its `data` output,
+//! optionally waiting `SOURCE_INTERVAL_MS` between them.
+//!
+//! Each message is filled with its sequence number, truncated to a byte.
+
+use dora_integration_tests::env_var;
+use dora_node_api::{dora_core::config::DataId, DoraNode, MetadataParameters};
+use std::time::Duration;
+
+fn main() -> eyre::Result<()> {
+    let count: u64 = env_var("SOURCE_COUNT")?.unwrap_or(100);
+    let len: usize = env_var("SOURCE_LEN")?.unwrap_or(8);
+    let interval = env_var("SOURCE_INTERVAL_MS")?.map(Duration::from_millis);
+
+    let output = DataId::from("data".to_owned());
+    let (mut node, _events) = DoraNode::init_from_env()?;
+    for i in 0..count {
+        let data = vec![i as u8; len];
+        node.send_output_bytes(output.clone(), MetadataParameters::default(), len, &data)?;
+        if let Some(interval) = interval {
+            std::thread::sleep(interval);
+        }
+    }
+
+    Ok(())
+}
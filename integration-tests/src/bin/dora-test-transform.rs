@@ -0,0 +1,29 @@
+//! Forwards its `in` input to its `out` output.
+//!
+//! Panics after forwarding `TRANSFORM_CRASH_AFTER` messages, if set.
+
+use dora_integration_tests::env_var;
+use dora_node_api::{dora_core::config::DataId, DoraNode, Event};
+
+fn main() -> eyre::Result<()> {
+    let crash_after: Option<u64> = env_var("TRANSFORM_CRASH_AFTER")?;
+
+    let output = DataId::from("out".to_owned());
+    let (mut node, mut events) = DoraNode::init_from_env()?;
+    let mut forwarded = 0;
+    while let Some(event) = events.recv() {
+        match event {
+            Event::Input { metadata, data, .. } => {
+                node.send_output(output.clone(), metadata.parameters, data.0)?;
+                forwarded += 1;
+                if crash_after.is_some_and(|n| forwarded >= n) {
+                    panic!("crashing after forwarding {forwarded} messages");
+                }
+            }
+            Event::Error(err) => eprintln!("received error event: {err}"),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! End-to-end tests that run dataflows of small test nodes on a local daemon.
+//!
+//! The test nodes are the binaries in `src/bin`. They are configured through
+//! environment variables and write a [`SinkReport`] to the file given in
+//! [`REPORT_FILE_ENV`] when they exit, which the tests then check.
+
+use dora_daemon::Daemon;
+use dora_message::{
+    coordinator_to_cli::{DataflowResult, NodeError},
+    summary::EdgeSummary,
+};
+use dora_node_api::dora_core::config::NodeId;
+use eyre::{bail, eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+/// Path of the file to which a test node writes its report.
+pub const REPORT_FILE_ENV: &str = "DORA_TEST_REPORT_FILE";
+
+/// Queue size of the inputs added through [`TestNode::input`], large enough
+/// that no messages are dropped in the tests.
+pub const LOSSLESS_QUEUE_SIZE: usize = 10_000;
+
+/// How long to wait for node processes and shared memory regions to
+/// disappear after a dataflow finished.
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Dataflows share the shared memory namespace and the node binaries, so
+/// they run one at a time to check for leftovers.
+static RUN_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Messages received by a test sink.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SinkReport {
+    /// Received inputs, including zero-length ones.
+    pub received: u64,
+    /// Received inputs without data.
+    pub zero_length: u64,
+    /// Sum of the lengths of all received arrays.
+    pub total_len: u64,
+    /// Received byte arrays whose bytes differ from each other, although
+    /// the test source fills each message with a single value.
+    pub corrupted: u64,
+    /// Inputs that were closed before the sink exited.
+    pub closed_inputs: u64,
+    /// Sum of the messages that the daemon delivered to the closed inputs,
+    /// as reported in their `InputClosed` events.
+    pub delivered: u64,
+    /// Whether the sink received a stop event.
+    pub stopped: bool,
+}
+
+impl SinkReport {
+    /// Writes the report to the file given in [`REPORT_FILE_ENV`].
+    pub fn write(&self) -> eyre::Result<()> {
+        let path = std::env::var(REPORT_FILE_ENV)
+            .wrap_err_with(|| format!("{REPORT_FILE_ENV} is not set"))?;
+        let report = serde_json::to_vec_pretty(self)?;
+        std::fs::write(&path, report).wrap_err_with(|| format!("failed to write report to {path}"))
+    }
+}
+
+/// Parses the given environment variable of a test node, if it is set.
+pub fn env_var<T>(name: &str) -> eyre::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| eyre!("invalid value `{value}` for {name}: {err}")),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err).wrap_err_with(|| format!("failed to read {name}")),
+    }
+}
+
+/// A node of a [`TestDataflow`].
+pub struct TestNode {
+    id: String,
+    path: PathBuf,
+    args: Option<String>,
+    inputs: BTreeMap<String, serde_json::Value>,
+    outputs: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl TestNode {
+    /// Creates a node that runs the given executable, e.g. a path from
+    /// `env!("CARGO_BIN_EXE_dora-test-sink")`.
+    pub fn new(id: &str, path: impl Into<PathBuf>) -> Self {
+        Self {
+            id: id.to_owned(),
+            path: path.into(),
+            args: None,
+            inputs: BTreeMap::new(),
+            outputs: Vec::new(),
+            env: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an input with a queue size of [`LOSSLESS_QUEUE_SIZE`].
+    pub fn input(self, id: &str, source: &str) -> Self {
+        self.input_with_queue_size(id, source, LOSSLESS_QUEUE_SIZE)
+    }
+
+    pub fn input_with_queue_size(mut self, id: &str, source: &str, queue_size: usize) -> Self {
+        self.inputs.insert(
+            id.to_owned(),
+            json!({ "source": source, "queue_size": queue_size }),
+        );
+        self
+    }
+
+    pub fn output(mut self, id: &str) -> Self {
+        self.outputs.push(id.to_owned());
+        self
+    }
+
+    pub fn args(mut self, args: &str) -> Self {
+        self.args = Some(args.to_owned());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: impl ToString) -> Self {
+        self.env.insert(key.to_owned(), value.to_string());
+        self
+    }
+
+    fn descriptor(&self, report_dir: &Path) -> serde_json::Value {
+        let mut env = self.env.clone();
+        env.insert(
+            REPORT_FILE_ENV.to_owned(),
+            report_path(report_dir, &self.id).display().to_string(),
+        );
+        json!({
+            "id": self.id,
+            "path": self.path,
+            "args": self.args,
+            "inputs": self.inputs,
+            "outputs": self.outputs,
+            "env": env,
+        })
+    }
+}
+
+/// A dataflow that is generated in a temporary directory and run on a
+/// local daemon through [`Daemon::run_dataflow`].
+#[derive(Default)]
+pub struct TestDataflow {
+    nodes: Vec<TestNode>,
+}
+
+impl TestDataflow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node(mut self, node: TestNode) -> Self {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Runs the dataflow until all nodes exited.
+    ///
+    /// Fails if node processes or shared memory regions of the run are left
+    /// over afterwards, or if messages of an edge were neither delivered nor
+    /// dropped.
+    pub async fn run(self) -> eyre::Result<FinishedDataflow> {
+        let _guard = RUN_LOCK.lock().await;
+
+        let dir = tempfile::tempdir().context("failed to create dataflow dir")?;
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| node.descriptor(dir.path()))
+            .collect();
+        // JSON is valid YAML
+        let descriptor = serde_json::to_string_pretty(&json!({ "nodes": nodes }))?;
+        let dataflow_path = dir.path().join("dataflow.yml");
+        std::fs::write(&dataflow_path, descriptor).context("failed to write dataflow")?;
+
+        let regions_before = shared_memory_regions()?;
+        // fails if the daemon didn't clean up after the dataflow
+        let result = Daemon::run_dataflow_checked(&dataflow_path, None, true)
+            .await
+            .wrap_err("failed to run dataflow")?;
+        let summary = result.summary.as_ref().context("dataflow has no summary")?;
+        let unaccounted: Vec<_> = summary
+            .unaccounted_edges()
+            .map(|(source, receiver, edge)| format!("`{source}` -> `{receiver}`: {edge}"))
+            .collect();
+        if !unaccounted.is_empty() {
+            bail!("messages of edges are not accounted for: {unaccounted:?}");
+        }
+
+        let binaries: Vec<_> = self.nodes.iter().map(|node| node.path.clone()).collect();
+        let deadline = Instant::now() + CLEANUP_TIMEOUT;
+        loop {
+            let processes = running_processes(&binaries)?;
+            let regions: Vec<_> = shared_memory_regions()?
+                .difference(&regions_before)
+                .cloned()
+                .collect();
+            if processes.is_empty() && regions.is_empty() {
+                break;
+            }
+            if Instant::now() > deadline {
+                bail!(
+                    "dataflow left node processes {processes:?} and \
+                    shared memory regions {regions:?} behind"
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        Ok(FinishedDataflow { result, dir })
+    }
+}
+
+/// Result of a [`TestDataflow`] run.
+pub struct FinishedDataflow {
+    pub result: DataflowResult,
+    /// Keeps the reports of the nodes until the test is done.
+    dir: TempDir,
+}
+
+impl FinishedDataflow {
+    pub fn node_result(&self, node_id: &str) -> eyre::Result<&Result<(), NodeError>> {
+        self.result
+            .node_results
+            .get(&NodeId::from(node_id.to_owned()))
+            .ok_or_else(|| eyre!("no result for node `{node_id}`"))
+    }
+
+    /// Fails with the node error if the given node didn't succeed.
+    pub fn check_success(&self, node_id: &str) -> eyre::Result<()> {
+        match self.node_result(node_id)? {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("node `{node_id}` failed: {err}"),
+        }
+    }
+
+    /// Message counts of the edge from the given output to the given input,
+    /// both as `node_id/data_id`.
+    pub fn edge(&self, source: &str, receiver: &str) -> eyre::Result<&EdgeSummary> {
+        self.result
+            .summary
+            .as_ref()
+            .and_then(|summary| summary.edges.get(source)?.get(receiver))
+            .ok_or_else(|| eyre!("no edge from `{source}` to `{receiver}`"))
+    }
+
+    pub fn sink_report(&self, node_id: &str) -> eyre::Result<SinkReport> {
+        let path = report_path(self.dir.path(), node_id);
+        let report =
+            std::fs::read(&path).wrap_err_with(|| format!("node `{node_id}` wrote no report"))?;
+        serde_json::from_slice(&report)
+            .wrap_err_with(|| format!("invalid report of node `{node_id}`"))
+    }
+}
+
+fn report_path(dir: &Path, node_id: &str) -> PathBuf {
+    dir.join(format!("{node_id}.report.json"))
+}
+
+/// Names of the POSIX shared memory regions that currently exist.
+#[cfg(target_os = "linux")]
+fn shared_memory_regions() -> eyre::Result<BTreeSet<String>> {
+    let entries = std::fs::read_dir("/dev/shm").context("failed to read /dev/shm")?;
+    let mut regions = BTreeSet::new();
+    for entry in entries {
+        regions.insert(entry?.file_name().to_string_lossy().into_owned());
+    }
+    Ok(regions)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn shared_memory_regions() -> eyre::Result<BTreeSet<String>> {
+    Ok(BTreeSet::new())
+}
+
+/// IDs of the running processes of the given executables.
+#[cfg(target_os = "linux")]
+fn running_processes(binaries: &[PathBuf]) -> eyre::Result<Vec<u32>> {
+    let binaries: Vec<_> = binaries
+        .iter()
+        .filter_map(|b| b.canonicalize().ok())
+        .collect();
+    let mut processes = Vec::new();
+    for entry in std::fs::read_dir("/proc").context("failed to read /proc")? {
+        let entry = entry?;
+        let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        // processes can exit while we look at them
+        let Ok(exe) = std::fs::read_link(entry.path().join("exe")) else {
+            continue;
+        };
+        if binaries.contains(&exe) {
+            processes.push(pid);
+        }
+    }
+    Ok(processes)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn running_processes(_binaries: &[PathBuf]) -> eyre::Result<Vec<u32>> {
+    Ok(Vec::new())
+}
@@ -0,0 +1,177 @@
+use dora_integration_tests::{TestDataflow, TestNode};
+use dora_message::coordinator_to_cli::{NodeErrorCause, NodeExitStatus};
+
+const SOURCE: &str = env!("CARGO_BIN_EXE_dora-test-source");
+const TRANSFORM: &str = env!("CARGO_BIN_EXE_dora-test-transform");
+const SINK: &str = env!("CARGO_BIN_EXE_dora-test-sink");
+
+/// `source` -> `transform` -> `sink`, without message drops.
+fn pipeline(count: u64, len: usize) -> (TestNode, TestNode, TestNode) {
+    let source = TestNode::new("source", SOURCE)
+        .output("data")
+        .env("SOURCE_COUNT", count)
+        .env("SOURCE_LEN", len);
+    let transform = TestNode::new("transform", TRANSFORM)
+        .input("in", "source/data")
+        .output("out");
+    let sink = TestNode::new("sink", SINK).input("in", "transform/out");
+    (source, transform, sink)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_pass_through_pipeline() -> eyre::Result<()> {
+    let (source, transform, sink) = pipeline(100, 8);
+    let finished = TestDataflow::new()
+        .node(source)
+        .node(transform)
+        .node(sink)
+        .run()
+        .await?;
+
+    for node in ["source", "transform", "sink"] {
+        finished.check_success(node)?;
+    }
+    let report = finished.sink_report("sink")?;
+    assert_eq!(report.received, 100);
+    assert_eq!(report.delivered, 100);
+    assert_eq!(report.total_len, 800);
+    assert_eq!(report.corrupted, 0);
+    assert_eq!(report.closed_inputs, 1);
+    for (source, receiver) in [
+        ("source/data", "transform/in"),
+        ("transform/out", "sink/in"),
+    ] {
+        let edge = finished.edge(source, receiver)?;
+        assert_eq!(
+            (edge.sent, edge.delivered),
+            (100, 100),
+            "{source} -> {receiver}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn crashing_node_closes_downstream_inputs() -> eyre::Result<()> {
+    let (source, transform, sink) = pipeline(50, 8);
+    let finished = TestDataflow::new()
+        .node(source.env("SOURCE_INTERVAL_MS", 1))
+        .node(transform.env("TRANSFORM_CRASH_AFTER", 5))
+        .node(sink)
+        .run()
+        .await?;
+
+    finished.check_success("source")?;
+    finished.check_success("sink")?;
+    let Err(error) = finished.node_result("transform")? else {
+        panic!("crashing node succeeded");
+    };
+    // exit code of panicking Rust programs
+    assert!(matches!(error.exit_status, NodeExitStatus::ExitCode(101)));
+    assert!(!matches!(error.cause, NodeErrorCause::Cascading { .. }));
+
+    let report = finished.sink_report("sink")?;
+    assert_eq!(report.received, 5);
+    assert_eq!(report.closed_inputs, 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_sink_drops_messages() -> eyre::Result<()> {
+    // large enough to be sent through shared memory
+    let source = TestNode::new("source", SOURCE)
+        .output("data")
+        .env("SOURCE_COUNT", 200)
+        .env("SOURCE_LEN", 64 * 1024);
+    let sink = TestNode::new("sink", SINK)
+        .input_with_queue_size("in", "source/data", 1)
+        .env("SINK_DELAY_MS", 5);
+    let finished = TestDataflow::new().node(source).node(sink).run().await?;
+
+    finished.check_success("source")?;
+    finished.check_success("sink")?;
+    let report = finished.sink_report("sink")?;
+    assert_eq!(report.delivered, 200);
+    assert!(report.received > 0);
+    assert!(
+        report.received < report.delivered,
+        "no messages were dropped: {report:?}"
+    );
+    assert_eq!(report.total_len, report.received * 64 * 1024);
+    assert_eq!(report.corrupted, 0);
+    let edge = finished.edge("source/data", "sink/in")?;
+    assert_eq!(edge.sent, 200);
+    assert!(edge.delivered >= report.received, "{edge}");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn timer_drives_sink() -> eyre::Result<()> {
+    let sink = TestNode::new("sink", SINK)
+        .input("tick", "dora/timer/millis/10")
+        .env("SINK_MAX_INPUTS", 20);
+    let finished = TestDataflow::new().node(sink).run().await?;
+
+    finished.check_success("sink")?;
+    let report = finished.sink_report("sink")?;
+    assert_eq!(report.received, 20);
+    assert_eq!(report.closed_inputs, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn zero_length_messages_are_delivered() -> eyre::Result<()> {
+    let (source, transform, sink) = pipeline(50, 0);
+    let finished = TestDataflow::new()
+        .node(source)
+        .node(transform)
+        .node(sink)
+        .run()
+        .await?;
+
+    for node in ["source", "transform", "sink"] {
+        finished.check_success(node)?;
+    }
+    let report = finished.sink_report("sink")?;
+    assert_eq!(report.received, 50);
+    assert_eq!(report.zero_length, 50);
+    assert_eq!(report.total_len, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn detached_node_runs_until_its_process_exits() -> eyre::Result<()> {
+    let (source, transform, sink) = pipeline(20, 8);
+    // neither inputs nor outputs, and no dora API
+    let sleeper = TestNode::new("sleeper", "sleep").args("1");
+    let start = std::time::Instant::now();
+    let finished = TestDataflow::new()
+        .node(sleeper)
+        .node(source)
+        .node(transform)
+        .node(sink)
+        .run()
+        .await?;
+
+    for node in ["sleeper", "source", "transform", "sink"] {
+        finished.check_success(node)?;
+    }
+    // the dataflow is finished once the detached process exited
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    assert_eq!(finished.sink_report("sink")?.received, 20);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn detached_node_failure_is_reported() -> eyre::Result<()> {
+    let finished = TestDataflow::new()
+        .node(TestNode::new("failing", "false"))
+        .run()
+        .await?;
+
+    let Err(error) = finished.node_result("failing")? else {
+        panic!("failing detached node succeeded");
+    };
+    assert!(matches!(error.exit_status, NodeExitStatus::ExitCode(1)));
+    Ok(())
+}
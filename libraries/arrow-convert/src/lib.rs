@@ -11,9 +11,32 @@ pub trait IntoArrow {
     fn into_arrow(self) -> Self::A;
 }
 
+/// Received input data.
+///
+/// The data of shared memory messages is not copied: the array points into
+/// the read-only mapping of the shared memory region. The region stays
+/// mapped until this value and all arrays that were cloned from it are
+/// dropped. At this point, the sender is notified that it can reuse the
+/// region.
 #[derive(Debug)]
 pub struct ArrowData(pub arrow::array::ArrayRef);
 
+impl ArrowData {
+    /// Borrows the raw bytes of a `UInt8` array (e.g. sent through
+    /// `send_output_raw`), without copying them.
+    pub fn as_bytes(&self) -> eyre::Result<&[u8]> {
+        self.try_into()
+    }
+
+    /// Copies the raw bytes of a `UInt8` array into a new `Vec`.
+    ///
+    /// Releases the underlying shared memory region, so this is useful if the
+    /// data needs to be kept for a long time.
+    pub fn into_vec(self) -> eyre::Result<Vec<u8>> {
+        (&self).try_into()
+    }
+}
+
 impl Deref for ArrowData {
     type Target = arrow::array::ArrayRef;
 
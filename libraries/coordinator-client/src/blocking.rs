@@ -0,0 +1,374 @@
+//! Blocking version of the [`CoordinatorClient`](crate::CoordinatorClient).
+//!
+//! Each method runs the corresponding async method to completion on an
+//! internal single-threaded runtime, so this must not be used from within an
+//! async context.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    time::Duration,
+};
+
+use dora_core::{
+    config::{DataId, NodeId, OperatorId},
+    descriptor::Descriptor,
+};
+use dora_message::{
+    cli_to_coordinator::{RegistryKey, Role},
+    coordinator_to_cli::{
+        CoordinatorEvent, DaemonDiagnostics, DataflowDiff, DataflowList, DataflowListEntry,
+        DataflowPlan, DataflowResult, LogMessage, MachineStatus, NodeJoinInfo, NodeMigrationReport,
+        NodeReloadReport, ReconfigureReport, RegistryEntry, TappedMessage,
+    },
+    reconfigure::InputChange,
+};
+use futures::{Stream, StreamExt};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+use crate::{ClientError, DataflowHandle, StartOptions};
+
+/// Blocking connection to the control server of a coordinator.
+pub struct CoordinatorClient {
+    inner: crate::CoordinatorClient,
+    runtime: Runtime,
+}
+
+/// Blocking iterator over the messages of a subscription, e.g. from
+/// [`CoordinatorClient::subscribe_logs`].
+///
+/// The iterator owns its connection, so it can be moved to another thread.
+pub struct Messages<T> {
+    stream: Pin<Box<dyn Stream<Item = Result<T, ClientError>> + Send>>,
+    runtime: Runtime,
+}
+
+impl<T> Iterator for Messages<T> {
+    type Item = Result<T, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
+
+impl CoordinatorClient {
+    /// Connects to the control server of the coordinator at the given address.
+    pub fn connect(addr: SocketAddr) -> Result<Self, ClientError> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::CoordinatorClient::connect(addr))?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// The address of the coordinator's control server.
+    pub fn addr(&self) -> SocketAddr {
+        self.inner.addr()
+    }
+
+    /// See [`crate::CoordinatorClient::authenticate`].
+    pub fn authenticate(&mut self, token: String) -> Result<Role, ClientError> {
+        self.runtime.block_on(self.inner.authenticate(token))
+    }
+
+    /// See [`crate::CoordinatorClient::start`].
+    pub fn start(
+        &mut self,
+        dataflow: Descriptor,
+        options: StartOptions,
+    ) -> Result<DataflowHandle, ClientError> {
+        self.runtime.block_on(self.inner.start(dataflow, options))
+    }
+
+    /// See [`crate::CoordinatorClient::start_registered`].
+    pub fn start_registered(
+        &mut self,
+        dataflow: RegistryKey,
+        options: StartOptions,
+    ) -> Result<DataflowHandle, ClientError> {
+        self.runtime
+            .block_on(self.inner.start_registered(dataflow, options))
+    }
+
+    /// See [`crate::CoordinatorClient::plan`].
+    pub fn plan(
+        &mut self,
+        dataflow: Descriptor,
+        options: StartOptions,
+    ) -> Result<DataflowPlan, ClientError> {
+        self.runtime.block_on(self.inner.plan(dataflow, options))
+    }
+
+    /// See [`crate::CoordinatorClient::list`].
+    pub fn list(&mut self) -> Result<DataflowList, ClientError> {
+        self.runtime.block_on(self.inner.list())
+    }
+
+    /// See [`crate::CoordinatorClient::status`].
+    pub fn status(&mut self, dataflow_id: Uuid) -> Result<DataflowListEntry, ClientError> {
+        self.runtime.block_on(self.inner.status(dataflow_id))
+    }
+
+    /// See [`crate::CoordinatorClient::resolve`].
+    pub fn resolve(&mut self, dataflow: &str) -> Result<Uuid, ClientError> {
+        self.runtime.block_on(self.inner.resolve(dataflow))
+    }
+
+    /// See [`crate::CoordinatorClient::result`].
+    pub fn result(&mut self, dataflow_id: Uuid) -> Result<Option<DataflowResult>, ClientError> {
+        self.runtime.block_on(self.inner.result(dataflow_id))
+    }
+
+    /// See [`crate::CoordinatorClient::stop`].
+    pub fn stop(
+        &mut self,
+        dataflow_id: Uuid,
+        grace_duration: Option<Duration>,
+    ) -> Result<DataflowResult, ClientError> {
+        self.runtime
+            .block_on(self.inner.stop(dataflow_id, grace_duration))
+    }
+
+    /// See [`crate::CoordinatorClient::stop_by_name`].
+    pub fn stop_by_name(
+        &mut self,
+        name: String,
+        instance: Option<String>,
+        grace_duration: Option<Duration>,
+    ) -> Result<(Uuid, DataflowResult), ClientError> {
+        self.runtime
+            .block_on(self.inner.stop_by_name(name, instance, grace_duration))
+    }
+
+    /// See [`crate::CoordinatorClient::reload`].
+    pub fn reload(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        operator_id: Option<OperatorId>,
+    ) -> Result<(), ClientError> {
+        self.runtime
+            .block_on(self.inner.reload(dataflow_id, node_id, operator_id))
+    }
+
+    /// See [`crate::CoordinatorClient::reload_node`].
+    pub fn reload_node(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+    ) -> Result<NodeReloadReport, ClientError> {
+        self.runtime
+            .block_on(self.inner.reload_node(dataflow_id, node_id))
+    }
+
+    /// See [`crate::CoordinatorClient::migrate_node`].
+    pub fn migrate_node(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        target_machine: String,
+    ) -> Result<NodeMigrationReport, ClientError> {
+        self.runtime.block_on(
+            self.inner
+                .migrate_node(dataflow_id, node_id, target_machine),
+        )
+    }
+
+    /// See [`crate::CoordinatorClient::reconfigure`].
+    pub fn reconfigure(
+        &mut self,
+        dataflow_id: Uuid,
+        changes: Vec<InputChange>,
+    ) -> Result<ReconfigureReport, ClientError> {
+        self.runtime
+            .block_on(self.inner.reconfigure(dataflow_id, changes))
+    }
+
+    /// See [`crate::CoordinatorClient::logs`].
+    pub fn logs(
+        &mut self,
+        dataflow_id: Uuid,
+        node: &str,
+        tail: Option<usize>,
+    ) -> Result<Vec<u8>, ClientError> {
+        self.runtime
+            .block_on(self.inner.logs(dataflow_id, node, tail))
+    }
+
+    /// See [`crate::CoordinatorClient::logs_by_name`].
+    pub fn logs_by_name(
+        &mut self,
+        name: String,
+        node: &str,
+        tail: Option<usize>,
+    ) -> Result<Vec<u8>, ClientError> {
+        self.runtime
+            .block_on(self.inner.logs_by_name(name, node, tail))
+    }
+
+    /// See [`crate::CoordinatorClient::resolve_node`].
+    pub fn resolve_node(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+    ) -> Result<NodeJoinInfo, ClientError> {
+        self.runtime
+            .block_on(self.inner.resolve_node(dataflow_id, node_id))
+    }
+
+    /// See [`crate::CoordinatorClient::resolve_node_by_name`].
+    pub fn resolve_node_by_name(
+        &mut self,
+        name: String,
+        node_id: NodeId,
+    ) -> Result<NodeJoinInfo, ClientError> {
+        self.runtime
+            .block_on(self.inner.resolve_node_by_name(name, node_id))
+    }
+
+    /// See [`crate::CoordinatorClient::descriptor`].
+    pub fn descriptor(&mut self, dataflow_id: Uuid) -> Result<String, ClientError> {
+        self.runtime.block_on(self.inner.descriptor(dataflow_id))
+    }
+
+    /// See [`crate::CoordinatorClient::diff`].
+    pub fn diff(
+        &mut self,
+        dataflow_id: Uuid,
+        dataflow: Descriptor,
+    ) -> Result<DataflowDiff, ClientError> {
+        self.runtime
+            .block_on(self.inner.diff(dataflow_id, dataflow))
+    }
+
+    /// See [`crate::CoordinatorClient::register`].
+    pub fn register(
+        &mut self,
+        name: String,
+        version: Option<u32>,
+        dataflow: Descriptor,
+        working_dir: PathBuf,
+    ) -> Result<RegistryEntry, ClientError> {
+        self.runtime
+            .block_on(self.inner.register(name, version, dataflow, working_dir))
+    }
+
+    /// See [`crate::CoordinatorClient::list_registered`].
+    pub fn list_registered(&mut self) -> Result<Vec<RegistryEntry>, ClientError> {
+        self.runtime.block_on(self.inner.list_registered())
+    }
+
+    /// See [`crate::CoordinatorClient::fetch_registered`].
+    pub fn fetch_registered(
+        &mut self,
+        dataflow: RegistryKey,
+    ) -> Result<(RegistryEntry, Descriptor), ClientError> {
+        self.runtime.block_on(self.inner.fetch_registered(dataflow))
+    }
+
+    /// See [`crate::CoordinatorClient::delete_registered`].
+    pub fn delete_registered(
+        &mut self,
+        dataflow: RegistryKey,
+    ) -> Result<Vec<RegistryEntry>, ClientError> {
+        self.runtime
+            .block_on(self.inner.delete_registered(dataflow))
+    }
+
+    /// See [`crate::CoordinatorClient::daemon_connected`].
+    pub fn daemon_connected(&mut self) -> Result<bool, ClientError> {
+        self.runtime.block_on(self.inner.daemon_connected())
+    }
+
+    /// See [`crate::CoordinatorClient::connected_machines`].
+    pub fn connected_machines(&mut self) -> Result<BTreeSet<String>, ClientError> {
+        self.runtime.block_on(self.inner.connected_machines())
+    }
+
+    /// See [`crate::CoordinatorClient::daemon_status`].
+    pub fn daemon_status(&mut self) -> Result<BTreeMap<String, MachineStatus>, ClientError> {
+        self.runtime.block_on(self.inner.daemon_status())
+    }
+
+    /// See [`crate::CoordinatorClient::set_log_level`].
+    pub fn set_log_level(
+        &mut self,
+        machine_id: String,
+        filter: String,
+    ) -> Result<String, ClientError> {
+        self.runtime
+            .block_on(self.inner.set_log_level(machine_id, filter))
+    }
+
+    /// See [`crate::CoordinatorClient::diagnostics`].
+    pub fn diagnostics(
+        &mut self,
+        machine_id: String,
+        gc: Option<Duration>,
+    ) -> Result<DaemonDiagnostics, ClientError> {
+        self.runtime
+            .block_on(self.inner.diagnostics(machine_id, gc))
+    }
+
+    /// See [`crate::CoordinatorClient::destroy`].
+    pub fn destroy(&mut self) -> Result<(), ClientError> {
+        self.runtime.block_on(self.inner.destroy())
+    }
+
+    /// See [`crate::CoordinatorClient::subscribe_logs`].
+    pub fn subscribe_logs(
+        &self,
+        dataflow_id: Uuid,
+        level: log::LevelFilter,
+    ) -> Result<Messages<LogMessage>, ClientError> {
+        // the subscription gets its own runtime so that it can be consumed
+        // on a different thread
+        let runtime = runtime()?;
+        let stream = runtime.block_on(self.inner.subscribe_logs(dataflow_id, level))?;
+        Ok(Messages {
+            stream: Box::pin(stream),
+            runtime,
+        })
+    }
+
+    /// See [`crate::CoordinatorClient::subscribe_events`].
+    pub fn subscribe_events(&self) -> Result<Messages<CoordinatorEvent>, ClientError> {
+        let runtime = runtime()?;
+        let stream = runtime.block_on(self.inner.subscribe_events())?;
+        Ok(Messages {
+            stream: Box::pin(stream),
+            runtime,
+        })
+    }
+
+    /// See [`crate::CoordinatorClient::tap`].
+    pub fn tap(
+        &self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output_id: DataId,
+        duration: Duration,
+        max_rate: Option<f64>,
+    ) -> Result<Messages<TappedMessage>, ClientError> {
+        let runtime = runtime()?;
+        let stream = runtime.block_on(self.inner.tap(
+            dataflow_id,
+            node_id,
+            output_id,
+            duration,
+            max_rate,
+        ))?;
+        Ok(Messages {
+            stream: Box::pin(stream),
+            runtime,
+        })
+    }
+}
+
+fn runtime() -> Result<Runtime, ClientError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    Ok(runtime)
+}
@@ -0,0 +1,1045 @@
+//! Client for the control protocol of `dora-coordinator`.
+//!
+//! This is the protocol that the `dora` CLI uses to start, stop, and monitor
+//! dataflows. Each request is sent as a length-prefixed JSON message over
+//! TCP, and the coordinator answers with a [`ControlRequestReply`].
+//!
+//! For synchronous code, see the [`blocking`] module.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    time::Duration,
+};
+
+use dora_core::{
+    config::{DataId, NodeId, OperatorId},
+    descriptor::Descriptor,
+};
+use dora_message::{
+    cli_to_coordinator::{ControlRequest, InstanceKey, RegistryKey, Role},
+    coordinator_to_cli::{
+        AccessError, ControlRequestReply, CoordinatorEvent, DaemonDiagnostics, DataflowDiff,
+        DataflowList, DataflowListEntry, DataflowPlan, DataflowResult, LogMessage, MachineStatus,
+        NodeJoinInfo, NodeMigrationReport, NodeReloadReport, ReconfigureReport, RegistryEntry,
+        TappedMessage,
+    },
+    reconfigure::InputChange,
+};
+use futures::{stream, Stream};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use uuid::Uuid;
+
+pub mod blocking;
+
+/// Connection to the control server of a coordinator.
+pub struct CoordinatorClient {
+    addr: SocketAddr,
+    connection: TcpStream,
+    /// Token of the last successful [`Self::authenticate`] call, used for
+    /// the separate connections of subscriptions.
+    token: Option<String>,
+}
+
+/// Options for starting a dataflow, see [`CoordinatorClient::start`].
+#[derive(Debug, Clone)]
+pub struct StartOptions {
+    /// Directory that relative node paths are resolved against, typically
+    /// the directory of the dataflow file.
+    pub working_dir: PathBuf,
+    /// Working directories of specific machines, for machines that don't
+    /// share the `working_dir`.
+    pub machine_working_dirs: BTreeMap<String, PathBuf>,
+    /// Name of the dataflow.
+    pub name: Option<String>,
+    /// Starts the dataflow as an instance of `name`, so that multiple
+    /// dataflows can run under the same name.
+    pub instance: Option<InstanceKey>,
+    /// Start parameters, which nodes can read through
+    /// `DoraNode::dataflow_params`.
+    pub params: BTreeMap<String, String>,
+}
+
+/// A dataflow that was started through [`CoordinatorClient::start`].
+#[derive(Debug, Clone)]
+pub struct DataflowHandle {
+    pub uuid: Uuid,
+    /// Machines that the coordinator picked for nodes without a fixed
+    /// machine.
+    pub assignments: BTreeMap<NodeId, String>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The coordinator is not reachable or the connection failed.
+    Connection(std::io::Error),
+    /// The request is invalid, e.g. because of an invalid dataflow
+    /// descriptor.
+    Validation(String),
+    /// There is no dataflow with the given UUID or name.
+    NotFound(String),
+    /// The coordinator has access control and didn't permit the request.
+    AccessDenied(AccessError),
+    /// The coordinator failed to handle the request.
+    Coordinator(String),
+    /// The coordinator sent a reply that doesn't match the request.
+    UnexpectedReply(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Connection(err) => {
+                write!(f, "failed to communicate with dora-coordinator: {err}")
+            }
+            ClientError::Validation(err) => write!(f, "invalid request: {err}"),
+            ClientError::NotFound(err) => write!(f, "{err}"),
+            ClientError::AccessDenied(err) => write!(f, "{err}"),
+            ClientError::Coordinator(err) => write!(f, "{err}"),
+            ClientError::UnexpectedReply(reply) => {
+                write!(f, "unexpected reply from dora-coordinator: {reply}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Connection(err) => Some(err),
+            ClientError::AccessDenied(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(err: std::io::Error) -> Self {
+        ClientError::Connection(err)
+    }
+}
+
+impl CoordinatorClient {
+    /// Connects to the control server of the coordinator at the given address.
+    pub async fn connect(addr: SocketAddr) -> Result<Self, ClientError> {
+        let connection = TcpStream::connect(addr).await?;
+        connection.set_nodelay(true)?;
+        Ok(Self {
+            addr,
+            connection,
+            token: None,
+        })
+    }
+
+    /// Authenticates the connection with the given token, which is required
+    /// by coordinators with access control.
+    ///
+    /// Returns the role of the token. The token is also sent on the
+    /// connections that [`Self::subscribe_logs`], [`Self::subscribe_events`],
+    /// and [`Self::tap`] open later.
+    pub async fn authenticate(&mut self, token: String) -> Result<Role, ClientError> {
+        let request = ControlRequest::Authenticate {
+            token: token.clone(),
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::Authenticated { role } => {
+                self.token = Some(token);
+                Ok(role)
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// The address of the coordinator's control server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Starts the given dataflow.
+    ///
+    /// The descriptor is validated before it is sent. Node paths are not
+    /// checked, as they are resolved on the machines of the nodes.
+    pub async fn start(
+        &mut self,
+        dataflow: Descriptor,
+        options: StartOptions,
+    ) -> Result<DataflowHandle, ClientError> {
+        let StartOptions {
+            working_dir,
+            machine_working_dirs,
+            name,
+            instance,
+            params,
+        } = options;
+        dataflow
+            .check_in_daemon(&working_dir, &[], true)
+            .map_err(|err| ClientError::Validation(format!("{err:#}")))?;
+        let request = ControlRequest::Start {
+            dataflow,
+            name,
+            local_working_dir: working_dir,
+            machine_working_dirs,
+            instance,
+            params,
+            dry_run: false,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::DataflowStarted { uuid, assignments } => {
+                Ok(DataflowHandle { uuid, assignments })
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Starts a dataflow from the descriptor registry of the coordinator.
+    ///
+    /// Relative node paths are resolved against `options.working_dir`,
+    /// typically the [`RegistryEntry::working_dir`] of the entry.
+    pub async fn start_registered(
+        &mut self,
+        dataflow: RegistryKey,
+        options: StartOptions,
+    ) -> Result<DataflowHandle, ClientError> {
+        let StartOptions {
+            working_dir,
+            machine_working_dirs,
+            name,
+            instance,
+            params,
+        } = options;
+        let request = ControlRequest::StartRegistered {
+            dataflow,
+            name,
+            local_working_dir: working_dir,
+            machine_working_dirs,
+            instance,
+            params,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::DataflowStarted { uuid, assignments } => {
+                Ok(DataflowHandle { uuid, assignments })
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Resolves what [`Self::start`] would spawn for the given dataflow,
+    /// without starting it.
+    pub async fn plan(
+        &mut self,
+        dataflow: Descriptor,
+        options: StartOptions,
+    ) -> Result<DataflowPlan, ClientError> {
+        let StartOptions {
+            working_dir,
+            machine_working_dirs,
+            name,
+            instance,
+            params,
+        } = options;
+        dataflow
+            .check_in_daemon(&working_dir, &[], true)
+            .map_err(|err| ClientError::Validation(format!("{err:#}")))?;
+        let request = ControlRequest::Start {
+            dataflow,
+            name,
+            local_working_dir: working_dir,
+            machine_working_dirs,
+            instance,
+            params,
+            dry_run: true,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::DataflowPlan(plan) => Ok(plan),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Lists the running dataflows and the results of finished ones.
+    pub async fn list(&mut self) -> Result<DataflowList, ClientError> {
+        match self.request(&ControlRequest::List).await? {
+            ControlRequestReply::DataflowList(list) => Ok(list),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Status of the given running or finished dataflow.
+    pub async fn status(&mut self, dataflow_id: Uuid) -> Result<DataflowListEntry, ClientError> {
+        self.list()
+            .await?
+            .0
+            .into_iter()
+            .find(|entry| entry.id.uuid == dataflow_id)
+            .ok_or_else(|| not_found(dataflow_id))
+    }
+
+    /// Looks up the running dataflow with the given UUID or name.
+    pub async fn resolve(&mut self, dataflow: &str) -> Result<Uuid, ClientError> {
+        if let Ok(uuid) = Uuid::parse_str(dataflow) {
+            return Ok(uuid);
+        }
+        let active = self.list().await?.get_active();
+        let mut matching = active
+            .iter()
+            .filter(|d| d.name.as_deref() == Some(dataflow));
+        match (matching.next(), matching.next()) {
+            (Some(d), None) => Ok(d.uuid),
+            (None, _) => Err(ClientError::NotFound(format!(
+                "no running dataflow with name `{dataflow}`"
+            ))),
+            (Some(_), Some(_)) => Err(ClientError::Validation(format!(
+                "multiple running dataflows with name `{dataflow}`"
+            ))),
+        }
+    }
+
+    /// Returns the result of the given dataflow if it finished, or `None`
+    /// if it is still running.
+    pub async fn result(
+        &mut self,
+        dataflow_id: Uuid,
+    ) -> Result<Option<DataflowResult>, ClientError> {
+        let request = ControlRequest::Check {
+            dataflow_uuid: dataflow_id,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::DataflowStarted { .. } => Ok(None),
+            ControlRequestReply::DataflowStopped { result, .. } => Ok(Some(result)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stops the given dataflow and waits for its result.
+    ///
+    /// The nodes are killed if they don't stop within the grace duration.
+    pub async fn stop(
+        &mut self,
+        dataflow_id: Uuid,
+        grace_duration: Option<Duration>,
+    ) -> Result<DataflowResult, ClientError> {
+        let request = ControlRequest::Stop {
+            dataflow_uuid: dataflow_id,
+            grace_duration,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::DataflowStopped { result, .. } => Ok(result),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stops the running dataflow with the given name, optionally restricted
+    /// to the given instance.
+    ///
+    /// Returns the UUID of the stopped dataflow together with its result.
+    pub async fn stop_by_name(
+        &mut self,
+        name: String,
+        instance: Option<String>,
+        grace_duration: Option<Duration>,
+    ) -> Result<(Uuid, DataflowResult), ClientError> {
+        let active = self.list().await?.get_active();
+        let running = active.iter().any(|d| {
+            d.name.as_ref() == Some(&name)
+                && (instance.is_none() || d.instance.as_ref() == instance.as_ref())
+        });
+        if !running {
+            return Err(ClientError::NotFound(match instance {
+                Some(instance) => format!("no instance `{instance}` of dataflow `{name}`"),
+                None => format!("no running dataflow with name `{name}`"),
+            }));
+        }
+        let request = ControlRequest::StopByName {
+            name,
+            instance,
+            grace_duration,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::DataflowStopped { uuid, result } => Ok((uuid, result)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Reloads the given Python operator of a running dataflow.
+    pub async fn reload(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        operator_id: Option<OperatorId>,
+    ) -> Result<(), ClientError> {
+        let request = ControlRequest::Reload {
+            dataflow_id,
+            node_id,
+            operator_id,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::DataflowReloaded { .. } => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Restarts a single node of a running dataflow.
+    pub async fn reload_node(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+    ) -> Result<NodeReloadReport, ClientError> {
+        let request = ControlRequest::ReloadNode {
+            dataflow_id,
+            node_id,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::NodeReloaded { report, .. } => Ok(report),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Moves a node of a running dataflow to another machine that runs
+    /// nodes of the dataflow.
+    ///
+    /// A failed phase is reported in the returned report, check
+    /// [`NodeMigrationReport::is_success`].
+    pub async fn migrate_node(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        target_machine: String,
+    ) -> Result<NodeMigrationReport, ClientError> {
+        let request = ControlRequest::MigrateNode {
+            dataflow_id,
+            node_id,
+            target_machine,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::NodeMigrated { report, .. } => Ok(report),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Changes the queue settings of inputs of a running dataflow.
+    ///
+    /// Failed changes are reported in the returned report, check
+    /// [`ReconfigureReport::is_success`].
+    pub async fn reconfigure(
+        &mut self,
+        dataflow_id: Uuid,
+        changes: Vec<InputChange>,
+    ) -> Result<ReconfigureReport, ClientError> {
+        let request = ControlRequest::Reconfigure {
+            dataflow_id,
+            changes,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::Reconfigured { report, .. } => Ok(report),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Log output of the given node, optionally limited to the last `tail`
+    /// lines.
+    pub async fn logs(
+        &mut self,
+        dataflow_id: Uuid,
+        node: &str,
+        tail: Option<usize>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let request = ControlRequest::Logs {
+            uuid: Some(dataflow_id),
+            name: None,
+            node: node.to_owned(),
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::Logs(logs) => Ok(last_lines(logs, tail)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Like [`Self::logs`], but looks up the dataflow by name, including
+    /// finished dataflows.
+    pub async fn logs_by_name(
+        &mut self,
+        name: String,
+        node: &str,
+        tail: Option<usize>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let list = self.list().await?;
+        if !list.0.iter().any(|d| d.id.name.as_ref() == Some(&name)) {
+            return Err(ClientError::NotFound(format!(
+                "no dataflow with name `{name}`"
+            )));
+        }
+        let request = ControlRequest::Logs {
+            uuid: None,
+            name: Some(name),
+            node: node.to_owned(),
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::Logs(logs) => Ok(last_lines(logs, tail)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Looks up the daemon that runs the given node and requests a join
+    /// token for it.
+    ///
+    /// The returned info is passed to `DoraObserver::connect_with_join_info`
+    /// or `DoraNode::init_from_join_info` of the node API, also on other
+    /// machines.
+    pub async fn resolve_node(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+    ) -> Result<NodeJoinInfo, ClientError> {
+        let request = ControlRequest::ResolveNode {
+            uuid: Some(dataflow_id),
+            name: None,
+            node_id,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::NodeResolved(info) => Ok(info),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Like [`Self::resolve_node`], but looks up the dataflow by name.
+    pub async fn resolve_node_by_name(
+        &mut self,
+        name: String,
+        node_id: NodeId,
+    ) -> Result<NodeJoinInfo, ClientError> {
+        let request = ControlRequest::ResolveNode {
+            uuid: None,
+            name: Some(name),
+            node_id,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::NodeResolved(info) => Ok(info),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// The descriptor of the given running dataflow as YAML, with the nodes
+    /// in the resolved form the daemons run them in.
+    pub async fn descriptor(&mut self, dataflow_id: Uuid) -> Result<String, ClientError> {
+        let request = ControlRequest::Descriptor {
+            dataflow_uuid: dataflow_id,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::Descriptor(yaml) => Ok(yaml),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Compares the given running dataflow with the given (edited) descriptor.
+    pub async fn diff(
+        &mut self,
+        dataflow_id: Uuid,
+        dataflow: Descriptor,
+    ) -> Result<DataflowDiff, ClientError> {
+        let request = ControlRequest::Diff {
+            dataflow_uuid: dataflow_id,
+            dataflow,
+        };
+        match self.dataflow_request(dataflow_id, &request).await? {
+            ControlRequestReply::Diff(diff) => Ok(diff),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stores the given descriptor in the registry of the coordinator.
+    ///
+    /// Without version, the version after the latest registered version of
+    /// `name` is used. Registering a different descriptor under an existing
+    /// version fails.
+    pub async fn register(
+        &mut self,
+        name: String,
+        version: Option<u32>,
+        dataflow: Descriptor,
+        working_dir: PathBuf,
+    ) -> Result<RegistryEntry, ClientError> {
+        dataflow
+            .check_in_daemon(&working_dir, &[], true)
+            .map_err(|err| ClientError::Validation(format!("{err:#}")))?;
+        let request = ControlRequest::Register {
+            name,
+            version,
+            dataflow,
+            working_dir,
+        };
+        match self.request(&request).await? {
+            ControlRequestReply::Registered(entry) => Ok(entry),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// All registered descriptors, ordered by name and version.
+    pub async fn list_registered(&mut self) -> Result<Vec<RegistryEntry>, ClientError> {
+        match self.request(&ControlRequest::ListRegistered).await? {
+            ControlRequestReply::Registry(entries) => Ok(entries),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Fetches the given registered descriptor.
+    pub async fn fetch_registered(
+        &mut self,
+        dataflow: RegistryKey,
+    ) -> Result<(RegistryEntry, Descriptor), ClientError> {
+        let request = ControlRequest::FetchRegistered {
+            dataflow: dataflow.clone(),
+        };
+        match self.registry_request(&dataflow, &request).await? {
+            ControlRequestReply::RegisteredDataflow { entry, dataflow } => Ok((entry, dataflow)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Removes the given version, or all versions if the key has no version,
+    /// from the registry.
+    ///
+    /// Returns the removed entries.
+    pub async fn delete_registered(
+        &mut self,
+        dataflow: RegistryKey,
+    ) -> Result<Vec<RegistryEntry>, ClientError> {
+        let request = ControlRequest::DeleteRegistered {
+            dataflow: dataflow.clone(),
+        };
+        match self.registry_request(&dataflow, &request).await? {
+            ControlRequestReply::RegistryEntriesDeleted(entries) => Ok(entries),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Whether at least one daemon is connected to the coordinator.
+    pub async fn daemon_connected(&mut self) -> Result<bool, ClientError> {
+        match self.request(&ControlRequest::DaemonConnected).await? {
+            ControlRequestReply::DaemonConnected(connected) => Ok(connected),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Machine IDs of the connected daemons.
+    pub async fn connected_machines(&mut self) -> Result<BTreeSet<String>, ClientError> {
+        match self.request(&ControlRequest::ConnectedMachines).await? {
+            ControlRequestReply::ConnectedMachines(machines) => Ok(machines),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Status of all connected daemons, by machine ID.
+    pub async fn daemon_status(&mut self) -> Result<BTreeMap<String, MachineStatus>, ClientError> {
+        match self.request(&ControlRequest::DaemonStatus).await? {
+            ControlRequestReply::DaemonStatus(status) => Ok(status),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Replaces the log filter of the daemon on the given machine.
+    ///
+    /// Returns the previous filter.
+    pub async fn set_log_level(
+        &mut self,
+        machine_id: String,
+        filter: String,
+    ) -> Result<String, ClientError> {
+        let request = ControlRequest::SetLogLevel { machine_id, filter };
+        match self.request(&request).await? {
+            ControlRequestReply::LogLevelSet { previous } => Ok(previous),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Snapshot of the internal state of the daemon on the given machine.
+    ///
+    /// With `gc`, the drop tokens that are pending for longer than the given
+    /// duration are released first.
+    pub async fn diagnostics(
+        &mut self,
+        machine_id: String,
+        gc: Option<Duration>,
+    ) -> Result<DaemonDiagnostics, ClientError> {
+        let request = ControlRequest::Diagnostics { machine_id, gc };
+        match self.request(&request).await? {
+            ControlRequestReply::Diagnostics(diagnostics) => Ok(diagnostics),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stops all dataflows and shuts down the coordinator and its daemons.
+    pub async fn destroy(&mut self) -> Result<(), ClientError> {
+        match self.request(&ControlRequest::Destroy).await? {
+            ControlRequestReply::DestroyOk | ControlRequestReply::CoordinatorStopped => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Subscribes to the log messages of the given dataflow.
+    ///
+    /// The stream uses a separate connection and ends when the dataflow
+    /// finishes.
+    pub async fn subscribe_logs(
+        &self,
+        dataflow_id: Uuid,
+        level: log::LevelFilter,
+    ) -> Result<impl Stream<Item = Result<LogMessage, ClientError>>, ClientError> {
+        let mut connection = self.open_connection().await?;
+        send(
+            &mut connection,
+            &ControlRequest::LogSubscribe { dataflow_id, level },
+        )
+        .await?;
+        Ok(messages(connection))
+    }
+
+    /// Subscribes to the lifecycle events of all dataflows and daemons.
+    ///
+    /// The stream uses a separate connection and ends when the coordinator
+    /// stops.
+    pub async fn subscribe_events(
+        &self,
+    ) -> Result<impl Stream<Item = Result<CoordinatorEvent, ClientError>>, ClientError> {
+        let mut connection = self.open_connection().await?;
+        send(&mut connection, &ControlRequest::EventSubscribe).await?;
+        Ok(messages(connection))
+    }
+
+    /// Receives copies of the messages of the given node output for the
+    /// given duration, at most `max_rate` messages per second.
+    ///
+    /// The stream uses a separate connection and ends when the tap expires.
+    pub async fn tap(
+        &self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output_id: DataId,
+        duration: Duration,
+        max_rate: Option<f64>,
+    ) -> Result<impl Stream<Item = Result<TappedMessage, ClientError>>, ClientError> {
+        let mut connection = self.open_connection().await?;
+        let request = ControlRequest::Tap {
+            dataflow_id,
+            node_id,
+            output_id,
+            duration,
+            max_rate,
+        };
+        send(&mut connection, &request).await?;
+        match parse_reply(&receive(&mut connection).await?)? {
+            ControlRequestReply::TapStarted => Ok(messages(connection)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Opens another connection to the coordinator, authenticated with the
+    /// token of this connection.
+    async fn open_connection(&self) -> Result<TcpStream, ClientError> {
+        let mut connection = TcpStream::connect(self.addr).await?;
+        if let Some(token) = &self.token {
+            let request = ControlRequest::Authenticate {
+                token: token.clone(),
+            };
+            send(&mut connection, &request).await?;
+            match parse_reply(&receive(&mut connection).await?)? {
+                ControlRequestReply::Authenticated { .. } => {}
+                other => return Err(unexpected(other)),
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Sends the given request and waits for the reply.
+    ///
+    /// Error replies are returned as [`ClientError::Coordinator`], rejected
+    /// requests as [`ClientError::AccessDenied`].
+    async fn request(
+        &mut self,
+        request: &ControlRequest,
+    ) -> Result<ControlRequestReply, ClientError> {
+        send(&mut self.connection, request).await?;
+        let reply = receive(&mut self.connection).await?;
+        parse_reply(&reply)
+    }
+
+    /// Like [`Self::request`], but reports errors for unknown dataflows as
+    /// [`ClientError::NotFound`].
+    async fn dataflow_request(
+        &mut self,
+        dataflow_id: Uuid,
+        request: &ControlRequest,
+    ) -> Result<ControlRequestReply, ClientError> {
+        match self.request(request).await {
+            Err(ClientError::Coordinator(err)) => match self.status(dataflow_id).await {
+                Err(not_found @ ClientError::NotFound(_)) => Err(not_found),
+                _ => Err(ClientError::Coordinator(err)),
+            },
+            other => other,
+        }
+    }
+
+    /// Like [`Self::request`], but reports errors for unknown registry
+    /// entries as [`ClientError::NotFound`].
+    async fn registry_request(
+        &mut self,
+        dataflow: &RegistryKey,
+        request: &ControlRequest,
+    ) -> Result<ControlRequestReply, ClientError> {
+        match self.request(request).await {
+            Err(ClientError::Coordinator(err)) => {
+                let registered = self.list_registered().await?.into_iter().any(|entry| {
+                    entry.name == dataflow.name
+                        && dataflow.version.map_or(true, |v| v == entry.version)
+                });
+                if registered {
+                    Err(ClientError::Coordinator(err))
+                } else {
+                    Err(ClientError::NotFound(format!(
+                        "dataflow `{dataflow}` is not registered"
+                    )))
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+async fn send(connection: &mut TcpStream, request: &ControlRequest) -> Result<(), ClientError> {
+    let message = serde_json::to_vec(request)
+        .map_err(|err| ClientError::Validation(format!("failed to serialize request: {err}")))?;
+    let len_raw = (message.len() as u64).to_le_bytes();
+    connection.write_all(&len_raw).await?;
+    connection.write_all(&message).await?;
+    connection.flush().await?;
+    Ok(())
+}
+
+async fn receive(connection: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let reply_len = {
+        let mut raw = [0; 8];
+        connection.read_exact(&mut raw).await?;
+        u64::from_le_bytes(raw) as usize
+    };
+    let mut reply = vec![0; reply_len];
+    connection.read_exact(&mut reply).await?;
+    Ok(reply)
+}
+
+/// Parses the reply, returning error replies as [`ClientError`]s.
+fn parse_reply(raw: &[u8]) -> Result<ControlRequestReply, ClientError> {
+    let reply = serde_json::from_slice(raw)
+        .map_err(|err| ClientError::UnexpectedReply(format!("failed to parse reply: {err}")))?;
+    match reply {
+        ControlRequestReply::Error(err) => Err(ClientError::Coordinator(err)),
+        ControlRequestReply::AccessDenied(err) => Err(ClientError::AccessDenied(err)),
+        reply => Ok(reply),
+    }
+}
+
+/// Parses the messages that the coordinator sends on the given connection,
+/// until it closes the connection.
+fn messages<T: serde::de::DeserializeOwned>(
+    connection: TcpStream,
+) -> impl Stream<Item = Result<T, ClientError>> {
+    stream::unfold(Some(connection), |connection| async move {
+        let mut connection = connection?;
+        let raw = receive(&mut connection).await.ok()?;
+        match serde_json::from_slice(&raw) {
+            Ok(message) => Some((Ok(message), Some(connection))),
+            // the coordinator rejects subscriptions with an error reply, e.g.
+            // `AccessDenied`, and sends nothing else
+            Err(err) => match parse_reply(&raw) {
+                Err(rejected) => Some((Err(rejected), None)),
+                Ok(_) => Some((
+                    Err(ClientError::UnexpectedReply(format!(
+                        "failed to parse message: {err}"
+                    ))),
+                    Some(connection),
+                )),
+            },
+        }
+    })
+}
+
+fn unexpected(reply: ControlRequestReply) -> ClientError {
+    ClientError::UnexpectedReply(format!("{reply:?}"))
+}
+
+fn not_found(dataflow_id: Uuid) -> ClientError {
+    ClientError::NotFound(format!("no dataflow with UUID `{dataflow_id}`"))
+}
+
+/// Keeps the last `tail` lines of the given logs.
+fn last_lines(mut logs: Vec<u8>, tail: Option<usize>) -> Vec<u8> {
+    let Some(tail) = tail else {
+        return logs;
+    };
+    if tail == 0 {
+        return Vec::new();
+    }
+    let content = logs.strip_suffix(b"\n").unwrap_or(&logs);
+    let start = content
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, &b)| b == b'\n')
+        .nth(tail - 1)
+        .map(|(i, _)| i + 1);
+    if let Some(start) = start {
+        logs.drain(..start);
+    }
+    logs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_message::coordinator_to_cli::{DataflowIdAndName, DataflowStatus};
+    use tokio::net::TcpListener;
+
+    /// Answers the requests on the first connection with the given function.
+    async fn fake_coordinator(
+        handler: impl Fn(ControlRequest) -> ControlRequestReply + Send + 'static,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut connection, _) = listener.accept().await.unwrap();
+            while let Ok(raw) = receive(&mut connection).await {
+                let reply = handler(serde_json::from_slice(&raw).unwrap());
+                let reply = serde_json::to_vec(&reply).unwrap();
+                connection
+                    .write_all(&(reply.len() as u64).to_le_bytes())
+                    .await
+                    .unwrap();
+                connection.write_all(&reply).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn running(uuid: Uuid, name: &str) -> DataflowListEntry {
+        DataflowListEntry {
+            id: DataflowIdAndName {
+                uuid,
+                name: Some(name.to_owned()),
+                instance: None,
+            },
+            status: DataflowStatus::Running,
+            assignments: BTreeMap::new(),
+            params: BTreeMap::new(),
+            stalled_on: BTreeSet::new(),
+            origin: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_for_unknown_dataflows_are_typed() {
+        let known = Uuid::new_v4();
+        let addr = fake_coordinator(move |request| match request {
+            ControlRequest::List => {
+                ControlRequestReply::DataflowList(DataflowList(vec![running(known, "camera")]))
+            }
+            ControlRequest::Stop { dataflow_uuid, .. } if dataflow_uuid == known => {
+                ControlRequestReply::Error("failed to send stop message to daemon".into())
+            }
+            ControlRequest::Descriptor { dataflow_uuid } if dataflow_uuid == known => {
+                ControlRequestReply::Descriptor("nodes: []\n".into())
+            }
+            _ => ControlRequestReply::Error("no known running dataflow".into()),
+        })
+        .await;
+        let mut client = CoordinatorClient::connect(addr).await.unwrap();
+
+        assert_eq!(client.resolve("camera").await.unwrap(), known);
+        assert!(matches!(
+            client.resolve("lidar").await,
+            Err(ClientError::NotFound(_))
+        ));
+        assert!(matches!(
+            client.status(Uuid::new_v4()).await,
+            Err(ClientError::NotFound(_))
+        ));
+        assert!(matches!(
+            client.stop(Uuid::new_v4(), None).await,
+            Err(ClientError::NotFound(_))
+        ));
+        assert!(matches!(
+            client.stop(known, None).await,
+            Err(ClientError::Coordinator(_))
+        ));
+        assert_eq!(client.descriptor(known).await.unwrap(), "nodes: []\n");
+        assert!(matches!(
+            client.descriptor(Uuid::new_v4()).await,
+            Err(ClientError::NotFound(_))
+        ));
+        assert!(matches!(
+            client.daemon_connected().await,
+            Err(ClientError::Coordinator(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn access_errors_are_typed() {
+        let addr = fake_coordinator(|request| match request {
+            ControlRequest::Authenticate { token } if token == "ops" => {
+                ControlRequestReply::Authenticated {
+                    role: Role::Operator,
+                }
+            }
+            ControlRequest::Authenticate { .. } => {
+                ControlRequestReply::AccessDenied(AccessError::InvalidToken)
+            }
+            ControlRequest::Destroy => ControlRequestReply::AccessDenied(AccessError::Forbidden {
+                role: Role::Operator,
+                required: Role::Admin,
+            }),
+            _ => ControlRequestReply::DaemonConnected(true),
+        })
+        .await;
+        let mut client = CoordinatorClient::connect(addr).await.unwrap();
+
+        assert!(matches!(
+            client.authenticate("admin".into()).await,
+            Err(ClientError::AccessDenied(AccessError::InvalidToken))
+        ));
+        assert_eq!(client.token, None);
+        assert_eq!(
+            client.authenticate("ops".into()).await.unwrap(),
+            Role::Operator
+        );
+        assert!(client.daemon_connected().await.unwrap());
+        assert!(matches!(
+            client.destroy().await,
+            Err(ClientError::AccessDenied(AccessError::Forbidden {
+                required: Role::Admin,
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connection_errors_are_typed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(matches!(
+            CoordinatorClient::connect(addr).await,
+            Err(ClientError::Connection(_))
+        ));
+    }
+
+    #[test]
+    fn logs_are_tailed() {
+        let logs = b"a\nb\nc\n".to_vec();
+        assert_eq!(last_lines(logs.clone(), None), b"a\nb\nc\n");
+        assert_eq!(last_lines(logs.clone(), Some(2)), b"b\nc\n");
+        assert_eq!(last_lines(logs.clone(), Some(5)), b"a\nb\nc\n");
+        assert_eq!(last_lines(logs, Some(0)), b"");
+        assert_eq!(last_lines(b"a\nb".to_vec(), Some(1)), b"b");
+    }
+}
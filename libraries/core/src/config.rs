@@ -6,6 +6,7 @@ use std::{
     collections::{BTreeMap, BTreeSet},
     convert::Infallible,
     fmt,
+    num::{NonZeroU64, NonZeroUsize},
     str::FromStr,
     time::Duration,
 };
@@ -127,9 +128,23 @@ impl Borrow<str> for DataId {
     }
 }
 
+/// Output of the `dora` pseudo node on which the daemon publishes records of
+/// dropped messages, see [`InputMapping::DropEvents`].
+pub const DROP_EVENTS_OUTPUT: &str = "drop_events";
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
 pub enum InputMapping {
-    Timer { interval: Duration },
+    Timer {
+        interval: Duration,
+    },
+    /// Messages sent by external processes to the `external_inputs` endpoint
+    /// with the given name.
+    External {
+        name: String,
+    },
+    /// Records of the messages that the daemon dropped, see the
+    /// `drop_events` field of the dataflow descriptor.
+    DropEvents,
     User(UserInputMapping),
 }
 
@@ -139,7 +154,9 @@ impl InputMapping {
 
         match self {
             InputMapping::User(mapping) => &mapping.source,
-            InputMapping::Timer { .. } => DORA_NODE_ID.get_or_init(|| NodeId("dora".to_string())),
+            InputMapping::Timer { .. }
+            | InputMapping::External { .. }
+            | InputMapping::DropEvents => DORA_NODE_ID.get_or_init(|| NodeId("dora".to_string())),
         }
     }
 }
@@ -151,6 +168,8 @@ impl fmt::Display for InputMapping {
                 let duration = format_duration(*interval);
                 write!(f, "dora/timer/{duration}")
             }
+            InputMapping::External { name } => write!(f, "dora/external/{name}"),
+            InputMapping::DropEvents => write!(f, "dora/{DROP_EVENTS_OUTPUT}"),
             InputMapping::User(mapping) => {
                 write!(f, "{}/{}", mapping.source, mapping.output)
             }
@@ -173,49 +192,31 @@ impl<'de> Deserialize<'de> for InputMapping {
         D: serde::Deserializer<'de>,
     {
         let string = String::deserialize(deserializer)?;
+        string.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for InputMapping {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
         let (source, output) = string
             .split_once('/')
-            .ok_or_else(|| serde::de::Error::custom("input must start with `<source>/`"))?;
+            .ok_or_else(|| "input must start with `<source>/`".to_owned())?;
 
-        let deserialized = match source {
+        let parsed = match source {
             "dora" => match output.split_once('/') {
                 Some(("timer", output)) => {
-                    let (unit, value) = output.split_once('/').ok_or_else(|| {
-                        serde::de::Error::custom(
-                            "timer input must specify unit and value (e.g. `secs/5` or `millis/100`)",
-                        )
-                    })?;
-                    let interval = match unit {
-                        "secs" => {
-                            let value = value.parse().map_err(|_| {
-                                serde::de::Error::custom(format!(
-                                    "secs must be an integer (got `{value}`)"
-                                ))
-                            })?;
-                            Duration::from_secs(value)
-                        }
-                        "millis" => {
-                            let value = value.parse().map_err(|_| {
-                                serde::de::Error::custom(format!(
-                                    "millis must be an integer (got `{value}`)"
-                                ))
-                            })?;
-                            Duration::from_millis(value)
-                        }
-                        other => {
-                            return Err(serde::de::Error::custom(format!(
-                                "timer unit must be either secs or millis (got `{other}`"
-                            )))
-                        }
-                    };
+                    let interval = parse_timer_interval(output)
+                        .map_err(|err| format!("invalid timer input `{string}`: {err}"))?;
                     Self::Timer { interval }
                 }
-                Some((other, _)) => {
-                    return Err(serde::de::Error::custom(format!(
-                        "unknown dora input `{other}`"
-                    )))
-                }
-                None => return Err(serde::de::Error::custom("dora input has invalid format")),
+                Some(("external", name)) => Self::External {
+                    name: name.to_owned(),
+                },
+                Some((other, _)) => return Err(format!("unknown dora input `{other}`")),
+                None if output == DROP_EVENTS_OUTPUT => Self::DropEvents,
+                None => return Err("dora input has invalid format".to_owned()),
             },
             _ => Self::User(UserInputMapping {
                 source: source.to_owned().into(),
@@ -223,7 +224,7 @@ impl<'de> Deserialize<'de> for InputMapping {
             }),
         };
 
-        Ok(deserialized)
+        Ok(parsed)
     }
 }
 
@@ -233,14 +234,145 @@ pub struct UserInputMapping {
     pub output: DataId,
 }
 
+/// Matches any node or output ID when used in an input mapping, e.g. `camera/*` or `*/*`.
+pub const INPUT_WILDCARD: &str = "*";
+
+impl UserInputMapping {
+    /// Whether the source or output of this mapping is a wildcard.
+    pub fn is_wildcard(&self) -> bool {
+        self.source.as_ref() == INPUT_WILDCARD || self.output.as_str() == INPUT_WILDCARD
+    }
+
+    /// Checks whether the given output matches this mapping, taking wildcards into account.
+    pub fn matches(&self, source: &NodeId, output: &DataId) -> bool {
+        (self.source.as_ref() == INPUT_WILDCARD || &self.source == source)
+            && (self.output.as_str() == INPUT_WILDCARD || &self.output == output)
+    }
+}
+
+/// Shortest timer interval that is accepted in dataflow descriptors.
+pub const MIN_TIMER_INTERVAL: Duration = Duration::from_micros(100);
+
+const TIMER_INTERVAL_FORMS: &str = "expected a number with unit (e.g. `dora/timer/10ms`, \
+    `dora/timer/2.5s`, `dora/timer/1min`, or `dora/timer/500us`) or the `secs/<N>` \
+    and `millis/<N>` forms";
+
+/// Parses the interval of a `dora/timer/<interval>` input.
+///
+/// Accepts a decimal number followed by one of the units `ns`, `us` (or `µs`),
+/// `ms`, `s`, and `min`, as well as the `secs/<N>` and `millis/<N>` forms. Numbers
+/// without unit are rejected to avoid silently wrong timer rates. Intervals
+/// below [`MIN_TIMER_INTERVAL`] are rejected too.
+///
+/// The parsed interval is exact, i.e. formatting it through [`format_duration`]
+/// and parsing it again yields the same value:
+///
+/// ```
+/// use dora_core::config::{format_duration, parse_timer_interval};
+/// use std::time::Duration;
+///
+/// let interval = parse_timer_interval("2.5s").unwrap();
+/// assert_eq!(interval, Duration::from_millis(2500));
+/// let formatted = format_duration(interval).to_string();
+/// assert_eq!(parse_timer_interval(&formatted).unwrap(), interval);
+///
+/// assert!(parse_timer_interval("100").is_err());
+/// assert!(parse_timer_interval("0ms").is_err());
+/// assert!(parse_timer_interval("50us").is_err());
+/// ```
+pub fn parse_timer_interval(value: &str) -> eyre::Result<Duration> {
+    let interval = match value.split_once('/') {
+        Some((unit, number)) => {
+            let number: u64 = number
+                .parse()
+                .map_err(|_| eyre::eyre!("{unit} must be an integer (got `{number}`)"))?;
+            match unit {
+                "secs" => Duration::from_secs(number),
+                "millis" => Duration::from_millis(number),
+                other => eyre::bail!(
+                    "timer unit must be either secs or millis (got `{other}`); \
+                    {TIMER_INTERVAL_FORMS}"
+                ),
+            }
+        }
+        None => parse_duration_with_unit(value)?,
+    };
+    if interval.is_zero() {
+        eyre::bail!("timer interval must not be zero");
+    }
+    if interval < MIN_TIMER_INTERVAL {
+        eyre::bail!(
+            "timer interval `{value}` is shorter than the minimum of {}us",
+            MIN_TIMER_INTERVAL.as_micros()
+        );
+    }
+    Ok(interval)
+}
+
+fn parse_duration_with_unit(value: &str) -> eyre::Result<Duration> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(|| {
+            eyre::eyre!("timer interval `{value}` has no unit; {TIMER_INTERVAL_FORMS}")
+        })?;
+    let (number, unit) = value.split_at(split);
+    let unit_nanos: u128 = match unit {
+        "ns" => 1,
+        "us" | "µs" => 1_000,
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "min" => 60_000_000_000,
+        other => eyre::bail!("unknown unit `{other}` in timer interval; {TIMER_INTERVAL_FORMS}"),
+    };
+
+    // compute the value in integer nanoseconds to keep it exact
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if integer.is_empty() || fraction.contains('.') {
+        eyre::bail!("invalid number `{number}` in timer interval; {TIMER_INTERVAL_FORMS}");
+    }
+    let too_long = || eyre::eyre!("timer interval `{value}` is too long");
+    let mut nanos = integer
+        .parse::<u128>()
+        .map_err(|_| too_long())?
+        .checked_mul(unit_nanos)
+        .ok_or_else(too_long)?;
+    if !fraction.is_empty() {
+        let fraction_digits = u32::try_from(fraction.len()).map_err(|_| too_long())?;
+        let scale = 10u128
+            .checked_pow(fraction_digits)
+            .ok_or_else(|| eyre::eyre!("timer interval `{value}` has too many decimal places"))?;
+        let fraction_nanos = fraction
+            .parse::<u128>()
+            .ok()
+            .and_then(|f| f.checked_mul(unit_nanos))
+            .ok_or_else(|| eyre::eyre!("timer interval `{value}` has too many decimal places"))?;
+        if fraction_nanos % scale != 0 {
+            eyre::bail!("timer interval `{value}` is more precise than a nanosecond");
+        }
+        nanos = nanos
+            .checked_add(fraction_nanos / scale)
+            .ok_or_else(too_long)?;
+    }
+
+    let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| too_long())?;
+    Ok(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+/// Formats a timer interval so that [`parse_timer_interval`] yields the exact same value.
 pub struct FormattedDuration(pub Duration);
 
 impl fmt::Display for FormattedDuration {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.0.subsec_millis() == 0 {
-            write!(f, "secs/{}", self.0.as_secs())
+        let interval = self.0;
+        let nanos = interval.subsec_nanos();
+        if nanos == 0 {
+            write!(f, "secs/{}", interval.as_secs())
+        } else if interval.subsec_millis() * 1_000_000 == nanos {
+            write!(f, "millis/{}", interval.as_millis())
+        } else if interval.subsec_micros() * 1_000 == nanos {
+            write!(f, "{}us", interval.as_micros())
         } else {
-            write!(f, "millis/{}", self.0.as_millis())
+            write!(f, "{}ns", interval.as_nanos())
         }
     }
 }
@@ -259,6 +391,64 @@ pub struct NodeRunConfig {
     ///
     ///   example_input: example_node/example_output1
     ///
+    /// The source node and/or the output can be set to `*` to subscribe to
+    /// all matching outputs, e.g. `all: camera/*` or `all: "*/*"`. Such
+    /// wildcard inputs are expanded into one input per matched output when
+    /// the dataflow is spawned, using input IDs of the form
+    /// `<input>/<source>/<output>` (e.g. `all/camera/image`). Each expanded
+    /// input gets its own queue of the configured `queue_size`. Outputs of
+    /// the node itself are never matched.
+    ///
+    /// An input can also receive messages from multiple sources by
+    /// specifying a list, e.g. `command: [joystick/cmd, planner/cmd]`. Such
+    /// inputs are only closed once all of their sources are closed. The
+    /// source of each message is reported in the metadata parameters.
+    /// Wildcards in the list are replaced by all matching outputs, e.g.
+    /// `sensors: [camera/*, lidar/scan]`, which all feed the same input.
+    ///
+    /// Messages can be filtered before they are delivered to an input, e.g.
+    /// to feed a camera stream into a logger at a lower rate:
+    ///
+    /// inputs:
+    ///
+    ///   image:
+    ///
+    ///    source: camera/image
+    ///
+    ///    throttle: { max_rate: 1Hz }
+    ///
+    /// Similarly, `decimate: { keep_every: 10 }` can be used to
+    /// only deliver every 10th message. Filters only apply to the input they
+    /// are defined on, so other receivers of the same output still get all
+    /// messages.
+    ///
+    /// To keep a slow receiver live instead of dropping from its full queue,
+    /// `adaptive: { min_rate: 5Hz }` lets the daemon downsample the input
+    /// while its queue overflows, but never below the given rate. The input
+    /// returns to the full rate once the receiver keeps up again.
+    ///
+    /// For inputs that only need the most recent value (e.g. pose updates),
+    /// `latest: true` can be set. Pending messages are then replaced by
+    /// newer messages instead of being queued.
+    ///
+    /// If a node receives inputs at very different rates, important inputs
+    /// can be given a higher `priority` (default `0`). Pending messages of
+    /// inputs with a higher priority are delivered before pending messages
+    /// of other inputs, so that e.g. a `command` input is not delayed by a
+    /// burst of `lidar` messages.
+    ///
+    /// Nodes that need to react when an input falls silent (e.g. to stop a
+    /// robot when no more commands arrive) can set a `timeout: 500ms`. The
+    /// node then receives an `InputTimeout` event whenever no message
+    /// arrived on the input for this long, repeated at the same cadence
+    /// until the next message arrives.
+    ///
+    /// High-rate inputs (e.g. IMU samples) can be delivered in batches to
+    /// reduce the per-message overhead, e.g. `batch: { max: 64, max_delay: 2ms }`.
+    /// The daemon then collects up to `max` messages and sends them to the
+    /// node together, at the latest `max_delay` after the first message of
+    /// the batch arrived. The node still receives the messages one by one.
+    ///
     #[serde(default)]
     pub inputs: BTreeMap<DataId, Input>,
     /// List of output IDs.
@@ -274,56 +464,432 @@ pub struct NodeRunConfig {
     pub outputs: BTreeSet<DataId>,
 }
 
+/// An input of a node or operator.
+///
+/// New options are added over time, so inputs can't be constructed as struct
+/// literals outside of this crate. Use [`Input::new`] instead.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
-#[serde(deny_unknown_fields, from = "InputDef", into = "InputDef")]
+#[serde(deny_unknown_fields, try_from = "InputDef", into = "InputDef")]
+#[non_exhaustive]
 pub struct Input {
     pub mapping: InputMapping,
+    /// Further sources that are merged into this input (fan-in).
+    ///
+    /// Set when a list of sources is given for the input, e.g.
+    /// `command: [joystick/cmd, planner/cmd]`. The first entry of the list is
+    /// stored in `mapping`. Use [`Input::mappings`] to access all sources.
+    #[serde(default)]
+    pub(crate) additional_mappings: Vec<InputMapping>,
     pub queue_size: Option<usize>,
+    /// Limits the rate at which messages are delivered to this input.
+    #[serde(default)]
+    pub throttle: Option<Throttle>,
+    /// Only delivers every n-th message to this input.
+    #[serde(default)]
+    pub decimate: Option<Decimate>,
+    /// Downsamples this input while the receiver can't keep up.
+    #[serde(default)]
+    pub adaptive: Option<Adaptive>,
+    /// Only delivers the newest message if multiple messages are pending.
+    ///
+    /// Older messages are dropped as soon as a newer message arrives,
+    /// independent of the `queue_size`.
+    #[serde(default)]
+    pub latest: bool,
+    /// Delivery priority of this input, higher values are delivered first.
+    ///
+    /// When multiple messages are pending for a node, messages of inputs
+    /// with a higher priority are delivered before messages of inputs with a
+    /// lower priority. Messages of inputs with equal priority keep their
+    /// arrival order.
+    #[serde(default)]
+    pub priority: u8,
+    /// Maximum time without messages on this input before the node is sent
+    /// an `InputTimeout` event.
+    ///
+    /// The timeout starts when the node subscribes to its events and is
+    /// reset by every delivered message. While the input stays silent, the
+    /// event is repeated after every further `timeout`. Closed inputs don't
+    /// time out. Not supported for timer inputs.
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub timeout: Option<Duration>,
+    /// Collects messages of this input and delivers them together.
+    #[serde(default)]
+    pub batch: Option<Batch>,
+}
+
+impl Input {
+    /// Creates an input with a single source and default options.
+    pub fn new(mapping: InputMapping) -> Self {
+        Self {
+            mapping,
+            additional_mappings: Vec::new(),
+            queue_size: None,
+            throttle: None,
+            decimate: None,
+            adaptive: None,
+            latest: false,
+            priority: 0,
+            timeout: None,
+            batch: None,
+        }
+    }
+
+    /// All sources of this input, starting with the primary `mapping`.
+    pub fn mappings(&self) -> impl Iterator<Item = &InputMapping> {
+        std::iter::once(&self.mapping).chain(&self.additional_mappings)
+    }
+
+    pub fn mappings_mut(&mut self) -> impl Iterator<Item = &mut InputMapping> {
+        std::iter::once(&mut self.mapping).chain(&mut self.additional_mappings)
+    }
+
+    /// Whether this input has more than one source.
+    pub fn is_fan_in(&self) -> bool {
+        !self.additional_mappings.is_empty()
+    }
+
+    /// Maximum number of pending messages of this input.
+    ///
+    /// Defaults to 10 messages, or to one full `batch` if that is larger.
+    pub fn queue_size_or_default(&self) -> usize {
+        self.queue_size.unwrap_or_else(|| {
+            let batch_size = self.batch.as_ref().map_or(0, |batch| batch.max.get());
+            batch_size.max(10)
+        })
+    }
+
+    /// Whether messages might be filtered out before they are delivered to
+    /// this input.
+    pub fn is_filtered(&self) -> bool {
+        self.throttle.is_some() || self.decimate.is_some()
+    }
+}
+
+/// Drops messages that arrive faster than the given rate, e.g.
+/// `throttle: { max_rate: 1Hz }`.
+///
+/// The rate is measured using the timestamps of the messages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Throttle {
+    #[schemars(with = "String")]
+    pub max_rate: Rate,
+}
+
+/// Only keeps every n-th message, e.g. `decimate: { keep_every: 10 }`.
+///
+/// The first message is always kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Decimate {
+    pub keep_every: NonZeroU64,
+}
+
+/// Downsamples an input under sustained queue overflows, e.g.
+/// `adaptive: { min_rate: 5Hz }`.
+///
+/// The daemon switches to delivering only every n-th message when messages
+/// are dropped from the full input queue for a while, and adjusts `n` to
+/// the rate at which the receiver takes messages from its queue. It never
+/// reduces the input below `min_rate` and returns to the full rate step by
+/// step once no more messages are dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Adaptive {
+    #[schemars(with = "String")]
+    pub min_rate: Rate,
+}
+
+/// Delivers the messages of an input in batches, e.g.
+/// `batch: { max: 64, max_delay: 2ms }`.
+///
+/// A batch is delivered once it contains `max` messages, or once its first
+/// message waited for `max_delay`. This adds up to `max_delay` of latency,
+/// but the receiver needs fewer wakeups at high message rates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    pub max: NonZeroUsize,
+    #[serde(with = "crate::descriptor::duration_with_unit")]
+    #[schemars(with = "String")]
+    pub max_delay: Duration,
+}
+
+/// A frequency in Hertz, e.g. `30Hz` or `0.5 Hz`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate(f64);
+
+impl Rate {
+    pub fn hertz(&self) -> f64 {
+        self.0
+    }
+
+    /// The minimal time between two messages at this rate.
+    pub fn interval(&self) -> Duration {
+        // checked when the rate is created
+        Duration::try_from_secs_f64(1.0 / self.0).unwrap_or(Duration::MAX)
+    }
+}
+
+// rates are always finite and positive, with a representable interval
+impl Eq for Rate {}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let number = trimmed
+            .strip_suffix("Hz")
+            .or_else(|| trimmed.strip_suffix("hz"))
+            .unwrap_or(trimmed);
+        let hertz: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid rate `{s}` (expected e.g. `10Hz`)"))?;
+        Self::try_from(hertz)
+    }
+}
+
+impl TryFrom<f64> for Rate {
+    type Error = String;
+
+    fn try_from(hertz: f64) -> Result<Self, Self::Error> {
+        if !(hertz.is_finite() && hertz > 0.0) {
+            return Err(format!("rate must be positive (got {hertz})"));
+        }
+        if Duration::try_from_secs_f64(1.0 / hertz).is_err() {
+            return Err(format!("rate {hertz}Hz is too low"));
+        }
+        Ok(Self(hertz))
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}Hz", self.0)
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RateDef {
+            Hertz(f64),
+            String(String),
+        }
+        match RateDef::deserialize(deserializer)? {
+            RateDef::Hertz(hertz) => Rate::try_from(hertz),
+            RateDef::String(s) => s.parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum InputDef {
-    MappingOnly(InputMapping),
+    MappingOnly(InputMappingDef),
+    MultipleMappings(Vec<InputMappingDef>),
     WithOptions {
-        source: InputMapping,
+        source: InputSourceDef,
         queue_size: Option<usize>,
+        #[serde(default)]
+        throttle: Option<Throttle>,
+        #[serde(default)]
+        decimate: Option<Decimate>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        adaptive: Option<Adaptive>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        latest: bool,
+        #[serde(default, skip_serializing_if = "is_default_priority")]
+        priority: u8,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "crate::descriptor::timeout_with_unit"
+        )]
+        timeout: Option<Duration>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        batch: Option<Batch>,
     },
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InputSourceDef {
+    Single(InputMappingDef),
+    Multiple(Vec<InputMappingDef>),
+}
+
+/// An input mapping as written in the dataflow descriptor.
+///
+/// The mapping is only parsed when converting to [`Input`]. Otherwise, the
+/// untagged [`InputDef`] enum would replace parse errors (e.g. an invalid timer
+/// interval) with a generic "did not match any variant" error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputMappingDef(String);
+
+impl From<InputMapping> for InputMappingDef {
+    fn from(mapping: InputMapping) -> Self {
+        Self(mapping.to_string())
+    }
+}
+
+impl TryFrom<InputMappingDef> for InputMapping {
+    type Error = String;
+
+    fn try_from(value: InputMappingDef) -> Result<Self, Self::Error> {
+        value.0.parse()
+    }
+}
+
 impl From<Input> for InputDef {
     fn from(input: Input) -> Self {
-        match input {
-            Input {
-                mapping,
-                queue_size: None,
-            } => Self::MappingOnly(mapping),
-            Input {
-                mapping,
+        let Input {
+            mapping,
+            additional_mappings,
+            queue_size,
+            throttle,
+            decimate,
+            adaptive,
+            latest,
+            priority,
+            timeout,
+            batch,
+        } = input;
+        let source = if additional_mappings.is_empty() {
+            InputSourceDef::Single(mapping.into())
+        } else {
+            InputSourceDef::Multiple(
+                std::iter::once(mapping)
+                    .chain(additional_mappings)
+                    .map(Into::into)
+                    .collect(),
+            )
+        };
+        match (
+            source, queue_size, throttle, decimate, adaptive, latest, priority, timeout, batch,
+        ) {
+            (InputSourceDef::Single(mapping), None, None, None, None, false, 0, None, None) => {
+                Self::MappingOnly(mapping)
+            }
+            (InputSourceDef::Multiple(mappings), None, None, None, None, false, 0, None, None) => {
+                Self::MultipleMappings(mappings)
+            }
+            (
+                source,
                 queue_size,
-            } => Self::WithOptions {
-                source: mapping,
+                throttle,
+                decimate,
+                adaptive,
+                latest,
+                priority,
+                timeout,
+                batch,
+            ) => Self::WithOptions {
+                source,
                 queue_size,
+                throttle,
+                decimate,
+                adaptive,
+                latest,
+                priority,
+                timeout,
+                batch,
             },
         }
     }
 }
 
-impl From<InputDef> for Input {
-    fn from(value: InputDef) -> Self {
-        match value {
-            InputDef::MappingOnly(mapping) => Self {
-                mapping,
-                queue_size: None,
-            },
-            InputDef::WithOptions { source, queue_size } => Self {
-                mapping: source,
-                queue_size,
-            },
-        }
+impl TryFrom<InputDef> for Input {
+    type Error = String;
+
+    fn try_from(value: InputDef) -> Result<Self, Self::Error> {
+        let (source, queue_size, throttle, decimate, adaptive, latest, priority, timeout, batch) =
+            match value {
+                InputDef::MappingOnly(mapping) => (
+                    InputSourceDef::Single(mapping),
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                    0,
+                    None,
+                    None,
+                ),
+                InputDef::MultipleMappings(mappings) => (
+                    InputSourceDef::Multiple(mappings),
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                    0,
+                    None,
+                    None,
+                ),
+                InputDef::WithOptions {
+                    source,
+                    queue_size,
+                    throttle,
+                    decimate,
+                    adaptive,
+                    latest,
+                    priority,
+                    timeout,
+                    batch,
+                } => (
+                    source, queue_size, throttle, decimate, adaptive, latest, priority, timeout,
+                    batch,
+                ),
+            };
+        let (mapping, additional_mappings) = match source {
+            InputSourceDef::Single(mapping) => (mapping.try_into()?, Vec::new()),
+            InputSourceDef::Multiple(mappings) => {
+                let mut mappings = mappings.into_iter().map(InputMapping::try_from);
+                let mapping = mappings
+                    .next()
+                    .ok_or_else(|| "input source list must not be empty".to_owned())??;
+                (mapping, mappings.collect::<Result<_, _>>()?)
+            }
+        };
+        Ok(Self {
+            mapping,
+            additional_mappings,
+            queue_size,
+            throttle,
+            decimate,
+            adaptive,
+            latest,
+            priority,
+            timeout,
+            batch,
+        })
     }
 }
 
+fn is_default_priority(priority: &u8) -> bool {
+    *priority == 0
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, JsonSchema, Clone)]
 #[serde(deny_unknown_fields, rename_all = "lowercase")]
 pub struct CommunicationConfig {
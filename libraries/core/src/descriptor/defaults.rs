@@ -0,0 +1,248 @@
+//! Descriptor-level default values that are merged into every node.
+
+use super::EnvValue;
+use crate::config::NodeId;
+use eyre::{bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Default values for all nodes of the dataflow.
+///
+/// Values set on a node take precedence over the defaults. Setting a node
+/// field to `null` clears the default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NodeDefaults {
+    /// Environment variables of all nodes.
+    ///
+    /// They are merged with the `env` of each node, with the node's value
+    /// winning per variable. A variable that is set to `null` in the node's
+    /// `env` is removed. Set the node's `env` to `null` to not inherit any
+    /// default variables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, EnvValue>>,
+    /// Queue size of all inputs that don't set their own `queue_size`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_size: Option<usize>,
+}
+
+impl NodeDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.env.is_none() && self.queue_size.is_none()
+    }
+
+    pub(super) fn check(&self) -> eyre::Result<()> {
+        if self.queue_size == Some(0) {
+            bail!("invalid `queue_size` in `defaults`: inputs need a queue size of at least 1");
+        }
+        if let Some(env) = &self.env {
+            check_env_names(env.keys()).wrap_err("invalid `env` in `defaults`")?;
+        }
+        Ok(())
+    }
+
+    /// Merges the default environment variables with the `env` of the given
+    /// node.
+    pub(super) fn merge_env(
+        &self,
+        node_id: &NodeId,
+        env: &Override<BTreeMap<String, Option<EnvValue>>>,
+    ) -> eyre::Result<Option<BTreeMap<String, EnvValue>>> {
+        let mut merged = match (env, &self.env) {
+            (Override::Clear, _) | (Override::Inherit, None) => return Ok(None),
+            (_, defaults) => defaults.clone().unwrap_or_default(),
+        };
+        if let Override::Set(node_env) = env {
+            check_env_names(node_env.keys())
+                .wrap_err_with(|| format!("invalid `env` of node `{node_id}`"))?;
+            for (key, value) in node_env {
+                match value {
+                    Some(value) => merged.insert(key.clone(), value.clone()),
+                    None => merged.remove(key),
+                };
+            }
+        }
+        Ok(Some(merged))
+    }
+}
+
+fn check_env_names<'a>(names: impl IntoIterator<Item = &'a String>) -> eyre::Result<()> {
+    for name in names {
+        if name.is_empty() || name.contains(['=', '\0']) {
+            bail!("`{name}` is not a valid environment variable name");
+        }
+    }
+    Ok(())
+}
+
+/// Node-level value of a field that has a default in [`NodeDefaults`].
+///
+/// Distinguishes an explicit `null`, which clears the default, from a
+/// missing field, which inherits it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Override<T> {
+    #[default]
+    Inherit,
+    Clear,
+    Set(T),
+}
+
+impl<T> Override<T> {
+    pub fn is_inherit(&self) -> bool {
+        matches!(self, Self::Inherit)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Override<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Option::deserialize(deserializer)? {
+            Some(value) => Self::Set(value),
+            None => Self::Clear,
+        })
+    }
+}
+
+impl<T: Serialize> Serialize for Override<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Set(value) => serializer.serialize_some(value),
+            Self::Inherit | Self::Clear => serializer.serialize_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::DataId,
+        descriptor::{CoreNodeKind, Descriptor, ResolvedNode},
+    };
+
+    fn resolve(yaml: &str) -> eyre::Result<Vec<ResolvedNode>> {
+        Descriptor::parse(yaml.as_bytes().to_vec())?.resolve_aliases_and_set_defaults()
+    }
+
+    fn env_of(node: &ResolvedNode) -> Vec<(String, String)> {
+        node.env
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn env_is_merged_per_variable() {
+        let nodes = resolve(
+            r#"
+            defaults:
+              env:
+                RUST_LOG: info
+                ROBOT: alice
+            nodes:
+              - id: inherit
+                path: inherit
+              - id: override
+                path: override
+                env:
+                  RUST_LOG: debug
+                  EXTRA: 1
+              - id: remove
+                path: remove
+                env:
+                  ROBOT: null
+              - id: clear
+                path: clear
+                env: null
+            "#,
+        )
+        .unwrap();
+        let env = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+
+        assert_eq!(
+            env_of(&nodes[0]),
+            [env("ROBOT", "alice"), env("RUST_LOG", "info")]
+        );
+        assert_eq!(
+            env_of(&nodes[1]),
+            [
+                env("EXTRA", "1"),
+                env("ROBOT", "alice"),
+                env("RUST_LOG", "debug")
+            ]
+        );
+        assert_eq!(env_of(&nodes[2]), [env("RUST_LOG", "info")]);
+        assert!(nodes[3].env.is_none());
+    }
+
+    #[test]
+    fn queue_size_applies_to_inputs_without_own_value() {
+        let nodes = resolve(
+            r#"
+            defaults:
+              queue_size: 1
+            nodes:
+              - id: sink
+                path: sink
+                inputs:
+                  fast: dora/timer/millis/10
+                  slow:
+                    source: dora/timer/secs/1
+                    queue_size: 5
+            "#,
+        )
+        .unwrap();
+        let CoreNodeKind::Custom(sink) = &nodes[0].kind else {
+            panic!("expected custom node")
+        };
+        let inputs = &sink.run_config.inputs;
+        assert_eq!(inputs[&DataId::from("fast".to_owned())].queue_size, Some(1));
+        assert_eq!(inputs[&DataId::from("slow".to_owned())].queue_size, Some(5));
+    }
+
+    #[test]
+    fn errors_name_the_origin_of_the_value() {
+        let err = resolve(
+            r#"
+            defaults:
+              queue_size: 0
+            nodes:
+              - id: node
+                path: node
+            "#,
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("`defaults`"), "{err:#}");
+
+        let err = resolve(
+            r#"
+            defaults:
+              env:
+                A=B: 1
+            nodes:
+              - id: node
+                path: node
+            "#,
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("`defaults`"), "{err:#}");
+
+        let err = resolve(
+            r#"
+            nodes:
+              - id: node
+                path: node
+                env:
+                  A=B: 1
+            "#,
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("node `node`"), "{err:#}");
+    }
+}
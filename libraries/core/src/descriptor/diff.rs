@@ -0,0 +1,469 @@
+//! Structural differences between two versions of a dataflow, e.g. to review
+//! the changes of an edited descriptor before restarting a running dataflow.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DataId, Input, InputMapping, NodeId};
+
+use super::{CoreNodeKind, OperatorSource, ResolvedNode};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataflowDiff {
+    pub added_nodes: BTreeSet<NodeId>,
+    pub removed_nodes: BTreeSet<NodeId>,
+    /// Changes of the nodes that exist in both versions.
+    pub changed_nodes: BTreeMap<NodeId, Vec<NodeChange>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeChange {
+    /// The source of the node, or the sources of its operators for runtime
+    /// nodes.
+    Source {
+        old: String,
+        new: String,
+    },
+    Args {
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// Added, removed, or changed environment variable.
+    Env {
+        key: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    InputAdded {
+        input: DataId,
+        mappings: Vec<InputMapping>,
+    },
+    InputRemoved {
+        input: DataId,
+        mappings: Vec<InputMapping>,
+    },
+    /// The input is mapped to different sources.
+    InputMapping {
+        input: DataId,
+        old: Vec<InputMapping>,
+        new: Vec<InputMapping>,
+    },
+    /// The input is mapped to a `dora/timer` with a different interval.
+    TimerInterval {
+        input: DataId,
+        old: Duration,
+        new: Duration,
+    },
+    /// Delivery options of the input changed, e.g. its `queue_size`.
+    InputOptions {
+        input: DataId,
+    },
+    OutputAdded {
+        output: DataId,
+    },
+    OutputRemoved {
+        output: DataId,
+    },
+}
+
+impl DataflowDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty()
+            && self.removed_nodes.is_empty()
+            && self.changed_nodes.is_empty()
+    }
+}
+
+/// Compares the resolved nodes of two versions of a dataflow.
+///
+/// Nodes are matched by their ID, so a renamed node shows up as a removed and
+/// an added node. Wildcard inputs should be expanded in both versions (see
+/// [`super::expand_wildcard_inputs`]) to compare the actual mappings.
+pub fn diff_nodes(old: &[ResolvedNode], new: &[ResolvedNode]) -> DataflowDiff {
+    let old: BTreeMap<_, _> = old.iter().map(|n| (&n.id, n)).collect();
+    let new: BTreeMap<_, _> = new.iter().map(|n| (&n.id, n)).collect();
+
+    let mut diff = DataflowDiff::default();
+    for (id, old_node) in &old {
+        match new.get(id) {
+            Some(new_node) => {
+                let changes = diff_node(old_node, new_node);
+                if !changes.is_empty() {
+                    diff.changed_nodes.insert((*id).clone(), changes);
+                }
+            }
+            None => {
+                diff.removed_nodes.insert((*id).clone());
+            }
+        }
+    }
+    diff.added_nodes = new
+        .keys()
+        .filter(|id| !old.contains_key(*id))
+        .map(|id| (*id).clone())
+        .collect();
+    diff
+}
+
+fn diff_node(old: &ResolvedNode, new: &ResolvedNode) -> Vec<NodeChange> {
+    let mut changes = Vec::new();
+
+    let (old_source, new_source) = (source(&old.kind), source(&new.kind));
+    if old_source != new_source {
+        changes.push(NodeChange::Source {
+            old: old_source,
+            new: new_source,
+        });
+    }
+    let (old_args, new_args) = (args(&old.kind), args(&new.kind));
+    if old_args != new_args {
+        changes.push(NodeChange::Args {
+            old: old_args,
+            new: new_args,
+        });
+    }
+
+    let (old_env, new_env) = (env(old), env(new));
+    let keys: BTreeSet<_> = old_env.keys().chain(new_env.keys()).collect();
+    for key in keys {
+        let (old, new) = (old_env.get(key), new_env.get(key));
+        if old != new {
+            changes.push(NodeChange::Env {
+                key: key.clone(),
+                old: old.cloned(),
+                new: new.cloned(),
+            });
+        }
+    }
+
+    let (old_config, new_config) = (old.kind.run_config(), new.kind.run_config());
+    for (input_id, old_input) in &old_config.inputs {
+        match new_config.inputs.get(input_id) {
+            Some(new_input) => {
+                if let Some(change) = diff_input(input_id, old_input, new_input) {
+                    changes.push(change);
+                }
+            }
+            None => changes.push(NodeChange::InputRemoved {
+                input: input_id.clone(),
+                mappings: old_input.mappings().cloned().collect(),
+            }),
+        }
+    }
+    for (input_id, new_input) in &new_config.inputs {
+        if !old_config.inputs.contains_key(input_id) {
+            changes.push(NodeChange::InputAdded {
+                input: input_id.clone(),
+                mappings: new_input.mappings().cloned().collect(),
+            });
+        }
+    }
+
+    for output in old_config.outputs.difference(&new_config.outputs) {
+        changes.push(NodeChange::OutputRemoved {
+            output: output.clone(),
+        });
+    }
+    for output in new_config.outputs.difference(&old_config.outputs) {
+        changes.push(NodeChange::OutputAdded {
+            output: output.clone(),
+        });
+    }
+
+    changes
+}
+
+fn diff_input(input_id: &DataId, old: &Input, new: &Input) -> Option<NodeChange> {
+    let old_mappings: Vec<_> = old.mappings().cloned().collect();
+    let new_mappings: Vec<_> = new.mappings().cloned().collect();
+    let change = match (&old_mappings[..], &new_mappings[..]) {
+        (old_mappings, new_mappings) if old_mappings == new_mappings => {
+            if old == new {
+                return None;
+            }
+            NodeChange::InputOptions {
+                input: input_id.clone(),
+            }
+        }
+        ([InputMapping::Timer { interval: old }], [InputMapping::Timer { interval: new }]) => {
+            NodeChange::TimerInterval {
+                input: input_id.clone(),
+                old: *old,
+                new: *new,
+            }
+        }
+        _ => NodeChange::InputMapping {
+            input: input_id.clone(),
+            old: old_mappings,
+            new: new_mappings,
+        },
+    };
+    Some(change)
+}
+
+fn source(kind: &CoreNodeKind) -> String {
+    match kind {
+        CoreNodeKind::Custom(n) => n.source.clone(),
+        CoreNodeKind::Runtime(n) => {
+            let operators: Vec<_> = n
+                .operators
+                .iter()
+                .map(|op| {
+                    let source = match &op.config.source {
+                        OperatorSource::SharedLibrary(source) => source,
+                        OperatorSource::Python(python) => &python.source,
+                        OperatorSource::Wasm(source) => source,
+                    };
+                    format!("{}: {source}", op.id)
+                })
+                .collect();
+            format!("operators [{}]", operators.join(", "))
+        }
+    }
+}
+
+fn args(kind: &CoreNodeKind) -> Option<String> {
+    match kind {
+        CoreNodeKind::Custom(n) => n.args.clone(),
+        CoreNodeKind::Runtime(_) => None,
+    }
+}
+
+/// Environment variables of the node, including the deprecated `envs` field
+/// of custom nodes.
+fn env(node: &ResolvedNode) -> BTreeMap<String, String> {
+    let custom_envs = match &node.kind {
+        CoreNodeKind::Custom(n) => n.envs.as_ref(),
+        CoreNodeKind::Runtime(_) => None,
+    };
+    node.env
+        .iter()
+        .chain(custom_envs)
+        .flatten()
+        .map(|(key, value)| (key.clone(), value.to_string()))
+        .collect()
+}
+
+fn mappings_to_string(mappings: &[InputMapping]) -> String {
+    let mappings: Vec<_> = mappings.iter().map(|m| m.to_string()).collect();
+    mappings.join(", ")
+}
+
+fn optional_to_string(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("`{value}`"),
+        None => "(none)".to_owned(),
+    }
+}
+
+impl fmt::Display for NodeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeChange::Source { old, new } => write!(f, "source: `{old}` -> `{new}`"),
+            NodeChange::Args { old, new } => write!(
+                f,
+                "args: {} -> {}",
+                optional_to_string(old),
+                optional_to_string(new)
+            ),
+            NodeChange::Env { key, old, new } => write!(
+                f,
+                "env `{key}`: {} -> {}",
+                optional_to_string(old),
+                optional_to_string(new)
+            ),
+            NodeChange::InputAdded { input, mappings } => {
+                write!(f, "+ input `{input}` <- {}", mappings_to_string(mappings))
+            }
+            NodeChange::InputRemoved { input, mappings } => {
+                write!(f, "- input `{input}` <- {}", mappings_to_string(mappings))
+            }
+            NodeChange::InputMapping { input, old, new } => write!(
+                f,
+                "input `{input}`: {} -> {}",
+                mappings_to_string(old),
+                mappings_to_string(new)
+            ),
+            NodeChange::TimerInterval { input, old, new } => {
+                write!(f, "input `{input}`: timer interval {old:?} -> {new:?}")
+            }
+            NodeChange::InputOptions { input } => write!(f, "input `{input}`: options changed"),
+            NodeChange::OutputAdded { output } => write!(f, "+ output `{output}`"),
+            NodeChange::OutputRemoved { output } => write!(f, "- output `{output}`"),
+        }
+    }
+}
+
+impl fmt::Display for DataflowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        for node in &self.added_nodes {
+            writeln!(f, "+ node `{node}`")?;
+        }
+        for node in &self.removed_nodes {
+            writeln!(f, "- node `{node}`")?;
+        }
+        for (node, changes) in &self.changed_nodes {
+            writeln!(f, "~ node `{node}`:")?;
+            for change in changes {
+                writeln!(f, "    {change}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{diff_nodes, DataflowDiff, NodeChange};
+    use crate::{
+        config::{DataId, NodeId},
+        descriptor::{expand_wildcard_inputs, Descriptor, ResolvedNode},
+    };
+
+    fn resolve(yaml: &str) -> Vec<ResolvedNode> {
+        let mut nodes = Descriptor::parse(yaml.as_bytes().to_vec())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap();
+        expand_wildcard_inputs(&mut nodes);
+        nodes
+    }
+
+    fn changes_of<'a>(diff: &'a DataflowDiff, node: &str) -> &'a [NodeChange] {
+        &diff.changed_nodes[&NodeId::from(node.to_owned())]
+    }
+
+    const DATAFLOW: &str = r#"
+        nodes:
+          - id: camera
+            path: camera.py
+            args: --device 0
+            env:
+              FPS: 30
+            inputs:
+              tick: dora/timer/millis/50
+            outputs:
+              - image
+          - id: plot
+            path: plot.py
+            inputs:
+              image: camera/image
+    "#;
+
+    #[test]
+    fn unchanged_dataflow() {
+        let nodes = resolve(DATAFLOW);
+        let diff = diff_nodes(&nodes, &nodes);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no changes\n");
+
+        // the daemons store the nodes in serialized form
+        let yaml = serde_yaml::to_string(&nodes).unwrap();
+        let stored: Vec<ResolvedNode> = serde_yaml::from_str(&yaml).unwrap();
+        assert!(diff_nodes(&stored, &nodes).is_empty());
+    }
+
+    #[test]
+    fn renamed_output() {
+        let old = resolve(DATAFLOW);
+        let new = resolve(&DATAFLOW.replace("image", "frame"));
+        let diff = diff_nodes(&old, &new);
+
+        assert!(diff.added_nodes.is_empty() && diff.removed_nodes.is_empty());
+        assert_eq!(
+            changes_of(&diff, "camera"),
+            [
+                NodeChange::OutputRemoved {
+                    output: DataId::from("image".to_owned())
+                },
+                NodeChange::OutputAdded {
+                    output: DataId::from("frame".to_owned())
+                },
+            ]
+        );
+        // the input of `plot` is renamed too, so it shows up as removed and added
+        let plot = changes_of(&diff, "plot");
+        assert_eq!(plot.len(), 2);
+        assert!(
+            matches!(&plot[0], NodeChange::InputRemoved { input, .. } if input.as_str() == "image")
+        );
+        assert!(
+            matches!(&plot[1], NodeChange::InputAdded { input, mappings }
+            if input.as_str() == "frame" && mappings[0].to_string() == "camera/frame")
+        );
+    }
+
+    #[test]
+    fn changed_node_config() {
+        let old = resolve(DATAFLOW);
+        let edited = DATAFLOW
+            .replace("args: --device 0", "args: --device 1")
+            .replace("FPS: 30", "FPS: 60\n              EXPOSURE: auto")
+            .replace("millis/50", "millis/20")
+            .replace("image: camera/image", "image: logger/image")
+            .replace("plot.py", "plot_v2.py");
+        let new = resolve(
+            &(edited
+                + "
+          - id: logger
+            path: logger.py
+            outputs:
+              - image"),
+        );
+        let diff = diff_nodes(&old, &new);
+
+        assert_eq!(diff.added_nodes, [NodeId::from("logger".to_owned())].into());
+        assert!(diff.removed_nodes.is_empty());
+        assert_eq!(
+            changes_of(&diff, "camera"),
+            [
+                NodeChange::Args {
+                    old: Some("--device 0".into()),
+                    new: Some("--device 1".into())
+                },
+                NodeChange::Env {
+                    key: "EXPOSURE".into(),
+                    old: None,
+                    new: Some("auto".into())
+                },
+                NodeChange::Env {
+                    key: "FPS".into(),
+                    old: Some("30".into()),
+                    new: Some("60".into())
+                },
+                NodeChange::TimerInterval {
+                    input: DataId::from("tick".to_owned()),
+                    old: Duration::from_millis(50),
+                    new: Duration::from_millis(20)
+                },
+            ]
+        );
+        let report = diff.to_string();
+        assert!(report.contains("+ node `logger`"), "{report}");
+        assert!(
+            report.contains("source: `plot.py` -> `plot_v2.py`"),
+            "{report}"
+        );
+        assert!(
+            report.contains("input `image`: camera/image -> logger/image"),
+            "{report}"
+        );
+
+        let diff = diff_nodes(&new, &old);
+        assert_eq!(
+            diff.removed_nodes,
+            [NodeId::from("logger".to_owned())].into()
+        );
+    }
+}
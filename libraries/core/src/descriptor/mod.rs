@@ -1,22 +1,35 @@
 use crate::config::{
     CommunicationConfig, DataId, Input, InputMapping, NodeId, NodeRunConfig, OperatorId,
+    UserInputMapping,
 };
+pub use defaults::{NodeDefaults, Override};
+pub use diff::{diff_nodes, DataflowDiff, NodeChange};
 use eyre::{bail, eyre, Context, OptionExt, Result};
+use once_cell::sync::OnceCell;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with_expand_env::with_expand_envs;
+pub use start_order::{start_layers, StartOrder};
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
     env::consts::EXE_EXTENSION,
     fmt,
     path::{Path, PathBuf},
+    time::Duration,
 };
 use tracing::warn;
+pub use validate::check_input_options;
 pub use visualize::collect_dora_timers;
+mod defaults;
+mod diff;
+mod start_order;
 mod validate;
 mod visualize;
 pub const SHELL_SOURCE: &str = "shell";
 pub const DYNAMIC_SOURCE: &str = "dynamic";
+/// Reserved input on which nodes with `subscribe_lifecycle: true` receive
+/// lifecycle events of the dataflow.
+pub const LIFECYCLE_INPUT: &str = "dora/lifecycle";
 
 /// Dataflow description
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -29,14 +42,129 @@ pub struct Descriptor {
     #[schemars(skip)]
     #[serde(default, rename = "_unstable_deploy")]
     pub deploy: Deploy,
+    /// Default values for all nodes, e.g. shared environment variables.
+    ///
+    /// e.g.
+    ///
+    /// defaults:
+    ///
+    ///   env: { RUST_LOG: info }
+    ///
+    ///   queue_size: 1
+    #[serde(default, skip_serializing_if = "NodeDefaults::is_empty")]
+    pub defaults: NodeDefaults,
     pub nodes: Vec<Node>,
+    /// Node outputs that are made available to external (non-dora) processes,
+    /// as a map from endpoint name to `node_id/output_id`.
+    ///
+    /// e.g.
+    ///
+    /// expose:
+    ///
+    ///   camera_feed: camera/image
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expose: BTreeMap<String, InputMapping>,
+    /// Endpoints that allow external (non-dora) processes to send messages
+    /// to node inputs, as a map from endpoint name to `node_id/input_id`.
+    ///
+    /// The given input is added to the node automatically, so it must not
+    /// be listed in the node's `inputs`.
+    ///
+    /// e.g.
+    ///
+    /// external_inputs:
+    ///
+    ///   commands: robot/command
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub external_inputs: BTreeMap<String, String>,
+    /// Node input (`node_id/input_id`) that receives a record whenever the
+    /// daemon drops a message of the dataflow, e.g. because the queue of an
+    /// input is full.
+    ///
+    /// The input is added to the node automatically, so it must not be
+    /// listed in the node's `inputs`. The records are rate limited and drops
+    /// of this input itself are not reported.
+    ///
+    /// e.g.
+    ///
+    /// drop_events: supervisor/drops
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drop_events: Option<String>,
+    /// File to which a JSON summary of the result is written when the
+    /// dataflow finishes, relative to the working directory of the dataflow.
+    ///
+    /// e.g.
+    ///
+    /// result_file: out/result.json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_file: Option<PathBuf>,
+    /// Time source of the timer inputs of the dataflow.
+    ///
+    /// By default, timers follow the wall clock. With an `external` clock,
+    /// they follow the time that a node sends on the given output instead,
+    /// e.g. the simulation time of a simulator.
+    ///
+    /// e.g.
+    ///
+    /// clock:
+    ///
+    ///   external: { source: simulator/clock }
+    #[serde(default, skip_serializing_if = "ClockConfig::is_wall")]
+    pub clock: ClockConfig,
+    /// Order in which the nodes are spawned.
+    ///
+    /// By default, all nodes are spawned at once. With `dependency`, nodes
+    /// are spawned in layers, after the nodes that they receive inputs from.
+    /// Cycles are broken at nodes with timer inputs.
+    ///
+    /// e.g.
+    ///
+    /// start_order: dependency
+    #[serde(default, skip_serializing_if = "StartOrder::is_parallel")]
+    pub start_order: StartOrder,
+    /// Maximum time to wait for the nodes of a layer to become ready before
+    /// the next layer is spawned, e.g. `start_layer_timeout: 10s`.
+    ///
+    /// Only used with `start_order: dependency`. If not set, the layers are
+    /// spawned right after each other, without waiting.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "timeout_with_unit"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub start_layer_timeout: Option<Duration>,
+    /// Don't report the dataflow as stalled when no messages flow for a
+    /// while, e.g. for purely event-driven dataflows that wait for external
+    /// input.
+    ///
+    /// By default, the daemon warns when none of the local nodes of a running
+    /// dataflow received a message or timer tick within its stall threshold.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub idle_ok: bool,
+    /// Keep the runtime directory of the dataflow when it finishes.
+    ///
+    /// Each daemon creates a runtime directory for the dataflow in its state
+    /// dir, which the nodes find in the `DORA_DATAFLOW_DIR` environment
+    /// variable. `TMPDIR` points to its `tmp` subdirectory. The directory is
+    /// removed when the dataflow finishes successfully, unless this is set.
+    /// The directories of failed dataflows are always kept.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keep_artifacts: bool,
 }
 
 pub const SINGLE_OPERATOR_DEFAULT_ID: &str = "op";
 
 impl Descriptor {
-    pub fn resolve_aliases_and_set_defaults(&self) -> eyre::Result<Vec<ResolvedNode>> {
-        let default_op_id = OperatorId::from(SINGLE_OPERATOR_DEFAULT_ID.to_string());
+    /// Returns a function that rewrites `node_id/output_id` references to
+    /// their canonical form.
+    ///
+    /// This resolves output aliases and adds the operator ID to outputs of
+    /// single-operator nodes.
+    fn output_resolver(&self) -> eyre::Result<impl Fn(&mut UserInputMapping) + '_> {
+        static DEFAULT_OP_ID: OnceCell<OperatorId> = OnceCell::new();
+        let default_op_id =
+            DEFAULT_OP_ID.get_or_init(|| OperatorId::from(SINGLE_OPERATOR_DEFAULT_ID.to_string()));
 
         let single_operator_nodes: HashMap<_, _> = self
             .nodes
@@ -44,15 +172,107 @@ impl Descriptor {
             .filter_map(|n| {
                 n.operator
                     .as_ref()
-                    .map(|op| (&n.id, op.id.as_ref().unwrap_or(&default_op_id)))
+                    .map(|op| (&n.id, op.id.as_ref().unwrap_or(default_op_id)))
             })
             .collect();
 
+        let mut output_aliases = HashMap::new();
+        for node in &self.nodes {
+            node.check_output_aliases()?;
+            if !node.output_aliases.is_empty() {
+                output_aliases.insert(&node.id, &node.output_aliases);
+            }
+        }
+
+        Ok(move |mapping: &mut UserInputMapping| {
+            if let Some(output) = output_aliases
+                .get(&mapping.source)
+                .and_then(|aliases| aliases.get(&mapping.output))
+            {
+                mapping.output = output.clone();
+            }
+            if let Some(op_name) = single_operator_nodes.get(&mapping.source).copied() {
+                mapping.output = DataId::from(format!("{op_name}/{}", mapping.output));
+            }
+        })
+    }
+
+    /// Resolves the `expose` section into a map from endpoint name to the
+    /// canonical output.
+    pub fn resolve_exposed_outputs(&self) -> eyre::Result<BTreeMap<String, UserInputMapping>> {
+        let resolve_output = self.output_resolver()?;
+        let mut exposed = BTreeMap::new();
+        for (name, mapping) in &self.expose {
+            let InputMapping::User(mapping) = mapping else {
+                bail!("exposed output `{name}` must refer to a node output (got `{mapping}`)");
+            };
+            let mut mapping = mapping.clone();
+            resolve_output(&mut mapping);
+            exposed.insert(name.clone(), mapping);
+        }
+        Ok(exposed)
+    }
+
+    /// Resolves the source of an `external` clock to its canonical output.
+    pub fn resolve_clock_source(&self) -> eyre::Result<Option<UserInputMapping>> {
+        let ClockConfig::External { source } = &self.clock else {
+            return Ok(None);
+        };
+        let InputMapping::User(mapping) = source else {
+            bail!("clock source must refer to a node output (got `{source}`)");
+        };
+        let mut mapping = mapping.clone();
+        self.output_resolver()?(&mut mapping);
+        Ok(Some(mapping))
+    }
+
+    /// Parses the targets of the `external_inputs` section and of
+    /// `drop_events`, grouped by node.
+    fn builtin_input_targets(&self) -> eyre::Result<HashMap<NodeId, Vec<(DataId, InputMapping)>>> {
+        let external = self
+            .external_inputs
+            .iter()
+            .map(|(name, target)| (target, InputMapping::External { name: name.clone() }));
+        let drop_events = self
+            .drop_events
+            .iter()
+            .map(|target| (target, InputMapping::DropEvents));
+
+        let mut targets: HashMap<_, Vec<_>> = HashMap::new();
+        for (target, mapping) in external.chain(drop_events) {
+            let origin = builtin_input_origin(&mapping);
+            let (node_id, input_id) = target
+                .split_once('/')
+                .ok_or_else(|| eyre!("{origin} must target a node input (`<node>/<input>`)"))?;
+            let node_id = NodeId::from(node_id.to_owned());
+            if !self.nodes.iter().any(|n| n.id == node_id) {
+                bail!("node `{node_id}` targeted by {origin} does not exist");
+            }
+            targets
+                .entry(node_id)
+                .or_default()
+                .push((DataId::from(input_id.to_owned()), mapping));
+        }
+        Ok(targets)
+    }
+
+    pub fn resolve_aliases_and_set_defaults(&self) -> eyre::Result<Vec<ResolvedNode>> {
+        self.defaults.check()?;
+        let default_op_id = OperatorId::from(SINGLE_OPERATOR_DEFAULT_ID.to_string());
+        let resolve_output = self.output_resolver()?;
+        let mut builtin_input_targets = self.builtin_input_targets()?;
+
         let mut resolved = vec![];
         for mut node in self.nodes.clone() {
+            for (input_id, mapping) in builtin_input_targets.remove(&node.id).unwrap_or_default() {
+                node.add_builtin_input(input_id, mapping)?;
+            }
+
+            let env = self.defaults.merge_env(&node.id, &node.env)?;
+
             // adjust input mappings
             let mut node_kind = node.kind_mut()?;
-            let input_mappings: Vec<_> = match &mut node_kind {
+            let inputs: Vec<_> = match &mut node_kind {
                 NodeKindMut::Standard { path: _, inputs } => inputs.values_mut().collect(),
                 NodeKindMut::Runtime(node) => node
                     .operators
@@ -62,15 +282,17 @@ impl Descriptor {
                 NodeKindMut::Custom(node) => node.run_config.inputs.values_mut().collect(),
                 NodeKindMut::Operator(operator) => operator.config.inputs.values_mut().collect(),
             };
-            for mapping in input_mappings
-                .into_iter()
-                .filter_map(|i| match &mut i.mapping {
-                    InputMapping::Timer { .. } => None,
+            for input in inputs {
+                if input.queue_size.is_none() {
+                    input.queue_size = self.defaults.queue_size;
+                }
+                for mapping in input.mappings_mut().filter_map(|m| match m {
+                    InputMapping::Timer { .. }
+                    | InputMapping::External { .. }
+                    | InputMapping::DropEvents => None,
                     InputMapping::User(m) => Some(m),
-                })
-            {
-                if let Some(op_name) = single_operator_nodes.get(&mapping.source).copied() {
-                    mapping.output = DataId::from(format!("{op_name}/{}", mapping.output));
+                }) {
+                    resolve_output(mapping);
                 }
             }
 
@@ -81,6 +303,8 @@ impl Descriptor {
                     args: node.args,
                     build: node.build,
                     send_stdout_as: node.send_stdout_as,
+                    raw: node.raw,
+                    framing: node.framing,
                     run_config: NodeRunConfig {
                         inputs: node.inputs,
                         outputs: node.outputs,
@@ -101,8 +325,16 @@ impl Descriptor {
                 id: node.id,
                 name: node.name,
                 description: node.description,
-                env: node.env,
+                env,
                 deploy: ResolvedDeploy::new(node.deploy, self),
+                working_dir: node.working_dir,
+                log_format: node.log_format,
+                ready_timeout: node.ready_timeout,
+                subscribe_lifecycle: node.subscribe_lifecycle,
+                allowed_to_stop: node.allowed_to_stop,
+                persistent_outputs: node.persistent_outputs,
+                remote_transport: node.remote_transport,
+                output_types: node.output_types,
                 kind,
             });
         }
@@ -134,7 +366,17 @@ impl Descriptor {
     }
 
     pub fn check(&self, working_dir: &Path) -> eyre::Result<()> {
-        validate::check_dataflow(self, working_dir, None, false)
+        validate::check_dataflow(self, Some(working_dir), None, false)
+            .wrap_err("Dataflow could not be validated.")
+    }
+
+    /// Checks the dataflow without accessing the local file system.
+    ///
+    /// Useful on machines that don't run any nodes of the dataflow, e.g. on
+    /// the coordinator. The sources of the nodes are checked by the daemons
+    /// that spawn them.
+    pub fn check_without_paths(&self) -> eyre::Result<()> {
+        validate::check_dataflow(self, None, None, false)
             .wrap_err("Dataflow could not be validated.")
     }
 
@@ -146,18 +388,36 @@ impl Descriptor {
     ) -> eyre::Result<()> {
         validate::check_dataflow(
             self,
-            working_dir,
+            Some(working_dir),
             Some(remote_machine_id),
             coordinator_is_remote,
         )
         .wrap_err("Dataflow could not be validated.")
     }
+
+    /// Warnings about valid, but likely unintended configurations, which the
+    /// `check` functions log.
+    pub fn warnings(&self) -> eyre::Result<Vec<String>> {
+        validate::dataflow_warnings(self)
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Deploy {
+    /// ID of the machine, or a glob pattern like `robot-*` that matches the
+    /// IDs of multiple machines.
     pub machine: Option<String>,
+    /// Labels that the machine must have, e.g. `gpu` or `camera=usb`.
+    ///
+    /// The coordinator picks a connected machine that matches both `machine`
+    /// and all required labels.
+    pub requires: Option<Vec<String>>,
+    /// Glob pattern of the machines that are preferred, e.g. `jetson-*`.
+    ///
+    /// Other matching machines are only used if no preferred machine meets
+    /// the requirements.
+    pub prefer: Option<String>,
 }
 
 /// Dora Node
@@ -171,13 +431,119 @@ pub struct Node {
     /// Description of the node
     pub description: Option<String>,
     /// Environment variables
-    pub env: Option<BTreeMap<String, EnvValue>>,
+    ///
+    /// Merged with the `env` of the `defaults` section. Set a variable to
+    /// `null` to remove a default variable, or set `env` to `null` to not
+    /// inherit any default variables.
+    #[serde(default, skip_serializing_if = "Override::is_inherit")]
+    #[schemars(with = "Option<BTreeMap<String, Option<EnvValue>>>")]
+    pub env: Override<BTreeMap<String, Option<EnvValue>>>,
 
     /// Unstable machine deployment configuration
     #[schemars(skip)]
     #[serde(default, rename = "_unstable_deploy")]
     pub deploy: Deploy,
 
+    /// Working directory of the node process.
+    ///
+    /// Relative paths are resolved against the working directory of the
+    /// dataflow on the machine that runs the node. The directory must exist
+    /// when the node is spawned, unless `create` is set.
+    ///
+    /// e.g.
+    ///
+    /// working_dir: { path: out/camera, create: true }
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<NodeWorkingDir>,
+
+    /// Format of the log lines that the node writes to stdout and stderr.
+    ///
+    /// With `json`, the daemon parses each line and re-emits it as a log
+    /// event of the daemon, so that `RUST_LOG` filters apply to node logs
+    /// too (target `dora_node`). Error entries are also forwarded to the
+    /// coordinator.
+    #[serde(default, skip_serializing_if = "LogFormat::is_text")]
+    pub log_format: LogFormat,
+
+    /// Maximum time that the node may take to connect to dora after it was
+    /// spawned, e.g. `ready_timeout: 30s`.
+    ///
+    /// Nodes that are not ready in time are killed and reported as failed,
+    /// so that a node that hangs during initialization (e.g. while waiting
+    /// for hardware) doesn't keep the dataflow from starting forever. By
+    /// default, nodes can take as long as they need.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "timeout_with_unit"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub ready_timeout: Option<Duration>,
+
+    /// Deliver lifecycle events of the dataflow to this node, e.g. to put
+    /// the robot in a safe state when a sibling node crashes.
+    ///
+    /// The events are delivered on the reserved `dora/lifecycle` input, as
+    /// JSON-encoded strings: node started, node stopped, node crashed (with
+    /// the error), and dataflow stopping. Only nodes that run on the same
+    /// machine are reported. The lifecycle input doesn't keep the node
+    /// running, i.e. the node still stops when all its other inputs are
+    /// closed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub subscribe_lifecycle: bool,
+
+    /// Allow this node to stop the whole dataflow, e.g. a safety node that
+    /// decides when the mission ends.
+    ///
+    /// The node requests the stop through `request_stop` of its node API,
+    /// which runs the same stop procedure as `dora stop` and reports the node
+    /// as the initiator in the result of the dataflow. Requests of other nodes are rejected.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allowed_to_stop: bool,
+
+    /// Outputs whose messages are kept in the persistent output cache of
+    /// the daemon, e.g. large maps that are the same on every run.
+    ///
+    /// The cache entries are memory-mapped files in the cache directory of
+    /// the daemon, keyed by output and content hash. They are kept after
+    /// the dataflow stops, so that the node can republish an unchanged
+    /// message on the next run through `publish_cached`, without copying
+    /// it again. Storing a message with a different hash invalidates the
+    /// previous entry of the output.
+    ///
+    /// e.g.
+    ///
+    /// persistent_outputs: [map]
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub persistent_outputs: BTreeSet<DataId>,
+
+    /// Transport of outputs to receivers on other machines, by output.
+    ///
+    /// Outputs are sent over the TCP connections between the daemons by
+    /// default. With `udp`, messages are sent as best-effort UDP datagrams
+    /// instead, which avoids head-of-line blocking for high-rate streams
+    /// that can tolerate loss, e.g. preview images. Lost messages are
+    /// reported as dropped on the edges of the output. Ignored with the
+    /// `zenoh` inter-daemon transport.
+    ///
+    /// e.g.
+    ///
+    /// remote_transport: { preview: udp }
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remote_transport: BTreeMap<DataId, RemoteTransport>,
+
+    /// Declared message types of outputs, by output.
+    ///
+    /// The types are not checked by dora. They are reported to the node and
+    /// to the receivers of the outputs through the topology query, so that
+    /// generic nodes like recorders can interpret the messages.
+    ///
+    /// e.g.
+    ///
+    /// output_types: { image: sensor_msgs/Image }
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub output_types: BTreeMap<DataId, String>,
+
     #[serde(default, skip_serializing_if = "Option::is_none")]
     operators: Option<RuntimeNode>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -193,13 +559,124 @@ pub struct Node {
     pub build: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub send_stdout_as: Option<String>,
+    /// Run `path` as raw node, see [`CustomNode::raw`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub raw: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<RawFraming>,
     #[serde(default)]
     pub inputs: BTreeMap<DataId, Input>,
     #[serde(default)]
     pub outputs: BTreeSet<DataId>,
+    /// Additional names for outputs of this node, as a map from alias to
+    /// output ID.
+    ///
+    /// Inputs of other nodes can refer to an output by any of its aliases,
+    /// e.g. `image_raw: image` allows mapping `camera/image_raw` to the
+    /// `image` output of the `camera` node.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "deserialize_output_aliases"
+    )]
+    pub output_aliases: BTreeMap<DataId, DataId>,
 }
 
 impl Node {
+    /// The node-level IDs of all outputs of this node.
+    ///
+    /// For runtime nodes with multiple operators, the output IDs are
+    /// prefixed with the operator ID.
+    fn declared_outputs(&self) -> eyre::Result<BTreeSet<DataId>> {
+        let outputs = match self.kind()? {
+            NodeKind::Standard(_) => self.outputs.clone(),
+            NodeKind::Runtime(runtime) => runtime_node_outputs(runtime),
+            NodeKind::Custom(custom) => custom.run_config.outputs.clone(),
+            NodeKind::Operator(operator) => operator.config.outputs.clone(),
+        };
+        Ok(outputs)
+    }
+
+    /// Adds an input with the given built-in mapping, e.g. an input that
+    /// receives the messages sent to an external input endpoint.
+    fn add_builtin_input(&mut self, input_id: DataId, mapping: InputMapping) -> eyre::Result<()> {
+        let node_id = self.id.clone();
+        let origin = builtin_input_origin(&mapping);
+        let (inputs, local_input_id) = match self.kind()? {
+            NodeKind::Standard(_) => (&mut self.inputs, input_id.clone()),
+            NodeKind::Custom(_) => match &mut self.custom {
+                Some(custom) => (&mut custom.run_config.inputs, input_id.clone()),
+                None => bail!("no custom"),
+            },
+            NodeKind::Operator(_) => match &mut self.operator {
+                Some(operator) => (&mut operator.config.inputs, input_id.clone()),
+                None => bail!("no operator"),
+            },
+            NodeKind::Runtime(_) => {
+                let (operator_id, local_input_id) = input_id.split_once('/').ok_or_else(|| {
+                    eyre!(
+                        "{origin} must target an operator input of runtime \
+                        node `{node_id}` (`{node_id}/<operator>/<input>`)"
+                    )
+                })?;
+                let operator = self
+                    .operators
+                    .as_mut()
+                    .and_then(|r| {
+                        r.operators
+                            .iter_mut()
+                            .find(|o| o.id.as_ref() == operator_id)
+                    })
+                    .ok_or_else(|| {
+                        eyre!("operator `{node_id}/{operator_id}` targeted by {origin} does not exist")
+                    })?;
+                (
+                    &mut operator.config.inputs,
+                    DataId::from(local_input_id.to_owned()),
+                )
+            }
+        };
+        match inputs.entry(local_input_id) {
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(Input::new(mapping));
+            }
+            std::collections::btree_map::Entry::Occupied(_) => bail!(
+                "input `{node_id}/{input_id}` targeted by {origin} is \
+                already defined in the `inputs` of node `{node_id}`"
+            ),
+        }
+        Ok(())
+    }
+
+    /// Checks that the output aliases of this node point to existing outputs
+    /// and don't shadow other outputs.
+    fn check_output_aliases(&self) -> eyre::Result<()> {
+        let outputs = self.declared_outputs()?;
+        let node_id = &self.id;
+        for (alias, output) in &self.output_aliases {
+            if outputs.contains(alias) {
+                bail!(
+                    "output alias `{node_id}/{alias}` (defined in `output_aliases` as alias \
+                    for `{output}`) conflicts with the output `{node_id}/{alias}` declared \
+                    in `outputs`"
+                );
+            }
+            if let Some(other) = self.output_aliases.get(output) {
+                bail!(
+                    "output alias `{node_id}/{alias}` points to `{output}`, which is itself \
+                    defined as an alias for `{other}` in `output_aliases`"
+                );
+            }
+            if !outputs.contains(output) {
+                bail!(
+                    "output alias `{node_id}/{alias}` points to `{output}`, which is not \
+                    an output of node `{node_id}`"
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub fn kind(&self) -> eyre::Result<NodeKind> {
         match (&self.path, &self.operators, &self.custom, &self.operator) {
             (None, None, None, None) => {
@@ -280,12 +757,46 @@ pub struct ResolvedNode {
 
     #[serde(default)]
     pub deploy: ResolvedDeploy,
+    #[serde(default)]
+    pub working_dir: Option<NodeWorkingDir>,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default, with = "timeout_with_unit")]
+    pub ready_timeout: Option<Duration>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub subscribe_lifecycle: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allowed_to_stop: bool,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub persistent_outputs: BTreeSet<DataId>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remote_transport: BTreeMap<DataId, RemoteTransport>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub output_types: BTreeMap<DataId, String>,
 
     #[serde(flatten)]
     pub kind: CoreNodeKind,
 }
 
 impl ResolvedNode {
+    /// Whether this is a custom node without inputs and outputs.
+    ///
+    /// Such nodes have nothing to exchange with other nodes, so they might
+    /// never connect to dora. The daemon doesn't wait for them before it
+    /// starts the dataflow and only tracks their process.
+    pub fn detached(&self) -> bool {
+        match &self.kind {
+            CoreNodeKind::Runtime(_) => false,
+            CoreNodeKind::Custom(n) => {
+                n.source != DYNAMIC_SOURCE
+                    && !n.raw
+                    && !self.subscribe_lifecycle
+                    && n.run_config.inputs.is_empty()
+                    && n.run_config.outputs.is_empty()
+            }
+        }
+    }
+
     pub fn send_stdout_as(&self) -> Result<Option<String>> {
         match &self.kind {
             // TODO: Split stdout between operators
@@ -315,6 +826,10 @@ impl ResolvedNode {
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ResolvedDeploy {
     pub machine: String,
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(default)]
+    pub prefer: Option<String>,
 }
 impl ResolvedDeploy {
     fn new(deploy: Deploy, descriptor: &Descriptor) -> Self {
@@ -323,7 +838,25 @@ impl ResolvedDeploy {
             Some(m) => m,
             None => default_machine.to_owned(),
         };
-        Self { machine }
+        let requires = deploy
+            .requires
+            .or_else(|| descriptor.deploy.requires.clone())
+            .unwrap_or_default();
+        let prefer = deploy.prefer.or_else(|| descriptor.deploy.prefer.clone());
+        Self {
+            machine,
+            requires,
+            prefer,
+        }
+    }
+
+    /// Whether the machine needs to be picked by the coordinator, i.e. if
+    /// `machine` is a pattern, if labels are required, or if no machine is
+    /// given but a preferred one.
+    pub fn is_pattern(&self) -> bool {
+        self.machine.contains(['*', '?', '['])
+            || !self.requires.is_empty()
+            || (self.machine.is_empty() && self.prefer.is_some())
     }
 }
 
@@ -336,6 +869,142 @@ pub enum CoreNodeKind {
     Custom(CustomNode),
 }
 
+/// Replaces all wildcard inputs (e.g. `camera/*`) of the given nodes by concrete
+/// inputs, one for every matching output.
+///
+/// The expanded inputs are named `<input>/<source>/<output>`. Wildcards in the
+/// source list of a fan-in input are expanded within that input instead.
+/// Wildcards that don't match any output are removed with a warning.
+pub fn expand_wildcard_inputs(nodes: &mut [ResolvedNode]) {
+    let outputs: Vec<(NodeId, DataId)> = nodes
+        .iter()
+        .flat_map(|node| {
+            node.kind
+                .run_config()
+                .outputs
+                .into_iter()
+                .map(|output| (node.id.clone(), output))
+        })
+        .collect();
+
+    for node in nodes.iter_mut() {
+        let node_outputs = || outputs.iter().filter(|(source, _)| source != &node.id);
+        let unmatched = match &mut node.kind {
+            CoreNodeKind::Custom(custom) => {
+                expand_wildcards(&mut custom.run_config.inputs, node_outputs())
+            }
+            CoreNodeKind::Runtime(runtime) => runtime
+                .operators
+                .iter_mut()
+                .flat_map(|operator| expand_wildcards(&mut operator.config.inputs, node_outputs()))
+                .collect(),
+        };
+        for (input_id, mapping) in unmatched {
+            warn!(
+                "wildcard input `{}/{input_id}` (mapped to `{mapping}`) does not match any output",
+                node.id
+            );
+        }
+    }
+}
+
+/// Expands the wildcard inputs of a single node or operator and returns the
+/// wildcards that didn't match any of the given outputs.
+///
+/// A single wildcard source is expanded into one input per matching output.
+/// Wildcards in the source list of a fan-in input are replaced by all
+/// matching outputs, which are then merged into that input.
+fn expand_wildcards<'a>(
+    inputs: &mut BTreeMap<DataId, Input>,
+    outputs: impl Iterator<Item = &'a (NodeId, DataId)> + Clone,
+) -> Vec<(DataId, InputMapping)> {
+    let is_wildcard =
+        |mapping: &InputMapping| matches!(mapping, InputMapping::User(m) if m.is_wildcard());
+    let matching = |mapping: &UserInputMapping| {
+        outputs
+            .clone()
+            .filter(|(s, o)| mapping.matches(s, o))
+            .map(|(source, output)| UserInputMapping {
+                source: source.clone(),
+                output: output.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut unmatched = Vec::new();
+    let wildcards: Vec<_> = inputs
+        .iter()
+        .filter(|(_, input)| input.mappings().any(is_wildcard))
+        .map(|(id, _)| id.clone())
+        .collect();
+    for input_id in wildcards {
+        let Some(input) = inputs.remove(&input_id) else {
+            continue;
+        };
+        if input.is_fan_in() {
+            let mut mappings = Vec::new();
+            for mapping in input.mappings() {
+                let expanded = match mapping {
+                    InputMapping::User(user) if user.is_wildcard() => {
+                        let matches = matching(user);
+                        if matches.is_empty() {
+                            unmatched.push((input_id.clone(), mapping.clone()));
+                        }
+                        matches.into_iter().map(InputMapping::User).collect()
+                    }
+                    other => vec![other.clone()],
+                };
+                for mapping in expanded {
+                    if !mappings.contains(&mapping) {
+                        mappings.push(mapping);
+                    }
+                }
+            }
+            let mut mappings = mappings.into_iter();
+            if let Some(mapping) = mappings.next() {
+                inputs.insert(
+                    input_id,
+                    Input {
+                        mapping,
+                        additional_mappings: mappings.collect(),
+                        ..input
+                    },
+                );
+            }
+            continue;
+        }
+
+        let InputMapping::User(mapping) = &input.mapping else {
+            continue;
+        };
+        let matches = matching(mapping);
+        if matches.is_empty() {
+            unmatched.push((input_id, input.mapping));
+            continue;
+        }
+        for mapping in matches {
+            inputs.insert(
+                DataId::from(format!("{input_id}/{}/{}", mapping.source, mapping.output)),
+                Input {
+                    mapping: InputMapping::User(mapping),
+                    ..input.clone()
+                },
+            );
+        }
+    }
+    unmatched
+}
+
+/// Describes where an input that was added through [`Node::add_builtin_input`]
+/// comes from, for error messages.
+fn builtin_input_origin(mapping: &InputMapping) -> String {
+    match mapping {
+        InputMapping::External { name } => format!("external input `{name}`"),
+        InputMapping::DropEvents => "`drop_events`".to_owned(),
+        other => format!("`{other}`"),
+    }
+}
+
 pub fn runtime_node_inputs(n: &RuntimeNode) -> BTreeMap<DataId, Input> {
     n.operators
         .iter()
@@ -475,26 +1144,113 @@ impl From<PythonSourceDef> for PythonSource {
     }
 }
 
+/// Working directory of a node, given either as a plain path or with options.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(
+    deny_unknown_fields,
+    from = "NodeWorkingDirDef",
+    into = "NodeWorkingDirDef"
+)]
+pub struct NodeWorkingDir {
+    pub path: PathBuf,
+    /// Create the directory (and its parents) if it doesn't exist.
+    pub create: bool,
+}
+
+impl NodeWorkingDir {
+    /// Resolves the directory against the working directory of the dataflow.
+    ///
+    /// Creates the directory if requested and checks that it exists.
+    pub fn resolve(&self, base: &Path) -> eyre::Result<PathBuf> {
+        let path = base.join(&self.path);
+        if self.create {
+            std::fs::create_dir_all(&path)
+                .wrap_err_with(|| format!("failed to create `{}`", path.display()))?;
+        }
+        if !path.is_dir() {
+            bail!("directory `{}` does not exist", path.display());
+        }
+        path.canonicalize()
+            .wrap_err_with(|| format!("failed to canonicalize `{}`", path.display()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum NodeWorkingDirDef {
+    PathOnly(PathBuf),
+    WithOptions {
+        path: PathBuf,
+        #[serde(default)]
+        create: bool,
+    },
+}
+
+impl From<NodeWorkingDir> for NodeWorkingDirDef {
+    fn from(input: NodeWorkingDir) -> Self {
+        match input {
+            NodeWorkingDir {
+                path,
+                create: false,
+            } => Self::PathOnly(path),
+            NodeWorkingDir { path, create } => Self::WithOptions { path, create },
+        }
+    }
+}
+
+impl From<NodeWorkingDirDef> for NodeWorkingDir {
+    fn from(value: NodeWorkingDirDef) -> Self {
+        match value {
+            NodeWorkingDirDef::PathOnly(path) => Self {
+                path,
+                create: false,
+            },
+            NodeWorkingDirDef::WithOptions { path, create } => Self { path, create },
+        }
+    }
+}
+
 pub fn source_is_url(source: &str) -> bool {
     source.contains("://")
 }
 
+/// Resolves a node source to an absolute path.
+///
+/// The source is searched in the working directory first and then in `$PATH`.
+/// Sources without extension are resolved to executables of the current
+/// platform, i.e. on Windows `./node` matches `node.exe`, `node.bat`, or
+/// `node.cmd` (in that order).
 pub fn resolve_path(source: &str, working_dir: &Path) -> Result<PathBuf> {
-    let path = Path::new(&source);
-    let path = if path.extension().is_none() {
-        path.with_extension(EXE_EXTENSION)
-    } else {
-        path.to_owned()
-    };
+    let candidates = source_candidates(Path::new(source));
 
     // Search path within current working directory
-    if let Ok(abs_path) = working_dir.join(&path).canonicalize() {
-        Ok(abs_path)
+    for path in &candidates {
+        if let Ok(abs_path) = working_dir.join(path).canonicalize() {
+            return Ok(abs_path);
+        }
+    }
     // Search path within $PATH
-    } else if let Ok(abs_path) = which::which(&path) {
-        Ok(abs_path)
+    for path in &candidates {
+        if let Ok(abs_path) = which::which(path) {
+            return Ok(abs_path);
+        }
+    }
+    bail!("Could not find source path {}", candidates[0].display())
+}
+
+/// Extensions of files that can be executed directly on Windows.
+const WINDOWS_EXECUTABLE_EXTENSIONS: &[&str] = &["exe", "bat", "cmd"];
+
+fn source_candidates(path: &Path) -> Vec<PathBuf> {
+    if path.extension().is_some() {
+        vec![path.to_owned()]
+    } else if cfg!(windows) {
+        WINDOWS_EXECUTABLE_EXTENSIONS
+            .iter()
+            .map(|ext| path.with_extension(ext))
+            .collect()
     } else {
-        bail!("Could not find source path {}", path.display())
+        vec![path.with_extension(EXE_EXTENSION)]
     }
 }
 
@@ -533,11 +1289,250 @@ pub struct CustomNode {
     /// Send stdout and stderr to another node
     #[serde(skip_serializing_if = "Option::is_none")]
     pub send_stdout_as: Option<String>,
+    /// Runs an executable that doesn't use the dora API as node.
+    ///
+    /// The daemon writes all inputs of the node to the stdin of the process
+    /// and sends out everything that the process writes to its stdout on the
+    /// single output of the node (if any). The message format is set through
+    /// the `framing` field.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub raw: bool,
+    /// Framing of the messages that are exchanged with a `raw` node.
+    ///
+    /// Defaults to `length-prefixed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<RawFraming>,
 
     #[serde(flatten)]
     pub run_config: NodeRunConfig,
 }
 
+impl CustomNode {
+    /// Checks the `raw` and `framing` fields of this node.
+    pub fn check_raw_config(&self) -> eyre::Result<()> {
+        if !self.raw {
+            if self.framing.is_some() {
+                bail!("`framing` can only be set for `raw` nodes");
+            }
+            return Ok(());
+        }
+        if self.source == DYNAMIC_SOURCE {
+            bail!("dynamic nodes cannot be `raw` nodes");
+        }
+        if self.run_config.outputs.len() > 1 {
+            bail!(
+                "`raw` nodes can have at most one output, since all of their stdout is sent to it"
+            );
+        }
+        if self.send_stdout_as.is_some() && !self.run_config.outputs.is_empty() {
+            bail!("`send_stdout_as` cannot be used for `raw` nodes with an output");
+        }
+        Ok(())
+    }
+}
+
+/// Framing of the messages that are exchanged with a `raw` node through its
+/// stdin and stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RawFraming {
+    /// Each message is prefixed with its length, encoded as little-endian `u64`.
+    ///
+    /// Inputs are expected as byte arrays or strings.
+    #[default]
+    LengthPrefixed,
+    /// Each message is a line of UTF-8 text.
+    ///
+    /// Inputs are expected as strings, outputs are sent as strings (without
+    /// the trailing newline).
+    Text,
+}
+
+/// Time source of the timers of a dataflow.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockConfig {
+    /// Timers fire based on the wall clock of the daemon.
+    #[default]
+    Wall,
+    /// Timers fire based on the time that is sent on the given node output.
+    ///
+    /// The output needs to send the current time as a single `u64` value in
+    /// nanoseconds. Timers don't fire while the time doesn't change, and
+    /// times that are not after the previous time are ignored.
+    External { source: InputMapping },
+}
+
+impl ClockConfig {
+    pub fn is_wall(&self) -> bool {
+        *self == Self::Wall
+    }
+}
+
+/// (De)serializes optional timeouts as numbers with a unit, e.g. `500ms`,
+/// `30s`, or `2min`.
+pub(crate) mod timeout_with_unit {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match value {
+            Some(timeout) if timeout.subsec_nanos() == 0 => {
+                serializer.serialize_str(&format!("{}s", timeout.as_secs()))
+            }
+            Some(timeout) => {
+                serializer.serialize_str(&format!("{}ms", timeout.as_secs_f64() * 1e3))
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| parse(&value).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+
+    fn parse(value: &str) -> Result<Duration, String> {
+        let trimmed = value.trim();
+        let split = trimmed
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("invalid timeout `{value}` (expected e.g. `30s`)"))?;
+        let secs = match unit.trim() {
+            "ms" => number / 1e3,
+            "s" => number,
+            "min" => number * 60.0,
+            "" => {
+                return Err(format!(
+                    "timeout `{value}` has no unit (expected e.g. `30s`)"
+                ))
+            }
+            other => return Err(format!("unknown unit `{other}` in timeout `{value}`")),
+        };
+        Duration::try_from_secs_f64(secs).map_err(|err| format!("invalid timeout `{value}`: {err}"))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parse_units() {
+            assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+            assert_eq!(parse("30s"), Ok(Duration::from_secs(30)));
+            assert_eq!(parse("1.5 s"), Ok(Duration::from_millis(1500)));
+            assert_eq!(parse("2min"), Ok(Duration::from_secs(120)));
+            assert!(parse("30").is_err());
+            assert!(parse("1h").is_err());
+        }
+    }
+}
+
+/// (De)serializes required durations as numbers with a unit, see
+/// [`timeout_with_unit`].
+pub(crate) mod duration_with_unit {
+    use std::time::Duration;
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        super::timeout_with_unit::serialize(&Some(*value), serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::timeout_with_unit::deserialize(deserializer)?
+            .ok_or_else(|| serde::de::Error::custom("expected a duration, e.g. `2ms`"))
+    }
+}
+
+/// Deserializes the `output_aliases` of a node, rejecting aliases that are
+/// defined more than once (instead of silently keeping the last definition).
+fn deserialize_output_aliases<'de, D>(deserializer: D) -> Result<BTreeMap<DataId, DataId>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct AliasVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for AliasVisitor {
+        type Value = BTreeMap<DataId, DataId>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map from output alias to output ID")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::MapAccess<'de>,
+        {
+            let mut aliases = BTreeMap::new();
+            while let Some((alias, output)) = map.next_entry::<DataId, DataId>()? {
+                if let Some(previous) = aliases.get(&alias) {
+                    return Err(serde::de::Error::custom(format!(
+                        "output alias `{alias}` is defined twice in `output_aliases`: \
+                        as alias for `{previous}` and as alias for `{output}`"
+                    )));
+                }
+                aliases.insert(alias, output);
+            }
+            Ok(aliases)
+        }
+    }
+
+    deserializer.deserialize_map(AliasVisitor)
+}
+
+/// Format of the log lines that a node writes to stdout and stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Lines are forwarded as they are.
+    #[default]
+    Text,
+    /// Each line is a JSON object with a `level`, a `message` (or `msg`),
+    /// and optional additional fields.
+    ///
+    /// Lines that can't be parsed are forwarded as text.
+    Json,
+}
+
+impl LogFormat {
+    pub fn is_text(&self) -> bool {
+        *self == Self::Text
+    }
+}
+
+/// Transport of an output to receivers on other machines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemoteTransport {
+    /// Reliable and in order, through the transport between the daemons.
+    #[default]
+    Tcp,
+    /// Best-effort UDP datagrams.
+    ///
+    /// Messages that are not received completely are dropped. Messages are
+    /// still delivered in order, i.e. a message that completes after a newer
+    /// message of the same output is dropped too.
+    Udp,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum EnvValue {
@@ -558,3 +1553,184 @@ impl fmt::Display for EnvValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATAFLOW: &str = r#"
+        nodes:
+          - id: camera
+            path: camera.py
+            outputs:
+              - image
+              - depth
+          - id: lidar
+            path: lidar.py
+            outputs:
+              - points
+          - id: recorder
+            path: recorder.py
+            inputs:
+              all:
+                source: camera/*
+                queue_size: 3
+            outputs:
+              - stats
+    "#;
+
+    fn expanded(yaml: &str) -> Vec<ResolvedNode> {
+        let mut nodes = Descriptor::parse(yaml.as_bytes().to_vec())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap();
+        expand_wildcard_inputs(&mut nodes);
+        nodes
+    }
+
+    fn inputs_of(nodes: &[ResolvedNode], node: &str) -> BTreeMap<DataId, Input> {
+        nodes
+            .iter()
+            .find(|n| n.id.as_ref() == node)
+            .unwrap()
+            .kind
+            .run_config()
+            .inputs
+    }
+
+    fn mappings(inputs: &BTreeMap<DataId, Input>) -> Vec<(String, String)> {
+        inputs
+            .iter()
+            .map(|(id, input)| (id.to_string(), input.mapping.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn node_wildcard_is_expanded_per_output() {
+        let inputs = inputs_of(&expanded(DATAFLOW), "recorder");
+        assert_eq!(
+            mappings(&inputs),
+            [
+                ("all/camera/depth".into(), "camera/depth".into()),
+                ("all/camera/image".into(), "camera/image".into()),
+            ]
+        );
+        // the options of the wildcard input are kept
+        assert!(inputs.values().all(|input| input.queue_size == Some(3)));
+    }
+
+    #[test]
+    fn full_wildcard_skips_own_outputs() {
+        let inputs = inputs_of(&expanded(&DATAFLOW.replace("camera/*", "*/*")), "recorder");
+        assert_eq!(
+            mappings(&inputs),
+            [
+                ("all/camera/depth".into(), "camera/depth".into()),
+                ("all/camera/image".into(), "camera/image".into()),
+                ("all/lidar/points".into(), "lidar/points".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unmatched_wildcard_is_removed_and_reported() {
+        let yaml = DATAFLOW.replace("camera/*", "radar/*");
+        assert!(inputs_of(&expanded(&yaml), "recorder").is_empty());
+
+        let nodes = Descriptor::parse(yaml.into_bytes())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap();
+        let mut inputs = inputs_of(&nodes, "recorder");
+        let outputs = [(
+            NodeId::from("camera".to_owned()),
+            DataId::from("image".to_owned()),
+        )];
+        let unmatched = expand_wildcards(&mut inputs, outputs.iter());
+        assert!(inputs.is_empty());
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].0.as_str(), "all");
+        assert_eq!(unmatched[0].1.to_string(), "radar/*");
+    }
+
+    #[test]
+    fn fan_in_wildcards_are_expanded_within_the_input() {
+        let yaml = DATAFLOW.replace("camera/*", "[camera/*, lidar/points, radar/*]");
+        let nodes = expanded(&yaml);
+        let inputs = inputs_of(&nodes, "recorder");
+        assert_eq!(inputs.len(), 1);
+        let input = &inputs[&DataId::from("all".to_owned())];
+        let sources: Vec<_> = input.mappings().map(|m| m.to_string()).collect();
+        assert_eq!(sources, ["camera/depth", "camera/image", "lidar/points"]);
+        assert_eq!(input.queue_size, Some(3));
+        // the expanded dataflow passes validation
+        Descriptor::parse(yaml.into_bytes())
+            .unwrap()
+            .check_without_paths()
+            .unwrap();
+
+        // duplicates are merged and inputs without any matched source are removed
+        let yaml = DATAFLOW.replace("camera/*", "[camera/*, camera/image]");
+        let input = &inputs_of(&expanded(&yaml), "recorder")[&DataId::from("all".to_owned())];
+        assert_eq!(input.mappings().count(), 2);
+        let yaml = DATAFLOW.replace("camera/*", "[radar/*, sonar/*]");
+        assert!(inputs_of(&expanded(&yaml), "recorder").is_empty());
+    }
+
+    const ALIASES: &str = r#"
+        nodes:
+          - id: camera
+            path: camera.py
+            outputs:
+              - image
+              - depth
+            output_aliases:
+              image_raw: image
+          - id: plot
+            path: plot.py
+            inputs:
+              image: camera/image_raw
+    "#;
+
+    #[test]
+    fn output_aliases_resolve_to_canonical_output() {
+        let nodes = expanded(ALIASES);
+        assert_eq!(
+            mappings(&inputs_of(&nodes, "plot")),
+            [("image".into(), "camera/image".into())]
+        );
+    }
+
+    #[test]
+    fn conflicting_output_aliases_are_rejected() {
+        let yaml = ALIASES.replace(
+            "image_raw: image",
+            "image_raw: image\n              image_raw: depth",
+        );
+        let err = Descriptor::parse(yaml.into_bytes()).unwrap_err();
+        let err = format!("{err:?}");
+        assert!(err.contains("`image_raw` is defined twice"), "{err}");
+        assert!(
+            err.contains("alias for `image`") && err.contains("alias for `depth`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn output_alias_must_not_shadow_output() {
+        let yaml = ALIASES.replace("image_raw: image", "depth: image");
+        let err = Descriptor::parse(yaml.into_bytes())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap_err();
+        let err = format!("{err:?}");
+        assert!(
+            err.contains("output alias `camera/depth` (defined in `output_aliases`"),
+            "{err}"
+        );
+        assert!(
+            err.contains("conflicts with the output `camera/depth` declared in `outputs`"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{InputMapping, NodeId};
+
+use super::ResolvedNode;
+
+/// Order in which the nodes of a dataflow are spawned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StartOrder {
+    /// All nodes are spawned at once.
+    #[default]
+    Parallel,
+    /// Nodes are spawned in layers, after the nodes that they receive inputs
+    /// from (see [`start_layers`]).
+    Dependency,
+}
+
+impl StartOrder {
+    pub fn is_parallel(&self) -> bool {
+        *self == Self::Parallel
+    }
+}
+
+/// Splits the given nodes into layers that can be started one after another,
+/// so that every node is started after the nodes that it receives inputs from.
+///
+/// The first layer contains the nodes without upstream nodes, e.g. nodes that
+/// only have timer inputs. Cycles in the mapping graph are broken at the
+/// nodes with timer inputs, which are started before the other nodes of the
+/// cycle. Cycles without such nodes are started as a single layer.
+///
+/// The nodes of each layer keep their order in the descriptor.
+pub fn start_layers(nodes: &[ResolvedNode]) -> Vec<Vec<NodeId>> {
+    let ids: BTreeSet<&NodeId> = nodes.iter().map(|n| &n.id).collect();
+    let mut upstream: BTreeMap<&NodeId, BTreeSet<&NodeId>> = BTreeMap::new();
+    let mut timer_fed = BTreeSet::new();
+    for node in nodes {
+        let run_config = node.kind.run_config();
+        let sources = upstream.entry(&node.id).or_default();
+        for mapping in run_config
+            .inputs
+            .values()
+            .flat_map(|input| input.mappings())
+        {
+            match mapping {
+                InputMapping::User(mapping) => {
+                    if let Some(source) = ids.get(&mapping.source) {
+                        if *source != &node.id {
+                            sources.insert(*source);
+                        }
+                    }
+                }
+                InputMapping::Timer { .. } => {
+                    timer_fed.insert(&node.id);
+                }
+                InputMapping::External { .. } | InputMapping::DropEvents => {}
+            }
+        }
+    }
+
+    let mut started = BTreeSet::new();
+    let mut layers = Vec::new();
+    while started.len() < nodes.len() {
+        let remaining = || {
+            nodes
+                .iter()
+                .map(|n| &n.id)
+                .filter(|id| !started.contains(id))
+        };
+        let mut layer: Vec<&NodeId> = remaining()
+            .filter(|id| upstream[id].iter().all(|source| started.contains(source)))
+            .collect();
+        if layer.is_empty() {
+            // only cycles are left
+            layer = remaining().filter(|id| timer_fed.contains(id)).collect();
+        }
+        if layer.is_empty() {
+            layer = remaining().collect();
+        }
+        started.extend(layer.iter().copied());
+        layers.push(layer.into_iter().cloned().collect());
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::start_layers;
+    use crate::descriptor::Descriptor;
+
+    fn layers(yaml: &str) -> Vec<Vec<String>> {
+        let nodes = Descriptor::parse(yaml.as_bytes().to_vec())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap();
+        start_layers(&nodes)
+            .into_iter()
+            .map(|layer| layer.into_iter().map(|id| id.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sources_are_started_first() {
+        let layers = layers(
+            r#"
+            nodes:
+              - id: plot
+                path: plot
+                inputs:
+                  image: camera/image
+                  objects: detector/objects
+              - id: detector
+                path: detector
+                inputs:
+                  image: camera/image
+                outputs:
+                  - objects
+              - id: camera
+                path: camera
+                inputs:
+                  tick: dora/timer/millis/20
+                outputs:
+                  - image
+              - id: logger
+                path: logger
+            "#,
+        );
+        assert_eq!(
+            layers,
+            [vec!["camera", "logger"], vec!["detector"], vec!["plot"]]
+        );
+    }
+
+    #[test]
+    fn cycles_are_broken_at_timer_fed_nodes() {
+        let layers = layers(
+            r#"
+            nodes:
+              - id: controller
+                path: controller
+                inputs:
+                  state: robot/state
+                outputs:
+                  - command
+              - id: robot
+                path: robot
+                inputs:
+                  tick: dora/timer/millis/10
+                  command: controller/command
+                outputs:
+                  - state
+              - id: a
+                path: a
+                inputs:
+                  b: b/out
+                outputs:
+                  - out
+              - id: b
+                path: b
+                inputs:
+                  a: a/out
+                outputs:
+                  - out
+            "#,
+        );
+        assert_eq!(layers, [vec!["robot"], vec!["controller"], vec!["a", "b"]]);
+    }
+}
@@ -1,74 +1,87 @@
 use crate::{
     adjust_shared_library_path,
-    config::{DataId, Input, InputMapping, OperatorId, UserInputMapping},
+    config::{format_duration, DataId, Input, InputMapping, OperatorId, UserInputMapping},
     descriptor::{self, source_is_url, CoreNodeKind, OperatorSource},
     get_python_path,
 };
 
 use eyre::{bail, eyre, Context};
-use std::{path::Path, process::Command};
-use tracing::info;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    process::Command,
+    time::Duration,
+};
+use tracing::{info, warn};
 
-use super::{resolve_path, Descriptor, DYNAMIC_SOURCE, SHELL_SOURCE};
+use super::{resolve_path, Descriptor, DYNAMIC_SOURCE, LIFECYCLE_INPUT, SHELL_SOURCE};
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Validates the given dataflow.
+///
+/// The sources of nodes and operators are only checked if a `working_dir` is
+/// given. Otherwise, the local file system is not accessed at all.
 pub fn check_dataflow(
     dataflow: &Descriptor,
-    working_dir: &Path,
+    working_dir: Option<&Path>,
     remote_daemon_id: Option<&[&str]>,
     coordinator_is_remote: bool,
 ) -> eyre::Result<()> {
-    let nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    let mut nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    descriptor::expand_wildcard_inputs(&mut nodes);
     let mut has_python_operator = false;
 
     // check that nodes and operators exist
-    for node in &nodes {
-        match &node.kind {
-            descriptor::CoreNodeKind::Custom(custom) => match custom.source.as_str() {
-                SHELL_SOURCE => (),
-                DYNAMIC_SOURCE => (),
-                source => {
-                    if source_is_url(source) {
-                        info!("{source} is a URL."); // TODO: Implement url check.
-                    } else if let Some(remote_daemon_id) = remote_daemon_id {
-                        if remote_daemon_id.contains(&node.deploy.machine.as_str())
-                            || coordinator_is_remote
-                        {
-                            info!("skipping path check for remote node `{}`", node.id);
-                        }
-                    } else {
-                        resolve_path(source, working_dir)
-                            .wrap_err_with(|| format!("Could not find source path `{}`", source))?;
-                    };
-                }
-            },
-            descriptor::CoreNodeKind::Runtime(node) => {
-                for operator_definition in &node.operators {
-                    match &operator_definition.config.source {
-                        OperatorSource::SharedLibrary(path) => {
-                            if source_is_url(path) {
-                                info!("{path} is a URL."); // TODO: Implement url check.
-                            } else {
-                                let path = adjust_shared_library_path(Path::new(&path))?;
-                                if !working_dir.join(&path).exists() {
-                                    bail!("no shared library at `{}`", path.display());
+    if let Some(working_dir) = working_dir {
+        for node in &nodes {
+            match &node.kind {
+                descriptor::CoreNodeKind::Custom(custom) => match custom.source.as_str() {
+                    SHELL_SOURCE => (),
+                    DYNAMIC_SOURCE => (),
+                    source => {
+                        if source_is_url(source) {
+                            info!("{source} is a URL."); // TODO: Implement url check.
+                        } else if let Some(remote_daemon_id) = remote_daemon_id {
+                            if remote_daemon_id.contains(&node.deploy.machine.as_str())
+                                || coordinator_is_remote
+                            {
+                                info!("skipping path check for remote node `{}`", node.id);
+                            }
+                        } else {
+                            resolve_path(source, working_dir).wrap_err_with(|| {
+                                format!("Could not find source path `{}`", source)
+                            })?;
+                        };
+                    }
+                },
+                descriptor::CoreNodeKind::Runtime(node) => {
+                    for operator_definition in &node.operators {
+                        match &operator_definition.config.source {
+                            OperatorSource::SharedLibrary(path) => {
+                                if source_is_url(path) {
+                                    info!("{path} is a URL."); // TODO: Implement url check.
+                                } else {
+                                    let path = adjust_shared_library_path(Path::new(&path))?;
+                                    if !working_dir.join(&path).exists() {
+                                        bail!("no shared library at `{}`", path.display());
+                                    }
                                 }
                             }
-                        }
-                        OperatorSource::Python(python_source) => {
-                            has_python_operator = true;
-                            let path = &python_source.source;
-                            if source_is_url(path) {
-                                info!("{path} is a URL."); // TODO: Implement url check.
-                            } else if !working_dir.join(path).exists() {
-                                bail!("no Python library at `{path}`");
+                            OperatorSource::Python(python_source) => {
+                                has_python_operator = true;
+                                let path = &python_source.source;
+                                if source_is_url(path) {
+                                    info!("{path} is a URL."); // TODO: Implement url check.
+                                } else if !working_dir.join(path).exists() {
+                                    bail!("no Python library at `{path}`");
+                                }
                             }
-                        }
-                        OperatorSource::Wasm(path) => {
-                            if source_is_url(path) {
-                                info!("{path} is a URL."); // TODO: Implement url check.
-                            } else if !working_dir.join(path).exists() {
-                                bail!("no WASM library at `{path}`");
+                            OperatorSource::Wasm(path) => {
+                                if source_is_url(path) {
+                                    info!("{path} is a URL."); // TODO: Implement url check.
+                                } else if !working_dir.join(path).exists() {
+                                    bail!("no WASM library at `{path}`");
+                                }
                             }
                         }
                     }
@@ -82,15 +95,34 @@ pub fn check_dataflow(
         match &node.kind {
             descriptor::CoreNodeKind::Custom(custom_node) => {
                 for (input_id, input) in &custom_node.run_config.inputs {
-                    check_input(input, &nodes, &format!("{}/{input_id}", node.id))?;
+                    if input_id.starts_with("dora/") {
+                        bail!(
+                            "input `{}/{input_id}` uses the reserved `dora/` prefix \
+                            (use `subscribe_lifecycle: true` for `{LIFECYCLE_INPUT}`)",
+                            node.id
+                        );
+                    }
+                    check_input(
+                        input,
+                        &nodes,
+                        &dataflow.external_inputs,
+                        &format!("{}/{input_id}", node.id),
+                    )?;
                 }
             }
             descriptor::CoreNodeKind::Runtime(runtime_node) => {
+                if node.subscribe_lifecycle {
+                    bail!(
+                        "node `{}`: `subscribe_lifecycle` is only supported for custom nodes",
+                        node.id
+                    );
+                }
                 for operator_definition in &runtime_node.operators {
                     for (input_id, input) in &operator_definition.config.inputs {
                         check_input(
                             input,
                             &nodes,
+                            &dataflow.external_inputs,
                             &format!("{}/{}/{input_id}", operator_definition.id, node.id),
                         )?;
                     }
@@ -99,26 +131,236 @@ pub fn check_dataflow(
         };
     }
 
+    for node in &nodes {
+        for warning in node_warnings(node) {
+            warn!("{warning}");
+        }
+    }
+
+    // check that persistent outputs are declared
+    for node in &nodes {
+        let outputs = node.kind.run_config().outputs;
+        if let Some(output) = node
+            .persistent_outputs
+            .iter()
+            .find(|output| !outputs.contains(*output))
+        {
+            bail!(
+                "persistent output `{}/{output}` is not declared in the `outputs` of the node",
+                node.id
+            );
+        }
+    }
+
+    // check that the outputs with a remote transport are declared
+    for node in &nodes {
+        let outputs = node.kind.run_config().outputs;
+        if let Some(output) = node
+            .remote_transport
+            .keys()
+            .find(|output| !outputs.contains(*output))
+        {
+            bail!(
+                "output `{}/{output}` has a `remote_transport`, but is not declared in the \
+                `outputs` of the node",
+                node.id
+            );
+        }
+    }
+
+    // check that the outputs with a declared type exist
+    for node in &nodes {
+        let outputs = node.kind.run_config().outputs;
+        if let Some(output) = node
+            .output_types
+            .keys()
+            .find(|output| !outputs.contains(*output))
+        {
+            bail!(
+                "output `{}/{output}` has a declared type, but is not declared in the \
+                `outputs` of the node",
+                node.id
+            );
+        }
+    }
+
+    // check that all exposed outputs exist
+    for (name, mapping) in dataflow.resolve_exposed_outputs()? {
+        check_input_mapping(
+            &InputMapping::User(mapping),
+            &nodes,
+            &dataflow.external_inputs,
+            &format!("expose/{name}"),
+        )?;
+    }
+
+    // check that the clock source exists
+    if let Some(mapping) = dataflow.resolve_clock_source()? {
+        check_input_mapping(
+            &InputMapping::User(mapping),
+            &nodes,
+            &dataflow.external_inputs,
+            "clock/source",
+        )?;
+    }
+
     // Check that nodes can resolve `send_stdout_as`
     for node in &nodes {
         node.send_stdout_as()
             .context("Could not resolve `send_stdout_as` configuration")?;
     }
 
-    if has_python_operator {
+    // Check the configuration of raw nodes
+    for node in &nodes {
+        if let CoreNodeKind::Custom(custom) = &node.kind {
+            custom
+                .check_raw_config()
+                .wrap_err_with(|| format!("invalid raw node configuration of `{}`", node.id))?;
+        }
+    }
+
+    if has_python_operator && working_dir.is_some() {
         check_python_runtime()?;
     }
 
     Ok(())
 }
 
+/// Collects the warnings about the given dataflow that [`check_dataflow`]
+/// logs, without validating it.
+pub fn dataflow_warnings(dataflow: &Descriptor) -> eyre::Result<Vec<String>> {
+    let mut nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    descriptor::expand_wildcard_inputs(&mut nodes);
+    Ok(nodes.iter().flat_map(node_warnings).collect())
+}
+
+fn node_warnings(node: &super::ResolvedNode) -> Vec<String> {
+    let mut warnings = timer_warnings(node);
+    if node.detached() {
+        warnings.push(format!(
+            "node `{}` has no inputs and no outputs, so it runs detached: the \
+            dataflow doesn't wait for it to connect to dora and it is finished \
+            when its process exits",
+            node.id
+        ));
+    }
+    warnings
+}
+
+/// Warns about timer intervals that the daemon is unlikely to honor and about
+/// intervals that look identical but create separate timers.
+fn timer_warnings(node: &super::ResolvedNode) -> Vec<String> {
+    let inputs: Vec<_> = match &node.kind {
+        CoreNodeKind::Custom(custom) => custom.run_config.inputs.values().collect(),
+        CoreNodeKind::Runtime(runtime) => runtime
+            .operators
+            .iter()
+            .flat_map(|o| o.config.inputs.values())
+            .collect(),
+    };
+    let intervals: BTreeSet<Duration> = inputs
+        .into_iter()
+        .flat_map(|input| input.mappings())
+        .filter_map(|mapping| match mapping {
+            InputMapping::Timer { interval } => Some(*interval),
+            _ => None,
+        })
+        .collect();
+
+    let mut warnings = Vec::new();
+    for interval in &intervals {
+        if *interval < Duration::from_millis(1) {
+            warnings.push(format!(
+                "timer `dora/timer/{}` of node `{}` is shorter than 1ms, which the \
+                daemon might not be able to keep up with",
+                format_duration(*interval),
+                node.id
+            ));
+        }
+    }
+    let rounded_millis = |d: &Duration| (d.as_nanos() + 500_000) / 1_000_000;
+    for (a, b) in intervals.iter().zip(intervals.iter().skip(1)) {
+        if rounded_millis(a) == rounded_millis(b) {
+            warnings.push(format!(
+                "timers `dora/timer/{}` and `dora/timer/{}` of node `{}` differ only \
+                by rounding, but they are separate timers that tick independently",
+                format_duration(*a),
+                format_duration(*b),
+                node.id
+            ));
+        }
+    }
+    warnings
+}
+
 fn check_input(
     input: &Input,
     nodes: &[super::ResolvedNode],
+    external_inputs: &BTreeMap<String, String>,
     input_id_str: &str,
 ) -> Result<(), eyre::ErrReport> {
-    match &input.mapping {
-        InputMapping::Timer { interval: _ } => {}
+    for mapping in input.mappings() {
+        check_input_mapping(mapping, nodes, external_inputs, input_id_str)?;
+    }
+    check_input_options(input, input_id_str)
+}
+
+/// Checks that the options of the given input can be combined with each
+/// other and with its sources.
+pub fn check_input_options(input: &Input, input_id_str: &str) -> eyre::Result<()> {
+    for mapping in input.mappings() {
+        if input.timeout.is_some() && matches!(mapping, InputMapping::Timer { .. }) {
+            bail!("input `{input_id_str}` has a `timeout`, which is not supported for timers");
+        }
+        if input.adaptive.is_some() && matches!(mapping, InputMapping::Timer { .. }) {
+            bail!("input `{input_id_str}` is `adaptive`, which is not supported for timers");
+        }
+        if input.batch.is_some() && matches!(mapping, InputMapping::Timer { .. }) {
+            bail!("input `{input_id_str}` has a `batch`, which is not supported for timers");
+        }
+    }
+    if let Some((batch, queue_size)) = input.batch.as_ref().zip(input.queue_size) {
+        if queue_size < batch.max.get() {
+            bail!(
+                "`queue_size` of input `{input_id_str}` is smaller than its `batch` size, so \
+                the queue would drop messages of every full batch"
+            );
+        }
+    }
+    if input.batch.is_some() && input.latest {
+        bail!(
+            "input `{input_id_str}` has a `batch` and is `latest`, but `latest` inputs only \
+            deliver a single message"
+        );
+    }
+    if input.adaptive.is_some() && input.latest {
+        bail!(
+            "input `{input_id_str}` is `adaptive` and `latest`, but the queue of `latest` \
+            inputs never overflows"
+        );
+    }
+    if input.timeout.is_some_and(|timeout| timeout.is_zero()) {
+        bail!("`timeout` of input `{input_id_str}` must not be zero");
+    }
+    Ok(())
+}
+
+fn check_input_mapping(
+    mapping: &InputMapping,
+    nodes: &[super::ResolvedNode],
+    external_inputs: &BTreeMap<String, String>,
+    input_id_str: &str,
+) -> Result<(), eyre::ErrReport> {
+    match mapping {
+        InputMapping::Timer { interval: _ } | InputMapping::DropEvents => {}
+        InputMapping::External { name } => {
+            if !external_inputs.contains_key(name) {
+                bail!(
+                    "external input `{name}` mapped to input `{input_id_str}` is not \
+                    declared in the `external_inputs` section",
+                );
+            }
+        }
         InputMapping::User(UserInputMapping { source, output }) => {
             let source_node = nodes.iter().find(|n| &n.id == source).ok_or_else(|| {
                 eyre!("source node `{source}` mapped to input `{input_id_str}` does not exist",)
@@ -188,3 +430,189 @@ assert dora.__version__=='{VERSION}',  'Python dora-rs should be {VERSION}, but
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::descriptor::Descriptor;
+
+    fn check(yaml: &str) -> eyre::Result<()> {
+        Descriptor::parse(yaml.as_bytes().to_vec())?.check_without_paths()
+    }
+
+    #[test]
+    fn reserved_inputs_are_rejected() {
+        let err = check(
+            r#"
+            nodes:
+              - id: camera
+                path: camera.py
+                outputs:
+                  - image
+              - id: supervisor
+                path: supervisor.py
+                inputs:
+                  dora/lifecycle: camera/image
+            "#,
+        )
+        .unwrap_err();
+        assert!(format!("{err:?}").contains("reserved"), "{err:?}");
+
+        check(
+            r#"
+            nodes:
+              - id: supervisor
+                path: supervisor.py
+                subscribe_lifecycle: true
+                inputs:
+                  tick: dora/timer/secs/1
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn drop_events_input_is_added_to_target_node() {
+        let yaml = r#"
+            nodes:
+              - id: camera
+                path: camera.py
+                outputs:
+                  - image
+              - id: supervisor
+                path: supervisor.py
+                inputs:
+                  image: camera/image
+            drop_events: supervisor/drops
+            "#;
+        check(yaml).unwrap();
+        let descriptor = Descriptor::parse(yaml.as_bytes().to_vec()).unwrap();
+        let supervisor = descriptor
+            .resolve_aliases_and_set_defaults()
+            .unwrap()
+            .into_iter()
+            .find(|n| n.id.as_ref() == "supervisor")
+            .unwrap();
+        let inputs = supervisor.kind.run_config().inputs;
+        assert_eq!(
+            inputs[&"drops".to_owned().into()].mapping.to_string(),
+            "dora/drop_events"
+        );
+
+        let err = check(&yaml.replace("image: camera/image", "drops: camera/image")).unwrap_err();
+        assert!(format!("{err:?}").contains("already defined"), "{err:?}");
+    }
+
+    #[test]
+    fn input_timeouts_are_parsed_and_checked() {
+        let yaml = r#"
+            nodes:
+              - id: planner
+                path: planner.py
+                outputs:
+                  - cmd
+              - id: robot
+                path: robot.py
+                inputs:
+                  cmd:
+                    source: planner/cmd
+                    timeout: 200ms
+            "#;
+        check(yaml).unwrap();
+        let robot = Descriptor::parse(yaml.as_bytes().to_vec())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap()
+            .into_iter()
+            .find(|n| n.id.as_ref() == "robot")
+            .unwrap();
+        assert_eq!(
+            robot.kind.run_config().inputs[&"cmd".to_owned().into()].timeout,
+            Some(std::time::Duration::from_millis(200))
+        );
+
+        let err = check(&yaml.replace("planner/cmd", "dora/timer/millis/10")).unwrap_err();
+        assert!(
+            format!("{err:?}").contains("not supported for timers"),
+            "{err:?}"
+        );
+        let err = check(&yaml.replace("200ms", "0s")).unwrap_err();
+        assert!(format!("{err:?}").contains("must not be zero"), "{err:?}");
+    }
+
+    #[test]
+    fn input_batches_are_parsed_and_checked() {
+        let yaml = r#"
+            nodes:
+              - id: imu
+                path: imu.py
+                outputs:
+                  - sample
+              - id: filter
+                path: filter.py
+                inputs:
+                  sample:
+                    source: imu/sample
+                    batch: { max: 64, max_delay: 2ms }
+            "#;
+        check(yaml).unwrap();
+        let filter = Descriptor::parse(yaml.as_bytes().to_vec())
+            .unwrap()
+            .resolve_aliases_and_set_defaults()
+            .unwrap()
+            .into_iter()
+            .find(|n| n.id.as_ref() == "filter")
+            .unwrap();
+        let run_config = filter.kind.run_config();
+        let input = &run_config.inputs[&"sample".to_owned().into()];
+        let batch = input.batch.clone().unwrap();
+        assert_eq!(batch.max.get(), 64);
+        assert_eq!(batch.max_delay, std::time::Duration::from_millis(2));
+        // the default queue holds a full batch
+        assert_eq!(input.queue_size_or_default(), 64);
+
+        let err = check(&yaml.replace("imu/sample", "dora/timer/millis/10")).unwrap_err();
+        assert!(
+            format!("{err:?}").contains("not supported for timers"),
+            "{err:?}"
+        );
+        let err =
+            check(&yaml.replace("batch:", "latest: true\n                    batch:")).unwrap_err();
+        assert!(format!("{err:?}").contains("`latest`"), "{err:?}");
+        let err = check(&yaml.replace("batch:", "queue_size: 10\n                    batch:"))
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("`queue_size`"), "{err:?}");
+        assert!(Descriptor::parse(yaml.replace("max: 64", "max: 0").into_bytes()).is_err());
+        assert!(Descriptor::parse(yaml.replace("2ms", "2").into_bytes()).is_err());
+    }
+
+    #[test]
+    fn nodes_without_inputs_and_outputs_are_detached() {
+        let yaml = r#"
+            nodes:
+              - id: recorder
+                path: sleep
+                args: "1"
+              - id: camera
+                path: camera.py
+                outputs:
+                  - image
+            "#;
+        check(yaml).unwrap();
+        let descriptor = Descriptor::parse(yaml.as_bytes().to_vec()).unwrap();
+        let detached: Vec<_> = descriptor
+            .resolve_aliases_and_set_defaults()
+            .unwrap()
+            .into_iter()
+            .filter(|n| n.detached())
+            .map(|n| n.id.to_string())
+            .collect();
+        assert_eq!(detached, ["recorder"]);
+        let warnings = super::dataflow_warnings(&descriptor).unwrap();
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.contains("`recorder`") && w.contains("detached")),
+            "{warnings:?}"
+        );
+    }
+}
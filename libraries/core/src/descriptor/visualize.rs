@@ -55,9 +55,9 @@ fn collect_dora_nodes(
     values: std::collections::btree_map::Values<DataId, Input>,
     dora_timers: &mut BTreeSet<Duration>,
 ) {
-    for input in values {
-        match &input.mapping {
-            InputMapping::User(_) => {}
+    for mapping in values.flat_map(|input| input.mappings()) {
+        match mapping {
+            InputMapping::User(_) | InputMapping::External { .. } | InputMapping::DropEvents => {}
             InputMapping::Timer { interval } => {
                 dora_timers.insert(*interval);
             }
@@ -154,9 +154,14 @@ fn visualize_inputs(
     flowchart: &mut String,
     nodes: &HashMap<&NodeId, &ResolvedNode>,
 ) {
-    for (input_id, input) in inputs {
-        match &input.mapping {
-            mapping @ InputMapping::Timer { .. } => {
+    for (input_id, mapping) in inputs
+        .iter()
+        .flat_map(|(id, input)| input.mappings().map(move |m| (id, m)))
+    {
+        match mapping {
+            mapping @ (InputMapping::Timer { .. }
+            | InputMapping::External { .. }
+            | InputMapping::DropEvents) => {
                 writeln!(flowchart, "  {} -- {input_id} --> {target}", mapping).unwrap();
             }
             InputMapping::User(mapping) => {
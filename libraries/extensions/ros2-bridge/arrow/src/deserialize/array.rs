@@ -1,7 +1,7 @@
 use arrow::array::ArrayData;
 use dora_ros2_bridge_msg_gen::types::sequences;
 
-use crate::typed::TypeInfo;
+use crate::TypeInfo;
 
 use super::sequence::SequenceVisitor;
 
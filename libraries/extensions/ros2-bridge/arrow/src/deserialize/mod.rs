@@ -119,8 +119,12 @@ impl<'a, 'de> serde::de::Visitor<'de> for StructVisitor<'a> {
                             dora_ros2_bridge_msg_gen::types::primitives::GenericString::String | dora_ros2_bridge_msg_gen::types::primitives::GenericString::BoundedString(_)=> {
                                 data.next_element_seed(string::StringDeserializer)?
                             },
-                            dora_ros2_bridge_msg_gen::types::primitives::GenericString::WString => todo!("deserialize WString"),
-                            dora_ros2_bridge_msg_gen::types::primitives::GenericString::BoundedWString(_) => todo!("deserialize BoundedWString"),
+                            dora_ros2_bridge_msg_gen::types::primitives::GenericString::WString | dora_ros2_bridge_msg_gen::types::primitives::GenericString::BoundedWString(_) => {
+                                return Err(error(format!(
+                                    "struct field {} is a wide string, which is not supported yet",
+                                    member.name
+                                )));
+                            }
                         }
                     }
                 },
@@ -10,7 +10,7 @@ use dora_ros2_bridge_msg_gen::types::primitives::{self, BasicType, NestableType}
 use serde::Deserialize;
 use std::{borrow::Cow, ops::Deref, sync::Arc};
 
-use crate::typed::TypeInfo;
+use crate::TypeInfo;
 
 use super::{error, StructDeserializer};
 
@@ -110,9 +110,9 @@ impl<'de> serde::de::Visitor<'de> for SequenceVisitor<'_> {
                     list.append(true);
                     Ok(list.finish().into())
                 }
-                primitives::GenericString::WString => todo!("deserialize sequence of WString"),
-                primitives::GenericString::BoundedWString(_) => {
-                    todo!("deserialize sequence of BoundedWString")
+                primitives::GenericString::WString
+                | primitives::GenericString::BoundedWString(_) => {
+                    Err(error("sequences of wide strings are not supported yet"))
                 }
             },
         }
@@ -0,0 +1,74 @@
+//! Conversion between Arrow arrays and ROS2 messages.
+//!
+//! The message definitions are parsed at runtime (see [`load_messages`]), so
+//! messages of any type can be (de)serialized without generated code.
+//! [`TypedValue`] serializes an Arrow struct array as the given message type
+//! and [`deserialize::StructDeserializer`] deserializes a message into an
+//! Arrow struct array.
+
+use dora_ros2_bridge_msg_gen::types::Message;
+use eyre::{eyre, Context};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub use serialize::TypedValue;
+
+pub mod deserialize;
+pub mod serialize;
+
+/// Message definitions by package name and message name.
+pub type Messages = Arc<HashMap<String, HashMap<String, Message>>>;
+
+#[derive(Debug, Clone)]
+pub struct TypeInfo<'a> {
+    pub package_name: Cow<'a, str>,
+    pub message_name: Cow<'a, str>,
+    pub messages: Messages,
+}
+
+/// Parses the message definitions of the given ROS2 installation paths.
+///
+/// The folder structure of each path should be the following:
+///
+/// - For messages: <namespace>/msg/<name>.msg
+/// - For services: <namespace>/srv/<name>.srv
+pub fn load_messages(paths: &[&Path]) -> eyre::Result<Messages> {
+    let packages = dora_ros2_bridge_msg_gen::get_packages(paths)
+        .map_err(|err| eyre!(err))
+        .context("failed to parse ROS2 message types")?;
+
+    let mut messages = HashMap::new();
+    for message in packages.into_iter().flat_map(|p| p.messages.into_iter()) {
+        let entry: &mut HashMap<String, Message> =
+            messages.entry(message.package.clone()).or_default();
+        entry.insert(message.name.clone(), message);
+    }
+    Ok(Arc::new(messages))
+}
+
+/// The paths of the sourced ROS2 installation, as given through the
+/// `AMENT_PREFIX_PATH` environment variable.
+pub fn ament_prefix_paths() -> eyre::Result<Vec<PathBuf>> {
+    match std::env::var("AMENT_PREFIX_PATH") {
+        Ok(path) => Ok(path.split(':').map(PathBuf::from).collect()),
+        Err(std::env::VarError::NotPresent) => Ok(Vec::new()),
+        Err(std::env::VarError::NotUnicode(s)) => eyre::bail!(
+            "AMENT_PREFIX_PATH is not valid unicode: `{}`",
+            s.to_string_lossy()
+        ),
+    }
+}
+
+/// Serde requires that struct and field names are known at
+/// compile time with a `'static` lifetime, which is not
+/// possible in this case. Thus, we need to use dummy names
+/// instead.
+///
+/// The actual names do not really matter because
+/// the CDR format of ROS2 does not encode struct or field
+/// names.
+const DUMMY_STRUCT_NAME: &str = "struct";
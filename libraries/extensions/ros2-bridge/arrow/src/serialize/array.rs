@@ -10,7 +10,7 @@ use dora_ros2_bridge_msg_gen::types::{
 };
 use serde::ser::SerializeTuple;
 
-use crate::typed::TypeInfo;
+use crate::TypeInfo;
 
 use super::{error, TypedValue};
 
@@ -118,7 +118,7 @@ impl serde::Serialize for ArraySerializeWrapper<'_> {
                     let row = array.slice(i, 1);
                     seq.serialize_element(&TypedValue {
                         value: &(Arc::new(row) as ArrayRef),
-                        type_info: &crate::typed::TypeInfo {
+                        type_info: &crate::TypeInfo {
                             package_name: Cow::Borrowed(&self.type_info.package_name),
                             message_name: Cow::Borrowed(&name.0),
                             messages: self.type_info.messages.clone(),
@@ -142,7 +142,7 @@ impl serde::Serialize for ArraySerializeWrapper<'_> {
                     let row = array.slice(i, 1);
                     seq.serialize_element(&TypedValue {
                         value: &(Arc::new(row) as ArrayRef),
-                        type_info: &crate::typed::TypeInfo {
+                        type_info: &crate::TypeInfo {
                             package_name: Cow::Borrowed(&reference.package),
                             message_name: Cow::Borrowed(&reference.name),
                             messages: self.type_info.messages.clone(),
@@ -165,10 +165,9 @@ impl serde::Serialize for ArraySerializeWrapper<'_> {
                         }
                     }
                 }
-                GenericString::WString => {
-                    todo!("serializing WString sequences")
+                GenericString::WString | GenericString::BoundedWString(_) => {
+                    Err(error("arrays of wide strings are not supported yet"))
                 }
-                GenericString::BoundedWString(_) => todo!("serializing BoundedWString sequences"),
             },
         }
     }
@@ -160,7 +160,7 @@ fn preset_default_for_basic_type(t: &NestableType, preset: &str) -> Result<Array
             .into(),
         },
         NestableType::GenericString(_) => StringArray::from(vec![preset]).into(),
-        _ => todo!("preset_default_for_basic_type (other)"),
+        other => eyre::bail!("default values are not supported for type {other:?}"),
     })
 }
 
@@ -164,9 +164,11 @@ impl<'a> TypedValue<'a> {
                         };
                         s.serialize_field(string)?;
                     }
-                    GenericString::WString => todo!("serializing WString types"),
-                    GenericString::BoundedWString(_) => {
-                        todo!("serializing BoundedWString types")
+                    GenericString::WString | GenericString::BoundedWString(_) => {
+                        return Err(error(format!(
+                            "struct field {} is a wide string, which is not supported yet",
+                            field.name
+                        )));
                     }
                 },
             },
@@ -7,7 +7,7 @@ use arrow::{
 use dora_ros2_bridge_msg_gen::types::primitives::{BasicType, GenericString, NestableType};
 use serde::ser::{SerializeSeq, SerializeTuple};
 
-use crate::typed::TypeInfo;
+use crate::TypeInfo;
 
 use super::{error, TypedValue};
 
@@ -106,7 +106,7 @@ impl serde::Serialize for SequenceSerializeWrapper<'_> {
                     let row = array.slice(i, 1);
                     seq.serialize_element(&TypedValue {
                         value: &(Arc::new(row) as ArrayRef),
-                        type_info: &crate::typed::TypeInfo {
+                        type_info: &crate::TypeInfo {
                             package_name: Cow::Borrowed(&self.type_info.package_name),
                             message_name: Cow::Borrowed(&name.0),
                             messages: self.type_info.messages.clone(),
@@ -130,7 +130,7 @@ impl serde::Serialize for SequenceSerializeWrapper<'_> {
                     let row = array.slice(i, 1);
                     seq.serialize_element(&TypedValue {
                         value: &(Arc::new(row) as ArrayRef),
-                        type_info: &crate::typed::TypeInfo {
+                        type_info: &crate::TypeInfo {
                             package_name: Cow::Borrowed(&reference.package),
                             message_name: Cow::Borrowed(&reference.name),
                             messages: self.type_info.messages.clone(),
@@ -151,10 +151,9 @@ impl serde::Serialize for SequenceSerializeWrapper<'_> {
                         }
                     }
                 }
-                GenericString::WString => {
-                    todo!("serializing WString sequences")
+                GenericString::WString | GenericString::BoundedWString(_) => {
+                    Err(error("sequences of wide strings are not supported yet"))
                 }
-                GenericString::BoundedWString(_) => todo!("serializing BoundedWString sequences"),
             },
         }
     }
@@ -1,8 +1,6 @@
 use std::{
     borrow::Cow,
-    collections::HashMap,
     path::{Path, PathBuf},
-    sync::Arc,
 };
 
 use ::dora_ros2_bridge::{ros2_client, rustdds};
@@ -10,7 +8,6 @@ use arrow::{
     array::{make_array, ArrayData},
     pyarrow::{FromPyArrow, ToPyArrow},
 };
-use dora_ros2_bridge_msg_gen::types::Message;
 use eyre::{eyre, Context, ContextCompat, Result};
 use futures::{Stream, StreamExt};
 use pyo3::{
@@ -19,10 +16,11 @@ use pyo3::{
     Bound, PyAny, PyObject, PyResult, Python,
 };
 use pyo3_special_method_derive::{Dict, Dir, Repr, Str};
-use typed::{deserialize::StructDeserializer, TypeInfo, TypedValue};
+use typed::{deserialize::StructDeserializer, Messages, TypeInfo, TypedValue};
+
+pub use dora_ros2_bridge_arrow as typed;
 
 pub mod qos;
-pub mod typed;
 
 /// ROS2 Context holding all messages definition for receiving and sending messages to ROS2.
 ///
@@ -49,7 +47,7 @@ pub mod typed;
 #[derive(Str, Repr, Dir, Dict)]
 pub struct Ros2Context {
     context: ros2_client::Context,
-    messages: Arc<HashMap<String, HashMap<String, Message>>>,
+    messages: Messages,
 }
 
 #[pymethods]
@@ -67,41 +65,15 @@ impl Ros2Context {
             .wrap_err("failed to call `warnings.warn` module")?;
             Ok(())
         })?;
-        let ament_prefix_path = std::env::var("AMENT_PREFIX_PATH");
-        let empty = String::new();
-
-        let paths: Vec<_> = match &ros_paths {
-            Some(paths) => paths.iter().map(|p| p.as_path()).collect(),
-            None => {
-                let ament_prefix_path_parsed = match &ament_prefix_path {
-                    Ok(path) => path,
-                    Err(std::env::VarError::NotPresent) => &empty,
-                    Err(std::env::VarError::NotUnicode(s)) => {
-                        eyre::bail!(
-                            "AMENT_PREFIX_PATH is not valid unicode: `{}`",
-                            s.to_string_lossy()
-                        );
-                    }
-                };
-
-                ament_prefix_path_parsed.split(':').map(Path::new).collect()
-            }
+        let ros_paths = match ros_paths {
+            Some(paths) => paths,
+            None => typed::ament_prefix_paths()?,
         };
-
-        let packages = dora_ros2_bridge_msg_gen::get_packages(&paths)
-            .map_err(|err| eyre!(err))
-            .context("failed to parse ROS2 message types")?;
-
-        let mut messages = HashMap::new();
-        for message in packages.into_iter().flat_map(|p| p.messages.into_iter()) {
-            let entry: &mut HashMap<String, Message> =
-                messages.entry(message.package.clone()).or_default();
-            entry.insert(message.name.clone(), message);
-        }
+        let paths: Vec<&Path> = ros_paths.iter().map(|p| p.as_path()).collect();
 
         Ok(Self {
             context: ros2_client::Context::new()?,
-            messages: Arc::new(messages),
+            messages: typed::load_messages(&paths)?,
         })
     }
 
@@ -153,7 +125,7 @@ impl Ros2Context {
 #[derive(Str, Repr, Dir, Dict)]
 pub struct Ros2Node {
     node: ros2_client::Node,
-    messages: Arc<HashMap<String, HashMap<String, Message>>>,
+    messages: Messages,
 }
 
 #[pymethods]
@@ -454,3 +426,101 @@ pub fn create_dora_ros2_bridge_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::typed::deserialize::StructDeserializer;
+    use crate::typed::serialize;
+    use crate::typed::TypeInfo;
+    use crate::Ros2Context;
+
+    use arrow::array::make_array;
+    use arrow::pyarrow::FromPyArrow;
+    use arrow::pyarrow::ToPyArrow;
+
+    use pyo3::types::IntoPyDict;
+    use pyo3::types::PyAnyMethods;
+    use pyo3::types::PyDict;
+    use pyo3::types::PyList;
+    use pyo3::types::PyModule;
+    use pyo3::types::PyTuple;
+    use pyo3::PyNativeType;
+    use pyo3::Python;
+    use serde::de::DeserializeSeed;
+    use serde::Serialize;
+
+    use serde_assert::Serializer;
+    use serialize::TypedValue;
+
+    use eyre::{Context, Result};
+    use serde_assert::Deserializer;
+    #[test]
+    fn test_python_array_code() -> Result<()> {
+        pyo3::prepare_freethreaded_python();
+        let context = Ros2Context::new(None).context("Could not create a context")?;
+        let messages = context.messages.clone();
+        let serializer = Serializer::builder().build();
+
+        Python::with_gil(|py| -> Result<()> {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")); //.join("test_utils.py"); // Adjust this path as needed
+
+            // Add the Python module's directory to sys.path
+            py.run_bound(
+                "import sys; sys.path.append(str(path))",
+                Some(&[("path", path)].into_py_dict_bound(py)),
+                None,
+            )?;
+
+            let my_module = PyModule::import_bound(py, "test_utils")?;
+
+            let arrays: &PyList = my_module.getattr("TEST_ARRAYS")?.extract()?;
+            for array_wrapper in arrays.iter() {
+                let arrays: &PyTuple = array_wrapper.extract()?;
+                let package_name: String = arrays.get_item(0)?.extract()?;
+                let message_name: String = arrays.get_item(1)?.extract()?;
+                println!("Checking {}::{}", package_name, message_name);
+                let in_pyarrow = arrays.get_item(2)?;
+
+                let array = arrow::array::ArrayData::from_pyarrow_bound(&in_pyarrow.as_borrowed())?;
+                let type_info = TypeInfo {
+                    package_name: package_name.into(),
+                    message_name: message_name.clone().into(),
+                    messages: messages.clone(),
+                };
+                let typed_value = TypedValue {
+                    value: &make_array(array.clone()),
+                    type_info: &type_info.clone(),
+                };
+
+                let typed_deserializer =
+                    StructDeserializer::new(std::borrow::Cow::Owned(type_info));
+                let tokens = typed_value.serialize(&serializer)?;
+                let mut deserializer = Deserializer::builder(tokens).build();
+
+                let out_value = typed_deserializer
+                    .deserialize(&mut deserializer)
+                    .context("could not deserialize array")?;
+
+                let out_pyarrow = out_value.to_pyarrow(py)?;
+
+                let test_utils = PyModule::import_bound(py, "test_utils")?;
+                let context = PyDict::new_bound(py);
+
+                context.set_item("test_utils", test_utils)?;
+                context.set_item("in_pyarrow", in_pyarrow)?;
+                context.set_item("out_pyarrow", out_pyarrow)?;
+
+                let _ = py
+                    .eval_bound(
+                        "test_utils.is_subset(in_pyarrow, out_pyarrow)",
+                        Some(&context),
+                        None,
+                    )
+                    .context("could not check if it is a subset")?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -3,31 +3,40 @@
 //! This module init a tracing propagator for Rust code that requires tracing, and is
 //! able to serialize and deserialize context that has been sent via the middleware.
 
-use std::path::Path;
+use std::{path::Path, sync::OnceLock};
 
 use eyre::Context as EyreContext;
 use tracing::metadata::LevelFilter;
 use tracing_subscriber::{
-    filter::FilterExt, prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Layer,
+    filter::FilterExt, prelude::__tracing_subscriber_SubscriberExt, reload, EnvFilter, Layer,
 };
 
 use eyre::ContextCompat;
+pub use recent::{recent_warnings, RECENT_WARNINGS_LIMIT};
 use tracing_subscriber::Registry;
+mod recent;
 pub mod telemetry;
 
+/// Reload handles for the filters of the stdout and file outputs.
+static LOG_FILTERS: OnceLock<Vec<reload::Handle<EnvFilter, Registry>>> = OnceLock::new();
+
 pub fn set_up_tracing(name: &str) -> eyre::Result<()> {
     set_up_tracing_opts(name, true, None)
 }
 
 pub fn set_up_tracing_opts(name: &str, stdout: bool, filename: Option<&str>) -> eyre::Result<()> {
-    let mut layers = Vec::new();
+    let mut layers = vec![recent::RecentWarningsLayer
+        .with_filter(LevelFilter::WARN)
+        .boxed()];
+    let mut filter_handles = Vec::new();
 
     if stdout {
         // Filter log using `RUST_LOG`. More useful for CLI.
-        let env_filter = EnvFilter::from_default_env().or(LevelFilter::WARN);
+        let (env_filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+        filter_handles.push(handle);
         let layer = tracing_subscriber::fmt::layer()
             .compact()
-            .with_filter(env_filter);
+            .with_filter(env_filter.or(LevelFilter::WARN));
         layers.push(layer.boxed());
     }
 
@@ -40,11 +49,12 @@ pub fn set_up_tracing_opts(name: &str, stdout: bool, filename: Option<&str>) ->
             .append(true)
             .open(path)
             .context("failed to create log file")?;
-        // Filter log using `RUST_LOG`. More useful for CLI.
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        filter_handles.push(handle);
         let layer = tracing_subscriber::fmt::layer()
             .with_ansi(false)
             .with_writer(file)
-            .with_filter(LevelFilter::INFO);
+            .with_filter(filter);
         layers.push(layer.boxed());
     }
 
@@ -61,5 +71,62 @@ pub fn set_up_tracing_opts(name: &str, stdout: bool, filename: Option<&str>) ->
     let registry = Registry::default().with(layers);
     tracing::subscriber::set_global_default(registry).context(format!(
         "failed to set tracing global subscriber for {name}"
-    ))
+    ))?;
+    let _ = LOG_FILTERS.set(filter_handles);
+    Ok(())
+}
+
+/// Replaces the log filter of the stdout and file outputs at runtime.
+///
+/// The filter uses the same syntax as `RUST_LOG`, e.g.
+/// `info,dora_daemon=trace`. Warnings are always printed to stdout.
+///
+/// Returns the previously active filter of the first output. Invalid filters
+/// are rejected without changing the current filter.
+pub fn set_log_filter(filter: &str) -> eyre::Result<String> {
+    let handles = LOG_FILTERS
+        .get()
+        .filter(|handles| !handles.is_empty())
+        .context("no reloadable log output was set up")?;
+    reload_filters(handles, filter)
+}
+
+fn reload_filters(
+    handles: &[reload::Handle<EnvFilter, Registry>],
+    filter: &str,
+) -> eyre::Result<String> {
+    // validate the filter before modifying any output
+    EnvFilter::try_new(filter).with_context(|| format!("invalid log filter `{filter}`"))?;
+
+    let previous = handles[0]
+        .with_current(|current| current.to_string())
+        .context("failed to read current log filter")?;
+    for handle in handles {
+        let new = EnvFilter::try_new(filter)?;
+        handle.reload(new).context("failed to replace log filter")?;
+    }
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_log_filters_are_rejected() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(filter);
+        let handles = [handle];
+        let current = || handles[0].with_current(|f| f.to_string()).unwrap();
+
+        let err = reload_filters(&handles, "dora_daemon=loud").unwrap_err();
+        assert!(format!("{err:?}").contains("invalid log filter"), "{err:?}");
+        assert_eq!(current(), "info");
+
+        assert_eq!(
+            reload_filters(&handles, "dora_daemon=trace").unwrap(),
+            "info"
+        );
+        assert_eq!(current(), "dora_daemon=trace");
+    }
 }
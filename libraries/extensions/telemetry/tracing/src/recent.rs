@@ -0,0 +1,67 @@
+//! Keeps the most recent warnings and errors in memory, so that they can be
+//! retrieved from a running process, e.g. for a diagnostics dump.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{field::Field, Event, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Number of warnings that are kept by [`recent_warnings`].
+pub const RECENT_WARNINGS_LIMIT: usize = 100;
+
+static RECENT_WARNINGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Returns the most recent warnings and errors, oldest first.
+///
+/// Only events that were logged after [`set_up_tracing`][crate::set_up_tracing]
+/// are recorded.
+pub fn recent_warnings() -> Vec<String> {
+    match RECENT_WARNINGS.lock() {
+        Ok(warnings) => warnings.iter().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Records all events that it receives, to be used with a `WARN` filter.
+pub(crate) struct RecentWarningsLayer;
+
+impl<S: Subscriber> Layer<S> for RecentWarningsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = format!(
+            "{}.{:03} {} {}:",
+            time.as_secs(),
+            time.subsec_millis(),
+            metadata.level(),
+            metadata.target()
+        );
+        event.record(&mut FieldWriter(&mut line));
+
+        if let Ok(mut warnings) = RECENT_WARNINGS.lock() {
+            if warnings.len() >= RECENT_WARNINGS_LIMIT {
+                warnings.pop_front();
+            }
+            warnings.push_back(line);
+        }
+    }
+}
+
+struct FieldWriter<'a>(&'a mut String);
+
+impl tracing::field::Visit for FieldWriter<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = if field.name() == "message" {
+            write!(self.0, " {value:?}")
+        } else {
+            write!(self.0, " {}={value:?}", field.name())
+        };
+    }
+}
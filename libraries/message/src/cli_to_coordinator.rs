@@ -1,25 +1,87 @@
-use std::{path::PathBuf, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 
 use dora_core::{
-    config::{NodeId, OperatorId},
+    config::{DataId, NodeId, OperatorId},
     descriptor::Descriptor,
 };
 use uuid::Uuid;
 
+use crate::reconfigure::InputChange;
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum ControlRequest {
+    /// Authenticates the connection for a coordinator with access control.
+    ///
+    /// Must be sent before any other request on the connection, also on the
+    /// connections of `LogSubscribe`, `EventSubscribe`, and `Tap`. The
+    /// coordinator replies with `Authenticated` or `AccessDenied`.
+    Authenticate {
+        token: String,
+    },
     Start {
         dataflow: Descriptor,
         name: Option<String>,
         // TODO: remove this once we figure out deploying of node/operator
         // binaries from CLI to coordinator/daemon
         local_working_dir: PathBuf,
+        /// Working directories of specific machines, used to resolve relative
+        /// node paths on these machines.
+        ///
+        /// Machines without entry use the `local_working_dir`.
+        #[serde(default)]
+        machine_working_dirs: BTreeMap<String, PathBuf>,
+        /// Starts the dataflow as an instance of the given `name`, so that
+        /// multiple dataflows can run under the same name.
+        #[serde(default)]
+        instance: Option<InstanceKey>,
+        /// Start parameters of the dataflow, which are passed to all nodes.
+        #[serde(default)]
+        params: BTreeMap<String, String>,
+        /// Only validate and resolve the dataflow and reply with the
+        /// `DataflowPlan`, without spawning any nodes.
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Starts a dataflow from the descriptor registry, see `Register`.
+    ///
+    /// The fields are the same as for `Start`.
+    StartRegistered {
+        dataflow: RegistryKey,
+        name: Option<String>,
+        local_working_dir: PathBuf,
+        #[serde(default)]
+        machine_working_dirs: BTreeMap<String, PathBuf>,
+        #[serde(default)]
+        instance: Option<InstanceKey>,
+        #[serde(default)]
+        params: BTreeMap<String, String>,
     },
     Reload {
         dataflow_id: Uuid,
         node_id: NodeId,
         operator_id: Option<OperatorId>,
     },
+    /// Restart a single node of a running dataflow without stopping the others.
+    ReloadNode {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+    },
+    /// Move a node of a running dataflow to another machine without stopping
+    /// the dataflow.
+    ///
+    /// The target machine must run other nodes of the dataflow already.
+    MigrateNode {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        target_machine: String,
+    },
+    /// Change the queue settings of inputs of a running dataflow, see
+    /// [`reconfigure`](crate::reconfigure).
+    Reconfigure {
+        dataflow_id: Uuid,
+        changes: Vec<InputChange>,
+    },
     Check {
         dataflow_uuid: Uuid,
     },
@@ -29,6 +91,9 @@ pub enum ControlRequest {
     },
     StopByName {
         name: String,
+        /// Selects an instance if multiple dataflows run under `name`.
+        #[serde(default)]
+        instance: Option<String>,
         grace_duration: Option<Duration>,
     },
     Logs {
@@ -36,12 +101,197 @@ pub enum ControlRequest {
         name: Option<String>,
         node: String,
     },
+    Descriptor {
+        dataflow_uuid: Uuid,
+    },
+    /// Look up the machine that runs the given node and request a join token
+    /// for it from the daemon of that machine.
+    ///
+    /// The reply is a `NodeResolved`, which external nodes and observers use
+    /// for connecting to the daemon, also from other machines.
+    ResolveNode {
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        node_id: NodeId,
+    },
+    /// Compare the running dataflow with the given (edited) descriptor.
+    Diff {
+        dataflow_uuid: Uuid,
+        dataflow: Descriptor,
+    },
+    /// Store a descriptor in the registry of the coordinator under the
+    /// given name and version.
+    ///
+    /// Without version, the version after the latest registered version of
+    /// `name` is used. Registered versions can't be changed, registering the
+    /// same descriptor again is a no-op.
+    Register {
+        name: String,
+        version: Option<u32>,
+        dataflow: Descriptor,
+        /// Directory of the registered dataflow file, which relative node
+        /// paths are resolved against.
+        working_dir: PathBuf,
+    },
+    ListRegistered,
+    FetchRegistered {
+        dataflow: RegistryKey,
+    },
+    /// Removes the given version, or all versions if the key has no version,
+    /// from the registry. Running dataflows are not affected.
+    DeleteRegistered {
+        dataflow: RegistryKey,
+    },
     Destroy,
     List,
     DaemonConnected,
     ConnectedMachines,
+    /// Request the status of all connected daemons.
+    DaemonStatus,
+    /// Replace the log filter of the daemon on the given machine.
+    SetLogLevel {
+        machine_id: String,
+        filter: String,
+    },
+    /// Request a snapshot of the internal state of the daemon on the given
+    /// machine, see `DaemonCoordinatorEvent::Diagnostics`.
+    Diagnostics {
+        machine_id: String,
+        gc: Option<Duration>,
+    },
     LogSubscribe {
         dataflow_id: Uuid,
         level: log::LevelFilter,
     },
+    /// Receive the lifecycle events of all dataflows and daemons.
+    ///
+    /// Like `LogSubscribe`, this takes over the connection: the coordinator
+    /// sends `CoordinatorEvent`s until the connection is closed.
+    EventSubscribe,
+    /// Receive copies of the messages of a node output for the given duration.
+    ///
+    /// Like `LogSubscribe`, this takes over the connection: the coordinator
+    /// replies with `TapStarted` and then sends `TappedMessage`s until the
+    /// tap expires, at which point the connection is closed.
+    Tap {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output_id: DataId,
+        duration: Duration,
+        /// Maximum number of messages per second.
+        max_rate: Option<f64>,
+    },
+}
+
+impl ControlRequest {
+    /// Role that a connection needs for this request on a coordinator with
+    /// access control.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ControlRequest::Authenticate { .. } => Role::ReadOnly,
+            ControlRequest::Start { dry_run: true, .. } => Role::ReadOnly,
+            ControlRequest::Check { .. }
+            | ControlRequest::Logs { .. }
+            | ControlRequest::Descriptor { .. }
+            | ControlRequest::Diff { .. }
+            | ControlRequest::ListRegistered
+            | ControlRequest::FetchRegistered { .. }
+            | ControlRequest::List
+            | ControlRequest::DaemonConnected
+            | ControlRequest::ConnectedMachines
+            | ControlRequest::DaemonStatus
+            | ControlRequest::LogSubscribe { .. }
+            | ControlRequest::EventSubscribe
+            | ControlRequest::Tap { .. } => Role::ReadOnly,
+            ControlRequest::Start { .. }
+            | ControlRequest::StartRegistered { .. }
+            | ControlRequest::Reload { .. }
+            | ControlRequest::ReloadNode { .. }
+            | ControlRequest::MigrateNode { .. }
+            | ControlRequest::Reconfigure { .. }
+            | ControlRequest::Stop { .. }
+            | ControlRequest::StopByName { .. }
+            | ControlRequest::ResolveNode { .. }
+            | ControlRequest::Register { .. }
+            | ControlRequest::DeleteRegistered { .. } => Role::Operator,
+            ControlRequest::Destroy
+            | ControlRequest::SetLogLevel { .. }
+            | ControlRequest::Diagnostics { .. } => Role::Admin,
+        }
+    }
+}
+
+/// Permissions of a control connection, ordered from least to most
+/// privileged.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Listing and inspecting dataflows, machines, and logs.
+    ReadOnly,
+    /// Starting, stopping, and changing dataflows.
+    Operator,
+    /// Destroying the coordinator and changing or inspecting daemons.
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::ReadOnly => f.write_str("read-only"),
+            Role::Operator => f.write_str("operator"),
+            Role::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+/// Key of a dataflow instance, unique among the running dataflows with the
+/// same name.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum InstanceKey {
+    Explicit(String),
+    /// Use the lowest number that is not used by another running instance.
+    Next,
+}
+
+/// Reference to a descriptor in the registry of the coordinator, written as
+/// `name@version` or `name` for the latest version.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct RegistryKey {
+    pub name: String,
+    /// The latest version if `None`.
+    pub version: Option<u32>,
+}
+
+impl std::str::FromStr for RegistryKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = match s.split_once('@') {
+            Some((name, version)) => {
+                let version = version
+                    .parse()
+                    .map_err(|_| format!("invalid version `{version}` in `{s}`"))?;
+                (name, Some(version))
+            }
+            None => (s, None),
+        };
+        if name.is_empty() {
+            return Err(format!("missing dataflow name in `{s}`"));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            version,
+        })
+    }
+}
+
+impl std::fmt::Display for RegistryKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "{}@{version}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
 }
@@ -1,11 +1,14 @@
 use core::fmt;
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
 
 use aligned_vec::{AVec, ConstAlign};
-use dora_core::{config::NodeId, uhlc};
+use dora_core::{
+    config::{DataId, NodeId},
+    uhlc,
+};
 use uuid::Uuid;
 
-use crate::DataflowId;
+use crate::{metadata::Metadata, DataflowId};
 
 pub use log::Level as LogLevel;
 
@@ -22,6 +25,64 @@ pub struct LogMessage {
     pub message: String,
 }
 
+/// Everything that an external node or observer on another machine needs
+/// for connecting to the daemon that runs a node.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NodeJoinInfo {
+    pub dataflow_id: DataflowId,
+    pub node_id: NodeId,
+    /// Machine of the daemon that runs the node.
+    pub machine_id: String,
+    /// Address of the listener for dynamic nodes and observers of the
+    /// daemon, or `None` if it only accepts connections from its own machine.
+    pub daemon_address: Option<std::net::SocketAddr>,
+    /// Token for joining the dataflow as the node or as an observer of it.
+    pub token: String,
+    /// Time after which the daemon rejects the token, counted from the time
+    /// it was minted.
+    pub valid_for: Duration,
+}
+
+/// Maximum number of payload bytes that are copied into a [`TappedMessage`].
+pub const TAP_PAYLOAD_LIMIT: usize = 1024;
+
+/// Copy of an output message, taken by an active output tap.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TappedMessage {
+    pub dataflow_id: DataflowId,
+    pub node_id: NodeId,
+    pub output_id: DataId,
+    pub metadata: Metadata,
+    /// Size of the full payload in bytes.
+    pub len: usize,
+    /// Start of the payload, at most [`TAP_PAYLOAD_LIMIT`] bytes.
+    pub payload: Vec<u8>,
+}
+
+impl TappedMessage {
+    pub fn new(
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+        metadata: Metadata,
+        data: &[u8],
+    ) -> Self {
+        Self {
+            dataflow_id,
+            node_id,
+            output_id,
+            metadata,
+            len: data.len(),
+            payload: data[..data.len().min(TAP_PAYLOAD_LIMIT)].to_vec(),
+        }
+    }
+
+    /// Whether the payload was cut off at [`TAP_PAYLOAD_LIMIT`].
+    pub fn is_truncated(&self) -> bool {
+        self.payload.len() < self.len
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct NodeError {
     pub timestamp: uhlc::Timestamp,
@@ -63,10 +124,19 @@ impl std::fmt::Display for NodeError {
 
         match &self.cause {
             NodeErrorCause::GraceDuration => {}, // handled above
+            NodeErrorCause::StartupTimeout { timeout } => write!(
+                f,
+                ". The node was killed by dora because it didn't connect to dora within its `ready_timeout` of {:.1}s.",
+                timeout.as_secs_f64()
+            )?,
             NodeErrorCause::Cascading { caused_by_node } => write!(
                 f,
                 ". This error occurred because node `{caused_by_node}` exited before connecting to dora."
             )?,
+            NodeErrorCause::Reported { message } => {
+                let line: &str = "---------------------------------------------------------------------------------\n";
+                write!(f, " after reporting an error:\n{line}{}\n{line}", message.trim_end())?
+            },
             NodeErrorCause::Other { stderr } if stderr.is_empty() => {}
             NodeErrorCause::Other { stderr } => {
                 let line: &str = "---------------------------------------------------------------------------------\n";
@@ -86,6 +156,15 @@ pub enum NodeErrorCause {
     Cascading {
         caused_by_node: NodeId,
     },
+    /// Node was killed because it didn't connect to dora within its
+    /// `ready_timeout`.
+    StartupTimeout {
+        timeout: Duration,
+    },
+    /// Node reported the given error, e.g. a panic, before it exited.
+    Reported {
+        message: String,
+    },
     Other {
         stderr: String,
     },
@@ -132,10 +211,17 @@ pub struct Timestamped<T> {
 
 pub type SharedMemoryId = String;
 
+/// Prefix of the `shared_memory_id` of messages that are backed by a file of
+/// the persistent output cache of the daemon. The prefix is followed by the
+/// path of the file, which receivers map instead of a shared memory region.
+pub const CACHED_FILE_PREFIX: &str = "file:";
+
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub enum DataMessage {
     Vec(AVec<u8, ConstAlign<128>>),
     SharedMemory {
+        /// ID of the shared memory region, or [`CACHED_FILE_PREFIX`] followed
+        /// by the path of a cached file.
         shared_memory_id: String,
         len: usize,
         drop_token: DropToken,
@@ -175,10 +261,47 @@ impl fmt::Debug for DataMessage {
 #[derive(
     Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
 )]
-pub struct DropToken(Uuid);
+pub struct DropToken {
+    /// Run ID of the daemon that the token was generated for, see
+    /// [`DropToken::generate_in`].
+    namespace: Uuid,
+    id: Uuid,
+}
 
 impl DropToken {
+    /// Generates a token without namespace.
+    pub fn generate() -> Self {
+        Self::generate_in(Uuid::nil())
+    }
+
+    /// Generates a token in the namespace of the given daemon run, so that
+    /// tokens of different daemon runs never collide.
+    pub fn generate_in(namespace: Uuid) -> Self {
+        Self {
+            namespace,
+            id: Uuid::new_v7(uuid::Timestamp::now(uuid::NoContext)),
+        }
+    }
+
+    pub fn namespace(&self) -> Uuid {
+        self.namespace
+    }
+}
+
+/// Identifies an output ring of shared memory slots that was prepared by a node.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct OutputRingId(Uuid);
+
+impl OutputRingId {
     pub fn generate() -> Self {
         Self(Uuid::new_v7(uuid::Timestamp::now(uuid::NoContext)))
     }
 }
+
+impl fmt::Display for OutputRingId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -0,0 +1,349 @@
+//! Compatibility rules for the messages between coordinator and daemons.
+//!
+//! The coordinator and its daemons are often not updated at the same time,
+//! so the messages in [`daemon_to_coordinator`](crate::daemon_to_coordinator)
+//! and [`coordinator_to_daemon`](crate::coordinator_to_daemon) need to stay
+//! readable across releases:
+//!
+//! - The registration handshake
+//!   ([`DaemonRegisterRequest`](crate::daemon_to_coordinator::DaemonRegisterRequest) and
+//!   [`RegisterResult`](crate::coordinator_to_daemon::RegisterResult)) is
+//!   frozen. It may only gain fields with a default value.
+//! - [`DaemonRegisterRequest::check_version`](crate::daemon_to_coordinator::DaemonRegisterRequest::check_version)
+//!   rejects daemons whose
+//!   `dora-message` version is not semver-compatible with the coordinator,
+//!   i.e. that differ in the minor version while the crate is at `0.x`.
+//! - Within compatible versions, messages may only gain fields with a default
+//!   value and new variants that are [ignorable](Message::is_ignorable).
+//!   Unknown fields are ignored on deserialization.
+//! - Removing or changing fields, and adding variants that must be
+//!   understood, needs a new minor version of `dora-message`.
+//! - The events between daemons are not covered by these rules. Their
+//!   layout may change with every minor version, and daemons only accept
+//!   connections of compatible daemons, see
+//!   [`InterDaemonHello`](crate::daemon_to_daemon::InterDaemonHello).
+//!
+//! Ignorable messages are sent in an [`Envelope`] that marks them as such, so
+//! that receivers that don't know them skip them instead of failing, see
+//! [`decode`]. Messages that must be understood are sent in the same layout
+//! without the marker, which is also the layout of a [`Timestamped`]
+//! message of older releases.
+//!
+//! Snapshots of the messages of the current release are kept in the
+//! `fixtures` directory of this crate. The tests check that they can still
+//! be decoded, and that the re-encoded messages are still readable by the
+//! release of the snapshot. When releasing a new minor version, add a new
+//! snapshot directory and drop the snapshots of incompatible versions, except
+//! for their handshake messages. These still need to be readable, so that
+//! older daemons are rejected with a version error.
+
+use dora_core::uhlc;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use crate::{
+    common::Timestamped,
+    coordinator_to_daemon::DaemonCoordinatorEvent,
+    daemon_to_coordinator::{CoordinatorRequest, DaemonEvent},
+};
+
+/// A message between coordinator and daemons.
+pub trait Message {
+    /// Whether a receiver that doesn't understand the message may skip it.
+    ///
+    /// Notifications that don't expect a reply and don't change the state of
+    /// a dataflow are ignorable. Requests and state changes must be
+    /// understood, as skipping them would leave the sender waiting or the
+    /// dataflow in an inconsistent state.
+    fn is_ignorable(&self) -> bool;
+}
+
+impl Message for CoordinatorRequest {
+    fn is_ignorable(&self) -> bool {
+        match self {
+            CoordinatorRequest::Register(_) => false,
+            CoordinatorRequest::Event { event, .. } => event.is_ignorable(),
+            // coordinators that don't know the message don't require tokens
+            CoordinatorRequest::AuthenticateEvents { .. } => true,
+        }
+    }
+}
+
+impl Message for DaemonEvent {
+    fn is_ignorable(&self) -> bool {
+        match self {
+            DaemonEvent::Health(_)
+            | DaemonEvent::Log(_)
+            | DaemonEvent::Tapped { .. }
+            | DaemonEvent::DataflowStalled { .. }
+            | DaemonEvent::DataflowRecovered { .. } => true,
+            // the coordinator considers daemons without heartbeats as lost
+            DaemonEvent::Heartbeat
+            | DaemonEvent::AllNodesReady { .. }
+            | DaemonEvent::AllNodesFinished { .. }
+            | DaemonEvent::TapFinished { .. }
+            | DaemonEvent::StopRequested { .. } => false,
+        }
+    }
+}
+
+impl Message for DaemonCoordinatorEvent {
+    fn is_ignorable(&self) -> bool {
+        match self {
+            DaemonCoordinatorEvent::TimeSync(_) => true,
+            // the daemon considers the coordinator as lost without heartbeats
+            DaemonCoordinatorEvent::Heartbeat
+            | DaemonCoordinatorEvent::Spawn(_)
+            | DaemonCoordinatorEvent::AllNodesReady { .. }
+            | DaemonCoordinatorEvent::StopDataflow { .. }
+            | DaemonCoordinatorEvent::ReloadDataflow { .. }
+            | DaemonCoordinatorEvent::ReloadNode { .. }
+            | DaemonCoordinatorEvent::PrepareNodeMigration { .. }
+            | DaemonCoordinatorEvent::SwitchNodeRoutes { .. }
+            | DaemonCoordinatorEvent::StopMigratedNode { .. }
+            | DaemonCoordinatorEvent::FinishNodeMigration { .. }
+            | DaemonCoordinatorEvent::Reconfigure { .. }
+            | DaemonCoordinatorEvent::Logs { .. }
+            | DaemonCoordinatorEvent::GetDescriptor { .. }
+            | DaemonCoordinatorEvent::Destroy
+            | DaemonCoordinatorEvent::Status
+            | DaemonCoordinatorEvent::SetLogLevel { .. }
+            | DaemonCoordinatorEvent::Diagnostics { .. }
+            | DaemonCoordinatorEvent::TapOutput { .. }
+            | DaemonCoordinatorEvent::MintToken { .. } => false,
+        }
+    }
+}
+
+/// A [`Timestamped`] message that is marked if it is ignorable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub inner: T,
+    pub timestamp: uhlc::Timestamp,
+    /// See [`Message::is_ignorable`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignorable: bool,
+}
+
+impl<T: Message> Envelope<T> {
+    pub fn new(inner: T, timestamp: uhlc::Timestamp) -> Self {
+        Self {
+            ignorable: inner.is_ignorable(),
+            inner,
+            timestamp,
+        }
+    }
+}
+
+/// A received message, see [`decode`].
+#[derive(Debug)]
+pub enum Decoded<T> {
+    Message(Timestamped<T>),
+    /// An ignorable message that could not be decoded, e.g. because it was
+    /// added in a newer release.
+    Skipped {
+        timestamp: uhlc::Timestamp,
+        error: serde_json::Error,
+    },
+}
+
+/// Decodes a JSON-serialized [`Envelope`] or [`Timestamped`] message.
+///
+/// Messages that can't be decoded are skipped if they are marked as
+/// ignorable. Otherwise the decoding error is returned.
+pub fn decode<T>(raw: &[u8]) -> serde_json::Result<Decoded<T>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let error = match serde_json::from_slice::<Envelope<T>>(raw) {
+        Ok(Envelope {
+            inner, timestamp, ..
+        }) => return Ok(Decoded::Message(Timestamped { inner, timestamp })),
+        Err(err) => err,
+    };
+    match serde_json::from_slice::<Envelope<IgnoredAny>>(raw) {
+        Ok(Envelope {
+            timestamp,
+            ignorable: true,
+            ..
+        }) => Ok(Decoded::Skipped { timestamp, error }),
+        _ => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coordinator_to_daemon::RegisterResult, daemon_to_coordinator::DaemonCoordinatorReply,
+    };
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+
+    /// Snapshots of the messages of `dora-message` v0.5.
+    mod v0_5 {
+        pub const COORDINATOR_REQUEST: &str =
+            include_str!("../fixtures/v0.5/coordinator_request.json");
+        pub const DAEMON_COORDINATOR_EVENT: &str =
+            include_str!("../fixtures/v0.5/daemon_coordinator_event.json");
+        pub const DAEMON_COORDINATOR_REPLY: &str =
+            include_str!("../fixtures/v0.5/daemon_coordinator_reply.json");
+        pub const REGISTER_RESULT: &str = include_str!("../fixtures/v0.5/register_result.json");
+    }
+
+    /// Handshake messages of `dora-message` v0.4, which are frozen.
+    mod v0_4 {
+        pub const COORDINATOR_REQUEST: &str =
+            include_str!("../fixtures/v0.4/coordinator_request.json");
+        pub const REGISTER_RESULT: &str = include_str!("../fixtures/v0.4/register_result.json");
+    }
+
+    /// Decodes every message of the given snapshot and checks that the
+    /// re-encoded message is still readable by the release of the snapshot.
+    fn check_snapshot<T: Serialize + DeserializeOwned>(snapshot: &str) {
+        let messages: Vec<Value> = serde_json::from_str(snapshot).unwrap();
+        assert!(!messages.is_empty());
+        for old in messages {
+            let message: T = serde_json::from_value(old.clone())
+                .unwrap_or_else(|err| panic!("failed to decode `{old}`: {err}"));
+            let new = serde_json::to_value(&message).unwrap();
+            assert_readable(&old, &new);
+        }
+    }
+
+    /// Checks that `new` contains everything of `old`. Older releases ignore
+    /// the fields that were added since.
+    fn assert_readable(old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Object(old_fields), Value::Object(new_fields)) => {
+                for (key, old_value) in old_fields {
+                    match new_fields.get(key) {
+                        Some(new_value) => assert_readable(old_value, new_value),
+                        None => panic!("field `{key}` is missing in `{new}`"),
+                    }
+                }
+            }
+            (Value::Array(old_items), Value::Array(new_items)) => {
+                assert_eq!(old_items.len(), new_items.len(), "`{old}` became `{new}`");
+                for (old_item, new_item) in old_items.iter().zip(new_items) {
+                    assert_readable(old_item, new_item);
+                }
+            }
+            _ => assert_eq!(old, new, "`{old}` became `{new}`"),
+        }
+    }
+
+    #[test]
+    fn snapshots_are_compatible() {
+        check_snapshot::<CoordinatorRequest>(v0_5::COORDINATOR_REQUEST);
+        check_snapshot::<DaemonCoordinatorEvent>(v0_5::DAEMON_COORDINATOR_EVENT);
+        check_snapshot::<DaemonCoordinatorReply>(v0_5::DAEMON_COORDINATOR_REPLY);
+        check_snapshot::<RegisterResult>(v0_5::REGISTER_RESULT);
+    }
+
+    #[test]
+    fn incompatible_daemons_are_rejected() {
+        // the coordinator can still read the registration of older daemons,
+        // and they can read the rejection
+        check_snapshot::<CoordinatorRequest>(v0_4::COORDINATOR_REQUEST);
+        check_snapshot::<RegisterResult>(v0_4::REGISTER_RESULT);
+
+        let requests: Vec<CoordinatorRequest> =
+            serde_json::from_str(v0_4::COORDINATOR_REQUEST).unwrap();
+        for request in requests {
+            let CoordinatorRequest::Register(register) = request else {
+                panic!("unexpected request {request:?}");
+            };
+            let err = register.check_version().unwrap_err();
+            assert!(err.contains("version mismatch"), "{err}");
+        }
+    }
+
+    #[test]
+    fn unknown_messages_are_skipped_if_ignorable() {
+        let timestamp = uhlc::HLC::default().new_timestamp();
+        let encode = |inner: Value, ignorable: Option<bool>| {
+            let mut message = json!({ "inner": inner, "timestamp": timestamp });
+            if let Some(ignorable) = ignorable {
+                message["ignorable"] = ignorable.into();
+            }
+            serde_json::to_vec(&message).unwrap()
+        };
+        let future = json!({ "Rebalance": { "dataflow_id": uuid::Uuid::nil() } });
+
+        let decoded = decode::<DaemonCoordinatorEvent>(&encode(future.clone(), Some(true)));
+        assert!(
+            matches!(decoded, Ok(Decoded::Skipped { .. })),
+            "{decoded:?}"
+        );
+        assert!(decode::<DaemonCoordinatorEvent>(&encode(future.clone(), Some(false))).is_err());
+        assert!(decode::<DaemonCoordinatorEvent>(&encode(future, None)).is_err());
+
+        // unknown fields of known messages are ignored
+        let set_log_level = json!({ "SetLogLevel": { "filter": "debug", "machine": "A" } });
+        let decoded = decode::<DaemonCoordinatorEvent>(&encode(set_log_level, None)).unwrap();
+        assert!(matches!(
+            decoded,
+            Decoded::Message(Timestamped {
+                inner: DaemonCoordinatorEvent::SetLogLevel { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn heartbeats_of_older_daemons_are_understood() {
+        let timestamp = uhlc::HLC::default().new_timestamp();
+        let heartbeat = json!({
+            "inner": { "Event": { "machine_id": "A", "event": "Heartbeat" } },
+            "timestamp": timestamp,
+        });
+        let decoded = decode::<CoordinatorRequest>(&serde_json::to_vec(&heartbeat).unwrap());
+        assert!(
+            matches!(
+                decoded,
+                Ok(Decoded::Message(Timestamped {
+                    inner: CoordinatorRequest::Event {
+                        event: DaemonEvent::Heartbeat,
+                        ..
+                    },
+                    ..
+                }))
+            ),
+            "{decoded:?}"
+        );
+
+        // older coordinators skip the health snapshots that are sent next to heartbeats
+        let health = json!({ "version": 1, "timestamp": timestamp });
+        let health = CoordinatorRequest::Event {
+            machine_id: "A".into(),
+            event: DaemonEvent::Health(serde_json::from_value(health).unwrap()),
+        };
+        assert!(Envelope::new(health, timestamp).ignorable);
+    }
+
+    #[test]
+    fn must_understand_messages_keep_the_timestamped_layout() {
+        let timestamp = uhlc::HLC::default().new_timestamp();
+        let envelope = Envelope::new(DaemonCoordinatorEvent::Destroy, timestamp);
+        assert!(!envelope.ignorable);
+        let old: Timestamped<DaemonCoordinatorEvent> =
+            serde_json::from_slice(&serde_json::to_vec(&envelope).unwrap()).unwrap();
+        assert!(matches!(old.inner, DaemonCoordinatorEvent::Destroy));
+        assert_eq!(
+            serde_json::to_value(&envelope).unwrap(),
+            serde_json::to_value(&old).unwrap()
+        );
+
+        let sync = DaemonCoordinatorEvent::TimeSync(crate::coordinator_to_daemon::TimeSync {
+            daemon_sent: 1,
+            coordinator_received: 2,
+            coordinator_sent: 3,
+        });
+        let envelope = Envelope::new(sync, timestamp);
+        assert!(envelope.ignorable);
+        // older releases ignore the marker
+        let old: Timestamped<DaemonCoordinatorEvent> =
+            serde_json::from_slice(&serde_json::to_vec(&envelope).unwrap()).unwrap();
+        assert!(matches!(old.inner, DaemonCoordinatorEvent::TimeSync(_)));
+    }
+}
@@ -1,24 +1,151 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
+pub use crate::cli_to_coordinator::Role;
 use dora_core::config::NodeId;
-use dora_core::uhlc;
+pub use dora_core::descriptor::DataflowDiff;
+use dora_core::{descriptor::Descriptor, uhlc};
 use uuid::Uuid;
 
-pub use crate::common::LogMessage;
+pub use crate::common::{LogMessage, NodeJoinInfo, TappedMessage};
 pub use crate::common::{NodeError, NodeErrorCause, NodeExitStatus};
+pub use crate::daemon_to_coordinator::{
+    DaemonHealth, DaemonStatus, MachineMetadata, NodeReloadReport,
+};
+pub use crate::diagnostics::DaemonDiagnostics;
+pub use crate::migration::NodeMigrationReport;
+pub use crate::plan::DataflowPlan;
+pub use crate::reconfigure::ReconfigureReport;
+pub use crate::summary::DataflowSummary;
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub enum ControlRequestReply {
     Error(String),
+    /// The connection is not allowed to send the request.
+    AccessDenied(AccessError),
+    /// Reply to an `Authenticate` request. Without access control, all
+    /// connections have the `Admin` role.
+    Authenticated {
+        role: Role,
+    },
     CoordinatorStopped,
-    DataflowStarted { uuid: Uuid },
-    DataflowReloaded { uuid: Uuid },
-    DataflowStopped { uuid: Uuid, result: DataflowResult },
+    DataflowStarted {
+        uuid: Uuid,
+        /// Machines that the coordinator picked for nodes without a fixed
+        /// machine, e.g. because of `deploy.requires`.
+        #[serde(default)]
+        assignments: BTreeMap<NodeId, String>,
+    },
+    DataflowReloaded {
+        uuid: Uuid,
+    },
+    NodeReloaded {
+        uuid: Uuid,
+        node_id: NodeId,
+        report: NodeReloadReport,
+    },
+    /// Reply to a `MigrateNode` request, also if one of its phases failed.
+    NodeMigrated {
+        uuid: Uuid,
+        node_id: NodeId,
+        report: NodeMigrationReport,
+    },
+    /// Reply to a `Reconfigure` request, also if some of the changes failed.
+    Reconfigured {
+        uuid: Uuid,
+        report: ReconfigureReport,
+    },
+    DataflowStopped {
+        uuid: Uuid,
+        result: DataflowResult,
+    },
     DataflowList(DataflowList),
     DestroyOk,
     DaemonConnected(bool),
     ConnectedMachines(BTreeSet<String>),
     Logs(Vec<u8>),
+    Descriptor(String),
+    NodeResolved(NodeJoinInfo),
+    /// Structural changes from the running dataflow to the given descriptor.
+    Diff(DataflowDiff),
+    /// Status of each connected daemon, by machine ID.
+    DaemonStatus(BTreeMap<String, MachineStatus>),
+    LogLevelSet {
+        previous: String,
+    },
+    Diagnostics(DaemonDiagnostics),
+    TapStarted,
+    /// Reply to a `Start` request with `dry_run` set.
+    DataflowPlan(DataflowPlan),
+    Registered(RegistryEntry),
+    Registry(Vec<RegistryEntry>),
+    RegisteredDataflow {
+        entry: RegistryEntry,
+        dataflow: Descriptor,
+    },
+    RegistryEntriesDeleted(Vec<RegistryEntry>),
+}
+
+/// Reason why a coordinator with access control rejected a request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum AccessError {
+    /// The connection didn't send a `ControlRequest::Authenticate` request.
+    Unauthenticated,
+    /// The token of the `Authenticate` request is not known.
+    InvalidToken,
+    /// The role of the connection is not sufficient for the request.
+    Forbidden { role: Role, required: Role },
+}
+
+impl std::fmt::Display for AccessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessError::Unauthenticated => {
+                f.write_str("dora-coordinator requires a token (set `DORA_COORDINATOR_TOKEN`)")
+            }
+            AccessError::InvalidToken => f.write_str("invalid coordinator token"),
+            AccessError::Forbidden { role, required } => write!(
+                f,
+                "permission denied: the request requires the `{required}` role, \
+                but the token has the `{role}` role"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+/// Lifecycle event sent to the subscribers of `ControlRequest::EventSubscribe`.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub enum CoordinatorEvent {
+    DataflowStarted {
+        id: DataflowIdAndName,
+        #[serde(default)]
+        assignments: BTreeMap<NodeId, String>,
+    },
+    DataflowFinished {
+        id: DataflowIdAndName,
+        result: DataflowResult,
+    },
+    DaemonConnected {
+        machine_id: String,
+    },
+    DaemonDisconnected {
+        machine_id: String,
+    },
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct MachineStatus {
+    /// Status reported by the daemon, or an error if it could not be retrieved.
+    pub status: Result<DaemonStatus, String>,
+    /// Health snapshot of the most recent heartbeat of the daemon.
+    pub last_heartbeat: Option<DaemonHealth>,
+    /// Machine information that the daemon reported on registration.
+    #[serde(default)]
+    pub metadata: MachineMetadata,
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -26,6 +153,9 @@ pub struct DataflowResult {
     pub uuid: Uuid,
     pub timestamp: uhlc::Timestamp,
     pub node_results: BTreeMap<NodeId, Result<(), NodeError>>,
+    /// Summary of the finished dataflow, including message counts.
+    #[serde(default)]
+    pub summary: Option<DataflowSummary>,
 }
 
 impl DataflowResult {
@@ -34,6 +164,7 @@ impl DataflowResult {
             uuid,
             timestamp,
             node_results: Default::default(),
+            summary: None,
         }
     }
 
@@ -59,6 +190,69 @@ impl DataflowList {
 pub struct DataflowListEntry {
     pub id: DataflowIdAndName,
     pub status: DataflowStatus,
+    /// Machines that the coordinator picked for nodes of a running dataflow.
+    #[serde(default)]
+    pub assignments: BTreeMap<NodeId, String>,
+    /// Parameters that the dataflow was started with.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Machines on which the running dataflow has no message flow, as
+    /// reported by daemons with stall reporting enabled.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub stalled_on: BTreeSet<String>,
+    /// Registry entry or hash of the descriptor that the dataflow was
+    /// started from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<DataflowOrigin>,
+}
+
+/// Descriptor that is stored in the registry of the coordinator.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub version: u32,
+    /// SHA-256 hash of the descriptor, see [`DataflowOrigin`].
+    pub hash: String,
+    /// Directory of the registered dataflow file on the machine that
+    /// registered it.
+    pub working_dir: PathBuf,
+}
+
+/// Descriptor that a dataflow was started from.
+///
+/// The hash is the hex-encoded SHA-256 hash of the JSON encoding of the
+/// parsed descriptor, so changes to comments or formatting of the dataflow
+/// file don't change it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum DataflowOrigin {
+    Registered {
+        name: String,
+        version: u32,
+        hash: String,
+    },
+    /// Started from a dataflow file that is not registered.
+    Unregistered { hash: String },
+}
+
+impl DataflowOrigin {
+    pub fn hash(&self) -> &str {
+        match self {
+            DataflowOrigin::Registered { hash, .. } | DataflowOrigin::Unregistered { hash } => hash,
+        }
+    }
+}
+
+impl std::fmt::Display for DataflowOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hash = self.hash();
+        let short_hash = &hash[..hash.len().min(12)];
+        match self {
+            DataflowOrigin::Registered { name, version, .. } => {
+                write!(f, "{name}@{version} ({short_hash})")
+            }
+            DataflowOrigin::Unregistered { .. } => write!(f, "unregistered ({short_hash})"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
@@ -72,11 +266,15 @@ pub enum DataflowStatus {
 pub struct DataflowIdAndName {
     pub uuid: Uuid,
     pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
 }
 
 impl std::fmt::Display for DataflowIdAndName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(name) = &self.name {
+        if let (Some(name), Some(instance)) = (&self.name, &self.instance) {
+            write!(f, "[{name}#{instance}] {}", self.uuid)
+        } else if let Some(name) = &self.name {
             write!(f, "[{name}] {}", self.uuid)
         } else {
             write!(f, "[<unnamed>] {}", self.uuid)
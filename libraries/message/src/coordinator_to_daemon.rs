@@ -1,12 +1,14 @@
 use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf, time::Duration};
 
 use dora_core::{
-    config::{NodeId, OperatorId},
+    config::{DataId, NodeId, OperatorId},
     // TODO: how should we version these?
     descriptor::{Descriptor, ResolvedNode},
 };
 
-use crate::DataflowId;
+use uuid::Uuid;
+
+use crate::{daemon_to_daemon::InterDaemonTransport, reconfigure::InputChange, DataflowId};
 
 pub use crate::common::Timestamped;
 
@@ -25,6 +27,8 @@ impl RegisterResult {
     }
 }
 
+// events are serialized right away, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub enum DaemonCoordinatorEvent {
     Spawn(SpawnDataflowNodes),
@@ -41,19 +45,182 @@ pub enum DaemonCoordinatorEvent {
         node_id: NodeId,
         operator_id: Option<OperatorId>,
     },
+    /// Restart a single node of a running dataflow, e.g. to pick up a new build.
+    ///
+    /// The node is stopped and spawned again while the rest of the dataflow
+    /// keeps running. Messages for the node are buffered while it is down.
+    ReloadNode {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+    /// Spawn a node of a running dataflow on this machine, as the first step
+    /// of migrating it away from `from_machine`.
+    ///
+    /// The new instance stays in standby, i.e. it receives no inputs until
+    /// the routes are switched through `SwitchNodeRoutes`. The daemon replies
+    /// with a `ReloadNodeResult` once the new instance subscribed.
+    PrepareNodeMigration {
+        dataflow_id: DataflowId,
+        /// The node, already assigned to this machine.
+        node: ResolvedNode,
+        from_machine: String,
+    },
+    /// Route the inputs of a migrating node to its instance on `to_machine`,
+    /// or back to `from_machine` if `revert` is set.
+    ///
+    /// Sent to every machine of the dataflow, one after the other, starting
+    /// with the machine that the inputs are routed away from.
+    SwitchNodeRoutes {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        from_machine: String,
+        to_machine: String,
+        revert: bool,
+    },
+    /// Stop the old instance of a migrated node.
+    ///
+    /// The daemon replies once the instance exited.
+    StopMigratedNode {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+    /// Forget the migration state of a node. If `abort` is set, the standby
+    /// instance of the node on this machine is stopped.
+    FinishNodeMigration {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        abort: bool,
+    },
+    /// Change the queue settings of inputs of local nodes. The daemon replies
+    /// with a `ReconfigureResult` that has an entry for every change.
+    Reconfigure {
+        dataflow_id: DataflowId,
+        changes: Vec<InputChange>,
+    },
     Logs {
         dataflow_id: DataflowId,
         node_id: NodeId,
     },
+    /// Request the resolved descriptor of a running dataflow.
+    GetDescriptor {
+        dataflow_id: DataflowId,
+    },
     Destroy,
     Heartbeat,
+    /// Sent in reply to a daemon heartbeat, for estimating the offset between
+    /// the clocks of the daemon and the coordinator.
+    TimeSync(TimeSync),
+    /// Request the health and build information of the daemon.
+    Status,
+    /// Replace the log filter of the daemon, using the `RUST_LOG` syntax.
+    SetLogLevel {
+        filter: String,
+    },
+    /// Request a snapshot of the internal state of the daemon.
+    ///
+    /// If `gc` is set, drop tokens that are pending for longer than the given
+    /// duration are released before the snapshot is taken.
+    Diagnostics {
+        gc: Option<Duration>,
+    },
+    /// Copy the messages of a local output to the coordinator for the given
+    /// duration, as [`DaemonEvent::Tapped`](crate::daemon_to_coordinator::DaemonEvent::Tapped)
+    /// events.
+    ///
+    /// The tap does not affect the delivery of the messages to their
+    /// receivers.
+    TapOutput {
+        tap_id: Uuid,
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+        duration: Duration,
+        /// Maximum number of copied messages per second.
+        max_rate: Option<f64>,
+    },
+    /// Create a short-lived token that allows an external node or observer
+    /// to connect to the listener for dynamic nodes of the daemon, also
+    /// from other machines.
+    ///
+    /// The token is only valid for the given node, which must run on the
+    /// machine of the daemon. The daemon replies with a `MintTokenResult`.
+    MintToken {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+}
+
+/// Wall-clock timestamps of a heartbeat exchange, in nanoseconds since the
+/// Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct TimeSync {
+    /// Time at which the daemon sent the heartbeat, by the daemon's clock.
+    pub daemon_sent: u64,
+    /// Time at which the coordinator received the heartbeat.
+    pub coordinator_received: u64,
+    /// Time at which the coordinator sent this reply.
+    pub coordinator_sent: u64,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct SpawnDataflowNodes {
     pub dataflow_id: DataflowId,
+    /// Working directory on the machine that submitted the dataflow.
+    ///
+    /// Only used if the daemon has no default working directory configured.
     pub working_dir: PathBuf,
+    /// Working directory that was given explicitly for the machine of the
+    /// daemon when the dataflow was started.
+    ///
+    /// Takes precedence over the default working directory of the daemon.
+    #[serde(default)]
+    pub machine_working_dir: Option<PathBuf>,
     pub nodes: Vec<ResolvedNode>,
     pub machine_listen_ports: BTreeMap<String, SocketAddr>,
     pub dataflow_descriptor: Descriptor,
+    /// Transport for delivering outputs between the machines of this dataflow.
+    #[serde(default)]
+    pub inter_daemon_transport: InterDaemonTransport,
+    /// Set if the dataflow was started as an instance of a named dataflow.
+    #[serde(default)]
+    pub instance: Option<DataflowInstance>,
+    /// Start parameters of the dataflow, see [`NodeConfig::dataflow_params`].
+    ///
+    /// [`NodeConfig::dataflow_params`]: crate::daemon_to_node::NodeConfig::dataflow_params
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Only check and resolve the local nodes and reply with a `PlanResult`,
+    /// without spawning anything or keeping any state.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Name and key of a dataflow instance.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct DataflowInstance {
+    pub name: String,
+    pub key: String,
+}
+
+impl DataflowInstance {
+    /// Returns `<name>-<key>`, with all characters that are not safe in
+    /// file names replaced by `_`.
+    pub fn label(&self) -> String {
+        format!("{}-{}", self.name, self.key)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for DataflowInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{}", self.name, self.key)
+    }
 }
@@ -1,12 +1,26 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, fmt, net::SocketAddr, path::PathBuf, time::Duration};
 
-use dora_core::{config::NodeId, uhlc};
+use dora_core::{
+    config::{DataId, NodeId},
+    uhlc,
+};
 
 pub use crate::common::{
-    DataMessage, LogLevel, LogMessage, NodeError, NodeErrorCause, NodeExitStatus, Timestamped,
+    DataMessage, LogLevel, LogMessage, NodeError, NodeErrorCause, NodeExitStatus, TappedMessage,
+    Timestamped,
+};
+use crate::{
+    current_crate_version,
+    daemon_to_daemon::InterDaemonTransport,
+    diagnostics::DaemonDiagnostics,
+    plan::MachinePlan,
+    reconfigure::InputChangeResult,
+    summary::{EdgeSummary, InputSummary, OutputSummary, SizeHistogram},
+    versions_compatible, DataflowId,
 };
-use crate::{current_crate_version, versions_compatible, DataflowId};
 
+// events are serialized right away, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum CoordinatorRequest {
     Register(DaemonRegisterRequest),
@@ -14,6 +28,15 @@ pub enum CoordinatorRequest {
         machine_id: String,
         event: DaemonEvent,
     },
+    /// Authenticates the connection that the daemon sends its events on,
+    /// with the token of its [`DaemonRegisterRequest`].
+    ///
+    /// Only sent by daemons that have a machine token. Coordinators that
+    /// require a token drop the events of connections that didn't send it.
+    AuthenticateEvents {
+        machine_id: String,
+        machine_token: String,
+    },
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -21,14 +44,31 @@ pub struct DaemonRegisterRequest {
     dora_version: semver::Version,
     pub machine_id: String,
     pub listen_port: u16,
+    /// The transport that this daemon prefers for inter-daemon communication.
+    #[serde(default)]
+    pub inter_daemon_transport: InterDaemonTransport,
+    #[serde(default)]
+    pub metadata: MachineMetadata,
+    /// Token that the coordinator requires from its daemons, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine_token: Option<String>,
 }
 
 impl DaemonRegisterRequest {
-    pub fn new(machine_id: String, listen_port: u16) -> Self {
+    pub fn new(
+        machine_id: String,
+        listen_port: u16,
+        inter_daemon_transport: InterDaemonTransport,
+        metadata: MachineMetadata,
+        machine_token: Option<String>,
+    ) -> Self {
         Self {
             dora_version: current_crate_version(),
             machine_id,
             listen_port,
+            inter_daemon_transport,
+            metadata,
+            machine_token,
         }
     }
 
@@ -48,6 +88,69 @@ impl DaemonRegisterRequest {
     }
 }
 
+/// Information about the machine of a daemon, sent on registration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MachineMetadata {
+    pub hostname: Option<String>,
+    pub os: String,
+    pub arch: String,
+    pub dora_version: String,
+    /// User-defined labels of the machine, e.g. `gpu=true`.
+    ///
+    /// Nodes can require labels through `deploy.requires`.
+    pub labels: BTreeMap<String, String>,
+}
+
+impl MachineMetadata {
+    /// Creates the metadata of the local machine.
+    pub fn local(hostname: Option<String>, labels: BTreeMap<String, String>) -> Self {
+        Self {
+            hostname,
+            os: std::env::consts::OS.to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            dora_version: current_crate_version().to_string(),
+            labels,
+        }
+    }
+
+    /// Checks whether the machine has the given label.
+    ///
+    /// Requirements have the same format as labels, see [`parse_label`].
+    pub fn satisfies(&self, requirement: &str) -> bool {
+        let (key, value) = parse_label(requirement);
+        self.labels.get(key) == Some(&value.to_owned())
+    }
+}
+
+impl fmt::Display for MachineMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}/{}, dora v{})",
+            self.hostname.as_deref().unwrap_or("unknown host"),
+            self.os,
+            self.arch,
+            self.dora_version
+        )?;
+        if !self.labels.is_empty() {
+            let labels: Vec<_> = self
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            write!(f, " [{}]", labels.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a `key=value` label into key and value.
+///
+/// A label without `=` is a flag, i.e. `gpu` is equivalent to `gpu=true`.
+pub fn parse_label(label: &str) -> (&str, &str) {
+    label.split_once('=').unwrap_or((label, "true"))
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum DaemonEvent {
     AllNodesReady {
@@ -59,13 +162,180 @@ pub enum DaemonEvent {
         result: DataflowDaemonResult,
     },
     Heartbeat,
+    /// Health snapshot of the daemon, sent next to every heartbeat.
+    Health(DaemonHealth),
     Log(LogMessage),
+    /// Copy of an output message for the tap with the given ID.
+    Tapped {
+        tap_id: uuid::Uuid,
+        message: TappedMessage,
+    },
+    /// The tap with the given ID expired or its dataflow finished.
+    TapFinished {
+        tap_id: uuid::Uuid,
+    },
+    /// A node with `allowed_to_stop: true` requested to stop the dataflow.
+    StopRequested {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        grace_duration: Option<Duration>,
+    },
+    /// The dataflow has running nodes on the machine, but no message was
+    /// delivered to them for `idle`.
+    DataflowStalled {
+        dataflow_id: DataflowId,
+        idle: Duration,
+    },
+    /// Messages flow again in a dataflow that was reported as stalled.
+    DataflowRecovered {
+        dataflow_id: DataflowId,
+    },
+}
+
+/// Cheap health snapshot that is sent next to every heartbeat.
+///
+/// Fields that are added in later versions must be optional or have a
+/// default value. Unknown fields are ignored on deserialization, so older
+/// coordinators can still read snapshots of newer daemons.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DaemonHealth {
+    /// Format version of the snapshot, see [`DaemonHealth::VERSION`].
+    pub version: u32,
+    /// Current time of the daemon's clock.
+    pub timestamp: uhlc::Timestamp,
+    #[serde(default)]
+    pub running_dataflows: usize,
+    #[serde(default)]
+    pub running_nodes: usize,
+    /// Number of messages that were not dropped by all of their receivers yet.
+    #[serde(default)]
+    pub pending_drop_tokens: usize,
+    /// Total size of the shared memory regions that were sent, but not
+    /// dropped by all of their receivers yet.
+    #[serde(default)]
+    pub shared_memory_in_flight: u64,
+    /// Number of inputs that were dropped since the last heartbeat because
+    /// the event queue of the receiver was full.
+    #[serde(default)]
+    pub dropped_messages: u64,
+    /// Number of dropped inputs since the last heartbeat per receiving
+    /// input, keyed by `<dataflow_id>/<node_id>/<input_id>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dropped_inputs: BTreeMap<String, u64>,
+    /// Wall-clock time at which the snapshot was sent, in nanoseconds since
+    /// the Unix epoch. The coordinator echoes it in a
+    /// [`TimeSync`][crate::coordinator_to_daemon::TimeSync] reply.
+    #[serde(default)]
+    pub wall_clock: Option<u64>,
+    /// Current estimate of the offset to the coordinator's clock.
+    #[serde(default)]
+    pub clock_offset: Option<ClockOffset>,
+    /// Node connections that the daemon closed since the last heartbeat
+    /// because they exceeded a limit.
+    #[serde(default, skip_serializing_if = "RejectedConnections::is_empty")]
+    pub rejected_connections: RejectedConnections,
+    /// Number of events that are waiting to be processed by the daemon, per
+    /// running dataflow.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub event_queue_depths: BTreeMap<DataflowId, usize>,
+}
+
+impl DaemonHealth {
+    pub const VERSION: u32 = 1;
+}
+
+impl fmt::Display for DaemonHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} running dataflows with {} nodes, {} pending drop tokens, \
+            {} bytes of shared memory in flight, {} messages dropped since previous heartbeat",
+            self.running_dataflows,
+            self.running_nodes,
+            self.pending_drop_tokens,
+            self.shared_memory_in_flight,
+            self.dropped_messages
+        )?;
+        if !self.rejected_connections.is_empty() {
+            write!(f, ", {}", self.rejected_connections)?;
+        }
+        Ok(())
+    }
+}
+
+/// Numbers of node connections that were closed because they exceeded a
+/// limit, by limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct RejectedConnections {
+    /// Rejected because the maximum number of connections was open.
+    pub limit_reached: u64,
+    /// Closed because no request was received within the handshake timeout.
+    pub handshake_timeout: u64,
+    /// Closed because the request rate limit was exceeded.
+    pub rate_limited: u64,
+}
+
+impl RejectedConnections {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for RejectedConnections {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rejected node connections: {} over limit, {} handshake timeouts, {} rate limited",
+            self.limit_reached, self.handshake_timeout, self.rate_limited
+        )
+    }
+}
+
+/// Estimated offset between the wall clocks of a daemon and the coordinator.
+///
+/// This is purely observational, e.g. for interpreting latencies between
+/// machines. The clocks are not adjusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct ClockOffset {
+    /// Time that the coordinator's clock is ahead of the daemon's clock, in
+    /// nanoseconds.
+    pub offset: i64,
+    /// Round-trip time of the heartbeat exchanges, in nanoseconds.
+    pub round_trip: u64,
+}
+
+impl fmt::Display for ClockOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:+.3}ms (round trip {:.3}ms)",
+            self.offset as f64 / 1e6,
+            self.round_trip as f64 / 1e6
+        )
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct DataflowDaemonResult {
     pub timestamp: uhlc::Timestamp,
     pub node_results: BTreeMap<NodeId, Result<(), NodeError>>,
+    /// Message counts of the inputs of the local nodes.
+    #[serde(default)]
+    pub inputs: BTreeMap<NodeId, BTreeMap<DataId, InputSummary>>,
+    /// Message sizes of the outputs of the local nodes.
+    #[serde(default)]
+    pub outputs: BTreeMap<NodeId, BTreeMap<DataId, OutputSummary>>,
+    /// Highest total size of the shared memory regions that were in use at
+    /// the same time, in bytes.
+    #[serde(default)]
+    pub peak_shared_memory: u64,
+    /// Message counts of the edges to the inputs of the local nodes, see
+    /// [`DataflowSummary::edges`][crate::summary::DataflowSummary::edges].
+    #[serde(default)]
+    pub edges: BTreeMap<String, BTreeMap<String, EdgeSummary>>,
+    /// Local node that requested to stop the dataflow, if any.
+    #[serde(default)]
+    pub stopped_by: Option<NodeId>,
 }
 
 impl DataflowDaemonResult {
@@ -76,8 +346,13 @@ impl DataflowDaemonResult {
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub enum DaemonCoordinatorReply {
-    SpawnResult(Result<(), String>),
+    /// The resolved absolute working directory of each spawned node.
+    SpawnResult(Result<BTreeMap<NodeId, PathBuf>, String>),
     ReloadResult(Result<(), String>),
+    ReloadNodeResult(Result<NodeReloadReport, String>),
+    /// Reply to the node migration events, except for `PrepareNodeMigration`.
+    NodeMigrationResult(Result<(), String>),
+    ReconfigureResult(Result<Vec<InputChangeResult>, String>),
     StopResult(Result<(), String>),
     DestroyResult {
         result: Result<(), String>,
@@ -85,4 +360,146 @@ pub enum DaemonCoordinatorReply {
         notify: Option<tokio::sync::oneshot::Sender<()>>,
     },
     Logs(Result<Vec<u8>, String>),
+    /// YAML-serialized descriptor of a running dataflow, as it was spawned.
+    Descriptor(Result<String, String>),
+    Status(DaemonStatus),
+    /// The previously active log filter.
+    SetLogLevelResult(Result<String, String>),
+    Diagnostics(DaemonDiagnostics),
+    TapResult(Result<(), String>),
+    /// Reply to a `Spawn` event with `dry_run` set.
+    PlanResult(Result<MachinePlan, String>),
+    MintTokenResult(Result<MintedToken, String>),
+}
+
+/// Reply to a `MintToken` event.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct MintedToken {
+    pub token: String,
+    /// Address of the listener for dynamic nodes, or `None` if it is bound
+    /// to a loopback address.
+    ///
+    /// The IP address is unspecified if the listener is bound to all
+    /// interfaces.
+    pub listen_address: Option<SocketAddr>,
+    pub valid_for: Duration,
+}
+
+/// Timings of a node restart through a `ReloadNode` event.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NodeReloadReport {
+    /// Time between the reload request and the subscription of the new node.
+    pub down_time: Duration,
+    /// Number of messages that were buffered and delivered to the new node.
+    pub buffered_messages: usize,
+    /// Number of buffered messages that were dropped because the input
+    /// queue was full.
+    pub dropped_messages: usize,
+}
+
+impl fmt::Display for NodeReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "down for {:.3}s, delivered {} buffered messages",
+            self.down_time.as_secs_f64(),
+            self.buffered_messages
+        )?;
+        if self.dropped_messages > 0 {
+            write!(f, " ({} dropped)", self.dropped_messages)?;
+        }
+        Ok(())
+    }
+}
+
+/// Health and build information of a daemon.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DaemonStatus {
+    /// Version of the `dora-daemon` crate.
+    pub version: String,
+    /// Git commit that the daemon was built from, if known at build time.
+    pub git_hash: Option<String>,
+    pub machine_id: String,
+    pub uptime: Duration,
+    pub running_dataflows: usize,
+    pub running_nodes: usize,
+    /// Total size of the shared memory regions that were sent, but not
+    /// dropped by all of their receivers yet.
+    pub shared_memory_in_flight: u64,
+    /// Highest value of `shared_memory_in_flight` since the daemon started.
+    #[serde(default)]
+    pub peak_shared_memory: u64,
+    /// Sizes of the messages that the local nodes of running dataflows sent,
+    /// keyed by `<dataflow_id>/<node_id>/<output_id>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub message_sizes: BTreeMap<String, SizeHistogram>,
+    /// Address that other daemons connect to.
+    pub listen_address: Option<SocketAddr>,
+    /// Estimated offset to the coordinator's clock.
+    #[serde(default)]
+    pub clock_offset: Option<ClockOffset>,
+    /// Dataflows with running nodes, but no message flow, together with the
+    /// time since their last delivered message.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stalled_dataflows: BTreeMap<DataflowId, Duration>,
+}
+
+impl fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dora-daemon {}", self.version)?;
+        if let Some(git_hash) = &self.git_hash {
+            write!(f, " ({git_hash})")?;
+        }
+        if !self.machine_id.is_empty() {
+            write!(f, " on machine `{}`", self.machine_id)?;
+        }
+        write!(
+            f,
+            ", up {}s, {} running dataflows with {} nodes, {} bytes of shared memory in flight \
+            (peak {} bytes)",
+            self.uptime.as_secs(),
+            self.running_dataflows,
+            self.running_nodes,
+            self.shared_memory_in_flight,
+            self.peak_shared_memory
+        )?;
+        if let Some(address) = self.listen_address {
+            write!(f, ", listening on {address}")?;
+        }
+        if let Some(offset) = self.clock_offset {
+            write!(f, ", clock offset to coordinator {offset}")?;
+        }
+        for (dataflow_id, idle) in &self.stalled_dataflows {
+            write!(
+                f,
+                ", dataflow `{dataflow_id}` stalled for {}s",
+                idle.as_secs()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requirements_match_labels() {
+        let labels = ["gpu", "camera=usb"]
+            .into_iter()
+            .map(|label| {
+                let (key, value) = parse_label(label);
+                (key.to_owned(), value.to_owned())
+            })
+            .collect();
+        let metadata = MachineMetadata::local(None, labels);
+
+        assert!(metadata.satisfies("gpu"));
+        assert!(metadata.satisfies("gpu=true"));
+        assert!(metadata.satisfies("camera=usb"));
+        assert!(!metadata.satisfies("camera"));
+        assert!(!metadata.satisfies("camera=csi"));
+        assert!(!metadata.satisfies("lidar"));
+    }
 }
@@ -1,10 +1,48 @@
-use std::collections::BTreeSet;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
 
 use aligned_vec::{AVec, ConstAlign};
 use dora_core::config::{DataId, NodeId};
+use uuid::Uuid;
 
-use crate::{metadata::Metadata, DataflowId};
+use crate::{current_crate_version, metadata::Metadata, versions_compatible, DataflowId};
 
+/// First message on every TCP connection between daemons.
+///
+/// The layout of [`InterDaemonEvent`] may change between minor versions of
+/// `dora-message`, so the receiving daemon closes connections of incompatible
+/// daemons instead of misinterpreting their events.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct InterDaemonHello {
+    dora_version: semver::Version,
+}
+
+impl InterDaemonHello {
+    pub fn current() -> Self {
+        Self {
+            dora_version: current_crate_version(),
+        }
+    }
+
+    pub fn check_version(&self) -> Result<(), String> {
+        let crate_version = current_crate_version();
+        if versions_compatible(&crate_version, &self.dora_version)? {
+            Ok(())
+        } else {
+            Err(format!(
+                "version mismatch: message format v{} is not compatible \
+                with expected message format v{crate_version}",
+                self.dora_version
+            ))
+        }
+    }
+}
+
+// outputs are by far the most common event, so we don't box them
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub enum InterDaemonEvent {
     Output {
@@ -14,8 +52,113 @@ pub enum InterDaemonEvent {
         metadata: Metadata,
         data: Option<AVec<u8, ConstAlign<128>>>,
     },
+    /// Part of an output whose data is too large to be sent in a single
+    /// frame on a TCP connection between daemons.
+    ///
+    /// The chunks of a message are sent in order, but they may be
+    /// interleaved with the messages of other outputs.
+    OutputChunk(OutputChunk),
+    /// Part of a message of a best-effort output, see
+    /// [`RemoteTransport::Udp`][dora_core::descriptor::RemoteTransport::Udp].
+    ///
+    /// Only sent as UDP datagram, never on the TCP connections between
+    /// daemons.
+    OutputDatagram(OutputDatagram),
     InputsClosed {
         dataflow_id: DataflowId,
-        inputs: BTreeSet<(NodeId, DataId)>,
+        /// Maps each closed output (`(node_id, output_id)`) to the inputs it
+        /// was connected to.
+        inputs: BTreeMap<(NodeId, DataId), BTreeSet<(NodeId, DataId)>>,
+    },
+    /// The given output was closed, so all inputs connected to it should be
+    /// closed too.
+    ///
+    /// Used by transports that deliver outputs by publishing them on a
+    /// per-output stream, which don't know the receiving inputs.
+    OutputClosed {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+    },
+    /// The sending machine switched the routes of its outputs to the inputs
+    /// of a migrating node.
+    ///
+    /// Sent to both machines of the migration, after all outputs that were
+    /// routed the old way. This tells the receivers from which point on the
+    /// outputs of the sending machine are meant for the other instance.
+    NodeRoutesSwitched {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        machine_id: String,
+        revert: bool,
     },
 }
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct OutputChunk {
+    pub dataflow_id: DataflowId,
+    pub node_id: NodeId,
+    pub output_id: DataId,
+    /// Identifies the message that the chunk belongs to.
+    pub message_id: Uuid,
+    pub index: u32,
+    /// Number of chunks of the message.
+    pub total: u32,
+    /// Length of the data of the whole message.
+    pub len: u64,
+    /// Only set on the first chunk of a message.
+    pub metadata: Option<Metadata>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct OutputDatagram {
+    pub dataflow_id: DataflowId,
+    pub node_id: NodeId,
+    pub output_id: DataId,
+    /// Number of the message on the output, which the sending daemon
+    /// increments by one for every message that it sends to the receiving
+    /// machine.
+    pub sequence: u64,
+    pub index: u32,
+    /// Number of datagrams of the message.
+    pub total: u32,
+    /// Length of the data of the whole message.
+    pub len: u64,
+    /// Only set on the first datagram of a message.
+    pub metadata: Option<Metadata>,
+    pub data: Vec<u8>,
+}
+
+/// Transport used to deliver outputs between daemons on different machines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum InterDaemonTransport {
+    /// Direct TCP connections between daemons.
+    #[default]
+    Tcp,
+    /// Publish outputs through zenoh, using one key expression per output.
+    Zenoh,
+}
+
+impl fmt::Display for InterDaemonTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterDaemonTransport::Tcp => write!(f, "tcp"),
+            InterDaemonTransport::Zenoh => write!(f, "zenoh"),
+        }
+    }
+}
+
+impl FromStr for InterDaemonTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Self::Tcp),
+            "zenoh" => Ok(Self::Zenoh),
+            other => Err(format!(
+                "unknown inter-daemon transport `{other}` (expected `tcp` or `zenoh`)"
+            )),
+        }
+    }
+}
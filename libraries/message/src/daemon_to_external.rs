@@ -0,0 +1,20 @@
+use std::net::SocketAddr;
+
+pub use crate::external_to_daemon::ExternalMessage;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub enum ExternalReply {
+    Ok,
+    Err(String),
+}
+
+/// Connection information for the external endpoint server of a dataflow.
+///
+/// The daemon writes this as JSON to `out/<dataflow_id>/external_endpoints.json`
+/// in the working directory of the dataflow.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ExternalEndpointsInfo {
+    pub address: SocketAddr,
+    /// Access token that must be included in every `ExternalRequest`.
+    pub token: String,
+}
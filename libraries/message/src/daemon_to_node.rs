@@ -1,13 +1,59 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    time::Duration,
+};
 
+use aligned_vec::{AVec, ConstAlign};
 use dora_core::{
-    config::{DataId, NodeId, NodeRunConfig, OperatorId},
+    config::{DataId, InputMapping, NodeId, NodeRunConfig, OperatorId},
     descriptor::{Descriptor, OperatorDefinition},
+    uhlc,
 };
 
+use uuid::Uuid;
+
 use crate::{metadata::Metadata, DataflowId};
 
-pub use crate::common::{DataMessage, DropToken, SharedMemoryId, Timestamped};
+pub use crate::common::{DataMessage, DropToken, OutputRingId, SharedMemoryId, Timestamped};
+
+/// Environment variables that the daemon sets for spawned nodes.
+///
+/// These variables are a stable interface: nodes (and tools that start
+/// nodes externally) can rely on them. The working directory of a spawned
+/// node is set to the working directory of the dataflow.
+pub mod env {
+    /// Address of the local listener of the daemon, e.g. `127.0.0.1:53291`.
+    ///
+    /// External nodes use it to request their [`NodeConfig`](super::NodeConfig)
+    /// from the daemon. Not set if the daemon has no local listener.
+    pub const DORA_DAEMON_ADDR: &str = "DORA_DAEMON_ADDR";
+    /// ID of the dataflow that the node belongs to.
+    pub const DORA_DATAFLOW_ID: &str = "DORA_DATAFLOW_ID";
+    /// ID of the node, as specified in the dataflow descriptor.
+    pub const DORA_NODE_ID: &str = "DORA_NODE_ID";
+    /// Name and key of the dataflow instance (`<name>#<key>`). Only set if
+    /// the dataflow was started as an instance.
+    pub const DORA_DATAFLOW_INSTANCE: &str = "DORA_DATAFLOW_INSTANCE";
+    /// YAML-serialized [`NodeConfig`](super::NodeConfig) of the node, which
+    /// includes its `run_config`.
+    pub const DORA_NODE_CONFIG: &str = "DORA_NODE_CONFIG";
+    /// Token that the node uses to authenticate to the daemon.
+    ///
+    /// Reserved for future use, not set yet.
+    pub const DORA_NODE_TOKEN: &str = "DORA_NODE_TOKEN";
+    /// YAML-serialized [`RuntimeConfig`](super::RuntimeConfig), only set
+    /// for runtime nodes.
+    pub const DORA_RUNTIME_CONFIG: &str = "DORA_RUNTIME_CONFIG";
+    /// Runtime directory of the dataflow on the machine of the node, for
+    /// temporary files, pipes, and caches that are only needed while the
+    /// dataflow runs. Removed when the dataflow finishes successfully.
+    ///
+    /// Not set if the daemon has no writable state dir.
+    pub const DORA_DATAFLOW_DIR: &str = "DORA_DATAFLOW_DIR";
+}
 
 // Passed via env variable
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -24,6 +70,44 @@ pub struct NodeConfig {
     pub daemon_communication: DaemonCommunication,
     pub dataflow_descriptor: Descriptor,
     pub dynamic: bool,
+    /// Name and key of the dataflow instance (`<name>#<key>`), if the
+    /// dataflow was started as an instance.
+    #[serde(default)]
+    pub dataflow_instance: Option<String>,
+    /// Parameters that the dataflow was started with, e.g. through
+    /// `dora start --param seed=42`.
+    #[serde(default)]
+    pub dataflow_params: BTreeMap<String, String>,
+    /// Run ID of the daemon, used as namespace for the drop tokens of the
+    /// node, see [`DropToken::generate_in`](DropToken::generate_in).
+    #[serde(default)]
+    pub drop_token_namespace: Uuid,
+    /// Message format version of the daemon, see
+    /// [`supports_multiplexed_requests`](Self::supports_multiplexed_requests).
+    ///
+    /// Not set by daemons that predate this field.
+    #[serde(default)]
+    pub daemon_version: Option<semver::Version>,
+}
+
+impl NodeConfig {
+    /// Whether the daemon understands [`Multiplexed`](crate::node_to_daemon::DaemonRequest::Multiplexed)
+    /// requests.
+    ///
+    /// All daemons that report their version do. Older daemons must be sent
+    /// one request at a time, without request ID.
+    pub fn supports_multiplexed_requests(&self) -> bool {
+        self.daemon_version.is_some()
+    }
+
+    /// Whether the daemon understands [`NextControlEvents`](crate::node_to_daemon::DaemonRequest::NextControlEvents)
+    /// requests.
+    ///
+    /// All daemons that report their version do. Older daemons only deliver
+    /// control events in order with the other events.
+    pub fn supports_control_event_requests(&self) -> bool {
+        self.daemon_version.is_some()
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -47,13 +131,265 @@ pub enum DaemonCommunication {
 #[must_use]
 pub enum DaemonReply {
     Result(Result<(), String>),
-    PreparedMessage { shared_memory_id: SharedMemoryId },
+    PreparedMessage {
+        shared_memory_id: SharedMemoryId,
+    },
     NextEvents(Vec<Timestamped<NodeEvent>>),
     NextDropEvents(Vec<Timestamped<NodeDropEvent>>),
-    NodeConfig { result: Result<NodeConfig, String> },
+    NodeConfig {
+        result: Result<NodeConfig, String>,
+    },
+    Topology {
+        result: Result<NodeTopology, String>,
+    },
+    /// Reply to [`QuerySubscribers`][crate::node_to_daemon::DaemonRequest::QuerySubscribers].
+    Subscribers {
+        result: Result<u64, String>,
+    },
+    SendOutResult(Result<(), SendOutputError>),
+    OutputRing {
+        result: Result<OutputRingInfo, SendOutputError>,
+    },
+    /// Reply to [`SendOutSlot`][crate::node_to_daemon::DaemonRequest::SendOutSlot].
+    ///
+    /// Contains the drop token that is reported through the drop stream once
+    /// all receivers are done with the message, i.e. when the slot can be
+    /// reused.
+    SendOutSlotResult(Result<DropToken, SendOutputError>),
+    /// Reply to [`SendCached`][crate::node_to_daemon::DaemonRequest::SendCached].
+    ///
+    /// Contains `None` if the persistent output cache holds no message with
+    /// the requested hash.
+    SendCachedResult(Result<Option<DropToken>, SendOutputError>),
+    /// Reply to [`RequestDataflowStop`][crate::node_to_daemon::DaemonRequest::RequestDataflowStop].
+    StopRequestResult(Result<(), StopRequestError>),
+    /// Reply to a [`Multiplexed`][crate::node_to_daemon::DaemonRequest::Multiplexed]
+    /// request with the same `request_id`.
+    Multiplexed {
+        request_id: u64,
+        reply: Box<DaemonReply>,
+    },
+    /// Copy of a message of the output that an observer subscribed to
+    /// through [`Observe`][crate::node_to_daemon::DaemonRequest::Observe].
+    ObservedMessage(ObservedMessage),
     Empty,
 }
 
+/// A message of an output, copied to an observer.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ObservedMessage {
+    pub metadata: Metadata,
+    pub data: Option<AVec<u8, ConstAlign<128>>>,
+    /// Number of messages of the output that were skipped right before this
+    /// one because the observer didn't keep up.
+    pub skipped: u64,
+}
+
+/// Shared memory slots of an output ring, allocated by the daemon.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OutputRingInfo {
+    pub ring_id: OutputRingId,
+    /// IDs of the shared memory regions, indexed by slot.
+    pub slot_ids: Vec<SharedMemoryId>,
+}
+
+/// Reasons why the daemon rejected an output message.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SendOutputError {
+    /// The output is not listed in the `outputs` of the node.
+    OutputNotDeclared { output_id: DataId },
+    /// There is no output ring with the given ID for the node.
+    UnknownOutputRing { ring_id: OutputRingId },
+    /// The slot index or length is out of range for the output ring.
+    InvalidSlot {
+        ring_id: OutputRingId,
+        slot_index: usize,
+        valid_len: usize,
+    },
+    /// The previous message sent from this slot is still accessed by receivers.
+    SlotBusy {
+        ring_id: OutputRingId,
+        slot_index: usize,
+    },
+    /// The daemon failed to allocate the shared memory for an output ring.
+    AllocationFailed { reason: String },
+    /// The source timestamp of the message is invalid, see
+    /// [`Metadata::check_source_timestamp`][crate::metadata::Metadata::check_source_timestamp].
+    InvalidSourceTimestamp { reason: String },
+    /// The output is not listed in the `persistent_outputs` of the node.
+    OutputNotPersistent { output_id: DataId },
+}
+
+impl fmt::Display for SendOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendOutputError::OutputNotDeclared { output_id } => write!(
+                f,
+                "output `{output_id}` is not declared in the `outputs` of the node"
+            ),
+            SendOutputError::UnknownOutputRing { ring_id } => {
+                write!(f, "unknown output ring `{ring_id}`")
+            }
+            SendOutputError::InvalidSlot {
+                ring_id,
+                slot_index,
+                valid_len,
+            } => write!(
+                f,
+                "invalid slot {slot_index} with length {valid_len} for output ring `{ring_id}`"
+            ),
+            SendOutputError::SlotBusy {
+                ring_id,
+                slot_index,
+            } => write!(
+                f,
+                "slot {slot_index} of output ring `{ring_id}` is still in use by receivers"
+            ),
+            SendOutputError::AllocationFailed { reason } => {
+                write!(f, "failed to allocate output ring: {reason}")
+            }
+            SendOutputError::InvalidSourceTimestamp { reason } => {
+                write!(f, "invalid source timestamp: {reason}")
+            }
+            SendOutputError::OutputNotPersistent { output_id } => write!(
+                f,
+                "output `{output_id}` is not listed in the `persistent_outputs` of the node"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SendOutputError {}
+
+/// Reasons why the daemon rejected a request of a node to stop the dataflow.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StopRequestError {
+    /// The node doesn't have `allowed_to_stop: true` in the descriptor.
+    PermissionDenied { node_id: NodeId },
+    /// The stop could not be started, e.g. because the coordinator is not
+    /// reachable.
+    Failed { reason: String },
+}
+
+impl fmt::Display for StopRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopRequestError::PermissionDenied { node_id } => write!(
+                f,
+                "node `{node_id}` is not allowed to stop the dataflow \
+                (set `allowed_to_stop: true` in the descriptor)"
+            ),
+            StopRequestError::Failed { reason } => {
+                write!(f, "failed to stop the dataflow: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StopRequestError {}
+
+/// Lifecycle event of a dataflow, delivered as JSON string on the
+/// `dora/lifecycle` input of nodes with `subscribe_lifecycle: true`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// The node connected to dora.
+    NodeStarted { node_id: NodeId },
+    /// The node exited successfully.
+    NodeStopped { node_id: NodeId },
+    /// The node failed with the given error.
+    NodeCrashed { node_id: NodeId, error: String },
+    /// The dataflow is being stopped, a `Stop` event follows.
+    DataflowStopping,
+}
+
+/// Record of messages that the daemon dropped, delivered as JSON string on
+/// the input that the `drop_events` of the dataflow point to.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DropEvent {
+    /// Input whose messages were dropped, as `node_id/input_id`.
+    pub input: String,
+    /// Sources of the input, e.g. `node_id/output_id` or `dora/timer/...`.
+    pub sources: Vec<String>,
+    pub reason: DropReason,
+    /// Number of dropped messages.
+    pub count: u64,
+    /// Machine of the daemon that dropped the messages.
+    pub machine: String,
+    /// Number of the record, counting up from 0 on each machine.
+    ///
+    /// Gaps indicate that records were dropped themselves, e.g. because the
+    /// queue of the `drop_events` input was full.
+    pub sequence: u64,
+    /// Time at which the daemon noticed the drop, in milliseconds since the
+    /// Unix epoch.
+    pub time_ms: u64,
+    /// Messages that were dropped since the previous record, but not reported
+    /// in records of their own because of the rate limit.
+    pub suppressed: u64,
+}
+
+/// Why the daemon dropped messages, see [`DropEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// The queue of the input was full, so the oldest messages were dropped.
+    QueueFull,
+}
+
+/// The position of a node in the dataflow graph, as seen by the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NodeTopology {
+    /// Inputs of the node, mapped to their sources.
+    ///
+    /// Wildcard inputs are already expanded, so every entry corresponds to an
+    /// input that can actually receive messages. Inputs with multiple
+    /// sources (fan-in) list all of them.
+    pub inputs: BTreeMap<DataId, Vec<InputMapping>>,
+    /// Outputs of the node.
+    ///
+    /// For runtime nodes, the output IDs are prefixed with the operator ID.
+    pub outputs: BTreeSet<DataId>,
+    /// Declared message types of the inputs, as given in the `output_types`
+    /// of their sources.
+    ///
+    /// Inputs whose sources don't declare a type, or declare different
+    /// types, are not included.
+    #[serde(default)]
+    pub input_types: BTreeMap<DataId, String>,
+    /// Declared message types of the outputs, as given in the
+    /// `output_types` of the node.
+    #[serde(default)]
+    pub output_types: BTreeMap<DataId, String>,
+    /// Nodes that send messages to at least one input of this node.
+    ///
+    /// Built-in sources such as timers are not included.
+    pub upstream: BTreeSet<NodeId>,
+    /// Nodes that receive at least one output of this node.
+    pub downstream: BTreeSet<NodeId>,
+}
+
+impl NodeTopology {
+    pub fn from_run_config(
+        run_config: &NodeRunConfig,
+        upstream: BTreeSet<NodeId>,
+        downstream: BTreeSet<NodeId>,
+    ) -> Self {
+        Self {
+            inputs: run_config
+                .inputs
+                .iter()
+                .map(|(id, input)| (id.clone(), input.mappings().cloned().collect()))
+                .collect(),
+            outputs: run_config.outputs.clone(),
+            input_types: BTreeMap::new(),
+            output_types: BTreeMap::new(),
+            upstream,
+            downstream,
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum NodeEvent {
     Stop,
@@ -67,8 +403,67 @@ pub enum NodeEvent {
     },
     InputClosed {
         id: DataId,
+        /// Number of messages that the daemon delivered to the input,
+        /// including messages that the node dropped because its input queue
+        /// was full.
+        #[serde(default)]
+        delivered: u64,
+        /// Timestamp of the last delivered message.
+        #[serde(default)]
+        last_timestamp: Option<uhlc::Timestamp>,
     },
     AllInputsClosed,
+    /// A new message is available for the given `latest` input.
+    ///
+    /// The message itself is kept by the daemon until it is taken through a
+    /// [`DaemonRequest::TakeLatest`][crate::node_to_daemon::DaemonRequest::TakeLatest]
+    /// request, so that it can still be replaced by newer messages.
+    LatestAvailable {
+        id: DataId,
+    },
+    /// The number of receivers of an output of the node changed from zero
+    /// to nonzero or back.
+    ///
+    /// Allows source nodes to skip expensive work while nobody receives
+    /// their output. Other changes of the number are not reported, see
+    /// [`DaemonRequest::QuerySubscribers`][crate::node_to_daemon::DaemonRequest::QuerySubscribers].
+    SubscribersChanged {
+        output_id: DataId,
+        subscribers: u64,
+    },
+    /// No message arrived on the given input within its configured
+    /// `timeout`.
+    ///
+    /// Repeated after every further `timeout` while the input stays silent.
+    InputTimeout {
+        id: DataId,
+        /// Time since the last delivered message, or since the node
+        /// subscribed if no message was delivered yet.
+        elapsed: Duration,
+    },
+    /// Multiple messages of an input with a `batch` config, in arrival order.
+    ///
+    /// Each message keeps its own data and drop token, as if it was sent as
+    /// a separate `Input` event.
+    InputBatch {
+        id: DataId,
+        messages: Vec<BatchedInput>,
+    },
+}
+
+/// A single message of a [`NodeEvent::InputBatch`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BatchedInput {
+    pub metadata: Metadata,
+    pub data: Option<DataMessage>,
+}
+
+impl BatchedInput {
+    /// The `Input` event for this message of the input with the given ID.
+    pub fn into_event(self, id: DataId) -> NodeEvent {
+        let Self { metadata, data } = self;
+        NodeEvent::Input { id, metadata, data }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
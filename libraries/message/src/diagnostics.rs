@@ -0,0 +1,317 @@
+//! Snapshot of the internal state of a daemon, for debugging daemons in the
+//! field without restarting them.
+//!
+//! The snapshot only contains counts and ages of the bookkeeping entries, not
+//! the entries themselves, so that it stays small for large dataflows.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::Duration,
+};
+
+use dora_core::config::{DataId, NodeId};
+
+use crate::{reconfigure::InputSettings, DataflowId};
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DaemonDiagnostics {
+    pub machine_id: String,
+    pub uptime: Duration,
+    /// Configuration in force, by setting name.
+    pub config: BTreeMap<String, String>,
+    pub dataflows: BTreeMap<DataflowId, DataflowDiagnostics>,
+    /// Total size of the shared memory regions that are in flight, across
+    /// all dataflows.
+    pub shared_memory_in_flight: u64,
+    /// Highest value of `shared_memory_in_flight` since the daemon started.
+    pub peak_shared_memory: u64,
+    /// Most recent warnings and errors of the daemon, oldest first.
+    pub recent_warnings: Vec<String>,
+    /// Result of the garbage collection that ran before the snapshot was
+    /// taken, if requested.
+    pub gc: Option<GcReport>,
+    /// Drop reports of tokens that were not pending, across all dataflows
+    /// since the daemon started.
+    #[serde(default)]
+    pub drop_token_reports: DropTokenReports,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DataflowDiagnostics {
+    /// Name of the dataflow instance, if started as one.
+    pub instance: Option<String>,
+    pub nodes: BTreeMap<NodeId, NodeDiagnostics>,
+    /// Sent messages whose shared memory is still accessed by receivers.
+    pub pending_drop_tokens: EntryStats,
+    /// Shared memory rings that nodes prepared for sending outputs.
+    pub output_rings: EntryStats,
+    /// Number of events that are waiting to be processed by the daemon.
+    pub event_queue_depth: usize,
+    /// Drop reports of tokens that were not pending.
+    #[serde(default)]
+    pub drop_token_reports: DropTokenReports,
+    /// Remote outputs that are still being reassembled from their chunks.
+    #[serde(default)]
+    pub partial_remote_outputs: EntryStats,
+    /// Remote outputs that were discarded before all of their chunks
+    /// arrived.
+    #[serde(default)]
+    pub discarded_remote_outputs: u64,
+    /// Messages of best-effort outputs of other machines, by source
+    /// (`node_id/output_id`).
+    #[serde(default)]
+    pub best_effort_outputs: BTreeMap<String, DatagramStats>,
+    /// Time since the last delivered message, if the dataflow is stalled.
+    #[serde(default)]
+    pub stalled_for: Option<Duration>,
+    /// Local inputs with `adaptive` downsampling, by `node_id/input_id`.
+    #[serde(default)]
+    pub adaptive_inputs: BTreeMap<String, AdaptiveInputStats>,
+    /// Settings of the inputs that were changed through a `Reconfigure`
+    /// request, by `node_id/input_id`.
+    #[serde(default)]
+    pub reconfigured_inputs: BTreeMap<String, InputSettings>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NodeDiagnostics {
+    pub state: NodeState,
+    pub pid: Option<u32>,
+    pub open_inputs: BTreeSet<DataId>,
+    /// Number of messages that the node received, but did not drop yet.
+    pub pending_drop_tokens: usize,
+    /// `ready_timeout` of the node, while the node is not ready yet.
+    #[serde(default)]
+    pub ready_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeState {
+    /// Spawned, but not subscribed to its events yet.
+    Pending,
+    Running,
+    /// Being restarted through a `ReloadNode` event.
+    Reloading,
+}
+
+impl fmt::Display for NodeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeState::Pending => f.write_str("pending"),
+            NodeState::Running => f.write_str("running"),
+            NodeState::Reloading => f.write_str("reloading"),
+        }
+    }
+}
+
+/// Number, total size, and age of the oldest of a set of entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct EntryStats {
+    pub count: usize,
+    pub bytes: u64,
+    pub oldest: Option<Duration>,
+}
+
+impl EntryStats {
+    pub fn add(&mut self, bytes: u64, age: Duration) {
+        self.count += 1;
+        self.bytes += bytes;
+        self.oldest = Some(self.oldest.map_or(age, |oldest| oldest.max(age)));
+    }
+}
+
+impl fmt::Display for EntryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} bytes)", self.count, self.bytes)?;
+        if let Some(oldest) = self.oldest {
+            write!(f, ", oldest {:.1}s", oldest.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of drop reports for tokens that were not pending.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct DropTokenReports {
+    /// Reports of tokens that were freed shortly before, e.g. duplicate
+    /// reports or reports that arrive after the dataflow was torn down.
+    pub late: u64,
+    /// Reports of tokens that were never issued.
+    pub unknown: u64,
+}
+
+impl DropTokenReports {
+    pub fn merge(&mut self, other: DropTokenReports) {
+        self.late += other.late;
+        self.unknown += other.unknown;
+    }
+}
+
+impl fmt::Display for DropTokenReports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} late, {} unknown", self.late, self.unknown)
+    }
+}
+
+/// Number of received and lost messages of a best-effort output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct DatagramStats {
+    pub received: u64,
+    /// Messages that were not received completely, including messages that
+    /// completed after a newer message.
+    pub lost: u64,
+}
+
+impl DatagramStats {
+    /// Fraction of the messages that were lost, or `None` if no message was
+    /// sent yet.
+    pub fn loss_rate(&self) -> Option<f64> {
+        let total = self.received + self.lost;
+        (total > 0).then(|| self.lost as f64 / total as f64)
+    }
+}
+
+impl fmt::Display for DatagramStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} received, {} lost", self.received, self.lost)?;
+        if let Some(rate) = self.loss_rate() {
+            write!(f, " ({:.1}% loss)", rate * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// State of the `adaptive` downsampling of an input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct AdaptiveInputStats {
+    /// Only one of every `keep_every` messages is delivered, `1` at full
+    /// rate.
+    pub keep_every: u64,
+    /// Messages that were skipped by the downsampling.
+    pub downsampled: u64,
+    /// Number of times that the rate was reduced.
+    pub reductions: u64,
+    /// Number of times that the rate was raised again.
+    pub raises: u64,
+}
+
+impl fmt::Display for AdaptiveInputStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.keep_every {
+            1 => f.write_str("full rate")?,
+            n => write!(f, "1 of every {n} messages")?,
+        }
+        write!(
+            f,
+            ", {} downsampled, reduced {} times, raised {} times",
+            self.downsampled, self.reductions, self.raises
+        )
+    }
+}
+
+/// Drop tokens that were released because they were pending for too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct GcReport {
+    /// Age from which pending drop tokens were released.
+    pub older_than: Duration,
+    pub released_drop_tokens: usize,
+    /// Total size of the shared memory regions of the released drop tokens.
+    pub reclaimed_bytes: u64,
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "released {} drop tokens older than {:.1}s, reclaimed {} bytes",
+            self.released_drop_tokens,
+            self.older_than.as_secs_f64(),
+            self.reclaimed_bytes
+        )
+    }
+}
+
+impl fmt::Display for DaemonDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let machine = if self.machine_id.is_empty() {
+            "default machine".to_owned()
+        } else {
+            format!("machine `{}`", self.machine_id)
+        };
+        writeln!(f, "daemon on {machine}, up {}s", self.uptime.as_secs())?;
+        if let Some(gc) = &self.gc {
+            writeln!(f, "garbage collection: {gc}")?;
+        }
+        writeln!(f, "config:")?;
+        for (name, value) in &self.config {
+            writeln!(f, "  {name}: {value}")?;
+        }
+        writeln!(
+            f,
+            "shared memory: {} bytes in flight, peak {} bytes",
+            self.shared_memory_in_flight, self.peak_shared_memory
+        )?;
+        writeln!(
+            f,
+            "drop reports of freed tokens: {}",
+            self.drop_token_reports
+        )?;
+        writeln!(f, "dataflows: {}", self.dataflows.len())?;
+        for (id, dataflow) in &self.dataflows {
+            match &dataflow.instance {
+                Some(instance) => writeln!(f, "  {id} ({instance}):")?,
+                None => writeln!(f, "  {id}:")?,
+            }
+            writeln!(
+                f,
+                "    pending drop tokens: {}",
+                dataflow.pending_drop_tokens
+            )?;
+            writeln!(
+                f,
+                "    drop reports of freed tokens: {}",
+                dataflow.drop_token_reports
+            )?;
+            writeln!(f, "    output rings: {}", dataflow.output_rings)?;
+            writeln!(f, "    event queue depth: {}", dataflow.event_queue_depth)?;
+            if let Some(idle) = dataflow.stalled_for {
+                writeln!(f, "    stalled: no message delivered for {idle:.0?}")?;
+            }
+            for (source, stats) in &dataflow.best_effort_outputs {
+                writeln!(f, "    best-effort output `{source}`: {stats}")?;
+            }
+            for (input, stats) in &dataflow.adaptive_inputs {
+                writeln!(f, "    adaptive input `{input}`: {stats}")?;
+            }
+            for (input, settings) in &dataflow.reconfigured_inputs {
+                writeln!(f, "    reconfigured input `{input}`: {settings}")?;
+            }
+            for (node_id, node) in &dataflow.nodes {
+                write!(f, "    node `{node_id}`: {}", node.state)?;
+                if let Some(pid) = node.pid {
+                    write!(f, " (pid {pid})")?;
+                }
+                if let Some(timeout) = node.ready_timeout {
+                    write!(
+                        f,
+                        ", not ready yet (ready timeout {:.1}s)",
+                        timeout.as_secs_f64()
+                    )?;
+                }
+                write!(f, ", {} pending drop tokens", node.pending_drop_tokens)?;
+                if !node.open_inputs.is_empty() {
+                    let inputs: Vec<_> = node.open_inputs.iter().map(|i| i.to_string()).collect();
+                    write!(f, ", open inputs: {}", inputs.join(", "))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        writeln!(f, "recent warnings: {}", self.recent_warnings.len())?;
+        for warning in &self.recent_warnings {
+            writeln!(f, "  {warning}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use aligned_vec::{AVec, ConstAlign};
+
+use crate::metadata::Metadata;
+
+/// First message that an external process sends after connecting to the
+/// external endpoint server of a dataflow.
+///
+/// All messages on the connection are bincode-encoded and prefixed with their
+/// length as little-endian `u64`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub enum ExternalRequest {
+    /// Receive all messages of the exposed output with the given name.
+    ///
+    /// The daemon answers with an [`ExternalReply`][crate::daemon_to_external::ExternalReply]
+    /// and then sends an [`ExternalMessage`] for every message of the output.
+    Subscribe { token: String, name: String },
+    /// Send messages to the external input with the given name.
+    ///
+    /// The daemon answers with an [`ExternalReply`][crate::daemon_to_external::ExternalReply].
+    /// Afterwards, the external process can send [`ExternalMessage`]s.
+    Publish { token: String, name: String },
+}
+
+/// A message that is exchanged with an external process.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ExternalMessage {
+    pub metadata: Metadata,
+    pub data: Option<AVec<u8, ConstAlign<128>>>,
+}
@@ -4,6 +4,8 @@
 #![allow(clippy::missing_safety_doc)]
 
 pub mod common;
+pub mod compat;
+mod depth_limit;
 pub mod metadata;
 
 pub mod coordinator_to_daemon;
@@ -17,9 +19,20 @@ pub mod node_to_daemon;
 pub mod cli_to_coordinator;
 pub mod coordinator_to_cli;
 
+pub mod daemon_to_external;
+pub mod external_to_daemon;
+
+pub mod diagnostics;
+pub mod migration;
+pub mod plan;
+pub mod reconfigure;
+pub mod summary;
+
 pub type DataflowId = uuid::Uuid;
 
-fn current_crate_version() -> semver::Version {
+/// Version of the message format, which is exchanged between nodes and
+/// daemons to check their compatibility.
+pub fn current_crate_version() -> semver::Version {
     let crate_version_raw = env!("CARGO_PKG_VERSION");
     let crate_version = semver::Version::parse(crate_version_raw).unwrap();
     crate_version
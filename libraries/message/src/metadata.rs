@@ -1,4 +1,7 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use arrow_data::ArrayData;
 use arrow_schema::DataType;
@@ -6,14 +9,29 @@ use dora_core::uhlc;
 use eyre::Context;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Metadata {
     metadata_version: u16,
     timestamp: uhlc::Timestamp,
+    #[serde(deserialize_with = "deserialize_type_info")]
     pub type_info: ArrowTypeInfo,
     pub parameters: MetadataParameters,
 }
 
+/// Maximum number of nested containers in a received [`ArrowTypeInfo`].
+///
+/// Each level of a nested arrow type takes a few containers (e.g. the
+/// `ArrowTypeInfo` struct, its `child_data` list, and the `DataType` enum),
+/// so this allows for types that are nested far deeper than in practice.
+const MAX_TYPE_INFO_DEPTH: usize = 128;
+
+fn deserialize_type_info<'de, D>(deserializer: D) -> Result<ArrowTypeInfo, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    crate::depth_limit::deserialize(deserializer, MAX_TYPE_INFO_DEPTH)
+}
+
 impl Metadata {
     pub fn new(timestamp: uhlc::Timestamp, type_info: ArrowTypeInfo) -> Self {
         Self::from_parameters(timestamp, type_info, Default::default())
@@ -36,6 +54,61 @@ impl Metadata {
         self.timestamp
     }
 
+    /// The HLC timestamp that the sending node took when it sent the message.
+    ///
+    /// Same as [`Self::timestamp`].
+    pub fn publish_timestamp(&self) -> uhlc::Timestamp {
+        self.timestamp
+    }
+
+    /// The time at which the data of this message was captured, if the
+    /// sending node provided one (see [`set_source_timestamp`]).
+    ///
+    /// For sensor data, this is typically the hardware time of the
+    /// measurement, e.g. the exposure time of a camera frame. Unlike the
+    /// [publish timestamp](Self::publish_timestamp), it is not affected by
+    /// processing delays in the sending node.
+    pub fn source_timestamp(&self) -> Option<uhlc::NTP64> {
+        let nanos = self.get_int(SOURCE_TIMESTAMP_PARAMETER)?;
+        let nanos = u64::try_from(nanos).ok()?;
+        Some(uhlc::NTP64::from(Duration::from_nanos(nanos)))
+    }
+
+    /// The time that should be used to align this message with messages of
+    /// other inputs.
+    ///
+    /// This is the source timestamp if there is one, and the time of the
+    /// publish timestamp otherwise.
+    pub fn sync_time(&self) -> uhlc::NTP64 {
+        self.source_timestamp()
+            .unwrap_or(*self.timestamp.get_time())
+    }
+
+    /// Checks that the source timestamp, if set, is a valid time that is at
+    /// most [`MAX_SOURCE_TIMESTAMP_AHEAD`] after the publish timestamp.
+    pub fn check_source_timestamp(&self) -> Result<(), String> {
+        let Some(value) = self.parameters.get(SOURCE_TIMESTAMP_PARAMETER) else {
+            return Ok(());
+        };
+        let Some(source) = self.source_timestamp() else {
+            return Err(format!(
+                "source timestamp must be a positive integer (nanoseconds since the UNIX \
+                epoch), got {value:?}"
+            ));
+        };
+        let publish = *self.timestamp.get_time();
+        if source > publish {
+            let ahead = (source - publish).to_duration();
+            if ahead > MAX_SOURCE_TIMESTAMP_AHEAD {
+                return Err(format!(
+                    "source timestamp is {:.1}s ahead of the publish time",
+                    ahead.as_secs_f64()
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn open_telemetry_context(&self) -> String {
         if let Some(Parameter::String(otel)) = self.parameters.get("open_telemetry_context") {
             otel.to_string()
@@ -43,10 +116,132 @@ impl Metadata {
             "".to_string()
         }
     }
+
+    /// The output (`<node>/<output>`) that sent this message, if it was
+    /// received through an input with multiple sources.
+    pub fn input_source(&self) -> Option<&str> {
+        match self.parameters.get(INPUT_SOURCE_PARAMETER) {
+            Some(Parameter::String(source)) => Some(source),
+            _ => None,
+        }
+    }
+
+    /// The estimated offset of the sending machine's clock to the
+    /// coordinator's clock in nanoseconds, for messages from other machines.
+    ///
+    /// Subtracting the offset of the receiving machine gives an estimate of
+    /// the clock skew between the sending and the receiving machine.
+    pub fn clock_offset_hint(&self) -> Option<i64> {
+        match self.parameters.get(CLOCK_OFFSET_HINT_PARAMETER) {
+            Some(Parameter::Integer(offset)) => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// The number of older messages that were replaced by this message, for
+    /// inputs with `latest: true`.
+    pub fn superseded(&self) -> u64 {
+        match self.parameters.get(SUPERSEDED_PARAMETER) {
+            Some(Parameter::Integer(count)) => (*count).try_into().unwrap_or_default(),
+            _ => 0,
+        }
+    }
+
+    /// The hash under which the message is stored in the persistent output
+    /// cache of the daemon, if set by the sending node.
+    pub fn content_hash(&self) -> Option<&str> {
+        match self.parameters.get(CONTENT_HASH_PARAMETER) {
+            Some(Parameter::String(hash)) => Some(hash),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.parameters.get(key)?.as_bool()
+    }
+
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        self.parameters.get(key)?.as_int()
+    }
+
+    pub fn get_float(&self, key: &str) -> Option<f64> {
+        self.parameters.get(key)?.as_float()
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.parameters.get(key)?.as_str()
+    }
+
+    pub fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+        self.parameters.get(key)?.as_bytes()
+    }
+
+    pub fn get_int_list(&self, key: &str) -> Option<&[i64]> {
+        self.parameters.get(key)?.as_int_list()
+    }
+
+    pub fn get_float_list(&self, key: &str) -> Option<&[f64]> {
+        self.parameters.get(key)?.as_float_list()
+    }
+
+    pub fn get_str_list(&self, key: &str) -> Option<&[String]> {
+        self.parameters.get(key)?.as_str_list()
+    }
+
+    /// Sets the given parameter, replacing any previous value.
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<Parameter>) {
+        self.parameters.insert(key.into(), value.into());
+    }
 }
 
 pub type MetadataParameters = BTreeMap<String, Parameter>;
 
+/// Metadata parameter that the daemon sets on messages delivered to inputs
+/// with multiple sources. The value is the sending output as `<node>/<output>`.
+pub const INPUT_SOURCE_PARAMETER: &str = "input_source";
+
+/// Metadata parameter that the daemon sets on messages delivered to inputs
+/// with `latest: true` if older pending messages were replaced by it.
+pub const SUPERSEDED_PARAMETER: &str = "superseded";
+
+/// Metadata parameter that the daemon sets on messages sent to other
+/// machines. The value is the estimated offset of the sender's clock to the
+/// coordinator's clock, see [`ClockOffset`][crate::daemon_to_coordinator::ClockOffset].
+pub const CLOCK_OFFSET_HINT_PARAMETER: &str = "clock_offset_hint";
+
+/// Metadata parameter for the time at which the data of a message was
+/// captured, set by the sending node. The value is in nanoseconds since the
+/// UNIX epoch, see [`Metadata::source_timestamp`].
+pub const SOURCE_TIMESTAMP_PARAMETER: &str = "source_timestamp";
+
+/// Metadata parameter for the hash of the payload of messages on persistent
+/// outputs, set by the sending node. The message is stored in the persistent
+/// output cache of the daemon under this hash. If not set, the daemon uses
+/// the SHA-256 hash of the payload.
+pub const CONTENT_HASH_PARAMETER: &str = "content_hash";
+
+/// How far the source timestamp of a message may be ahead of its publish
+/// timestamp, to allow for some skew between the sensor and the system clock.
+pub const MAX_SOURCE_TIMESTAMP_AHEAD: Duration = Duration::from_secs(60);
+
+/// Sets the source timestamp of a message that is about to be sent, see
+/// [`Metadata::source_timestamp`].
+pub fn set_source_timestamp(
+    parameters: &mut MetadataParameters,
+    time: SystemTime,
+) -> eyre::Result<()> {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .context("source timestamp is before the UNIX epoch")?
+        .as_nanos();
+    let nanos = i64::try_from(nanos).context("source timestamp is too far in the future")?;
+    parameters.insert(
+        SOURCE_TIMESTAMP_PARAMETER.to_owned(),
+        Parameter::Integer(nanos),
+    );
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ArrowTypeInfo {
     pub data_type: DataType,
@@ -129,11 +324,115 @@ impl ArrowTypeInfo {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+/// Typed value of a metadata parameter.
+///
+/// New variants must be added at the end to keep the encoding of the
+/// existing variants.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Parameter {
     Bool(bool),
     Integer(i64),
     String(String),
+    Float(f64),
+    Bytes(Vec<u8>),
+    ListInt(Vec<i64>),
+    ListFloat(Vec<f64>),
+    ListString(Vec<String>),
+}
+
+// With the `legacy-string-parameters` feature, the getters also parse
+// stringified values, e.g. `String("640")` for `as_int`. This keeps nodes
+// working that were written before parameters were typed.
+impl Parameter {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Parameter::Bool(value) => Some(*value),
+            #[cfg(feature = "legacy-string-parameters")]
+            Parameter::String(value) => value.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Parameter::Integer(value) => Some(*value),
+            #[cfg(feature = "legacy-string-parameters")]
+            Parameter::String(value) => value.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Parameter::Float(value) => Some(*value),
+            #[cfg(feature = "legacy-string-parameters")]
+            Parameter::String(value) => value.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Parameter::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Parameter::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_list(&self) -> Option<&[i64]> {
+        match self {
+            Parameter::ListInt(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_float_list(&self) -> Option<&[f64]> {
+        match self {
+            Parameter::ListFloat(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str_list(&self) -> Option<&[String]> {
+        match self {
+            Parameter::ListString(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from_for_parameter {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Parameter {
+                fn from(value: $ty) -> Self {
+                    Parameter::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_parameter! {
+    bool => Bool,
+    i64 => Integer,
+    i32 => Integer,
+    u32 => Integer,
+    f64 => Float,
+    f32 => Float,
+    String => String,
+    &str => String,
+    Vec<u8> => Bytes,
+    &[u8] => Bytes,
+    Vec<i64> => ListInt,
+    Vec<f64> => ListFloat,
+    Vec<String> => ListString,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -141,3 +440,44 @@ pub struct BufferOffset {
     pub offset: usize,
     pub len: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stringified_parameters_need_legacy_feature() {
+        let parameter = Parameter::from("640");
+        assert_eq!(parameter.as_str(), Some("640"));
+        let expected = cfg!(feature = "legacy-string-parameters").then_some(640);
+        assert_eq!(parameter.as_int(), expected);
+        assert_eq!(Parameter::from(640).as_str(), None);
+    }
+
+    #[test]
+    fn source_timestamp_is_validated() {
+        let hlc = uhlc::HLC::default();
+        let mut metadata = Metadata::new(hlc.new_timestamp(), ArrowTypeInfo::empty());
+        assert_eq!(metadata.source_timestamp(), None);
+        assert_eq!(
+            metadata.sync_time(),
+            *metadata.publish_timestamp().get_time()
+        );
+        assert!(metadata.check_source_timestamp().is_ok());
+
+        let exposure = SystemTime::now() - Duration::from_millis(30);
+        set_source_timestamp(&mut metadata.parameters, exposure).unwrap();
+        let source = metadata.source_timestamp().unwrap();
+        assert!(source < *metadata.publish_timestamp().get_time());
+        assert_eq!(metadata.sync_time(), source);
+        assert!(metadata.check_source_timestamp().is_ok());
+
+        let future = SystemTime::now() + MAX_SOURCE_TIMESTAMP_AHEAD * 2;
+        set_source_timestamp(&mut metadata.parameters, future).unwrap();
+        assert!(metadata.check_source_timestamp().is_err());
+
+        metadata.set(SOURCE_TIMESTAMP_PARAMETER, -1);
+        assert_eq!(metadata.source_timestamp(), None);
+        assert!(metadata.check_source_timestamp().is_err());
+    }
+}
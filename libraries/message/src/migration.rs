@@ -0,0 +1,83 @@
+//! Report of a node migration through a `MigrateNode` request.
+//!
+//! The coordinator migrates a node in several phases, each of which involves
+//! one or more daemons. The report lists the outcome of every phase that was
+//! attempted, so that a failed migration shows how far it got and whether
+//! it was reverted.
+
+use std::{fmt, time::Duration};
+
+/// Step of a node migration, in the order in which they are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum MigrationPhase {
+    /// The node is spawned on the target machine, without receiving inputs.
+    Spawn,
+    /// The machines of the dataflow route the inputs of the node to the
+    /// target machine.
+    Switch,
+    /// The instance on the source machine is stopped.
+    StopOld,
+    /// The daemons forget the migration state.
+    Confirm,
+    /// The routes are switched back to the source machine and the new
+    /// instance is stopped, after a failed `Switch`.
+    Revert,
+}
+
+impl fmt::Display for MigrationPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MigrationPhase::Spawn => "spawn",
+            MigrationPhase::Switch => "switch",
+            MigrationPhase::StopOld => "stop old",
+            MigrationPhase::Confirm => "confirm",
+            MigrationPhase::Revert => "revert",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct MigrationPhaseReport {
+    pub phase: MigrationPhase,
+    pub duration: Duration,
+    pub result: Result<(), String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NodeMigrationReport {
+    pub from_machine: String,
+    pub to_machine: String,
+    pub phases: Vec<MigrationPhaseReport>,
+}
+
+impl NodeMigrationReport {
+    /// Whether the node runs on the target machine now.
+    pub fn is_success(&self) -> bool {
+        self.phases.iter().all(|phase| phase.result.is_ok())
+            && self
+                .phases
+                .last()
+                .is_some_and(|phase| phase.phase == MigrationPhase::Confirm)
+    }
+}
+
+impl fmt::Display for NodeMigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let machine = |m: &str| if m.is_empty() { "<default>" } else { m }.to_owned();
+        writeln!(
+            f,
+            "migration from machine `{}` to `{}`:",
+            machine(&self.from_machine),
+            machine(&self.to_machine)
+        )?;
+        for phase in &self.phases {
+            let duration = phase.duration.as_secs_f64();
+            match &phase.result {
+                Ok(()) => writeln!(f, "  {}: ok ({duration:.3}s)", phase.phase)?,
+                Err(err) => writeln!(f, "  {}: failed ({duration:.3}s): {err}", phase.phase)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,26 +1,60 @@
 pub use crate::common::{
-    DataMessage, DropToken, LogLevel, LogMessage, SharedMemoryId, Timestamped,
+    DataMessage, DropToken, LogLevel, LogMessage, OutputRingId, SharedMemoryId, Timestamped,
+};
+use crate::{
+    current_crate_version, daemon_to_node::NodeEvent, metadata::Metadata, versions_compatible,
+    DataflowId,
 };
-use crate::{current_crate_version, metadata::Metadata, versions_compatible, DataflowId};
 
 use dora_core::config::{DataId, NodeId};
+use std::time::Duration;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum DaemonRequest {
     Register(NodeRegisterRequest),
-    Subscribe,
+    Subscribe {
+        /// Kinds of events that the daemon sends to the node.
+        #[serde(default)]
+        interest: EventInterest,
+    },
+    /// Sends a message on the given output.
+    ///
+    /// The daemon replies with an error if the output is not declared in the
+    /// node's config.
     SendMessage {
         output_id: DataId,
         metadata: Metadata,
         data: Option<DataMessage>,
     },
+    /// Sends a message without payload on the given output.
+    ///
+    /// Used for zero-length messages, which don't need a data buffer. The
+    /// receivers get an empty array of the type given in the metadata.
+    SendEmptyMessage {
+        output_id: DataId,
+        metadata: Metadata,
+    },
     CloseOutputs(Vec<DataId>),
     /// Signals that the node is finished sending outputs and that it received all
     /// required drop tokens.
     OutputsDone,
+    /// Signals that the node finished its initialization.
+    ///
+    /// Nodes are also considered ready when they subscribe to their events.
+    /// Stops the `ready_timeout` of the node, if any.
+    Ready,
     NextEvent {
         drop_tokens: Vec<DropToken>,
     },
+    /// Takes the queued control events that may be delivered before the
+    /// other queued events, without waiting for new events.
+    ///
+    /// These are `Stop` events and `InputClosed` events that no message of
+    /// the same input is queued before. Allows the node to see a `Stop` while
+    /// it is still busy with previously received inputs. The daemon replies
+    /// with a `NextEvents` reply, which is empty if no control event is
+    /// queued.
+    NextControlEvents,
     ReportDropTokens {
         drop_tokens: Vec<DropToken>,
     },
@@ -30,44 +64,269 @@ pub enum DaemonRequest {
     NodeConfig {
         node_id: NodeId,
     },
+    /// Requests the resolved inputs and outputs of the node and the IDs of
+    /// the nodes connected to it.
+    QueryTopology,
+    /// Requests the number of receivers that the given output of the node
+    /// currently reaches.
+    ///
+    /// These are the subscribed local receivers, the receivers on other
+    /// machines, and the observers and external subscribers of the output.
+    /// Changes between zero and a nonzero number are also pushed to the node
+    /// as [`SubscribersChanged`][crate::daemon_to_node::NodeEvent::SubscribersChanged]
+    /// events.
+    QuerySubscribers {
+        output_id: DataId,
+    },
+    /// Takes the pending message of the given `latest` input, if any.
+    ///
+    /// Sent in response to a [`NodeEvent::LatestAvailable`][crate::daemon_to_node::NodeEvent::LatestAvailable]
+    /// event.
+    TakeLatest {
+        id: DataId,
+    },
+    /// Allocates a ring of `slots` shared memory regions of `slot_len` bytes
+    /// for sending messages on the given output.
+    ///
+    /// The regions are allocated once and reused for every message that is
+    /// sent through [`SendOutSlot`][Self::SendOutSlot]. The daemon replies
+    /// with the ring ID and the IDs of the shared memory regions.
+    PrepareOutputRing {
+        output_id: DataId,
+        slot_len: usize,
+        slots: usize,
+    },
+    /// Sends the first `valid_len` bytes of the given slot of an output ring.
+    ///
+    /// The daemon rejects the message with a [`SlotBusy`][crate::daemon_to_node::SendOutputError::SlotBusy]
+    /// error if the previous message sent from this slot is still accessed
+    /// by receivers.
+    SendOutSlot {
+        ring_id: OutputRingId,
+        slot_index: usize,
+        valid_len: usize,
+        metadata: Metadata,
+    },
+    /// Sends the message that is stored under the given content hash in the
+    /// persistent output cache of the daemon, without copying it again.
+    ///
+    /// The type info of the given metadata is replaced by the stored one.
+    /// The daemon replies with the drop token of the message, or with `None`
+    /// if the cache holds no message with this hash for the output.
+    SendCached {
+        output_id: DataId,
+        metadata: Metadata,
+        hash: String,
+    },
+    /// Wraps a request that is answered with a [`Multiplexed`][crate::daemon_to_node::DaemonReply::Multiplexed]
+    /// reply with the same `request_id`.
+    ///
+    /// Allows a node to send requests from multiple threads over a single
+    /// connection, as it matches the replies by ID instead of relying on
+    /// their order. Multiplexed requests can't be nested.
+    Multiplexed {
+        request_id: u64,
+        #[serde(deserialize_with = "deserialize_multiplexed_request")]
+        request: Box<DaemonRequest>,
+    },
+    /// Receive copies of the messages of the given output, without being part
+    /// of the dataflow.
+    ///
+    /// Sent as first request on a connection to the local listener of the
+    /// daemon, or right after a [`Join`][Self::Join]. The daemon replies with a `Result` and then sends an
+    /// [`ObservedMessage`][crate::daemon_to_node::DaemonReply::ObservedMessage]
+    /// reply for every message of the output, until the connection is
+    /// closed. Observers don't affect the delivery of the output to the
+    /// nodes of the dataflow. If an observer doesn't keep up, messages are
+    /// skipped for it.
+    Observe {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output_id: DataId,
+    },
+    /// Authenticates a connection to the local listener of the daemon with a
+    /// join token, as minted for a `ResolveNode` request to the coordinator.
+    ///
+    /// Required as first request on connections from other machines. The
+    /// following `NodeConfig` or `Observe` request must be for the node that
+    /// the token was minted for. The daemon replies with a `Result`.
+    Join {
+        token: String,
+    },
+    /// Stops the whole dataflow, like `dora stop`.
+    ///
+    /// Only allowed for nodes with `allowed_to_stop: true`, the daemon
+    /// rejects requests of other nodes with a
+    /// [`PermissionDenied`][crate::daemon_to_node::StopRequestError::PermissionDenied]
+    /// error. The requesting node is reported as initiator in the result of
+    /// the dataflow.
+    RequestDataflowStop {
+        grace_duration: Option<Duration>,
+    },
+    /// Reports a fatal error of the node, e.g. a panic, right before the
+    /// node exits.
+    ///
+    /// If the node then exits with an error, the daemon reports the message
+    /// as cause instead of just the exit code. Only the first reported error
+    /// is kept.
+    NodeError {
+        message: String,
+    },
+}
+
+/// Maximum number of nested containers in the request of a
+/// [`DaemonRequest::Multiplexed`] request.
+///
+/// Leaves room for the nested type info of the wrapped request, while a
+/// malformed chain of nested multiplexed requests can't overflow the stack.
+const MAX_MULTIPLEXED_REQUEST_DEPTH: usize = 256;
+
+fn deserialize_multiplexed_request<'de, D>(deserializer: D) -> Result<Box<DaemonRequest>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    crate::depth_limit::deserialize(deserializer, MAX_MULTIPLEXED_REQUEST_DEPTH)
 }
 
 impl DaemonRequest {
     pub fn expects_tcp_bincode_reply(&self) -> bool {
         #[allow(clippy::match_like_matches_macro)]
         match self {
-            DaemonRequest::SendMessage { .. }
-            | DaemonRequest::NodeConfig { .. }
-            | DaemonRequest::ReportDropTokens { .. } => false,
+            DaemonRequest::Multiplexed { request, .. } => request.expects_tcp_bincode_reply(),
+            DaemonRequest::NodeConfig { .. } | DaemonRequest::ReportDropTokens { .. } => false,
             DaemonRequest::Register(NodeRegisterRequest { .. })
-            | DaemonRequest::Subscribe
+            | DaemonRequest::SendMessage { .. }
+            | DaemonRequest::SendEmptyMessage { .. }
+            | DaemonRequest::Subscribe { .. }
             | DaemonRequest::CloseOutputs(_)
             | DaemonRequest::OutputsDone
+            | DaemonRequest::Ready
             | DaemonRequest::NextEvent { .. }
+            | DaemonRequest::NextControlEvents
             | DaemonRequest::SubscribeDrop
             | DaemonRequest::NextFinishedDropTokens
-            | DaemonRequest::EventStreamDropped => true,
+            | DaemonRequest::EventStreamDropped
+            | DaemonRequest::QueryTopology
+            | DaemonRequest::QuerySubscribers { .. }
+            | DaemonRequest::TakeLatest { .. }
+            | DaemonRequest::PrepareOutputRing { .. }
+            | DaemonRequest::SendOutSlot { .. }
+            | DaemonRequest::SendCached { .. }
+            | DaemonRequest::Observe { .. }
+            | DaemonRequest::Join { .. }
+            | DaemonRequest::RequestDataflowStop { .. }
+            | DaemonRequest::NodeError { .. } => true,
         }
     }
 
     pub fn expects_tcp_json_reply(&self) -> bool {
         #[allow(clippy::match_like_matches_macro)]
         match self {
+            DaemonRequest::Multiplexed { request, .. } => request.expects_tcp_json_reply(),
             DaemonRequest::NodeConfig { .. } => true,
             DaemonRequest::Register(NodeRegisterRequest { .. })
-            | DaemonRequest::Subscribe
+            | DaemonRequest::Subscribe { .. }
             | DaemonRequest::CloseOutputs(_)
             | DaemonRequest::OutputsDone
+            | DaemonRequest::Ready
             | DaemonRequest::NextEvent { .. }
+            | DaemonRequest::NextControlEvents
             | DaemonRequest::SubscribeDrop
             | DaemonRequest::NextFinishedDropTokens
             | DaemonRequest::ReportDropTokens { .. }
             | DaemonRequest::SendMessage { .. }
-            | DaemonRequest::EventStreamDropped => false,
+            | DaemonRequest::SendEmptyMessage { .. }
+            | DaemonRequest::EventStreamDropped
+            | DaemonRequest::QueryTopology
+            | DaemonRequest::QuerySubscribers { .. }
+            | DaemonRequest::TakeLatest { .. }
+            | DaemonRequest::PrepareOutputRing { .. }
+            | DaemonRequest::SendOutSlot { .. }
+            | DaemonRequest::SendCached { .. }
+            | DaemonRequest::Observe { .. }
+            | DaemonRequest::Join { .. }
+            | DaemonRequest::RequestDataflowStop { .. }
+            | DaemonRequest::NodeError { .. } => false,
         }
     }
 }
 
+/// Kinds of events that a node wants to receive, declared when subscribing.
+///
+/// The daemon doesn't send events of other kinds to the node. Nodes without
+/// interest in [`INPUTS`](Self::INPUTS) and [`INPUT_CLOSED`](Self::INPUT_CLOSED)
+/// are not sent an `AllInputsClosed` event, so their event stream stays open
+/// for control events until the dataflow is stopped.
+///
+/// Unknown bits are reserved for event kinds of future versions (e.g. error
+/// notifications), which are included in [`ALL`](Self::ALL).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EventInterest(u32);
+
+impl EventInterest {
+    /// Messages of data and timer inputs, and `InputTimeout` events.
+    pub const INPUTS: Self = Self(1 << 0);
+    /// `InputClosed` and `AllInputsClosed` events.
+    pub const INPUT_CLOSED: Self = Self(1 << 1);
+    /// `Stop` events.
+    pub const STOP: Self = Self(1 << 2);
+    /// `Reload` events of operators.
+    pub const RELOAD: Self = Self(1 << 3);
+    /// `SubscribersChanged` events of the outputs of the node.
+    pub const SUBSCRIBERS: Self = Self(1 << 4);
+    /// All events, the default.
+    pub const ALL: Self = Self(u32::MAX);
+    /// All events except inputs and their close events, for nodes that
+    /// don't have any inputs.
+    pub const CONTROL: Self = Self::ALL.without(Self::INPUTS).without(Self::INPUT_CLOSED);
+
+    /// Default interest of a node, depending on whether it has inputs.
+    pub const fn for_node(has_inputs: bool) -> Self {
+        if has_inputs {
+            Self::ALL
+        } else {
+            Self::CONTROL
+        }
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Checks whether the given event is of a kind that the node wants.
+    pub fn wants(self, event: &NodeEvent) -> bool {
+        let kind = match event {
+            NodeEvent::Input { .. }
+            | NodeEvent::InputBatch { .. }
+            | NodeEvent::LatestAvailable { .. }
+            | NodeEvent::InputTimeout { .. } => Self::INPUTS,
+            NodeEvent::InputClosed { .. } | NodeEvent::AllInputsClosed => Self::INPUT_CLOSED,
+            NodeEvent::Stop => Self::STOP,
+            NodeEvent::Reload { .. } => Self::RELOAD,
+            NodeEvent::SubscribersChanged { .. } => Self::SUBSCRIBERS,
+        };
+        self.contains(kind)
+    }
+}
+
+impl Default for EventInterest {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl std::ops::BitOr for EventInterest {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct NodeRegisterRequest {
     pub dataflow_id: DataflowId,
@@ -131,3 +390,35 @@ pub struct SharedMemoryInput {
 pub enum DynamicNodeEvent {
     NodeConfig { node_id: NodeId },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_interest() {
+        let input = NodeEvent::Input {
+            id: DataId::from("image".to_owned()),
+            metadata: Metadata::new(
+                dora_core::uhlc::HLC::default().new_timestamp(),
+                crate::metadata::ArrowTypeInfo::empty(),
+            ),
+            data: None,
+        };
+        assert!(EventInterest::default().wants(&input));
+        assert!(!EventInterest::CONTROL.wants(&input));
+        assert!(!EventInterest::CONTROL.wants(&NodeEvent::AllInputsClosed));
+        assert!(EventInterest::CONTROL.wants(&NodeEvent::Stop));
+        assert!(!EventInterest::INPUTS.wants(&NodeEvent::Stop));
+        assert!((EventInterest::INPUTS | EventInterest::STOP).wants(&NodeEvent::Stop));
+
+        // the interest defaults to all events
+        let request: DaemonRequest = serde_json::from_str(r#"{"Subscribe":{}}"#).unwrap();
+        assert!(matches!(
+            request,
+            DaemonRequest::Subscribe {
+                interest: EventInterest::ALL
+            }
+        ));
+    }
+}
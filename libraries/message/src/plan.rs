@@ -0,0 +1,122 @@
+//! Plan of a dataflow start, as reported by `dora start --dry-run`.
+//!
+//! The plan describes what the daemons would spawn after all defaults and
+//! substitutions were applied. Nothing is spawned to create it.
+
+use std::{collections::BTreeMap, fmt, path::PathBuf, time::Duration};
+
+use dora_core::config::{format_duration, DataId, NodeId};
+
+use crate::daemon_to_daemon::InterDaemonTransport;
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DataflowPlan {
+    pub name: Option<String>,
+    /// Machines that the coordinator picked for nodes without a fixed
+    /// machine.
+    pub assignments: BTreeMap<NodeId, String>,
+    pub inter_daemon_transport: InterDaemonTransport,
+    /// Plan of each machine that runs nodes of the dataflow, by machine ID.
+    pub machines: BTreeMap<String, MachinePlan>,
+    /// Warnings about valid, but likely unintended configurations.
+    pub warnings: Vec<String>,
+}
+
+/// Plan of a single daemon for its local nodes.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct MachinePlan {
+    /// Working directory of the dataflow on this machine.
+    pub working_dir: PathBuf,
+    /// Local nodes, in spawn order.
+    pub nodes: Vec<NodePlan>,
+    /// Inputs of local nodes that each timer drives, as `node/input`.
+    pub timers: BTreeMap<Duration, Vec<String>>,
+    /// Local outputs that are exposed to external clients, by name.
+    pub exposed_outputs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NodePlan {
+    pub id: NodeId,
+    /// Start layer of the node, counting from 1.
+    ///
+    /// All nodes are in the same layer unless the dataflow uses
+    /// `start_order: dependency`.
+    pub layer: usize,
+    pub working_dir: PathBuf,
+    /// Process that runs the node, or `None` for dynamic nodes, which are
+    /// started by the user.
+    pub command: Option<NodeCommand>,
+    /// Sources of each input, e.g. `camera/image` or `dora/timer/millis/100`.
+    pub inputs: BTreeMap<DataId, Vec<String>>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NodeCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Environment variables that are set in addition to the environment of
+    /// the daemon.
+    ///
+    /// The serialized node configuration is omitted, as it is only known
+    /// once the node is spawned.
+    pub env: BTreeMap<String, String>,
+}
+
+impl fmt::Display for DataflowPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => writeln!(f, "dataflow `{name}`")?,
+            None => writeln!(f, "unnamed dataflow")?,
+        }
+        writeln!(f, "inter-daemon transport: {}", self.inter_daemon_transport)?;
+        for (machine_id, machine) in &self.machines {
+            if machine_id.is_empty() {
+                writeln!(f, "default machine:")?;
+            } else {
+                writeln!(f, "machine `{machine_id}`:")?;
+            }
+            writeln!(f, "  working dir: {}", machine.working_dir.display())?;
+            for node in &machine.nodes {
+                write!(f, "  node `{}` (layer {})", node.id, node.layer)?;
+                if let Some(machine) = self.assignments.get(&node.id) {
+                    write!(f, ", assigned to `{machine}`")?;
+                }
+                writeln!(f)?;
+                writeln!(f, "    working dir: {}", node.working_dir.display())?;
+                match &node.command {
+                    Some(command) => {
+                        write!(f, "    command: {}", command.program.display())?;
+                        for arg in &command.args {
+                            write!(f, " {arg}")?;
+                        }
+                        writeln!(f)?;
+                        for (key, value) in &command.env {
+                            writeln!(f, "    env: {key}={value}")?;
+                        }
+                    }
+                    None => writeln!(f, "    dynamic node, started by the user")?,
+                }
+                for (input_id, sources) in &node.inputs {
+                    writeln!(f, "    input `{input_id}` <- {}", sources.join(", "))?;
+                }
+            }
+            for (interval, inputs) in &machine.timers {
+                writeln!(
+                    f,
+                    "  timer dora/timer/{} -> {}",
+                    format_duration(*interval),
+                    inputs.join(", ")
+                )?;
+            }
+            for (name, output) in &machine.exposed_outputs {
+                writeln!(f, "  exposed output `{name}` <- {output}")?;
+            }
+        }
+        writeln!(f, "warnings: {}", self.warnings.len())?;
+        for warning in &self.warnings {
+            writeln!(f, "  {warning}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Changes of the input settings of a running dataflow through a
+//! `Reconfigure` request.
+//!
+//! Only settings that the daemon applies itself can be changed, without
+//! touching the running node processes: the queue size, the queue policy,
+//! the delivery priority, the minimum rate of `adaptive` downsampling, and
+//! the input timeout. Each change is applied or rejected on its own, so a
+//! request with an invalid target still applies its other changes.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use dora_core::config::{DataId, NodeId, Rate};
+
+/// What happens with new messages when the queue of an input is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueuePolicy {
+    /// The oldest queued messages are dropped, up to `queue_size` messages
+    /// are kept.
+    #[default]
+    DropOldest,
+    /// Only the newest message is kept, see the `latest` input option.
+    Latest,
+}
+
+impl fmt::Display for QueuePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueuePolicy::DropOldest => f.write_str("drop-oldest"),
+            QueuePolicy::Latest => f.write_str("latest"),
+        }
+    }
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(QueuePolicy::DropOldest),
+            "latest" => Ok(QueuePolicy::Latest),
+            other => Err(format!(
+                "unknown queue policy `{other}` (expected `drop-oldest` or `latest`)"
+            )),
+        }
+    }
+}
+
+/// New settings for a single input. Settings that are `None` keep their
+/// current value.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct InputChange {
+    pub node_id: NodeId,
+    pub input_id: DataId,
+    #[serde(default)]
+    pub queue_size: Option<usize>,
+    #[serde(default)]
+    pub policy: Option<QueuePolicy>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// Enables `adaptive` downsampling with the given minimum rate, or
+    /// changes the minimum rate if it is enabled already.
+    #[serde(default)]
+    pub adaptive_min_rate: Option<Rate>,
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+}
+
+impl InputChange {
+    pub fn new(node_id: NodeId, input_id: DataId) -> Self {
+        Self {
+            node_id,
+            input_id,
+            queue_size: None,
+            policy: None,
+            priority: None,
+            adaptive_min_rate: None,
+            timeout: None,
+        }
+    }
+}
+
+/// Effective settings of an input after a change.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct InputSettings {
+    pub queue_size: usize,
+    pub policy: QueuePolicy,
+    pub priority: u8,
+    pub adaptive_min_rate: Option<Rate>,
+    pub timeout: Option<Duration>,
+}
+
+impl fmt::Display for InputSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queue size {}, {}, priority {}",
+            self.queue_size, self.policy, self.priority
+        )?;
+        if let Some(rate) = self.adaptive_min_rate {
+            write!(f, ", adaptive (min {rate})")?;
+        }
+        if let Some(timeout) = self.timeout {
+            write!(f, ", timeout {timeout:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a single [`InputChange`].
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct InputChangeResult {
+    pub node_id: NodeId,
+    pub input_id: DataId,
+    pub result: Result<InputSettings, String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct ReconfigureReport {
+    /// The outcome of every requested change, in the order of the request.
+    pub inputs: Vec<InputChangeResult>,
+}
+
+impl ReconfigureReport {
+    /// Whether all changes were applied.
+    pub fn is_success(&self) -> bool {
+        self.inputs.iter().all(|input| input.result.is_ok())
+    }
+}
+
+impl fmt::Display for ReconfigureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for input in &self.inputs {
+            match &input.result {
+                Ok(settings) => writeln!(f, "{}/{}: {settings}", input.node_id, input.input_id)?,
+                Err(err) => writeln!(f, "{}/{}: failed: {err}", input.node_id, input.input_id)?,
+            }
+        }
+        Ok(())
+    }
+}
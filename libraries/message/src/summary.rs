@@ -0,0 +1,495 @@
+//! Machine-readable summary of a finished dataflow.
+//!
+//! The summary is written as JSON to the result file of the dataflow, so
+//! that tools like CI jobs can check the outcome of a dataflow without
+//! parsing logs. Changes to these types change the file format, so fields
+//! should only be added, not renamed or removed.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dora_core::config::{DataId, NodeId};
+
+use crate::{
+    common::{NodeError, NodeExitStatus},
+    daemon_to_coordinator::DataflowDaemonResult,
+    DataflowId,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DataflowSummary {
+    pub dataflow_id: DataflowId,
+    pub name: Option<String>,
+    /// Time at which the dataflow was started, in milliseconds since the Unix
+    /// epoch.
+    pub start_time_ms: u64,
+    /// Time at which the last node of the dataflow finished, in milliseconds
+    /// since the Unix epoch.
+    pub end_time_ms: u64,
+    pub nodes: BTreeMap<NodeId, NodeSummary>,
+    /// Highest total size of the shared memory regions that were in use at
+    /// the same time, in bytes.
+    ///
+    /// For dataflows that run on multiple machines, this is the highest
+    /// value of any machine.
+    pub peak_shared_memory: u64,
+    /// Parameters that the dataflow was started with.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Message counts of the connections between outputs and inputs, keyed
+    /// by source (`node_id/output_id`) and receiving input
+    /// (`node_id/input_id`).
+    #[serde(default)]
+    pub edges: BTreeMap<String, BTreeMap<String, EdgeSummary>>,
+    /// Node that stopped the dataflow through its node API, if any.
+    #[serde(default)]
+    pub stopped_by: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NodeSummary {
+    pub success: bool,
+    /// Exit code of the node process, if it exited normally.
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the node process.
+    pub signal: Option<i32>,
+    /// Description of the error, for failed nodes.
+    pub error: Option<String>,
+    pub inputs: BTreeMap<DataId, InputSummary>,
+    #[serde(default)]
+    pub outputs: BTreeMap<DataId, OutputSummary>,
+}
+
+/// Message counts of a node input.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InputSummary {
+    /// Number of messages that the daemon delivered to the input, by source
+    /// (`node_id/output_id` or `dora/timer/...`).
+    pub delivered: BTreeMap<String, u64>,
+    /// Number of messages that were dropped because the input queue was full.
+    ///
+    /// The source of dropped messages is not known, so inputs with multiple
+    /// sources only report the total.
+    pub dropped: u64,
+    /// Number of messages that were suppressed by the `throttle` or
+    /// `decimate` filter of the input.
+    #[serde(default)]
+    pub filtered: u64,
+}
+
+/// Message sizes of a node output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OutputSummary {
+    /// Sizes of the data of all messages that the node sent on the output.
+    pub message_sizes: SizeHistogram,
+}
+
+/// Message counts of the connection from an output to a node input.
+///
+/// Every message that is sent on the edge is either delivered or dropped,
+/// so `sent` should always equal `delivered` plus the `dropped` messages.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EdgeSummary {
+    /// Number of messages that reached the daemon of the receiver.
+    ///
+    /// Messages of publishers on other machines that were lost on the way
+    /// are not included, except for best-effort outputs, whose lost
+    /// messages are counted as dropped with [`EdgeDropReason::Lost`].
+    pub sent: u64,
+    /// Number of messages that were passed to the receiver, not including
+    /// messages that were dropped from its input queue afterwards.
+    pub delivered: u64,
+    /// Number of messages that were not delivered, by reason.
+    pub dropped: BTreeMap<EdgeDropReason, u64>,
+}
+
+/// Why a message was not delivered on an edge.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeDropReason {
+    /// The input queue of the receiver was full.
+    QueueFull,
+    /// The message was filtered out by the `throttle` or `decimate` of the
+    /// input.
+    Filtered,
+    /// The message was skipped by the `adaptive` downsampling of the input.
+    Downsampled,
+    /// The message was replaced by a newer message on a `latest` input.
+    Superseded,
+    /// The receiver didn't accept inputs anymore, e.g. because it stopped.
+    ReceiverClosed,
+    /// The message of a best-effort output was not received completely
+    /// from the machine of the sender.
+    Lost,
+}
+
+impl EdgeSummary {
+    pub fn record_drop(&mut self, reason: EdgeDropReason, count: u64) {
+        *self.dropped.entry(reason).or_default() += count;
+    }
+
+    /// Number of sent messages that were neither delivered nor dropped.
+    ///
+    /// Negative if more messages were counted as delivered or dropped than
+    /// were sent.
+    pub fn unaccounted(&self) -> i128 {
+        let dropped: u64 = self.dropped.values().sum();
+        i128::from(self.sent) - i128::from(self.delivered) - i128::from(dropped)
+    }
+
+    /// Fraction of the sent messages that were lost on the way, which is only
+    /// known for best-effort outputs.
+    ///
+    /// Returns `None` if no message was sent on the edge.
+    pub fn loss_rate(&self) -> Option<f64> {
+        let lost = self
+            .dropped
+            .get(&EdgeDropReason::Lost)
+            .copied()
+            .unwrap_or(0);
+        (self.sent > 0).then(|| lost as f64 / self.sent as f64)
+    }
+
+    fn merge(&mut self, other: &EdgeSummary) {
+        self.sent += other.sent;
+        self.delivered += other.delivered;
+        for (&reason, &count) in &other.dropped {
+            self.record_drop(reason, count);
+        }
+    }
+}
+
+impl fmt::Display for EdgeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} sent, {} delivered", self.sent, self.delivered)?;
+        for (reason, count) in &self.dropped {
+            write!(f, ", {count} dropped ({reason:?})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Histogram of message sizes with power-of-two buckets.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SizeHistogram {
+    pub count: u64,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+    /// Number of messages per bucket, keyed by the upper bound of the bucket.
+    ///
+    /// Messages of `n` bytes are counted in the bucket of the smallest power
+    /// of two that is at least `n`, empty messages in bucket `0`.
+    pub buckets: BTreeMap<u64, u64>,
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, len: u64) {
+        self.count += 1;
+        self.total_bytes = self.total_bytes.saturating_add(len);
+        self.max_bytes = self.max_bytes.max(len);
+        *self.buckets.entry(Self::bucket(len)).or_default() += 1;
+    }
+
+    /// Upper bound of the bucket that messages of the given size are counted in.
+    pub fn bucket(len: u64) -> u64 {
+        match len {
+            0 => 0,
+            len => len.checked_next_power_of_two().unwrap_or(u64::MAX),
+        }
+    }
+}
+
+impl fmt::Display for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} messages", self.count)?;
+        if let Some(average) = self.total_bytes.checked_div(self.count) {
+            write!(
+                f,
+                " of {average} bytes on average, {} bytes at most",
+                self.max_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl DataflowSummary {
+    /// Combines the results that the daemons reported for the dataflow.
+    pub fn new<'a>(
+        dataflow_id: DataflowId,
+        name: Option<String>,
+        start_time: SystemTime,
+        end_time: SystemTime,
+        results: impl IntoIterator<Item = &'a DataflowDaemonResult>,
+    ) -> Self {
+        let mut nodes: BTreeMap<NodeId, NodeSummary> = BTreeMap::new();
+        let mut edges: BTreeMap<String, BTreeMap<String, EdgeSummary>> = BTreeMap::new();
+        let mut peak_shared_memory = 0;
+        let mut stopped_by = None;
+        for result in results {
+            for (node_id, node_result) in &result.node_results {
+                let node = nodes.entry(node_id.clone()).or_default();
+                node.set_result(node_result);
+            }
+            for (node_id, inputs) in &result.inputs {
+                let node = nodes.entry(node_id.clone()).or_default();
+                node.inputs.extend(inputs.clone());
+            }
+            for (node_id, outputs) in &result.outputs {
+                let node = nodes.entry(node_id.clone()).or_default();
+                node.outputs.extend(outputs.clone());
+            }
+            for (source, receivers) in &result.edges {
+                let source = edges.entry(source.clone()).or_default();
+                for (receiver, edge) in receivers {
+                    source.entry(receiver.clone()).or_default().merge(edge);
+                }
+            }
+            peak_shared_memory = peak_shared_memory.max(result.peak_shared_memory);
+            stopped_by = stopped_by.or_else(|| result.stopped_by.clone());
+        }
+        Self {
+            dataflow_id,
+            name,
+            start_time_ms: unix_millis(start_time),
+            end_time_ms: unix_millis(end_time),
+            nodes,
+            peak_shared_memory,
+            params: BTreeMap::new(),
+            edges,
+            stopped_by,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.nodes.values().all(|n| n.success)
+    }
+
+    /// Edges with messages that were neither delivered nor dropped, as
+    /// `(source, receiver, summary)`.
+    pub fn unaccounted_edges(&self) -> impl Iterator<Item = (&str, &str, &EdgeSummary)> {
+        self.edges.iter().flat_map(|(source, receivers)| {
+            receivers
+                .iter()
+                .filter(|(_, edge)| edge.unaccounted() != 0)
+                .map(move |(receiver, edge)| (source.as_str(), receiver.as_str(), edge))
+        })
+    }
+}
+
+impl NodeSummary {
+    fn set_result(&mut self, result: &Result<(), NodeError>) {
+        self.success = result.is_ok();
+        (self.exit_code, self.signal, self.error) = match result {
+            Ok(()) => (Some(0), None, None),
+            Err(err) => {
+                let (exit_code, signal) = match err.exit_status {
+                    NodeExitStatus::ExitCode(code) => (Some(code), None),
+                    NodeExitStatus::Signal(signal) => (None, Some(signal)),
+                    NodeExitStatus::Success => (Some(0), None),
+                    NodeExitStatus::IoError(_) | NodeExitStatus::Unknown => (None, None),
+                };
+                (exit_code, signal, Some(err.to_string()))
+            }
+        };
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::NodeErrorCause;
+    use dora_core::uhlc::HLC;
+    use std::time::Duration;
+
+    #[test]
+    fn summary_format_is_stable() {
+        let clock = HLC::default();
+        let id = |s: &str| NodeId::from(s.to_owned());
+        let input = |delivered: &[(&str, u64)], dropped, filtered| InputSummary {
+            delivered: delivered
+                .iter()
+                .map(|(source, count)| (source.to_string(), *count))
+                .collect(),
+            dropped,
+            filtered,
+        };
+        let sizes = |lens: &[u64]| {
+            let mut message_sizes = SizeHistogram::default();
+            for len in lens {
+                message_sizes.record(*len);
+            }
+            OutputSummary { message_sizes }
+        };
+        let camera_machine = DataflowDaemonResult {
+            timestamp: clock.new_timestamp(),
+            node_results: [(id("camera"), Ok(()))].into(),
+            inputs: [(
+                id("camera"),
+                [(
+                    DataId::from("tick".to_owned()),
+                    input(&[("dora/timer/millis/100", 20)], 0, 0),
+                )]
+                .into(),
+            )]
+            .into(),
+            outputs: [(
+                id("camera"),
+                [(DataId::from("image".to_owned()), sizes(&[921_600; 20]))].into(),
+            )]
+            .into(),
+            peak_shared_memory: 4096,
+            edges: Default::default(),
+            stopped_by: Some(id("camera")),
+        };
+        let detector_machine = DataflowDaemonResult {
+            timestamp: clock.new_timestamp(),
+            node_results: [(
+                id("detector"),
+                Err(NodeError {
+                    timestamp: clock.new_timestamp(),
+                    cause: NodeErrorCause::Other {
+                        stderr: String::new(),
+                    },
+                    exit_status: NodeExitStatus::ExitCode(1),
+                }),
+            )]
+            .into(),
+            inputs: [(
+                id("detector"),
+                [(
+                    DataId::from("image".to_owned()),
+                    input(&[("camera/image", 15)], 2, 3),
+                )]
+                .into(),
+            )]
+            .into(),
+            outputs: Default::default(),
+            peak_shared_memory: 1024,
+            edges: [(
+                "camera/image".to_owned(),
+                [(
+                    "detector/image".to_owned(),
+                    EdgeSummary {
+                        sent: 20,
+                        delivered: 15,
+                        dropped: [
+                            (EdgeDropReason::QueueFull, 2),
+                            (EdgeDropReason::Filtered, 3),
+                        ]
+                        .into(),
+                    },
+                )]
+                .into(),
+            )]
+            .into(),
+            stopped_by: None,
+        };
+
+        let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let summary = DataflowSummary {
+            params: [("seed".to_owned(), "42".to_owned())].into(),
+            ..DataflowSummary::new(
+                DataflowId::nil(),
+                Some("detection".into()),
+                start,
+                start + Duration::from_millis(2500),
+                [&camera_machine, &detector_machine],
+            )
+        };
+        assert!(!summary.is_ok());
+        assert_eq!(summary.unaccounted_edges().count(), 0);
+
+        let expected = serde_json::json!({
+            "dataflow_id": "00000000-0000-0000-0000-000000000000",
+            "name": "detection",
+            "start_time_ms": 1_700_000_000_000u64,
+            "end_time_ms": 1_700_000_002_500u64,
+            "nodes": {
+                "camera": {
+                    "success": true,
+                    "exit_code": 0,
+                    "signal": null,
+                    "error": null,
+                    "inputs": {
+                        "tick": {
+                            "delivered": { "dora/timer/millis/100": 20 },
+                            "dropped": 0,
+                            "filtered": 0
+                        }
+                    },
+                    "outputs": {
+                        "image": {
+                            "message_sizes": {
+                                "count": 20,
+                                "total_bytes": 18_432_000,
+                                "max_bytes": 921_600,
+                                "buckets": { "1048576": 20 }
+                            }
+                        }
+                    }
+                },
+                "detector": {
+                    "success": false,
+                    "exit_code": 1,
+                    "signal": null,
+                    "error": "exited with code 1",
+                    "inputs": {
+                        "image": {
+                            "delivered": { "camera/image": 15 },
+                            "dropped": 2,
+                            "filtered": 3
+                        }
+                    },
+                    "outputs": {}
+                }
+            },
+            "peak_shared_memory": 4096,
+            "params": { "seed": "42" },
+            "edges": {
+                "camera/image": {
+                    "detector/image": {
+                        "sent": 20,
+                        "delivered": 15,
+                        "dropped": { "queue_full": 2, "filtered": 3 }
+                    }
+                }
+            },
+            "stopped_by": "camera"
+        });
+        assert_eq!(serde_json::to_value(&summary).unwrap(), expected);
+        let parsed: DataflowSummary = serde_json::from_value(expected).unwrap();
+        assert_eq!(parsed, summary);
+    }
+
+    #[test]
+    fn size_histogram_buckets() {
+        assert_eq!(SizeHistogram::bucket(0), 0);
+        assert_eq!(SizeHistogram::bucket(1), 1);
+        assert_eq!(SizeHistogram::bucket(1000), 1024);
+        assert_eq!(SizeHistogram::bucket(1024), 1024);
+        assert_eq!(SizeHistogram::bucket(1025), 2048);
+        assert_eq!(SizeHistogram::bucket(u64::MAX), u64::MAX);
+
+        let mut histogram = SizeHistogram::default();
+        for len in [0, 10, 16, 4000] {
+            histogram.record(len);
+        }
+        assert_eq!(histogram.buckets, [(0, 1), (16, 2), (4096, 1)].into());
+        assert_eq!(
+            histogram.to_string(),
+            "4 messages of 1006 bytes on average, 4000 bytes at most"
+        );
+    }
+}
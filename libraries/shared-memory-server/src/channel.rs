@@ -1,4 +1,4 @@
-use eyre::{eyre, Context};
+use eyre::{bail, eyre, Context};
 use raw_sync_2::events::{Event, EventImpl, EventInit, EventState};
 use serde::{Deserialize, Serialize};
 use shared_memory_extended::Shmem;
@@ -148,9 +148,12 @@ impl ShmemChannel {
         }
 
         // then read len for synchronization
-        let msg_len = self.data_len().load(std::sync::atomic::Ordering::Acquire) as usize;
-        assert_ne!(msg_len, 0);
-        assert!(msg_len < self.memory.len() - self.data_offset);
+        let msg_len = self.data_len().load(std::sync::atomic::Ordering::Acquire);
+        // the length is written by the other side, so it can't be trusted
+        if msg_len == 0 || msg_len > (self.memory.len() - self.data_offset) as u64 {
+            bail!("invalid message length {msg_len} in ShmemChannel");
+        }
+        let msg_len = msg_len as usize;
 
         // finally read the data
         let value_raw = unsafe { slice::from_raw_parts(self.data(), msg_len) };
@@ -0,0 +1,116 @@
+use dora_node_api::dora_core::config::DataId;
+use eyre::{bail, eyre, Context};
+use rumqttc::QoS;
+use std::{collections::BTreeMap, time::Duration};
+
+/// Address of the MQTT broker as `<host>:<port>` (default: `localhost:1883`).
+pub const BROKER_ENV: &str = "MQTT_BROKER";
+/// MQTT client ID (default: the node ID).
+pub const CLIENT_ID_ENV: &str = "MQTT_CLIENT_ID";
+/// Comma-separated list of `<input>=<topic>` publications.
+pub const PUBLISH_ENV: &str = "MQTT_PUBLISH";
+/// Comma-separated list of `<topic>=<output>` subscriptions.
+pub const SUBSCRIBE_ENV: &str = "MQTT_SUBSCRIBE";
+/// QoS level for publications and subscriptions: `0`, `1` or `2` (default: `1`).
+pub const QOS_ENV: &str = "MQTT_QOS";
+/// Wrap payloads in a JSON envelope that includes the HLC timestamp (default: `false`).
+pub const JSON_ENVELOPE_ENV: &str = "MQTT_JSON_ENVELOPE";
+/// Delay in milliseconds before reconnecting to the broker (default: `1000`).
+pub const RECONNECT_DELAY_ENV: &str = "MQTT_RECONNECT_DELAY_MS";
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    pub broker_host: String,
+    pub broker_port: u16,
+    pub client_id: String,
+    pub qos: QoS,
+    pub json_envelope: bool,
+    pub reconnect_delay: Duration,
+    /// Maps node inputs to the MQTT topics they are published on.
+    pub publish: BTreeMap<DataId, String>,
+    /// Maps MQTT topic filters to the node outputs that received messages are sent on.
+    pub subscribe: BTreeMap<String, DataId>,
+}
+
+impl BridgeConfig {
+    pub fn new(client_id: String) -> Self {
+        Self {
+            broker_host: "localhost".into(),
+            broker_port: 1883,
+            client_id,
+            qos: QoS::AtLeastOnce,
+            json_envelope: false,
+            reconnect_delay: Duration::from_secs(1),
+            publish: BTreeMap::new(),
+            subscribe: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the bridge configuration from the environment variables that are
+    /// set in the `env` section of the node in the dataflow descriptor.
+    pub fn from_env(default_client_id: &str) -> eyre::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok(), default_client_id)
+    }
+
+    pub fn from_vars(
+        var: impl Fn(&str) -> Option<String>,
+        default_client_id: &str,
+    ) -> eyre::Result<Self> {
+        let mut config =
+            Self::new(var(CLIENT_ID_ENV).unwrap_or_else(|| default_client_id.to_owned()));
+        if let Some(broker) = var(BROKER_ENV) {
+            let (host, port) = broker
+                .rsplit_once(':')
+                .ok_or_else(|| eyre!("{BROKER_ENV} must be of the form `<host>:<port>`"))?;
+            config.broker_host = host.to_owned();
+            config.broker_port = port
+                .parse()
+                .wrap_err_with(|| format!("invalid port in {BROKER_ENV}: `{port}`"))?;
+        }
+        if let Some(qos) = var(QOS_ENV) {
+            config.qos = match qos.trim() {
+                "0" => QoS::AtMostOnce,
+                "1" => QoS::AtLeastOnce,
+                "2" => QoS::ExactlyOnce,
+                other => bail!("{QOS_ENV} must be 0, 1, or 2 (got `{other}`)"),
+            };
+        }
+        if let Some(envelope) = var(JSON_ENVELOPE_ENV) {
+            config.json_envelope = envelope
+                .trim()
+                .parse()
+                .wrap_err_with(|| format!("{JSON_ENVELOPE_ENV} must be `true` or `false`"))?;
+        }
+        if let Some(delay) = var(RECONNECT_DELAY_ENV) {
+            let millis = delay
+                .trim()
+                .parse()
+                .wrap_err_with(|| format!("{RECONNECT_DELAY_ENV} must be an integer"))?;
+            config.reconnect_delay = Duration::from_millis(millis);
+        }
+        if let Some(publish) = var(PUBLISH_ENV) {
+            for (input, topic) in parse_pairs(&publish, PUBLISH_ENV)? {
+                config.publish.insert(DataId::from(input), topic);
+            }
+        }
+        if let Some(subscribe) = var(SUBSCRIBE_ENV) {
+            for (topic, output) in parse_pairs(&subscribe, SUBSCRIBE_ENV)? {
+                config.subscribe.insert(topic, DataId::from(output));
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_pairs(list: &str, var: &str) -> eyre::Result<Vec<(String, String)>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (left, right) = entry
+                .split_once('=')
+                .ok_or_else(|| eyre!("invalid entry `{entry}` in {var} (expected `a=b`)"))?;
+            Ok((left.trim().to_owned(), right.trim().to_owned()))
+        })
+        .collect()
+}
@@ -0,0 +1,142 @@
+//! Bridge between dataflow inputs/outputs and MQTT topics.
+//!
+//! The bridge is a normal dataflow node: its inputs are published to MQTT
+//! topics and messages received on subscribed MQTT topics are sent out as
+//! node outputs. See [`config`] for the available settings.
+
+use dora_node_api::{arrow::array::ArrayRef, dora_core::config::DataId, uhlc::Timestamp};
+use rumqttc::{mqttbytes::matches, AsyncClient, Event, MqttOptions, Packet};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub use config::BridgeConfig;
+
+pub mod config;
+pub mod payload;
+
+/// A node input that should be published to MQTT.
+#[derive(Debug)]
+pub struct OutgoingMessage {
+    pub input_id: DataId,
+    pub timestamp: Timestamp,
+    pub data: ArrayRef,
+}
+
+/// A message received from MQTT that should be sent out as node output.
+#[derive(Debug)]
+pub struct IncomingMessage {
+    pub output_id: DataId,
+    pub data: ArrayRef,
+}
+
+/// Runs the MQTT side of the bridge until the `outgoing` channel is closed.
+///
+/// Lost broker connections are re-established after the configured
+/// reconnect delay. Subscriptions are renewed on every reconnect.
+pub async fn run_bridge(
+    config: BridgeConfig,
+    mut outgoing: mpsc::Receiver<OutgoingMessage>,
+    incoming: mpsc::Sender<IncomingMessage>,
+) -> eyre::Result<()> {
+    let mut options = MqttOptions::new(
+        config.client_id.clone(),
+        config.broker_host.clone(),
+        config.broker_port,
+    );
+    options.set_keep_alive(Duration::from_secs(5));
+    let (client, eventloop) = AsyncClient::new(options, 64);
+
+    let event_task = tokio::spawn(poll_events(
+        config.clone(),
+        client.clone(),
+        eventloop,
+        incoming,
+    ));
+
+    while let Some(message) = outgoing.recv().await {
+        let Some(topic) = config.publish.get(&message.input_id) else {
+            tracing::warn!("no MQTT topic configured for input `{}`", message.input_id);
+            continue;
+        };
+        let payload = match payload::encode(
+            &message.input_id,
+            &message.timestamp,
+            &message.data,
+            config.json_envelope,
+        ) {
+            Ok(payload) => payload,
+            Err(err) => {
+                tracing::warn!("failed to encode input `{}`: {err:?}", message.input_id);
+                continue;
+            }
+        };
+        client.publish(topic, config.qos, false, payload).await?;
+    }
+
+    let _ = client.disconnect().await;
+    event_task.abort();
+    Ok(())
+}
+
+async fn poll_events(
+    config: BridgeConfig,
+    client: AsyncClient,
+    mut eventloop: rumqttc::EventLoop,
+    incoming: mpsc::Sender<IncomingMessage>,
+) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!(
+                    "connected to MQTT broker at {}:{}",
+                    config.broker_host,
+                    config.broker_port
+                );
+                // subscribe from a separate task since the request channel is
+                // only drained while the event loop is polled
+                let client = client.clone();
+                let topics: Vec<_> = config.subscribe.keys().cloned().collect();
+                let qos = config.qos;
+                tokio::spawn(async move {
+                    for topic in topics {
+                        if let Err(err) = client.subscribe(&topic, qos).await {
+                            tracing::warn!("failed to subscribe to `{topic}`: {err}");
+                        }
+                    }
+                });
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let Some(output_id) = config
+                    .subscribe
+                    .iter()
+                    .find(|(filter, _)| matches(&publish.topic, filter))
+                    .map(|(_, output)| output.clone())
+                else {
+                    continue;
+                };
+                match payload::decode(&publish.payload, config.json_envelope) {
+                    Ok(data) => {
+                        if incoming
+                            .send(IncomingMessage { output_id, data })
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!("failed to decode message on `{}`: {err:?}", publish.topic)
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!(
+                    "MQTT connection error: {err}, reconnecting in {:?}",
+                    config.reconnect_delay
+                );
+                tokio::time::sleep(config.reconnect_delay).await;
+            }
+        }
+    }
+}
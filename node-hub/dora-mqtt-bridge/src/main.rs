@@ -0,0 +1,40 @@
+use dora_mqtt_bridge::{run_bridge, BridgeConfig, IncomingMessage, OutgoingMessage};
+use dora_node_api::{DoraNode, Event, MetadataParameters};
+use eyre::Context;
+use tokio::sync::mpsc;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> eyre::Result<()> {
+    let (mut node, mut events) = DoraNode::init_from_env()?;
+    let config = BridgeConfig::from_env(node.id().as_ref())
+        .context("invalid MQTT bridge configuration")?;
+
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(64);
+    let (incoming_tx, mut incoming_rx) = mpsc::channel(64);
+    let bridge = tokio::spawn(run_bridge(config, outgoing_rx, incoming_tx));
+
+    loop {
+        tokio::select! {
+            event = events.recv_async() => match event {
+                Some(Event::Input { id, metadata, data }) => {
+                    let message = OutgoingMessage {
+                        input_id: id,
+                        timestamp: metadata.timestamp(),
+                        data: data.0,
+                    };
+                    if outgoing_tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Event::Stop) | None => break,
+                Some(_) => {}
+            },
+            Some(IncomingMessage { output_id, data }) = incoming_rx.recv() => {
+                node.send_output(output_id, MetadataParameters::default(), data)?;
+            }
+        }
+    }
+
+    drop(outgoing_tx);
+    bridge.await.context("MQTT bridge task panicked")?
+}
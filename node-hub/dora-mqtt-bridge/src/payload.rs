@@ -0,0 +1,101 @@
+use dora_node_api::{
+    arrow::{
+        array::{
+            Array, ArrayRef, AsArray, BooleanArray, Float64Array, Int64Array, StringArray,
+            UInt8Array,
+        },
+        datatypes::{DataType, Field, Schema, UInt8Type},
+        record_batch::RecordBatch,
+    },
+    dora_core::config::DataId,
+    uhlc::Timestamp,
+};
+use eyre::{bail, Context, ContextCompat};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Encodes a dataflow message as MQTT payload.
+///
+/// Without envelope, byte arrays are sent as-is and single strings as UTF-8.
+/// All other data is sent as JSON array. The JSON envelope has the form
+/// `{"id": <input>, "timestamp": <HLC timestamp>, "data": <JSON array>}`.
+pub fn encode(
+    id: &DataId,
+    timestamp: &Timestamp,
+    data: &ArrayRef,
+    json_envelope: bool,
+) -> eyre::Result<Vec<u8>> {
+    if json_envelope {
+        let envelope = json!({
+            "id": id.as_str(),
+            "timestamp": timestamp.to_string(),
+            "data": to_json(data)?,
+        });
+        return serde_json::to_vec(&envelope).wrap_err("failed to serialize JSON envelope");
+    }
+
+    match data.data_type() {
+        DataType::UInt8 => Ok(data.as_primitive::<UInt8Type>().values().to_vec()),
+        DataType::Utf8 if data.len() == 1 => Ok(data.as_string::<i32>().value(0).into()),
+        _ => serde_json::to_vec(&to_json(data)?).wrap_err("failed to serialize data as JSON"),
+    }
+}
+
+/// Decodes an MQTT payload into an arrow array.
+///
+/// Without envelope, the payload is forwarded as byte array. With envelope,
+/// the `data` field must be a string or an array of numbers, booleans, or
+/// strings.
+pub fn decode(payload: &[u8], json_envelope: bool) -> eyre::Result<ArrayRef> {
+    if !json_envelope {
+        return Ok(Arc::new(UInt8Array::from(payload.to_vec())));
+    }
+
+    let envelope: Value =
+        serde_json::from_slice(payload).wrap_err("failed to parse JSON envelope")?;
+    let data = envelope
+        .get("data")
+        .context("JSON envelope has no `data` field")?;
+    from_json(data)
+}
+
+fn to_json(data: &ArrayRef) -> eyre::Result<Value> {
+    let schema = Schema::new(vec![Field::new("data", data.data_type().clone(), true)]);
+    let batch = RecordBatch::try_new(Arc::new(schema), vec![data.clone()])
+        .wrap_err("failed to create record batch")?;
+
+    let mut writer = arrow_json::WriterBuilder::new()
+        .with_explicit_nulls(true)
+        .build::<_, arrow_json::writer::JsonArray>(Vec::new());
+    writer
+        .write(&batch)
+        .wrap_err("failed to convert data to JSON")?;
+    writer.finish().wrap_err("failed to convert data to JSON")?;
+    let rows: Vec<Value> = serde_json::from_slice(&writer.into_inner())?;
+
+    Ok(Value::Array(
+        rows.into_iter()
+            .map(|mut row| row.get_mut("data").map(Value::take).unwrap_or_default())
+            .collect(),
+    ))
+}
+
+fn from_json(data: &Value) -> eyre::Result<ArrayRef> {
+    let array: ArrayRef = match data {
+        Value::String(s) => Arc::new(StringArray::from(vec![s.as_str()])),
+        Value::Array(values) if values.iter().all(|v| v.is_i64()) => Arc::new(Int64Array::from(
+            values.iter().map(Value::as_i64).collect::<Vec<_>>(),
+        )),
+        Value::Array(values) if values.iter().all(Value::is_number) => Arc::new(
+            Float64Array::from(values.iter().map(Value::as_f64).collect::<Vec<_>>()),
+        ),
+        Value::Array(values) if values.iter().all(Value::is_boolean) => Arc::new(
+            BooleanArray::from(values.iter().map(Value::as_bool).collect::<Vec<_>>()),
+        ),
+        Value::Array(values) if values.iter().all(Value::is_string) => Arc::new(StringArray::from(
+            values.iter().map(Value::as_str).collect::<Vec<_>>(),
+        )),
+        other => bail!("unsupported `data` in JSON envelope: {other}"),
+    };
+    Ok(array)
+}
@@ -0,0 +1,299 @@
+//! Runs the MQTT side of the bridge against a minimal in-process MQTT broker.
+
+use bytes::BytesMut;
+use dora_mqtt_bridge::{run_bridge, BridgeConfig, IncomingMessage, OutgoingMessage};
+use dora_node_api::{
+    arrow::array::{Array, AsArray, StringArray, UInt8Array},
+    arrow::datatypes::UInt8Type,
+    dora_core::config::DataId,
+    uhlc::HLC,
+};
+use rumqttc::{
+    mqttbytes::{
+        self,
+        v4::{self, ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, Publish, SubAck},
+    },
+    QoS, SubscribeReasonCode,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::timeout,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+enum BrokerEvent {
+    Connected,
+    Subscribed(String),
+    Published(Publish),
+}
+
+enum BrokerCommand {
+    Publish(Publish),
+    Disconnect,
+}
+
+struct MockBroker {
+    port: u16,
+    events: mpsc::UnboundedReceiver<BrokerEvent>,
+    commands: mpsc::UnboundedSender<BrokerCommand>,
+}
+
+impl MockBroker {
+    /// Accepts one client connection at a time. Commands are delivered to the
+    /// currently connected client.
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (commands, mut commands_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((connection, _)) = listener.accept().await {
+                serve_client(connection, &events_tx, &mut commands_rx).await;
+            }
+        });
+        Self {
+            port,
+            events,
+            commands,
+        }
+    }
+
+    async fn next_event(&mut self) -> BrokerEvent {
+        timeout(TIMEOUT, self.events.recv())
+            .await
+            .expect("timeout waiting for broker event")
+            .expect("broker stopped")
+    }
+}
+
+async fn serve_client(
+    mut connection: TcpStream,
+    events: &mpsc::UnboundedSender<BrokerEvent>,
+    commands: &mut mpsc::UnboundedReceiver<BrokerCommand>,
+) {
+    let mut read_buf = BytesMut::new();
+    loop {
+        let packet = loop {
+            match v4::read(&mut read_buf, 1 << 20) {
+                Ok(packet) => break Some(packet),
+                Err(mqttbytes::Error::InsufficientBytes(_)) => {}
+                Err(err) => panic!("invalid MQTT packet: {err}"),
+            }
+            tokio::select! {
+                read = connection.read_buf(&mut read_buf) => {
+                    if read.unwrap_or(0) == 0 {
+                        return;
+                    }
+                }
+                command = commands.recv() => match command {
+                    Some(BrokerCommand::Publish(publish)) => {
+                        let mut write_buf = BytesMut::new();
+                        publish.write(&mut write_buf).unwrap();
+                        connection.write_all(&write_buf).await.unwrap();
+                    }
+                    Some(BrokerCommand::Disconnect) | None => return,
+                },
+            }
+        };
+
+        let mut reply = BytesMut::new();
+        match packet {
+            Some(Packet::Connect(_)) => {
+                ConnAck::new(ConnectReturnCode::Success, false)
+                    .write(&mut reply)
+                    .unwrap();
+                let _ = events.send(BrokerEvent::Connected);
+            }
+            Some(Packet::Subscribe(subscribe)) => {
+                let codes = subscribe
+                    .filters
+                    .iter()
+                    .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                    .collect();
+                SubAck::new(subscribe.pkid, codes)
+                    .write(&mut reply)
+                    .unwrap();
+                for filter in subscribe.filters {
+                    let _ = events.send(BrokerEvent::Subscribed(filter.path));
+                }
+            }
+            Some(Packet::Publish(publish)) => {
+                if publish.qos == QoS::AtLeastOnce {
+                    PubAck::new(publish.pkid).write(&mut reply).unwrap();
+                }
+                let _ = events.send(BrokerEvent::Published(publish));
+            }
+            Some(Packet::PingReq) => {
+                PingResp.write(&mut reply).unwrap();
+            }
+            _ => {}
+        }
+        if !reply.is_empty() && connection.write_all(&reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn bridge_config(port: u16, json_envelope: bool) -> BridgeConfig {
+    BridgeConfig::from_vars(
+        |name| match name {
+            "MQTT_BROKER" => Some(format!("127.0.0.1:{port}")),
+            "MQTT_PUBLISH" => Some("random=robot/random, status=robot/status".into()),
+            "MQTT_SUBSCRIBE" => Some("robot/+/command=command".into()),
+            "MQTT_JSON_ENVELOPE" => Some(json_envelope.to_string()),
+            "MQTT_RECONNECT_DELAY_MS" => Some("50".into()),
+            _ => None,
+        },
+        "mqtt-bridge-test",
+    )
+    .unwrap()
+}
+
+async fn expect_subscription(broker: &mut MockBroker) {
+    loop {
+        if let BrokerEvent::Subscribed(topic) = broker.next_event().await {
+            assert_eq!(topic, "robot/+/command");
+            return;
+        }
+    }
+}
+
+async fn expect_publish(broker: &mut MockBroker) -> Publish {
+    loop {
+        if let BrokerEvent::Published(publish) = broker.next_event().await {
+            return publish;
+        }
+    }
+}
+
+#[tokio::test]
+async fn publish_and_subscribe() {
+    let mut broker = MockBroker::start().await;
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(10);
+    let (incoming_tx, mut incoming_rx) = mpsc::channel(10);
+    let bridge = tokio::spawn(run_bridge(
+        bridge_config(broker.port, false),
+        outgoing_rx,
+        incoming_tx,
+    ));
+    expect_subscription(&mut broker).await;
+
+    let hlc = HLC::default();
+    outgoing_tx
+        .send(OutgoingMessage {
+            input_id: DataId::from("random".to_owned()),
+            timestamp: hlc.new_timestamp(),
+            data: Arc::new(UInt8Array::from(vec![1, 2, 3])),
+        })
+        .await
+        .unwrap();
+    let publish = expect_publish(&mut broker).await;
+    assert_eq!(publish.topic, "robot/random");
+    assert_eq!(publish.qos, QoS::AtLeastOnce);
+    assert_eq!(&publish.payload[..], &[1, 2, 3]);
+
+    outgoing_tx
+        .send(OutgoingMessage {
+            input_id: DataId::from("status".to_owned()),
+            timestamp: hlc.new_timestamp(),
+            data: Arc::new(StringArray::from(vec!["ok"])),
+        })
+        .await
+        .unwrap();
+    let publish = expect_publish(&mut broker).await;
+    assert_eq!(publish.topic, "robot/status");
+    assert_eq!(&publish.payload[..], b"ok");
+
+    broker
+        .commands
+        .send(BrokerCommand::Publish(Publish::new(
+            "robot/1/command",
+            QoS::AtMostOnce,
+            vec![42],
+        )))
+        .unwrap();
+    let IncomingMessage { output_id, data } =
+        timeout(TIMEOUT, incoming_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(output_id.as_str(), "command");
+    assert_eq!(data.as_primitive::<UInt8Type>().values().as_ref(), &[42]);
+
+    drop(outgoing_tx);
+    timeout(TIMEOUT, bridge).await.unwrap().unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn json_envelope() {
+    let mut broker = MockBroker::start().await;
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(10);
+    let (incoming_tx, mut incoming_rx) = mpsc::channel(10);
+    tokio::spawn(run_bridge(
+        bridge_config(broker.port, true),
+        outgoing_rx,
+        incoming_tx,
+    ));
+    expect_subscription(&mut broker).await;
+
+    let timestamp = HLC::default().new_timestamp();
+    outgoing_tx
+        .send(OutgoingMessage {
+            input_id: DataId::from("random".to_owned()),
+            timestamp,
+            data: Arc::new(UInt8Array::from(vec![7, 8])),
+        })
+        .await
+        .unwrap();
+    let publish = expect_publish(&mut broker).await;
+    let envelope: serde_json::Value = serde_json::from_slice(&publish.payload).unwrap();
+    assert_eq!(envelope["id"], "random");
+    assert_eq!(envelope["timestamp"], timestamp.to_string());
+    assert_eq!(envelope["data"], serde_json::json!([7, 8]));
+
+    broker
+        .commands
+        .send(BrokerCommand::Publish(Publish::new(
+            "robot/2/command",
+            QoS::AtMostOnce,
+            br#"{"data": "stop"}"#.to_vec(),
+        )))
+        .unwrap();
+    let IncomingMessage { data, .. } = timeout(TIMEOUT, incoming_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(data.len(), 1);
+    assert_eq!(data.as_string::<i32>().value(0), "stop");
+}
+
+#[tokio::test]
+async fn reconnect_after_broker_disconnect() {
+    let mut broker = MockBroker::start().await;
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(10);
+    let (incoming_tx, _incoming_rx) = mpsc::channel(10);
+    tokio::spawn(run_bridge(
+        bridge_config(broker.port, false),
+        outgoing_rx,
+        incoming_tx,
+    ));
+    expect_subscription(&mut broker).await;
+
+    broker.commands.send(BrokerCommand::Disconnect).unwrap();
+    loop {
+        if let BrokerEvent::Connected = broker.next_event().await {
+            break;
+        }
+    }
+    // subscriptions are renewed after reconnecting
+    expect_subscription(&mut broker).await;
+
+    outgoing_tx
+        .send(OutgoingMessage {
+            input_id: DataId::from("random".to_owned()),
+            timestamp: HLC::default().new_timestamp(),
+            data: Arc::new(UInt8Array::from(vec![5])),
+        })
+        .await
+        .unwrap();
+    let publish = expect_publish(&mut broker).await;
+    assert_eq!(publish.topic, "robot/random");
+}
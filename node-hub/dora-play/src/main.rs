@@ -0,0 +1,284 @@
+use clap::Parser;
+use dora_node_api::{
+    self,
+    arrow::{
+        array::{Array, ArrayRef, AsArray},
+        datatypes::UInt64Type,
+        record_batch::RecordBatch,
+    },
+    dora_core::{config::DataId, uhlc::NTP64},
+    set_source_timestamp, DoraNode, Event, EventStream, MetadataParameters,
+};
+use eyre::{bail, Context, ContextCompat};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+/// Replays a recording of `dora-record` by sending the recorded messages as outputs.
+///
+/// Each recorded input file is sent on the output with the same name, e.g. the
+/// messages of `out/<DATAFLOW_ID>/image.parquet` are sent on the `image` output.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Recording directory, e.g. `out/<DATAFLOW_ID>`.
+    recording: PathBuf,
+    /// Playback speed factor, e.g. `2.0` to replay twice as fast.
+    #[clap(long, default_value_t = 1.0)]
+    rate: f64,
+    /// Restart from the beginning when the end of the recording is reached.
+    #[clap(long = "loop")]
+    repeat: bool,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Args::parse();
+    if !(args.rate > 0.0 && args.rate.is_finite()) {
+        bail!("`--rate` must be a positive number, got {}", args.rate);
+    }
+
+    let (mut node, mut events) = DoraNode::init_from_env()?;
+
+    let mut streams = BTreeMap::new();
+    for (id, path) in find_recorded_streams(&args.recording)? {
+        let output_id = DataId::from(id);
+        if node.node_config().outputs.contains(&output_id) {
+            streams.insert(output_id, path);
+        } else {
+            println!("Skipping recorded stream `{output_id}`, which is not a declared output");
+        }
+    }
+    if streams.is_empty() {
+        bail!(
+            "recording at `{}` contains no stream that matches the declared outputs",
+            args.recording.display()
+        );
+    }
+
+    loop {
+        let stopped = play(&mut node, &mut events, &streams, args.rate).await?;
+        if stopped || !args.repeat {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends all messages of the given streams in the order of their timestamps.
+///
+/// Returns `true` if playback was stopped by a `Stop` event.
+async fn play(
+    node: &mut DoraNode,
+    events: &mut EventStream,
+    streams: &BTreeMap<DataId, PathBuf>,
+    rate: f64,
+) -> eyre::Result<bool> {
+    let mut readers = streams
+        .iter()
+        .map(|(id, path)| StreamReader::open(id.clone(), path))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    // the event stream is closed right away if the node has no inputs
+    let mut events_open = true;
+    let start = Instant::now();
+    let mut first_timestamp = None;
+    loop {
+        // send the message with the oldest timestamp across all streams next
+        let mut next: Option<(usize, NTP64)> = None;
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(timestamp) = reader.peek_timestamp()? {
+                if !matches!(next, Some((_, t)) if t <= timestamp) {
+                    next = Some((i, timestamp));
+                }
+            }
+        }
+        let Some((index, timestamp)) = next else {
+            return Ok(false);
+        };
+
+        let first_timestamp = *first_timestamp.get_or_insert(timestamp);
+        let offset = timestamp
+            .to_duration()
+            .saturating_sub(first_timestamp.to_duration());
+        let deadline = start + offset.div_f64(rate);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                event = events.recv_async(), if events_open => match event {
+                    Some(Event::Stop) => return Ok(true),
+                    Some(Event::Error(err)) => println!("Error: {err}"),
+                    Some(_) => {}
+                    None => events_open = false,
+                },
+            }
+        }
+
+        let reader = &mut readers[index];
+        let (data, parameters) = reader
+            .next_message()?
+            .context("recorded message vanished")?;
+        node.send_output(reader.output_id.clone(), parameters, data)
+            .with_context(|| format!("failed to send `{}`", reader.output_id))?;
+    }
+}
+
+/// Collects all `.parquet` files of the recording, keyed by the recorded input ID.
+///
+/// Inputs expanded from wildcards are stored in subdirectories, so their ID is
+/// the relative path of the file, e.g. `all/camera/image`.
+fn find_recorded_streams(recording: &Path) -> eyre::Result<BTreeMap<String, PathBuf>> {
+    let mut streams = BTreeMap::new();
+    let mut pending = vec![recording.to_owned()];
+    while let Some(dir) = pending.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("failed to read recording dir `{}`", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "parquet") {
+                let relative = path.strip_prefix(recording)?.with_extension("");
+                let id = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                streams.insert(id, path);
+            }
+        }
+    }
+    Ok(streams)
+}
+
+/// Reads the messages of a single recorded input in file order.
+struct StreamReader {
+    output_id: DataId,
+    batches: ParquetRecordBatchReader,
+    current: Option<RecordBatch>,
+    row: usize,
+}
+
+impl StreamReader {
+    fn open(output_id: DataId, path: &Path) -> eyre::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open recording `{}`", path.display()))?;
+        let batches = ParquetRecordBatchReaderBuilder::try_new(file)
+            .and_then(|builder| builder.build())
+            .with_context(|| format!("failed to read recording `{}`", path.display()))?;
+        Ok(Self {
+            output_id,
+            batches,
+            current: None,
+            row: 0,
+        })
+    }
+
+    /// Loads the next record batch if needed; returns `false` at the end of the stream.
+    fn fill(&mut self) -> eyre::Result<bool> {
+        while !matches!(&self.current, Some(b) if self.row < b.num_rows()) {
+            match self.batches.next() {
+                Some(batch) => {
+                    self.current = Some(batch.context("failed to read record batch")?);
+                    self.row = 0;
+                }
+                None => {
+                    self.current = None;
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn peek_timestamp(&mut self) -> eyre::Result<Option<NTP64>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        let batch = self.current.as_ref().unwrap();
+        let timestamps = batch
+            .column_by_name("timestamp_uhlc")
+            .context("recording has no `timestamp_uhlc` column")?
+            .as_primitive_opt::<UInt64Type>()
+            .context("`timestamp_uhlc` column is not of type u64")?;
+        Ok(Some(NTP64(timestamps.value(self.row))))
+    }
+
+    /// Returns the next payload, with the recorded metadata parameters.
+    fn next_message(&mut self) -> eyre::Result<Option<(ArrayRef, MetadataParameters)>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        let batch = self.current.as_ref().unwrap();
+        let mut parameters = MetadataParameters::default();
+        // recordings of older `dora-record` versions have no source timestamps
+        if let Some(column) = batch.column_by_name("source_timestamp") {
+            let source_timestamps = column
+                .as_primitive_opt::<UInt64Type>()
+                .context("`source_timestamp` column is not of type u64")?;
+            if source_timestamps.is_valid(self.row) {
+                let nanos = source_timestamps.value(self.row);
+                set_source_timestamp(&mut parameters, UNIX_EPOCH + Duration::from_nanos(nanos))?;
+            }
+        }
+        // the payload is stored in the last column, which is named after the input
+        let payload = batch
+            .columns()
+            .last()
+            .context("recording has no payload column")?
+            .as_list_opt::<i32>()
+            .context("payload column is not a list")?;
+        let data = payload.value(self.row);
+        self.row += 1;
+        Ok(Some((data, parameters)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_message::metadata::ArrowTypeInfo;
+    use dora_node_api::{arrow::array::UInt8Array, uhlc::HLC, Metadata};
+    use parquet::arrow::AsyncArrowWriter;
+    use std::{sync::Arc, time::SystemTime};
+
+    #[tokio::test]
+    async fn source_timestamps_survive_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.parquet");
+        let id = DataId::from("image".to_owned());
+        let data: ArrayRef = Arc::new(UInt8Array::from(vec![1, 2, 3]));
+
+        let schema = dora_record::schema(&id, data.data_type());
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        let mut writer = AsyncArrowWriter::try_new(file, schema.clone(), None).unwrap();
+        let source_time = SystemTime::now() - Duration::from_millis(1234);
+        let mut with_source_timestamp = MetadataParameters::default();
+        set_source_timestamp(&mut with_source_timestamp, source_time).unwrap();
+        let clock = HLC::default();
+        for parameters in [with_source_timestamp.clone(), MetadataParameters::default()] {
+            let metadata = Metadata::from_parameters(
+                clock.new_timestamp(),
+                ArrowTypeInfo::byte_array(data.len()),
+                parameters,
+            );
+            dora_record::write_event(&mut writer, data.clone(), &metadata, schema.clone())
+                .await
+                .unwrap();
+        }
+        writer.close().await.unwrap();
+
+        let mut reader = StreamReader::open(id, &path).unwrap();
+        let (replayed, parameters) = reader.next_message().unwrap().unwrap();
+        assert_eq!(replayed.to_data(), data.to_data());
+        assert_eq!(parameters, with_source_timestamp);
+        let (_, parameters) = reader.next_message().unwrap().unwrap();
+        assert_eq!(parameters, MetadataParameters::default());
+        assert!(reader.next_message().unwrap().is_none());
+    }
+}
@@ -0,0 +1,107 @@
+//! Writing of the recorded messages, shared with the tests of `dora-play`.
+
+use chrono::{DateTime, Utc};
+use dora_node_api::{
+    arrow::{
+        array::{
+            make_array, Array, ListArray, StringArray, TimestampMillisecondArray, UInt64Array,
+        },
+        buffer::{OffsetBuffer, ScalarBuffer},
+        datatypes::{DataType, Field, Schema, TimeUnit},
+        record_batch::RecordBatch,
+    },
+    dora_core::config::DataId,
+    Metadata, SOURCE_TIMESTAMP_PARAMETER,
+};
+use dora_tracing::telemetry::deserialize_to_hashmap;
+use eyre::{Context, ContextCompat};
+use parquet::arrow::AsyncArrowWriter;
+use std::sync::Arc;
+
+/// Schema of the recording of the given input.
+///
+/// The payload is stored in the last column, which is named after the input.
+pub fn schema(id: &DataId, data_type: &DataType) -> Arc<Schema> {
+    let field_uhlc = Field::new("timestamp_uhlc", DataType::UInt64, false);
+    let field_utc_epoch = Field::new(
+        "timestamp_utc",
+        DataType::Timestamp(TimeUnit::Millisecond, None),
+        false,
+    );
+    let field_source_timestamp = Field::new("source_timestamp", DataType::UInt64, true);
+    let field_trace_id = Field::new("trace_id", DataType::Utf8, true);
+    let field_span_id = Field::new("span_id", DataType::Utf8, true);
+    let field_values = Arc::new(Field::new("item", data_type.clone(), true));
+    let field_data = Field::new(id.to_string(), DataType::List(field_values), true);
+
+    Arc::new(Schema::new(vec![
+        field_trace_id,
+        field_span_id,
+        field_uhlc,
+        field_utc_epoch,
+        field_source_timestamp,
+        field_data,
+    ]))
+}
+
+/// Write a row of data into the writer
+pub async fn write_event(
+    writer: &mut AsyncArrowWriter<tokio::fs::File>,
+    data: Arc<dyn Array>,
+    metadata: &Metadata,
+    schema: Arc<Schema>,
+) -> eyre::Result<()> {
+    let offsets = OffsetBuffer::new(ScalarBuffer::from(vec![0, data.len() as i32]));
+    let field = Arc::new(Field::new("item", data.data_type().clone(), true));
+    let list = ListArray::new(field, offsets, data.clone(), None);
+
+    let timestamp = metadata.timestamp();
+    let timestamp_uhlc = UInt64Array::from(vec![timestamp.get_time().0]);
+    let timestamp_uhlc = make_array(timestamp_uhlc.into());
+    let system_time = timestamp.get_time().to_system_time();
+
+    let dt: DateTime<Utc> = system_time.into();
+    let timestamp_utc = TimestampMillisecondArray::from(vec![dt.timestamp_millis()]);
+    let timestamp_utc = make_array(timestamp_utc.into());
+
+    let string_otel_context = metadata.open_telemetry_context();
+    let otel_context = deserialize_to_hashmap(&string_otel_context);
+    let traceparent = otel_context.get("traceparent");
+    let trace_id = match traceparent {
+        None => "",
+        Some(trace) => trace.split('-').nth(1).context("Trace is malformatted")?,
+    };
+    let span_id = match traceparent {
+        None => "",
+        Some(trace) => trace.split('-').nth(2).context("Trace is malformatted")?,
+    };
+    // nanoseconds since the UNIX epoch, as in the metadata parameter
+    let source_timestamp = metadata
+        .get_int(SOURCE_TIMESTAMP_PARAMETER)
+        .and_then(|nanos| u64::try_from(nanos).ok());
+    let source_timestamp = make_array(UInt64Array::from(vec![source_timestamp]).into());
+
+    let trace_id_array = StringArray::from(vec![trace_id]);
+    let trace_id_array = make_array(trace_id_array.into());
+    let span_id_array = StringArray::from(vec![span_id]);
+    let span_id_array = make_array(span_id_array.into());
+
+    let record = RecordBatch::try_new(
+        schema,
+        vec![
+            trace_id_array,
+            span_id_array,
+            timestamp_uhlc,
+            timestamp_utc,
+            source_timestamp,
+            make_array(list.into()),
+        ],
+    )
+    .context("Could not create record batch with the given data")?;
+    writer
+        .write(&record)
+        .await
+        .context("Could not write recordbatch to file")?;
+
+    Ok(())
+}
@@ -1,64 +1,64 @@
-use chrono::{DateTime, Utc};
-use dora_node_api::{
-    self,
-    arrow::{
-        array::{
-            make_array, Array, ListArray, StringArray, TimestampMillisecondArray, UInt64Array,
-        },
-        buffer::{OffsetBuffer, ScalarBuffer},
-        datatypes::{DataType, Field, Schema},
-        record_batch::RecordBatch,
-    },
-    DoraNode, Event, Metadata,
+use dora_node_api::{self, arrow::array::Array, dora_core::config::DataId, DoraNode, Event};
+use dora_record::{schema, write_event};
+use eyre::Context;
+use parquet::{
+    arrow::AsyncArrowWriter,
+    basic::BrotliLevel,
+    file::{metadata::KeyValue, properties::WriterProperties},
+    format::FileMetaData,
 };
-use dora_tracing::telemetry::deserialize_to_hashmap;
-use eyre::{Context, ContextCompat};
-use parquet::{arrow::AsyncArrowWriter, basic::BrotliLevel, file::properties::WriterProperties};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
-use tokio::sync::mpsc;
+use std::{collections::HashMap, path::PathBuf};
+use tokio::{sync::mpsc, task::JoinHandle};
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    let (node, mut events) = DoraNode::init_from_env()?;
-    let dataflow_id = node.dataflow_id();
+    let (mut node, mut events) = DoraNode::init_from_env()?;
+    let dataflow_id = *node.dataflow_id();
     let mut writers = HashMap::new();
 
+    // look up the sources of all recorded inputs, including the ones that
+    // were expanded from wildcard inputs such as `all: "*/*"`
+    let sources: HashMap<_, _> = match node.topology() {
+        Ok(topology) => topology
+            .inputs
+            .into_iter()
+            .map(|(id, mappings)| {
+                let sources: Vec<_> = mappings.iter().map(|m| m.to_string()).collect();
+                (id, sources.join(","))
+            })
+            .collect(),
+        Err(err) => {
+            println!("Could not query topology, recording without source information: {err:?}");
+            HashMap::new()
+        }
+    };
+    for (id, source) in &sources {
+        println!("Recording `{id}` from `{source}`");
+    }
+
     while let Some(event) = events.recv() {
         match event {
             Event::Input { id, data, metadata } => {
                 match writers.get(&id) {
                     None => {
-                        let field_uhlc = Field::new("timestamp_uhlc", DataType::UInt64, false);
-                        let field_utc_epoch = Field::new(
-                            "timestamp_utc",
-                            DataType::Timestamp(
-                                dora_node_api::arrow::datatypes::TimeUnit::Millisecond,
-                                None,
-                            ),
-                            false,
-                        );
-                        let field_trace_id = Field::new("trace_id", DataType::Utf8, true);
-                        let field_span_id = Field::new("span_id", DataType::Utf8, true);
-                        let field_values =
-                            Arc::new(Field::new("item", data.data_type().clone(), true));
-                        let field_data = Field::new(id.clone(), DataType::List(field_values), true);
-
-                        let schema = Arc::new(Schema::new(vec![
-                            field_trace_id,
-                            field_span_id,
-                            field_uhlc,
-                            field_utc_epoch,
-                            field_data,
-                        ]));
+                        let schema = schema(&id, data.data_type());
                         let dataflow_dir = PathBuf::from("out").join(dataflow_id.to_string());
                         if !dataflow_dir.exists() {
                             std::fs::create_dir_all(&dataflow_dir)
                                 .context("could not create dataflow_dir")?;
                         }
-                        let file =
-                            tokio::fs::File::create(dataflow_dir.join(format!("{id}.parquet")))
-                                .await
-                                .context("Couldn't create write file")?;
+                        // expanded wildcard inputs contain slashes, e.g. `all/camera/image`
+                        let path = dataflow_dir.join(format!("{id}.parquet"));
+                        if let Some(parent) = path.parent() {
+                            std::fs::create_dir_all(parent)
+                                .context("could not create input directory")?;
+                        }
+                        let file = tokio::fs::File::create(path)
+                            .await
+                            .context("Couldn't create write file")?;
+                        let key_value_metadata = sources.get(&id).map(|source| {
+                            vec![KeyValue::new("source".to_string(), source.clone())]
+                        });
                         let mut writer = AsyncArrowWriter::try_new(
                             file,
                             schema.clone(),
@@ -67,6 +67,7 @@ async fn main() -> eyre::Result<()> {
                                     .set_compression(parquet::basic::Compression::BROTLI(
                                         BrotliLevel::default(),
                                     ))
+                                    .set_key_value_metadata(key_value_metadata)
                                     .build(),
                             ),
                         )
@@ -96,10 +97,16 @@ async fn main() -> eyre::Result<()> {
                     }
                 };
             }
-            Event::InputClosed { id } => match writers.remove(&id) {
-                None => {}
-                Some(tx) => drop(tx),
-            },
+            Event::InputClosed { id, .. } => {
+                if let Some((tx, join_handle)) = writers.remove(&id) {
+                    close_writer(&id, tx, join_handle).await?;
+                }
+            }
+            Event::Stop => {
+                // keep receiving until the event stream is closed to record
+                // messages that are still in flight
+                println!("Received stop, finishing recording");
+            }
             Event::Error(err) => {
                 println!("Error: {}", err);
             }
@@ -110,69 +117,24 @@ async fn main() -> eyre::Result<()> {
     }
 
     for (id, (tx, join_handle)) in writers {
-        drop(tx);
-        join_handle
-            .await
-            .context("Writer thread failed")?
-            .context(format!(
-                "Could not close the Parquet writer for {id} parquet writer"
-            ))?;
+        close_writer(&id, tx, join_handle).await?;
     }
 
     Ok(())
 }
 
-/// Write a row of data into the writer
-async fn write_event(
-    writer: &mut AsyncArrowWriter<tokio::fs::File>,
-    data: Arc<dyn Array>,
-    metadata: &Metadata,
-    schema: Arc<Schema>,
+/// Waits until all pending messages of the input are written and the file is closed.
+async fn close_writer<T>(
+    id: &DataId,
+    tx: mpsc::Sender<T>,
+    join_handle: JoinHandle<parquet::errors::Result<FileMetaData>>,
 ) -> eyre::Result<()> {
-    let offsets = OffsetBuffer::new(ScalarBuffer::from(vec![0, data.len() as i32]));
-    let field = Arc::new(Field::new("item", data.data_type().clone(), true));
-    let list = ListArray::new(field, offsets, data.clone(), None);
-
-    let timestamp = metadata.timestamp();
-    let timestamp_uhlc = UInt64Array::from(vec![timestamp.get_time().0]);
-    let timestamp_uhlc = make_array(timestamp_uhlc.into());
-    let system_time = timestamp.get_time().to_system_time();
-
-    let dt: DateTime<Utc> = system_time.into();
-    let timestamp_utc = TimestampMillisecondArray::from(vec![dt.timestamp_millis()]);
-    let timestamp_utc = make_array(timestamp_utc.into());
-
-    let string_otel_context = metadata.open_telemetry_context();
-    let otel_context = deserialize_to_hashmap(&string_otel_context);
-    let traceparent = otel_context.get("traceparent");
-    let trace_id = match traceparent {
-        None => "",
-        Some(trace) => trace.split('-').nth(1).context("Trace is malformatted")?,
-    };
-    let span_id = match traceparent {
-        None => "",
-        Some(trace) => trace.split('-').nth(2).context("Trace is malformatted")?,
-    };
-    let trace_id_array = StringArray::from(vec![trace_id]);
-    let trace_id_array = make_array(trace_id_array.into());
-    let span_id_array = StringArray::from(vec![span_id]);
-    let span_id_array = make_array(span_id_array.into());
-
-    let record = RecordBatch::try_new(
-        schema,
-        vec![
-            trace_id_array,
-            span_id_array,
-            timestamp_uhlc,
-            timestamp_utc,
-            make_array(list.into()),
-        ],
-    )
-    .context("Could not create record batch with the given data")?;
-    writer
-        .write(&record)
+    drop(tx);
+    join_handle
         .await
-        .context("Could not write recordbatch to file")?;
-
+        .context("Writer thread failed")?
+        .context(format!(
+            "Could not close the Parquet writer for {id} parquet writer"
+        ))?;
     Ok(())
 }
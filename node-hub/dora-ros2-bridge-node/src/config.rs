@@ -0,0 +1,261 @@
+use dora_node_api::dora_core::config::DataId;
+use eyre::{bail, eyre, Context};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// Set by the `setup.bash` script of every ROS 2 installation.
+pub const ROS_DISTRO_ENV: &str = "ROS_DISTRO";
+/// DDS domain that the bridge joins (default: `0`).
+pub const ROS_DOMAIN_ID_ENV: &str = "ROS_DOMAIN_ID";
+/// Comma-separated list of `<topic>:<type>=<output>` subscriptions.
+pub const SUBSCRIBE_ENV: &str = "ROS2_SUBSCRIBE";
+/// Comma-separated list of `<input>=<topic>:<type>` publications.
+pub const PUBLISH_ENV: &str = "ROS2_PUBLISH";
+/// Namespace for relative topic names (default: `/`).
+pub const NAMESPACE_ENV: &str = "ROS2_NAMESPACE";
+/// Use reliable instead of best-effort delivery (default: `true`).
+pub const RELIABLE_ENV: &str = "ROS2_RELIABLE";
+
+/// Highest domain ID that ROS 2 supports on all platforms.
+const MAX_DOMAIN_ID: u16 = 232;
+
+/// A ROS 2 message type such as `sensor_msgs/msg/Image`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageType {
+    pub package: String,
+    pub name: String,
+}
+
+impl FromStr for MessageType {
+    type Err = eyre::Report;
+
+    /// Accepts both `<package>/msg/<name>` and `<package>/<name>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (package, name) = match s.split('/').collect::<Vec<_>>().as_slice() {
+            [package, "msg", name] | [package, name] => (*package, *name),
+            _ => bail!("invalid message type `{s}` (expected `<package>/msg/<name>`)"),
+        };
+        if package.is_empty() || name.is_empty() {
+            bail!("invalid message type `{s}` (expected `<package>/msg/<name>`)");
+        }
+        Ok(Self {
+            package: package.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/msg/{}", self.package, self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topic {
+    /// Fully qualified ROS 2 topic name, e.g. `/turtle1/cmd_vel`.
+    pub name: String,
+    pub message_type: MessageType,
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    pub domain_id: u16,
+    pub reliable: bool,
+    /// Maps ROS 2 topics to the node outputs that received messages are sent on.
+    pub subscribe: BTreeMap<DataId, Topic>,
+    /// Maps node inputs to the ROS 2 topics they are published on.
+    pub publish: BTreeMap<DataId, Topic>,
+}
+
+impl BridgeConfig {
+    /// Reads the bridge configuration from the environment variables that are
+    /// set in the `env` section of the node in the dataflow descriptor.
+    pub fn from_env() -> eyre::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> eyre::Result<Self> {
+        if var(ROS_DISTRO_ENV).is_none() {
+            bail!(
+                "no ROS 2 environment found: `{ROS_DISTRO_ENV}` is not set\n\n\
+                Source your ROS 2 installation before starting the dataflow, \
+                e.g. `source /opt/ros/humble/setup.bash`"
+            );
+        }
+        let domain_id = match var(ROS_DOMAIN_ID_ENV) {
+            Some(id) if !id.trim().is_empty() => {
+                let id: u16 = id
+                    .trim()
+                    .parse()
+                    .wrap_err_with(|| format!("{ROS_DOMAIN_ID_ENV} must be an integer"))?;
+                if id > MAX_DOMAIN_ID {
+                    bail!("{ROS_DOMAIN_ID_ENV} must be between 0 and {MAX_DOMAIN_ID} (got {id})");
+                }
+                id
+            }
+            _ => 0,
+        };
+        let reliable = parse_bool(var(RELIABLE_ENV), RELIABLE_ENV, true)?;
+        let namespace = var(NAMESPACE_ENV).unwrap_or_else(|| "/".to_owned());
+
+        let mut subscribe = BTreeMap::new();
+        if let Some(list) = var(SUBSCRIBE_ENV) {
+            for (topic, output) in parse_pairs(&list, SUBSCRIBE_ENV)? {
+                let topic = parse_topic(&topic, &namespace)?;
+                if subscribe.insert(DataId::from(output.clone()), topic).is_some() {
+                    bail!("output `{output}` is used for multiple topics in {SUBSCRIBE_ENV}");
+                }
+            }
+        }
+        let mut publish = BTreeMap::new();
+        if let Some(list) = var(PUBLISH_ENV) {
+            for (input, topic) in parse_pairs(&list, PUBLISH_ENV)? {
+                let topic = parse_topic(&topic, &namespace)?;
+                if publish.insert(DataId::from(input.clone()), topic).is_some() {
+                    bail!("input `{input}` is mapped to multiple topics in {PUBLISH_ENV}");
+                }
+            }
+        }
+        if subscribe.is_empty() && publish.is_empty() {
+            bail!("no topics configured, set `{SUBSCRIBE_ENV}` and/or `{PUBLISH_ENV}`");
+        }
+
+        Ok(Self {
+            domain_id,
+            reliable,
+            subscribe,
+            publish,
+        })
+    }
+}
+
+/// Parses a `<topic>:<type>` pair. Relative topic names are resolved against
+/// the given namespace.
+fn parse_topic(entry: &str, namespace: &str) -> eyre::Result<Topic> {
+    let (name, message_type) = entry
+        .split_once(':')
+        .ok_or_else(|| eyre!("missing message type in `{entry}` (expected `<topic>:<type>`)"))?;
+    let name = name.trim();
+    if name.is_empty() || name.ends_with('/') {
+        bail!("invalid topic name `{name}`");
+    }
+    let name = if name.starts_with('/') {
+        name.to_owned()
+    } else {
+        format!("/{}/{name}", namespace.trim_matches('/')).replace("//", "/")
+    };
+    Ok(Topic {
+        name,
+        message_type: message_type.trim().parse()?,
+    })
+}
+
+fn parse_bool(value: Option<String>, var: &str, default: bool) -> eyre::Result<bool> {
+    match value {
+        Some(value) => value
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("{var} must be `true` or `false`")),
+        None => Ok(default),
+    }
+}
+
+fn parse_pairs(list: &str, var: &str) -> eyre::Result<Vec<(String, String)>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (left, right) = entry
+                .split_once('=')
+                .ok_or_else(|| eyre!("invalid entry `{entry}` in {var} (expected `a=b`)"))?;
+            Ok((left.trim().to_owned(), right.trim().to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn parse_message_type() {
+        let image: MessageType = "sensor_msgs/msg/Image".parse().unwrap();
+        assert_eq!(image.package, "sensor_msgs");
+        assert_eq!(image.name, "Image");
+        assert_eq!("sensor_msgs/Image".parse::<MessageType>().unwrap(), image);
+        assert!("Image".parse::<MessageType>().is_err());
+        assert!("sensor_msgs/srv/Image".parse::<MessageType>().is_err());
+    }
+
+    #[test]
+    fn missing_ros_environment() {
+        let err = BridgeConfig::from_vars(vars(&[(
+            SUBSCRIBE_ENV,
+            "/chatter:std_msgs/msg/String=chatter",
+        )]))
+        .unwrap_err();
+        assert!(err.to_string().contains("`ROS_DISTRO` is not set"));
+    }
+
+    #[test]
+    fn topics() {
+        let config = BridgeConfig::from_vars(vars(&[
+            (ROS_DISTRO_ENV, "humble"),
+            (ROS_DOMAIN_ID_ENV, "7"),
+            (NAMESPACE_ENV, "/turtle1"),
+            (
+                SUBSCRIBE_ENV,
+                "pose:turtlesim/msg/Pose=pose, /camera/image:sensor_msgs/Image=image",
+            ),
+            (PUBLISH_ENV, "twist=cmd_vel:geometry_msgs/msg/Twist"),
+        ]))
+        .unwrap();
+        assert_eq!(config.domain_id, 7);
+        assert!(config.reliable);
+
+        let pose = &config.subscribe[&DataId::from("pose".to_owned())];
+        assert_eq!(pose.name, "/turtle1/pose");
+        assert_eq!(pose.message_type.to_string(), "turtlesim/msg/Pose");
+        let image = &config.subscribe[&DataId::from("image".to_owned())];
+        assert_eq!(image.name, "/camera/image");
+        assert_eq!(image.message_type.to_string(), "sensor_msgs/msg/Image");
+
+        let twist = &config.publish[&DataId::from("twist".to_owned())];
+        assert_eq!(twist.name, "/turtle1/cmd_vel");
+    }
+
+    #[test]
+    fn duplicate_outputs() {
+        let err = BridgeConfig::from_vars(vars(&[
+            (ROS_DISTRO_ENV, "humble"),
+            (
+                SUBSCRIBE_ENV,
+                "/chatter:std_msgs/msg/String=text, /status:std_msgs/msg/String=text",
+            ),
+        ]))
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("output `text`"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn invalid_domain_id() {
+        let err = BridgeConfig::from_vars(vars(&[
+            (ROS_DISTRO_ENV, "humble"),
+            (ROS_DOMAIN_ID_ENV, "300"),
+            (SUBSCRIBE_ENV, "/chatter:std_msgs/msg/String=chatter"),
+        ]))
+        .unwrap_err();
+        assert!(err.to_string().contains(ROS_DOMAIN_ID_ENV));
+    }
+}
@@ -0,0 +1,168 @@
+//! Bridge between dataflow inputs/outputs and ROS 2 topics.
+//!
+//! Messages received on subscribed ROS 2 topics are sent out as node outputs
+//! and node inputs are published to ROS 2 topics. See [`config`] for the
+//! available settings.
+//!
+//! Messages are converted from and to Arrow struct arrays using the message
+//! definitions of the sourced ROS 2 installation, in the same way as the
+//! Python ROS 2 bridge does. Messages of types without a definition are
+//! passed through as raw CDR payload instead, see [`raw`].
+
+use dora_node_api::{arrow::array::ArrayData, MetadataParameters, Parameter};
+use dora_ros2_bridge::rustdds::{self, policy};
+use dora_ros2_bridge_arrow::{deserialize::StructDeserializer, Messages, TypeInfo, TypedValue};
+use serde::de::DeserializeSeed;
+use std::borrow::Cow;
+
+pub use config::BridgeConfig;
+
+pub mod config;
+pub mod raw;
+
+/// Metadata parameter that contains the ROS 2 message type, e.g.
+/// `geometry_msgs/msg/Twist`.
+pub const TYPE_PARAMETER: &str = "ros2_type";
+/// Metadata parameter that contains the ROS 2 topic name.
+pub const TOPIC_PARAMETER: &str = "ros2_topic";
+/// Metadata parameter that is set to `true` if the message is sent as raw
+/// CDR payload.
+pub const RAW_CDR_PARAMETER: &str = "ros2_raw_cdr";
+
+/// How the messages of a topic are converted.
+#[derive(Debug, Clone)]
+pub enum Encoding {
+    /// Arrow struct array with one field per message field.
+    Struct(TypeInfo<'static>),
+    /// `UInt8` array with the serialized CDR payload of the message.
+    RawCdr,
+}
+
+impl Encoding {
+    /// Looks up the message definition for the given topic, falling back to
+    /// raw CDR if the message type is not defined in the sourced ROS 2
+    /// installation.
+    pub fn for_topic(topic: &config::Topic, messages: &Messages) -> Self {
+        let message_type = &topic.message_type;
+        let known = messages
+            .get(&message_type.package)
+            .is_some_and(|package| package.contains_key(&message_type.name));
+        if known {
+            Self::Struct(TypeInfo {
+                package_name: Cow::Owned(message_type.package.clone()),
+                message_name: Cow::Owned(message_type.name.clone()),
+                messages: messages.clone(),
+            })
+        } else {
+            tracing::info!(
+                "no definition of message type `{message_type}` found in `AMENT_PREFIX_PATH`, \
+                passing messages of topic `{}` through as raw CDR",
+                topic.name
+            );
+            Self::RawCdr
+        }
+    }
+
+    pub fn deserializer(&self) -> MessageDeserializer {
+        match self {
+            Self::Struct(type_info) => {
+                MessageDeserializer::Struct(StructDeserializer::new(Cow::Owned(type_info.clone())))
+            }
+            Self::RawCdr => MessageDeserializer::RawCdr(raw::RawDeserializer),
+        }
+    }
+}
+
+/// Returns the metadata parameters for a message received on the given topic.
+pub fn output_parameters(topic: &config::Topic, encoding: &Encoding) -> MetadataParameters {
+    let mut parameters = MetadataParameters::default();
+    parameters.insert(
+        TYPE_PARAMETER.to_owned(),
+        Parameter::String(topic.message_type.to_string()),
+    );
+    parameters.insert(
+        TOPIC_PARAMETER.to_owned(),
+        Parameter::String(topic.name.clone()),
+    );
+    if let Encoding::RawCdr = encoding {
+        parameters.insert(RAW_CDR_PARAMETER.to_owned(), Parameter::Bool(true));
+    }
+    parameters
+}
+
+/// Deserializes received messages according to their [`Encoding`].
+#[derive(Debug, Clone)]
+pub enum MessageDeserializer {
+    Struct(StructDeserializer<'static>),
+    RawCdr(raw::RawDeserializer),
+}
+
+impl<'de> DeserializeSeed<'de> for MessageDeserializer {
+    type Value = ArrayData;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match self {
+            Self::Struct(seed) => seed.deserialize(deserializer),
+            Self::RawCdr(seed) => seed.deserialize(deserializer),
+        }
+    }
+}
+
+/// A message that is published according to its [`Encoding`].
+#[derive(Debug, Clone)]
+pub enum MessageValue<'a> {
+    Struct(TypedValue<'a>),
+    RawCdr(raw::RawValue<'a>),
+}
+
+impl serde::Serialize for MessageValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Struct(value) => value.serialize(serializer),
+            Self::RawCdr(value) => value.serialize(serializer),
+        }
+    }
+}
+
+/// The default QoS profile of ROS 2, with configurable reliability.
+pub fn qos(reliable: bool) -> rustdds::QosPolicies {
+    let reliability = if reliable {
+        policy::Reliability::Reliable {
+            max_blocking_time: rustdds::Duration::from_millis(100),
+        }
+    } else {
+        policy::Reliability::BestEffort
+    };
+    rustdds::QosPolicyBuilder::new()
+        .reliability(reliability)
+        .history(policy::History::KeepLast { depth: 10 })
+        .durability(policy::Durability::Volatile)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, sync::Arc};
+
+    #[test]
+    fn unknown_message_types_are_passed_through() {
+        let topic = config::Topic {
+            name: "/turtle1/cmd_vel".to_owned(),
+            message_type: "geometry_msgs/msg/Twist".parse().unwrap(),
+        };
+        let encoding = Encoding::for_topic(&topic, &Arc::new(HashMap::new()));
+        assert!(matches!(encoding, Encoding::RawCdr));
+        let parameters = output_parameters(&topic, &encoding);
+        assert_eq!(
+            parameters.get(RAW_CDR_PARAMETER),
+            Some(&Parameter::Bool(true))
+        );
+    }
+}
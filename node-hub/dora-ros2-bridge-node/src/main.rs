@@ -0,0 +1,124 @@
+use dora_node_api::{
+    arrow::array::{make_array, ArrayData},
+    DoraNode, Event,
+};
+use dora_ros2_bridge::{ros2_client, rustdds};
+use dora_ros2_bridge_arrow::TypedValue;
+use dora_ros2_bridge_node::{
+    config::Topic, output_parameters, qos, raw::RawValue, BridgeConfig, Encoding, MessageValue,
+};
+use eyre::{eyre, Context};
+use futures::StreamExt;
+use std::{collections::BTreeMap, path::Path};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> eyre::Result<()> {
+    let (mut node, mut events) = DoraNode::init_from_env()?;
+    let config = BridgeConfig::from_env().context("invalid ROS 2 bridge configuration")?;
+
+    let ament_prefix_paths = dora_ros2_bridge_arrow::ament_prefix_paths()?;
+    let paths: Vec<&Path> = ament_prefix_paths.iter().map(|p| p.as_path()).collect();
+    let messages = dora_ros2_bridge_arrow::load_messages(&paths)?;
+
+    let participant = rustdds::DomainParticipant::new(config.domain_id)
+        .map_err(|err| eyre!("failed to join DDS domain {}: {err:?}", config.domain_id))?;
+    let context = ros2_client::Context::from_domain_participant(participant)
+        .map_err(|err| eyre!("failed to create ROS 2 context: {err:?}"))?;
+    let mut ros_node = context
+        .new_node(
+            ros2_client::NodeName::new("/", "dora_ros2_bridge")
+                .map_err(|err| eyre!("invalid ROS 2 node name: {err:?}"))?,
+            ros2_client::NodeOptions::new(),
+        )
+        .map_err(|err| eyre!("failed to create ROS 2 node: {err:?}"))?;
+    let qos = qos(config.reliable);
+
+    let mut publishers = BTreeMap::new();
+    for (input, topic) in &config.publish {
+        let encoding = Encoding::for_topic(topic, &messages);
+        let ros_topic = create_topic(&mut ros_node, topic, &qos)?;
+        let publisher = ros_node
+            .create_publisher::<MessageValue<'static>>(&ros_topic, None)
+            .map_err(|err| eyre!("failed to publish to `{}`: {err:?}", topic.name))?;
+        publishers.insert(input.clone(), (topic, encoding, publisher));
+    }
+    let mut subscriptions = Vec::new();
+    for (output, topic) in &config.subscribe {
+        let encoding = Encoding::for_topic(topic, &messages);
+        let ros_topic = create_topic(&mut ros_node, topic, &qos)?;
+        let subscription = ros_node
+            .create_subscription::<ArrayData>(&ros_topic, None)
+            .map_err(|err| eyre!("failed to subscribe to `{}`: {err:?}", topic.name))?;
+        let parameters = output_parameters(topic, &encoding);
+        subscriptions.push((output, topic, parameters, subscription, encoding));
+    }
+    let mut samples = futures::stream::select_all(subscriptions.iter().map(
+        |(output, topic, parameters, subscription, encoding)| {
+            subscription
+                .async_stream_seed(encoding.deserializer())
+                .map(move |sample| (*output, *topic, parameters, sample))
+                .boxed_local()
+        },
+    ));
+
+    loop {
+        tokio::select! {
+            event = events.recv_async() => match event {
+                Some(Event::Input { id, data, .. }) => {
+                    let Some((topic, encoding, publisher)) = publishers.get(&id) else {
+                        tracing::warn!("no ROS 2 topic configured for input `{id}`");
+                        continue;
+                    };
+                    let value = match encoding {
+                        Encoding::Struct(type_info) => MessageValue::Struct(TypedValue {
+                            value: &data.0,
+                            type_info,
+                        }),
+                        Encoding::RawCdr => match <&[u8]>::try_from(&data) {
+                            Ok(bytes) => MessageValue::RawCdr(RawValue(bytes)),
+                            Err(err) => {
+                                tracing::warn!(
+                                    "input `{id}` must be a UInt8 array with the CDR payload \
+                                    of a `{}` message: {err:?}",
+                                    topic.message_type
+                                );
+                                continue;
+                            }
+                        },
+                    };
+                    if let Err(err) = publisher.publish(value).map_err(|e| e.forget_data()) {
+                        tracing::warn!("failed to publish input `{id}` on `{}`: {err:?}", topic.name);
+                    }
+                }
+                Some(Event::Stop) | None => break,
+                Some(_) => {}
+            },
+            Some((output, topic, parameters, sample)) = samples.next() => {
+                let data = match sample {
+                    Ok((data, _info)) => data,
+                    Err(err) => {
+                        tracing::warn!("failed to read message on `{}`: {err:?}", topic.name);
+                        continue;
+                    }
+                };
+                node.send_output(output.clone(), parameters.clone(), make_array(data))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn create_topic(
+    ros_node: &mut ros2_client::Node,
+    topic: &Topic,
+    qos: &rustdds::QosPolicies,
+) -> eyre::Result<rustdds::Topic> {
+    let name = ros2_client::Name::parse(&topic.name)
+        .map_err(|err| eyre!("invalid ROS 2 topic name `{}`: {err}", topic.name))?;
+    let message_type =
+        ros2_client::MessageTypeName::new(&topic.message_type.package, &topic.message_type.name);
+    ros_node
+        .create_topic(&name, message_type, qos)
+        .map_err(|err| eyre!("failed to create topic `{}`: {err:?}", topic.name))
+}
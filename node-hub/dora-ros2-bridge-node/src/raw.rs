@@ -0,0 +1,83 @@
+//! Raw CDR passthrough for message types without a definition.
+//!
+//! Messages are exchanged as `UInt8` arrays that contain the CDR payload of
+//! the message, without the 4-byte encapsulation header. To decode them with
+//! the ROS 2 serialization functions (e.g. `rclpy.serialization`), prepend the
+//! little-endian header `[0, 1, 0, 0]`.
+
+use dora_node_api::arrow::array::{ArrayData, UInt8Array};
+use serde::{
+    de::{DeserializeSeed, SeqAccess, Visitor},
+    ser::SerializeTuple,
+};
+use std::fmt;
+
+/// Reads the complete CDR payload of a message as bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct RawDeserializer;
+
+impl<'de> DeserializeSeed<'de> for RawDeserializer {
+    type Value = ArrayData;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // CDR tuples have no length prefix, so the payload can be read byte
+        // by byte until its end
+        deserializer.deserialize_tuple(usize::MAX, RawVisitor)
+    }
+}
+
+struct RawVisitor;
+
+impl<'de> Visitor<'de> for RawVisitor {
+    type Value = ArrayData;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a CDR payload")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::new();
+        // the CDR deserializer fails once the payload is exhausted
+        while let Ok(Some(byte)) = seq.next_element::<u8>() {
+            bytes.push(byte);
+        }
+        Ok(UInt8Array::from(bytes).into())
+    }
+}
+
+/// Writes the given bytes as CDR payload, without any length prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct RawValue<'a>(pub &'a [u8]);
+
+impl serde::Serialize for RawValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for byte in self.0 {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{Error, SeqDeserializer};
+
+    #[test]
+    fn read_until_end_of_payload() {
+        let deserializer = SeqDeserializer::<_, Error>::new([1u8, 2, 3].into_iter());
+        let data = RawDeserializer.deserialize(deserializer).unwrap();
+        let array = UInt8Array::from(data);
+        assert_eq!(array.values().as_ref(), [1, 2, 3]);
+    }
+}